@@ -0,0 +1,112 @@
+mod synthetic;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use geno::{
+    GenoAstBuilder,
+    arena::Arena,
+    ast::SchemaRef,
+    codegen::{Options, Registry},
+    incremental::Document,
+};
+use std::hint::black_box;
+
+/// Schema sizes, in declarations
+const SIZES: [(&str, usize); 3] = [("small", 10), ("medium", 500), ("huge", 10_000)];
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+
+    for (name, types) in SIZES {
+        let source = synthetic::schema(types);
+        let builder = GenoAstBuilder::new("synthetic.geno".into());
+
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::new("owned", name), &source, |b, source| {
+            b.iter(|| builder.build_from_str(black_box(source)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("borrowed", name), &source, |b, source| {
+            b.iter(|| builder.build_ref(black_box(source)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("arena", name), &source, |b, source| {
+            b.iter(|| {
+                let arena = Arena::new();
+
+                builder
+                    .build_from_str_in(&arena, black_box(source))
+                    .map(|schema| schema.declarations.len())
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn validate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validate");
+
+    for (name, types) in SIZES {
+        let schema = GenoAstBuilder::new("synthetic.geno".into())
+            .build_from_str(&synthetic::schema(types))
+            .unwrap();
+
+        group.throughput(Throughput::Elements(types as u64));
+        group.bench_with_input(BenchmarkId::new("owned", name), &schema, |b, schema| {
+            b.iter(|| black_box(schema).validate().unwrap())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("borrowed", name),
+            &SchemaRef::from(&schema),
+            |b, schema| b.iter(|| black_box(schema).validate().unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn edit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("edit");
+
+    for (name, types) in SIZES {
+        let (mut document, result) =
+            Document::new("synthetic.geno".into(), synthetic::schema(types));
+        // Rename a field in the middle of the schema back and forth
+        let at = document.source().len() / 2;
+        let at = at + document.source()[at..].find("field0").unwrap();
+
+        result.unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                document.edit(at..at + 5, "Field").unwrap();
+                document.edit(at..at + 5, "field").unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn generate(c: &mut Criterion) {
+    let registry = Registry::with_builtins();
+    let options = Options::new();
+
+    for generator in registry.names() {
+        let mut group = c.benchmark_group(format!("generate/{generator}"));
+
+        for (name, types) in SIZES {
+            let schema = GenoAstBuilder::new("synthetic.geno".into())
+                .build_from_str(&synthetic::schema(types))
+                .unwrap();
+
+            group.throughput(Throughput::Elements(types as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(name), &schema, |b, schema| {
+                b.iter(|| {
+                    registry
+                        .generate(generator, black_box(schema), &options)
+                        .unwrap()
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, parse, validate, edit, generate);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! Synthetic schemas of any size for benchmarking
+
+use std::fmt::Write;
+
+const BUILTINS: [&str; 6] = ["i32", "u64", "f64", "string", "bool", "u8"];
+
+/// Schema source with `types` declarations.  Every fourth declaration is an enum, and each struct
+/// refers to types declared before it through plain, nullable, list and map fields, so the
+/// schema is always valid and the same for a given size.
+pub fn schema(types: usize) -> String {
+    let mut source = String::from("meta { format = 1 }\n\n");
+    let mut enums = Vec::new();
+    let mut structs = Vec::new();
+
+    for i in 0..types {
+        if i % 4 == 0 {
+            let ident = format!("enum{i}");
+
+            writeln!(source, "enum {ident}: u16 {{").unwrap();
+            for v in 0..(i % 7 + 1) {
+                writeln!(source, "    v{v} = {v},").unwrap();
+            }
+            writeln!(source, "}}\n").unwrap();
+            enums.push(ident);
+        } else {
+            let ident = format!("struct{i}");
+
+            writeln!(source, "struct {ident} {{").unwrap();
+            for f in 0..(i % 9 + 2) {
+                let builtin = BUILTINS[(i + f) % BUILTINS.len()];
+                let field_type = match f % 5 {
+                    0 => builtin.to_string(),
+                    1 => format!("{builtin}?"),
+                    2 => format!("[{builtin}]"),
+                    3 => match enums.last() {
+                        Some(enum_ident) => format!("{{{enum_ident}: {builtin}}}"),
+                        None => format!("{{string: {builtin}}}"),
+                    },
+                    _ => match structs.get(i * 31 % structs.len().max(1)) {
+                        Some(struct_ident) => format!("{struct_ident}?"),
+                        None => format!("[{builtin}; 4]"),
+                    },
+                };
+                writeln!(source, "    field{f}: {field_type},").unwrap();
+            }
+            writeln!(source, "}}\n").unwrap();
+            structs.push(ident);
+        }
+    }
+
+    source
+}
@@ -0,0 +1,59 @@
+#ifndef GENO_H
+#define GENO_H
+
+/* Generated by cbindgen from src/lib.rs, don't edit it by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ * A parsed and validated schema
+ */
+typedef struct GenoSchema GenoSchema;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ * Parse and validate a schema.  Returns NULL if the schema is invalid, with the reason given by
+ * [geno_last_error].  `file_path` is only used in error messages, and can be NULL.
+ *
+ * # Safety
+ *
+ * `source` must be a NUL terminated string, and `file_path` NULL or a NUL terminated string.
+ */
+GenoSchema *geno_parse(const char *source, const char *file_path);
+
+/*
+ * The message of the last [geno_parse] on this thread that failed, or NULL if the last one
+ * succeeded.  The message is valid until the next call to [geno_parse] on the thread.
+ */
+const char *geno_last_error(void);
+
+/*
+ * The AST of a schema as JSON, in the form of the `schema.json` in a bundle.  The JSON belongs
+ * to the schema and is valid until it is freed.
+ *
+ * # Safety
+ *
+ * `schema` must have come from [geno_parse] and not yet been freed.
+ */
+const char *geno_ast_to_json(const GenoSchema *schema);
+
+/*
+ * Free a schema returned by [geno_parse], along with its JSON.  Does nothing if `schema` is NULL.
+ *
+ * # Safety
+ *
+ * `schema` must be NULL or have come from [geno_parse], and not have been freed already.
+ */
+void geno_free(GenoSchema *schema);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* GENO_H */
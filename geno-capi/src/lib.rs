@@ -0,0 +1,162 @@
+//! A C ABI for parsing and validating schemas, so that build tools written in other languages can
+//! embed `geno` without running it as a process.  The crate builds a static and a shared
+//! `geno_capi` library.  `include/geno.h` declares it, and is generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/geno.h`.
+//!
+//! Strings passed in and out are NUL terminated UTF-8.  A schema returned by [geno_parse] owns
+//! the JSON returned for it, and both are released by [geno_free].
+
+#![warn(missing_docs)]
+
+use geno_core::{GenoAstBuilder, ast};
+use std::{
+    cell::{OnceCell, RefCell},
+    ffi::{CStr, CString, c_char},
+    ptr,
+};
+
+/// A parsed and validated schema
+pub struct GenoSchema {
+    schema: ast::Schema,
+    json: OnceCell<CString>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: Option<String>) {
+    // C strings end at the first NUL, so the message does too
+    let message = message.map(|message| {
+        CString::new(message).unwrap_or_else(|err| {
+            let end = err.nul_position();
+
+            CString::new(&err.into_vec()[..end]).unwrap()
+        })
+    });
+
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Parse and validate a schema.  Returns NULL if the schema is invalid, with the reason given by
+/// [geno_last_error].  `file_path` is only used in error messages, and can be NULL.
+///
+/// # Safety
+///
+/// `source` must be a NUL terminated string, and `file_path` NULL or a NUL terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn geno_parse(
+    source: *const c_char,
+    file_path: *const c_char,
+) -> *mut GenoSchema {
+    if source.is_null() {
+        set_last_error(Some("source is NULL".to_string()));
+        return ptr::null_mut();
+    }
+
+    let Ok(source) = unsafe { CStr::from_ptr(source) }.to_str() else {
+        set_last_error(Some("source isn't UTF-8".to_string()));
+        return ptr::null_mut();
+    };
+    let file_path = if file_path.is_null() {
+        "<source>".to_string()
+    } else {
+        unsafe { CStr::from_ptr(file_path) }
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    match GenoAstBuilder::new(file_path.into()).build_from_str(source) {
+        Ok(schema) => {
+            set_last_error(None);
+            Box::into_raw(Box::new(GenoSchema {
+                schema,
+                json: OnceCell::new(),
+            }))
+        }
+        Err(err) => {
+            set_last_error(Some(err.to_string()));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The message of the last [geno_parse] on this thread that failed, or NULL if the last one
+/// succeeded.  The message is valid until the next call to [geno_parse] on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn geno_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// The AST of a schema as JSON, in the form of the `schema.json` in a bundle.  The JSON belongs
+/// to the schema and is valid until it is freed.
+///
+/// # Safety
+///
+/// `schema` must have come from [geno_parse] and not yet been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn geno_ast_to_json(schema: *const GenoSchema) -> *const c_char {
+    let Some(schema) = (unsafe { schema.as_ref() }) else {
+        return ptr::null();
+    };
+
+    // JSON escapes control characters, so it never holds a NUL
+    schema
+        .json
+        .get_or_init(|| CString::new(serde_json::to_string(&schema.schema).unwrap()).unwrap())
+        .as_ptr()
+}
+
+/// Free a schema returned by [geno_parse], along with its JSON.  Does nothing if `schema` is NULL.
+///
+/// # Safety
+///
+/// `schema` must be NULL or have come from [geno_parse], and not have been freed already.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn geno_free(schema: *mut GenoSchema) {
+    if !schema.is_null() {
+        drop(unsafe { Box::from_raw(schema) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_to_json() {
+        let source = c"meta { format = 1 } struct point { x: i32, y: i32 }";
+        let schema = unsafe { geno_parse(source.as_ptr(), ptr::null()) };
+
+        assert!(!schema.is_null());
+        assert!(geno_last_error().is_null());
+
+        let json = unsafe { CStr::from_ptr(geno_ast_to_json(schema)) };
+        let value: serde_json::Value = serde_json::from_slice(json.to_bytes()).unwrap();
+
+        assert_eq!(value["declarations"][0]["Struct"]["ident"], "point");
+        assert_eq!(unsafe { geno_ast_to_json(schema) }, json.as_ptr());
+
+        unsafe { geno_free(schema) };
+
+        let schema = unsafe {
+            geno_parse(
+                c"meta { format = 1 } struct a { b: c }".as_ptr(),
+                c"a.geno".as_ptr(),
+            )
+        };
+
+        assert!(schema.is_null());
+
+        let message = unsafe { CStr::from_ptr(geno_last_error()) };
+
+        assert!(message.to_str().unwrap().contains("'c'"));
+        assert!(unsafe { geno_parse(ptr::null(), ptr::null()) }.is_null());
+        assert!(unsafe { geno_ast_to_json(ptr::null()) }.is_null());
+        unsafe { geno_free(ptr::null_mut()) };
+    }
+}
@@ -0,0 +1,39 @@
+//! Arena allocation of schema sources.  [GenoAstBuilder::build_in](crate::GenoAstBuilder::build_in)
+//! reads a schema and every module it imports into an [Arena], and builds an
+//! [ast::SchemaRef](crate::ast::SchemaRef) whose identifiers and strings all borrow from it.  A
+//! large schema then costs one allocation per file rather than one per identifier, and the whole
+//! AST is freed at once when the arena is dropped.
+use bumpalo::Bump;
+use std::fmt;
+
+/// Holds the sources that [SchemaRef](crate::ast::SchemaRef)s built with
+/// [build_in](crate::GenoAstBuilder::build_in) borrow from.  One arena can hold several schemas.
+#[derive(Default)]
+pub struct Arena {
+    bump: Bump,
+}
+
+impl Arena {
+    /// Create an empty arena
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy `s` into the arena
+    pub fn alloc_str(&self, s: &str) -> &str {
+        self.bump.alloc_str(s)
+    }
+
+    /// Bytes allocated by the arena so far, including any unused space in its chunks
+    pub fn allocated_bytes(&self) -> usize {
+        self.bump.allocated_bytes()
+    }
+}
+
+impl fmt::Debug for Arena {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Arena")
+            .field("allocated_bytes", &self.allocated_bytes())
+            .finish()
+    }
+}
@@ -0,0 +1,3512 @@
+use crate::{
+    clock::Instant,
+    error::*,
+    intern::{FnvHashMap, FnvHashSet, Interner},
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    ops::Range,
+};
+
+/// Enum representing integer types
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IntegerType {
+    /// Signed 8-bit integer
+    I8,
+    /// Signed 16-bit integer
+    I16,
+    /// Signed 32-bit integer
+    I32,
+    /// Signed 64-bit integer
+    I64,
+    /// Unsigned 8-bit integer
+    U8,
+    /// Unsigned 16-bit integer
+    U16,
+    /// Unsigned 32-bit integer
+    U32,
+    /// Unsigned 64-bit integer
+    U64,
+}
+
+/// Enum representing integer values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IntegerValue {
+    /// Signed 8-bit integer value
+    I8(i8),
+    /// Signed 16-bit integer value
+    I16(i16),
+    /// Signed 32-bit integer value
+    I32(i32),
+    /// Signed 64-bit integer value
+    I64(i64),
+    /// Unsigned 8-bit integer value
+    U8(u8),
+    /// Unsigned 16-bit integer value
+    U16(u16),
+    /// Unsigned 32-bit integer value
+    U32(u32),
+    /// Unsigned 64-bit integer value
+    U64(u64),
+}
+
+impl IntegerType {
+    /// The schema (and Rust) spelling of the type
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IntegerType::I8 => "i8",
+            IntegerType::I16 => "i16",
+            IntegerType::I32 => "i32",
+            IntegerType::I64 => "i64",
+            IntegerType::U8 => "u8",
+            IntegerType::U16 => "u16",
+            IntegerType::U32 => "u32",
+            IntegerType::U64 => "u64",
+        }
+    }
+
+    /// Size of the type in bits
+    pub fn bits(&self) -> u32 {
+        match self {
+            IntegerType::I8 | IntegerType::U8 => 8,
+            IntegerType::I16 | IntegerType::U16 => 16,
+            IntegerType::I32 | IntegerType::U32 => 32,
+            IntegerType::I64 | IntegerType::U64 => 64,
+        }
+    }
+
+    /// Whether the type holds `value`
+    pub fn contains(&self, value: i128) -> bool {
+        let bits = self.bits();
+
+        match self {
+            IntegerType::I8 | IntegerType::I16 | IntegerType::I32 | IntegerType::I64 => {
+                value >= -(1 << (bits - 1)) && value < 1 << (bits - 1)
+            }
+            _ => value >= 0 && value < 1 << bits,
+        }
+    }
+
+    /// The narrowest type that holds every value from `min` to `max`, unsigned if none of them
+    /// are negative
+    pub fn narrowest(min: i128, max: i128) -> IntegerType {
+        let fits = |bits: u32| {
+            if min >= 0 {
+                max < 1 << bits
+            } else {
+                min >= -(1 << (bits - 1)) && max < 1 << (bits - 1)
+            }
+        };
+
+        match (min >= 0, [8, 16, 32].into_iter().find(|bits| fits(*bits))) {
+            (true, Some(8)) => IntegerType::U8,
+            (true, Some(16)) => IntegerType::U16,
+            (true, Some(_)) => IntegerType::U32,
+            (true, None) => IntegerType::U64,
+            (false, Some(8)) => IntegerType::I8,
+            (false, Some(16)) => IntegerType::I16,
+            (false, Some(_)) => IntegerType::I32,
+            (false, None) => IntegerType::I64,
+        }
+    }
+}
+
+impl IntegerValue {
+    /// The value, widened so that every integer type fits
+    pub fn to_i128(&self) -> i128 {
+        match self {
+            IntegerValue::I8(n) => *n as i128,
+            IntegerValue::I16(n) => *n as i128,
+            IntegerValue::I32(n) => *n as i128,
+            IntegerValue::I64(n) => *n as i128,
+            IntegerValue::U8(n) => *n as i128,
+            IntegerValue::U16(n) => *n as i128,
+            IntegerValue::U32(n) => *n as i128,
+            IntegerValue::U64(n) => *n as i128,
+        }
+    }
+}
+
+/// Enum representing float values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FloatType {
+    /// 32-bit floating-point value
+    F32,
+    /// 64-bit floating-point value
+    F64,
+}
+
+/// Enum representing all built-in types
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BuiltinType {
+    /// Integer types
+    Integer(IntegerType),
+    /// Float types
+    Float(FloatType),
+    /// String type
+    String,
+    /// Localized string type, text keyed by locale
+    LocalizedString,
+    /// Bool type
+    Bool,
+}
+
+/// Enum representing the types allowed as map keys
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MapKeyType {
+    /// Builtin type, other than a float
+    Builtin(BuiltinType),
+    /// User-defined enum type, by its identifier, qualified with its namespace if
+    /// [imported](crate::imports)
+    UserDefined(String),
+}
+
+/// Enum representing all field types
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldType {
+    /// Array type
+    Array(Box<FieldType>, Option<usize>, bool),
+    /// Map type
+    Map(MapKeyType, Box<FieldType>, bool),
+    /// Builtin type
+    Builtin(BuiltinType, bool),
+    /// User-defined type, by the identifier of its declaration.  A type
+    /// [imported](crate::imports) from a package is qualified with the package's namespace, as in
+    /// `common.address`, whether or not the schema wrote it that way.
+    UserDefined(String, bool),
+}
+
+/// Enum representing metadata values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MetadataValue {
+    /// String value, without the quotes and with escapes replaced
+    String(String),
+    /// Integer value
+    Integer(IntegerValue),
+}
+
+/// Enum representing annotation argument values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AnnotationValue {
+    /// Integer value
+    Integer(i64),
+    /// String value, without the quotes and with escapes replaced
+    String(String),
+    /// Identifier
+    Identifier(String),
+}
+
+/// An annotation argument, either `value` or `name = value`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnnotationArg {
+    /// Argument name, if given
+    pub name: Option<String>,
+    /// Argument value
+    pub value: AnnotationValue,
+}
+
+/// An annotation such as `@since(3)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// Annotation name, without the `@`
+    pub name: String,
+    /// Annotation arguments
+    pub args: Vec<AnnotationArg>,
+}
+
+/// The annotations on a declaration and on its fields or variants
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotations {
+    /// Annotations on the declaration itself
+    pub declaration: Vec<Annotation>,
+    /// Annotations on fields or variants, by name
+    pub members: BTreeMap<String, Vec<Annotation>>,
+    /// Comments on the declaration and on its fields or variants.  They aren't serialized, so
+    /// they don't change the [fingerprint](Schema::fingerprint).
+    #[serde(skip)]
+    pub comments: Comments,
+}
+
+/// The comments attached to a declaration and to its fields or variants.  A comment is attached
+/// to the declaration, field or variant after it when it starts a line of its own and no blank
+/// line comes between them.  Comments are kept as written, `//` or `/* */` included.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Comments {
+    /// Comments before the declaration itself
+    pub declaration: Vec<String>,
+    /// Comments before fields or variants, by name
+    pub members: BTreeMap<String, Vec<String>>,
+}
+
+/// Who can use an enum or struct in the generated code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Visibility {
+    /// Part of the public API, which is the default
+    #[default]
+    Public,
+    /// Only for use within the crate or package the code is generated into
+    Internal,
+}
+
+impl Visibility {
+    /// Whether the visibility is public
+    pub fn is_public(&self) -> bool {
+        *self == Visibility::Public
+    }
+}
+
+/// Enum representing declarations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Declaration {
+    /// Enum declaration
+    Enum {
+        /// Enum identifier
+        ident: String,
+        /// Enum base integer type
+        base_type: IntegerType,
+        /// Enum variants
+        variants: Vec<(String, IntegerValue)>,
+        /// Enum visibility
+        #[serde(default, skip_serializing_if = "Visibility::is_public")]
+        visibility: Visibility,
+        /// Annotations on the enum and its variants
+        #[serde(default, skip_serializing_if = "Annotations::is_empty")]
+        annotations: Annotations,
+    },
+    /// Struct declaration
+    Struct {
+        /// Struct identifier
+        ident: String,
+        /// Struct fields
+        fields: Vec<(String, FieldType)>,
+        /// Identifiers of the interfaces the struct implements
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        implements: Vec<String>,
+        /// Struct visibility
+        #[serde(default, skip_serializing_if = "Visibility::is_public")]
+        visibility: Visibility,
+        /// Annotations on the struct and its fields
+        #[serde(default, skip_serializing_if = "Annotations::is_empty")]
+        annotations: Annotations,
+    },
+    /// Interface declaration, fields shared by the structs that implement it.  Interfaces
+    /// aren't types and don't appear on the wire.
+    Interface {
+        /// Interface identifier
+        ident: String,
+        /// Fields each implementing struct has
+        fields: Vec<(String, FieldType)>,
+        /// Interface visibility
+        #[serde(default, skip_serializing_if = "Visibility::is_public")]
+        visibility: Visibility,
+        /// Annotations on the interface and its fields
+        #[serde(default, skip_serializing_if = "Annotations::is_empty")]
+        annotations: Annotations,
+    },
+    /// Publish/subscribe topic declaration
+    Topic {
+        /// Topic identifier
+        ident: String,
+        /// Identifier of the struct published on the topic
+        payload: String,
+        /// Annotations on the topic
+        #[serde(default, skip_serializing_if = "Annotations::is_empty")]
+        annotations: Annotations,
+    },
+}
+
+/// Schema declaration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    /// Schema metadata
+    pub metadata: HashMap<String, MetadataValue>,
+    /// Schema declarations
+    pub declarations: Vec<Declaration>,
+}
+
+/// A schema's `version` metadata, either a single integer, as in `version = 3`, or a semantic
+/// version, as in `version = "2.1.0"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaVersion {
+    /// A version that counts every change that isn't a patch
+    Integer(i64),
+    /// A version whose major, minor and patch components count each class of change
+    Semantic(i64, i64, i64),
+}
+
+impl SchemaVersion {
+    /// Parse a `"major.minor.patch"` version.  The major version must be positive, as `@since`
+    /// and `@removed` count major versions.
+    pub fn parse_semantic(s: &str) -> Option<Self> {
+        let mut parts = s.split('.').map(|part| {
+            (!part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+                .then(|| part.parse::<i64>().ok())
+                .flatten()
+        });
+        let version = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) if major > 0 => {
+                Self::Semantic(major, minor, patch)
+            }
+            _ => return None,
+        };
+
+        Some(version)
+    }
+
+    /// The version that `@since` and `@removed` annotations count, which is the major version
+    /// of a semantic version
+    pub fn major(self) -> i64 {
+        match self {
+            Self::Integer(version) | Self::Semantic(version, _, _) => version,
+        }
+    }
+}
+
+impl From<i64> for SchemaVersion {
+    fn from(version: i64) -> Self {
+        Self::Integer(version)
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(version) => write!(f, "{version}"),
+            Self::Semantic(major, minor, patch) => write!(f, "{major}.{minor}.{patch}"),
+        }
+    }
+}
+
+impl Serialize for SchemaVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Integer(version) => serializer.serialize_i64(*version),
+            Self::Semantic(..) => serializer.collect_str(self),
+        }
+    }
+}
+
+/// What a code generator's target language can represent.  A schema is checked against the
+/// capabilities of a target with [Schema::validate_for], so that a schema the target can't handle
+/// is rejected before any code is generated for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    /// Floats can be map keys
+    pub float_map_keys: bool,
+    /// Fixed length arrays such as `[u8; 4]` can be generated
+    pub fixed_arrays: bool,
+    /// `u64` values above `i64::MAX` survive a round trip.  A target without this can still
+    /// generate code for `u64` fields, with a [GenoWarning::LossyU64] for each of them.
+    pub u64: bool,
+}
+
+impl Capabilities {
+    /// Everything the schema language allows
+    pub const ALL: Capabilities = Capabilities {
+        float_map_keys: true,
+        fixed_arrays: true,
+        u64: true,
+    };
+}
+
+/// Borrowed form of [MapKeyType]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum MapKeyTypeRef<'a> {
+    /// Builtin type, other than a float
+    Builtin(BuiltinType),
+    /// User-defined enum type
+    UserDefined(&'a str),
+}
+
+/// Borrowed form of [FieldType]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum FieldTypeRef<'a> {
+    /// Array type
+    Array(Box<FieldTypeRef<'a>>, Option<usize>, bool),
+    /// Map type
+    Map(MapKeyTypeRef<'a>, Box<FieldTypeRef<'a>>, bool),
+    /// Builtin type
+    Builtin(BuiltinType, bool),
+    /// User-defined type
+    UserDefined(&'a str, bool),
+}
+
+/// Borrowed form of [MetadataValue]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum MetadataValueRef<'a> {
+    /// String value, borrowed unless it had escapes
+    String(Cow<'a, str>),
+    /// Integer value
+    Integer(IntegerValue),
+}
+
+/// Borrowed form of [AnnotationValue]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum AnnotationValueRef<'a> {
+    /// Integer value
+    Integer(i64),
+    /// String value, borrowed unless it had escapes
+    String(Cow<'a, str>),
+    /// Identifier
+    Identifier(&'a str),
+}
+
+/// Borrowed form of [AnnotationArg]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnnotationArgRef<'a> {
+    /// Argument name, if given
+    pub name: Option<&'a str>,
+    /// Argument value
+    pub value: AnnotationValueRef<'a>,
+}
+
+/// Borrowed form of [Annotation]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnnotationRef<'a> {
+    /// Annotation name, without the `@`
+    pub name: &'a str,
+    /// Annotation arguments
+    pub args: Vec<AnnotationArgRef<'a>>,
+}
+
+/// Borrowed form of [Annotations]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AnnotationsRef<'a> {
+    /// Annotations on the declaration itself
+    pub declaration: Vec<AnnotationRef<'a>>,
+    /// Annotations on fields or variants, by name
+    pub members: BTreeMap<&'a str, Vec<AnnotationRef<'a>>>,
+    /// Comments on the declaration and on its fields or variants
+    #[serde(skip)]
+    pub comments: CommentsRef<'a>,
+}
+
+/// Borrowed form of [Comments]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CommentsRef<'a> {
+    /// Comments before the declaration itself
+    pub declaration: Vec<&'a str>,
+    /// Comments before fields or variants, by name
+    pub members: BTreeMap<&'a str, Vec<&'a str>>,
+}
+
+/// Borrowed form of [Declaration]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum DeclarationRef<'a> {
+    /// Enum declaration
+    Enum {
+        /// Enum identifier
+        ident: &'a str,
+        /// Enum base integer type
+        base_type: IntegerType,
+        /// Enum variants
+        variants: Vec<(&'a str, IntegerValue)>,
+        /// Enum visibility
+        #[serde(skip_serializing_if = "Visibility::is_public")]
+        visibility: Visibility,
+        /// Annotations on the enum and its variants
+        #[serde(skip_serializing_if = "AnnotationsRef::is_empty")]
+        annotations: AnnotationsRef<'a>,
+    },
+    /// Struct declaration
+    Struct {
+        /// Struct identifier
+        ident: &'a str,
+        /// Struct fields
+        fields: Vec<(&'a str, FieldTypeRef<'a>)>,
+        /// Identifiers of the interfaces the struct implements
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        implements: Vec<&'a str>,
+        /// Struct visibility
+        #[serde(skip_serializing_if = "Visibility::is_public")]
+        visibility: Visibility,
+        /// Annotations on the struct and its fields
+        #[serde(skip_serializing_if = "AnnotationsRef::is_empty")]
+        annotations: AnnotationsRef<'a>,
+    },
+    /// Interface declaration
+    Interface {
+        /// Interface identifier
+        ident: &'a str,
+        /// Fields each implementing struct has
+        fields: Vec<(&'a str, FieldTypeRef<'a>)>,
+        /// Interface visibility
+        #[serde(skip_serializing_if = "Visibility::is_public")]
+        visibility: Visibility,
+        /// Annotations on the interface and its fields
+        #[serde(skip_serializing_if = "AnnotationsRef::is_empty")]
+        annotations: AnnotationsRef<'a>,
+    },
+    /// Publish/subscribe topic declaration
+    Topic {
+        /// Topic identifier
+        ident: &'a str,
+        /// Identifier of the struct published on the topic
+        payload: &'a str,
+        /// Annotations on the topic
+        #[serde(skip_serializing_if = "AnnotationsRef::is_empty")]
+        annotations: AnnotationsRef<'a>,
+    },
+}
+
+/// Schema whose identifiers and strings borrow from the parsed source or from a [Schema], so
+/// that re-parsing doesn't allocate a `String` for each of them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaRef<'a> {
+    /// Schema metadata
+    pub metadata: HashMap<&'a str, MetadataValueRef<'a>>,
+    /// Schema declarations
+    pub declarations: Vec<DeclarationRef<'a>>,
+}
+
+impl<'a> From<&'a MapKeyType> for MapKeyTypeRef<'a> {
+    fn from(key_type: &'a MapKeyType) -> Self {
+        match key_type {
+            MapKeyType::Builtin(builtin_type) => MapKeyTypeRef::Builtin(builtin_type.clone()),
+            MapKeyType::UserDefined(name) => MapKeyTypeRef::UserDefined(name),
+        }
+    }
+}
+
+impl From<MapKeyTypeRef<'_>> for MapKeyType {
+    fn from(key_type: MapKeyTypeRef<'_>) -> Self {
+        match key_type {
+            MapKeyTypeRef::Builtin(builtin_type) => MapKeyType::Builtin(builtin_type),
+            MapKeyTypeRef::UserDefined(name) => MapKeyType::UserDefined(name.to_string()),
+        }
+    }
+}
+
+impl<'a> From<&'a FieldType> for FieldTypeRef<'a> {
+    fn from(field_type: &'a FieldType) -> Self {
+        match field_type {
+            FieldType::Array(inner, length, nullable) => {
+                FieldTypeRef::Array(Box::new(inner.as_ref().into()), *length, *nullable)
+            }
+            FieldType::Map(key_type, value_type, nullable) => FieldTypeRef::Map(
+                key_type.into(),
+                Box::new(value_type.as_ref().into()),
+                *nullable,
+            ),
+            FieldType::Builtin(builtin_type, nullable) => {
+                FieldTypeRef::Builtin(builtin_type.clone(), *nullable)
+            }
+            FieldType::UserDefined(name, nullable) => FieldTypeRef::UserDefined(name, *nullable),
+        }
+    }
+}
+
+impl From<FieldTypeRef<'_>> for FieldType {
+    fn from(field_type: FieldTypeRef<'_>) -> Self {
+        match field_type {
+            FieldTypeRef::Array(inner, length, nullable) => {
+                FieldType::Array(Box::new((*inner).into()), length, nullable)
+            }
+            FieldTypeRef::Map(key_type, value_type, nullable) => {
+                FieldType::Map(key_type.into(), Box::new((*value_type).into()), nullable)
+            }
+            FieldTypeRef::Builtin(builtin_type, nullable) => {
+                FieldType::Builtin(builtin_type, nullable)
+            }
+            FieldTypeRef::UserDefined(name, nullable) => {
+                FieldType::UserDefined(name.to_string(), nullable)
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a MetadataValue> for MetadataValueRef<'a> {
+    fn from(value: &'a MetadataValue) -> Self {
+        match value {
+            MetadataValue::String(s) => MetadataValueRef::String(Cow::Borrowed(s)),
+            MetadataValue::Integer(n) => MetadataValueRef::Integer(n.clone()),
+        }
+    }
+}
+
+impl From<MetadataValueRef<'_>> for MetadataValue {
+    fn from(value: MetadataValueRef<'_>) -> Self {
+        match value {
+            MetadataValueRef::String(s) => MetadataValue::String(s.into_owned()),
+            MetadataValueRef::Integer(n) => MetadataValue::Integer(n),
+        }
+    }
+}
+
+impl<'a> From<&'a Annotation> for AnnotationRef<'a> {
+    fn from(annotation: &'a Annotation) -> Self {
+        AnnotationRef {
+            name: &annotation.name,
+            args: annotation
+                .args
+                .iter()
+                .map(|arg| AnnotationArgRef {
+                    name: arg.name.as_deref(),
+                    value: match &arg.value {
+                        AnnotationValue::Integer(n) => AnnotationValueRef::Integer(*n),
+                        AnnotationValue::String(s) => AnnotationValueRef::String(Cow::Borrowed(s)),
+                        AnnotationValue::Identifier(s) => AnnotationValueRef::Identifier(s),
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<AnnotationRef<'_>> for Annotation {
+    fn from(annotation: AnnotationRef<'_>) -> Self {
+        Annotation {
+            name: annotation.name.to_string(),
+            args: annotation
+                .args
+                .into_iter()
+                .map(|arg| AnnotationArg {
+                    name: arg.name.map(String::from),
+                    value: match arg.value {
+                        AnnotationValueRef::Integer(n) => AnnotationValue::Integer(n),
+                        AnnotationValueRef::String(s) => AnnotationValue::String(s.into_owned()),
+                        AnnotationValueRef::Identifier(s) => {
+                            AnnotationValue::Identifier(s.to_string())
+                        }
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'a> From<&'a Annotations> for AnnotationsRef<'a> {
+    fn from(annotations: &'a Annotations) -> Self {
+        AnnotationsRef {
+            declaration: annotations.declaration.iter().map(Into::into).collect(),
+            members: annotations
+                .members
+                .iter()
+                .map(|(name, list)| (name.as_str(), list.iter().map(Into::into).collect()))
+                .collect(),
+            comments: CommentsRef {
+                declaration: annotations
+                    .comments
+                    .declaration
+                    .iter()
+                    .map(String::as_str)
+                    .collect(),
+                members: annotations
+                    .comments
+                    .members
+                    .iter()
+                    .map(|(name, list)| (name.as_str(), list.iter().map(String::as_str).collect()))
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl From<AnnotationsRef<'_>> for Annotations {
+    fn from(annotations: AnnotationsRef<'_>) -> Self {
+        Annotations {
+            declaration: annotations
+                .declaration
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            members: annotations
+                .members
+                .into_iter()
+                .map(|(name, list)| (name.to_string(), list.into_iter().map(Into::into).collect()))
+                .collect(),
+            comments: Comments {
+                declaration: annotations
+                    .comments
+                    .declaration
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                members: annotations
+                    .comments
+                    .members
+                    .into_iter()
+                    .map(|(name, list)| {
+                        (
+                            name.to_string(),
+                            list.into_iter().map(String::from).collect(),
+                        )
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl<'a> From<&'a Declaration> for DeclarationRef<'a> {
+    fn from(decl: &'a Declaration) -> Self {
+        match decl {
+            Declaration::Enum {
+                ident,
+                base_type,
+                variants,
+                visibility,
+                annotations,
+            } => DeclarationRef::Enum {
+                ident,
+                base_type: base_type.clone(),
+                variants: variants
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.clone()))
+                    .collect(),
+                visibility: *visibility,
+                annotations: annotations.into(),
+            },
+            Declaration::Struct {
+                ident,
+                fields,
+                implements,
+                visibility,
+                annotations,
+            } => DeclarationRef::Struct {
+                ident,
+                fields: fields
+                    .iter()
+                    .map(|(name, field_type)| (name.as_str(), field_type.into()))
+                    .collect(),
+                implements: implements.iter().map(String::as_str).collect(),
+                visibility: *visibility,
+                annotations: annotations.into(),
+            },
+            Declaration::Interface {
+                ident,
+                fields,
+                visibility,
+                annotations,
+            } => DeclarationRef::Interface {
+                ident,
+                fields: fields
+                    .iter()
+                    .map(|(name, field_type)| (name.as_str(), field_type.into()))
+                    .collect(),
+                visibility: *visibility,
+                annotations: annotations.into(),
+            },
+            Declaration::Topic {
+                ident,
+                payload,
+                annotations,
+            } => DeclarationRef::Topic {
+                ident,
+                payload,
+                annotations: annotations.into(),
+            },
+        }
+    }
+}
+
+impl From<DeclarationRef<'_>> for Declaration {
+    fn from(decl: DeclarationRef<'_>) -> Self {
+        match decl {
+            DeclarationRef::Enum {
+                ident,
+                base_type,
+                variants,
+                visibility,
+                annotations,
+            } => Declaration::Enum {
+                ident: ident.to_string(),
+                base_type,
+                variants: variants
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+                visibility,
+                annotations: annotations.into(),
+            },
+            DeclarationRef::Struct {
+                ident,
+                fields,
+                implements,
+                visibility,
+                annotations,
+            } => Declaration::Struct {
+                ident: ident.to_string(),
+                fields: fields
+                    .into_iter()
+                    .map(|(name, field_type)| (name.to_string(), field_type.into()))
+                    .collect(),
+                implements: implements.into_iter().map(String::from).collect(),
+                visibility,
+                annotations: annotations.into(),
+            },
+            DeclarationRef::Interface {
+                ident,
+                fields,
+                visibility,
+                annotations,
+            } => Declaration::Interface {
+                ident: ident.to_string(),
+                fields: fields
+                    .into_iter()
+                    .map(|(name, field_type)| (name.to_string(), field_type.into()))
+                    .collect(),
+                visibility,
+                annotations: annotations.into(),
+            },
+            DeclarationRef::Topic {
+                ident,
+                payload,
+                annotations,
+            } => Declaration::Topic {
+                ident: ident.to_string(),
+                payload: payload.to_string(),
+                annotations: annotations.into(),
+            },
+        }
+    }
+}
+
+impl<'a> From<&'a Schema> for SchemaRef<'a> {
+    fn from(schema: &'a Schema) -> Self {
+        SchemaRef {
+            metadata: schema
+                .metadata
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.into()))
+                .collect(),
+            declarations: schema.declarations.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<SchemaRef<'_>> for Schema {
+    fn from(schema: SchemaRef<'_>) -> Self {
+        Schema {
+            metadata: schema
+                .metadata
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.into()))
+                .collect(),
+            declarations: schema.declarations.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// The versions a field or variant without `@since` or `@removed` annotations is in
+pub const ALL_VERSIONS: Range<i64> = 1..i64::MAX;
+
+/// The targets `@only` and `@exclude` can name, each the language of some of the generators
+pub const TARGETS: &[&str] = &["rust", "dart"];
+
+impl Annotation {
+    /// The value of the only argument, if it is an unnamed integer
+    pub fn integer(&self) -> Option<i64> {
+        match self.args.as_slice() {
+            [
+                AnnotationArg {
+                    name: None,
+                    value: AnnotationValue::Integer(n),
+                },
+            ] => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+/// A piece of a `@key` template
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyPart<'a> {
+    /// Text copied into the key as is
+    Text(&'a str),
+    /// A `{field}` placeholder, replaced by the value of the field
+    Field(&'a str),
+}
+
+/// Split a `@key` template such as `user:{id}` into text and placeholders, or `None` if a brace
+/// isn't part of a placeholder or the text has characters that can't go in a string literal
+pub fn key_parts(template: &str) -> Option<Vec<KeyPart<'_>>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while !rest.is_empty() {
+        match rest.find('{') {
+            Some(0) => {
+                let end = rest.find('}')?;
+                let field = &rest[1..end];
+
+                if field.is_empty() || field.contains('{') {
+                    return None;
+                }
+                parts.push(KeyPart::Field(field));
+                rest = &rest[end + 1..];
+            }
+            start => {
+                let (text, tail) = rest.split_at(start.unwrap_or(rest.len()));
+
+                if text
+                    .chars()
+                    .any(|c| matches!(c, '}' | '"' | '\'' | '\\' | '$') || c.is_control())
+                {
+                    return None;
+                }
+                parts.push(KeyPart::Text(text));
+                rest = tail;
+            }
+        }
+    }
+
+    Some(parts)
+}
+
+impl Annotation {
+    /// The value of the only argument, if it is an unnamed string
+    pub fn string(&self) -> Option<&str> {
+        match self.args.as_slice() {
+            [
+                AnnotationArg {
+                    name: None,
+                    value: AnnotationValue::String(s),
+                },
+            ] => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of the only argument, if it is an unnamed identifier
+    pub fn identifier(&self) -> Option<&str> {
+        AnnotationRef::from(self).identifier()
+    }
+
+    /// The value of the only argument, if it is a string called `name`
+    pub fn named_string(&self, name: &str) -> Option<&str> {
+        match self.args.as_slice() {
+            [
+                AnnotationArg {
+                    name: Some(arg_name),
+                    value: AnnotationValue::String(s),
+                },
+            ] if arg_name == name => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of the only argument, if it is an integer called `name`
+    pub fn named_integer(&self, name: &str) -> Option<i64> {
+        AnnotationRef::from(self).named_integer(name)
+    }
+
+    /// The bounds given by the arguments, as for [AnnotationRef::bounds]
+    pub fn bounds(&self) -> Option<Bounds> {
+        AnnotationRef::from(self).bounds()
+    }
+}
+
+impl Annotations {
+    /// Whether there are no annotations at all
+    pub fn is_empty(&self) -> bool {
+        self.declaration.is_empty() && self.members.is_empty()
+    }
+
+    /// The annotations on a field or variant
+    pub fn member(&self, member: &str) -> &[Annotation] {
+        self.members.get(member).map_or(&[], Vec::as_slice)
+    }
+
+    /// The variant marked `@default`, or the first variant if there isn't one
+    pub fn default_variant<'a>(
+        &self,
+        variants: &'a [(String, IntegerValue)],
+    ) -> &'a (String, IntegerValue) {
+        variants
+            .iter()
+            .find(|(name, _)| {
+                self.member(name)
+                    .iter()
+                    .any(|annotation| annotation.name == "default")
+            })
+            .unwrap_or(&variants[0])
+    }
+
+    /// The team that owns the declaration, from `@owner("team")`
+    pub fn owner(&self) -> Option<&str> {
+        self.declaration
+            .iter()
+            .find(|annotation| annotation.name == "owner")
+            .and_then(Annotation::string)
+    }
+
+    /// The template of the keys that values of a struct are stored under, from
+    /// `@key("user:{id}")`
+    pub fn key(&self) -> Option<&str> {
+        self.declaration
+            .iter()
+            .find(|annotation| annotation.name == "key")
+            .and_then(Annotation::string)
+    }
+
+    /// Whether a struct is application config, from `@config`
+    pub fn is_config(&self) -> bool {
+        self.declaration
+            .iter()
+            .any(|annotation| annotation.name == "config")
+    }
+
+    /// Whether a struct is the arguments of a command line tool, from `@cli`
+    pub fn is_cli(&self) -> bool {
+        self.declaration
+            .iter()
+            .any(|annotation| annotation.name == "cli")
+    }
+
+    /// Whether a struct is a flat record of scalar fields, from `@record`
+    pub fn is_record(&self) -> bool {
+        self.declaration
+            .iter()
+            .any(|annotation| annotation.name == "record")
+    }
+
+    /// The most bytes a struct may pack to, from `@budget(max_packed_bytes = N)`
+    pub fn budget(&self) -> Option<u64> {
+        self.declaration
+            .iter()
+            .filter(|annotation| annotation.name == "budget")
+            .find_map(|annotation| annotation.named_integer("max_packed_bytes"))
+            .map(|n| n as u64)
+    }
+
+    /// Whether a struct has a fixed in-memory layout shared with C, from `@abi(stable)`
+    pub fn is_abi_stable(&self) -> bool {
+        self.declaration
+            .iter()
+            .any(|annotation| annotation.name == "abi")
+    }
+
+    /// The value a struct field takes when it isn't given, from `@default(value)`
+    pub fn default_value(&self, member: &str) -> Option<&AnnotationValue> {
+        self.member(member)
+            .iter()
+            .filter(|annotation| annotation.name == "default")
+            .find_map(|annotation| annotation.args.first())
+            .map(|arg| &arg.value)
+    }
+
+    /// Whether a field is marked `@sensitive` or `@pii`
+    pub fn is_sensitive(&self, member: &str) -> bool {
+        self.member(member)
+            .iter()
+            .any(|annotation| matches!(annotation.name.as_str(), "sensitive" | "pii"))
+    }
+
+    /// The versions a field or variant is in, from its `@since` and `@removed` annotations
+    pub fn versions(&self, member: &str) -> Range<i64> {
+        let mut versions = ALL_VERSIONS;
+
+        for annotation in self.member(member) {
+            match (annotation.name.as_str(), annotation.integer()) {
+                ("since", Some(since)) => versions.start = since,
+                ("removed", Some(removed)) => versions.end = removed,
+                _ => {}
+            }
+        }
+
+        versions
+    }
+
+    /// Whether a struct field is marked `@flatten`, so its fields are inlined in JSON
+    pub fn is_flattened(&self, member: &str) -> bool {
+        self.member(member)
+            .iter()
+            .any(|annotation| annotation.name == "flatten")
+    }
+
+    /// Whether a field is in the code for `target`, from its `@only` and `@exclude` annotations.
+    /// With no target, whether it is in the code for every target.
+    pub fn in_target(&self, member: &str, target: Option<&str>) -> bool {
+        self.member(member).iter().all(|annotation| {
+            let named = annotation.args.iter().any(|arg| {
+                matches!(&arg.value, AnnotationValue::Identifier(name) if Some(name.as_str()) == target)
+            });
+
+            match annotation.name.as_str() {
+                "only" => named,
+                "exclude" => target.is_some() && !named,
+                _ => true,
+            }
+        })
+    }
+
+    /// The name of a field in JSON, from `@json(name = "...")` or else the field name
+    pub fn json_name<'a>(&'a self, member: &'a str) -> &'a str {
+        self.member(member)
+            .iter()
+            .filter(|annotation| annotation.name == "json")
+            .find_map(|annotation| annotation.named_string("name"))
+            .unwrap_or(member)
+    }
+
+    /// Whether `@allow` turns off `lint` for a field, or for the whole declaration
+    pub fn allows(&self, member: &str, lint: &str) -> bool {
+        self.declaration
+            .iter()
+            .chain(self.member(member))
+            .filter(|annotation| annotation.name == "allow")
+            .flat_map(|annotation| &annotation.args)
+            .any(|arg| matches!(&arg.value, AnnotationValue::Identifier(name) if name == lint))
+    }
+}
+
+impl Comments {
+    /// The comments on a field or variant
+    pub fn member(&self, member: &str) -> &[String] {
+        self.members.get(member).map_or(&[], Vec::as_slice)
+    }
+
+    /// The text of a comment without its markers, or the leading `*` on the lines of a block
+    /// comment, for use as documentation
+    pub fn text(comment: &str) -> String {
+        let (body, block) = match comment.strip_prefix("/*") {
+            Some(body) => (body.strip_suffix("*/").unwrap_or(body), true),
+            None => (comment.trim_start_matches('/'), false),
+        };
+        let lines: Vec<&str> = body
+            .lines()
+            .map(|line| {
+                let line = line.trim();
+
+                if block {
+                    line.strip_prefix('*').unwrap_or(line).trim_start()
+                } else {
+                    line
+                }
+            })
+            .collect();
+
+        lines.join("\n").trim().to_string()
+    }
+}
+
+impl<'a> AnnotationRef<'a> {
+    /// The value of the only argument, if it is an unnamed integer
+    pub fn integer(&self) -> Option<i64> {
+        match self.args.as_slice() {
+            [
+                AnnotationArgRef {
+                    name: None,
+                    value: AnnotationValueRef::Integer(n),
+                },
+            ] => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value of the only argument, if it is an unnamed string
+    pub fn string(&self) -> Option<&str> {
+        match self.args.as_slice() {
+            [
+                AnnotationArgRef {
+                    name: None,
+                    value: AnnotationValueRef::String(s),
+                },
+            ] => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of the only argument, if it is an unnamed identifier
+    pub fn identifier(&self) -> Option<&'a str> {
+        match self.args.as_slice() {
+            [
+                AnnotationArgRef {
+                    name: None,
+                    value: AnnotationValueRef::Identifier(s),
+                },
+            ] => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of the only argument, if it is a string called `name`
+    pub fn named_string(&self, name: &str) -> Option<&str> {
+        match self.args.as_slice() {
+            [
+                AnnotationArgRef {
+                    name: Some(arg_name),
+                    value: AnnotationValueRef::String(s),
+                },
+            ] if *arg_name == name => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of the only argument, if it is an integer called `name`
+    pub fn named_integer(&self, name: &str) -> Option<i64> {
+        match self.args.as_slice() {
+            [
+                AnnotationArgRef {
+                    name: Some(arg_name),
+                    value: AnnotationValueRef::Integer(n),
+                },
+            ] if *arg_name == name => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The bounds given by integer arguments `(min, max)`, or named `min` and `max` arguments,
+    /// or `None` if the arguments are anything else or the bounds are out of order
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut bounds = Bounds::default();
+
+        for (i, arg) in self.args.iter().enumerate() {
+            let AnnotationValueRef::Integer(n) = arg.value else {
+                return None;
+            };
+            let bound = match arg.name.or(["min", "max"].get(i).copied()) {
+                Some("min") => &mut bounds.min,
+                Some("max") => &mut bounds.max,
+                _ => return None,
+            };
+
+            if bound.replace(n).is_some() {
+                return None;
+            }
+        }
+
+        match bounds {
+            Bounds {
+                min: None,
+                max: None,
+            } => None,
+            Bounds {
+                min: Some(min),
+                max: Some(max),
+            } if min > max => None,
+            bounds => Some(bounds),
+        }
+    }
+}
+
+/// Inclusive bounds from an annotation such as `@range(0, 100)` or `@len(max = 64)`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Bounds {
+    /// Smallest allowed value, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<i64>,
+    /// Largest allowed value, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<i64>,
+}
+
+/// Metadata keys that set the naming convention for types, fields and variants
+const NAMING_KEYS: [&str; 3] = ["type_case", "field_case", "variant_case"];
+
+/// Naming conventions the metadata can ask for, named as in the generators' naming options
+const NAMING_CONVENTIONS: [&str; 4] = ["pascal", "camel", "snake", "screaming-snake"];
+
+/// Whether an identifier follows a naming convention.  Letters without case, as in many
+/// scripts, suit every convention.
+fn follows_case(ident: &str, case: &str) -> bool {
+    let first = ident.chars().next().unwrap_or_default();
+    let underscores = !ident.contains("__") && !ident.ends_with('_');
+
+    match case {
+        "pascal" => !first.is_lowercase() && !ident.contains('_'),
+        "camel" => !first.is_uppercase() && !ident.contains('_'),
+        "snake" => underscores && !ident.chars().any(char::is_uppercase),
+        "screaming-snake" => underscores && !ident.chars().any(char::is_lowercase),
+        _ => true,
+    }
+}
+
+/// An integer type that is wider than the values it holds, as found by [Schema::narrowings]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Narrowing {
+    /// The enum, or the field as `struct.field`
+    pub name: String,
+    /// The type as declared
+    pub from: IntegerType,
+    /// The narrowest type that holds every value
+    pub to: IntegerType,
+}
+
+impl AnnotationsRef<'_> {
+    /// Whether there are no annotations at all
+    pub fn is_empty(&self) -> bool {
+        self.declaration.is_empty() && self.members.is_empty()
+    }
+}
+
+impl FieldType {
+    fn nullable_mut(&mut self) -> &mut bool {
+        match self {
+            FieldType::Array(_, _, nullable)
+            | FieldType::Map(_, _, nullable)
+            | FieldType::Builtin(_, nullable)
+            | FieldType::UserDefined(_, nullable) => nullable,
+        }
+    }
+
+    /// Whether the field can be null
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            FieldType::Array(_, _, nullable)
+            | FieldType::Map(_, _, nullable)
+            | FieldType::Builtin(_, nullable)
+            | FieldType::UserDefined(_, nullable) => *nullable,
+        }
+    }
+
+    /// Whether the field type, or one inside it, is a nullable array or map of nullable values
+    fn is_double_nullable(&self) -> bool {
+        match self {
+            FieldType::Array(inner, _, nullable) | FieldType::Map(_, inner, nullable) => {
+                (*nullable && inner.is_nullable()) || inner.is_double_nullable()
+            }
+            FieldType::Builtin(_, _) | FieldType::UserDefined(_, _) => false,
+        }
+    }
+
+    /// Whether the field type, or one inside it, is a `u64`
+    fn has_u64(&self) -> bool {
+        match self {
+            FieldType::Array(inner, _, _) => inner.has_u64(),
+            FieldType::Map(key_type, value_type, _) => {
+                *key_type == MapKeyType::Builtin(BuiltinType::Integer(IntegerType::U64))
+                    || value_type.has_u64()
+            }
+            FieldType::Builtin(builtin_type, _) => {
+                *builtin_type == BuiltinType::Integer(IntegerType::U64)
+            }
+            FieldType::UserDefined(_, _) => false,
+        }
+    }
+
+    /// Add the user-defined types used in the field type to `types`
+    fn user_types<'a>(&'a self, types: &mut Vec<&'a str>) {
+        match self {
+            FieldType::Array(inner, _, _) => inner.user_types(types),
+            FieldType::Map(key_type, value_type, _) => {
+                if let MapKeyType::UserDefined(name) = key_type {
+                    types.push(name);
+                }
+                value_type.user_types(types);
+            }
+            FieldType::Builtin(_, _) => {}
+            FieldType::UserDefined(name, _) => types.push(name),
+        }
+    }
+}
+
+impl Schema {
+    /// The `version` metadata value, if there is one, as the number that `@since` and `@removed`
+    /// annotations count.  That is the major version of a semantic version.
+    pub fn version(&self) -> Option<i64> {
+        self.schema_version().map(SchemaVersion::major)
+    }
+
+    /// The `version` metadata value, if there is one, as an integer or a semantic version
+    pub fn schema_version(&self) -> Option<SchemaVersion> {
+        match self.metadata.get("version") {
+            Some(MetadataValue::Integer(IntegerValue::I64(version))) => {
+                Some(SchemaVersion::Integer(*version))
+            }
+            Some(MetadataValue::String(version)) => SchemaVersion::parse_semantic(version),
+            _ => None,
+        }
+    }
+
+    /// Whether the schema asks for strict mode with `strict = 1` in its metadata
+    pub fn is_strict(&self) -> bool {
+        self.metadata.get("strict") == Some(&MetadataValue::Integer(IntegerValue::I64(1)))
+    }
+
+    /// Whether the schema has `require_docs = 1` in its metadata, so that types, fields and
+    /// variants without doc comments are warned about
+    pub fn requires_docs(&self) -> bool {
+        self.metadata.get("require_docs") == Some(&MetadataValue::Integer(IntegerValue::I64(1)))
+    }
+
+    /// The naming convention the schema's metadata asks for with `key`, one of `type_case`,
+    /// `field_case` and `variant_case`
+    pub fn naming_convention(&self, key: &str) -> Option<&str> {
+        match self.metadata.get(key) {
+            Some(MetadataValue::String(case)) => Some(case),
+            _ => None,
+        }
+    }
+
+    /// Whether the schema's metadata sets any naming convention, which also turns on the
+    /// `short_name` lint
+    pub fn checks_names(&self) -> bool {
+        NAMING_KEYS
+            .iter()
+            .any(|key| self.naming_convention(key).is_some())
+    }
+
+    /// Whether the schema has `narrow_integers = 1` in its metadata, so that integer types wider
+    /// than their values are warned about
+    pub fn narrows_integers(&self) -> bool {
+        self.metadata.get("narrow_integers") == Some(&MetadataValue::Integer(IntegerValue::I64(1)))
+    }
+
+    /// Enums whose variant values, and integer fields whose `@range`, fit in a narrower type than
+    /// the one declared, less those allowed with `@allow(wide_integer)`.  Fields an interface
+    /// shares are left alone, as every struct that implements it would have to change too.
+    pub fn narrowings(&self) -> Vec<Narrowing> {
+        let interfaces: HashMap<&str, &Vec<(String, FieldType)>> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                Declaration::Interface { ident, fields, .. } => Some((ident.as_str(), fields)),
+                _ => None,
+            })
+            .collect();
+        let mut narrowings = Vec::new();
+
+        for decl in &self.declarations {
+            match decl {
+                Declaration::Enum {
+                    ident,
+                    base_type,
+                    variants,
+                    annotations,
+                    ..
+                } if !annotations.allows("", "wide_integer") => {
+                    let values = variants.iter().map(|(_, value)| value.to_i128());
+                    let to = IntegerType::narrowest(
+                        values.clone().min().unwrap_or(0),
+                        values.max().unwrap_or(0),
+                    );
+
+                    if to.bits() < base_type.bits() {
+                        narrowings.push(Narrowing {
+                            name: ident.clone(),
+                            from: base_type.clone(),
+                            to,
+                        });
+                    }
+                }
+                Declaration::Struct {
+                    ident,
+                    fields,
+                    implements,
+                    annotations,
+                    ..
+                } => {
+                    let shared = |name: &str| {
+                        implements
+                            .iter()
+                            .filter_map(|interface| interfaces.get(interface.as_str()))
+                            .any(|fields| fields.iter().any(|(field_name, _)| field_name == name))
+                    };
+
+                    for (name, field_type) in fields {
+                        let FieldType::Builtin(BuiltinType::Integer(from), _) = field_type else {
+                            continue;
+                        };
+                        let Some(Bounds {
+                            min: Some(min),
+                            max: Some(max),
+                        }) = annotations
+                            .member(name)
+                            .iter()
+                            .find(|annotation| annotation.name == "range")
+                            .and_then(Annotation::bounds)
+                        else {
+                            continue;
+                        };
+                        let to = IntegerType::narrowest(min as i128, max as i128);
+
+                        if to.bits() < from.bits()
+                            && !shared(name)
+                            && !annotations.allows(name, "wide_integer")
+                        {
+                            narrowings.push(Narrowing {
+                                name: format!("{ident}.{name}"),
+                                from: from.clone(),
+                                to,
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        narrowings
+    }
+
+    /// Check the rules of strict mode that apply to a built schema: every enum, struct and
+    /// interface has a doc comment, and there are no [warnings](Schema::warnings)
+    pub fn check_strict(&self) -> Result<(), GenoError> {
+        for decl in &self.declarations {
+            if let Declaration::Enum {
+                ident, annotations, ..
+            }
+            | Declaration::Struct {
+                ident, annotations, ..
+            }
+            | Declaration::Interface {
+                ident, annotations, ..
+            } = decl
+                && annotations.comments.declaration.is_empty()
+            {
+                return Err(GenoError::Strict(format!(
+                    "type '{ident}' has no doc comment"
+                )));
+            }
+        }
+
+        match self.warnings().into_iter().next() {
+            Some(warning) => Err(GenoError::Strict(warning.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// The schema as it was at `version`, without the fields and variants added after it or
+    /// removed by it
+    pub fn at_version(&self, version: i64) -> Schema {
+        let mut schema = self.clone();
+
+        for decl in &mut schema.declarations {
+            match decl {
+                Declaration::Enum {
+                    variants,
+                    annotations,
+                    ..
+                } => variants.retain(|(name, _)| annotations.versions(name).contains(&version)),
+                Declaration::Struct {
+                    fields,
+                    annotations,
+                    ..
+                }
+                | Declaration::Interface {
+                    fields,
+                    annotations,
+                    ..
+                } => fields.retain(|(name, _)| annotations.versions(name).contains(&version)),
+                Declaration::Topic { .. } => {}
+            }
+        }
+
+        schema
+    }
+
+    /// The schema with the fields and variants of every version, for reading data written at
+    /// any version.  Fields that some versions don't have are made nullable.
+    pub fn all_versions(&self) -> Schema {
+        let mut schema = self.clone();
+
+        for decl in &mut schema.declarations {
+            if let Declaration::Struct {
+                fields,
+                annotations,
+                ..
+            }
+            | Declaration::Interface {
+                fields,
+                annotations,
+                ..
+            } = decl
+            {
+                for (name, field_type) in fields {
+                    if annotations.versions(name) != ALL_VERSIONS {
+                        *field_type.nullable_mut() = true;
+                    }
+                }
+            }
+        }
+
+        schema
+    }
+
+    /// The fields that `@only` or `@exclude` leave out of some targets, as `struct.field`
+    pub fn target_fields(&self) -> Vec<String> {
+        let mut found = Vec::new();
+
+        for decl in &self.declarations {
+            if let Declaration::Struct {
+                ident,
+                fields,
+                annotations,
+                ..
+            }
+            | Declaration::Interface {
+                ident,
+                fields,
+                annotations,
+                ..
+            } = decl
+            {
+                for (name, _) in fields {
+                    if !annotations.in_target(name, None) {
+                        found.push(format!("{ident}.{name}"));
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// The schema with only the fields in the code for `target`, or with no target only the
+    /// fields every target has
+    pub fn for_target(&self, target: Option<&str>) -> Schema {
+        let mut schema = self.clone();
+
+        for decl in &mut schema.declarations {
+            if let Declaration::Struct {
+                fields,
+                annotations,
+                ..
+            }
+            | Declaration::Interface {
+                fields,
+                annotations,
+                ..
+            } = decl
+            {
+                fields.retain(|(name, _)| annotations.in_target(name, target));
+            }
+        }
+
+        schema
+    }
+
+    /// The top-level message types, which are the structs marked `@root` and the payloads of
+    /// topics, in the order they first appear
+    pub fn roots(&self) -> Vec<&str> {
+        let mut roots: Vec<&str> = Vec::new();
+
+        for decl in &self.declarations {
+            let root = match decl {
+                Declaration::Struct {
+                    ident, annotations, ..
+                } if annotations
+                    .declaration
+                    .iter()
+                    .any(|annotation| annotation.name == "root") =>
+                {
+                    ident
+                }
+                Declaration::Topic { payload, .. } => payload,
+                _ => continue,
+            };
+
+            if !roots.contains(&root.as_str()) {
+                roots.push(root);
+            }
+        }
+
+        roots
+    }
+
+    /// The enums and structs that no root uses, directly or through other types, in
+    /// declaration order.  Empty if the schema has no roots.
+    pub fn unreachable(&self) -> Vec<&str> {
+        let roots = self.roots();
+
+        if roots.is_empty() {
+            return Vec::new();
+        }
+
+        let reachable = self.reachable(roots);
+
+        self.declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                Declaration::Enum { ident, .. } | Declaration::Struct { ident, .. } => {
+                    Some(ident.as_str())
+                }
+                Declaration::Interface { .. } | Declaration::Topic { .. } => None,
+            })
+            .filter(|ident| !reachable.contains(ident))
+            .collect()
+    }
+
+    /// The types in `types` and every type their fields use, directly or through other types
+    fn reachable<'a>(&'a self, mut types: Vec<&'a str>) -> FnvHashSet<&'a str> {
+        let fields: HashMap<&str, &[(String, FieldType)]> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                Declaration::Struct { ident, fields, .. } => {
+                    Some((ident.as_str(), fields.as_slice()))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut reachable = FnvHashSet::default();
+
+        while let Some(ident) = types.pop() {
+            if reachable.insert(ident)
+                && let Some(fields) = fields.get(ident)
+            {
+                for (_, field_type) in fields.iter() {
+                    field_type.user_types(&mut types);
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// The schema with just the types in `only`, or every type if `only` is empty, less those in
+    /// `exclude`.  The types they use are kept too, even if excluded, as are the topics whose
+    /// payloads are kept and the interfaces kept structs implement.
+    pub fn subset(&self, only: &[String], exclude: &[String]) -> Result<Schema, GenoError> {
+        let types: FnvHashSet<&str> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                Declaration::Enum { ident, .. } | Declaration::Struct { ident, .. } => {
+                    Some(ident.as_str())
+                }
+                Declaration::Interface { .. } | Declaration::Topic { .. } => None,
+            })
+            .collect();
+
+        if let Some(name) = only
+            .iter()
+            .chain(exclude)
+            .find(|name| !types.contains(name.as_str()))
+        {
+            return Err(GenoError::UndefinedType(name.clone()));
+        }
+
+        let selected = types
+            .into_iter()
+            .filter(|ident| only.is_empty() || only.iter().any(|name| name == ident))
+            .filter(|ident| !exclude.iter().any(|name| name == ident))
+            .collect();
+        let kept = self.reachable(selected);
+        let implemented: FnvHashSet<&str> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                Declaration::Struct {
+                    ident, implements, ..
+                } if kept.contains(ident.as_str()) => Some(implements),
+                _ => None,
+            })
+            .flatten()
+            .map(String::as_str)
+            .collect();
+
+        Ok(Schema {
+            metadata: self.metadata.clone(),
+            declarations: self
+                .declarations
+                .iter()
+                .filter(|decl| match decl {
+                    Declaration::Enum { ident, .. } | Declaration::Struct { ident, .. } => {
+                        kept.contains(ident.as_str())
+                    }
+                    Declaration::Interface { ident, .. } => implemented.contains(ident.as_str()),
+                    Declaration::Topic { payload, .. } => kept.contains(payload.as_str()),
+                })
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// The schema with its declarations in a canonical order, so that moving declarations around
+    /// in the source doesn't change it.  Each type comes after the types it uses, and otherwise
+    /// in alphabetical order, with types that use each other ordered alphabetically.  Interfaces
+    /// are ordered along with the types, and topics follow in alphabetical order.
+    pub fn sorted(&self) -> Schema {
+        let types: BTreeMap<&str, &Declaration> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                Declaration::Enum { ident, .. }
+                | Declaration::Struct { ident, .. }
+                | Declaration::Interface { ident, .. } => Some((ident.as_str(), decl)),
+                Declaration::Topic { .. } => None,
+            })
+            .collect();
+        let mut uses: BTreeMap<&str, BTreeSet<&str>> = types
+            .iter()
+            .map(|(ident, decl)| {
+                let mut used = Vec::new();
+
+                if let Declaration::Struct { fields, .. } | Declaration::Interface { fields, .. } =
+                    decl
+                {
+                    for (_, field_type) in fields {
+                        field_type.user_types(&mut used);
+                    }
+                }
+                let used = used
+                    .into_iter()
+                    .filter(|name| name != ident && types.contains_key(name))
+                    .collect();
+
+                (*ident, used)
+            })
+            .collect();
+        let mut declarations = Vec::with_capacity(self.declarations.len());
+
+        while let Some(next) = uses
+            .iter()
+            .find(|(_, used)| used.is_empty())
+            .or_else(|| uses.first_key_value())
+            .map(|(ident, _)| *ident)
+        {
+            uses.remove(next);
+            for used in uses.values_mut() {
+                used.remove(next);
+            }
+            declarations.push(types[next].clone());
+        }
+
+        let topics: BTreeMap<&str, &Declaration> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                Declaration::Topic { ident, .. } => Some((ident.as_str(), decl)),
+                _ => None,
+            })
+            .collect();
+
+        declarations.extend(topics.into_values().cloned());
+
+        Schema {
+            metadata: self.metadata.clone(),
+            declarations,
+        }
+    }
+
+    /// Problems that don't stop the schema being used, such as types no root uses, less those
+    /// turned off with `@allow`
+    pub fn warnings(&self) -> Vec<GenoWarning> {
+        let unreachable = self.unreachable();
+        let requires_docs = self.requires_docs();
+        let mut warnings = Vec::new();
+
+        for decl in &self.declarations {
+            let (ident, annotations) = match decl {
+                Declaration::Enum {
+                    ident, annotations, ..
+                }
+                | Declaration::Struct {
+                    ident, annotations, ..
+                }
+                | Declaration::Interface {
+                    ident, annotations, ..
+                } => (ident, annotations),
+                Declaration::Topic { .. } => continue,
+            };
+
+            if unreachable.contains(&ident.as_str()) && !annotations.allows("", "unreachable_type")
+            {
+                warnings.push(GenoWarning::UnreachableType(ident.clone()));
+            }
+            if let Declaration::Struct { fields, .. } | Declaration::Interface { fields, .. } = decl
+            {
+                for (name, field_type) in fields {
+                    if field_type.is_double_nullable()
+                        && !annotations.allows(name, "double_nullable")
+                    {
+                        warnings.push(GenoWarning::DoubleNullable(format!("{ident}.{name}")));
+                    }
+                }
+            }
+            if requires_docs {
+                if annotations.comments.declaration.is_empty()
+                    && !annotations.allows("", "missing_docs")
+                {
+                    warnings.push(GenoWarning::MissingDoc(ident.clone()));
+                }
+                let members: Vec<&String> = match decl {
+                    Declaration::Enum { variants, .. } => {
+                        variants.iter().map(|(name, _)| name).collect()
+                    }
+                    Declaration::Struct { fields, .. } | Declaration::Interface { fields, .. } => {
+                        fields.iter().map(|(name, _)| name).collect()
+                    }
+                    Declaration::Topic { .. } => continue,
+                };
+
+                for name in members {
+                    if annotations.comments.member(name).is_empty()
+                        && !annotations.allows(name, "missing_docs")
+                    {
+                        warnings.push(GenoWarning::MissingDoc(format!("{ident}.{name}")));
+                    }
+                }
+            }
+        }
+        if self.checks_names() {
+            warnings.extend(self.naming_warnings());
+        }
+        if self.narrows_integers() {
+            for narrowing in self.narrowings() {
+                warnings.push(GenoWarning::WideInteger(
+                    narrowing.name,
+                    format!("{:?}", narrowing.from).to_lowercase(),
+                    format!("{:?}", narrowing.to).to_lowercase(),
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// Identifiers that don't follow the naming conventions in the metadata, or are too short to
+    /// say what they hold
+    fn naming_warnings(&self) -> Vec<GenoWarning> {
+        let mut warnings = Vec::new();
+
+        for decl in &self.declarations {
+            let (ident, members, member_key, annotations) = match decl {
+                Declaration::Enum {
+                    ident,
+                    variants,
+                    annotations,
+                    ..
+                } => (
+                    ident,
+                    variants.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                    "variant_case",
+                    annotations,
+                ),
+                Declaration::Struct {
+                    ident,
+                    fields,
+                    annotations,
+                    ..
+                }
+                | Declaration::Interface {
+                    ident,
+                    fields,
+                    annotations,
+                    ..
+                } => (
+                    ident,
+                    fields.iter().map(|(name, _)| name).collect(),
+                    "field_case",
+                    annotations,
+                ),
+                Declaration::Topic { .. } => continue,
+            };
+            // The declaration itself is the member with no name
+            let names = std::iter::once(("", "type_case"))
+                .chain(members.into_iter().map(|name| (name.as_str(), member_key)));
+
+            for (member, key) in names {
+                let (name, full_name) = if member.is_empty() {
+                    (ident.as_str(), ident.clone())
+                } else {
+                    (member, format!("{ident}.{member}"))
+                };
+
+                if let Some(case) = self.naming_convention(key)
+                    && !follows_case(name, case)
+                    && !annotations.allows(member, "naming_convention")
+                {
+                    warnings.push(GenoWarning::NamingConvention(
+                        full_name.clone(),
+                        case.to_string(),
+                    ));
+                }
+                if name.chars().skip(1).all(|c| c.is_ascii_digit())
+                    && !annotations.allows(member, "short_name")
+                {
+                    warnings.push(GenoWarning::ShortName(full_name));
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Problems with generating code for the schema in a target with the given capabilities
+    /// that don't stop code being generated, less those turned off with `@allow`
+    pub fn target_warnings(&self, capabilities: &Capabilities) -> Vec<GenoWarning> {
+        let mut warnings = Vec::new();
+
+        if capabilities.u64 {
+            return warnings;
+        }
+        for decl in &self.declarations {
+            if let Declaration::Struct {
+                ident,
+                fields,
+                annotations,
+                ..
+            } = decl
+            {
+                for (name, field_type) in fields {
+                    // Fields encoded as strings keep every digit
+                    let as_string = annotations.member(name).iter().any(|annotation| {
+                        annotation.name == "int64"
+                            && matches!(annotation.identifier(), Some("bigint" | "string"))
+                    });
+
+                    if field_type.has_u64()
+                        && !as_string
+                        && !annotations.allows(name, "u64_precision")
+                    {
+                        warnings.push(GenoWarning::LossyU64(format!("{ident}.{name}")));
+                    }
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Validate the schema, checking for duplicate type definitions and duplicate fields/variants within each declaration
+    pub fn validate(&self) -> Result<(), GenoError> {
+        SchemaRef::from(self).validate()
+    }
+
+    /// Validate the schema for a target with the given capabilities
+    pub fn validate_for(&self, capabilities: &Capabilities) -> Result<(), GenoError> {
+        SchemaRef::from(self).validate_for(capabilities)
+    }
+
+    /// Check an already validated schema against the capabilities of a target
+    pub fn check_capabilities(&self, capabilities: &Capabilities) -> Result<(), GenoError> {
+        SchemaRef::from(self).check_capabilities(capabilities)
+    }
+
+    /// SHA-256 of the schema in a canonical JSON form, as lowercase hex.  Formatting, comments
+    /// and the order of metadata entries don't change it.
+    pub fn fingerprint(&self) -> String {
+        let metadata: BTreeMap<&String, &MetadataValue> = self.metadata.iter().collect();
+        let canonical = serde_json::to_vec(&(metadata, &self.declarations))
+            .expect("schema is always serializable");
+
+        Sha256::digest(canonical)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// Schemas with fewer declarations than this are validated on the calling thread, as handing the
+/// work to the thread pool costs more than it saves
+const PARALLEL_DECLARATIONS: usize = 256;
+
+impl SchemaRef<'_> {
+    /// Validate the schema, checking for duplicate type definitions and duplicate fields/variants within each declaration
+    pub fn validate(&self) -> Result<(), GenoError> {
+        let expected_format: i64 = 1;
+
+        if self.metadata.get("format")
+            != Some(&MetadataValueRef::Integer(IntegerValue::I64(
+                expected_format,
+            )))
+        {
+            return Err(GenoError::InvalidMetadataFormat());
+        }
+
+        let version = match self.metadata.get("version") {
+            None => None,
+            Some(MetadataValueRef::Integer(IntegerValue::I64(version))) if *version > 0 => {
+                Some(*version)
+            }
+            Some(MetadataValueRef::String(version)) => match SchemaVersion::parse_semantic(version)
+            {
+                Some(version) => Some(version.major()),
+                None => return Err(GenoError::InvalidMetadataVersion()),
+            },
+            Some(_) => return Err(GenoError::InvalidMetadataVersion()),
+        };
+
+        match self.metadata.get("strict") {
+            None | Some(MetadataValueRef::Integer(IntegerValue::I64(0 | 1))) => {}
+            Some(_) => return Err(GenoError::InvalidMetadataStrict()),
+        }
+
+        match self.metadata.get("require_docs") {
+            None | Some(MetadataValueRef::Integer(IntegerValue::I64(0 | 1))) => {}
+            Some(_) => return Err(GenoError::InvalidMetadataRequireDocs()),
+        }
+
+        for key in NAMING_KEYS {
+            match self.metadata.get(key) {
+                None => {}
+                Some(MetadataValueRef::String(case)) if NAMING_CONVENTIONS.contains(&&**case) => {}
+                Some(_) => return Err(GenoError::InvalidMetadataNaming(key.to_string())),
+            }
+        }
+
+        match self.metadata.get("narrow_integers") {
+            None | Some(MetadataValueRef::Integer(IntegerValue::I64(0 | 1))) => {}
+            Some(_) => return Err(GenoError::InvalidMetadataNarrowIntegers()),
+        }
+
+        match self.metadata.get("imports") {
+            None => {}
+            Some(MetadataValueRef::String(value)) if crate::imports::parse(value).is_some() => {}
+            Some(_) => return Err(GenoError::InvalidMetadataImports()),
+        }
+
+        let index = TypeIndex::new(&self.declarations)?;
+
+        self.check_each("declarations", Self::check_declaration)?;
+        self.check_each("versions", |decl| Self::check_versions(decl, version))?;
+        self.check_each("field_annotations", Self::check_field_annotations)?;
+        self.check_each("roots", Self::check_roots)?;
+        self.check_each("owners", Self::check_owners)?;
+        self.check_each("defaults", Self::check_defaults)?;
+        self.check_each("allows", Self::check_allows)?;
+
+        // Check for undefined user-defined types
+        self.check_each("undefined_types", |decl| {
+            Self::struct_fields(decl)
+                .try_for_each(|field_type| Self::check_undefined_types(field_type, &index))
+        })?;
+
+        // Map keys must be hashable, which rules out structs
+        self.check_each("map_keys", |decl| {
+            Self::check_field_types(decl, &index, &Capabilities::ALL)
+        })?;
+        self.check_each("keys", |decl| Self::check_keys(decl, &index))?;
+        self.check_each("config", |decl| Self::check_marker(decl, "config"))?;
+        self.check_each("cli", |decl| Self::check_marker(decl, "cli"))?;
+        self.check_each("records", |decl| Self::check_records(decl, &index))?;
+
+        self.check_each("budgets", Self::check_budget)?;
+
+        let stable: FnvHashSet<&str> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                DeclarationRef::Struct {
+                    ident, annotations, ..
+                } if annotations
+                    .declaration
+                    .iter()
+                    .any(|annotation| annotation.name == "abi") =>
+                {
+                    Some(*ident)
+                }
+                _ => None,
+            })
+            .collect();
+
+        self.check_each("abi", |decl| Self::check_abi(decl, &index, &stable))?;
+
+        let enums: FnvHashMap<&str, &[(&str, IntegerValue)]> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                DeclarationRef::Enum {
+                    ident, variants, ..
+                } => Some((*ident, variants.as_slice())),
+                _ => None,
+            })
+            .collect();
+
+        self.check_each("field_defaults", |decl| {
+            Self::check_field_defaults(decl, &enums)
+        })?;
+
+        let structs: FnvHashMap<&str, &DeclarationRef> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                DeclarationRef::Struct { ident, .. } => Some((*ident, decl)),
+                _ => None,
+            })
+            .collect();
+
+        self.check_each("json_names", |decl| match decl {
+            DeclarationRef::Struct { ident, .. } => Self::check_json_names(
+                ident,
+                decl,
+                &structs,
+                &mut FnvHashSet::default(),
+                &mut vec![],
+            ),
+            _ => Ok(()),
+        })?;
+
+        self.check_each("abi_cycles", |decl| match decl {
+            DeclarationRef::Struct { ident, .. } if stable.contains(ident) => {
+                Self::check_abi_cycles(ident, &structs, &mut vec![])
+            }
+            _ => Ok(()),
+        })?;
+        self.check_each("topics", |decl| Self::check_topic(decl, &index))?;
+
+        let interfaces: FnvHashMap<&str, &DeclarationRef> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                DeclarationRef::Interface { ident, .. } => Some((*ident, decl)),
+                _ => None,
+            })
+            .collect();
+
+        self.check_each("interfaces", |decl| {
+            Self::check_interfaces(decl, &interfaces, &index)
+        })?;
+
+        let internal: FnvHashSet<&str> = self
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                DeclarationRef::Enum {
+                    ident,
+                    visibility: Visibility::Internal,
+                    ..
+                }
+                | DeclarationRef::Struct {
+                    ident,
+                    visibility: Visibility::Internal,
+                    ..
+                } => Some(*ident),
+                _ => None,
+            })
+            .collect();
+
+        if internal.is_empty() {
+            return Ok(());
+        }
+
+        self.check_each("visibility", |decl| Self::check_visibility(decl, &internal))
+    }
+
+    /// Validate the schema for a target with the given capabilities
+    pub fn validate_for(&self, capabilities: &Capabilities) -> Result<(), GenoError> {
+        self.validate()?;
+        self.check_capabilities(capabilities)
+    }
+
+    /// Check an already validated schema against the capabilities of a target
+    pub fn check_capabilities(&self, capabilities: &Capabilities) -> Result<(), GenoError> {
+        let index = TypeIndex::new(&self.declarations)?;
+
+        self.check_each("capabilities", |decl| {
+            Self::check_field_types(decl, &index, capabilities)
+        })
+    }
+
+    /// Run a check on every declaration, in parallel for large schemas.  The error returned is
+    /// always the one from the first offending declaration.
+    fn check_each<F>(&self, pass: &str, check: F) -> Result<(), GenoError>
+    where
+        F: Fn(&DeclarationRef) -> Result<(), GenoError> + Sync,
+    {
+        let start = Instant::now();
+        let err = if self.declarations.len() < PARALLEL_DECLARATIONS {
+            self.declarations.iter().find_map(|decl| check(decl).err())
+        } else {
+            self.declarations
+                .par_iter()
+                .with_min_len(PARALLEL_DECLARATIONS / 4)
+                .find_map_first(|decl| check(decl).err())
+        };
+
+        tracing::trace!(pass, elapsed = ?start.elapsed(), "validation pass");
+        err.map_or(Ok(()), Err)
+    }
+
+    /// Check for duplicate fields/variants within a declaration
+    fn check_declaration(decl: &DeclarationRef) -> Result<(), GenoError> {
+        match decl {
+            DeclarationRef::Enum {
+                ident, variants, ..
+            } => {
+                let mut variant_names = FnvHashSet::default();
+                let mut variant_values = FnvHashSet::default();
+
+                // Don't allow enum with no variants
+                if variants.is_empty() {
+                    return Err(GenoError::EmptyEnum(ident.to_string()));
+                }
+
+                for (variant_name, variant_value) in variants {
+                    if !variant_names.insert(*variant_name) {
+                        return Err(GenoError::DuplicateVariant(
+                            ident.to_string(),
+                            variant_name.to_string(),
+                        ));
+                    }
+
+                    if !variant_values.insert(variant_value.to_i128()) {
+                        return Err(GenoError::DuplicateVariantValue(
+                            variant_name.to_string(),
+                            Self::integer_value_str(variant_value),
+                        ));
+                    }
+                }
+            }
+
+            DeclarationRef::Struct { ident, fields, .. }
+            | DeclarationRef::Interface { ident, fields, .. } => {
+                let mut field_names = FnvHashSet::default();
+
+                for (field_name, _) in fields {
+                    if !field_names.insert(*field_name) {
+                        return Err(GenoError::DuplicateField(
+                            ident.to_string(),
+                            field_name.to_string(),
+                        ));
+                    }
+                }
+            }
+
+            DeclarationRef::Topic { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@range`, `@len` and `@pattern` annotations have good arguments and are on
+    /// fields of a type they apply to, and that `@sensitive` and `@pii` are only on fields
+    fn check_field_annotations(decl: &DeclarationRef) -> Result<(), GenoError> {
+        let (ident, annotations, fields) = match decl {
+            DeclarationRef::Struct {
+                ident,
+                annotations,
+                fields,
+                ..
+            }
+            | DeclarationRef::Interface {
+                ident,
+                annotations,
+                fields,
+                ..
+            } => (ident, annotations, fields.as_slice()),
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, &[][..]),
+        };
+        let is_field_only = |annotation: &&AnnotationRef| {
+            matches!(
+                annotation.name,
+                "range"
+                    | "len"
+                    | "pattern"
+                    | "sensitive"
+                    | "pii"
+                    | "int64"
+                    | "json"
+                    | "flatten"
+                    | "only"
+                    | "exclude"
+            )
+        };
+
+        if let Some(annotation) = annotations.declaration.iter().find(is_field_only) {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                format!("@{}", annotation.name),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            let field_type = fields
+                .iter()
+                .find(|(name, _)| name == member)
+                .map(|(_, field_type)| field_type);
+
+            for annotation in list.iter().filter(is_field_only) {
+                let valid = match (annotation.name, field_type) {
+                    (
+                        "range",
+                        Some(FieldTypeRef::Builtin(
+                            BuiltinType::Integer(_) | BuiltinType::Float(_),
+                            _,
+                        )),
+                    ) => annotation.bounds().is_some(),
+                    (
+                        "len",
+                        Some(
+                            FieldTypeRef::Builtin(BuiltinType::String, _)
+                            | FieldTypeRef::Array(..)
+                            | FieldTypeRef::Map(..),
+                        ),
+                    ) => annotation
+                        .bounds()
+                        .is_some_and(|bounds| bounds.min.unwrap_or(0) >= 0),
+                    ("pattern", Some(FieldTypeRef::Builtin(BuiltinType::String, _))) => {
+                        annotation.string().is_some()
+                    }
+                    ("sensitive" | "pii", Some(_)) => annotation.args.is_empty(),
+                    (
+                        "int64",
+                        Some(FieldTypeRef::Builtin(
+                            BuiltinType::Integer(IntegerType::I64 | IntegerType::U64),
+                            _,
+                        )),
+                    ) => matches!(
+                        annotation.identifier(),
+                        Some("int" | "bigint" | "string" | "error")
+                    ),
+                    // Whether the type is a struct is checked along with the JSON names
+                    ("flatten", Some(FieldTypeRef::UserDefined(_, false))) => {
+                        annotation.args.is_empty()
+                    }
+                    // Targets with the field read data written by those without it, so it has to
+                    // be nullable
+                    (
+                        "only" | "exclude",
+                        Some(
+                            FieldTypeRef::Array(.., true)
+                            | FieldTypeRef::Map(.., true)
+                            | FieldTypeRef::Builtin(_, true)
+                            | FieldTypeRef::UserDefined(_, true),
+                        ),
+                    ) => {
+                        !annotation.args.is_empty()
+                            && annotation.args.iter().all(|arg| {
+                                arg.name.is_none()
+                                    && matches!(arg.value, AnnotationValueRef::Identifier(target)
+                                        if TARGETS.contains(&target))
+                            })
+                    }
+                    // Names are written into string literals in several languages unescaped
+                    ("json", Some(_)) => annotation.named_string("name").is_some_and(|name| {
+                        !name.is_empty()
+                            && !name
+                                .chars()
+                                .any(|c| matches!(c, '"' | '\'' | '\\') || c.is_control())
+                    }),
+                    _ => false,
+                };
+
+                if !valid {
+                    return Err(GenoError::InvalidAnnotation(
+                        format!("{ident}.{member}"),
+                        format!("@{}", annotation.name),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@root` annotations are on structs and have no arguments
+    fn check_roots(decl: &DeclarationRef) -> Result<(), GenoError> {
+        let (ident, annotations, is_struct) = match decl {
+            DeclarationRef::Struct {
+                ident, annotations, ..
+            } => (ident, annotations, true),
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, false),
+        };
+
+        if let Some(annotation) = annotations
+            .declaration
+            .iter()
+            .find(|annotation| annotation.name == "root")
+            && (!is_struct || !annotation.args.is_empty())
+        {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                "@root".to_string(),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(|annotation| annotation.name == "root") {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@root".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@owner` is only on declarations, at most once, with a single string naming the
+    /// owner
+    fn check_owners(decl: &DeclarationRef) -> Result<(), GenoError> {
+        let (ident, annotations) = match decl {
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Struct {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations),
+        };
+        let mut owners = annotations
+            .declaration
+            .iter()
+            .filter(|annotation| annotation.name == "owner");
+
+        if owners.clone().count() > 1
+            || owners.any(|annotation| annotation.string().is_none_or(str::is_empty))
+        {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                "@owner".to_string(),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(|annotation| annotation.name == "owner") {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@owner".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@key` is only on structs, at most once, with a template whose placeholders name
+    /// fields that are integers, strings, bools or enums and aren't nullable
+    fn check_keys(decl: &DeclarationRef, index: &TypeIndex) -> Result<(), GenoError> {
+        let (ident, annotations, fields) = match decl {
+            DeclarationRef::Struct {
+                ident,
+                annotations,
+                fields,
+                ..
+            } => (ident, annotations, Some(fields)),
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, None),
+        };
+        let mut keys = annotations
+            .declaration
+            .iter()
+            .filter(|annotation| annotation.name == "key");
+        let valid = |annotation: &AnnotationRef| {
+            let (Some(fields), Some(parts)) = (fields, annotation.string().and_then(key_parts))
+            else {
+                return false;
+            };
+
+            parts.iter().all(|part| match part {
+                KeyPart::Text(_) => true,
+                KeyPart::Field(field) => fields.iter().any(|(name, ft)| {
+                    name == field
+                        && match ft {
+                            FieldTypeRef::Builtin(
+                                BuiltinType::Integer(_) | BuiltinType::String | BuiltinType::Bool,
+                                false,
+                            ) => true,
+                            FieldTypeRef::UserDefined(name, false) => {
+                                index.kind_of(name) == Some(TypeKind::Enum)
+                            }
+                            _ => false,
+                        }
+                }),
+            })
+        };
+
+        if keys.clone().count() > 1 || keys.any(|annotation| !valid(annotation)) {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                "@key".to_string(),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(|annotation| annotation.name == "key") {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@key".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@default` is only on enum variants, without arguments and at most once per enum,
+    /// or on struct fields, at most once with a single value, which [Self::check_field_defaults]
+    /// checks against the field's type
+    fn check_defaults(decl: &DeclarationRef) -> Result<(), GenoError> {
+        let (ident, annotations, is_enum, is_struct) = match decl {
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            } => (ident, annotations, true, false),
+            DeclarationRef::Struct {
+                ident, annotations, ..
+            } => (ident, annotations, false, true),
+            DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, false, false),
+        };
+        let is_default = |annotation: &&AnnotationRef| annotation.name == "default";
+
+        if annotations
+            .declaration
+            .iter()
+            .any(|annotation| is_default(&annotation))
+        {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                "@default".to_string(),
+            ));
+        }
+
+        let mut seen = false;
+
+        for (member, list) in &annotations.members {
+            let mut defaults = list.iter().filter(is_default);
+            let valid = if is_enum {
+                defaults.all(|annotation| {
+                    let first = !seen;
+
+                    seen = true;
+                    first && annotation.args.is_empty()
+                })
+            } else if is_struct {
+                defaults.clone().count() <= 1
+                    && defaults.all(|annotation| {
+                        matches!(annotation.args.as_slice(), [arg] if arg.name.is_none())
+                    })
+            } else {
+                defaults.next().is_none()
+            };
+
+            if !valid {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@default".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the `@default` of each struct field suits its type: an integer in range for
+    /// integers, or any integer for floats, a string for strings, `true` or `false` for bools, or
+    /// a variant of the enum.  Other types can't have defaults.
+    fn check_field_defaults(
+        decl: &DeclarationRef,
+        enums: &FnvHashMap<&str, &[(&str, IntegerValue)]>,
+    ) -> Result<(), GenoError> {
+        let DeclarationRef::Struct {
+            ident,
+            fields,
+            annotations,
+            ..
+        } = decl
+        else {
+            return Ok(());
+        };
+
+        for (name, field_type) in fields {
+            let Some(value) = annotations
+                .members
+                .get(name)
+                .into_iter()
+                .flatten()
+                .filter(|annotation| annotation.name == "default")
+                .find_map(|annotation| annotation.args.first())
+                .map(|arg| &arg.value)
+            else {
+                continue;
+            };
+            let valid = match (field_type, value) {
+                (
+                    FieldTypeRef::Builtin(BuiltinType::Integer(integer_type), _),
+                    AnnotationValueRef::Integer(n),
+                ) => integer_type.contains(*n as i128),
+                (
+                    FieldTypeRef::Builtin(BuiltinType::Float(_), _),
+                    AnnotationValueRef::Integer(_),
+                ) => true,
+                (FieldTypeRef::Builtin(BuiltinType::String, _), AnnotationValueRef::String(_)) => {
+                    true
+                }
+                (
+                    FieldTypeRef::Builtin(BuiltinType::Bool, _),
+                    AnnotationValueRef::Identifier(id),
+                ) => {
+                    matches!(*id, "true" | "false")
+                }
+                (FieldTypeRef::UserDefined(name, _), AnnotationValueRef::Identifier(id)) => enums
+                    .get(name)
+                    .is_some_and(|variants| variants.iter().any(|(variant, _)| variant == id)),
+                _ => false,
+            };
+
+            if !valid {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{name}"),
+                    "@default".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that a marker annotation, such as `@config`, is only on structs, without arguments
+    fn check_marker(decl: &DeclarationRef, marker: &str) -> Result<(), GenoError> {
+        let (ident, annotations, is_struct) = match decl {
+            DeclarationRef::Struct {
+                ident, annotations, ..
+            } => (ident, annotations, true),
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, false),
+        };
+
+        if annotations.declaration.iter().any(|annotation| {
+            annotation.name == marker && (!is_struct || !annotation.args.is_empty())
+        }) {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                format!("@{marker}"),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(|annotation| annotation.name == marker) {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    format!("@{marker}"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@record` is only on structs, without arguments, and that every field of a record
+    /// is a scalar, an integer, float, `string`, `bool` or enum, which can be nullable
+    fn check_records(decl: &DeclarationRef, index: &TypeIndex) -> Result<(), GenoError> {
+        let (ident, annotations, fields) = match decl {
+            DeclarationRef::Struct {
+                ident,
+                annotations,
+                fields,
+                ..
+            } => (ident, annotations, Some(fields)),
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, None),
+        };
+        let records: Vec<&AnnotationRef> = annotations
+            .declaration
+            .iter()
+            .filter(|annotation| annotation.name == "record")
+            .collect();
+
+        if !records.is_empty() {
+            let Some(fields) =
+                fields.filter(|_| records.iter().all(|annotation| annotation.args.is_empty()))
+            else {
+                return Err(GenoError::InvalidAnnotation(
+                    ident.to_string(),
+                    "@record".to_string(),
+                ));
+            };
+
+            for (name, ft) in fields.iter() {
+                let scalar = match ft {
+                    FieldTypeRef::Builtin(bt, _) => *bt != BuiltinType::LocalizedString,
+                    FieldTypeRef::UserDefined(name, _) => {
+                        index.kind_of(name) == Some(TypeKind::Enum)
+                    }
+                    _ => false,
+                };
+
+                if !scalar {
+                    return Err(GenoError::InvalidAnnotation(
+                        format!("{ident}.{name}"),
+                        "@record".to_string(),
+                    ));
+                }
+            }
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(|annotation| annotation.name == "record") {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@record".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@budget` is only on structs, with a `max_packed_bytes` that isn't negative
+    fn check_budget(decl: &DeclarationRef) -> Result<(), GenoError> {
+        let (ident, annotations, is_struct) = match decl {
+            DeclarationRef::Struct {
+                ident, annotations, ..
+            } => (ident, annotations, true),
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, false),
+        };
+
+        if annotations.declaration.iter().any(|annotation| {
+            annotation.name == "budget"
+                && !(is_struct
+                    && annotation
+                        .named_integer("max_packed_bytes")
+                        .is_some_and(|n| n >= 0))
+        }) {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                "@budget".to_string(),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(|annotation| annotation.name == "budget") {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@budget".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@abi(stable)` is only on structs, and that every field of such a struct is
+    /// FFI-safe: a non-nullable integer, float, `bool` or enum, another `@abi(stable)` struct, or
+    /// a fixed length array of one of these.  `stable` holds the structs marked `@abi`.
+    fn check_abi(
+        decl: &DeclarationRef,
+        index: &TypeIndex,
+        stable: &FnvHashSet<&str>,
+    ) -> Result<(), GenoError> {
+        let (ident, annotations, fields) = match decl {
+            DeclarationRef::Struct {
+                ident,
+                annotations,
+                fields,
+                ..
+            } => (ident, annotations, Some(fields)),
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, None),
+        };
+        let abis: Vec<&AnnotationRef> = annotations
+            .declaration
+            .iter()
+            .filter(|annotation| annotation.name == "abi")
+            .collect();
+
+        if !abis.is_empty() {
+            let Some(fields) = fields.filter(|_| {
+                abis.iter()
+                    .all(|annotation| annotation.identifier() == Some("stable"))
+            }) else {
+                return Err(GenoError::InvalidAnnotation(
+                    ident.to_string(),
+                    "@abi".to_string(),
+                ));
+            };
+
+            for (name, ft) in fields.iter() {
+                if !Self::is_ffi_safe(ft, index, stable) {
+                    return Err(GenoError::InvalidAnnotation(
+                        format!("{ident}.{name}"),
+                        "@abi".to_string(),
+                    ));
+                }
+            }
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(|annotation| annotation.name == "abi") {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@abi".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that an `@abi(stable)` struct doesn't contain itself, which would make it infinitely
+    /// large.  `stack` holds the structs containing this one.
+    fn check_abi_cycles<'a>(
+        ident: &'a str,
+        structs: &FnvHashMap<&'a str, &DeclarationRef<'a>>,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<(), GenoError> {
+        let Some(DeclarationRef::Struct { fields, .. }) = structs.get(ident) else {
+            return Ok(());
+        };
+
+        if stack.contains(&ident) {
+            return Err(GenoError::RecursiveType(ident.to_string()));
+        }
+        stack.push(ident);
+        for (_, field_type) in fields.iter() {
+            let mut field_type = field_type;
+            while let FieldTypeRef::Array(inner, ..) = field_type {
+                field_type = inner;
+            }
+            if let FieldTypeRef::UserDefined(name, _) = field_type {
+                Self::check_abi_cycles(name, structs, stack)?;
+            }
+        }
+        stack.pop();
+
+        Ok(())
+    }
+
+    /// Whether a field type has the same layout in C and Rust, for `@abi(stable)`
+    fn is_ffi_safe(ft: &FieldTypeRef, index: &TypeIndex, stable: &FnvHashSet<&str>) -> bool {
+        match ft {
+            FieldTypeRef::Builtin(bt, false) => matches!(
+                bt,
+                BuiltinType::Integer(_) | BuiltinType::Float(_) | BuiltinType::Bool
+            ),
+            FieldTypeRef::UserDefined(name, false) => {
+                index.kind_of(name) == Some(TypeKind::Enum) || stable.contains(name)
+            }
+            // C has no zero length arrays
+            FieldTypeRef::Array(inner, Some(length), false) => {
+                *length > 0 && Self::is_ffi_safe(inner, index, stable)
+            }
+            _ => false,
+        }
+    }
+
+    /// Check that no two fields of the struct `ident` have the same JSON name, including the fields
+    /// of the structs in `@flatten` fields, which must be structs that don't contain themselves.
+    /// `decl` is the struct whose fields are being added, `stack` the flattened structs above it.
+    fn check_json_names<'a>(
+        ident: &str,
+        decl: &'a DeclarationRef<'a>,
+        structs: &FnvHashMap<&str, &'a DeclarationRef<'a>>,
+        names: &mut FnvHashSet<&'a str>,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<(), GenoError> {
+        let DeclarationRef::Struct {
+            ident: decl_ident,
+            fields,
+            annotations,
+            ..
+        } = decl
+        else {
+            unreachable!("only structs are flattened");
+        };
+        let member = |name: &str| annotations.members.get(name).into_iter().flatten();
+
+        stack.push(decl_ident);
+        for (name, field_type) in fields {
+            if member(name).any(|annotation| annotation.name == "flatten") {
+                let Some((inner_ident, inner)) = (match field_type {
+                    FieldTypeRef::UserDefined(inner, _) => structs.get_key_value(inner),
+                    _ => None,
+                }) else {
+                    return Err(GenoError::InvalidAnnotation(
+                        format!("{decl_ident}.{name}"),
+                        "@flatten".to_string(),
+                    ));
+                };
+
+                if stack.contains(inner_ident) {
+                    return Err(GenoError::RecursiveType(inner_ident.to_string()));
+                }
+                Self::check_json_names(ident, inner, structs, names, stack)?;
+                continue;
+            }
+
+            let json_name = member(name)
+                .filter(|annotation| annotation.name == "json")
+                .find_map(|annotation| annotation.named_string("name"))
+                .unwrap_or(name);
+
+            if !names.insert(json_name) {
+                return Err(GenoError::DuplicateJsonName(
+                    ident.to_string(),
+                    json_name.to_string(),
+                ));
+            }
+        }
+        stack.pop();
+
+        Ok(())
+    }
+
+    /// Check that `@allow` annotations are on enums, structs or fields and name known lints
+    fn check_allows(decl: &DeclarationRef) -> Result<(), GenoError> {
+        // The lints that the declaration and its members can have `@allow` for
+        let (ident, annotations, on_declaration, on_members) = match decl {
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            } => (
+                ident,
+                annotations,
+                GenoWarning::LINTS,
+                GenoWarning::VARIANT_LINTS,
+            ),
+            DeclarationRef::Struct {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            } => (ident, annotations, GenoWarning::LINTS, GenoWarning::LINTS),
+            DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations, &[][..], &[][..]),
+        };
+        let is_lint = |lints: &'static [&'static str]| {
+            move |arg: &AnnotationArgRef| match arg {
+                AnnotationArgRef {
+                    name: None,
+                    value: AnnotationValueRef::Identifier(lint),
+                } => lints.contains(lint),
+                _ => false,
+            }
+        };
+        let is_bad = |lints: &'static [&'static str]| {
+            move |annotation: &AnnotationRef| {
+                annotation.name == "allow"
+                    && (annotation.args.is_empty() || !annotation.args.iter().all(is_lint(lints)))
+            }
+        };
+
+        if annotations.declaration.iter().any(is_bad(on_declaration)) {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                "@allow".to_string(),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            if list.iter().any(is_bad(on_members)) {
+                return Err(GenoError::InvalidAnnotation(
+                    format!("{ident}.{member}"),
+                    "@allow".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that `@since` and `@removed` annotations are on fields or variants, and that they
+    /// come in order no later than the schema `version`
+    fn check_versions(decl: &DeclarationRef, version: Option<i64>) -> Result<(), GenoError> {
+        let (ident, annotations) = match decl {
+            DeclarationRef::Enum {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Struct {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Interface {
+                ident, annotations, ..
+            }
+            | DeclarationRef::Topic {
+                ident, annotations, ..
+            } => (ident, annotations),
+        };
+
+        if let Some(annotation) = annotations
+            .declaration
+            .iter()
+            .find(|annotation| matches!(annotation.name, "since" | "removed"))
+        {
+            return Err(GenoError::InvalidAnnotation(
+                ident.to_string(),
+                format!("@{}", annotation.name),
+            ));
+        }
+
+        for (member, list) in &annotations.members {
+            let path = format!("{ident}.{member}");
+            let mut since = None;
+            let mut removed = None;
+
+            for annotation in list {
+                let slot = match annotation.name {
+                    "since" => &mut since,
+                    "removed" => &mut removed,
+                    _ => continue,
+                };
+
+                match annotation.integer() {
+                    Some(n) if n > 0 && slot.is_none() => *slot = Some(n),
+                    _ => {
+                        return Err(GenoError::InvalidAnnotation(
+                            path,
+                            format!("@{}", annotation.name),
+                        ));
+                    }
+                }
+            }
+
+            if since.is_none() && removed.is_none() {
+                continue;
+            }
+
+            let Some(version) = version else {
+                return Err(GenoError::InvalidVersion(
+                    path,
+                    "the metadata has no version".to_string(),
+                ));
+            };
+            let reason = match (since, removed) {
+                (Some(since), _) if since > version => {
+                    format!("added in {since} after the schema version {version}")
+                }
+                (_, Some(removed)) if removed > version => {
+                    format!("removed in {removed} after the schema version {version}")
+                }
+                (Some(since), Some(removed)) if removed <= since => {
+                    format!("removed in {removed} but not added until {since}")
+                }
+                _ => continue,
+            };
+
+            return Err(GenoError::InvalidVersion(path, reason));
+        }
+
+        Ok(())
+    }
+
+    /// Check that a topic's payload is a struct
+    fn check_topic(decl: &DeclarationRef, index: &TypeIndex) -> Result<(), GenoError> {
+        match decl {
+            DeclarationRef::Topic { ident, payload, .. } => match index.kind_of(payload) {
+                Some(TypeKind::Struct) => Ok(()),
+                Some(TypeKind::Enum) => Err(GenoError::InvalidTopicPayload(
+                    ident.to_string(),
+                    payload.to_string(),
+                )),
+                Some(TypeKind::Interface) => Err(GenoError::InterfaceAsType(payload.to_string())),
+                None => Err(GenoError::UndefinedType(payload.to_string())),
+            },
+            DeclarationRef::Enum { .. }
+            | DeclarationRef::Struct { .. }
+            | DeclarationRef::Interface { .. } => Ok(()),
+        }
+    }
+
+    /// Check that a struct implements only interfaces, and has every field of each with the same
+    /// type and the same `@int64`, `@since` and `@removed` annotations
+    fn check_interfaces(
+        decl: &DeclarationRef,
+        interfaces: &FnvHashMap<&str, &DeclarationRef>,
+        index: &TypeIndex,
+    ) -> Result<(), GenoError> {
+        let DeclarationRef::Struct {
+            ident,
+            fields,
+            implements,
+            annotations,
+            ..
+        } = decl
+        else {
+            return Ok(());
+        };
+        fn encoding<'b>(
+            annotations: &'b AnnotationsRef,
+            field: &str,
+        ) -> Vec<&'b AnnotationRef<'b>> {
+            annotations
+                .members
+                .get(field)
+                .into_iter()
+                .flatten()
+                .filter(|annotation| {
+                    matches!(
+                        annotation.name,
+                        "int64" | "since" | "removed" | "only" | "exclude"
+                    )
+                })
+                .collect()
+        }
+
+        for name in implements {
+            let Some(DeclarationRef::Interface {
+                fields: shared,
+                annotations: shared_annotations,
+                ..
+            }) = interfaces.get(name)
+            else {
+                return Err(match index.kind_of(name) {
+                    None => GenoError::UndefinedType(name.to_string()),
+                    Some(_) => GenoError::NotAnInterface(ident.to_string(), name.to_string()),
+                });
+            };
+
+            for (field_name, field_type) in shared {
+                let matches = fields
+                    .iter()
+                    .any(|(name, ft)| name == field_name && ft == field_type)
+                    && encoding(annotations, field_name)
+                        == encoding(shared_annotations, field_name);
+
+                if !matches {
+                    return Err(GenoError::InterfaceMismatch(
+                        ident.to_string(),
+                        name.to_string(),
+                        field_name.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that public structs, interfaces and topics don't expose internal types
+    fn check_visibility(
+        decl: &DeclarationRef,
+        internal: &FnvHashSet<&str>,
+    ) -> Result<(), GenoError> {
+        let (ident, exposed) = match decl {
+            DeclarationRef::Struct {
+                ident,
+                visibility: Visibility::Public,
+                ..
+            }
+            | DeclarationRef::Interface {
+                ident,
+                visibility: Visibility::Public,
+                ..
+            } => (
+                ident,
+                Self::struct_fields(decl)
+                    .find_map(|field_type| Self::internal_type(field_type, internal)),
+            ),
+            DeclarationRef::Topic { ident, payload, .. } => (ident, internal.get(payload).copied()),
+            DeclarationRef::Enum { .. }
+            | DeclarationRef::Struct { .. }
+            | DeclarationRef::Interface { .. } => return Ok(()),
+        };
+
+        match exposed {
+            Some(name) => Err(GenoError::InternalType(ident.to_string(), name.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// The first internal type used by a field type, if any
+    fn internal_type<'b>(
+        field_type: &FieldTypeRef<'b>,
+        internal: &FnvHashSet<&str>,
+    ) -> Option<&'b str> {
+        match field_type {
+            FieldTypeRef::UserDefined(name, _) => internal.contains(name).then_some(*name),
+            FieldTypeRef::Array(inner, _, _) => Self::internal_type(inner, internal),
+            FieldTypeRef::Map(key_type, value_type, _) => match key_type {
+                MapKeyTypeRef::UserDefined(name) if internal.contains(name) => Some(*name),
+                _ => Self::internal_type(value_type, internal),
+            },
+            FieldTypeRef::Builtin(_, _) => None,
+        }
+    }
+
+    /// The field types of a struct or interface, or nothing for an enum or topic
+    fn struct_fields<'b>(decl: &'b DeclarationRef) -> impl Iterator<Item = &'b FieldTypeRef<'b>> {
+        let fields: &[(&str, FieldTypeRef)] = match decl {
+            DeclarationRef::Struct { fields, .. } | DeclarationRef::Interface { fields, .. } => {
+                fields
+            }
+            DeclarationRef::Enum { .. } | DeclarationRef::Topic { .. } => &[],
+        };
+
+        fields.iter().map(|(_, field_type)| field_type)
+    }
+
+    fn integer_value_str(v: &IntegerValue) -> String {
+        v.to_i128().to_string()
+    }
+
+    fn check_undefined_types(
+        field_type: &FieldTypeRef,
+        index: &TypeIndex,
+    ) -> Result<(), GenoError> {
+        match field_type {
+            FieldTypeRef::UserDefined(name, _) => match index.kind_of(name) {
+                None => return Err(GenoError::UndefinedType(name.to_string())),
+                Some(TypeKind::Interface) => {
+                    return Err(GenoError::InterfaceAsType(name.to_string()));
+                }
+                Some(TypeKind::Enum | TypeKind::Struct) => {}
+            },
+            FieldTypeRef::Array(inner, _, _) => {
+                Self::check_undefined_types(inner, index)?;
+            }
+            FieldTypeRef::Map(key_type, value_type, _) => {
+                if let MapKeyTypeRef::UserDefined(name) = key_type
+                    && index.kind_of(name).is_none()
+                {
+                    return Err(GenoError::UndefinedType(name.to_string()));
+                }
+                Self::check_undefined_types(value_type, index)?;
+            }
+            FieldTypeRef::Builtin(_, _) => {}
+        }
+        Ok(())
+    }
+
+    fn check_field_types(
+        decl: &DeclarationRef,
+        index: &TypeIndex,
+        capabilities: &Capabilities,
+    ) -> Result<(), GenoError> {
+        let (DeclarationRef::Struct { ident, fields, .. }
+        | DeclarationRef::Interface { ident, fields, .. }) = decl
+        else {
+            return Ok(());
+        };
+
+        fields.iter().try_for_each(|(name, field_type)| {
+            Self::check_field_type(field_type, (ident, name), index, capabilities)
+        })
+    }
+
+    /// Check the map keys and arrays within the type of a `(struct, field)`
+    fn check_field_type(
+        field_type: &FieldTypeRef,
+        field: (&str, &str),
+        index: &TypeIndex,
+        capabilities: &Capabilities,
+    ) -> Result<(), GenoError> {
+        match field_type {
+            FieldTypeRef::Map(key_type, value_type, _) => {
+                match key_type {
+                    // Floats have no stable equality, as NaN isn't equal to itself, so most
+                    // targets can't use them as map keys
+                    MapKeyTypeRef::Builtin(BuiltinType::Float(float_type))
+                        if !capabilities.float_map_keys =>
+                    {
+                        return Err(GenoError::UnsupportedMapKey(
+                            format!("{}.{}", field.0, field.1),
+                            match float_type {
+                                FloatType::F32 => "f32",
+                                FloatType::F64 => "f64",
+                            }
+                            .to_string(),
+                        ));
+                    }
+                    MapKeyTypeRef::Builtin(BuiltinType::LocalizedString) => {
+                        return Err(GenoError::InvalidMapKey("lstring".to_string()));
+                    }
+                    MapKeyTypeRef::UserDefined(name)
+                        if index.kind_of(name) != Some(TypeKind::Enum) =>
+                    {
+                        return Err(GenoError::InvalidMapKey(name.to_string()));
+                    }
+                    _ => {}
+                }
+                Self::check_field_type(value_type, field, index, capabilities)
+            }
+            FieldTypeRef::Array(_, Some(_), _) if !capabilities.fixed_arrays => Err(
+                GenoError::UnsupportedFixedArray(format!("{}.{}", field.0, field.1)),
+            ),
+            FieldTypeRef::Array(inner, _, _) => {
+                Self::check_field_type(inner, field, index, capabilities)
+            }
+            FieldTypeRef::Builtin(..) | FieldTypeRef::UserDefined(..) => Ok(()),
+        }
+    }
+}
+
+/// Every declared type, built before the checks that look up types by name
+struct TypeIndex<'a> {
+    interner: Interner<'a>,
+    // Indexed by symbol, as only type names are interned
+    kinds: Vec<TypeKind>,
+}
+
+impl<'a> TypeIndex<'a> {
+    /// Index the declarations, checking for duplicate type and topic definitions
+    fn new(declarations: &[DeclarationRef<'a>]) -> Result<Self, GenoError> {
+        let mut interner = Interner::new();
+        let mut kinds = Vec::with_capacity(declarations.len());
+        let mut topics = FnvHashSet::default();
+
+        for decl in declarations {
+            let (ident, kind) = match decl {
+                DeclarationRef::Enum { ident, .. } => (*ident, TypeKind::Enum),
+                DeclarationRef::Struct { ident, .. } => (*ident, TypeKind::Struct),
+                // Interfaces aren't types, but share their names with them in generated code
+                DeclarationRef::Interface { ident, .. } => (*ident, TypeKind::Interface),
+                // Topics aren't types, so they have names of their own
+                DeclarationRef::Topic { ident, .. } => {
+                    if !topics.insert(*ident) {
+                        return Err(GenoError::DuplicateTopic(ident.to_string()));
+                    }
+                    continue;
+                }
+            };
+
+            if interner.intern(ident).index() < kinds.len() {
+                return Err(GenoError::DuplicateType(ident.to_string()));
+            }
+            kinds.push(kind);
+        }
+
+        Ok(Self { interner, kinds })
+    }
+
+    fn kind_of(&self, name: &str) -> Option<TypeKind> {
+        self.interner
+            .get(name)
+            .map(|symbol| self.kinds[symbol.index()])
+    }
+}
+
+/// Whether a declared name is an enum, a struct or an interface
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TypeKind {
+    Enum,
+    Struct,
+    Interface,
+}
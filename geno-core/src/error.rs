@@ -0,0 +1,329 @@
+use crate::{Rule, ast::SchemaVersion};
+use pest::iterators::Pair;
+use pest::{Span, error::LineColLocation};
+use std::{
+    fmt::{self, Display},
+    path::Path,
+};
+use thiserror::Error;
+
+/// How much a change affects data on the wire, from least to most
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeClass {
+    /// Nothing changes on the wire
+    Patch,
+    /// Data can be added, old readers still understand what they knew about
+    Minor,
+    /// Old readers or writers no longer understand the data
+    Major,
+}
+
+impl fmt::Display for ChangeClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeClass::Patch => "patch",
+            ChangeClass::Minor => "minor",
+            ChangeClass::Major => "major",
+        })
+    }
+}
+
+/// A location within source file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Location {
+    /// The one-based line number of the error.
+    pub line: usize,
+    /// The one-based column number of the error.
+    pub column: usize,
+}
+
+/// Create a location from a pest span
+impl From<&Span<'_>> for Location {
+    fn from(s: &Span<'_>) -> Self {
+        let (line, column) = s.start_pos().line_col();
+        Self { line, column }
+    }
+}
+
+impl From<LineColLocation> for Location {
+    fn from(lc: LineColLocation) -> Self {
+        match lc {
+            LineColLocation::Pos(pos) => Self {
+                line: pos.0,
+                column: pos.1,
+            },
+            LineColLocation::Span(start, _) => Self {
+                line: start.0,
+                column: start.1,
+            },
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// This crates error enum
+#[derive(Error, Debug)]
+pub enum GenoError {
+    /// I/O error
+    #[error("i/o error")]
+    Io(#[from] std::io::Error),
+    /// Parsing error
+    #[error("unable to parse '{content}' ({file}:{location})")]
+    Parse {
+        /// Content that caused the parse failure
+        content: String,
+        /// File path of the schema
+        file: String,
+        /// [Location] of the parse error
+        location: Location,
+    },
+    /// Number out of range error
+    #[error("value out of range '{content}' ({file}:{location})")]
+    NumberRange {
+        /// The content that caused the error
+        content: String,
+        /// File path of the schema
+        file: String,
+        /// [Location] of the parse error
+        location: Location,
+    },
+    /// String literal has an escape that isn't recognized
+    #[error("invalid escape in '{content}' ({file}:{location})")]
+    InvalidEscape {
+        /// The string literal with the escape
+        content: String,
+        /// File path of the schema
+        file: String,
+        /// [Location] of the string literal
+        location: Location,
+    },
+    /// Duplicate type error
+    #[error("duplicate type '{0}'")]
+    DuplicateType(String),
+    /// Undefined type error
+    #[error("undefined type '{0}'")]
+    UndefinedType(String),
+    /// Type named without a namespace is declared by more than one of the packages imported
+    #[error("ambiguous type '{0}', which could be any of {1}")]
+    AmbiguousType(String, String),
+    /// Duplicate constant error
+    #[error("duplicate constant '{0}'")]
+    DuplicateConstant(String),
+    /// Undefined constant error
+    #[error("undefined constant '{0}'")]
+    UndefinedConstant(String),
+    /// Duplicate field error
+    #[error("duplicate field '{1}' in struct '{0}'")]
+    DuplicateField(String, String),
+    /// Two fields of a struct have the same name in JSON
+    #[error("duplicate JSON name '{1}' in struct '{0}'")]
+    DuplicateJsonName(String, String),
+    /// Duplicate enum variant name
+    #[error("duplicate variant name '{1}' in enum '{0}'")]
+    DuplicateVariant(String, String),
+    /// Duplicate enum value
+    #[error("duplicate variant value '{1}' in enum '{0}'")]
+    DuplicateVariantValue(String, String),
+    /// Duplicate topic error
+    #[error("duplicate topic '{0}'")]
+    DuplicateTopic(String),
+    /// Topic payload is not a struct
+    #[error("payload '{1}' of topic '{0}' must be a struct")]
+    InvalidTopicPayload(String, String),
+    /// Public struct or topic uses an internal type
+    #[error("'{0}' is public but uses internal type '{1}'")]
+    InternalType(String, String),
+    /// Interface is used as the type of a field, map key or topic payload
+    #[error("interface '{0}' can't be used as a type")]
+    InterfaceAsType(String),
+    /// Struct implements something that isn't an interface
+    #[error("struct '{0}' implements '{1}', which is not an interface")]
+    NotAnInterface(String, String),
+    /// Struct lacks a field of an interface it implements, or has it with another type
+    #[error("struct '{0}' doesn't match field '{2}' of interface '{1}'")]
+    InterfaceMismatch(String, String, String),
+    /// Enumeration has no variants
+    #[error("enum '{0}' has no variants")]
+    EmptyEnum(String),
+    /// Map key type is a struct
+    #[error("invalid map key type '{0}', keys must be integers, strings, bools, floats or enums")]
+    InvalidMapKey(String),
+    /// Map key type of a field can't be used as a key by the target
+    #[error("map key type '{1}' of '{0}' is not supported by the target")]
+    UnsupportedMapKey(String, String),
+    /// Field is a fixed length array and the target has no fixed length arrays
+    #[error("fixed length array '{0}' is not supported by the target")]
+    UnsupportedFixedArray(String),
+    /// The `wire` metadata value is not a known wire format
+    #[error("invalid wire format '{0}', expected 'positional', 'keyed' or 'bitmap'")]
+    InvalidWireFormat(String),
+    /// Struct contains itself through non-nullable fields, so no value of it can be built
+    #[error("struct '{0}' contains itself through non-nullable fields")]
+    RecursiveType(String),
+    /// Metadata format is not valid
+    #[error("metadata format is invalid")]
+    InvalidMetadataFormat(),
+    /// Metadata version is not a positive integer or a `"major.minor.patch"` string
+    #[error("metadata version is invalid, expected a positive integer or \"major.minor.patch\"")]
+    InvalidMetadataVersion(),
+    /// Metadata strict value is not 0 or 1
+    #[error("metadata strict is invalid, expected 0 or 1")]
+    InvalidMetadataStrict(),
+    /// Metadata require_docs value is not 0 or 1
+    #[error("metadata require_docs is invalid, expected 0 or 1")]
+    InvalidMetadataRequireDocs(),
+    /// Metadata naming convention is not one of the conventions schemas can ask for
+    #[error(
+        "metadata {0} is invalid, expected \"pascal\", \"camel\", \"snake\" or \"screaming-snake\""
+    )]
+    InvalidMetadataNaming(String),
+    /// Metadata narrow_integers value is not 0 or 1
+    #[error("metadata narrow_integers is invalid, expected 0 or 1")]
+    InvalidMetadataNarrowIntegers(),
+    /// Metadata imports value is not a list of module names
+    #[error("metadata imports is invalid, expected a string of comma separated module names")]
+    InvalidMetadataImports(),
+    /// Imported module isn't in a `geno_modules` directory above the schema
+    #[error("import '{0}' not found in geno_modules")]
+    UnresolvedImport(String),
+    /// Schema breaks a rule of strict mode
+    #[error("strict mode: {0}")]
+    Strict(String),
+    /// Annotation is in the wrong place or has bad arguments
+    #[error("invalid annotation '{1}' on '{0}'")]
+    InvalidAnnotation(String, String),
+    /// Versions given by `@since` and `@removed` are out of order
+    #[error("invalid versions for '{0}', {1}")]
+    InvalidVersion(String, String),
+    /// No generator is registered for the format
+    #[error("unknown generator '{0}'")]
+    UnknownGenerator(String),
+    /// Generator option is not in `key=value` form, has a bad value or isn't read by the generator
+    #[error("invalid generator option '{0}'")]
+    InvalidOption(String),
+    /// Field is a 64-bit integer and `int64=error` or `@int64(error)` doesn't allow them
+    #[error("field '{0}' is a 64-bit integer, which int64=error doesn't allow")]
+    Int64NotAllowed(String),
+    /// Field is left out of some targets, which only the keyed wire format allows
+    #[error("field '{0}' is left out of some targets, which needs wire=keyed")]
+    TargetFieldNeedsKeyed(String),
+    /// A round trip driver program could not be built or run
+    #[error("driver for '{0}' failed: {1}")]
+    Driver(String, String),
+    /// A schema package could not be fetched, installed or recorded
+    #[error("schema package error: {0}")]
+    Package(String),
+    /// A schema package's schemas don't have the hash recorded in the lockfile, so they have
+    /// changed since it was added
+    #[error("schema package '{0}' doesn't match its hash in geno-lock.json")]
+    PackageMismatch(String),
+    /// A schema registry request failed or was rejected
+    #[error("schema registry error: {0}")]
+    Registry(String),
+    /// A schema doesn't match or isn't compatible with the one in the registry
+    #[error("incompatible schema: {0}")]
+    Incompatible(String),
+    /// A schema's version doesn't match the class of its changes
+    #[error("{0} changes need version {2}, but the schema has version {1}")]
+    VersionMismatch(ChangeClass, SchemaVersion, SchemaVersion),
+    /// Manifest of generated files could not be read
+    #[error("invalid manifest '{0}': {1}")]
+    InvalidManifest(String, String),
+    /// Generator produced several files where only one can be written
+    #[error("generator produced more than one file, give an output directory")]
+    MultipleFiles(),
+    /// Template could not be loaded or rendered
+    #[error("template error: {0}")]
+    Template(String),
+}
+
+/// Problems with a schema that don't stop it being used
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum GenoWarning {
+    /// Type isn't used by any root, directly or through other types
+    #[error("type '{0}' isn't reachable from any root")]
+    UnreachableType(String),
+    /// Field is a nullable array or map of nullable values, such as `[string?]?`
+    #[error("field '{0}' is a nullable container of nullable values")]
+    DoubleNullable(String),
+    /// Field holds a `u64` and the target can't represent values above `i64::MAX`
+    #[error("field '{0}' is a u64, which loses precision above i64::MAX")]
+    LossyU64(String),
+    /// Type, field or variant has no doc comment and the schema has `require_docs = 1`
+    #[error("'{0}' has no doc comment")]
+    MissingDoc(String),
+    /// Enum or integer field is wider than its values and the schema has `narrow_integers = 1`
+    #[error("'{0}' is {1}, but its values fit in {2}")]
+    WideInteger(String, String, String),
+    /// Identifier doesn't follow the naming convention the schema's metadata asks for
+    #[error("'{0}' isn't {1} case")]
+    NamingConvention(String, String),
+    /// Identifier is a single character, or one followed only by digits, in a schema with a
+    /// naming convention
+    #[error("'{0}' is too short to say what it is")]
+    ShortName(String),
+}
+
+impl GenoWarning {
+    /// The names of every lint, as used by `@allow`
+    pub const LINTS: &[&str] = &[
+        "unreachable_type",
+        "double_nullable",
+        "u64_precision",
+        "missing_docs",
+        "wide_integer",
+        "naming_convention",
+        "short_name",
+    ];
+
+    /// The lints that can be allowed on an enum variant
+    pub const VARIANT_LINTS: &[&str] = &["missing_docs", "naming_convention", "short_name"];
+
+    /// Name of the lint that gave the warning
+    pub fn lint(&self) -> &'static str {
+        match self {
+            GenoWarning::UnreachableType(_) => "unreachable_type",
+            GenoWarning::DoubleNullable(_) => "double_nullable",
+            GenoWarning::LossyU64(_) => "u64_precision",
+            GenoWarning::MissingDoc(_) => "missing_docs",
+            GenoWarning::WideInteger(..) => "wide_integer",
+            GenoWarning::NamingConvention(..) => "naming_convention",
+            GenoWarning::ShortName(_) => "short_name",
+        }
+    }
+}
+
+impl GenoError {
+    /// Where in the source the error is, for the errors found while parsing
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            GenoError::Parse { location, .. }
+            | GenoError::NumberRange { location, .. }
+            | GenoError::InvalidEscape { location, .. } => Some(*location),
+            _ => None,
+        }
+    }
+
+    /// Create a new number range error
+    pub fn new_number_range_error(pair: &Pair<'_, Rule>, file_path: &Path) -> Self {
+        Self::NumberRange {
+            content: pair.as_str().to_string(),
+            file: file_path.to_string_lossy().into_owned(),
+            location: Location::from(&pair.as_span()),
+        }
+    }
+
+    /// Create a new invalid escape error
+    pub fn new_invalid_escape_error(pair: &Pair<'_, Rule>, file_path: &Path) -> Self {
+        Self::InvalidEscape {
+            content: pair.as_str().to_string(),
+            file: file_path.to_string_lossy().into_owned(),
+            location: Location::from(&pair.as_span()),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Imports of schema modules.  A schema lists the modules it uses in its `imports` metadata, as
+//! in `imports = "common, billing.invoice"`, and they are looked for in the nearest
+//! `geno_modules` directory above the schema.  A module name starts with a namespace, the
+//! directory of a package within `geno_modules`, followed by the path of a file within it:
+//!
+//! - `common` is every `.geno` file at the top of `geno_modules/common`
+//! - `billing.invoice` is `geno_modules/billing/invoice.geno`
+//!
+//! The declarations of the imported modules, and of the modules they import in turn, go in the
+//! schema ahead of its own, each under the namespace of its package, so `address` in the package
+//! `common` is `common.address`.  Two packages, or a package and the schema, can then both declare
+//! an `address`.  A type can be named with the namespace of its package, as in
+//! `home: common.address`, if the package is imported.  A type named without one is the one
+//! declared alongside the reference, by the schema itself or by the same package, and otherwise
+//! the one declared by whichever imported package has it, which must be only one of them.  Each
+//! module is read once, however often it is imported.
+use crate::GenoError;
+use std::{
+    fs,
+    path::{self, Path, PathBuf},
+};
+
+/// Name of the directory holding schema packages
+pub const MODULES_DIR: &str = "geno_modules";
+
+/// A module read for a schema's imports
+#[derive(Debug, Clone)]
+pub(crate) struct Module<S> {
+    /// Path of the module's file
+    pub path: PathBuf,
+    /// Namespace of the package the module is in
+    pub namespace: String,
+    /// Source of the module, normalized to NFC
+    pub source: S,
+}
+
+impl Module<String> {
+    /// The module with its source borrowed
+    pub fn as_ref(&self) -> Module<&str> {
+        Module {
+            path: self.path.clone(),
+            namespace: self.namespace.clone(),
+            source: &self.source,
+        }
+    }
+}
+
+/// The module names in an `imports` value, each split into its parts, or `None` if one isn't a
+/// dotted list of ASCII identifiers
+pub(crate) fn parse(value: &str) -> Option<Vec<Vec<&str>>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let parts: Vec<&str> = name.split('.').collect();
+
+            parts
+                .iter()
+                .all(|part| is_identifier(part))
+                .then_some(parts)
+        })
+        .collect()
+}
+
+/// Whether `s` is an ASCII identifier, which is also a valid directory and file name everywhere
+pub fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split the identifier of a type into the namespace of the package it was imported from, if it
+/// was, and its identifier within the package.
+/// "common.address" -> (Some("common"), "address"), "order" -> (None, "order")
+pub fn split_namespace(ident: &str) -> (Option<&str>, &str) {
+    match ident.split_once('.') {
+        Some((namespace, ident)) => (Some(namespace), ident),
+        None => (None, ident),
+    }
+}
+
+/// The nearest `geno_modules` directory above the schema at `file_path`
+pub fn modules_dir(file_path: &Path) -> Option<PathBuf> {
+    let dir = match file_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    path::absolute(dir)
+        .ok()?
+        .ancestors()
+        .map(|dir| dir.join(MODULES_DIR))
+        .find(|dir| dir.is_dir())
+}
+
+/// The schema files of the module `name` in the modules directory `dir`, in a stable order
+pub(crate) fn module_files(dir: &Path, name: &[&str]) -> Result<Vec<PathBuf>, GenoError> {
+    let unresolved = || GenoError::UnresolvedImport(name.join("."));
+
+    if let [namespace] = name {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir.join(namespace))
+            .map_err(|_| unresolved())?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "geno"))
+            .collect();
+
+        files.sort();
+        return if files.is_empty() {
+            Err(unresolved())
+        } else {
+            Ok(files)
+        };
+    }
+
+    let mut path: PathBuf = name
+        .iter()
+        .fold(dir.to_path_buf(), |path, part| path.join(part));
+
+    path.set_extension("geno");
+    if path.is_file() {
+        Ok(vec![path])
+    } else {
+        Err(unresolved())
+    }
+}
@@ -0,0 +1,484 @@
+//! Incremental re-parsing.  A [Document] keeps the source of a schema along with its AST and the
+//! byte range of each declaration.  An edit re-parses only the declarations it touches and
+//! patches them into the AST, then validates the whole schema again, which is cheap next to
+//! parsing.  Edits that reach the metadata, and edits to schemas with constants or block comments,
+//! fall back to parsing the whole schema.
+//!
+//! When the touched text doesn't parse, its declarations are left out of the AST, and the text
+//! is re-parsed along with the next edit until it parses again.  This keeps typing inside one
+//! declaration cheap even while the text is incomplete.
+use crate::{GenoAstBuilder, GenoError, Location, Rule, ast};
+use std::{collections::HashMap, ops::Range, path::PathBuf};
+
+/// What an edit caused to be re-parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reparse {
+    /// The whole schema
+    Full,
+    /// Only these declarations, as indices into the edited schema
+    Declarations(Range<usize>),
+}
+
+/// A schema being edited
+pub struct Document {
+    builder: GenoAstBuilder,
+    source: String,
+    parsed: Option<Parsed>,
+}
+
+/// The AST of the source as of the last successful parse
+struct Parsed {
+    schema: ast::Schema,
+    meta_end: usize,
+    spans: Vec<Range<usize>>,
+    // Text left out of the AST because it doesn't parse
+    hole: Option<Range<usize>>,
+    valid: bool,
+    // Constants can be used anywhere after them, so any edit may change other declarations
+    has_constants: bool,
+    strict: bool,
+}
+
+impl Document {
+    /// Parse a schema.  The file path is only used in error messages.  The document is created
+    /// even if the source has errors, which are returned alongside it.  The source isn't
+    /// normalized, as edit ranges refer to it, so it should already be NFC.
+    pub fn new(file_path: PathBuf, source: String) -> (Self, Result<(), GenoError>) {
+        let mut document = Document {
+            builder: GenoAstBuilder::new(file_path),
+            source,
+            parsed: None,
+        };
+        let result = document.parse_full().map(|_| ());
+
+        (document, result)
+    }
+
+    /// The current source text
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The AST, if the current source parses and validates
+    pub fn schema(&self) -> Option<&ast::Schema> {
+        self.parsed
+            .as_ref()
+            .filter(|parsed| parsed.valid)
+            .map(|parsed| &parsed.schema)
+    }
+
+    /// Replace the text in the byte `range` of the current source with `text`, then update the
+    /// AST.  The edit is kept even if the result has errors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds or not on character boundaries.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> Result<Reparse, GenoError> {
+        let region = self
+            .parsed
+            .as_ref()
+            .filter(|parsed| !parsed.has_constants && !self.source.contains("/*"))
+            .and_then(|parsed| parsed.region(&self.source, &range));
+
+        self.source.replace_range(range.clone(), text);
+
+        // Block comments can span lines, so the edit may start or end one outside the region
+        if let Some((declarations, region)) = region.filter(|_| !self.source.contains("/*")) {
+            let delta = text.len() as isize - range.len() as isize;
+
+            if let Some(reparse) = self.parse_region(declarations, region, delta) {
+                return reparse;
+            }
+        }
+
+        self.parse_full()
+    }
+
+    fn parse_full(&mut self) -> Result<Reparse, GenoError> {
+        self.parsed = None;
+
+        let mut pairs = self.builder.parse(Rule::_schema, &self.source)?;
+        let meta_pair = pairs.next().unwrap();
+        let meta_end = meta_pair.as_span().end();
+        let metadata = self.builder.build_meta_decl(meta_pair)?;
+        let strict = self.builder.is_strict(&metadata);
+        let mut constants = HashMap::new();
+        let (declarations, spans) =
+            self.builder
+                .build_declarations(pairs, meta_end, strict, &mut constants)?;
+        let schema = ast::SchemaRef {
+            metadata,
+            declarations,
+        };
+        let result = schema.validate();
+        let schema = ast::Schema::from(schema);
+        let result = result.and_then(|_| {
+            if strict {
+                schema.check_strict()
+            } else {
+                Ok(())
+            }
+        });
+
+        self.parsed = Some(Parsed {
+            schema,
+            meta_end,
+            spans,
+            hole: None,
+            valid: result.is_ok(),
+            has_constants: !constants.is_empty(),
+            strict,
+        });
+
+        result.map(|_| Reparse::Full)
+    }
+
+    /// Re-parse the declarations that were at the indices `declarations` from a `region` of the
+    /// source that has grown by `delta` bytes, or `None` if the region doesn't parse on its own
+    fn parse_region(
+        &mut self,
+        declarations: Range<usize>,
+        region: Range<usize>,
+        delta: isize,
+    ) -> Option<Result<Reparse, GenoError>> {
+        let parsed = self.parsed.as_mut()?;
+        let region = region.start..region.end.checked_add_signed(delta)?;
+        let mut constants = HashMap::new();
+        let (new_declarations, new_spans, parse_err) = match self
+            .builder
+            .parse(Rule::_declarations, &self.source[region.clone()])
+            .and_then(|pairs| {
+                self.builder
+                    .build_declarations(pairs, 0, parsed.strict, &mut constants)
+            }) {
+            // A new constant may be used by declarations outside the region
+            Ok(_) if !constants.is_empty() => return None,
+            Ok((declarations, spans)) => (declarations, spans, None),
+            Err(err) => (Vec::new(), Vec::new(), Some(err)),
+        };
+        let count = new_declarations.len();
+
+        for span in &mut parsed.spans[declarations.end..] {
+            *span = span.start.checked_add_signed(delta)?..span.end.checked_add_signed(delta)?;
+        }
+        parsed.spans.splice(
+            declarations.clone(),
+            new_spans
+                .into_iter()
+                .map(|span| span.start + region.start..span.end + region.start),
+        );
+        parsed.schema.declarations.splice(
+            declarations.clone(),
+            new_declarations.into_iter().map(ast::Declaration::from),
+        );
+
+        if let Some(err) = parse_err {
+            parsed.hole = Some(region.clone());
+            parsed.valid = false;
+
+            // Errors are located within the region, which starts at the beginning of a line
+            let lines = self.source[..region.start].matches('\n').count();
+
+            return Some(Err(match err {
+                GenoError::Parse {
+                    content,
+                    file,
+                    location,
+                } => GenoError::Parse {
+                    content,
+                    file,
+                    location: Location {
+                        line: location.line + lines,
+                        column: location.column,
+                    },
+                },
+                GenoError::NumberRange {
+                    content,
+                    file,
+                    location,
+                } => GenoError::NumberRange {
+                    content,
+                    file,
+                    location: Location {
+                        line: location.line + lines,
+                        column: location.column,
+                    },
+                },
+                GenoError::InvalidEscape {
+                    content,
+                    file,
+                    location,
+                } => GenoError::InvalidEscape {
+                    content,
+                    file,
+                    location: Location {
+                        line: location.line + lines,
+                        column: location.column,
+                    },
+                },
+                err => err,
+            }));
+        }
+
+        let result = parsed.schema.validate().and_then(|_| {
+            if parsed.strict {
+                parsed.schema.check_strict()
+            } else {
+                Ok(())
+            }
+        });
+
+        parsed.hole = None;
+        parsed.valid = result.is_ok();
+
+        Some(result.map(|_| Reparse::Declarations(declarations.start..declarations.start + count)))
+    }
+}
+
+impl Parsed {
+    /// The declarations affected by an edit of `range`, and the region of the source holding
+    /// them and any hole, or `None` if the edit reaches the metadata.  The region takes in whole
+    /// lines so that a comment started or ended by the edit stays inside it, along with the
+    /// comment lines around them and the declaration on the line after, whose leading comments
+    /// the edit may change.
+    fn region(&self, source: &str, range: &Range<usize>) -> Option<(Range<usize>, Range<usize>)> {
+        let (mut start, mut end) = match &self.hole {
+            Some(hole) => (range.start.min(hole.start), range.end.max(hole.end)),
+            None => (range.start, range.end),
+        };
+        let line_start = |at: usize| source[..at].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = |at: usize| source[at..].find('\n').map_or(source.len(), |i| at + i);
+        let is_comment = |line: &str| line.trim_start().starts_with("//");
+
+        loop {
+            start = line_start(start);
+            while start > 0 && is_comment(&source[line_start(start - 1)..start - 1]) {
+                start = line_start(start - 1);
+            }
+            end = line_end(end);
+            while end < source.len() && is_comment(&source[end + 1..line_end(end + 1)]) {
+                end = line_end(end + 1);
+            }
+
+            if start < self.meta_end {
+                return None;
+            }
+
+            let next_line_end = if end < source.len() {
+                line_end(end + 1)
+            } else {
+                end
+            };
+            let first = self.spans.partition_point(|span| span.end < start);
+            let last = self
+                .spans
+                .partition_point(|span| span.start <= next_line_end);
+            let (new_start, new_end) = if first < last {
+                (
+                    start.min(self.spans[first].start),
+                    end.max(self.spans[last - 1].end),
+                )
+            } else {
+                (start, end)
+            };
+
+            if (new_start, new_end) == (start, end) {
+                return Some((first..last, start..end));
+            }
+            start = new_start;
+            end = new_end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "meta { format = 1 }
+
+enum color: u8 { red = 1, green = 2 }
+
+struct point {
+    x: i32, // across
+    y: i32,
+}
+
+struct shape { c: color, p: point? }
+";
+
+    fn document() -> Document {
+        let (document, result) = Document::new("test.geno".into(), SOURCE.to_string());
+
+        result.unwrap();
+        document
+    }
+
+    fn full_parse(document: &Document) -> Option<ast::Schema> {
+        GenoAstBuilder::new("test.geno".into())
+            .build_from_str(document.source())
+            .ok()
+    }
+
+    #[test]
+    fn edit_one_declaration() {
+        let mut document = document();
+        let at = SOURCE.find("y: i32").unwrap();
+
+        assert_eq!(
+            document.edit(at..at + 1, "z").unwrap(),
+            Reparse::Declarations(1..2)
+        );
+        assert_eq!(document.schema().cloned(), full_parse(&document));
+
+        let at = document.source().find("struct shape").unwrap();
+
+        assert_eq!(
+            document.edit(at..at, "struct extra { a: bool }\n").unwrap(),
+            Reparse::Declarations(2..4)
+        );
+        assert_eq!(document.schema().cloned(), full_parse(&document));
+        assert_eq!(document.schema().unwrap().declarations.len(), 4);
+
+        assert_eq!(document.edit(0..0, " ").unwrap(), Reparse::Full);
+    }
+
+    #[test]
+    fn constants() {
+        let mut document = document();
+        let at = document.source().find("enum color").unwrap();
+
+        assert_eq!(
+            document.edit(at..at, "const RED = 1;\n").unwrap(),
+            Reparse::Full
+        );
+
+        let at = document.source().find("red = 1").unwrap();
+
+        assert_eq!(
+            document.edit(at + 6..at + 7, "RED + 2").unwrap(),
+            Reparse::Full
+        );
+
+        let at = document.source().find("RED = 1").unwrap();
+
+        assert_eq!(document.edit(at + 6..at + 7, "4").unwrap(), Reparse::Full);
+        assert_eq!(document.schema().cloned(), full_parse(&document));
+    }
+
+    #[test]
+    fn comments() {
+        let mut document = document();
+        let at = document.source().find("struct point").unwrap();
+
+        assert_eq!(
+            document.edit(at..at, "// A point\n").unwrap(),
+            Reparse::Declarations(1..2)
+        );
+        assert_eq!(document.schema().cloned(), full_parse(&document));
+        match &document.schema().unwrap().declarations[1] {
+            ast::Declaration::Struct { annotations, .. } => {
+                assert_eq!(annotations.comments.declaration, ["// A point"])
+            }
+            _ => panic!("expected a struct"),
+        }
+
+        let at = document.source().find("struct shape").unwrap();
+
+        document.edit(at..at, "// Second line\n").unwrap();
+        document.edit(at..at, "// First line\n").unwrap();
+        assert_eq!(document.schema().cloned(), full_parse(&document));
+
+        // Filling in the blank line joins the comments before it to the declaration
+        let at = document.source().find("\n\n// First").unwrap();
+
+        document.edit(at..at + 1, "\n// Joined").unwrap();
+        assert_eq!(document.schema().cloned(), full_parse(&document));
+
+        let at = document.source().find("// A point").unwrap();
+
+        assert!(document.edit(at..at, "/* ").is_err());
+        assert!(document.schema().is_none());
+        assert_eq!(
+            document.edit(at + 13..at + 13, " */").unwrap(),
+            Reparse::Full
+        );
+        assert_eq!(document.schema().cloned(), full_parse(&document));
+    }
+
+    #[test]
+    fn typing_through_errors() {
+        let mut document = document();
+        let text = "struct line { a: point, b: point }\n";
+        let mut at = document.source().len();
+
+        for c in text.chars() {
+            let result = document.edit(at..at, c.encode_utf8(&mut [0; 4]));
+
+            at += c.len_utf8();
+            assert_eq!(document.schema().cloned(), full_parse(&document));
+            assert_eq!(result.is_ok(), document.schema().is_some());
+        }
+        assert_eq!(document.schema().unwrap().declarations.len(), 4);
+
+        let at = document.source().find("point }").unwrap();
+        let err = document.edit(at..at + 5, "").unwrap_err();
+
+        match err {
+            GenoError::Parse { location, .. } => assert_eq!(location.line, 11),
+            _ => panic!("expected GenoError::Parse"),
+        }
+
+        // Deleting the comment marker leaves text that doesn't parse
+        let at = document.source().find("// across").unwrap();
+
+        assert!(document.edit(at..at + 2, "").is_err());
+        assert!(document.schema().is_none());
+        assert!(document.edit(at..at, "//").is_err());
+        assert!(document.schema().is_none());
+        assert!(full_parse(&document).is_none());
+    }
+
+    #[test]
+    fn matches_full_parse() {
+        let snippets = [
+            "",
+            " ",
+            "\n",
+            "}",
+            "{",
+            ",",
+            "x",
+            "//",
+            "// c\n",
+            "/*",
+            "*/",
+            "struct q { a: i8 }",
+            ": u8",
+        ];
+        let mut document = document();
+        let mut seed: u64 = 42;
+        let mut next = |n: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+
+        for _ in 0..500 {
+            let len = document.source().len();
+            let start = next(len + 1);
+            let end = (start + next(4)).min(len);
+
+            let _ = document.edit(start..end, snippets[next(snippets.len())]);
+            assert_eq!(document.schema().cloned(), full_parse(&document));
+
+            // Put the original back now and then, so most edits land on a valid schema
+            if next(8) == 0 {
+                let len = document.source().len();
+
+                document.edit(0..len, SOURCE).unwrap();
+            }
+        }
+    }
+}
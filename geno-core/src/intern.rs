@@ -0,0 +1,112 @@
+//! Identifier interning.  Each distinct identifier gets a small [Symbol], so that passes over
+//! large schemas can compare and index identifiers without hashing or copying strings again.
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasherDefault, Hasher},
+};
+
+/// An interned identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Index of the symbol, counting from zero in the order identifiers were first interned
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Interns identifiers borrowed from a schema
+#[derive(Debug, Clone, Default)]
+pub struct Interner<'a> {
+    symbols: HashMap<&'a str, Symbol, BuildHasherDefault<FnvHasher>>,
+    names: Vec<&'a str>,
+}
+
+impl<'a> Interner<'a> {
+    /// Create an empty interner
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The symbol for `name`, interning it if it hasn't been seen before
+    pub fn intern(&mut self, name: &'a str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+
+        let symbol = Symbol(self.names.len() as u32);
+
+        self.symbols.insert(name, symbol);
+        self.names.push(name);
+
+        symbol
+    }
+
+    /// The symbol for `name`, if it has been interned
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    /// The identifier for a symbol from this interner
+    pub fn resolve(&self, symbol: Symbol) -> &'a str {
+        self.names[symbol.index()]
+    }
+
+    /// Number of interned identifiers
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether nothing has been interned
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// A set using [FnvHasher], for small keys such as identifiers
+pub(crate) type FnvHashSet<T> = HashSet<T, BuildHasherDefault<FnvHasher>>;
+
+/// A map using [FnvHasher], for small keys such as identifiers
+pub(crate) type FnvHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FnvHasher>>;
+
+/// FNV-1a, which is much quicker than the default hasher for short keys such as identifiers
+pub(crate) struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intern_and_resolve() {
+        let mut interner = Interner::new();
+        let point = interner.intern("point");
+        let color = interner.intern("color");
+
+        assert_eq!(interner.intern("point"), point);
+        assert_ne!(point, color);
+        assert_eq!(color.index(), 1);
+        assert_eq!(interner.resolve(point), "point");
+        assert_eq!(interner.get("color"), Some(color));
+        assert_eq!(interner.get("shape"), None);
+        assert_eq!(interner.len(), 2);
+    }
+}
@@ -0,0 +1,3209 @@
+//! The core of the Geno schema compiler: the Abstract Syntax Tree (AST), the parser, validation
+//! and diagnostics.  Generator plugins depend on this crate alone, and the `geno` command line
+//! tool and its built-in generators are built on it.
+//!
+//! # Stability
+//!
+//! This crate follows semantic versioning separately from the command line tool.  Plugins are
+//! sent the [ast::Schema] serialized as MessagePack with named fields, so the owned AST types
+//! ([ast::Schema], [ast::Declaration], [ast::FieldType] and the types inside them) are held to a
+//! stricter rule than the rest of the API:
+//!
+//! - A patch release never changes what an AST serializes to
+//! - A minor release may add enum variants, metadata keys and annotations, which plugins built
+//!   against an older version see as unknown, but never removes or renames anything
+//! - Anything else, such as a removed or renamed field or variant, or a changed type, needs a
+//!   major release
+//!
+//! The borrowed `*Ref` types, the builder and the errors follow the usual rules for Rust APIs.
+
+#![warn(missing_docs)]
+
+/// Arena allocation of schema sources for large schemas
+pub mod arena;
+/// Namespace containing the AST structures
+pub mod ast; // Keep the `ast::` module prefixwhen exporting from this crate
+mod error;
+/// Imports of schema modules from `geno_modules`
+pub mod imports;
+/// Incremental re-parsing for editors
+pub mod incremental;
+/// Identifier interning for passes over large schemas
+pub mod intern;
+/// The lockfile of schema packages in `geno_modules`
+pub mod lockfile;
+
+use clock::Instant;
+pub use error::*;
+use pest::{
+    Parser as PestParser,
+    iterators::{Pair, Pairs},
+};
+use pest_derive::Parser;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    ops::Range,
+    path::PathBuf,
+};
+use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
+
+// `std::time::Instant` panics in browsers, so there the timings in the logs are all zero
+mod clock {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub use std::time::Instant;
+
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    #[derive(Clone, Copy)]
+    pub struct Instant;
+
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    impl Instant {
+        pub fn now() -> Self {
+            Instant
+        }
+
+        pub fn elapsed(&self) -> std::time::Duration {
+            std::time::Duration::ZERO
+        }
+    }
+}
+
+// Put the Pest parser in a private module to suppress doc warnings
+// See [Issue #326](https://github.com/pest-parser/pest/issues/326)
+mod parser {
+    use super::*;
+
+    #[derive(Parser)]
+    #[grammar = "geno.pest"]
+    pub struct GenoParser;
+}
+
+use parser::{GenoParser, Rule};
+
+use crate::{arena::Arena, ast::IntegerType, imports::Module};
+
+/// A Geno AST builder
+pub struct GenoAstBuilder {
+    file_path: PathBuf,
+    strict: bool,
+    verify_packages: bool,
+}
+
+impl GenoAstBuilder {
+    /// Create a new Geno AST builder from a file path.  A file path is required
+    /// in order to give meaningful error messages.
+    pub fn new(file_path: PathBuf) -> Self {
+        GenoAstBuilder {
+            file_path,
+            strict: false,
+            verify_packages: true,
+        }
+    }
+
+    /// Build schemas in strict mode, as if they had `strict = 1` in their metadata.  Enums must
+    /// give their base type, enums and structs need doc comments, and warnings are errors.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Check each package imported from `geno_modules` against the project's
+    /// [lockfile](lockfile), which is the default.  Only tools that are installing packages, and
+    /// so are about to write the lockfile, should turn this off.
+    pub fn verify_packages(mut self, verify_packages: bool) -> Self {
+        self.verify_packages = verify_packages;
+        self
+    }
+
+    /// Build and validate the AST
+    pub fn build(&self) -> Result<ast::Schema, GenoError> {
+        let input = std::fs::read_to_string(&self.file_path)?;
+
+        self.build_from_str(&input)
+    }
+
+    /// Build and validate the AST, along with the paths of the module files its
+    /// [imports](imports) read, as [GenoAstBuilder::build_from_str_with_modules] does
+    pub fn build_with_modules(&self) -> Result<(ast::Schema, Vec<PathBuf>), GenoError> {
+        let input = std::fs::read_to_string(&self.file_path)?;
+
+        self.build_from_str_with_modules(&input)
+    }
+
+    /// Build and validate the AST from schema source that has already been read.  The file path
+    /// is used in error messages, and to find the `geno_modules` directory of any
+    /// [imports](imports).  The source is normalized to NFC first, so identifiers that look the
+    /// same are the same.
+    pub fn build_from_str(&self, input: &str) -> Result<ast::Schema, GenoError> {
+        self.build_from_str_with_modules(input)
+            .map(|(schema, _)| schema)
+    }
+
+    /// Build and validate the AST like [GenoAstBuilder::build_from_str], along with the paths of
+    /// the module files its [imports](imports) read, for callers that watch the files a schema
+    /// came from
+    pub fn build_from_str_with_modules(
+        &self,
+        input: &str,
+    ) -> Result<(ast::Schema, Vec<PathBuf>), GenoError> {
+        let input = match is_nfc_quick(input.chars()) {
+            IsNormalized::Yes => Cow::Borrowed(input),
+            _ => Cow::Owned(input.nfc().collect()),
+        };
+        let modules = self.load_imports(&input)?;
+        let sources: Vec<Module<&str>> = modules.iter().map(Module::as_ref).collect();
+        let arena = Arena::new();
+        let schema = self.build_from_sources(Some(&arena), &input, &sources)?;
+
+        Ok((
+            ast::Schema::from(schema),
+            modules.into_iter().map(|module| module.path).collect(),
+        ))
+    }
+
+    /// Build and validate an AST that borrows its identifiers and strings from `input`, for
+    /// callers such as editors that re-parse the same schema often.  Identifiers are compared as
+    /// they are written, so `input` should already be normalized to NFC.  [Imports](imports)
+    /// aren't read, so a schema that uses imported types needs [GenoAstBuilder::build_from_str].
+    pub fn build_ref<'a>(&self, input: &'a str) -> Result<ast::SchemaRef<'a>, GenoError> {
+        self.build_from_sources(None, input, &[])
+    }
+
+    /// Read the schema into `arena` and build it with [GenoAstBuilder::build_from_str_in]
+    pub fn build_in<'a>(&self, arena: &'a Arena) -> Result<ast::SchemaRef<'a>, GenoError> {
+        let input = std::fs::read_to_string(&self.file_path)?;
+
+        self.build_from_str_in(arena, &input)
+    }
+
+    /// Build and validate an AST like [GenoAstBuilder::build_from_str], normalizing the source and
+    /// reading its [imports](imports), but keep the sources of the schema and its modules in
+    /// `arena`, and return an AST that borrows from them rather than copying every identifier
+    pub fn build_from_str_in<'a>(
+        &self,
+        arena: &'a Arena,
+        input: &str,
+    ) -> Result<ast::SchemaRef<'a>, GenoError> {
+        let input = match is_nfc_quick(input.chars()) {
+            IsNormalized::Yes => arena.alloc_str(input),
+            _ => arena.alloc_str(&input.nfc().collect::<String>()),
+        };
+        let modules: Vec<Module<&str>> = self
+            .load_imports(input)?
+            .into_iter()
+            .map(|module| Module {
+                source: arena.alloc_str(&module.source),
+                path: module.path,
+                namespace: module.namespace,
+            })
+            .collect();
+
+        self.build_from_sources(Some(arena), input, &modules)
+    }
+
+    /// Build and validate an AST, with the declarations of the imported modules ahead of those of
+    /// `input`.  The identifiers of imported declarations, qualified with their namespaces, are
+    /// kept in `arena`, which is only needed if there are modules.
+    fn build_from_sources<'a>(
+        &self,
+        arena: Option<&'a Arena>,
+        input: &'a str,
+        modules: &[Module<&'a str>],
+    ) -> Result<ast::SchemaRef<'a>, GenoError> {
+        let _span = tracing::debug_span!("build", file = %self.file_path.display()).entered();
+        let (schema, strict) = self.build_validated(arena, input, modules)?;
+
+        if strict {
+            let start = Instant::now();
+
+            ast::Schema::from(schema.clone()).check_strict()?;
+            tracing::debug!(elapsed = ?start.elapsed(), "checked strict mode");
+        }
+
+        Ok(schema)
+    }
+
+    /// Build and validate an AST, leaving the rules of strict mode for the caller, along with
+    /// whether the schema is built in strict mode
+    fn build_validated<'a>(
+        &self,
+        arena: Option<&'a Arena>,
+        input: &'a str,
+        modules: &[Module<&'a str>],
+    ) -> Result<(ast::SchemaRef<'a>, bool), GenoError> {
+        let start = Instant::now();
+        let mut schema_pairs = self.parse(Rule::_schema, input)?;
+        let meta_pair = schema_pairs.next().unwrap();
+        let meta_end = meta_pair.as_span().end();
+        let metadata = self.build_meta_decl(meta_pair)?;
+        let strict = self.is_strict(&metadata);
+        let mut declarations = Vec::new();
+        let mut scopes = Vec::new();
+
+        // Each module has its own constants, and is built as strictly as the schema
+        for module in modules {
+            let builder = GenoAstBuilder::new(module.path.clone());
+            let mut pairs = builder.parse(Rule::_schema, module.source)?;
+            let meta_pair = pairs.next().unwrap();
+            let meta_end = meta_pair.as_span().end();
+            let imports = Scope::imports(&builder.build_meta_decl(meta_pair)?);
+            let (module_declarations, _) =
+                builder.build_declarations(pairs, meta_end, strict, &mut HashMap::new())?;
+            let arena = arena.expect("modules need an arena");
+            let first = declarations.len();
+
+            declarations.extend(
+                module_declarations
+                    .into_iter()
+                    .map(|decl| qualify(decl, &module.namespace, arena)),
+            );
+            scopes.push(Scope {
+                namespace: Some(&module.namespace),
+                imports,
+                declarations: first..declarations.len(),
+            });
+        }
+
+        let (own_declarations, _) =
+            self.build_declarations(schema_pairs, meta_end, strict, &mut HashMap::new())?;
+
+        scopes.push(Scope {
+            namespace: None,
+            imports: Scope::imports(&metadata),
+            declarations: declarations.len()..declarations.len() + own_declarations.len(),
+        });
+        declarations.extend(own_declarations);
+        if !modules.is_empty() {
+            resolve_types(&mut declarations, &scopes)?;
+        }
+
+        let schema = ast::SchemaRef {
+            metadata,
+            declarations,
+        };
+
+        tracing::debug!(
+            bytes = input.len(),
+            declarations = schema.declarations.len(),
+            elapsed = ?start.elapsed(),
+            "parsed"
+        );
+
+        let start = Instant::now();
+
+        schema.validate()?;
+        tracing::debug!(elapsed = ?start.elapsed(), "validated");
+
+        Ok((schema, strict))
+    }
+
+    /// Schema source with the metadata `version` set, adding it if there isn't one.  A semantic
+    /// version is written as a string.  The rest of the source is left as written.
+    pub fn set_version(
+        &self,
+        input: &str,
+        version: impl Into<ast::SchemaVersion>,
+    ) -> Result<String, GenoError> {
+        let version = match version.into() {
+            version @ ast::SchemaVersion::Integer(_) => version.to_string(),
+            version @ ast::SchemaVersion::Semantic(..) => format!("\"{version}\""),
+        };
+        let meta_pair = self.parse(Rule::_schema, input)?.next().unwrap();
+        let entries = meta_pair.into_inner().next().unwrap().into_inner();
+        let mut output = input.to_string();
+        let mut end = 0;
+
+        for entry in entries {
+            let mut inner_pairs = entry.clone().into_inner();
+            let ident = inner_pairs.next().unwrap().as_str();
+            let value = inner_pairs.next().unwrap().as_span();
+
+            if ident == "version" {
+                output.replace_range(value.start()..value.end(), &version);
+                return Ok(output);
+            }
+            end = entry.as_span().end();
+        }
+
+        output.insert_str(end, &format!(", version = {version}"));
+
+        Ok(output)
+    }
+
+    /// Schema source with each of its [narrowings](ast::Schema::narrowings) made, so that enums
+    /// and integer fields have the narrowest type that holds their values, along with the
+    /// narrowings.  An enum without a base type gets one.  The rest of the source is left as
+    /// written, normalized to NFC.
+    pub fn narrow_integers(&self, input: &str) -> Result<(String, Vec<ast::Narrowing>), GenoError> {
+        let input: String = input.nfc().collect();
+        let modules = self.load_imports(&input)?;
+        let modules: Vec<Module<&str>> = modules.iter().map(Module::as_ref).collect();
+        let arena = Arena::new();
+        // Strict mode would turn the narrowings into errors before they could be made
+        let (schema, _) = self.build_validated(Some(&arena), &input, &modules)?;
+        let narrowings = ast::Schema::from(schema).narrowings();
+        let narrowed: HashMap<&str, &IntegerType> = narrowings
+            .iter()
+            .map(|narrowing| (narrowing.name.as_str(), &narrowing.to))
+            .collect();
+        let mut edits = Vec::new();
+
+        for pair in self.parse(Rule::_schema, &input)? {
+            match pair.as_rule() {
+                Rule::enum_decl => {
+                    let mut inner_pairs = pair.into_inner();
+                    let ident_pair = inner_pairs
+                        .find(|pair| pair.as_rule() == Rule::identifier)
+                        .unwrap();
+                    let Some(to) = narrowed.get(ident_pair.as_str()) else {
+                        continue;
+                    };
+
+                    match inner_pairs.next().unwrap() {
+                        base if base.as_rule() == Rule::integer_type => {
+                            let span = base.as_span();
+
+                            edits.push((span.start()..span.end(), to.as_str().to_string()));
+                        }
+                        _ => {
+                            let end = ident_pair.as_span().end();
+
+                            edits.push((end..end, format!(": {}", to.as_str())));
+                        }
+                    }
+                }
+                Rule::struct_decl => {
+                    let mut inner_pairs = pair.into_inner();
+                    let ident = inner_pairs
+                        .find(|pair| pair.as_rule() == Rule::identifier)
+                        .unwrap()
+                        .as_str();
+                    let field_list = inner_pairs
+                        .find(|pair| pair.as_rule() == Rule::struct_field_list)
+                        .unwrap();
+
+                    for field_pair in field_list.into_inner() {
+                        let mut field_pairs = field_pair
+                            .into_inner()
+                            .filter(|pair| pair.as_rule() != Rule::annotation);
+                        let name = field_pairs.next().unwrap().as_str();
+                        let Some(to) = narrowed.get(format!("{ident}.{name}").as_str()) else {
+                            continue;
+                        };
+                        // field_type > builtin_type > integer_type
+                        let span = field_pairs
+                            .next()
+                            .and_then(|pair| pair.into_inner().next())
+                            .and_then(|pair| pair.into_inner().next())
+                            .unwrap()
+                            .as_span();
+
+                        edits.push((span.start()..span.end(), to.as_str().to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut output = input;
+
+        // Later edits first, so the ranges of earlier ones still hold
+        edits.sort_by_key(|(range, _)| range.start);
+        for (range, replacement) in edits.into_iter().rev() {
+            output.replace_range(range, &replacement);
+        }
+
+        Ok((output, narrowings))
+    }
+
+    /// Read the modules the schema `input` imports, and the modules they import in turn, in the
+    /// order their declarations go in the schema
+    fn load_imports(&self, input: &str) -> Result<Vec<Module<String>>, GenoError> {
+        let mut modules = Vec::new();
+
+        self.load_imports_into(input, &mut HashSet::new(), &mut modules)?;
+
+        Ok(modules)
+    }
+
+    fn load_imports_into(
+        &self,
+        input: &str,
+        seen: &mut HashSet<PathBuf>,
+        modules: &mut Vec<Module<String>>,
+    ) -> Result<(), GenoError> {
+        let meta_pair = self.parse(Rule::_meta, input)?.next().unwrap();
+        let metadata = self.build_meta_decl(meta_pair)?;
+        let names = match metadata.get("imports") {
+            None => return Ok(()),
+            Some(ast::MetadataValueRef::String(value)) => {
+                imports::parse(value).ok_or(GenoError::InvalidMetadataImports())?
+            }
+            Some(_) => return Err(GenoError::InvalidMetadataImports()),
+        };
+        let Some(first) = names.first() else {
+            return Ok(());
+        };
+        let dir = imports::modules_dir(&self.file_path)
+            .ok_or_else(|| GenoError::UnresolvedImport(first.join(".")))?;
+
+        for name in &names {
+            // Each package is checked once, before any of its modules are read
+            if self.verify_packages && seen.insert(dir.join(name[0])) {
+                lockfile::verify_package(&dir, name[0])?;
+            }
+
+            for path in imports::module_files(&dir, name)? {
+                if !seen.insert(path.clone()) {
+                    continue;
+                }
+
+                let source: String = fs::read_to_string(&path)?.nfc().collect();
+
+                GenoAstBuilder::new(path.clone())
+                    .verify_packages(self.verify_packages)
+                    .load_imports_into(&source, seen, modules)?;
+                modules.push(Module {
+                    path,
+                    namespace: name[0].to_string(),
+                    source,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether to build in strict mode, either because the builder was asked to or because the
+    /// schema has `strict = 1` in its metadata
+    fn is_strict(&self, metadata: &HashMap<&str, ast::MetadataValueRef>) -> bool {
+        self.strict
+            || metadata.get("strict")
+                == Some(&ast::MetadataValueRef::Integer(ast::IntegerValue::I64(1)))
+    }
+
+    fn parse<'a>(&self, rule: Rule, input: &'a str) -> Result<Pairs<'a, Rule>, GenoError> {
+        GenoParser::parse(rule, input).map_err(|err| GenoError::Parse {
+            content: err.line().to_string(),
+            file: self.file_path.to_string_lossy().into_owned(),
+            location: Location::from(err.line_col),
+        })
+    }
+
+    /// Build the declarations that follow the metadata, which ends at byte `start`, along with
+    /// the byte range of each.  Constants are folded into the enum variants that use them and
+    /// added to `constants`, but aren't declarations themselves.
+    #[allow(clippy::while_let_on_iterator)]
+    fn build_declarations<'a>(
+        &self,
+        mut pairs: Pairs<'a, Rule>,
+        start: usize,
+        strict: bool,
+        constants: &mut HashMap<&'a str, i128>,
+    ) -> Result<(Vec<ast::DeclarationRef<'a>>, Vec<Range<usize>>), GenoError> {
+        let mut declarations = Vec::new();
+        let mut spans = Vec::new();
+        let mut prev_end = start;
+
+        while let Some(pair) = pairs.next() {
+            if pair.as_rule() == Rule::EOI {
+                break;
+            }
+
+            let rule = pair.as_rule();
+            let span = pair.as_span();
+            let comments =
+                leading_comments(&span.get_input()[prev_end..span.start()], prev_end == 0);
+
+            prev_end = span.end();
+
+            let declaration = match rule {
+                Rule::const_decl => {
+                    self.build_const_decl(pair, constants)?;
+                    continue;
+                }
+                Rule::enum_decl => self.build_enum_decl(pair, comments, strict, constants),
+                Rule::struct_decl => self.build_struct_decl(pair, comments),
+                Rule::interface_decl => self.build_interface_decl(pair, comments),
+                Rule::topic_decl => self.build_topic_decl(pair, comments),
+                _ => {
+                    unreachable!(); // Pest problem?
+                }
+            }?;
+
+            declarations.push(declaration);
+            spans.push(span.start()..span.end());
+        }
+
+        Ok((declarations, spans))
+    }
+
+    fn build_meta_decl<'a>(
+        &self,
+        pair: Pair<'a, Rule>,
+    ) -> Result<HashMap<&'a str, ast::MetadataValueRef<'a>>, GenoError> {
+        let mut inner_pairs = pair.into_inner();
+        let inner_pair = inner_pairs.next().unwrap();
+        let mut metadata = HashMap::new();
+
+        // Parse 'meta_data_entry' pairs
+        for entry_pair in inner_pair.into_inner() {
+            let mut inner_pairs = entry_pair.into_inner();
+            let ident = inner_pairs.next().unwrap().as_str();
+            let value_pair = inner_pairs.next().unwrap();
+            let value = match value_pair.as_rule() {
+                Rule::string_literal => {
+                    ast::MetadataValueRef::String(self.build_string_literal(value_pair)?)
+                }
+                Rule::integer_literal => ast::MetadataValueRef::Integer(
+                    self.build_integer_literal(IntegerType::I64, value_pair)?,
+                ),
+                _ => {
+                    unreachable!(); // Pest problem?
+                }
+            };
+
+            metadata.insert(ident, value);
+        }
+
+        Ok(metadata)
+    }
+
+    fn build_integer_type(&self, pair: Pair<'_, Rule>) -> Result<ast::IntegerType, GenoError> {
+        let s = pair.as_str();
+
+        match s {
+            "i8" => Ok(ast::IntegerType::I8),
+            "u8" => Ok(ast::IntegerType::U8),
+            "i16" => Ok(ast::IntegerType::I16),
+            "u16" => Ok(ast::IntegerType::U16),
+            "i32" => Ok(ast::IntegerType::I32),
+            "u32" => Ok(ast::IntegerType::U32),
+            "i64" => Ok(ast::IntegerType::I64),
+            "u64" => Ok(ast::IntegerType::U64),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The value of an integer literal, in any base and with its sign, before it is given a type.
+    /// Literals that can't be a value of any integer type are out of range.
+    fn build_integer_value(&self, pair: &Pair<'_, Rule>) -> Result<i128, GenoError> {
+        let s = pair.as_str();
+        let (negative, unsigned) = match s.as_bytes()[0] {
+            b'-' => (true, &s[1..]),
+            b'+' => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (digits, radix) = match unsigned.get(..2) {
+            Some("0b") => (&unsigned[2..], 2),
+            Some("0x") => (&unsigned[2..], 16),
+            _ => (unsigned, 10),
+        };
+
+        u64::from_str_radix(digits, radix)
+            .ok()
+            .map(|magnitude| {
+                if negative {
+                    -i128::from(magnitude)
+                } else {
+                    i128::from(magnitude)
+                }
+            })
+            .filter(|value| *value >= i64::MIN.into())
+            .ok_or_else(|| GenoError::new_number_range_error(pair, &self.file_path))
+    }
+
+    /// The value of an integer literal as a `base_type`
+    fn build_integer_literal(
+        &self,
+        base_type: IntegerType,
+        pair: Pair<'_, Rule>,
+    ) -> Result<ast::IntegerValue, GenoError> {
+        let value = self.build_integer_value(&pair)?;
+
+        coerce_integer(&base_type, value)
+            .ok_or_else(|| GenoError::new_number_range_error(&pair, &self.file_path))
+    }
+
+    /// The value of a string literal, without its quotes and with its escapes replaced.  Raw
+    /// strings are taken as they are, and only strings with escapes need to be copied.
+    fn build_string_literal<'a>(&self, pair: Pair<'a, Rule>) -> Result<Cow<'a, str>, GenoError> {
+        let literal = pair.as_str();
+
+        if let Some(raw) = literal.strip_prefix('r') {
+            let hashes = raw.len() - raw.trim_start_matches('#').len();
+
+            return Ok(Cow::Borrowed(&raw[hashes + 1..raw.len() - hashes - 1]));
+        }
+
+        let body = &literal[1..literal.len() - 1];
+
+        if !body.contains('\\') {
+            return Ok(Cow::Borrowed(body));
+        }
+
+        let mut value = String::with_capacity(body.len());
+        let mut chars = body.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+
+            let escaped = match chars.next() {
+                Some('n') => Some('\n'),
+                Some('r') => Some('\r'),
+                Some('t') => Some('\t'),
+                Some('0') => Some('\0'),
+                Some('\\') => Some('\\'),
+                Some('"') => Some('"'),
+                Some('u') => unicode_escape(&mut chars),
+                _ => None,
+            };
+
+            match escaped {
+                Some(c) => value.push(c),
+                None => {
+                    return Err(GenoError::new_invalid_escape_error(&pair, &self.file_path));
+                }
+            }
+        }
+
+        Ok(Cow::Owned(value))
+    }
+
+    fn build_const_decl<'a>(
+        &self,
+        pair: Pair<'a, Rule>,
+        constants: &mut HashMap<&'a str, i128>,
+    ) -> Result<(), GenoError> {
+        let mut inner_pairs = pair.into_inner();
+        let ident = inner_pairs.next().unwrap().as_str();
+        let value = self.build_const_expr(inner_pairs.next().unwrap(), constants)?;
+
+        if constants.insert(ident, value).is_some() {
+            return Err(GenoError::DuplicateConstant(ident.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Fold a constant expression, applying the operators from left to right
+    fn build_const_expr(
+        &self,
+        pair: Pair<'_, Rule>,
+        constants: &HashMap<&str, i128>,
+    ) -> Result<i128, GenoError> {
+        let range_error = || GenoError::new_number_range_error(&pair, &self.file_path);
+        let mut inner_pairs = pair.clone().into_inner();
+        let mut value = self.build_const_term(inner_pairs.next().unwrap(), constants)?;
+
+        while let Some(op_pair) = inner_pairs.next() {
+            let term = self.build_const_term(inner_pairs.next().unwrap(), constants)?;
+
+            value = match op_pair.as_str() {
+                "+" => value.checked_add(term),
+                "-" => value.checked_sub(term),
+                "|" => Some(value | term),
+                "<<" => u32::try_from(term)
+                    .ok()
+                    .and_then(|shift| value.checked_mul(1i128.checked_shl(shift)?)),
+                _ => unreachable!(),
+            }
+            // Anything beyond 64 bits can't be a value of any integer type
+            .filter(|value| (i64::MIN.into()..=u64::MAX.into()).contains(value))
+            .ok_or_else(range_error)?;
+        }
+
+        Ok(value)
+    }
+
+    fn build_const_term(
+        &self,
+        pair: Pair<'_, Rule>,
+        constants: &HashMap<&str, i128>,
+    ) -> Result<i128, GenoError> {
+        match pair.as_rule() {
+            Rule::integer_literal => self.build_integer_value(&pair),
+            Rule::identifier => constants
+                .get(pair.as_str())
+                .copied()
+                .ok_or_else(|| GenoError::UndefinedConstant(pair.as_str().to_string())),
+            Rule::const_expr => self.build_const_expr(pair, constants),
+            _ => unreachable!(), // Pest problem?
+        }
+    }
+
+    /// The value of an enum variant, which is an integer literal or an expression using
+    /// constants, as a `base_type`
+    fn build_variant_value(
+        &self,
+        base_type: IntegerType,
+        pair: Pair<'_, Rule>,
+        constants: &HashMap<&str, i128>,
+    ) -> Result<ast::IntegerValue, GenoError> {
+        let value = self.build_const_expr(pair.clone(), constants)?;
+        let mut inner_pairs = pair.clone().into_inner();
+
+        // Point errors at a lone term rather than the whole expression, which takes in the
+        // whitespace after it
+        let pair = match (inner_pairs.next(), inner_pairs.next()) {
+            (Some(term), None) => term,
+            _ => pair,
+        };
+
+        coerce_integer(&base_type, value)
+            .ok_or_else(|| GenoError::new_number_range_error(&pair, &self.file_path))
+    }
+
+    fn build_enum_decl<'a>(
+        &self,
+        enum_decl_pair: Pair<'a, Rule>,
+        comments: Vec<&'a str>,
+        strict: bool,
+        constants: &HashMap<&str, i128>,
+    ) -> Result<ast::DeclarationRef<'a>, GenoError> {
+        let mut inner_pairs = enum_decl_pair.into_inner();
+        let mut annotations = ast::AnnotationsRef {
+            declaration: self.build_annotations(&mut inner_pairs)?,
+            comments: ast::CommentsRef {
+                declaration: comments,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let visibility = self.build_visibility(&mut inner_pairs);
+        let ident_pair = inner_pairs.next().unwrap();
+        let ident = ident_pair.as_str();
+        let mut prev_end = ident_pair.as_span().end();
+        let mut next_pair = inner_pairs.next().unwrap();
+        let base_type;
+
+        if next_pair.as_rule() == Rule::integer_type {
+            prev_end = next_pair.as_span().end();
+            base_type = self.build_integer_type(next_pair)?;
+            next_pair = inner_pairs.next().unwrap();
+        } else if strict {
+            return Err(GenoError::Strict(format!(
+                "enum '{ident}' has no base type"
+            )));
+        } else {
+            // No base type specified, default to i32
+            base_type = ast::IntegerType::I32
+        };
+
+        // next_pair is now an 'enum_variant_list'
+        let mut variants: Vec<(&str, ast::IntegerValue)> = Vec::new();
+
+        for enum_variant_pair in next_pair.into_inner() {
+            let span = enum_variant_pair.as_span();
+            let comments = leading_comments(&span.get_input()[prev_end..span.start()], false);
+
+            prev_end = span.end();
+
+            let mut variant_inner = enum_variant_pair.into_inner();
+            let variant_annotations = self.build_annotations(&mut variant_inner)?;
+            let variant_ident = variant_inner.next().unwrap().as_str();
+            let variant_value = self.build_variant_value(
+                base_type.clone(),
+                variant_inner.next().unwrap(),
+                constants,
+            )?;
+
+            if !variant_annotations.is_empty() {
+                annotations
+                    .members
+                    .insert(variant_ident, variant_annotations);
+            }
+            if !comments.is_empty() {
+                annotations.comments.members.insert(variant_ident, comments);
+            }
+            variants.push((variant_ident, variant_value));
+        }
+
+        Ok(ast::DeclarationRef::Enum {
+            ident,
+            base_type,
+            variants,
+            visibility,
+            annotations,
+        })
+    }
+
+    fn build_struct_decl<'a>(
+        &self,
+        struct_decl_pair: Pair<'a, Rule>,
+        comments: Vec<&'a str>,
+    ) -> Result<ast::DeclarationRef<'a>, GenoError> {
+        let mut inner_pairs = struct_decl_pair.into_inner();
+        let mut annotations = ast::AnnotationsRef {
+            declaration: self.build_annotations(&mut inner_pairs)?,
+            comments: ast::CommentsRef {
+                declaration: comments,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let visibility = self.build_visibility(&mut inner_pairs);
+        let ident_pair = inner_pairs.next().unwrap();
+        let ident = ident_pair.as_str();
+        let mut prev_end = ident_pair.as_span().end();
+        let mut implements = Vec::new();
+
+        if inner_pairs
+            .peek()
+            .is_some_and(|pair| pair.as_rule() == Rule::implements)
+        {
+            let implements_pair = inner_pairs.next().unwrap();
+
+            prev_end = implements_pair.as_span().end();
+            implements.extend(implements_pair.into_inner().map(|pair| pair.as_str()));
+        }
+
+        // next pair is now a 'struct_field_list'
+        let fields = self.build_fields(inner_pairs.next().unwrap(), prev_end, &mut annotations)?;
+
+        // Parse struct declaration
+        Ok(ast::DeclarationRef::Struct {
+            ident,
+            fields,
+            implements,
+            visibility,
+            annotations,
+        })
+    }
+
+    fn build_interface_decl<'a>(
+        &self,
+        interface_decl_pair: Pair<'a, Rule>,
+        comments: Vec<&'a str>,
+    ) -> Result<ast::DeclarationRef<'a>, GenoError> {
+        let mut inner_pairs = interface_decl_pair.into_inner();
+        let mut annotations = ast::AnnotationsRef {
+            declaration: self.build_annotations(&mut inner_pairs)?,
+            comments: ast::CommentsRef {
+                declaration: comments,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let visibility = self.build_visibility(&mut inner_pairs);
+        let ident_pair = inner_pairs.next().unwrap();
+        let ident = ident_pair.as_str();
+        let fields = self.build_fields(
+            inner_pairs.next().unwrap(),
+            ident_pair.as_span().end(),
+            &mut annotations,
+        )?;
+
+        Ok(ast::DeclarationRef::Interface {
+            ident,
+            fields,
+            visibility,
+            annotations,
+        })
+    }
+
+    /// Build the fields of a `struct_field_list`, adding their annotations and comments to
+    /// `annotations`.  Comments on the first field follow byte `prev_end`.
+    fn build_fields<'a>(
+        &self,
+        struct_field_list_pair: Pair<'a, Rule>,
+        mut prev_end: usize,
+        annotations: &mut ast::AnnotationsRef<'a>,
+    ) -> Result<Vec<(&'a str, ast::FieldTypeRef<'a>)>, GenoError> {
+        let mut fields: Vec<(&str, ast::FieldTypeRef)> = Vec::new();
+
+        for struct_field_pair in struct_field_list_pair.into_inner() {
+            let span = struct_field_pair.as_span();
+            let comments = leading_comments(&span.get_input()[prev_end..span.start()], false);
+
+            prev_end = span.end();
+
+            let mut struct_field_inner = struct_field_pair.into_inner();
+            let field_annotations = self.build_annotations(&mut struct_field_inner)?;
+            let field_ident = struct_field_inner.next().unwrap().as_str();
+
+            if !field_annotations.is_empty() {
+                annotations.members.insert(field_ident, field_annotations);
+            }
+            if !comments.is_empty() {
+                annotations.comments.members.insert(field_ident, comments);
+            }
+            fields.push((
+                field_ident,
+                self.build_field_type(struct_field_inner.next().unwrap())?,
+            ));
+        }
+
+        Ok(fields)
+    }
+
+    fn build_topic_decl<'a>(
+        &self,
+        topic_decl_pair: Pair<'a, Rule>,
+        comments: Vec<&'a str>,
+    ) -> Result<ast::DeclarationRef<'a>, GenoError> {
+        let mut inner_pairs = topic_decl_pair.into_inner();
+        let annotations = ast::AnnotationsRef {
+            declaration: self.build_annotations(&mut inner_pairs)?,
+            comments: ast::CommentsRef {
+                declaration: comments,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        Ok(ast::DeclarationRef::Topic {
+            ident: inner_pairs.next().unwrap().as_str(),
+            payload: inner_pairs.next().unwrap().as_str(),
+            annotations,
+        })
+    }
+
+    /// Build the annotations at the front of `pairs`, leaving the pairs after them
+    /// Build the optional visibility that follows the annotations on an enum or struct
+    fn build_visibility(&self, pairs: &mut Pairs<Rule>) -> ast::Visibility {
+        if pairs
+            .peek()
+            .is_some_and(|pair| pair.as_rule() == Rule::visibility)
+            && pairs.next().unwrap().as_str() == "internal"
+        {
+            ast::Visibility::Internal
+        } else {
+            ast::Visibility::Public
+        }
+    }
+
+    fn build_annotations<'a>(
+        &self,
+        pairs: &mut Pairs<'a, Rule>,
+    ) -> Result<Vec<ast::AnnotationRef<'a>>, GenoError> {
+        let mut annotations = Vec::new();
+
+        while pairs
+            .peek()
+            .is_some_and(|pair| pair.as_rule() == Rule::annotation)
+        {
+            let mut inner_pairs = pairs.next().unwrap().into_inner();
+            let name = inner_pairs.next().unwrap().as_str();
+            let mut args = Vec::new();
+
+            // Remaining pairs are 'annotation_arg', each an optional name followed by a value
+            for arg_pair in inner_pairs {
+                let mut arg_inner: Vec<_> = arg_pair.into_inner().collect();
+                let value_pair = arg_inner.pop().unwrap();
+                let value = match value_pair.as_rule() {
+                    Rule::integer_literal => {
+                        match self.build_integer_literal(IntegerType::I64, value_pair)? {
+                            ast::IntegerValue::I64(n) => ast::AnnotationValueRef::Integer(n),
+                            _ => unreachable!(),
+                        }
+                    }
+                    Rule::string_literal => {
+                        ast::AnnotationValueRef::String(self.build_string_literal(value_pair)?)
+                    }
+                    Rule::identifier => ast::AnnotationValueRef::Identifier(value_pair.as_str()),
+                    _ => {
+                        unreachable!(); // Pest problem?
+                    }
+                };
+
+                args.push(ast::AnnotationArgRef {
+                    name: arg_inner.pop().map(|name_pair| name_pair.as_str()),
+                    value,
+                });
+            }
+
+            annotations.push(ast::AnnotationRef { name, args });
+        }
+
+        Ok(annotations)
+    }
+
+    #[allow(clippy::needless_bool)]
+    fn build_field_type<'a>(
+        &self,
+        pair: Pair<'a, Rule>,
+    ) -> Result<ast::FieldTypeRef<'a>, GenoError> {
+        let mut inner_pairs = pair.into_inner();
+        let inner_pair = inner_pairs.next().unwrap();
+
+        let nullable = if let Some(nullable_pair) = inner_pairs.peek() {
+            if nullable_pair.as_rule() == Rule::nullable {
+                true
+            } else {
+                false
+            }
+        } else {
+            false
+        };
+
+        match inner_pair.as_rule() {
+            Rule::array_type => {
+                let mut inner_pairs = inner_pair.into_inner();
+                let element_type_pair = inner_pairs.next().unwrap();
+                let length = if let Some(length_pair) = inner_pairs.next() {
+                    Some(length_pair.as_str().parse::<usize>().map_err(|_| {
+                        GenoError::new_number_range_error(&length_pair, &self.file_path)
+                    })?)
+                } else {
+                    None
+                };
+                Ok(ast::FieldTypeRef::Array(
+                    Box::new(self.build_field_type(element_type_pair)?),
+                    length,
+                    nullable,
+                ))
+            }
+            Rule::map_type => {
+                let mut inner_pairs = inner_pair.into_inner();
+                let key_type_pair = inner_pairs.next().unwrap();
+                let value_type_pair = inner_pairs.next().unwrap();
+
+                Ok(ast::FieldTypeRef::Map(
+                    self.build_map_key_type(key_type_pair)?,
+                    Box::new(self.build_field_type(value_type_pair)?),
+                    nullable,
+                ))
+            }
+            Rule::builtin_type => Ok(ast::FieldTypeRef::Builtin(
+                self.build_builtin_type(inner_pair)?,
+                nullable,
+            )),
+            Rule::type_ref => Ok(ast::FieldTypeRef::UserDefined(
+                inner_pair.as_str(),
+                nullable,
+            )),
+            _ => unreachable!(),
+        }
+    }
+
+    fn build_map_key_type<'a>(
+        &self,
+        pair: Pair<'a, Rule>,
+    ) -> Result<ast::MapKeyTypeRef<'a>, GenoError> {
+        let inner_pair = pair.into_inner().next().unwrap();
+
+        match inner_pair.as_rule() {
+            Rule::builtin_type => Ok(ast::MapKeyTypeRef::Builtin(
+                self.build_builtin_type(inner_pair)?,
+            )),
+            Rule::type_ref => Ok(ast::MapKeyTypeRef::UserDefined(inner_pair.as_str())),
+            _ => unreachable!(),
+        }
+    }
+
+    fn build_builtin_type(&self, pair: Pair<'_, Rule>) -> Result<ast::BuiltinType, GenoError> {
+        let mut inner_pairs = pair.into_inner();
+        let inner_pair = inner_pairs.next().unwrap();
+
+        match inner_pair.as_rule() {
+            Rule::integer_type => self
+                .build_integer_type(inner_pair)
+                .map(ast::BuiltinType::Integer),
+            Rule::float_type => {
+                let s = inner_pair.as_str();
+                match s {
+                    "f32" => Ok(ast::BuiltinType::Float(ast::FloatType::F32)),
+                    "f64" => Ok(ast::BuiltinType::Float(ast::FloatType::F64)),
+                    _ => unreachable!(),
+                }
+            }
+            Rule::string_type => Ok(ast::BuiltinType::String),
+            Rule::lstring_type => Ok(ast::BuiltinType::LocalizedString),
+            Rule::bool_type => Ok(ast::BuiltinType::Bool),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// An integer value as a `base_type`, or `None` if it is out of range for the type
+/// Where the declarations of one file of a schema came from, for resolving the types they name
+struct Scope<'s> {
+    /// Namespace of the package the file is in, or `None` for the schema itself
+    namespace: Option<&'s str>,
+    /// Namespaces of the packages the file imports
+    imports: Vec<String>,
+    /// The file's declarations, as indices into the schema's
+    declarations: Range<usize>,
+}
+
+impl Scope<'_> {
+    /// Namespaces of the packages imported by a file with `metadata`, each once
+    fn imports(metadata: &HashMap<&str, ast::MetadataValueRef>) -> Vec<String> {
+        let mut namespaces = Vec::new();
+
+        if let Some(ast::MetadataValueRef::String(value)) = metadata.get("imports") {
+            for name in imports::parse(value).unwrap_or_default() {
+                if !namespaces.iter().any(|namespace| namespace == name[0]) {
+                    namespaces.push(name[0].to_string());
+                }
+            }
+        }
+
+        namespaces
+    }
+
+    /// The identifier of the type `name` that a file names.  A qualified name must be in the
+    /// file's own package or one it imports.  Otherwise a type declared by the file's own schema
+    /// or package comes first, then one declared by exactly one of the packages the file imports.
+    /// `types` holds the identifiers of every type, qualified if imported.
+    fn resolve<'a>(&self, name: &'a str, types: &HashSet<&'a str>) -> Result<&'a str, GenoError> {
+        if let (Some(namespace), _) = imports::split_namespace(name) {
+            let visible = self.namespace == Some(namespace)
+                || self.imports.iter().any(|import| import == namespace);
+
+            return match types.get(name) {
+                Some(ident) if visible => Ok(ident),
+                _ => Err(GenoError::UndefinedType(name.to_string())),
+            };
+        }
+
+        let qualified =
+            |namespace: &str| types.get(format!("{namespace}.{name}").as_str()).copied();
+        let own = match self.namespace {
+            None => types.get(name).copied(),
+            Some(namespace) => qualified(namespace),
+        };
+
+        if let Some(ident) = own {
+            return Ok(ident);
+        }
+
+        let found: Vec<&str> = self
+            .imports
+            .iter()
+            .filter(|namespace| Some(namespace.as_str()) != self.namespace)
+            .filter_map(|namespace| qualified(namespace))
+            .collect();
+
+        match found[..] {
+            [ident] => Ok(ident),
+            [] if self.namespace.is_none() => Ok(name), // Undefined, as validation will report
+            [] => Err(GenoError::UndefinedType(name.to_string())),
+            _ => Err(GenoError::AmbiguousType(name.to_string(), found.join(", "))),
+        }
+    }
+
+    fn resolve_field_type<'a>(
+        &self,
+        field_type: &mut ast::FieldTypeRef<'a>,
+        types: &HashSet<&'a str>,
+    ) -> Result<(), GenoError> {
+        match field_type {
+            ast::FieldTypeRef::Array(element_type, ..) => {
+                self.resolve_field_type(element_type, types)
+            }
+            ast::FieldTypeRef::Map(key_type, value_type, _) => {
+                if let ast::MapKeyTypeRef::UserDefined(name) = key_type {
+                    *name = self.resolve(name, types)?;
+                }
+                self.resolve_field_type(value_type, types)
+            }
+            ast::FieldTypeRef::Builtin(..) => Ok(()),
+            ast::FieldTypeRef::UserDefined(name, _) => {
+                *name = self.resolve(name, types)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Qualify the identifier of a declaration imported from a package with its namespace.  Topics
+/// keep theirs, as they name channels rather than types.
+fn qualify<'a>(
+    mut decl: ast::DeclarationRef<'a>,
+    namespace: &str,
+    arena: &'a Arena,
+) -> ast::DeclarationRef<'a> {
+    match &mut decl {
+        ast::DeclarationRef::Enum { ident, .. }
+        | ast::DeclarationRef::Struct { ident, .. }
+        | ast::DeclarationRef::Interface { ident, .. } => {
+            *ident = arena.alloc_str(&format!("{namespace}.{ident}"));
+        }
+        ast::DeclarationRef::Topic { .. } => {}
+    }
+
+    decl
+}
+
+/// Point each type named in the declarations at the declaration it resolves to in the scope of
+/// the file it's in
+fn resolve_types(
+    declarations: &mut [ast::DeclarationRef],
+    scopes: &[Scope],
+) -> Result<(), GenoError> {
+    let types: HashSet<&str> = declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            ast::DeclarationRef::Enum { ident, .. }
+            | ast::DeclarationRef::Struct { ident, .. }
+            | ast::DeclarationRef::Interface { ident, .. } => Some(*ident),
+            ast::DeclarationRef::Topic { .. } => None,
+        })
+        .collect();
+
+    for scope in scopes {
+        for decl in &mut declarations[scope.declarations.clone()] {
+            match decl {
+                ast::DeclarationRef::Enum { .. } => {}
+                ast::DeclarationRef::Struct {
+                    fields, implements, ..
+                } => {
+                    for name in implements {
+                        *name = scope.resolve(name, &types)?;
+                    }
+                    for (_, field_type) in fields {
+                        scope.resolve_field_type(field_type, &types)?;
+                    }
+                }
+                ast::DeclarationRef::Interface { fields, .. } => {
+                    for (_, field_type) in fields {
+                        scope.resolve_field_type(field_type, &types)?;
+                    }
+                }
+                ast::DeclarationRef::Topic { payload, .. } => {
+                    *payload = scope.resolve(payload, &types)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn coerce_integer(base_type: &IntegerType, value: i128) -> Option<ast::IntegerValue> {
+    match base_type {
+        IntegerType::I8 => i8::try_from(value).map(ast::IntegerValue::I8).ok(),
+        IntegerType::I16 => i16::try_from(value).map(ast::IntegerValue::I16).ok(),
+        IntegerType::I32 => i32::try_from(value).map(ast::IntegerValue::I32).ok(),
+        IntegerType::I64 => i64::try_from(value).map(ast::IntegerValue::I64).ok(),
+        IntegerType::U8 => u8::try_from(value).map(ast::IntegerValue::U8).ok(),
+        IntegerType::U16 => u16::try_from(value).map(ast::IntegerValue::U16).ok(),
+        IntegerType::U32 => u32::try_from(value).map(ast::IntegerValue::U32).ok(),
+        IntegerType::U64 => u64::try_from(value).map(ast::IntegerValue::U64).ok(),
+    }
+}
+
+/// The character of a `\\u{...}` escape, taking it from `chars`, which start after the `u`.  The
+/// braces hold one to six hex digits.
+fn unicode_escape(chars: &mut std::str::Chars<'_>) -> Option<char> {
+    let rest = chars.as_str().strip_prefix('{')?;
+    let end = rest.find('}')?;
+    let digits = &rest[..end];
+
+    if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let c = char::from_u32(u32::from_str_radix(digits, 16).ok()?)?;
+
+    *chars = rest[end + 1..].chars();
+    Some(c)
+}
+
+/// The comments in `gap`, the text between two items, that attach to the item after it.  Those
+/// are the comments that start a line, with no blank line or other text between them and the
+/// item.  `line_start` says whether the gap starts at the beginning of a line.
+fn leading_comments(gap: &str, line_start: bool) -> Vec<&str> {
+    let mut comments = Vec::new();
+    let mut own_line = line_start;
+    let mut newlines = 0;
+    let mut rest = gap;
+
+    while let Some(c) = rest.chars().next() {
+        let len = if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            rest.find("*/").map_or(rest.len(), |i| i + 2)
+        } else {
+            0
+        };
+
+        if len > 0 {
+            if !own_line || newlines > 1 {
+                comments.clear();
+            }
+            if own_line {
+                comments.push(rest[..len].trim_end());
+            }
+            rest = &rest[len..];
+            own_line = false;
+            newlines = 0;
+            continue;
+        }
+
+        if c == '\n' {
+            own_line = true;
+            newlines += 1;
+        } else if !c.is_whitespace() {
+            comments.clear();
+            own_line = false;
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    if newlines > 1 {
+        comments.clear();
+    }
+
+    comments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    fn gen_ast(input: &str) -> Result<ast::Schema, GenoError> {
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_path_buf();
+        fs::write(&path, input).unwrap();
+
+        GenoAstBuilder::new(path).build()
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrow)]
+    fn happy_path() {
+        let input = r#"
+meta { format = 1 }
+enum enum1: i16 {
+    default = -1,
+    banana = 0,
+    apple = 1,
+    orange = 2,
+    kiwiFruit = 3,
+    pear = 4,
+}
+// Another comment
+struct type1 {
+    alpha: i8,
+    alpha_beta: u8,
+    alphaBeta: i16,
+    a4: u16,
+    a5: i32,
+    a6: u32,
+    a7: i64,
+    a8: u64,
+    a9: f32,
+    a10: f64,
+    n1: i8?,
+    n2: u8?,
+    n3: i16?,
+    n4: u16?,
+    n5: i16?,
+    n6: u16?,
+    n7: i32?,
+    n8: u32?,
+    n9: i64?,
+    n10: u64?,
+    s1: string,
+    s2: string?,
+    b1: bool,
+    b2: bool?,
+    e1: enum1,
+    e2: enum1?,
+    r1: [ string ],
+    r2: [ string ]?,
+    r3: [ string; 10],
+    m1: { string : f64 },
+    m2: { string : string },
+    m3: { string : bool },
+    t1: type1,
+}"#;
+        gen_ast(&input).unwrap();
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrow, clippy::assertions_on_constants)]
+    fn bad_parse() {
+        let input = "meta { ";
+        let result = gen_ast(&input);
+
+        match result {
+            Err(GenoError::Parse { .. }) => {
+                assert!(true);
+            }
+            _ => {
+                panic!("expected GenoError::Parse");
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrow, clippy::assertions_on_constants)]
+    fn number_range() {
+        let input = r#"
+meta { format = 1 }
+enum A:i16 { v = 0xffffffff, }
+"#;
+        let result = gen_ast(&input);
+
+        match result {
+            Err(GenoError::NumberRange { .. }) => {
+                assert!(true);
+            }
+            _ => {
+                panic!("expected GenoError::NumberRange");
+            }
+        }
+    }
+
+    #[test]
+    fn typed_literals() {
+        let schema = gen_ast(
+            "meta { format = 1 }
+            enum a: i8 { min = -0x80, max = 0x7f, neg = -0b1, pos = +5 }
+            enum b: u8 { max = 0xFF, zero = -0 }
+            struct s { @range(-0x10, 0b1111) x: i32 }",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            &schema.declarations[0],
+            ast::Declaration::Enum { variants, .. } if variants.iter().map(|(_, v)| v.clone()).eq([
+                ast::IntegerValue::I8(-128),
+                ast::IntegerValue::I8(127),
+                ast::IntegerValue::I8(-1),
+                ast::IntegerValue::I8(5),
+            ])
+        ));
+        assert!(matches!(
+            &schema.declarations[1],
+            ast::Declaration::Enum { variants, .. } if variants[0].1 == ast::IntegerValue::U8(255)
+        ));
+
+        for (variant, literal) in [
+            ("i8 { a = 0x80 }", "0x80"),
+            ("i8 { a = -0x81 }", "-0x81"),
+            ("u8 { a = -1 }", "-1"),
+            ("u64 { a = 0x1ffffffffffffffff }", "0x1ffffffffffffffff"),
+            ("i64 { a = -0x8000000000000001 }", "-0x8000000000000001"),
+        ] {
+            match gen_ast(&format!("meta {{ format = 1 }}\nenum e: {variant}")) {
+                Err(GenoError::NumberRange {
+                    content, location, ..
+                }) => {
+                    assert_eq!(content, literal);
+                    assert_eq!(location.line, 2);
+                    assert_eq!(location.column, variant.find(literal).unwrap() + 9);
+                }
+                result => panic!("expected GenoError::NumberRange for {variant}, got {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn set_version() {
+        let builder = GenoAstBuilder::new("test.geno".into());
+
+        assert_eq!(
+            builder
+                .set_version("meta { format = 1, version = 2 } // v2\n", 3)
+                .unwrap(),
+            "meta { format = 1, version = 3 } // v2\n"
+        );
+        assert_eq!(
+            builder
+                .set_version("meta {\n    format = 1,\n}\n", 2)
+                .unwrap(),
+            "meta {\n    format = 1, version = 2,\n}\n"
+        );
+        assert_eq!(
+            builder
+                .set_version(
+                    "meta { format = 1, version = \"1.4.2\" }\n",
+                    ast::SchemaVersion::Semantic(1, 5, 0)
+                )
+                .unwrap(),
+            "meta { format = 1, version = \"1.5.0\" }\n"
+        );
+    }
+
+    #[test]
+    fn localized_strings() {
+        let schema =
+            gen_ast("meta { format = 1 } struct product { name: lstring, tagline: lstring? }")
+                .unwrap();
+
+        match &schema.declarations[0] {
+            ast::Declaration::Struct { fields, .. } => assert_eq!(
+                fields[1].1,
+                ast::FieldType::Builtin(ast::BuiltinType::LocalizedString, true)
+            ),
+            _ => panic!("expected a struct"),
+        }
+        assert!(matches!(
+            gen_ast("meta { format = 1 } struct a { m: { lstring: u8 } }"),
+            Err(GenoError::InvalidMapKey(key)) if key == "lstring"
+        ));
+    }
+
+    #[test]
+    fn map_keys() {
+        let input = r#"
+meta { format = 1 }
+enum color: u8 { red = 1 }
+struct palette {
+    by_id: { u32 : string },
+    by_flag: { bool : string },
+    by_color: { color : string }?,
+}"#;
+        let schema = gen_ast(input).unwrap();
+
+        match &schema.declarations[1] {
+            ast::Declaration::Struct { fields, .. } => assert_eq!(
+                fields[2].1,
+                ast::FieldType::Map(
+                    ast::MapKeyType::UserDefined("color".to_string()),
+                    Box::new(ast::FieldType::Builtin(ast::BuiltinType::String, false)),
+                    true
+                )
+            ),
+            _ => panic!("expected a struct"),
+        }
+
+        let input = "meta { format = 1 } struct a { m: [{ f64 : string }] }";
+        let schema = gen_ast(input).unwrap();
+
+        match schema.validate_for(&ast::Capabilities {
+            float_map_keys: false,
+            ..ast::Capabilities::ALL
+        }) {
+            Err(GenoError::UnsupportedMapKey(field, key)) => {
+                assert_eq!((field.as_str(), key.as_str()), ("a.m", "f64"))
+            }
+            _ => panic!("expected GenoError::UnsupportedMapKey"),
+        }
+
+        let input = "meta { format = 1 } struct a { m: { a : string } }";
+
+        match gen_ast(input) {
+            Err(GenoError::InvalidMapKey(key)) => assert_eq!(key, "a"),
+            _ => panic!("expected GenoError::InvalidMapKey"),
+        }
+
+        let input = "meta { format = 1 } struct a { m: { b : string } }";
+
+        match gen_ast(input) {
+            Err(GenoError::UndefinedType(name)) => assert_eq!(name, "b"),
+            _ => panic!("expected GenoError::UndefinedType"),
+        }
+    }
+
+    #[test]
+    fn borrowed_ast() {
+        let input = String::from(
+            "meta { format = 1, name = \"x\" } enum color { red = 1 } struct point { c: {color: [string]}? }",
+        );
+        let builder = GenoAstBuilder::new("test.geno".into());
+        let schema = builder.build_ref(&input).unwrap();
+
+        match &schema.declarations[1] {
+            ast::DeclarationRef::Struct { ident, fields, .. } => {
+                assert!(input.contains(ident));
+                assert_eq!(fields[0].0, "c");
+                assert!(matches!(
+                    fields[0].1,
+                    ast::FieldTypeRef::Map(ast::MapKeyTypeRef::UserDefined("color"), _, true)
+                ));
+            }
+            _ => panic!("expected a struct"),
+        }
+
+        let owned = builder.build_from_str(&input).unwrap();
+
+        assert_eq!(ast::Schema::from(schema.clone()), owned);
+        assert_eq!(ast::SchemaRef::from(&owned), schema);
+        assert!(matches!(
+            builder.build_ref("meta { format = 2 }"),
+            Err(GenoError::InvalidMetadataFormat())
+        ));
+    }
+
+    #[test]
+    fn large_schema_errors() {
+        let mut input = String::from("meta { format = 1 }\n");
+
+        for i in 0..1000 {
+            match i {
+                300 | 700 => input.push_str(&format!("struct s{i} {{ a: i8, a: i8 }}\n")),
+                500 => input.push_str(&format!("struct s{i} {{ a: missing }}\n")),
+                _ => input.push_str(&format!("struct s{i} {{ a: i8, b: s{}? }}\n", i / 2)),
+            }
+        }
+
+        match gen_ast(&input) {
+            Err(GenoError::DuplicateField(ident, field)) => {
+                assert_eq!((ident.as_str(), field.as_str()), ("s300", "a"))
+            }
+            _ => panic!("expected GenoError::DuplicateField"),
+        }
+    }
+
+    #[test]
+    fn annotations() {
+        let input = r#"
+meta { format = 1 }
+@root
+struct point {
+    @json(name = "x-coord") @sensitive
+    x: i32,
+    y: i32,
+}
+enum color { @alias(red, "rouge", 7,) red = 1, green = 2 }"#;
+        let schema = gen_ast(input).unwrap();
+
+        match &schema.declarations[0] {
+            ast::Declaration::Struct { annotations, .. } => {
+                assert_eq!(annotations.declaration[0].name, "root");
+                assert!(annotations.declaration[0].args.is_empty());
+                assert_eq!(
+                    annotations.member("x")[0].args,
+                    vec![ast::AnnotationArg {
+                        name: Some("name".to_string()),
+                        value: ast::AnnotationValue::String("x-coord".to_string()),
+                    }]
+                );
+                assert_eq!(annotations.member("x")[1].name, "sensitive");
+                assert!(annotations.member("y").is_empty());
+            }
+            _ => panic!("expected a struct"),
+        }
+
+        match &schema.declarations[1] {
+            ast::Declaration::Enum { annotations, .. } => assert_eq!(
+                annotations.member("red")[0]
+                    .args
+                    .iter()
+                    .map(|arg| arg.value.clone())
+                    .collect::<Vec<_>>(),
+                vec![
+                    ast::AnnotationValue::Identifier("red".to_string()),
+                    ast::AnnotationValue::String("rouge".to_string()),
+                    ast::AnnotationValue::Integer(7),
+                ]
+            ),
+            _ => panic!("expected an enum"),
+        }
+
+        let builder = GenoAstBuilder::new("test.geno".into());
+
+        assert_eq!(
+            ast::Schema::from(builder.build_ref(input).unwrap()),
+            builder.build_from_str(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn versions() {
+        let input = r#"
+meta { format = 1, version = 4 }
+enum color { red = 1, @since(2) green = 2, @removed(3) blue = 3 }
+struct point {
+    x: i32,
+    @since(2) @removed(4)
+    y: i32,
+    @since(3)
+    z: i32?,
+}"#;
+        let schema = gen_ast(input).unwrap();
+        let fields = |schema: &ast::Schema| match &schema.declarations[1] {
+            ast::Declaration::Struct { fields, .. } => fields.clone(),
+            _ => panic!("expected a struct"),
+        };
+
+        assert_eq!(schema.version(), Some(4));
+        assert_eq!(
+            fields(&schema.at_version(2))
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["x", "y"]
+        );
+        assert_eq!(fields(&schema.at_version(4)).len(), 2);
+        assert!(
+            fields(&schema.all_versions())
+                .iter()
+                .all(|(name, ft)| (name == "x") != ft.is_nullable())
+        );
+
+        let check = |meta: &str, body: &str| {
+            gen_ast(&format!(
+                "meta {{ format = 1{meta} }} struct p {{ {body} }}"
+            ))
+        };
+
+        assert!(matches!(
+            check(", version = 0", "a: i8"),
+            Err(GenoError::InvalidMetadataVersion())
+        ));
+        for version in [
+            "\"0.1.0\"",
+            "\"1.2\"",
+            "\"1.2.3.4\"",
+            "\"1.-2.3\"",
+            "\"v1.2.3\"",
+        ] {
+            assert!(matches!(
+                check(&format!(", version = {version}"), "a: i8"),
+                Err(GenoError::InvalidMetadataVersion())
+            ));
+        }
+        assert!(matches!(
+            check("", "@since(2) a: i8"),
+            Err(GenoError::InvalidVersion(path, _)) if path == "p.a"
+        ));
+        assert!(matches!(
+            check(", version = 3", "@since(4) a: i8"),
+            Err(GenoError::InvalidVersion(..))
+        ));
+        assert!(matches!(
+            check(", version = 3", "@since(3) @removed(2) a: i8"),
+            Err(GenoError::InvalidVersion(..))
+        ));
+        assert!(matches!(
+            check(", version = 3", "@since(\"2\") a: i8"),
+            Err(GenoError::InvalidAnnotation(..))
+        ));
+        assert!(matches!(
+            check(", version = 3", "@since(1) @since(2) a: i8"),
+            Err(GenoError::InvalidAnnotation(..))
+        ));
+        assert!(matches!(
+            gen_ast("meta { format = 1, version = 3 } @since(2) struct p { a: i8 }"),
+            Err(GenoError::InvalidAnnotation(ident, _)) if ident == "p"
+        ));
+    }
+
+    #[test]
+    fn constraints() {
+        let check = |field: &str| gen_ast(&format!("meta {{ format = 1 }} struct p {{ {field} }}"));
+
+        assert!(check("@range(-5, 5) a: i8, @range(max = 1) b: f64?").is_ok());
+        assert!(
+            check("@len(0, 3) a: string, @len(min = 1) b: [i8], @len(2) c: {string: i8}").is_ok()
+        );
+        assert!(check("@pattern(\"^[0-9]+$\") a: string?").is_ok());
+
+        for field in [
+            "@range(5, 1) a: i8",
+            "@range() a: i8",
+            "@range(min = 1, min = 2) a: i8",
+            "@range(\"1\") a: i8",
+            "@range(1) a: string",
+            "@len(-1) a: string",
+            "@len(1) a: i32",
+            "@pattern(1) a: string",
+            "@pattern(\"x\") a: [string]",
+        ] {
+            assert!(
+                matches!(check(field), Err(GenoError::InvalidAnnotation(path, _)) if path == "p.a"),
+                "{field}"
+            );
+        }
+        assert!(matches!(
+            gen_ast("meta { format = 1 } enum e { @range(1, 2) a = 1 }"),
+            Err(GenoError::InvalidAnnotation(..))
+        ));
+    }
+
+    #[test]
+    fn sensitive_fields() {
+        let schema =
+            gen_ast("meta { format = 1 } struct user { id: u64, @sensitive email: string, @pii name: string? }")
+                .unwrap();
+
+        match &schema.declarations[0] {
+            ast::Declaration::Struct { annotations, .. } => {
+                assert!(!annotations.is_sensitive("id"));
+                assert!(annotations.is_sensitive("email"));
+                assert!(annotations.is_sensitive("name"));
+            }
+            _ => panic!("expected a struct"),
+        }
+
+        for schema in [
+            "meta { format = 1 } struct p { @sensitive(true) a: i8 }",
+            "meta { format = 1 } @pii struct p { a: i8 }",
+            "meta { format = 1 } enum e { @sensitive a = 1 }",
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(..))),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn roots() {
+        let schema = gen_ast(
+            "meta { format = 1 } enum kind { a = 1 } enum unused { b = 1 } struct part { k: kind }
+            @root struct order { parts: [part] } struct event { id: u64 } struct spare { p: part }
+            topic events: event; topic orders: order;",
+        )
+        .unwrap();
+
+        assert_eq!(schema.roots(), ["order", "event"]);
+        assert_eq!(schema.unreachable(), ["unused", "spare"]);
+        assert_eq!(
+            schema.warnings(),
+            [
+                GenoWarning::UnreachableType("unused".to_string()),
+                GenoWarning::UnreachableType("spare".to_string()),
+            ]
+        );
+        assert!(
+            gen_ast("meta { format = 1 } struct a { b: i8 }")
+                .unwrap()
+                .unreachable()
+                .is_empty()
+        );
+
+        for schema in [
+            "meta { format = 1 } @root enum e { a = 1 }",
+            "meta { format = 1 } @root(1) struct s { a: i8 }",
+            "meta { format = 1 } struct s { @root a: i8 }",
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@root"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn owners() {
+        let schema = gen_ast(
+            r#"meta { format = 1 } @owner("team-payments") struct payment { amount: u64 }
+            struct audit { id: u64 } @owner("team-events") topic payments: payment;"#,
+        )
+        .unwrap();
+        let owners: Vec<Option<&str>> = schema
+            .declarations
+            .iter()
+            .map(|decl| match decl {
+                ast::Declaration::Enum { annotations, .. }
+                | ast::Declaration::Struct { annotations, .. }
+                | ast::Declaration::Interface { annotations, .. }
+                | ast::Declaration::Topic { annotations, .. } => annotations.owner(),
+            })
+            .collect();
+
+        assert_eq!(owners, [Some("team-payments"), None, Some("team-events")]);
+
+        for schema in [
+            "meta { format = 1 } @owner struct s { a: i8 }",
+            "meta { format = 1 } @owner(team) struct s { a: i8 }",
+            r#"meta { format = 1 } @owner("") struct s { a: i8 }"#,
+            r#"meta { format = 1 } @owner("a") @owner("b") struct s { a: i8 }"#,
+            r#"meta { format = 1 } struct s { @owner("a") a: i8 }"#,
+            r#"meta { format = 1 } enum e { @owner("a") a = 1 }"#,
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@owner"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn field_defaults() {
+        let schema = gen_ast(
+            r#"meta { format = 1 } enum level: u8 { info = 1, debug = 2 }
+            @config struct server { @default(8080) port: u16, @default("localhost") host: string,
+                @default(true) tls: bool, @default(debug) level: level?, @default(1) ratio: f32 }"#,
+        )
+        .unwrap();
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[1] else {
+            panic!("not a struct");
+        };
+
+        assert!(annotations.is_config());
+        assert_eq!(
+            annotations.default_value("port"),
+            Some(&ast::AnnotationValue::Integer(8080))
+        );
+        assert_eq!(
+            annotations.default_value("level"),
+            Some(&ast::AnnotationValue::Identifier("debug".to_string()))
+        );
+        assert_eq!(annotations.default_value("missing"), None);
+
+        for schema in [
+            r#"meta { format = 1 } struct s { @default(256) a: u8 }"#,
+            r#"meta { format = 1 } struct s { @default(-1) a: u32 }"#,
+            r#"meta { format = 1 } struct s { @default("a") a: i8 }"#,
+            r#"meta { format = 1 } struct s { @default(yes) a: bool }"#,
+            r#"meta { format = 1 } struct s { @default(1, 2) a: i8 }"#,
+            r#"meta { format = 1 } struct s { @default(value = 1) a: i8 }"#,
+            r#"meta { format = 1 } struct s { @default(1) @default(2) a: i8 }"#,
+            r#"meta { format = 1 } struct s { @default(1) a: [i8] }"#,
+            r#"meta { format = 1 } enum e { a = 1 } struct s { @default(b) a: e }"#,
+            r#"meta { format = 1 } interface i { @default(1) a: i8 }"#,
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@default"),
+                "{schema}"
+            );
+        }
+
+        for schema in [
+            r#"meta { format = 1 } @config enum e { a = 1 }"#,
+            r#"meta { format = 1 } @config(1) struct s { a: i8 }"#,
+            r#"meta { format = 1 } struct s { @config a: i8 }"#,
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@config"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn cli_structs() {
+        let schema = gen_ast(r#"meta { format = 1 } @cli struct args { verbose: bool }"#).unwrap();
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[0] else {
+            panic!("not a struct");
+        };
+
+        assert!(annotations.is_cli());
+
+        for schema in [
+            r#"meta { format = 1 } @cli enum e { a = 1 }"#,
+            r#"meta { format = 1 } @cli(1) struct s { a: i8 }"#,
+            r#"meta { format = 1 } struct s { @cli a: i8 }"#,
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@cli"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn records() {
+        let schema = gen_ast(
+            r#"meta { format = 1 } enum level: u8 { info = 1 }
+            @record struct event { id: u64, name: string, level: level?, ratio: f32?, ok: bool }"#,
+        )
+        .unwrap();
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[1] else {
+            panic!("not a struct");
+        };
+
+        assert!(annotations.is_record());
+
+        for (schema, path) in [
+            (r#"meta { format = 1 } @record enum e { a = 1 }"#, "e"),
+            (r#"meta { format = 1 } @record(1) struct s { a: i8 }"#, "s"),
+            (r#"meta { format = 1 } struct s { @record a: i8 }"#, "s.a"),
+            (r#"meta { format = 1 } @record struct s { a: [i8] }"#, "s.a"),
+            (
+                r#"meta { format = 1 } @record struct s { a: lstring }"#,
+                "s.a",
+            ),
+            (
+                r#"meta { format = 1 } struct t { a: i8 } @record struct s { a: i8, b: t? }"#,
+                "s.b",
+            ),
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(p, name)) if p == path && name == "@record"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn abi_stable() {
+        let schema = gen_ast(
+            r#"meta { format = 1 } enum kind: u8 { a = 1 }
+            @abi(stable) struct vec3 { x: f32, y: f32, z: f32 }
+            @abi(stable) struct body { kind: kind, at: vec3, path: [vec3; 4], live: bool }"#,
+        )
+        .unwrap();
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[2] else {
+            panic!("not a struct");
+        };
+
+        assert!(annotations.is_abi_stable());
+
+        for (schema, path) in [
+            (r#"meta { format = 1 } @abi(stable) enum e { a = 1 }"#, "e"),
+            (r#"meta { format = 1 } @abi struct s { a: i8 }"#, "s"),
+            (r#"meta { format = 1 } @abi(fast) struct s { a: i8 }"#, "s"),
+            (
+                r#"meta { format = 1 } struct s { @abi(stable) a: i8 }"#,
+                "s.a",
+            ),
+            (
+                r#"meta { format = 1 } @abi(stable) struct s { a: string }"#,
+                "s.a",
+            ),
+            (
+                r#"meta { format = 1 } @abi(stable) struct s { a: i8? }"#,
+                "s.a",
+            ),
+            (
+                r#"meta { format = 1 } @abi(stable) struct s { a: [i8] }"#,
+                "s.a",
+            ),
+            (
+                r#"meta { format = 1 } struct t { a: i8 } @abi(stable) struct s { a: t }"#,
+                "s.a",
+            ),
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(p, name)) if p == path && name == "@abi"),
+                "{schema}"
+            );
+        }
+
+        assert!(matches!(
+            gen_ast(
+                r#"meta { format = 1 } @abi(stable) struct a { b: [b; 2] } @abi(stable) struct b { a: a }"#
+            ),
+            Err(GenoError::RecursiveType(_))
+        ));
+    }
+
+    #[test]
+    fn budgets() {
+        let schema = gen_ast(
+            r#"meta { format = 1 } @root @budget(max_packed_bytes = 20) struct ping { seq: u32 }"#,
+        )
+        .unwrap();
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[0] else {
+            panic!("not a struct");
+        };
+
+        assert_eq!(annotations.budget(), Some(20));
+
+        for (schema, path) in [
+            (
+                r#"meta { format = 1 } @budget(max_packed_bytes = 1) enum e { a = 1 }"#,
+                "e",
+            ),
+            (r#"meta { format = 1 } @budget(20) struct s { a: i8 }"#, "s"),
+            (
+                r#"meta { format = 1 } @budget(max_packed_bytes = -1) struct s { a: i8 }"#,
+                "s",
+            ),
+            (
+                r#"meta { format = 1 } @budget(max_bytes = 20) struct s { a: i8 }"#,
+                "s",
+            ),
+            (
+                r#"meta { format = 1 } struct s { @budget(max_packed_bytes = 1) a: i8 }"#,
+                "s.a",
+            ),
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(p, name)) if p == path && name == "@budget"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let schema = gen_ast(
+            r#"meta { format = 1 } enum role: u8 { a = 1 }
+            @key("user:{role}:{id}") struct user { id: u64, role: role, name: string? }"#,
+        )
+        .unwrap();
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[1] else {
+            panic!("not a struct");
+        };
+
+        assert_eq!(annotations.key(), Some("user:{role}:{id}"));
+        assert_eq!(
+            ast::key_parts("user:{role}:{id}"),
+            Some(vec![
+                ast::KeyPart::Text("user:"),
+                ast::KeyPart::Field("role"),
+                ast::KeyPart::Text(":"),
+                ast::KeyPart::Field("id"),
+            ])
+        );
+
+        for schema in [
+            r#"meta { format = 1 } @key struct s { a: i8 }"#,
+            r#"meta { format = 1 } @key("s:{b}") struct s { a: i8 }"#,
+            r#"meta { format = 1 } @key("s:{a}") struct s { a: i8? }"#,
+            r#"meta { format = 1 } @key("s:{a}") struct s { a: [i8] }"#,
+            r#"meta { format = 1 } @key("s:{a}") struct s { a: t } struct t { b: i8 }"#,
+            r#"meta { format = 1 } @key("s:{a") struct s { a: i8 }"#,
+            r#"meta { format = 1 } @key("s:}") struct s { a: i8 }"#,
+            r#"meta { format = 1 } @key("s:$a") struct s { a: i8 }"#,
+            r#"meta { format = 1 } @key("a") @key("b") struct s { a: i8 }"#,
+            r#"meta { format = 1 } @key("e") enum e: u8 { a = 1 }"#,
+            r#"meta { format = 1 } struct s { @key("a") a: i8 }"#,
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@key"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn lints() {
+        let schema = gen_ast(
+            "meta { format = 1 } @root struct a { b: [string?]?, c: {string: [i8?]?}, d: [string?], e: [string]? }
+            @allow(unreachable_type) enum unused { x = 1 } struct spare { @allow(double_nullable) f: {i8: bool?}? }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            schema.warnings(),
+            [
+                GenoWarning::DoubleNullable("a.b".to_string()),
+                GenoWarning::DoubleNullable("a.c".to_string()),
+                GenoWarning::UnreachableType("spare".to_string()),
+            ]
+        );
+        assert_eq!(schema.warnings()[2].lint(), "unreachable_type");
+
+        for schema in [
+            "meta { format = 1 } struct s { @allow(unknown) a: i8 }",
+            "meta { format = 1 } struct s { @allow a: i8 }",
+            "meta { format = 1 } struct s { @allow(lint = double_nullable) a: i8 }",
+            "meta { format = 1 } enum e { @allow(double_nullable) a = 1 }",
+            "meta { format = 1 } struct s { a: i8 } @allow(unreachable_type) topic t: s;",
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@allow"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn require_docs() {
+        let schema = gen_ast(
+            "meta { format = 1, require_docs = 1 }
+// Shapes
+struct shape {
+    // Number of corners
+    corners: u8,
+    sides: u8,
+    @allow(missing_docs) spare: u8,
+}
+enum line: u8 { solid = 1 }
+@allow(missing_docs) enum fill: u8 { none = 1 }",
+        )
+        .unwrap();
+
+        assert!(schema.requires_docs());
+        assert_eq!(
+            schema.warnings(),
+            [
+                GenoWarning::MissingDoc("shape.sides".to_string()),
+                GenoWarning::MissingDoc("line".to_string()),
+                GenoWarning::MissingDoc("line.solid".to_string()),
+            ]
+        );
+        assert_eq!(schema.warnings()[0].lint(), "missing_docs");
+        assert!(
+            gen_ast("meta { format = 1 } struct shape { corners: u8 }")
+                .unwrap()
+                .warnings()
+                .is_empty()
+        );
+        assert!(matches!(
+            gen_ast("meta { format = 1, require_docs = 2 } struct shape { corners: u8 }"),
+            Err(GenoError::InvalidMetadataRequireDocs())
+        ));
+    }
+
+    #[test]
+    fn narrow_integers() {
+        let source = "meta { format = 1, narrow_integers = 1, strict = 1 }
+/// Kinds
+enum kind: i32 { a = 1, b = 300 }
+/// Signs
+enum sign: i64 { neg = -5, pos = 5 }
+/// Items
+struct item {
+    /// Percent
+    @range(0, 100) pct: i32?,
+    /// Other
+    @allow(wide_integer) @range(0, 100) other: u64,
+    /// Open
+    @range(max = 10) open: u32,
+}";
+        let builder = GenoAstBuilder::new("test.geno".into());
+
+        assert!(matches!(
+            builder.build_from_str(source),
+            Err(GenoError::Strict(warning)) if warning == "'kind' is i32, but its values fit in u16"
+        ));
+
+        let (narrowed, narrowings) = builder.narrow_integers(source).unwrap();
+        let names: Vec<&str> = narrowings
+            .iter()
+            .map(|narrowing| narrowing.name.as_str())
+            .collect();
+
+        assert_eq!(names, ["kind", "sign", "item.pct"]);
+        assert_eq!(narrowings[1].to, IntegerType::I8);
+        assert!(narrowed.contains("enum kind: u16 { a = 1, b = 300 }"));
+        assert!(narrowed.contains("enum sign: i8 {"));
+        assert!(narrowed.contains("@range(0, 100) pct: u8?,"));
+        assert!(narrowed.contains("@range(0, 100) other: u64,"));
+        assert!(
+            builder
+                .build_from_str(&narrowed)
+                .unwrap()
+                .narrowings()
+                .is_empty()
+        );
+
+        // Only asked for warnings, but fields shared with an interface are left alone either way
+        let schema = gen_ast(
+            "meta { format = 1 } enum kind { a = 1 } interface pct { @range(0, 1) p: u32 }
+            struct item: pct { @range(0, 1) p: u32, @range(0, 1) q: u32 }",
+        )
+        .unwrap();
+
+        assert!(schema.warnings().is_empty());
+        assert_eq!(schema.narrowings().len(), 2);
+        assert!(matches!(
+            gen_ast("meta { format = 1, narrow_integers = 2 } struct item { n: u8 }"),
+            Err(GenoError::InvalidMetadataNarrowIntegers())
+        ));
+    }
+
+    #[test]
+    fn naming_conventions() {
+        let schema = gen_ast(
+            r#"meta { format = 1, type_case = "snake", field_case = "snake", variant_case = "pascal" }
+enum Kind: u8 { Solid = 1, dashed = 2, @allow(short_name) X = 3 }
+struct shape { cornerCount: u8, x: u8, v2: u8, @allow(naming_convention) sideCount: u8 }
+@allow(naming_convention, short_name) struct Point { X: i32 }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            schema.warnings(),
+            [
+                GenoWarning::NamingConvention("Kind".to_string(), "snake".to_string()),
+                GenoWarning::NamingConvention("Kind.dashed".to_string(), "pascal".to_string()),
+                GenoWarning::NamingConvention("shape.cornerCount".to_string(), "snake".to_string()),
+                GenoWarning::ShortName("shape.x".to_string()),
+                GenoWarning::ShortName("shape.v2".to_string()),
+            ]
+        );
+        assert_eq!(schema.warnings()[3].lint(), "short_name");
+
+        // Names are only checked when the metadata asks for a convention
+        assert!(
+            gen_ast("meta { format = 1 } struct Point { x: i32 }")
+                .unwrap()
+                .warnings()
+                .is_empty()
+        );
+        assert!(matches!(
+            gen_ast(r#"meta { format = 1, type_case = "kebab" } struct point { n: u8 }"#),
+            Err(GenoError::InvalidMetadataNaming(key)) if key == "type_case"
+        ));
+    }
+
+    #[test]
+    fn strict() {
+        let strict = |input: &str| {
+            GenoAstBuilder::new("test.geno".into())
+                .strict(true)
+                .build_from_str(input)
+        };
+        let schema = gen_ast(
+            "meta { format = 1, strict = 1 }
+// Shapes
+@root struct shape { lines: [line] }
+// Lines
+enum line: u8 { solid = 1, dashed = 2 }",
+        )
+        .unwrap();
+
+        assert!(schema.is_strict());
+        assert!(
+            strict("meta { format = 1 }\n// Shapes\n@root struct shape { corners: u8 }").is_ok()
+        );
+        assert!(gen_ast("meta { format = 1, strict = 0 } struct shape { corners: u8 }").is_ok());
+
+        for (input, message) in [
+            (
+                "meta { format = 1 }\n// Lines\nenum line { solid = 1 }",
+                "enum 'line' has no base type",
+            ),
+            (
+                "meta { format = 1 } @root struct shape { corners: u8 }",
+                "type 'shape' has no doc comment",
+            ),
+            (
+                "meta { format = 1 }\n// Shapes\nstruct shape { lines: [u8?]? }",
+                "field 'shape.lines' is a nullable container of nullable values",
+            ),
+        ] {
+            assert!(
+                matches!(strict(input), Err(GenoError::Strict(m)) if m == message),
+                "{input}"
+            );
+        }
+        assert!(
+            strict(
+                "meta { format = 1 }\n// Shapes\n@allow(double_nullable) struct shape { a: [u8?]? }"
+            )
+            .is_ok()
+        );
+        assert!(matches!(
+            gen_ast("meta { format = 1, strict = 2 } struct shape { corners: u8 }"),
+            Err(GenoError::InvalidMetadataStrict())
+        ));
+    }
+
+    #[test]
+    fn target_capabilities() {
+        let schema = gen_ast(
+            "meta { format = 1 } struct a { id: u64, ids: {u64: [u64]}, @allow(u64_precision) raw: u64, rgb: [u8; 3] }",
+        )
+        .unwrap();
+        let capabilities = ast::Capabilities {
+            u64: false,
+            ..ast::Capabilities::ALL
+        };
+
+        assert!(schema.target_warnings(&ast::Capabilities::ALL).is_empty());
+        assert_eq!(
+            schema.target_warnings(&capabilities),
+            [
+                GenoWarning::LossyU64("a.id".to_string()),
+                GenoWarning::LossyU64("a.ids".to_string()),
+            ]
+        );
+        assert!(schema.check_capabilities(&capabilities).is_ok());
+
+        match schema.check_capabilities(&ast::Capabilities {
+            fixed_arrays: false,
+            ..ast::Capabilities::ALL
+        }) {
+            Err(GenoError::UnsupportedFixedArray(field)) => assert_eq!(field, "a.rgb"),
+            _ => panic!("expected GenoError::UnsupportedFixedArray"),
+        }
+    }
+
+    #[test]
+    fn default_variants() {
+        let schema =
+            gen_ast("meta { format = 1 } enum e { a = 1, @default b = 2 } enum f { c = 1 }")
+                .unwrap();
+        let default = |decl: &ast::Declaration| match decl {
+            ast::Declaration::Enum {
+                variants,
+                annotations,
+                ..
+            } => annotations.default_variant(variants).0.clone(),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(default(&schema.declarations[0]), "b");
+        assert_eq!(default(&schema.declarations[1]), "c");
+
+        for schema in [
+            "meta { format = 1 } enum e { @default a = 1, @default b = 2 }",
+            "meta { format = 1 } enum e { @default(1) a = 1 }",
+            "meta { format = 1 } @default enum e { a = 1 }",
+            "meta { format = 1 } struct s { @default a: i32 }",
+        ] {
+            assert!(
+                matches!(gen_ast(schema), Err(GenoError::InvalidAnnotation(_, name)) if name == "@default"),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_names() {
+        let check =
+            |fields: &str| gen_ast(&format!("meta {{ format = 1 }} struct p {{ {fields} }}"));
+        let schema = check("@json(name = \"user-id\") a: i32, b: i32").unwrap();
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[0] else {
+            unreachable!()
+        };
+
+        assert_eq!(annotations.json_name("a"), "user-id");
+        assert_eq!(annotations.json_name("b"), "b");
+        assert!(check("@json(name = \"b\") a: i32, @json(name = \"a\") b: i32").is_ok());
+        assert!(matches!(
+            check("@json(name = \"b\") a: i32, b: i32"),
+            Err(GenoError::DuplicateJsonName(ident, name)) if ident == "p" && name == "b"
+        ));
+
+        for field in [
+            "@json a: i32",
+            "@json(\"x\") a: i32",
+            "@json(name = \"\") a: i32",
+            "@json(name = \"it's\") a: i32",
+            "@json(label = \"x\") a: i32",
+        ] {
+            assert!(
+                matches!(check(field), Err(GenoError::InvalidAnnotation(path, _)) if path == "p.a"),
+                "{field}"
+            );
+        }
+        assert!(matches!(
+            gen_ast("meta { format = 1 } enum e { @json(name = \"x\") a = 1 }"),
+            Err(GenoError::InvalidAnnotation(..))
+        ));
+    }
+
+    #[test]
+    fn target_fields() {
+        let check =
+            |fields: &str| gen_ast(&format!("meta {{ format = 1 }} struct p {{ {fields} }}"));
+        let schema =
+            check("id: i32, @only(rust) hash: string?, @exclude(dart, rust) debug: [u8]?").unwrap();
+        let names = |schema: &ast::Schema| {
+            let ast::Declaration::Struct { fields, .. } = &schema.declarations[0] else {
+                unreachable!()
+            };
+
+            fields
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(schema.target_fields(), ["p.hash", "p.debug"]);
+        assert_eq!(names(&schema.for_target(Some("rust"))), ["id", "hash"]);
+        assert_eq!(names(&schema.for_target(Some("dart"))), ["id"]);
+        assert_eq!(names(&schema.for_target(None)), ["id"]);
+        assert!(check("id: i32").unwrap().target_fields().is_empty());
+
+        for field in [
+            "@only(rust) a: i32",
+            "@only a: i32?",
+            "@only(go) a: i32?",
+            "@only(\"rust\") a: i32?",
+            "@exclude(target = dart) a: i32?",
+        ] {
+            assert!(
+                matches!(check(field), Err(GenoError::InvalidAnnotation(path, _)) if path == "p.a"),
+                "{field}"
+            );
+        }
+        assert!(matches!(
+            gen_ast("meta { format = 1 } @only(rust) struct p { a: i32? }"),
+            Err(GenoError::InvalidAnnotation(..))
+        ));
+        assert!(matches!(
+            gen_ast(
+                "meta { format = 1 } interface i { a: i32? } struct p: i { @only(rust) a: i32? }"
+            ),
+            Err(GenoError::InterfaceMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn string_literals() {
+        let schema = gen_ast(
+            r##"meta {
+                format = 1,
+                plain = "a b",
+                escaped = "line\n\t\"quoted\" \\ \u{e9}\u{1F600}",
+                raw = r"C:\path",
+                hashed = r#"say "hi""#,
+            }
+            struct s { @pattern(r"^\d+$") @json(name = "a\u{2d}b") x: string }"##,
+        )
+        .unwrap();
+        let string = |key: &str| match &schema.metadata[key] {
+            ast::MetadataValue::String(s) => s.clone(),
+            _ => panic!("expected a string"),
+        };
+
+        assert_eq!(string("plain"), "a b");
+        assert_eq!(string("escaped"), "line\n\t\"quoted\" \\ \u{e9}\u{1F600}");
+        assert_eq!(string("raw"), "C:\\path");
+        assert_eq!(string("hashed"), "say \"hi\"");
+
+        let ast::Declaration::Struct { annotations, .. } = &schema.declarations[0] else {
+            unreachable!()
+        };
+
+        assert_eq!(annotations.member("x")[0].string(), Some("^\\d+$"));
+        assert_eq!(annotations.json_name("x"), "a-b");
+
+        for literal in [r#""\d""#, r#""\u{}""#, r#""\u{110000}""#, r#""\u41""#] {
+            match gen_ast(&format!("meta {{ format = 1, x = {literal} }}")) {
+                Err(GenoError::InvalidEscape {
+                    content, location, ..
+                }) => {
+                    assert_eq!(content, literal);
+                    assert_eq!(location.column, 24);
+                }
+                result => panic!("expected GenoError::InvalidEscape for {literal}, got {result:?}"),
+            }
+        }
+        assert!(matches!(
+            gen_ast(r##"meta { format = 1, x = r#"a"" }"##),
+            Err(GenoError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let schema =
+            gen_ast("meta { format = 1 } struct größe { straße: string, 名前: u8 }").unwrap();
+
+        assert!(matches!(
+            &schema.declarations[0],
+            ast::Declaration::Struct { ident, fields, .. }
+                if ident == "größe" && fields[0].0 == "straße" && fields[1].0 == "名前"
+        ));
+
+        // "é" written as "e" and a combining accent is normalized to the single character
+        let schema = gen_ast("meta { format = 1 } struct cafe\u{301} { x: i32 }").unwrap();
+
+        assert!(matches!(
+            &schema.declarations[0],
+            ast::Declaration::Struct { ident, .. } if ident == "caf\u{e9}"
+        ));
+        assert!(matches!(
+            gen_ast("meta { format = 1 } struct p { caf\u{e9}: i32, cafe\u{301}: i32 }"),
+            Err(GenoError::DuplicateField(..))
+        ));
+        assert!(matches!(
+            gen_ast("meta { format = 1 } struct _p { x: i32 }"),
+            Err(GenoError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn separators() {
+        let with_commas = gen_ast(
+            "meta { format = 1, version = 2 } enum e { a = 1, b = 2 } struct s { x: i32, y: {string: e} }",
+        )
+        .unwrap();
+        let without_commas = gen_ast(
+            "meta {
+                format = 1
+                version = 2,
+            }
+            enum e {
+                a = 1
+                b = 2,
+            }
+            struct s {
+                @range(1, 2,)
+                x: i32
+                y: {string: e,},
+            }",
+        )
+        .unwrap();
+
+        assert_eq!(with_commas.metadata, without_commas.metadata);
+        assert_eq!(with_commas.declarations[0], without_commas.declarations[0]);
+        assert!(matches!(
+            gen_ast("meta { format = 1 } struct s { x: i32,, y: i32 }"),
+            Err(GenoError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn comments() {
+        let schema = gen_ast(
+            "meta { format = 1 } // Not attached
+// Loose
+
+/** A shape
+ * with corners */
+// Second
+@root
+struct shape { // Not attached
+    // Number of corners
+    corners: u8, /* Not attached */
+    /* Lines */ lines: [line], // Not attached
+
+    name: string,
+}
+enum line { /* Solid */ solid = 1, dashed = 2 }
+",
+        )
+        .unwrap();
+
+        match &schema.declarations[0] {
+            ast::Declaration::Struct { annotations, .. } => {
+                let comments = &annotations.comments;
+
+                assert_eq!(
+                    comments.declaration,
+                    ["/** A shape\n * with corners */", "// Second"]
+                );
+                assert_eq!(comments.member("corners"), ["// Number of corners"]);
+                assert_eq!(comments.member("lines"), ["/* Lines */"]);
+                assert!(comments.member("name").is_empty());
+                assert_eq!(
+                    ast::Comments::text(&comments.declaration[0]),
+                    "A shape\nwith corners"
+                );
+                assert_eq!(ast::Comments::text(&comments.declaration[1]), "Second");
+            }
+            _ => panic!("expected a struct"),
+        }
+        match &schema.declarations[1] {
+            ast::Declaration::Enum { annotations, .. } => {
+                assert!(annotations.comments.declaration.is_empty());
+                assert!(annotations.comments.member("solid").is_empty());
+            }
+            _ => panic!("expected an enum"),
+        }
+
+        let plain = gen_ast(
+            "meta { format = 1 } @root struct shape { corners: u8, lines: [line], name: string }
+enum line { solid = 1, dashed = 2 }",
+        )
+        .unwrap();
+
+        assert_eq!(schema.fingerprint(), plain.fingerprint());
+        assert!(matches!(
+            gen_ast("meta { format = 1 } /* struct s { x: i32 }"),
+            Err(GenoError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn constants() {
+        let schema = gen_ast(
+            "meta { format = 1 } const BASE = 1000; const SHIFT = 0b10;
+            enum e: u16 { a = BASE + 1, b = BASE - 1 - 2, c = 1 + (2 << SHIFT) | 1, d = 0x10 }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            schema.declarations,
+            [ast::Declaration::Enum {
+                ident: "e".to_string(),
+                base_type: ast::IntegerType::U16,
+                variants: vec![
+                    ("a".to_string(), ast::IntegerValue::U16(1001)),
+                    ("b".to_string(), ast::IntegerValue::U16(997)),
+                    ("c".to_string(), ast::IntegerValue::U16(9)),
+                    ("d".to_string(), ast::IntegerValue::U16(16)),
+                ],
+                visibility: Default::default(),
+                annotations: Default::default(),
+            }]
+        );
+
+        assert!(matches!(
+            gen_ast("meta { format = 1 } enum e { a = BASE } const BASE = 1;"),
+            Err(GenoError::UndefinedConstant(name)) if name == "BASE"
+        ));
+        assert!(matches!(
+            gen_ast("meta { format = 1 } const A = 1; const A = 2; enum e { a = A }"),
+            Err(GenoError::DuplicateConstant(name)) if name == "A"
+        ));
+        assert!(matches!(
+            gen_ast("meta { format = 1 } const A = 200; enum e: i8 { a = A - 1 }"),
+            Err(GenoError::NumberRange { content, .. }) if content == "A - 1"
+        ));
+        assert!(matches!(
+            gen_ast("meta { format = 1 } const A = 1 << 64;"),
+            Err(GenoError::NumberRange { .. })
+        ));
+    }
+
+    #[test]
+    fn flatten() {
+        let check = |fields: &str| {
+            gen_ast(&format!(
+                "meta {{ format = 1 }} enum e {{ a = 1 }} struct env {{ id: i32, @flatten more: inner }} struct inner {{ x: i32 }} struct p {{ {fields} }}"
+            ))
+        };
+
+        assert!(check("@flatten env: env, y: i32").is_ok());
+        assert!(matches!(
+            check("@flatten env: env, x: i32"),
+            Err(GenoError::DuplicateJsonName(ident, name)) if ident == "p" && name == "x"
+        ));
+        assert!(matches!(
+            check("@flatten env: env, @json(name = \"id\") y: i32"),
+            Err(GenoError::DuplicateJsonName(..))
+        ));
+        assert!(matches!(
+            gen_ast("meta { format = 1 } struct a { @flatten b: b } struct b { @flatten a: a }"),
+            Err(GenoError::RecursiveType(_))
+        ));
+
+        for field in [
+            "@flatten a: e",
+            "@flatten a: env?",
+            "@flatten a: i32",
+            "@flatten(1) a: env",
+        ] {
+            assert!(
+                matches!(check(field), Err(GenoError::InvalidAnnotation(path, _)) if path == "p.a"),
+                "{field}"
+            );
+        }
+    }
+
+    #[test]
+    fn int64_annotations() {
+        let check = |field: &str| gen_ast(&format!("meta {{ format = 1 }} struct p {{ {field} }}"));
+        let capabilities = ast::Capabilities {
+            u64: false,
+            ..ast::Capabilities::ALL
+        };
+
+        assert!(check("@int64(int) a: i64, @int64(error) b: u64?").is_ok());
+        assert!(
+            check("@int64(string) a: u64, @int64(bigint) b: u64?")
+                .unwrap()
+                .target_warnings(&capabilities)
+                .is_empty()
+        );
+
+        for field in [
+            "@int64(big) a: i64",
+            "@int64 a: i64",
+            "@int64(\"string\") a: i64",
+            "@int64(string) a: i32",
+            "@int64(string) a: [i64]",
+        ] {
+            assert!(
+                matches!(check(field), Err(GenoError::InvalidAnnotation(path, _)) if path == "p.a"),
+                "{field}"
+            );
+        }
+    }
+
+    #[test]
+    fn subsets() {
+        let schema = gen_ast(
+            "meta { format = 1 } enum kind { a = 1 } struct part { k: kind } struct order { parts: [part] }
+            struct customer { id: u64 } topic orders: order;",
+        )
+        .unwrap();
+        let idents = |schema: ast::Schema| -> Vec<String> {
+            schema
+                .declarations
+                .iter()
+                .map(|decl| match decl {
+                    ast::Declaration::Enum { ident, .. }
+                    | ast::Declaration::Struct { ident, .. }
+                    | ast::Declaration::Interface { ident, .. }
+                    | ast::Declaration::Topic { ident, .. } => ident.clone(),
+                })
+                .collect()
+        };
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+
+        assert_eq!(
+            idents(schema.subset(&names(&["order"]), &[]).unwrap()),
+            ["kind", "part", "order", "orders"]
+        );
+        assert_eq!(
+            idents(schema.subset(&[], &names(&["order", "kind"])).unwrap()),
+            ["kind", "part", "customer"]
+        );
+        assert_eq!(
+            idents(schema.subset(&names(&["part"]), &names(&["part"])).unwrap()),
+            Vec::<String>::new()
+        );
+        assert!(
+            schema
+                .subset(&names(&["order"]), &[])
+                .unwrap()
+                .validate()
+                .is_ok()
+        );
+        assert!(matches!(
+            schema.subset(&names(&["missing"]), &[]),
+            Err(GenoError::UndefinedType(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn topics() {
+        let schema = gen_ast(
+            "meta { format = 1 } struct event { id: u64 } topic events: event; topic audit: event;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            schema.declarations[1],
+            ast::Declaration::Topic {
+                ident: "events".to_string(),
+                payload: "event".to_string(),
+                annotations: Default::default(),
+            }
+        );
+
+        let check = |topics: &str| {
+            gen_ast(&format!(
+                "meta {{ format = 1 }} enum kind {{ a = 1 }} struct event {{ k: kind }} {topics}"
+            ))
+        };
+
+        assert!(check("topic event: event;").is_ok());
+        assert!(matches!(
+            check("topic a: event; topic a: event;"),
+            Err(GenoError::DuplicateTopic(name)) if name == "a"
+        ));
+        assert!(matches!(
+            check("topic a: kind;"),
+            Err(GenoError::InvalidTopicPayload(..))
+        ));
+        assert!(matches!(
+            check("topic a: missing;"),
+            Err(GenoError::UndefinedType(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn interfaces() {
+        let schema = gen_ast(
+            "meta { format = 1 } interface named { name: string } struct user: named { id: u64, name: string }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            schema.declarations[0],
+            ast::Declaration::Interface {
+                ident: "named".to_string(),
+                fields: vec![(
+                    "name".to_string(),
+                    ast::FieldType::Builtin(ast::BuiltinType::String, false)
+                )],
+                visibility: Default::default(),
+                annotations: Default::default(),
+            }
+        );
+        assert!(matches!(
+            &schema.declarations[1],
+            ast::Declaration::Struct { implements, .. } if implements == &["named"]
+        ));
+
+        let check = |decls: &str| {
+            gen_ast(&format!(
+                "meta {{ format = 1 }} enum kind {{ a = 1 }} interface named {{ name: string? }} {decls}"
+            ))
+        };
+
+        assert!(check("struct a: named { name: string?, k: kind }").is_ok());
+        assert!(matches!(
+            check("struct a: named { k: kind }"),
+            Err(GenoError::InterfaceMismatch(ident, interface, field))
+                if ident == "a" && interface == "named" && field == "name"
+        ));
+        assert!(matches!(
+            check("struct a: named { name: string }"),
+            Err(GenoError::InterfaceMismatch(..))
+        ));
+        assert!(matches!(
+            check("struct a: kind { k: kind }"),
+            Err(GenoError::NotAnInterface(ident, name)) if ident == "a" && name == "kind"
+        ));
+        assert!(matches!(
+            check("struct a: missing { k: kind }"),
+            Err(GenoError::UndefinedType(name)) if name == "missing"
+        ));
+        assert!(matches!(
+            check("struct a { n: named }"),
+            Err(GenoError::InterfaceAsType(name)) if name == "named"
+        ));
+        assert!(matches!(
+            check("struct a: named { name: string? } topic t: named;"),
+            Err(GenoError::InterfaceAsType(name)) if name == "named"
+        ));
+    }
+
+    #[test]
+    fn visibility() {
+        let schema = gen_ast(
+            "meta { format = 1 } internal enum kind: u8 { a = 1 } @root public struct event { id: u64 }
+            internal struct entry { k: kind, e: event } topic events: event;",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            &schema.declarations[0],
+            ast::Declaration::Enum { visibility, .. } if *visibility == ast::Visibility::Internal
+        ));
+        assert!(matches!(
+            &schema.declarations[1],
+            ast::Declaration::Struct { visibility, .. } if visibility.is_public()
+        ));
+        let fingerprint = |visibility: &str| {
+            gen_ast(&format!(
+                "meta {{ format = 1 }} {visibility} struct event {{ id: u64 }}"
+            ))
+            .unwrap()
+            .fingerprint()
+        };
+
+        // Public is the default, so saying so doesn't change the fingerprint
+        assert_eq!(fingerprint(""), fingerprint("public"));
+        assert_ne!(fingerprint(""), fingerprint("internal"));
+
+        for (schema, ident, name) in [
+            ("struct s { k: [kind?] }", "s", "kind"),
+            ("struct s { k: {kind: u8} }", "s", "kind"),
+            ("topic t: entry;", "t", "entry"),
+        ] {
+            assert!(
+                matches!(
+                    gen_ast(&format!(
+                        "meta {{ format = 1 }} internal enum kind: u8 {{ a = 1 }} internal struct entry {{ k: kind }} {schema}"
+                    )),
+                    Err(GenoError::InternalType(i, n)) if i == ident && n == name
+                ),
+                "{schema}"
+            );
+        }
+    }
+
+    #[test]
+    fn fingerprint() {
+        let builder = GenoAstBuilder::new("test.geno".into());
+        let a = builder
+            .build_from_str("meta { format = 1, wire = \"keyed\" } struct p { x: i32 }")
+            .unwrap();
+        let b = builder
+            .build_from_str("meta {\n  wire = \"keyed\",\n  format = 1,\n}\n// Comment\nstruct p {\n  x: i32,\n}\n")
+            .unwrap();
+        let c = builder
+            .build_from_str("meta { format = 1, wire = \"keyed\" } struct p { x: i64 }")
+            .unwrap();
+
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), c.fingerprint());
+        assert_eq!(a.fingerprint().len(), 64);
+    }
+
+    #[test]
+    fn imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let modules = dir.path().join("geno_modules");
+        let write = |path: &str, source: &str| {
+            let path = modules.join(path);
+
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        };
+
+        write(
+            "common/money.geno",
+            "meta { format = 1 } const cents = 100; enum currency: u8 { usd = cents }",
+        );
+        write(
+            "common/ids.geno",
+            r#"meta { format = 1, imports = "common.money" } struct price { c: currency, n: i64 }"#,
+        );
+        write(
+            "billing/invoice.geno",
+            "meta { format = 1 } struct invoice { id: u64 }",
+        );
+
+        let schemas = dir.path().join("schemas");
+        let builder = GenoAstBuilder::new(schemas.join("api.geno"));
+
+        fs::create_dir(&schemas).unwrap();
+
+        // Imported modules come first, each once, and the schema's metadata is its own
+        let schema = builder
+            .build_from_str(
+                r#"meta { format = 1, imports = "billing.invoice, common" }
+                struct order { total: price, invoice: invoice }"#,
+            )
+            .unwrap();
+        let idents: Vec<&str> = schema
+            .declarations
+            .iter()
+            .map(|decl| match decl {
+                ast::Declaration::Enum { ident, .. } | ast::Declaration::Struct { ident, .. } => {
+                    ident.as_str()
+                }
+                _ => "",
+            })
+            .collect();
+
+        // Imported types are under the namespaces of their packages, and are named by their
+        // identifiers alone from the schema, and from other modules of the same package
+        assert_eq!(
+            idents,
+            [
+                "billing.invoice",
+                "common.currency",
+                "common.price",
+                "order"
+            ]
+        );
+        assert_eq!(schema.metadata.len(), 2);
+        let ast::Declaration::Struct { fields, .. } = &schema.declarations[2] else {
+            unreachable!()
+        };
+        assert_eq!(
+            fields[0].1,
+            ast::FieldType::UserDefined("common.currency".to_string(), false)
+        );
+        let ast::Declaration::Struct { fields, .. } = &schema.declarations[3] else {
+            unreachable!()
+        };
+        assert_eq!(
+            fields[0].1,
+            ast::FieldType::UserDefined("common.price".to_string(), false)
+        );
+
+        // The schema can declare a type a package also has, and its own comes first
+        let schema = builder
+            .build_from_str(
+                r#"meta { format = 1, imports = "billing.invoice" }
+                struct invoice { total: i64 } struct order { invoice: invoice }"#,
+            )
+            .unwrap();
+        let ast::Declaration::Struct { fields, .. } = &schema.declarations[2] else {
+            unreachable!()
+        };
+        assert_eq!(
+            fields[0].1,
+            ast::FieldType::UserDefined("invoice".to_string(), false)
+        );
+
+        // A type more than one imported package has can't be named by its identifier alone
+        write(
+            "billing/price.geno",
+            "meta { format = 1 } struct price { cents: i64 }",
+        );
+        assert!(matches!(
+            builder.build_from_str(
+                r#"meta { format = 1, imports = "billing, common" } struct s { p: price }"#
+            ),
+            Err(GenoError::AmbiguousType(name, candidates))
+                if name == "price" && candidates == "billing.price, common.price"
+        ));
+        assert!(
+            builder
+                .build_from_str(
+                    r#"meta { format = 1, imports = "billing, common" } struct s { i: invoice }"#
+                )
+                .is_ok()
+        );
+
+        // Unless it's qualified with the namespace of its package, in fields, map keys and
+        // modules alike
+        write(
+            "billing/tax.geno",
+            r#"meta { format = 1, imports = "common.money" } struct tax { c: common.currency }"#,
+        );
+        let schema = builder
+            .build_from_str(
+                r#"meta { format = 1, imports = "billing, common" }
+                struct s { p: billing.price, q: common.price?, r: {common.currency: tax} }"#,
+            )
+            .unwrap();
+        let Some(ast::Declaration::Struct { fields, .. }) = schema.declarations.last() else {
+            unreachable!()
+        };
+
+        assert_eq!(
+            fields[0].1,
+            ast::FieldType::UserDefined("billing.price".to_string(), false)
+        );
+        assert_eq!(
+            fields[1].1,
+            ast::FieldType::UserDefined("common.price".to_string(), true)
+        );
+        assert_eq!(
+            fields[2].1,
+            ast::FieldType::Map(
+                ast::MapKeyType::UserDefined("common.currency".to_string()),
+                Box::new(ast::FieldType::UserDefined(
+                    "billing.tax".to_string(),
+                    false
+                )),
+                false
+            )
+        );
+
+        // A namespace must be imported by the file that names it, not just by another module
+        assert!(matches!(
+            builder.build_from_str(
+                r#"meta { format = 1, imports = "billing.tax" } struct s { c: common.currency }"#
+            ),
+            Err(GenoError::UndefinedType(name)) if name == "common.currency"
+        ));
+        assert!(matches!(
+            builder.build_ref(r#"meta { format = 1 } struct s { p: common.price }"#),
+            Err(GenoError::UndefinedType(name)) if name == "common.price"
+        ));
+        assert!(matches!(
+            builder.build_from_str(
+                r#"meta { format = 1, imports = "common" } struct s { p: common.geo.point }"#
+            ),
+            Err(GenoError::Parse { .. })
+        ));
+
+        let (_, files) = builder
+            .build_from_str_with_modules(
+                r#"meta { format = 1, imports = "billing.invoice, common" }
+                struct order { total: price, invoice: invoice }"#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            files,
+            [
+                modules.join("billing/invoice.geno"),
+                modules.join("common/money.geno"),
+                modules.join("common/ids.geno"),
+            ]
+        );
+
+        assert!(matches!(
+            builder.build_from_str(r#"meta { format = 1, imports = "common.taxes" } struct s { a: i8 }"#),
+            Err(GenoError::UnresolvedImport(name)) if name == "common.taxes"
+        ));
+        assert!(matches!(
+            builder.build_from_str(
+                r#"meta { format = 1, imports = "common..ids" } struct s { a: i8 }"#
+            ),
+            Err(GenoError::InvalidMetadataImports())
+        ));
+        assert!(matches!(
+            builder.build_from_str("meta { format = 1, imports = 1 } struct s { a: i8 }"),
+            Err(GenoError::InvalidMetadataImports())
+        ));
+        // Borrowed ASTs don't read imports
+        assert!(matches!(
+            builder.build_ref(r#"meta { format = 1, imports = "common" } struct s { p: price }"#),
+            Err(GenoError::UndefinedType(_))
+        ));
+
+        // Unless their sources are kept in an arena, when they build the same schema
+        let arena = Arena::new();
+        let source = r#"meta { format = 1, imports = "billing.invoice, common" }
+            struct order { total: price, invoice: invoice }"#;
+        let schema_ref = builder.build_from_str_in(&arena, source).unwrap();
+
+        assert!(arena.allocated_bytes() >= source.len());
+        assert_eq!(
+            ast::Schema::from(schema_ref),
+            builder.build_from_str(source).unwrap()
+        );
+    }
+}
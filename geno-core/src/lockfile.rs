@@ -0,0 +1,285 @@
+//! The lockfile of schema packages.  A project that adds packages to its `geno_modules` records
+//! each of them in `geno-lock.json` in its root, next to `geno_modules`, with the commit it was
+//! fetched at and a SHA-256 of its schemas.  While a project has a lockfile, every package a
+//! schema [imports](crate::imports) is checked against it as the schema is built, so a package
+//! changed upstream or by hand is caught before anything is built from it.
+use crate::{
+    GenoError,
+    imports::{MODULES_DIR, is_identifier},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// File name of the lockfile in a project's root
+pub const LOCK_FILE: &str = "geno-lock.json";
+
+/// A package as recorded in the lockfile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    /// Repository of the package, as it was given
+    pub source: String,
+    /// Tag or branch that was asked for, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Commit the version resolved to
+    pub commit: String,
+    /// SHA-256 of the package's schemas, as [schemas_hash] gives it
+    pub sha256: String,
+}
+
+/// The packages of a project, by namespace
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// Every package, sorted by namespace
+    pub packages: BTreeMap<String, LockedPackage>,
+}
+
+impl Lockfile {
+    /// Read the lockfile in `dir`, or an empty lockfile if there isn't one.  Each namespace must
+    /// be an identifier, as it names a directory in `geno_modules` that is replaced, and each
+    /// commit a hex object name, as it is passed to `git`.
+    pub fn read(dir: &Path) -> Result<Self, GenoError> {
+        let path = dir.join(LOCK_FILE);
+        let invalid = |reason: String| {
+            GenoError::Package(format!("invalid lockfile '{}': {reason}", path.display()))
+        };
+        let lockfile: Self = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|err| invalid(err.to_string()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        for (namespace, locked) in &lockfile.packages {
+            if !is_identifier(namespace) {
+                return Err(invalid(format!("'{namespace}' isn't a valid namespace")));
+            }
+            if locked.commit.is_empty() || !locked.commit.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid(format!(
+                    "'{}' isn't a commit of '{namespace}'",
+                    locked.commit
+                )));
+            }
+        }
+
+        Ok(lockfile)
+    }
+
+    /// Write the lockfile into `dir`
+    pub fn write(&self, dir: &Path) -> Result<(), GenoError> {
+        let mut contents = serde_json::to_string_pretty(self).unwrap();
+
+        contents.push('\n');
+        fs::write(dir.join(LOCK_FILE), contents)?;
+
+        Ok(())
+    }
+}
+
+/// Check the package `namespace` in the `geno_modules` directory `modules` against the lockfile
+/// next to it.  The package must be locked and have its locked hash.  Without a lockfile there is
+/// nothing to check.
+pub fn verify_package(modules: &Path, namespace: &str) -> Result<(), GenoError> {
+    let Some(dir) = modules.parent() else {
+        return Ok(());
+    };
+
+    if !dir.join(LOCK_FILE).exists() {
+        return Ok(());
+    }
+
+    let lockfile = Lockfile::read(dir)?;
+    let package_dir = modules.join(namespace);
+    let Some(locked) = lockfile.packages.get(namespace) else {
+        return Err(GenoError::Package(format!(
+            "'{namespace}' in {MODULES_DIR} isn't in {LOCK_FILE}, add it with geno add"
+        )));
+    };
+
+    if !package_dir.is_dir() {
+        return Err(GenoError::Package(format!(
+            "'{namespace}' isn't in {MODULES_DIR}, run geno vendor to fetch it"
+        )));
+    }
+    if schemas_hash(&package_dir)? != locked.sha256 {
+        return Err(GenoError::PackageMismatch(namespace.to_string()));
+    }
+
+    Ok(())
+}
+
+/// The paths of the `.geno` files under `dir`, relative to it and sorted.  Hidden directories,
+/// such as `.git`, and the package's own `geno_modules` are skipped, as are symbolic links, which
+/// could loop or reach outside the package.
+pub fn schema_files(dir: &Path) -> Result<Vec<PathBuf>, GenoError> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), GenoError> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let file_type = fs::symlink_metadata(&path)?.file_type();
+
+            if file_type.is_dir() {
+                if !name.starts_with('.') && name != MODULES_DIR {
+                    walk(root, &path, files)?;
+                }
+            } else if file_type.is_file() && path.extension().is_some_and(|ext| ext == "geno") {
+                files.push(path.strip_prefix(root).unwrap().to_path_buf());
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+
+    walk(dir, dir, &mut files)?;
+    files.sort();
+
+    Ok(files)
+}
+
+/// SHA-256 of the schemas in an installed package, as lowercase hex.  Each schema's path, with
+/// `/` separators, and contents are hashed in path order, so any added, removed, renamed or
+/// changed schema changes the hash.
+pub fn schemas_hash(package_dir: &Path) -> Result<String, GenoError> {
+    let mut hasher = Sha256::new();
+
+    for path in schema_files(package_dir)? {
+        let contents = fs::read(package_dir.join(&path))?;
+        let name = path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        hasher.update((name.len() as u64).to_be_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update((contents.len() as u64).to_be_bytes());
+        hasher.update(&contents);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        fs::create_dir_all(dir.path().join("sub/.git")).unwrap();
+        fs::write(dir.path().join("a.geno"), "a").unwrap();
+        fs::write(dir.path().join("sub/b.geno"), "b").unwrap();
+        fs::write(dir.path().join("sub/.git/c.geno"), "c").unwrap();
+        fs::write(dir.path().join("README.md"), "readme").unwrap();
+
+        assert_eq!(
+            schema_files(dir.path()).unwrap(),
+            [PathBuf::from("a.geno"), PathBuf::from("sub/b.geno")]
+        );
+
+        let hash = schemas_hash(dir.path()).unwrap();
+
+        assert_eq!(hash.len(), 64);
+        fs::write(dir.path().join("README.md"), "changed").unwrap();
+        assert_eq!(schemas_hash(dir.path()).unwrap(), hash);
+        fs::rename(dir.path().join("a.geno"), dir.path().join("c.geno")).unwrap();
+        assert_ne!(schemas_hash(dir.path()).unwrap(), hash);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+
+        fs::write(dir.path().join("a.geno"), "a").unwrap();
+        fs::write(outside.path().join("secret.geno"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("outside")).unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("secret.geno"),
+            dir.path().join("b.geno"),
+        )
+        .unwrap();
+        // A link to a directory above would loop without end if it were followed
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("loop")).unwrap();
+
+        assert_eq!(schema_files(dir.path()).unwrap(), [PathBuf::from("a.geno")]);
+    }
+
+    #[test]
+    fn lockfile_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let lockfile = |namespace: &str, commit: &str| {
+            fs::write(
+                dir.path().join(LOCK_FILE),
+                format!(
+                    r#"{{"packages": {{"{namespace}": {{"source": "github.com/org/common", "commit": "{commit}", "sha256": ""}}}}}}"#
+                ),
+            )
+            .unwrap();
+            Lockfile::read(dir.path())
+        };
+
+        assert!(lockfile("common", "0a1b2c").is_ok());
+        assert!(matches!(
+            lockfile("../..", "0a1b2c"),
+            Err(GenoError::Package(_))
+        ));
+        assert!(matches!(
+            lockfile("common", "--orphan"),
+            Err(GenoError::Package(_))
+        ));
+        // A namespace can't reach outside `geno_modules`
+        assert!(lockfile("..", "0a1b2c").is_err());
+    }
+
+    #[test]
+    fn verifies_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let modules = dir.path().join(MODULES_DIR);
+        let package_dir = modules.join("common");
+
+        // Nothing to check without a lockfile
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("a.geno"), "a").unwrap();
+        assert!(verify_package(&modules, "common").is_ok());
+
+        let mut lockfile = Lockfile::default();
+
+        lockfile.packages.insert(
+            "common".to_string(),
+            LockedPackage {
+                source: "github.com/org/common".to_string(),
+                version: None,
+                commit: "0".repeat(40),
+                sha256: schemas_hash(&package_dir).unwrap(),
+            },
+        );
+        lockfile.write(dir.path()).unwrap();
+        assert!(verify_package(&modules, "common").is_ok());
+        assert!(matches!(
+            verify_package(&modules, "extra"),
+            Err(GenoError::Package(_))
+        ));
+
+        fs::write(package_dir.join("a.geno"), "changed").unwrap();
+        assert!(matches!(
+            verify_package(&modules, "common"),
+            Err(GenoError::PackageMismatch(namespace)) if namespace == "common"
+        ));
+
+        fs::remove_dir_all(&package_dir).unwrap();
+        assert!(matches!(
+            verify_package(&modules, "common"),
+            Err(GenoError::Package(_))
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Procedural macros for embedding Geno schemas in Rust code.
+//!
+//! The [include_schema!] macro parses and validates a `.geno` file at compile time and expands
+//! to the same types the `rust-serde` generator would write to disk, so single crate users
+//! need neither a `build.rs` nor checked in generated files.
+
+#![warn(missing_docs)]
+
+use geno::{
+    GenoAstBuilder,
+    codegen::{Options, rust_serde},
+};
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use std::path::PathBuf;
+use syn::{LitStr, parse_macro_input};
+
+/// Include the types from a Geno schema file.  The path is relative to the directory containing
+/// the invoking crate's `Cargo.toml`.
+///
+/// The generated types are declared in a hidden module and re-exported with `pub use`, so wrap
+/// the invocation in a module of your own to control visibility.  The invoking crate must
+/// depend on `serde` with the `derive` feature enabled.
+///
+/// ```ignore
+/// mod api {
+///     geno_macro::include_schema!("schemas/api.geno");
+/// }
+/// ```
+#[proc_macro]
+pub fn include_schema(input: TokenStream) -> TokenStream {
+    let path_lit = parse_macro_input!(input as LitStr);
+
+    match expand(&path_lit) {
+        Ok(tokens) => tokens.into(),
+        Err(message) => syn::Error::new(path_lit.span(), message)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn expand(path_lit: &LitStr) -> Result<proc_macro2::TokenStream, String> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| "CARGO_MANIFEST_DIR is not set".to_string())?;
+    let file_path = PathBuf::from(manifest_dir).join(path_lit.value());
+    let schema = GenoAstBuilder::new(file_path.clone())
+        .build()
+        .map_err(|err| err.to_string())?;
+    let declarations: proc_macro2::TokenStream =
+        rust_serde::generate_declarations(&schema, &Options::new())
+            .map_err(|err| err.to_string())?
+            .parse()
+            .map_err(|err| format!("generated code is not valid Rust: {err}"))?;
+
+    // Referencing the file with `include_str!` makes cargo rebuild when the schema changes
+    let file_str = file_path.to_string_lossy().into_owned();
+    let mod_ident = format_ident!("__geno_{}", module_suffix(&file_path));
+
+    Ok(quote! {
+        const _: &str = include_str!(#file_str);
+
+        #[doc(hidden)]
+        mod #mod_ident {
+            #[allow(unused_imports)]
+            use ::serde::{Deserialize, Serialize};
+            #[allow(unused_imports)]
+            use ::std::collections::HashMap;
+
+            #declarations
+        }
+
+        pub use #mod_ident::*;
+    })
+}
+
+/// Turn the schema file stem into something usable in an identifier, so that several schemas
+/// can be included in the same module.
+fn module_suffix(file_path: &std::path::Path) -> String {
+    file_path
+        .file_stem()
+        .map(|stem| {
+            stem.to_string_lossy()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect()
+        })
+        .unwrap_or_else(|| "schema".to_string())
+}
@@ -0,0 +1,47 @@
+use shop::Noted;
+use std::collections::HashMap;
+
+mod shop {
+    geno_macro::include_schema!("tests/shop.geno");
+}
+
+#[test]
+fn generated_types_roundtrip() {
+    let order = shop::Order {
+        id: 42,
+        fruit: shop::Fruit::KiwiFruit,
+        notes: Some("ripe".to_string()),
+        tags: vec!["green".to_string()],
+        prices: HashMap::from([("each".to_string(), 1.25)]),
+    };
+    let bytes = rmp_serde::to_vec(&order).unwrap();
+    let decoded: shop::Order = rmp_serde::from_slice(&bytes).unwrap();
+
+    assert_eq!(order, decoded);
+}
+
+#[test]
+fn first_variant_is_default() {
+    assert_eq!(shop::Fruit::default(), shop::Fruit::Apple);
+}
+
+#[test]
+fn topics_name_their_payload() {
+    let topic: shop::Topic<shop::Order> = shop::ORDERS;
+
+    assert_eq!(topic.name, "orders");
+}
+
+#[test]
+fn interfaces_are_traits() {
+    let order = shop::Order {
+        id: 1,
+        fruit: shop::Fruit::Apple,
+        notes: None,
+        tags: vec!["green".to_string()],
+        prices: HashMap::new(),
+    };
+
+    assert_eq!(order.notes(), None);
+    assert_eq!(order.tags(), ["green".to_string()]);
+}
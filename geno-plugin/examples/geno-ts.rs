@@ -0,0 +1,121 @@
+//! An example plugin that writes TypeScript interfaces and enums for a schema's types.  Build it
+//! with `cargo build -p geno-plugin --example geno-ts`, then run
+//! `GENO_PLUGIN_PATH=target/debug/examples geno -f ts api.geno`.
+use geno_plugin::{
+    CodeWriter, GeneratedFile, GeneratedFiles, Generator, GenoError, Naming, NamingConvention,
+    Options, ast, integer_value_str, select_target,
+};
+use std::fmt::Write as _;
+
+struct TsGenerator;
+
+const DEFAULT_NAMING: Naming = Naming {
+    types: NamingConvention::Pascal,
+    fields: NamingConvention::Camel,
+    variants: NamingConvention::Pascal,
+    ascii: false,
+};
+
+impl Generator for TsGenerator {
+    fn name(&self) -> &str {
+        "ts"
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        let schema = &*select_target(schema, options, Some("ts"))?;
+        let naming = Naming::from_options(
+            options,
+            DEFAULT_NAMING,
+            &[
+                NamingConvention::Pascal,
+                NamingConvention::Camel,
+                NamingConvention::Snake,
+                NamingConvention::Preserve,
+            ],
+        )?;
+        let mut w = CodeWriter::new("  ");
+
+        w.line("// Generated by geno-ts");
+        for decl in &schema.declarations {
+            match decl {
+                ast::Declaration::Enum {
+                    ident, variants, ..
+                } => {
+                    w.blank();
+                    w.block(
+                        &format!("export enum {} {{", naming.type_name(ident)),
+                        "}",
+                        |w| {
+                            for (name, value) in variants {
+                                let value = integer_value_str(value);
+
+                                writeln!(w, "{} = {value},", naming.variant_name(name)).unwrap();
+                            }
+                        },
+                    );
+                }
+                ast::Declaration::Struct { ident, fields, .. } => {
+                    w.blank();
+                    w.block(
+                        &format!("export interface {} {{", naming.type_name(ident)),
+                        "}",
+                        |w| {
+                            for (name, field_type) in fields {
+                                let name = naming.field_name(name);
+
+                                writeln!(w, "{name}: {};", type_str(field_type, &naming)).unwrap();
+                            }
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        Ok(vec![GeneratedFile::new("geno.ts", w.into_string())])
+    }
+}
+
+fn type_str(ft: &ast::FieldType, naming: &Naming) -> String {
+    let (base, nullable) = match ft {
+        ast::FieldType::Array(inner, _, nullable) => {
+            (format!("Array<{}>", type_str(inner, naming)), *nullable)
+        }
+        ast::FieldType::Map(key, value, nullable) => {
+            let key = match key {
+                ast::MapKeyType::Builtin(bt) => builtin_str(bt).to_string(),
+                ast::MapKeyType::UserDefined(name) => naming.type_name(name),
+            };
+
+            (
+                format!("Map<{key}, {}>", type_str(value, naming)),
+                *nullable,
+            )
+        }
+        ast::FieldType::Builtin(bt, nullable) => (builtin_str(bt).to_string(), *nullable),
+        ast::FieldType::UserDefined(name, nullable) => (naming.type_name(name), *nullable),
+    };
+
+    if nullable {
+        format!("{base} | null")
+    } else {
+        base
+    }
+}
+
+fn builtin_str(bt: &ast::BuiltinType) -> &'static str {
+    match bt {
+        ast::BuiltinType::Integer(_) | ast::BuiltinType::Float(_) => "number",
+        ast::BuiltinType::String => "string",
+        ast::BuiltinType::LocalizedString => "Record<string, string>",
+        ast::BuiltinType::Bool => "boolean",
+    }
+}
+
+fn main() {
+    geno_plugin::main(&TsGenerator)
+}
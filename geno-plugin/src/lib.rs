@@ -0,0 +1,118 @@
+//! Everything needed to write an external Geno code generator, a `geno-<format>` binary that
+//! `geno` runs for any format it doesn't have built in.
+//!
+//! Implement [Generator] and hand it to [main], which reads the MessagePack AST from stdin,
+//! parses the `key=value` options from the arguments and writes the generated code to stdout,
+//! reporting any error on stderr with a failing exit code:
+//!
+//! ```no_run
+//! use geno_plugin::{CodeWriter, GeneratedFile, GeneratedFiles, GenoError, Generator, Options, ast};
+//!
+//! struct Names;
+//!
+//! impl Generator for Names {
+//!     fn name(&self) -> &str {
+//!         "names"
+//!     }
+//!
+//!     fn generate(&self, schema: &ast::Schema, _: &Options) -> Result<GeneratedFiles, GenoError> {
+//!         let mut w = CodeWriter::new("  ");
+//!
+//!         for decl in &schema.declarations {
+//!             if let ast::Declaration::Struct { ident, .. } = decl {
+//!                 w.line(ident);
+//!             }
+//!         }
+//!
+//!         Ok(vec![GeneratedFile::new("names.txt", w.into_string())])
+//!     }
+//! }
+//!
+//! fn main() {
+//!     geno_plugin::main(&Names)
+//! }
+//! ```
+//!
+//! The [testing] module runs a generator the way `geno` does, from schema source, for unit and
+//! snapshot tests.
+
+#![warn(missing_docs)]
+
+/// Running generators from tests, through the plugin protocol
+pub mod testing;
+
+pub use geno::{
+    GenoAstBuilder, GenoError, ast,
+    codegen::{
+        GeneratedFile, GeneratedFiles, Generator, Options,
+        util::{
+            CodeWriter, Naming, NamingConvention, integer_type_str, integer_value_str, is_nullable,
+            non_nullable, schema_type_str, select_target, split_words, to_ascii_identifier,
+            to_kebab_case, to_lower_camel_case, to_pascal_case, to_screaming_snake_case,
+            to_snake_case,
+        },
+    },
+};
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// Errors running a generator as a plugin
+#[derive(Error, Debug)]
+pub enum PluginError {
+    /// Reading the AST or writing the generated code failed
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// The AST on stdin isn't MessagePack for an [ast::Schema]
+    #[error("Unable to deserialize AST from stdin: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+    /// The options were invalid or the generator failed
+    #[error(transparent)]
+    Geno(#[from] GenoError),
+}
+
+/// Run `generator` as a plugin on the process's stdin, arguments and stdout, then exit.  Errors
+/// are written to stderr and exit with a code of 1.
+pub fn main(generator: &dyn Generator) -> ! {
+    let result = run(
+        generator,
+        io::stdin().lock(),
+        std::env::args().skip(1),
+        io::stdout().lock(),
+    );
+
+    if let Err(err) = result {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+
+    std::process::exit(0);
+}
+
+/// Run `generator` on the MessagePack AST read from `input`, with options from `key=value`
+/// arguments, writing the contents of each generated file to `output` in turn
+pub fn run<I, S>(
+    generator: &dyn Generator,
+    mut input: impl Read,
+    args: I,
+    mut output: impl Write,
+) -> Result<(), PluginError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut buffer = Vec::new();
+
+    input.read_to_end(&mut buffer)?;
+
+    let schema: ast::Schema = rmp_serde::from_slice(&buffer)?;
+    let options = Options::parse(args)?;
+
+    options.check_keys([generator])?;
+
+    for file in generator.generate(&schema, &options)? {
+        output.write_all(&file.contents)?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
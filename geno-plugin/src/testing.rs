@@ -0,0 +1,79 @@
+//! Helpers for testing a generator.  [generate] passes the AST through MessagePack exactly as
+//! `geno` does before running the generator, so a test also catches AST types that don't
+//! survive the trip to a plugin.  Snapshot tests compare the output for a directory of `.geno`
+//! files against golden files with [check_snapshots], which `geno snapshot` also uses.
+use crate::{Generator, GenoAstBuilder, PluginError, run};
+
+pub use geno::snapshot::{Mismatch, Report, check as check_snapshots};
+
+/// Parse and validate the schema `source`, then run `generator` on it as a plugin with the
+/// `key=value` options in `options`, returning what it writes to stdout
+pub fn generate(
+    generator: &dyn Generator,
+    source: &str,
+    options: &[&str],
+) -> Result<String, PluginError> {
+    let schema = GenoAstBuilder::new("test.geno".into()).build_from_str(source)?;
+    let ast_bytes = rmp_serde::to_vec_named(&schema).expect("an AST always serializes");
+    let mut output = Vec::new();
+
+    run(generator, ast_bytes.as_slice(), options, &mut output)?;
+
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CodeWriter, GeneratedFile, GeneratedFiles, GenoError, Options, ast};
+
+    /// Writes the name of each struct, in the case given by the `case` option
+    struct Names;
+
+    impl Generator for Names {
+        fn name(&self) -> &str {
+            "names"
+        }
+
+        fn generate(
+            &self,
+            schema: &ast::Schema,
+            options: &Options,
+        ) -> Result<GeneratedFiles, GenoError> {
+            let case: crate::NamingConvention = match options.get("case") {
+                None => crate::NamingConvention::Preserve,
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| GenoError::InvalidOption(format!("case={value}")))?,
+            };
+            let mut w = CodeWriter::new("  ");
+
+            for decl in &schema.declarations {
+                if let ast::Declaration::Struct { ident, .. } = decl {
+                    w.line(case.apply(ident));
+                }
+            }
+
+            Ok(vec![GeneratedFile::new("names.txt", w.into_string())])
+        }
+    }
+
+    #[test]
+    fn through_the_protocol() {
+        let source = "meta { format = 1 }\nstruct order_line { a: u8 }\nstruct shop { b: string }";
+
+        assert_eq!(generate(&Names, source, &[]).unwrap(), "order_line\nshop\n");
+        assert_eq!(
+            generate(&Names, source, &["case=pascal"]).unwrap(),
+            "OrderLine\nShop\n"
+        );
+        assert!(matches!(
+            generate(&Names, source, &["case=shouting"]),
+            Err(PluginError::Geno(GenoError::InvalidOption(_)))
+        ));
+        assert!(matches!(
+            run(&Names, &[0xc1u8][..], [""; 0], Vec::new()),
+            Err(PluginError::Decode(_))
+        ));
+    }
+}
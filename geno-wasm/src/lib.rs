@@ -0,0 +1,118 @@
+//! WebAssembly bindings for the Geno schema language, for a web playground and for diagnostics in
+//! browser based editors.  Build the npm package with `wasm-pack build geno-wasm`.
+//!
+//! Every function takes the schema source as a string.  Results that are structured, such as the
+//! AST, are returned as JSON for the caller to `JSON.parse`.
+
+#![warn(missing_docs)]
+
+use geno_core::{GenoAstBuilder, GenoError};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// The file name used in error messages
+const FILE_PATH: &str = "schema.geno";
+
+/// An error or warning in a schema, for showing in an editor
+#[derive(Debug, PartialEq, Serialize)]
+struct Diagnostic {
+    /// `error` or `warning`
+    severity: &'static str,
+    message: String,
+    /// The lint that gave a warning, for `@allow`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lint: Option<&'static str>,
+    /// One-based line and column, for the errors found while parsing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+}
+
+/// Parse and validate a schema, returning its AST as JSON.  Throws the error if it isn't valid.
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<String, JsError> {
+    ast_json(source).map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// The errors and warnings in a schema, as a JSON array of objects with a `severity`, a
+/// `message`, the `lint` of a warning, and the `line` and `column` of an error found while
+/// parsing.  An empty array means the schema is valid and has nothing to warn about.
+#[wasm_bindgen]
+pub fn validate(source: &str) -> String {
+    serde_json::to_string(&diagnostics(source)).unwrap()
+}
+
+/// Rewrite a schema as `geno fmt --fix` does, keeping it as written apart from narrowing integer
+/// types to the ones their values fit in.  Throws the error if the schema isn't valid.
+#[wasm_bindgen]
+pub fn format(source: &str) -> Result<String, JsError> {
+    formatted(source).map_err(|err| JsError::new(&err.to_string()))
+}
+
+fn ast_json(source: &str) -> Result<String, GenoError> {
+    let schema = GenoAstBuilder::new(FILE_PATH.into()).build_from_str(source)?;
+
+    Ok(serde_json::to_string(&schema).unwrap())
+}
+
+fn diagnostics(source: &str) -> Vec<Diagnostic> {
+    match GenoAstBuilder::new(FILE_PATH.into()).build_from_str(source) {
+        Ok(schema) => schema
+            .warnings()
+            .into_iter()
+            .map(|warning| Diagnostic {
+                severity: "warning",
+                message: warning.to_string(),
+                lint: Some(warning.lint()),
+                line: None,
+                column: None,
+            })
+            .collect(),
+        Err(err) => vec![Diagnostic {
+            severity: "error",
+            message: err.to_string(),
+            lint: None,
+            line: err.location().map(|location| location.line),
+            column: err.location().map(|location| location.column),
+        }],
+    }
+}
+
+fn formatted(source: &str) -> Result<String, GenoError> {
+    GenoAstBuilder::new(FILE_PATH.into())
+        .narrow_integers(source)
+        .map(|(formatted, _)| formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playground() {
+        let json = ast_json("meta { format = 1 } struct point { x: i32 }").unwrap();
+
+        assert!(json.contains("\"ident\":\"point\""));
+        assert_eq!(
+            validate("meta { format = 1 } struct point { x: i32 }"),
+            "[]"
+        );
+
+        let errors = diagnostics("meta { format = 1 }\nstruct point { x: i32 ");
+
+        assert_eq!(
+            (errors[0].severity, errors[0].line, errors[0].column),
+            ("error", Some(2), Some(23))
+        );
+        assert_eq!(
+            validate("meta { format = 1 } @root struct a { x: i8 } struct b { y: i8 }"),
+            r#"[{"severity":"warning","message":"type 'b' isn't reachable from any root","lint":"unreachable_type"}]"#
+        );
+        assert!(ast_json("meta { format = 1 } struct a { b: c }").is_err());
+        assert_eq!(
+            formatted("meta { format = 1 }\nenum e { a = 1 }\n").unwrap(),
+            "meta { format = 1 }\nenum e: u8 { a = 1 }\n"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::serve::Server;
+#[cfg(unix)]
+use anyhow::Context;
+use anyhow::bail;
+use std::{path::Path, sync::Arc, thread, time::Duration};
+
+/// Keep the schemas under the server's roots parsed, scanning them for changes every `interval`
+fn watch(server: Arc<Server>, interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            for path in server.schema_files() {
+                if let Err(err) = server.load(&path, false) {
+                    tracing::warn!(path = %path.display(), "{err:#}");
+                }
+            }
+            server.forget_missing();
+            thread::sleep(interval);
+        }
+    });
+}
+
+/// Answer requests from clients connecting to a Unix socket at `socket`, each on its own thread,
+/// until the process is stopped
+#[cfg(unix)]
+pub fn run(server: Server, socket: &Path, interval: Duration) -> anyhow::Result<()> {
+    use std::{
+        fs,
+        io::BufReader,
+        os::unix::net::{UnixListener, UnixStream},
+    };
+
+    if socket.exists() {
+        // A socket left behind by a daemon that was killed refuses connections
+        if UnixStream::connect(socket).is_ok() {
+            bail!(
+                "A daemon is already listening on '{}'",
+                socket.to_string_lossy()
+            );
+        }
+        fs::remove_file(socket)?;
+    }
+
+    let listener = UnixListener::bind(socket).context(format!(
+        "Could not listen on '{}'",
+        socket.to_string_lossy()
+    ))?;
+    let server = Arc::new(server);
+
+    watch(server.clone(), interval);
+    tracing::info!(socket = %socket.display(), "listening");
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = server.clone();
+
+        thread::spawn(move || {
+            let result = stream
+                .try_clone()
+                .map_err(Into::into)
+                .and_then(|input| server.serve(BufReader::new(input), stream));
+
+            if let Err(err) = result {
+                tracing::warn!("{err:#}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Unix sockets are all the daemon listens on
+#[cfg(not(unix))]
+pub fn run(server: Server, socket: &Path, interval: Duration) -> anyhow::Result<()> {
+    let _ = (server, socket, interval, watch);
+
+    bail!("geno daemon needs Unix sockets, use geno serve --stdio instead")
+}
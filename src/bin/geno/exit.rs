@@ -0,0 +1,73 @@
+use geno::GenoError;
+use std::io;
+use thiserror::Error;
+
+/// Exit codes of `geno`, one for each class of failure so scripts can tell them apart
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Everything succeeded
+    Success = 0,
+    /// A check, such as a snapshot or round trip test, found differences
+    Failure = 1,
+    /// The command line was wrong
+    Usage = 2,
+    /// The schema could not be parsed
+    Parse = 3,
+    /// The schema parsed but is not valid
+    Validation = 4,
+    /// A generator could not generate code for the schema
+    Generator = 5,
+    /// A file or the network could not be read or written
+    Io = 6,
+    /// A schema is not compatible with the registered one
+    Incompatible = 7,
+}
+
+impl Exit {
+    /// The exit code for an error
+    pub fn of(err: &anyhow::Error) -> Self {
+        if err.downcast_ref::<UsageError>().is_some() {
+            Exit::Usage
+        } else if err.downcast_ref::<io::Error>().is_some()
+            || matches!(err.downcast_ref::<GenoError>(), Some(GenoError::Io(_)))
+        {
+            // A generator that couldn't write its output failed on I/O, not on the schema
+            Exit::Io
+        } else if err.downcast_ref::<GeneratorFailed>().is_some() {
+            Exit::Generator
+        } else if let Some(err) = err.downcast_ref::<GenoError>() {
+            match err {
+                GenoError::Parse { .. }
+                | GenoError::NumberRange { .. }
+                | GenoError::InvalidEscape { .. } => Exit::Parse,
+                GenoError::UnknownGenerator(_)
+                | GenoError::InvalidOption(_)
+                | GenoError::UnsupportedMapKey(..)
+                | GenoError::UnsupportedFixedArray(_)
+                | GenoError::Int64NotAllowed(_)
+                | GenoError::Driver(..)
+                | GenoError::MultipleFiles()
+                | GenoError::Template(_) => Exit::Generator,
+                GenoError::Io(_)
+                | GenoError::Registry(_)
+                | GenoError::InvalidManifest(..)
+                | GenoError::Package(_) => Exit::Io,
+                GenoError::PackageMismatch(_) => Exit::Failure,
+                GenoError::Incompatible(_) | GenoError::VersionMismatch(..) => Exit::Incompatible,
+                _ => Exit::Validation,
+            }
+        } else {
+            Exit::Failure
+        }
+    }
+}
+
+/// The command line asked for something that can't be done
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct UsageError(pub String);
+
+/// Context for errors from a generator, naming its format
+#[derive(Error, Debug)]
+#[error("{0} failed to generate code")]
+pub struct GeneratorFailed(pub String);
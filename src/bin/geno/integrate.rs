@@ -0,0 +1,139 @@
+use anyhow::{Context, bail};
+use clap::ValueEnum;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Build systems that `geno integrate` knows how to hook into
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum IntegrationTarget {
+    /// Flutter/Dart `build_runner`
+    Flutter,
+}
+
+const FLUTTER_BUILD_YAML: &str = r#"# Generated by `geno integrate flutter`
+builders:
+  geno:
+    import: "package:{{package}}/geno_builder.dart"
+    builder_factories: ["genoBuilder"]
+    build_extensions: {".geno": [".geno.dart"]}
+    auto_apply: root_package
+    build_to: source
+    defaults:
+      options:
+        format: {{format}}
+"#;
+
+const FLUTTER_BUILDER_DART: &str = r#"// Generated by `geno integrate flutter`. Runs the geno schema compiler for
+// every .geno file in the package as part of `dart run build_runner build`.
+import 'dart:io';
+
+import 'package:build/build.dart';
+
+Builder genoBuilder(BuilderOptions options) =>
+    GenoBuilder(options.config['format'] as String? ?? '{{format}}');
+
+class GenoBuilder implements Builder {
+  GenoBuilder(this.format);
+
+  final String format;
+
+  @override
+  final buildExtensions = const {
+    '.geno': ['.geno.dart'],
+  };
+
+  @override
+  Future<void> build(BuildStep buildStep) async {
+    final inputId = buildStep.inputId;
+
+    // Reading the input registers it as a dependency of the generated output
+    await buildStep.readAsString(inputId);
+
+    final result = await Process.run('geno', [inputId.path, '-f', format]);
+
+    if (result.exitCode != 0) {
+      throw StateError('geno failed for ${inputId.path}:\n${result.stderr}');
+    }
+
+    await buildStep.writeAsString(
+      inputId.changeExtension('.geno.dart'),
+      result.stdout as String,
+    );
+  }
+}
+"#;
+
+/// Write the files needed to integrate Geno with the given build system, returning the paths written
+pub fn integrate(
+    target: IntegrationTarget,
+    dir: &Path,
+    format: &str,
+    force: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    match target {
+        IntegrationTarget::Flutter => integrate_flutter(dir, format, force),
+    }
+}
+
+fn integrate_flutter(dir: &Path, format: &str, force: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let pubspec_path = dir.join("pubspec.yaml");
+    let pubspec = fs::read_to_string(&pubspec_path).context(format!(
+        "Could not read '{}', run this from the root of a Flutter or Dart package",
+        pubspec_path.to_string_lossy()
+    ))?;
+    let package = match pubspec_package_name(&pubspec) {
+        Some(name) => name,
+        None => bail!(
+            "No package name found in '{}'",
+            pubspec_path.to_string_lossy()
+        ),
+    };
+    let files = [
+        (
+            dir.join("build.yaml"),
+            FLUTTER_BUILD_YAML
+                .replace("{{package}}", package)
+                .replace("{{format}}", format),
+        ),
+        (
+            dir.join("lib").join("geno_builder.dart"),
+            FLUTTER_BUILDER_DART.replace("{{format}}", format),
+        ),
+    ];
+
+    // Check everything up front so that we never leave a half finished integration behind
+    if !force {
+        for (path, _) in &files {
+            if path.exists() {
+                bail!(
+                    "'{}' already exists, use --force to overwrite it",
+                    path.to_string_lossy()
+                );
+            }
+        }
+    }
+
+    let mut written = Vec::new();
+
+    for (path, content) in files {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)
+            .context(format!("Could not write '{}'", path.to_string_lossy()))?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Find the top level `name:` entry in a pubspec.yaml file
+fn pubspec_package_name(pubspec: &str) -> Option<&str> {
+    pubspec.lines().find_map(|line| {
+        line.strip_prefix("name:")
+            .map(|rest| rest.trim().trim_matches(|c| c == '"' || c == '\''))
+            .filter(|name| !name.is_empty())
+    })
+}
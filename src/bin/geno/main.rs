@@ -0,0 +1,1126 @@
+mod daemon;
+mod exit;
+mod integrate;
+mod plugin;
+mod scaffold;
+mod serve;
+
+use anyhow::{Context, bail};
+use clap::{ArgAction, Parser, Subcommand};
+use exit::{Exit, GeneratorFailed, UsageError};
+use geno::{
+    GenoAstBuilder, GenoError, GenoWarning,
+    ast::SchemaVersion,
+    budget, bundle,
+    codegen::{
+        CodeSink, GeneratedFile, Generator, Options, Registry,
+        sink::{DirSink, OutputSink, Staging},
+        template::TemplateGenerator,
+        util::integer_type_str,
+    },
+    compat::{self, Change},
+    depfile,
+    manifest::{self, Manifest},
+    packages::{self, PackageSpec},
+    roundtrip,
+    schema_registry::{RegistryClient, RegistryMode, SchemaSelector},
+    snapshot::{self, Mismatch},
+    strip,
+};
+use integrate::IntegrationTarget;
+use scaffold::GeneratorLang;
+use std::{
+    fs::{self, File},
+    io::{self, IsTerminal, Write, stdout},
+    path::{Path, PathBuf},
+    process::exit,
+    time::{Duration, Instant},
+};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser)]
+#[command(
+    name = "geno",
+    version,
+    about = "Geno schema compiler",
+    long_about = "Geno is a schema compiler for generating source code from a schema definition.",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input .geno file
+    #[arg(value_name = "INPUT_FILE", required = true)]
+    input_path: Option<PathBuf>,
+
+    /// Output file path for the generated source code, or STDOUT if not provided
+    #[arg(value_name = "OUTPUT_FILE", short = 'o', long)]
+    output_path: Option<PathBuf>,
+
+    /// Intermediate AST file path for debugging. Program will write the AST
+    /// to this file in MessagePack format then exit.
+    #[arg(value_name = "AST_FILE", short = 't', long)]
+    ast_path: Option<PathBuf>,
+
+    /// Output source code formats (e.g. -f dart-mp, -f rust-serde,dart-mp or -f template:DIR).
+    /// With more than one, each is written to a directory of its own in the output directory.
+    #[arg(
+        value_name = "FORMATS",
+        short = 'f',
+        long = "format",
+        value_delimiter = ','
+    )]
+    formats: Vec<String>,
+
+    /// Generator option, may be given more than once (e.g. -O derive=Hash)
+    #[arg(value_name = "KEY=VALUE", short = 'O', long = "option")]
+    options: Vec<String>,
+
+    /// Generate only these types and the types they use (e.g. --only order,customer)
+    #[arg(value_name = "TYPES", long, value_delimiter = ',')]
+    only: Vec<String>,
+
+    /// Leave out these types, unless a generated type uses them
+    #[arg(value_name = "TYPES", long, value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    /// Lints to turn off (e.g. --allow double_nullable,unreachable_type)
+    #[arg(value_name = "LINTS", long, value_delimiter = ',')]
+    allow: Vec<String>,
+
+    /// Record the generated files in a geno-manifest.json in the output directory
+    #[arg(long, requires = "output_path")]
+    manifest: bool,
+
+    /// Write a Makefile depfile listing the generated files and the files they depend on
+    #[arg(value_name = "DEPFILE", long, requires = "output_path")]
+    depfile: Option<PathBuf>,
+
+    /// Build in strict mode, as if the schema had strict = 1 in its metadata
+    #[arg(long)]
+    strict: bool,
+
+    /// Seconds to let a generator plugin run before stopping it
+    #[arg(value_name = "SECONDS", long, default_value_t = 60)]
+    plugin_timeout: u64,
+
+    /// Log what geno is doing to STDERR, -vv for more detail.  GENO_LOG overrides this.
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Wire Geno code generation into another build system
+    Integrate {
+        /// Build system to integrate with
+        #[arg(value_enum)]
+        target: IntegrationTarget,
+
+        /// Root directory of the project to integrate with
+        #[arg(value_name = "DIR", short = 'd', long, default_value = ".")]
+        dir: PathBuf,
+
+        /// Output source code format the build hook will generate
+        #[arg(value_name = "FORMAT", short = 'f', long, default_value = "dart-mp")]
+        format: String,
+
+        /// Overwrite files left by a previous integration
+        #[arg(long)]
+        force: bool,
+    },
+    /// Round trip the conformance test vectors through generated code for each target
+    Test {
+        /// Input .geno file
+        #[arg(value_name = "INPUT_FILE")]
+        input_path: PathBuf,
+
+        /// Targets to pass the vectors through, in order
+        #[arg(
+            value_name = "FORMATS",
+            long,
+            value_delimiter = ',',
+            default_value = "rust-mp,dart-mp"
+        )]
+        targets: Vec<String>,
+
+        /// Generator option, may be given more than once (e.g. -O wire=keyed)
+        #[arg(value_name = "KEY=VALUE", short = 'O', long = "option")]
+        options: Vec<String>,
+
+        /// Directory to keep the generated code and results in, or a temporary directory if not provided
+        #[arg(value_name = "DIR", short = 'd', long)]
+        dir: Option<PathBuf>,
+    },
+    /// Package a schema and the code generated from it into a tar archive
+    Bundle {
+        /// Input .geno file
+        #[arg(value_name = "INPUT_FILE")]
+        input_path: PathBuf,
+
+        /// Formats to generate into the bundle (e.g. -f rust-serde,dart-mp)
+        #[arg(
+            value_name = "FORMATS",
+            short = 'f',
+            long = "format",
+            value_delimiter = ',',
+            required = true
+        )]
+        formats: Vec<String>,
+
+        /// Generator option, may be given more than once (e.g. -O wire=keyed)
+        #[arg(value_name = "KEY=VALUE", short = 'O', long = "option")]
+        options: Vec<String>,
+
+        /// Bundle file path, or the input file name with a .tar extension if not provided
+        #[arg(value_name = "BUNDLE_FILE", short = 'o', long)]
+        output_path: Option<PathBuf>,
+
+        /// Seconds to let a generator plugin run before stopping it
+        #[arg(value_name = "SECONDS", long, default_value_t = 60)]
+        plugin_timeout: u64,
+    },
+    /// Check a schema for problems beyond validity, such as messages that can go over their size
+    /// budgets
+    Check {
+        /// Input .geno file
+        #[arg(value_name = "INPUT_FILE")]
+        input_path: PathBuf,
+
+        /// Check that no struct with a `@budget` can pack to more bytes than it allows
+        #[arg(long, required = true)]
+        budgets: bool,
+
+        /// Generator option, may be given more than once (e.g. -O wire=keyed)
+        #[arg(value_name = "KEY=VALUE", short = 'O', long = "option")]
+        options: Vec<String>,
+    },
+    /// Compare two versions of a schema and check that the new version suits its changes
+    Compat {
+        /// The schema before the changes
+        #[arg(value_name = "OLD_FILE")]
+        old_path: PathBuf,
+
+        /// The schema after the changes
+        #[arg(value_name = "NEW_FILE")]
+        new_path: PathBuf,
+    },
+    /// Set the version of a schema to the one its changes since an older version call for
+    Bump {
+        /// The schema before the changes
+        #[arg(value_name = "OLD_FILE")]
+        old_path: PathBuf,
+
+        /// The schema after the changes, whose version is set
+        #[arg(value_name = "NEW_FILE")]
+        new_path: PathBuf,
+
+        /// Only print the version, without changing NEW_FILE
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Rewrite a schema in place, keeping it as written apart from the changes asked for
+    Fmt {
+        /// Input .geno file
+        #[arg(value_name = "INPUT_FILE")]
+        input_path: PathBuf,
+
+        /// Narrow enums and integer fields to the smallest type that holds their values, rather
+        /// than only listing them
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Rename types, fields and variants to short tokens and drop comments and the annotations
+    /// that don't change the wire, writing the tokens' names to a rename map
+    Strip {
+        /// Input .geno file
+        #[arg(value_name = "INPUT_FILE")]
+        input_path: PathBuf,
+
+        /// Output file for the stripped schema, or STDOUT if not provided
+        #[arg(value_name = "OUTPUT_FILE", short = 'o', long)]
+        output_path: Option<PathBuf>,
+
+        /// File to write the rename map to, as JSON
+        #[arg(value_name = "MAP_FILE", short = 'm', long)]
+        map: PathBuf,
+    },
+    /// Answer JSON requests to parse, validate, generate and diff schemas, one per line, for
+    /// editors and build tools that keep geno running
+    Serve {
+        /// Read requests from STDIN and write responses to STDOUT
+        #[arg(long, required = true)]
+        stdio: bool,
+
+        /// Seconds to let a generator plugin run before stopping it
+        #[arg(value_name = "SECONDS", long, default_value_t = 60)]
+        plugin_timeout: u64,
+    },
+    /// Keep the schemas under some directories parsed as they change, and answer the requests of
+    /// serve mode from clients connecting to a Unix socket
+    Daemon {
+        /// Directories to watch for .geno files
+        #[arg(value_name = "ROOT_DIR", default_value = ".")]
+        roots: Vec<PathBuf>,
+
+        /// Path of the socket to listen on
+        #[arg(
+            value_name = "SOCKET_FILE",
+            short = 's',
+            long,
+            default_value = "geno.sock"
+        )]
+        socket: PathBuf,
+
+        /// Milliseconds between scans of the directories for changes
+        #[arg(value_name = "MILLISECONDS", long, default_value_t = 500)]
+        poll_interval: u64,
+
+        /// Seconds to let a generator plugin run before stopping it
+        #[arg(value_name = "SECONDS", long, default_value_t = 60)]
+        plugin_timeout: u64,
+    },
+    /// Publish a schema to a schema registry
+    Publish {
+        /// Input .geno file
+        #[arg(value_name = "INPUT_FILE")]
+        input_path: PathBuf,
+
+        /// Base URL of the schema registry
+        #[arg(value_name = "URL", short = 'r', long)]
+        registry: String,
+
+        /// Subject to publish the schema under
+        #[arg(value_name = "SUBJECT", short = 's', long)]
+        subject: String,
+
+        /// Use the Confluent Schema Registry request format
+        #[arg(long)]
+        confluent: bool,
+    },
+    /// Fetch a schema from a schema registry
+    Fetch {
+        /// Base URL of the schema registry
+        #[arg(value_name = "URL", short = 'r', long)]
+        registry: String,
+
+        /// Subject to fetch the schema from
+        #[arg(
+            value_name = "SUBJECT",
+            short = 's',
+            long,
+            required_unless_present = "id"
+        )]
+        subject: Option<String>,
+
+        /// Version of the subject, or the newest version if not provided
+        #[arg(value_name = "VERSION", short = 'v', long, requires = "subject")]
+        version: Option<u32>,
+
+        /// Registry wide schema id
+        #[arg(value_name = "ID", long, conflicts_with = "subject")]
+        id: Option<u64>,
+
+        /// Output file path for the schema, or STDOUT if not provided
+        #[arg(value_name = "OUTPUT_FILE", short = 'o', long)]
+        output_path: Option<PathBuf>,
+
+        /// Use the Confluent Schema Registry request format
+        #[arg(long)]
+        confluent: bool,
+    },
+    /// Remove the generated files recorded in a directory's geno-manifest.json
+    Clean {
+        /// Directory holding the manifest
+        #[arg(value_name = "DIR", default_value = ".")]
+        dir: PathBuf,
+
+        /// Only remove files written by this generator
+        #[arg(value_name = "FORMAT", short = 'f', long)]
+        format: Option<String>,
+
+        /// Only remove files generated from this schema
+        #[arg(value_name = "INPUT_FILE", short = 's', long)]
+        schema: Option<PathBuf>,
+
+        /// Remove files even if they changed after they were generated
+        #[arg(long)]
+        force: bool,
+    },
+    /// Start a project for a new generator plugin, with snapshot tests of the example schema
+    NewGenerator {
+        /// Format the plugin generates, which names its binary geno-<NAME>
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Language to write the plugin in
+        #[arg(value_enum, long)]
+        lang: GeneratorLang,
+
+        /// Directory to create the project in, geno-<NAME> if not provided
+        #[arg(value_name = "DIR", short = 'd', long)]
+        dir: Option<PathBuf>,
+    },
+    /// Add a schema package to geno_modules, so schemas can import it by namespace
+    Add {
+        /// Git repository of the package, with a tag or branch after an @
+        /// (e.g. github.com/org/common-schemas@v2)
+        #[arg(value_name = "PACKAGE")]
+        package: String,
+
+        /// Namespace to import the package by, from the repository name if not provided
+        #[arg(value_name = "NAMESPACE", long = "as")]
+        namespace: Option<String>,
+
+        /// Root directory of the project, holding geno_modules and geno-lock.json
+        #[arg(value_name = "DIR", short = 'd', long, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Fetch every package in geno-lock.json into geno_modules at its locked commit
+    Vendor {
+        /// Root directory of the project, holding geno_modules and geno-lock.json
+        #[arg(value_name = "DIR", short = 'd', long, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Show which generator would be used for each format, built in or a plugin binary
+    Doctor {
+        /// Formats to look up, or every built-in format and plugin found if not provided
+        #[arg(value_name = "FORMATS", value_delimiter = ',')]
+        formats: Vec<String>,
+    },
+    /// Compare generator output for each .geno file in a directory against golden files
+    Snapshot {
+        /// Directory of .geno inputs and their snapshots
+        #[arg(value_name = "DIR")]
+        dir: PathBuf,
+
+        /// Generators to check, or every built-in generator if not provided
+        #[arg(value_name = "FORMATS", short = 'f', long, value_delimiter = ',')]
+        formats: Vec<String>,
+
+        /// Generator option, may be given more than once (e.g. -O wire=keyed)
+        #[arg(value_name = "KEY=VALUE", short = 'O', long = "option")]
+        options: Vec<String>,
+
+        /// Update the snapshots to match the generated output
+        #[arg(long)]
+        bless: bool,
+    },
+}
+
+fn main() {
+    match run() {
+        Ok(code) => exit(code as i32),
+        Err(root_err) => {
+            for err in root_err.chain() {
+                eprintln!("error: {}", err);
+            }
+            exit(Exit::of(&root_err) as i32);
+        }
+    }
+}
+
+fn run() -> anyhow::Result<Exit> {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            // Help and version go to stdout, as they aren't errors, and real errors to stderr
+            err.print()?;
+            return Ok(if err.use_stderr() {
+                Exit::Usage
+            } else {
+                Exit::Success
+            });
+        }
+    };
+
+    init_logging(cli.verbose);
+
+    if let Some(command) = cli.command {
+        return run_command(command);
+    }
+
+    // Parse the input string into an AST
+    let input_path = cli
+        .input_path
+        .ok_or_else(|| UsageError("No input file specified".to_string()))?;
+
+    let ast_builder = GenoAstBuilder::new(input_path.clone()).strict(cli.strict);
+    let (ast, modules) = ast_builder.build_with_modules()?;
+    let strict = cli.strict || ast.is_strict();
+
+    if let Some(lint) = cli
+        .allow
+        .iter()
+        .find(|lint| !GenoWarning::LINTS.contains(&lint.as_str()))
+    {
+        bail!(UsageError(format!("Unknown lint '{lint}'")));
+    }
+
+    // Strict mode has already turned the schema's warnings into errors, only @allow can stop them
+    let allowed =
+        |warning: &GenoWarning| !strict && cli.allow.iter().any(|lint| lint == warning.lint());
+
+    for warning in ast.warnings() {
+        if !allowed(&warning) {
+            eprintln!("warning: {warning}");
+        }
+    }
+
+    let ast = if cli.only.is_empty() && cli.exclude.is_empty() {
+        ast
+    } else {
+        ast.subset(&cli.only, &cli.exclude)?
+    };
+
+    // If the user specified an AST output path, write the AST to that file and exit
+    if let Some(ast_path) = cli.ast_path {
+        let mut file = File::create(&ast_path).context(format!(
+            "Could not create AST file '{}'",
+            ast_path.to_string_lossy()
+        ))?;
+
+        rmp_serde::encode::write_named(&mut file, &ast)
+            .context("Failed to serialize AST to MessagePack")?;
+        return Ok(Exit::Success);
+    }
+
+    if cli.formats.is_empty() {
+        bail!(UsageError("No output format specified".to_string()));
+    }
+
+    let output_path = match (&cli.output_path, cli.formats.len()) {
+        (path, 1) => path.clone(),
+        (Some(path), _) => Some(path.clone()),
+        (None, _) => bail!(UsageError(
+            "Several output formats need an output directory".to_string()
+        )),
+    };
+    let options = Options::parse(&cli.options)?;
+    let plugin_timeout = Duration::from_secs(cli.plugin_timeout);
+    let registry = Registry::with_builtins();
+
+    check_options(&registry, &cli.formats, &options)?;
+
+    // Every target's files are staged, and only moved into place once all of them succeed
+    let mut staging = Staging::new(
+        output_path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(".")),
+    );
+    let mut targets = Vec::new();
+    // Generated code is stale once the schema or any module it imports, directly or not, changes
+    let mut inputs = vec![input_path.clone()];
+
+    inputs.extend(modules);
+
+    for format in &cli.formats {
+        let template_generator;
+        let generator: Option<&dyn Generator> = match format.strip_prefix("template:") {
+            Some(dir) => {
+                template_generator = TemplateGenerator::new(dir);
+                inputs.extend(template_generator.templates()?);
+                Some(&template_generator)
+            }
+            None => registry.get(format),
+        };
+
+        // Check the schema against the target before generating anything for it
+        if let Some(generator) = generator {
+            let capabilities = generator.capabilities();
+
+            for warning in ast.target_warnings(&capabilities) {
+                if strict {
+                    return Err(GenoError::Strict(format!("{format}: {warning}")).into());
+                } else if !allowed(&warning) {
+                    eprintln!("warning: {format}: {warning}");
+                }
+            }
+            ast.check_capabilities(&capabilities)
+                .with_context(|| format!("{format} can't generate code for this schema"))?;
+        }
+
+        let (target_path, written) = if cli.formats.len() == 1 {
+            let mut sink = OutputSink::new(output_path.clone(), &mut staging);
+
+            generate(format, generator, &ast, &options, plugin_timeout, &mut sink)?;
+            (output_path.clone(), sink.finish())
+        } else {
+            let dir = output_path
+                .as_deref()
+                .unwrap_or(Path::new("."))
+                .join(generator.map_or(format.as_str(), |generator| generator.name()));
+            let mut sink = DirSink::new(&dir, &mut staging);
+
+            generate(format, generator, &ast, &options, plugin_timeout, &mut sink)?;
+            (Some(dir), sink.written().to_vec())
+        };
+
+        targets.push((format, target_path, written));
+    }
+
+    staging.commit()?;
+
+    if let Some(depfile_path) = &cli.depfile {
+        let written: Vec<PathBuf> = targets
+            .iter()
+            .flat_map(|(_, _, written)| written.iter().cloned())
+            .collect();
+
+        depfile::write(depfile_path, &written, &inputs).context(format!(
+            "Could not write depfile '{}'",
+            depfile_path.to_string_lossy()
+        ))?;
+    }
+
+    if cli.manifest {
+        for (format, target_path, written) in targets {
+            if let Some(target_path) = target_path {
+                record_files(&written, &target_path, format, &input_path, &ast)?;
+            }
+        }
+    }
+
+    Ok(Exit::Success)
+}
+
+/// Generate code for one target into `sink`, with a built-in generator or a plugin
+fn generate(
+    format: &str,
+    generator: Option<&dyn Generator>,
+    ast: &geno::ast::Schema,
+    options: &Options,
+    plugin_timeout: Duration,
+    sink: &mut dyn CodeSink,
+) -> anyhow::Result<()> {
+    let _span = tracing::info_span!("generate", format).entered();
+    let start = Instant::now();
+    let result = match generator {
+        Some(generator) => generator
+            .generate_to(ast, options, sink)
+            .map_err(Into::into),
+        None => plugin::run(format, ast, options, plugin_timeout).and_then(|output| {
+            Ok(sink.write_file(Path::new("geno.out"), &mut |out| {
+                Ok(out.write_all(output.as_bytes())?)
+            })?)
+        }),
+    };
+
+    tracing::info!(ok = result.is_ok(), elapsed = ?start.elapsed(), "generated");
+    result.context(GeneratorFailed(format.to_string()))
+}
+
+/// Check that each option is read by the generator of one of `formats`.  Templates and plugins
+/// take any option, so with one of them every option passes.
+fn check_options(
+    registry: &Registry,
+    formats: &[String],
+    options: &Options,
+) -> Result<(), GenoError> {
+    let mut generators = Vec::new();
+
+    for format in formats {
+        match registry.get(format) {
+            Some(generator) => generators.push(generator),
+            None => return Ok(()),
+        }
+    }
+
+    options.check_keys(generators)
+}
+
+/// Log to STDERR at a level set by the number of `-v` flags, or by a `GENO_LOG` filter such as
+/// `GENO_LOG=geno=trace`
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => "warn",
+        1 => "geno=info",
+        2 => "geno=debug",
+        _ => "geno=trace",
+    };
+    let filter = EnvFilter::try_from_env("GENO_LOG").unwrap_or_else(|_| EnvFilter::new(level));
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+}
+
+/// Record the written files in the manifest of the directory they are written to
+fn record_files(
+    written: &[PathBuf],
+    output_path: &Path,
+    format: &str,
+    input_path: &Path,
+    ast: &geno::ast::Schema,
+) -> anyhow::Result<()> {
+    // A single file is written to the output path itself, so it is recorded by its file name
+    let dir = match written {
+        [path] if path == output_path => output_path.parent().unwrap_or(Path::new("")),
+        _ => output_path,
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let paths: Vec<PathBuf> = written
+        .iter()
+        .map(|path| {
+            path.strip_prefix(output_path)
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .unwrap_or(Path::new(path.file_name().unwrap_or_default()))
+                .to_path_buf()
+        })
+        .collect();
+    let mut manifest = Manifest::read(dir)?;
+
+    manifest.record_written(
+        dir,
+        format,
+        &input_path.to_string_lossy(),
+        &ast.fingerprint(),
+        &paths,
+    )?;
+    manifest.write(dir)?;
+
+    Ok(())
+}
+
+/// Print each change with its class, then the class of them all and the schema version
+fn print_changes(changes: &[Change], version: SchemaVersion) {
+    for change in changes {
+        println!("{}: {}", change.class, change.description);
+    }
+    println!("{} change, version {version}", compat::class(changes));
+}
+
+fn registry_mode(confluent: bool) -> RegistryMode {
+    if confluent {
+        RegistryMode::Confluent
+    } else {
+        RegistryMode::Geno
+    }
+}
+
+fn run_command(command: Command) -> anyhow::Result<Exit> {
+    match command {
+        Command::Integrate {
+            target,
+            dir,
+            format,
+            force,
+        } => {
+            for path in integrate::integrate(target, &dir, &format, force)? {
+                println!("Wrote '{}'", path.to_string_lossy());
+            }
+
+            match target {
+                IntegrationTarget::Flutter => println!(
+                    "Add 'build' to dependencies and 'build_runner' to dev_dependencies, then run 'dart run build_runner build'"
+                ),
+            }
+        }
+        Command::Bundle {
+            input_path,
+            formats,
+            options,
+            output_path,
+            plugin_timeout,
+        } => {
+            let source = fs::read_to_string(&input_path)
+                .context(format!("Could not read '{}'", input_path.to_string_lossy()))?;
+            let ast = GenoAstBuilder::new(input_path.clone()).build_from_str(&source)?;
+            let options = Options::parse(&options)?;
+            let registry = Registry::with_builtins();
+            let mut targets = Vec::new();
+
+            check_options(&registry, &formats, &options)?;
+
+            for format in &formats {
+                let template_generator;
+                let generator: Option<&dyn Generator> = match format.strip_prefix("template:") {
+                    Some(dir) => {
+                        template_generator = TemplateGenerator::new(dir);
+                        Some(&template_generator)
+                    }
+                    None => registry.get(format),
+                };
+                let files = match generator {
+                    Some(generator) => ast
+                        .check_capabilities(&generator.capabilities())
+                        .and_then(|()| generator.generate(&ast, &options))
+                        .map_err(Into::into),
+                    None => {
+                        plugin::run(format, &ast, &options, Duration::from_secs(plugin_timeout))
+                            .map(|output| vec![GeneratedFile::new("geno.out", output)])
+                    }
+                }
+                .context(GeneratorFailed(format.clone()))?;
+
+                targets.push((
+                    generator.map_or(format.clone(), |generator| generator.name().to_string()),
+                    files,
+                ));
+            }
+
+            let output_path = output_path.unwrap_or_else(|| input_path.with_extension("tar"));
+            let mut staging = Staging::new(output_path.parent().unwrap_or(Path::new(".")));
+            let mut sink = OutputSink::new(Some(output_path.clone()), &mut staging);
+
+            sink.write_file(Path::new("bundle.tar"), &mut |out| {
+                bundle::write(out, &input_path.to_string_lossy(), &source, &ast, &targets)
+            })?;
+            sink.finish();
+            staging.commit()?;
+            println!("Wrote '{}'", output_path.to_string_lossy());
+        }
+        Command::Test {
+            input_path,
+            targets,
+            options,
+            dir,
+        } => {
+            let ast = GenoAstBuilder::new(input_path).build()?;
+            let options = Options::parse(&options)?;
+            let temp_dir;
+            let work_dir = match dir {
+                Some(dir) => dir,
+                None => {
+                    temp_dir = tempfile::tempdir()?;
+                    temp_dir.path().to_path_buf()
+                }
+            };
+            let report = roundtrip::run(&ast, &options, &targets, &work_dir)?;
+
+            for failure in &report.failures {
+                eprintln!("{}: {}: {}", failure.target, failure.file, failure.message);
+            }
+            println!(
+                "{} vectors through {}, {} failed",
+                report.vectors,
+                targets.join(" -> "),
+                report.failures.len()
+            );
+
+            if !report.failures.is_empty() {
+                return Ok(Exit::Failure);
+            }
+        }
+        Command::Check {
+            input_path,
+            budgets: _,
+            options,
+        } => {
+            let ast = GenoAstBuilder::new(input_path).build()?;
+            let options = Options::parse(&options)?;
+            let report = budget::check(&ast, &options)?;
+            let over = report.iter().filter(|budget| !budget.is_met()).count();
+
+            for budget in &report {
+                match budget.upper_bound {
+                    Some(bound) if budget.is_met() => println!(
+                        "ok: '{}' packs to at most {bound} of {} bytes",
+                        budget.ident, budget.max_packed_bytes
+                    ),
+                    Some(bound) => println!(
+                        "over: '{}' can pack to {bound} bytes, more than its {}",
+                        budget.ident, budget.max_packed_bytes
+                    ),
+                    None => println!(
+                        "over: '{}' has no maximum size, as a string, list or map in it has no maximum length",
+                        budget.ident
+                    ),
+                }
+            }
+            println!("{} budgets checked, {over} over", report.len());
+            if over != 0 {
+                return Ok(Exit::Failure);
+            }
+        }
+        Command::Compat { old_path, new_path } => {
+            let old = GenoAstBuilder::new(old_path).build()?;
+            let new = GenoAstBuilder::new(new_path).build()?;
+            let changes = compat::changes(&old, &new)?;
+            let expected = compat::next_version(&old, &new, &changes);
+            let found = compat::version(&new);
+
+            print_changes(&changes, found);
+            if found != expected {
+                bail!(GenoError::VersionMismatch(
+                    compat::class(&changes),
+                    found,
+                    expected
+                ));
+            }
+        }
+        Command::Bump {
+            old_path,
+            new_path,
+            dry_run,
+        } => {
+            let old = GenoAstBuilder::new(old_path).build()?;
+            let source = fs::read_to_string(&new_path)
+                .context(format!("Could not read '{}'", new_path.to_string_lossy()))?;
+            let builder = GenoAstBuilder::new(new_path.clone());
+            let new = builder.build_from_str(&source)?;
+            let changes = compat::changes(&old, &new)?;
+            let version = compat::next_version(&old, &new, &changes);
+
+            print_changes(&changes, version);
+            if !dry_run && new.schema_version() != Some(version) {
+                fs::write(&new_path, builder.set_version(&source, version)?)?;
+                println!("Wrote '{}'", new_path.to_string_lossy());
+            }
+        }
+        Command::Fmt { input_path, fix } => {
+            let source = fs::read_to_string(&input_path)
+                .context(format!("Could not read '{}'", input_path.to_string_lossy()))?;
+            let (narrowed, narrowings) =
+                GenoAstBuilder::new(input_path.clone()).narrow_integers(&source)?;
+
+            for narrowing in &narrowings {
+                println!(
+                    "'{}' {} -> {}",
+                    narrowing.name,
+                    integer_type_str(&narrowing.from),
+                    integer_type_str(&narrowing.to)
+                );
+            }
+            if fix && narrowed != source {
+                fs::write(&input_path, narrowed)?;
+                println!("Wrote '{}'", input_path.to_string_lossy());
+            }
+        }
+        Command::Strip {
+            input_path,
+            output_path,
+            map,
+        } => {
+            let (stripped, renames) = strip::strip(&GenoAstBuilder::new(input_path).build()?);
+            let source = strip::source(&stripped);
+
+            fs::write(&map, serde_json::to_string_pretty(&renames)? + "\n")
+                .context(format!("Could not write '{}'", map.to_string_lossy()))?;
+            match output_path {
+                Some(path) => fs::write(path, source)?,
+                None => stdout().write_all(source.as_bytes())?,
+            }
+        }
+        Command::Serve {
+            stdio: _,
+            plugin_timeout,
+        } => serve::Server::new(Vec::new(), Duration::from_secs(plugin_timeout))
+            .serve(io::stdin().lock(), stdout().lock())?,
+        Command::Daemon {
+            roots,
+            socket,
+            poll_interval,
+            plugin_timeout,
+        } => daemon::run(
+            serve::Server::new(roots, Duration::from_secs(plugin_timeout)),
+            &socket,
+            Duration::from_millis(poll_interval),
+        )?,
+        Command::Publish {
+            input_path,
+            registry,
+            subject,
+            confluent,
+        } => {
+            let source = fs::read_to_string(&input_path)
+                .context(format!("Could not read '{}'", input_path.to_string_lossy()))?;
+            let client = RegistryClient::new(&registry, registry_mode(confluent));
+            let published = client.publish(&subject, &source)?;
+
+            match published.version {
+                Some(version) => println!(
+                    "Published '{subject}' version {version} as schema id {} ({})",
+                    published.id, published.fingerprint
+                ),
+                None => println!(
+                    "Published '{subject}' as schema id {} ({})",
+                    published.id, published.fingerprint
+                ),
+            }
+        }
+        Command::Fetch {
+            registry,
+            subject,
+            version,
+            id,
+            output_path,
+            confluent,
+        } => {
+            let selector = match (subject, id) {
+                (Some(subject), _) => SchemaSelector::Subject(subject, version),
+                (None, Some(id)) => SchemaSelector::Id(id),
+                (None, None) => bail!(UsageError("No subject or schema id specified".to_string())),
+            };
+            let client = RegistryClient::new(&registry, registry_mode(confluent));
+            let fetched = client.fetch(&selector)?;
+
+            match output_path {
+                Some(path) => fs::write(path, &fetched.source)?,
+                None => stdout().write_all(fetched.source.as_bytes())?,
+            }
+        }
+        Command::Clean {
+            dir,
+            format,
+            schema,
+            force,
+        } => {
+            let schema = schema.map(|path| path.to_string_lossy().into_owned());
+            let cleaned = manifest::clean(
+                &dir,
+                |entry| {
+                    format.as_ref().is_none_or(|format| entry.target == *format)
+                        && schema.as_ref().is_none_or(|schema| entry.schema == *schema)
+                },
+                force,
+            )?;
+
+            for path in &cleaned.modified {
+                eprintln!("changed, not removed: {}", path.to_string_lossy());
+            }
+            println!("{} files removed", cleaned.removed.len());
+
+            if !cleaned.modified.is_empty() {
+                return Ok(Exit::Failure);
+            }
+        }
+        Command::NewGenerator { name, lang, dir } => {
+            let dir = dir.unwrap_or_else(|| PathBuf::from(format!("geno-{name}")));
+
+            for path in scaffold::new_generator(&name, lang, &dir)? {
+                println!("Wrote '{}'", path.to_string_lossy());
+            }
+
+            match lang {
+                GeneratorLang::Rust => println!(
+                    "Run 'GENO_BLESS=1 cargo test' in '{}' to write the first snapshots",
+                    dir.to_string_lossy()
+                ),
+                GeneratorLang::Python => println!(
+                    "Run 'GENO_BLESS=1 python3 -m unittest' in '{}' to write the first snapshots",
+                    dir.to_string_lossy()
+                ),
+            }
+        }
+        Command::Add {
+            package,
+            namespace,
+            dir,
+        } => {
+            let spec = PackageSpec::parse(&package)?;
+            let (namespace, locked) = packages::add(&dir, &spec, namespace.as_deref())?;
+
+            println!(
+                "Added '{}' at {} as '{namespace}', import it with imports = \"{namespace}\"",
+                spec.source,
+                &locked.commit[..locked.commit.len().min(12)]
+            );
+        }
+        Command::Vendor { dir } => {
+            let namespaces = packages::vendor(&dir)?;
+
+            if namespaces.is_empty() {
+                println!("No packages in {}", packages::LOCK_FILE);
+            }
+            for namespace in namespaces {
+                println!("Vendored '{namespace}'");
+            }
+        }
+        Command::Doctor { formats } => {
+            let registry = Registry::with_builtins();
+            let formats = if formats.is_empty() {
+                let mut formats: Vec<String> = registry.names().map(String::from).collect();
+
+                formats.extend(
+                    plugin::discover()
+                        .into_iter()
+                        .filter(|format| registry.get(format).is_none()),
+                );
+                formats
+            } else {
+                formats
+            };
+            let mut missing = false;
+
+            if std::env::var("GENO_DEBUG").is_ok() {
+                println!("GENO_DEBUG is set, plugins are run with 'cargo run'");
+            }
+            for format in &formats {
+                if registry.get(format).is_some() {
+                    println!("{format}: built in");
+                    continue;
+                }
+                match plugin::resolve(format) {
+                    Ok(path) => println!("{format}: {}", path.to_string_lossy()),
+                    Err(_) => {
+                        println!("{format}: not found");
+                        missing = true;
+                    }
+                }
+            }
+
+            if missing {
+                return Ok(Exit::Failure);
+            }
+        }
+        Command::Snapshot {
+            dir,
+            formats,
+            options,
+            bless,
+        } => {
+            let options = Options::parse(&options)?;
+            let registry = Registry::with_builtins();
+            let formats = if formats.is_empty() {
+                registry.names().map(String::from).collect()
+            } else {
+                formats
+            };
+
+            check_options(&registry, &formats, &options)?;
+            let mut files = 0;
+            let mut mismatches = 0;
+
+            for format in &formats {
+                let generator = registry
+                    .get(format)
+                    .ok_or_else(|| GenoError::UnknownGenerator(format.clone()))?;
+                let report = snapshot::check(generator, &dir, &options, bless)?;
+
+                for mismatch in &report.mismatches {
+                    let verb = match (mismatch, bless) {
+                        (_, true) => "updated",
+                        (Mismatch::Missing(_), false) => "missing",
+                        (Mismatch::Changed(_), false) => "changed",
+                        (Mismatch::Unexpected(_), false) => "unexpected",
+                    };
+                    eprintln!("{verb}: {}", mismatch.path().to_string_lossy());
+                }
+                files += report.files;
+                mismatches += report.mismatches.len();
+            }
+
+            if bless {
+                println!("{files} files checked, {mismatches} updated");
+            } else {
+                println!("{files} files checked, {mismatches} differ");
+                if mismatches != 0 {
+                    return Ok(Exit::Failure);
+                }
+            }
+        }
+    }
+
+    Ok(Exit::Success)
+}
@@ -0,0 +1,122 @@
+use anyhow::{Context, bail};
+use duct::cmd;
+use geno::codegen::Options;
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Directories searched for generator plugins, those in `GENO_PLUGIN_PATH` before those in `PATH`
+pub fn search_path() -> Vec<PathBuf> {
+    ["GENO_PLUGIN_PATH", "PATH"]
+        .into_iter()
+        .filter_map(env::var_os)
+        .flat_map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .collect()
+}
+
+/// Find the `geno-<format>` binary for a format.  On Windows this also finds `.exe` and `.cmd`
+/// files, and anything else in `PATHEXT`.
+pub fn resolve(format: &str) -> anyhow::Result<PathBuf> {
+    let name = format!("geno-{format}");
+    let paths: OsString = env::join_paths(search_path())?;
+
+    which::which_in(&name, Some(paths), env::current_dir()?).with_context(|| {
+        format!(
+            "Unknown format '{format}', there is no '{name}' generator on GENO_PLUGIN_PATH or PATH"
+        )
+    })
+}
+
+/// Formats of the `geno-<format>` binaries found on the search path
+pub fn discover() -> BTreeSet<String> {
+    search_path()
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = if cfg!(windows) && has_executable_extension(&path) {
+                path.file_stem()?
+            } else {
+                path.file_name()?
+            };
+
+            name.to_str()?.strip_prefix("geno-").map(String::from)
+        })
+        .collect()
+}
+
+/// Whether the path ends in an extension Windows runs, such as `.exe` or `.cmd`
+fn has_executable_extension(path: &Path) -> bool {
+    let extensions = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+
+    path.extension().is_some_and(|extension| {
+        extensions.split(';').any(|known| {
+            known
+                .strip_prefix('.')
+                .is_some_and(|known| extension.eq_ignore_ascii_case(known))
+        })
+    })
+}
+
+/// Run an external `geno-<format>` generator, passing the AST on stdin and options as arguments.
+/// Its stderr is passed on once it exits, so it can't get mixed up with generated code, and it is
+/// killed if it runs for longer than `timeout`.
+pub fn run(
+    format: &str,
+    ast: &geno::ast::Schema,
+    options: &Options,
+    timeout: Duration,
+) -> anyhow::Result<String> {
+    let name = format!("geno-{format}");
+    let mut args: Vec<String> = Vec::new();
+    let program = if env::var("GENO_DEBUG").is_ok() {
+        args.extend(["run", "--bin", &name, "--"].map(String::from));
+        PathBuf::from("cargo")
+    } else {
+        resolve(format)?
+    };
+
+    args.extend(options.iter().map(|(key, value)| format!("{key}={value}")));
+
+    tracing::info!(program = %program.display(), ?args, "running plugin");
+
+    let ast_bytes =
+        rmp_serde::to_vec_named(ast).context("Failed to serialize AST to MessagePack")?;
+    let handle = cmd(&program, &args)
+        .stdin_bytes(ast_bytes)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .start()
+        .with_context(|| format!("Failed to run '{}'", program.display()))?;
+    let Some(output) = handle.wait_timeout(timeout)? else {
+        handle.kill()?;
+        bail!(
+            "'{}' didn't finish within {}s",
+            program.display(),
+            timeout.as_secs()
+        );
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if !output.status.success() {
+        bail!(
+            "'{}' failed with {}: {}",
+            program.display(),
+            output.status,
+            stderr.trim_end()
+        );
+    }
+    for line in stderr.lines() {
+        eprintln!("{name}: {line}");
+    }
+
+    String::from_utf8(output.stdout.clone())
+        .with_context(|| format!("'{}' wrote output that isn't UTF-8", program.display()))
+}
@@ -0,0 +1,402 @@
+use anyhow::{Context, bail};
+use clap::ValueEnum;
+use geno::codegen::util::{to_pascal_case, to_snake_case};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Languages that `geno new-generator` can start a plugin in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GeneratorLang {
+    /// A Rust crate using `geno-plugin`
+    Rust,
+    /// A Python script reading the AST as plain dicts and lists
+    Python,
+}
+
+/// The schema every new generator is snapshot tested against
+const EXAMPLE_GENO: &str = include_str!("../../../examples/example.geno");
+
+const RUST_CARGO_TOML: &str = r##"[package]
+name = "geno-{{name}}"
+version = "0.1.0"
+edition = "2024"
+description = "Geno generator plugin for the {{name}} format"
+
+[dependencies]
+geno-plugin = "{{version}}"
+"##;
+
+const RUST_LIB_RS: &str = r##"//! Geno generator plugin for the `{{name}}` format.  Replace the body of
+//! [{{type}}Generator::generate] with the code for your target.
+use geno_plugin::{
+    CodeWriter, GeneratedFile, GeneratedFiles, Generator, GenoError, Options, ast,
+    integer_value_str, schema_type_str, select_target,
+};
+use std::fmt::Write as _;
+
+/// Generator for the `{{name}}` format
+pub struct {{type}}Generator;
+
+impl Generator for {{type}}Generator {
+    fn name(&self) -> &str {
+        "{{name}}"
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        // Fields marked @target({{name}}) are only in this generator's output
+        let schema = &*select_target(schema, options, Some("{{name}}"))?;
+        let mut w = CodeWriter::new("  ");
+
+        w.line("# Generated by geno-{{name}}");
+        for decl in &schema.declarations {
+            match decl {
+                ast::Declaration::Struct { ident, fields, .. } => {
+                    w.blank();
+                    writeln!(w, "struct {ident}").unwrap();
+                    w.indent();
+                    for (name, field_type) in fields {
+                        writeln!(w, "{name}: {}", schema_type_str(field_type)).unwrap();
+                    }
+                    w.dedent();
+                }
+                ast::Declaration::Enum {
+                    ident, variants, ..
+                } => {
+                    w.blank();
+                    writeln!(w, "enum {ident}").unwrap();
+                    w.indent();
+                    for (name, value) in variants {
+                        writeln!(w, "{name} = {}", integer_value_str(value)).unwrap();
+                    }
+                    w.dedent();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(vec![GeneratedFile::new("geno.txt", w.into_string())])
+    }
+}
+"##;
+
+const RUST_MAIN_RS: &str = r##"fn main() {
+    geno_plugin::main(&geno_{{snake}}::{{type}}Generator)
+}
+"##;
+
+const RUST_SNAPSHOTS_RS: &str = r##"use geno_plugin::{Options, testing::check_snapshots};
+use geno_{{snake}}::{{type}}Generator;
+use std::{env, path::Path};
+
+/// Compare the output for each schema in tests/snapshots with the files under
+/// tests/snapshots/<schema>/{{name}}, or update them if GENO_BLESS is set
+#[test]
+fn snapshots() {
+    let bless = env::var_os("GENO_BLESS").is_some();
+    let report = check_snapshots(
+        &{{type}}Generator,
+        Path::new("tests/snapshots"),
+        &Options::new(),
+        bless,
+    )
+    .unwrap();
+
+    assert!(
+        bless || report.mismatches.is_empty(),
+        "snapshots differ, run with GENO_BLESS=1 to update them: {:?}",
+        report.mismatches
+    );
+}
+"##;
+
+const RUST_README_MD: &str = r##"# geno-{{name}}
+
+A [Geno](https://github.com/jlyonsmith/geno) generator plugin for the `{{name}}` format.
+
+```bash
+# Bless the snapshots of the example schema, then check them
+GENO_BLESS=1 cargo test
+cargo test
+
+# Generate code with the plugin
+cargo install --path .
+geno api.geno -f {{name}}
+```
+"##;
+
+const PYTHON_MODULE_PY: &str = r##""""Geno generator plugin for the {{name}} format.
+
+geno pipes the schema's AST to the plugin on stdin as MessagePack and passes generator options
+as key=value arguments.  read_ast decodes the AST into the same dicts and lists as the JSON AST
+a geno bundle holds in schema.json.  Replace the body of generate with the code for your target.
+"""
+
+import struct
+import sys
+
+
+def generate(schema, options):
+    """Return the generated code for a schema"""
+    lines = ["# Generated by geno-{{name}}"]
+
+    for decl in schema["declarations"]:
+        ((kind, body),) = decl.items()
+        if kind == "Struct":
+            lines += ["", f"struct {body['ident']}"]
+            for name, field_type in body["fields"]:
+                lines.append(f"  {name}: {type_str(field_type)}")
+        elif kind == "Enum":
+            lines += ["", f"enum {body['ident']}"]
+            for name, value in body["variants"]:
+                ((_, number),) = value.items()
+                lines.append(f"  {name} = {number}")
+
+    return "\n".join(lines) + "\n"
+
+
+def type_str(field_type):
+    """The schema spelling of a field type, e.g. [string; 10]?"""
+    ((kind, args),) = field_type.items()
+    if kind == "Array":
+        inner, length, nullable = args
+        base = type_str(inner) if length is None else f"{type_str(inner)}; {length}"
+        base = f"[{base}]"
+    elif kind == "Map":
+        key, value, nullable = args
+        ((key_kind, key_type),) = key.items()
+        key = builtin_str(key_type) if key_kind == "Builtin" else key_type
+        base = f"{{{key}: {type_str(value)}}}"
+    elif kind == "Builtin":
+        builtin, nullable = args
+        base = builtin_str(builtin)
+    else:
+        base, nullable = args
+    return f"{base}?" if nullable else base
+
+
+def builtin_str(builtin):
+    """The schema spelling of a builtin type"""
+    if isinstance(builtin, dict):
+        ((_, size),) = builtin.items()
+        return size.lower()
+    return {"String": "string", "LocalizedString": "lstring", "Bool": "bool"}[builtin]
+
+
+def read_ast(data):
+    """Decode the MessagePack AST from geno"""
+    value, _ = _unpack(data, 0)
+    return value
+
+
+# Formats with a fixed size header, the struct format of the header and what follows it
+_SIZED = {
+    0xC4: (">B", "bin"), 0xC5: (">H", "bin"), 0xC6: (">I", "bin"),
+    0xCA: (">f", "number"), 0xCB: (">d", "number"),
+    0xCC: (">B", "number"), 0xCD: (">H", "number"), 0xCE: (">I", "number"), 0xCF: (">Q", "number"),
+    0xD0: (">b", "number"), 0xD1: (">h", "number"), 0xD2: (">i", "number"), 0xD3: (">q", "number"),
+    0xD9: (">B", "str"), 0xDA: (">H", "str"), 0xDB: (">I", "str"),
+    0xDC: (">H", "array"), 0xDD: (">I", "array"),
+    0xDE: (">H", "map"), 0xDF: (">I", "map"),
+}
+
+
+def _unpack(data, i):
+    byte = data[i]
+    i += 1
+    if byte <= 0x7F:
+        return byte, i
+    if byte >= 0xE0:
+        return byte - 0x100, i
+    if byte & 0xF0 == 0x80:
+        return _unpack_map(data, i, byte & 0x0F)
+    if byte & 0xF0 == 0x90:
+        return _unpack_array(data, i, byte & 0x0F)
+    if byte & 0xE0 == 0xA0:
+        return data[i : i + (byte & 0x1F)].decode(), i + (byte & 0x1F)
+    if byte == 0xC0:
+        return None, i
+    if byte in (0xC2, 0xC3):
+        return byte == 0xC3, i
+    if byte not in _SIZED:
+        raise ValueError(f"unsupported MessagePack format 0x{byte:02x}")
+
+    header, kind = _SIZED[byte]
+    (n,) = struct.unpack_from(header, data, i)
+    i += struct.calcsize(header)
+    if kind == "number":
+        return n, i
+    if kind == "str":
+        return data[i : i + n].decode(), i + n
+    if kind == "bin":
+        return data[i : i + n], i + n
+    if kind == "array":
+        return _unpack_array(data, i, n)
+    return _unpack_map(data, i, n)
+
+
+def _unpack_array(data, i, n):
+    items = []
+    for _ in range(n):
+        item, i = _unpack(data, i)
+        items.append(item)
+    return items, i
+
+
+def _unpack_map(data, i, n):
+    entries = {}
+    for _ in range(n):
+        key, i = _unpack(data, i)
+        entries[key], i = _unpack(data, i)
+    return entries, i
+
+
+def main():
+    schema = read_ast(sys.stdin.buffer.read())
+    options = dict(arg.split("=", 1) for arg in sys.argv[1:])
+    try:
+        sys.stdout.write(generate(schema, options))
+    except Exception as err:
+        print(f"error: {err}", file=sys.stderr)
+        sys.exit(1)
+
+
+if __name__ == "__main__":
+    main()
+"##;
+
+const PYTHON_LAUNCHER: &str = r##"#!/usr/bin/env python3
+from geno_{{snake}} import main
+
+main()
+"##;
+
+const PYTHON_TEST_PY: &str = r##""""Snapshot tests of the {{name}} generator.
+
+Runs geno on each schema in tests/snapshots with this directory on the plugin search path and
+compares the output with tests/snapshots/<schema>/{{name}}/geno.txt, or updates it if GENO_BLESS
+is set.  Run with: python3 -m unittest
+"""
+
+import os
+import subprocess
+import unittest
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent
+SNAPSHOTS = ROOT / "tests" / "snapshots"
+
+
+class SnapshotTest(unittest.TestCase):
+    def test_snapshots(self):
+        env = dict(os.environ, GENO_PLUGIN_PATH=str(ROOT))
+        for schema in sorted(SNAPSHOTS.glob("*.geno")):
+            with self.subTest(schema=schema.name):
+                output = subprocess.run(
+                    ["geno", str(schema), "-f", "{{name}}"],
+                    env=env,
+                    capture_output=True,
+                    text=True,
+                    check=True,
+                ).stdout
+                snapshot = SNAPSHOTS / schema.stem / "{{name}}" / "geno.txt"
+                if os.environ.get("GENO_BLESS"):
+                    snapshot.parent.mkdir(parents=True, exist_ok=True)
+                    snapshot.write_text(output)
+                self.assertEqual(output, snapshot.read_text())
+
+
+if __name__ == "__main__":
+    unittest.main()
+"##;
+
+const PYTHON_README_MD: &str = r##"# geno-{{name}}
+
+A [Geno](https://github.com/jlyonsmith/geno) generator plugin for the `{{name}}` format, in
+Python 3 with no dependencies.
+
+```bash
+# Bless the snapshots of the example schema, then check them
+GENO_BLESS=1 python3 -m unittest
+python3 -m unittest
+
+# Generate code with the plugin
+GENO_PLUGIN_PATH=$PWD geno api.geno -f {{name}}
+```
+"##;
+
+/// Create a generator plugin project for the format `name` in `dir`, returning the paths written
+pub fn new_generator(name: &str, lang: GeneratorLang, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !name.starts_with(|c: char| c.is_ascii_lowercase())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        bail!(
+            "'{name}' isn't a valid format name, use lowercase letters, digits and hyphens, starting with a letter"
+        );
+    }
+    if dir.exists() {
+        bail!("'{}' already exists", dir.to_string_lossy());
+    }
+
+    let snake = to_snake_case(name);
+    let fill = |template: &str| {
+        template
+            .replace("{{name}}", name)
+            .replace("{{snake}}", &snake)
+            .replace("{{type}}", &to_pascal_case(name))
+            .replace("{{version}}", env!("CARGO_PKG_VERSION"))
+    };
+    let example = (
+        dir.join("tests/snapshots/example.geno"),
+        EXAMPLE_GENO.to_string(),
+    );
+    let files = match lang {
+        GeneratorLang::Rust => vec![
+            (dir.join("Cargo.toml"), fill(RUST_CARGO_TOML)),
+            (dir.join("README.md"), fill(RUST_README_MD)),
+            (dir.join(".gitignore"), "/target\n".to_string()),
+            (dir.join("src/lib.rs"), fill(RUST_LIB_RS)),
+            (dir.join("src/main.rs"), fill(RUST_MAIN_RS)),
+            (dir.join("tests/snapshots.rs"), fill(RUST_SNAPSHOTS_RS)),
+            example,
+        ],
+        GeneratorLang::Python => vec![
+            (dir.join("README.md"), fill(PYTHON_README_MD)),
+            (dir.join(".gitignore"), "__pycache__/\n".to_string()),
+            (dir.join(format!("geno_{snake}.py")), fill(PYTHON_MODULE_PY)),
+            (dir.join(format!("geno-{name}")), fill(PYTHON_LAUNCHER)),
+            (dir.join("test_snapshots.py"), fill(PYTHON_TEST_PY)),
+            example,
+        ],
+    };
+    let mut written = Vec::new();
+
+    for (path, content) in files {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)
+            .context(format!("Could not write '{}'", path.to_string_lossy()))?;
+        written.push(path);
+    }
+
+    // The plugin search only finds executable files
+    #[cfg(unix)]
+    if lang == GeneratorLang::Python {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(
+            dir.join(format!("geno-{name}")),
+            fs::Permissions::from_mode(0o755),
+        )?;
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,363 @@
+use crate::{
+    exit::{Exit, GeneratorFailed},
+    plugin,
+};
+use anyhow::{Context, bail};
+use geno::{
+    GenoAstBuilder, ast,
+    codegen::{GeneratedFile, Generator, Options, Registry, template::TemplateGenerator},
+    compat,
+    imports::MODULES_DIR,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// A request, one JSON object per line
+#[derive(Deserialize)]
+struct Request {
+    /// Echoed in the response, so that clients can match them up
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// A schema given by its source, or by the path of a file to read it from
+#[derive(Deserialize)]
+struct SchemaParams {
+    path: Option<PathBuf>,
+    source: Option<String>,
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(Deserialize)]
+struct GenerateParams {
+    #[serde(flatten)]
+    schema: SchemaParams,
+    format: String,
+    #[serde(default)]
+    options: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct DiffParams {
+    old: SchemaParams,
+    new: SchemaParams,
+}
+
+#[derive(Default, Deserialize)]
+struct CheckParams {
+    /// Schema files to check, or every one under the roots if not given
+    paths: Option<Vec<PathBuf>>,
+    #[serde(default)]
+    strict: bool,
+}
+
+/// A schema parsed from a file, with the modification times of the file and of every module it
+/// imports when it was parsed
+struct Cached {
+    files: Vec<(PathBuf, SystemTime)>,
+    schema: Arc<ast::Schema>,
+}
+
+/// Answers requests, keeping the schemas it reads from files so that it only parses them again
+/// once they change
+pub struct Server {
+    registry: Registry,
+    plugin_timeout: Duration,
+    /// Directories holding the schemas that `check` covers
+    roots: Vec<PathBuf>,
+    cache: Mutex<HashMap<(PathBuf, bool), Cached>>,
+}
+
+impl Server {
+    /// A server whose `check` requests cover the schemas under `roots`
+    pub fn new(roots: Vec<PathBuf>, plugin_timeout: Duration) -> Self {
+        Self {
+            registry: Registry::with_builtins(),
+            plugin_timeout,
+            roots,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Answer the requests read from `input` until it ends, writing each response to `output` as
+    /// soon as it is ready
+    pub fn serve(&self, input: impl BufRead, mut output: impl Write) -> anyhow::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => {
+                    let _span = tracing::info_span!("request", method = request.method).entered();
+
+                    match self.handle(&request) {
+                        Ok(result) => json!({ "id": request.id, "result": result }),
+                        Err(err) => error_response(request.id, &err),
+                    }
+                }
+                Err(err) => error_response(Value::Null, &err.into()),
+            };
+
+            serde_json::to_writer(&mut output, &response)?;
+            output.write_all(b"\n")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// The `.geno` files under the roots, sorted by path
+    pub fn schema_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+
+        for root in &self.roots {
+            find_schemas(root, &mut files);
+        }
+        files.sort();
+        files
+    }
+
+    /// Parse a schema file, unless it was parsed before and neither it nor any module it imports
+    /// has changed since
+    pub fn load(&self, path: &Path, strict: bool) -> anyhow::Result<Arc<ast::Schema>> {
+        let modified =
+            modified_time(path).context(format!("Could not read '{}'", path.to_string_lossy()))?;
+        let key = (path.to_path_buf(), strict);
+
+        if let Some(cached) = self.cache.lock().unwrap().get(&key)
+            && cached
+                .files
+                .iter()
+                .all(|(path, time)| modified_time(path).is_ok_and(|modified| modified == *time))
+        {
+            return Ok(cached.schema.clone());
+        }
+
+        let source = fs::read_to_string(path)
+            .context(format!("Could not read '{}'", path.to_string_lossy()))?;
+        let (schema, modules) = GenoAstBuilder::new(path.to_path_buf())
+            .strict(strict)
+            .build_from_str_with_modules(&source)?;
+        let schema = Arc::new(schema);
+        let mut files = vec![(path.to_path_buf(), modified)];
+
+        for module in modules {
+            let modified = modified_time(&module)?;
+
+            files.push((module, modified));
+        }
+
+        tracing::debug!(path = %path.display(), modules = files.len() - 1, "parsed");
+        self.cache.lock().unwrap().insert(
+            key,
+            Cached {
+                files,
+                schema: schema.clone(),
+            },
+        );
+
+        Ok(schema)
+    }
+
+    /// Forget the schemas of files that no longer exist
+    pub fn forget_missing(&self) {
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|(path, _), _| path.exists());
+    }
+
+    fn handle(&self, request: &Request) -> anyhow::Result<Value> {
+        let params = request.params.clone();
+
+        match request.method.as_str() {
+            "parse" => {
+                let schema = self.build(&serde_json::from_value(params)?)?;
+
+                Ok(serde_json::to_value(&*schema)?)
+            }
+            "validate" => {
+                let schema = self.build(&serde_json::from_value(params)?)?;
+
+                Ok(json!({
+                    "fingerprint": schema.fingerprint(),
+                    "warnings": warnings(&schema),
+                }))
+            }
+            "check" => {
+                let params: CheckParams = if params.is_null() {
+                    CheckParams::default()
+                } else {
+                    serde_json::from_value(params)?
+                };
+                let schemas: Vec<Value> = params
+                    .paths
+                    .unwrap_or_else(|| self.schema_files())
+                    .iter()
+                    .map(|path| match self.load(path, params.strict) {
+                        Ok(schema) => json!({ "path": path, "warnings": warnings(&schema) }),
+                        Err(err) => json!({ "path": path, "error": error_value(&err) }),
+                    })
+                    .collect();
+
+                Ok(json!({ "schemas": schemas }))
+            }
+            "generate" => {
+                let params: GenerateParams = serde_json::from_value(params)?;
+                let schema = self.build(&params.schema)?;
+                let options = Options::parse(
+                    params
+                        .options
+                        .iter()
+                        .map(|(key, value)| format!("{key}={value}")),
+                )?;
+                let files = self.generate(&params.format, &schema, &options)?;
+
+                Ok(json!({ "files": files.iter().map(file_value).collect::<Vec<_>>() }))
+            }
+            "diff" => {
+                let params: DiffParams = serde_json::from_value(params)?;
+                let old = self.build(&params.old)?;
+                let new = self.build(&params.new)?;
+                let changes = compat::changes(&old, &new)?;
+
+                Ok(json!({
+                    "changes": changes
+                        .iter()
+                        .map(|change| json!({
+                            "class": change.class.to_string(),
+                            "description": change.description,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "class": compat::class(&changes).to_string(),
+                    "version": compat::next_version(&old, &new, &changes),
+                }))
+            }
+            method => bail!("Unknown method '{method}'"),
+        }
+    }
+
+    fn build(&self, params: &SchemaParams) -> anyhow::Result<Arc<ast::Schema>> {
+        match (&params.path, &params.source) {
+            (path, Some(source)) => Ok(Arc::new(
+                GenoAstBuilder::new(path.clone().unwrap_or_else(|| PathBuf::from("<source>")))
+                    .strict(params.strict)
+                    .build_from_str(source)?,
+            )),
+            (Some(path), None) => self.load(path, params.strict),
+            (None, None) => bail!("A schema needs a 'path' or a 'source'"),
+        }
+    }
+
+    /// Generate the files for one target, with a built-in generator, templates or a plugin
+    fn generate(
+        &self,
+        format: &str,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> anyhow::Result<Vec<GeneratedFile>> {
+        let template_generator;
+        let generator: Option<&dyn Generator> = match format.strip_prefix("template:") {
+            Some(dir) => {
+                template_generator = TemplateGenerator::new(dir);
+                Some(&template_generator)
+            }
+            None => self.registry.get(format),
+        };
+
+        if let Some(generator) = generator {
+            options.check_keys([generator])?;
+        }
+
+        match generator {
+            Some(generator) => schema
+                .check_capabilities(&generator.capabilities())
+                .and_then(|()| generator.generate(schema, options))
+                .map_err(Into::into),
+            None => plugin::run(format, schema, options, self.plugin_timeout)
+                .map(|output| vec![GeneratedFile::new("geno.out", output)]),
+        }
+        .context(GeneratorFailed(format.to_string()))
+    }
+}
+
+/// Add the `.geno` files in `dir` and the directories below it to `files`.  Hidden directories
+/// such as `.git`, `geno_modules`, whose packages are read as the schemas that import them, and
+/// Cargo's `target` are skipped, and symbolic links aren't followed.
+fn find_schemas(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if file_type.is_dir() {
+            if !name.starts_with('.') && name != MODULES_DIR && name != "target" {
+                find_schemas(&path, files);
+            }
+        } else if file_type.is_file()
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "geno")
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// When a file was last modified
+fn modified_time(path: &Path) -> std::io::Result<SystemTime> {
+    fs::metadata(path)?.modified()
+}
+
+fn warnings(schema: &ast::Schema) -> Vec<Value> {
+    schema
+        .warnings()
+        .iter()
+        .map(|warning| json!({ "lint": warning.lint(), "message": warning.to_string() }))
+        .collect()
+}
+
+/// An error and its causes as one message, with the exit code its failure would give
+fn error_value(err: &anyhow::Error) -> Value {
+    let message = err
+        .chain()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ");
+
+    json!({ "message": message, "code": Exit::of(err) as i32 })
+}
+
+fn error_response(id: Value, err: &anyhow::Error) -> Value {
+    json!({ "id": id, "error": error_value(err) })
+}
+
+/// A generated file, with its contents as a string, or as an array of bytes if they aren't UTF-8
+fn file_value(file: &GeneratedFile) -> Value {
+    match file.as_str() {
+        Some(contents) => json!({ "path": file.path, "contents": contents }),
+        None => json!({ "path": file.path, "bytes": file.contents }),
+    }
+}
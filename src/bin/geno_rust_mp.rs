@@ -0,0 +1,38 @@
+use anyhow::Context;
+use geno::{
+    ast,
+    codegen::{Generator, Options, rust_mp::RustMpGenerator},
+};
+use std::io::{self, Read, Write};
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {err:#}");
+        std::process::exit(1);
+    }
+
+    std::process::exit(0);
+}
+
+fn run() -> anyhow::Result<()> {
+    let stdin = io::stdin();
+    let mut handle = stdin.lock();
+    let mut buffer = Vec::new();
+
+    // Read all bytes from stdin into the buffer
+    handle
+        .read_to_end(&mut buffer)
+        .context("Unable to read AST from stdin")?;
+
+    let schema: ast::Schema =
+        rmp_serde::from_slice(&buffer).context("Unable to deserialize AST from stdin")?;
+    let options = Options::parse(std::env::args().skip(1))?;
+
+    options.check_keys([&RustMpGenerator as &dyn Generator])?;
+
+    for file in RustMpGenerator.generate(&schema, &options)? {
+        io::stdout().write_all(&file.contents)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Size budgets for messages.  A struct marked `@budget(max_packed_bytes = N)`, usually a root
+//! sent over a constrained link such as BLE, must never pack to more than `N` bytes.  Checking
+//! works out the most bytes the MessagePack generators can write for each such struct, from its
+//! field types, fixed array lengths and `@len(max = ...)` constraints, as
+//! [packed_size_upper_bound] does.
+//!
+//! The `wire` and `int64` options change the packed size, so they are read as the generators
+//! read them.  Fields only some targets have are counted, as the worst case is the target with
+//! all of them.
+use crate::{
+    GenoError, ast,
+    codegen::{
+        Options,
+        util::{Int64, WireFormat, packed_size_upper_bound, select_schema},
+    },
+};
+
+/// The most bytes a struct with a `@budget` can pack to, and what its budget allows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    /// Name of the struct
+    pub ident: String,
+    /// The bytes allowed by `@budget(max_packed_bytes = N)`
+    pub max_packed_bytes: u64,
+    /// The most bytes the struct can pack to, or `None` if a string, list or map in it has no
+    /// maximum length or it holds itself
+    pub upper_bound: Option<usize>,
+}
+
+impl Budget {
+    /// Whether no value of the struct can pack to more bytes than its budget
+    pub fn is_met(&self) -> bool {
+        self.upper_bound
+            .is_some_and(|bound| bound as u64 <= self.max_packed_bytes)
+    }
+}
+
+/// Work out the packed size of every struct with a `@budget`, in declaration order
+pub fn check(schema: &ast::Schema, options: &Options) -> Result<Vec<Budget>, GenoError> {
+    let schema = &*select_schema(schema, options)?;
+    let wire = WireFormat::from_options(schema, options)?;
+    let int64 = Int64::from_options(options)?;
+
+    Ok(schema
+        .declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            ast::Declaration::Struct {
+                ident, annotations, ..
+            } => annotations.budget().map(|max_packed_bytes| Budget {
+                ident: ident.clone(),
+                max_packed_bytes,
+                upper_bound: packed_size_upper_bound(schema, ident, wire, int64),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GenoAstBuilder;
+
+    fn budgets(schema: &str, options: &[&str]) -> Vec<Budget> {
+        let schema = GenoAstBuilder::new("test.geno".into())
+            .build_from_str(schema)
+            .unwrap();
+
+        check(&schema, &Options::parse(options).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn packed_sizes() {
+        let schema = r#"meta { format = 1 }
+            @root @budget(max_packed_bytes = 16) struct reading { sensor: u8, value: f32, at: u32 }
+            @root @budget(max_packed_bytes = 16) struct note { @len(max = 8) text: string }
+            @root @budget(max_packed_bytes = 1000) struct log { lines: [string] }
+            struct other { a: u8 }"#;
+        let found = budgets(schema, &[]);
+
+        // A positional struct is its fields: a uint8, a float 64 and a uint32
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].ident, "reading");
+        assert_eq!(found[0].upper_bound, Some(2 + 9 + 5));
+        assert!(found[0].is_met());
+        // Up to 32 bytes of UTF-8, behind a str8 header
+        assert_eq!(found[1].upper_bound, Some(34));
+        assert!(!found[1].is_met());
+        assert_eq!(found[2].upper_bound, None);
+        assert!(!found[2].is_met());
+
+        // Keyed structs also pack a map header and the field names
+        let keyed = budgets(schema, &["wire=keyed"]);
+
+        assert!(keyed[0].upper_bound.unwrap() > found[0].upper_bound.unwrap());
+    }
+}
@@ -0,0 +1,150 @@
+//! Offline bundles of a schema and the code generated from it, for air-gapped deployments and
+//! release artifacts.  A bundle is a tar archive holding:
+//!
+//! - `schema.geno`, the schema source, normalized to NFC
+//! - `schema.json`, the schema's AST
+//! - `fingerprint`, the schema's [fingerprint](crate::ast::Schema::fingerprint)
+//! - `<target>/...`, the files each target generated
+//! - `geno-manifest.json`, a [Manifest] of the generated files
+//!
+//! Entries are sorted by path and have fixed times, owners and permissions, so the same schema
+//! and generators always give the same bytes.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles},
+    manifest::{MANIFEST_FILE, Manifest},
+};
+use std::{io::Write, path::Path};
+use unicode_normalization::UnicodeNormalization;
+
+/// The files of a bundle, with paths relative to its root
+pub fn files(
+    schema_path: &str,
+    source: &str,
+    schema: &ast::Schema,
+    targets: &[(String, GeneratedFiles)],
+) -> Vec<GeneratedFile> {
+    let fingerprint = schema.fingerprint();
+    let mut manifest = Manifest::default();
+    let mut files = vec![
+        GeneratedFile::new("schema.geno", source.nfc().collect::<String>()),
+        GeneratedFile::new(
+            "schema.json",
+            serde_json::to_string_pretty(schema).unwrap() + "\n",
+        ),
+        GeneratedFile::new("fingerprint", format!("{fingerprint}\n")),
+    ];
+
+    for (target, generated) in targets {
+        let generated: Vec<GeneratedFile> = generated
+            .iter()
+            .map(|file| GeneratedFile {
+                path: Path::new(target).join(&file.path),
+                contents: file.contents.clone(),
+            })
+            .collect();
+
+        manifest.record(target, schema_path, &fingerprint, &generated);
+        files.extend(generated);
+    }
+
+    files.push(GeneratedFile::new(
+        MANIFEST_FILE,
+        serde_json::to_string_pretty(&manifest).unwrap() + "\n",
+    ));
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+/// Write a bundle as a tar archive to `out`
+pub fn write(
+    out: impl Write,
+    schema_path: &str,
+    source: &str,
+    schema: &ast::Schema,
+    targets: &[(String, GeneratedFiles)],
+) -> Result<(), GenoError> {
+    let mut archive = tar::Builder::new(out);
+
+    for file in files(schema_path, source, schema, targets) {
+        let mut header = tar::Header::new_gnu();
+
+        header.set_size(file.contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        archive.append_data(&mut header, &file.path, file.contents.as_slice())?;
+    }
+
+    archive.into_inner()?.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn bundle() {
+        let source = "meta { format = 1 }\nstruct point { x: i32, y: i32 }\n";
+        let schema = crate::GenoAstBuilder::new("point.geno".into())
+            .build_from_str(source)
+            .unwrap();
+        let targets = vec![
+            (
+                "rust-serde".to_string(),
+                vec![GeneratedFile::new("geno.rs", "struct Point;\n")],
+            ),
+            (
+                "dart-mp".to_string(),
+                vec![GeneratedFile::new("lib/geno.dart", "class Point {}\n")],
+            ),
+        ];
+        let mut bytes = Vec::new();
+
+        write(&mut bytes, "point.geno", source, &schema, &targets).unwrap();
+
+        let mut again = Vec::new();
+
+        write(&mut again, "point.geno", source, &schema, &targets).unwrap();
+        assert_eq!(bytes, again);
+
+        let mut archive = tar::Archive::new(bytes.as_slice());
+        let mut entries = Vec::new();
+
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut contents = String::new();
+
+            entry.read_to_string(&mut contents).unwrap();
+            entries.push((
+                entry.path().unwrap().to_string_lossy().into_owned(),
+                contents,
+            ));
+        }
+
+        let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            [
+                "dart-mp/lib/geno.dart",
+                "fingerprint",
+                "geno-manifest.json",
+                "rust-serde/geno.rs",
+                "schema.geno",
+                "schema.json",
+            ]
+        );
+        assert_eq!(entries[1].1, format!("{}\n", schema.fingerprint()));
+        assert_eq!(entries[4].1, source);
+
+        let manifest: Manifest = serde_json::from_str(&entries[2].1).unwrap();
+
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.files[0].path, "dart-mp/lib/geno.dart");
+        assert_eq!(manifest.files[1].target, "rust-serde");
+    }
+}
@@ -0,0 +1,609 @@
+//! Geno archive generator.  Writes readers and writers for a file of records that can be read in
+//! any order, in `archive.rs` for the `rust-mp` types and `archive.dart` for the `dart-mp` types.
+//!
+//! An archive starts with a 16 byte header, the magic `GENA`, the format version and three zero
+//! bytes, and the first 8 bytes of the schema fingerprint.  The records follow, each a 4 byte
+//! big-endian length and the bytes of `to_bytes`.  Then comes the index, the big-endian `u64`
+//! offset of each record, and a 16 byte footer with the offset of the index and the number of
+//! records.  Readers check the fingerprint, so an archive written with another schema is an error
+//! rather than garbage.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, dart_mp, rust_serde, util::*},
+};
+use std::fmt::Write;
+
+/// Generator for the `archive` format
+pub struct ArchiveGenerator;
+
+/// The records are encoded by the `rust-mp` and `dart-mp` types
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+/// The Rust error, record trait, writer and reader, written once for all types
+const RUST_ARCHIVE: &str = r#"
+/// An error reading an archive
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// An error from the file
+    Io(std::io::Error),
+    /// The file doesn't start with the archive magic
+    NotArchive,
+    /// The archive has a format version this code doesn't read
+    Version(u8),
+    /// The archive was written with the schema that has this fingerprint
+    Fingerprint([u8; 8]),
+    /// The index or a record length points outside the records
+    Corrupt,
+    /// There is no record at the index
+    OutOfRange(usize),
+    /// A record doesn't decode
+    Decode(DecodeError),
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArchiveError::Io(err) => write!(f, "{err}"),
+            ArchiveError::NotArchive => write!(f, "the file is not an archive"),
+            ArchiveError::Version(version) => write!(f, "the archive has format version {version}"),
+            ArchiveError::Fingerprint(fingerprint) => write!(
+                f,
+                "the archive was written with schema {}, but this code is for schema {}",
+                archive_hex(fingerprint),
+                archive_hex(&ARCHIVE_FINGERPRINT)
+            ),
+            ArchiveError::Corrupt => write!(f, "the archive is corrupt"),
+            ArchiveError::OutOfRange(index) => write!(f, "the archive has no record {index}"),
+            ArchiveError::Decode(err) => write!(f, "{}", err.0),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(err: std::io::Error) -> Self {
+        ArchiveError::Io(err)
+    }
+}
+
+fn archive_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+const ARCHIVE_MAGIC: [u8; 4] = *b"GENA";
+const ARCHIVE_VERSION: u8 = 1;
+
+/// A type that can be stored in an archive
+pub trait ArchiveRecord: Sized {
+    /// Encode a record
+    fn encode_record(&self) -> Vec<u8>;
+    /// Decode a record
+    fn decode_record(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Writes records to an archive, starting at the beginning of `W`, and then their index when it
+/// is finished
+pub struct ArchiveWriter<W: std::io::Write, T> {
+    writer: W,
+    offsets: Vec<u64>,
+    position: u64,
+    records: std::marker::PhantomData<fn(&T)>,
+}
+
+impl<W: std::io::Write, T: ArchiveRecord> ArchiveWriter<W, T> {
+    /// Start an archive, writing its header
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(&ARCHIVE_MAGIC)?;
+        writer.write_all(&[ARCHIVE_VERSION, 0, 0, 0])?;
+        writer.write_all(&ARCHIVE_FINGERPRINT)?;
+        Ok(Self {
+            writer,
+            offsets: Vec::new(),
+            position: 16,
+            records: std::marker::PhantomData,
+        })
+    }
+
+    /// Append a record
+    pub fn push(&mut self, record: &T) -> std::io::Result<()> {
+        let bytes = record.encode_record();
+        let length = u32::try_from(bytes.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "the record is too long")
+        })?;
+
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.offsets.push(self.position);
+        self.position += 4 + u64::from(length);
+        Ok(())
+    }
+
+    /// Write the index and the footer, and return the writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        for offset in &self.offsets {
+            self.writer.write_all(&offset.to_be_bytes())?;
+        }
+        self.writer.write_all(&self.position.to_be_bytes())?;
+        self.writer.write_all(&(self.offsets.len() as u64).to_be_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the records of an archive in any order, using its index
+pub struct ArchiveReader<R: std::io::Read + std::io::Seek, T> {
+    reader: R,
+    offsets: Vec<u64>,
+    index: u64,
+    records: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<R: std::io::Read + std::io::Seek, T: ArchiveRecord> ArchiveReader<R, T> {
+    /// Open an archive, checking its header and reading its index
+    pub fn open(mut reader: R) -> Result<Self, ArchiveError> {
+        use std::io::SeekFrom;
+
+        let end = reader.seek(SeekFrom::End(0))?;
+        let mut header = [0u8; 16];
+        let mut footer = [0u8; 16];
+
+        if end < 32 {
+            return Err(ArchiveError::NotArchive);
+        }
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+        if header[..4] != ARCHIVE_MAGIC {
+            return Err(ArchiveError::NotArchive);
+        }
+        if header[4] != ARCHIVE_VERSION {
+            return Err(ArchiveError::Version(header[4]));
+        }
+        if header[8..] != ARCHIVE_FINGERPRINT {
+            return Err(ArchiveError::Fingerprint(header[8..].try_into().unwrap()));
+        }
+
+        reader.seek(SeekFrom::End(-16))?;
+        reader.read_exact(&mut footer)?;
+
+        let index = u64::from_be_bytes(footer[..8].try_into().unwrap());
+        let count = u64::from_be_bytes(footer[8..].try_into().unwrap());
+
+        let index_end = count
+            .checked_mul(8)
+            .and_then(|length| length.checked_add(index))
+            .and_then(|length| length.checked_add(16));
+
+        if index < 16 || index_end != Some(end) {
+            return Err(ArchiveError::Corrupt);
+        }
+
+        let mut bytes = vec![0u8; (count * 8) as usize];
+
+        reader.seek(SeekFrom::Start(index))?;
+        reader.read_exact(&mut bytes)?;
+        Ok(Self {
+            reader,
+            offsets: bytes
+                .chunks_exact(8)
+                .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+                .collect(),
+            index,
+            records: std::marker::PhantomData,
+        })
+    }
+
+    /// The number of records
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Whether the archive has no records
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Read the record at `index`
+    pub fn get(&mut self, index: usize) -> Result<T, ArchiveError> {
+        let offset = *self.offsets.get(index).ok_or(ArchiveError::OutOfRange(index))?;
+        let mut length = [0u8; 4];
+
+        self.reader.seek(std::io::SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut length)?;
+
+        let length = u32::from_be_bytes(length);
+
+        if offset
+            .checked_add(4 + u64::from(length))
+            .is_none_or(|record_end| record_end > self.index)
+        {
+            return Err(ArchiveError::Corrupt);
+        }
+
+        let mut bytes = vec![0u8; length as usize];
+
+        self.reader.read_exact(&mut bytes)?;
+        T::decode_record(&bytes).map_err(ArchiveError::Decode)
+    }
+
+    /// Return the reader of the archive
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+"#;
+
+/// The Dart writer and reader, written once for all types
+const DART_ARCHIVE: &str = r#"
+const _archiveMagic = [0x47, 0x45, 0x4e, 0x41];
+const _archiveVersion = 1;
+
+String _archiveHex(List<int> bytes) =>
+    bytes.map((byte) => byte.toRadixString(16).padLeft(2, '0')).join();
+
+/// Writes records to an archive, replacing the contents of the file, and then their index when
+/// it is closed
+class ArchiveWriter<T> {
+  final RandomAccessFile _file;
+  final Uint8List Function(T) _encode;
+  final _offsets = <int>[];
+  var _position = 16;
+
+  /// Start an archive, writing its header
+  ArchiveWriter(this._file, this._encode) {
+    _file
+      ..setPositionSync(0)
+      ..truncateSync(0)
+      ..writeFromSync([..._archiveMagic, _archiveVersion, 0, 0, 0, ...archiveFingerprint]);
+  }
+
+  /// Append a record
+  void add(T record) {
+    final bytes = _encode(record);
+
+    _file
+      ..writeFromSync((ByteData(4)..setUint32(0, bytes.length)).buffer.asUint8List())
+      ..writeFromSync(bytes);
+    _offsets.add(_position);
+    _position += 4 + bytes.length;
+  }
+
+  /// Write the index and the footer
+  void close() {
+    final index = ByteData(_offsets.length * 8 + 16);
+
+    for (var i = 0; i < _offsets.length; i++) {
+      index.setUint64(i * 8, _offsets[i]);
+    }
+    index
+      ..setUint64(_offsets.length * 8, _position)
+      ..setUint64(_offsets.length * 8 + 8, _offsets.length);
+    _file
+      ..writeFromSync(index.buffer.asUint8List())
+      ..flushSync();
+  }
+}
+
+/// Reads the records of an archive in any order, using its index
+class ArchiveReader<T> {
+  final RandomAccessFile _file;
+  final T Function(Uint8List) _decode;
+  final List<int> _offsets;
+  final int _index;
+
+  ArchiveReader._(this._file, this._decode, this._offsets, this._index);
+
+  /// Open an archive, checking its header and reading its index
+  factory ArchiveReader.open(RandomAccessFile file, T Function(Uint8List) decode) {
+    final end = file.lengthSync();
+
+    if (end < 32) {
+      throw FormatException('The file is not an archive');
+    }
+    file.setPositionSync(0);
+
+    final header = file.readSync(16);
+
+    for (var i = 0; i < 4; i++) {
+      if (header[i] != _archiveMagic[i]) {
+        throw FormatException('The file is not an archive');
+      }
+    }
+    if (header[4] != _archiveVersion) {
+      throw FormatException('The archive has format version ${header[4]}');
+    }
+    for (var i = 0; i < 8; i++) {
+      if (header[8 + i] != archiveFingerprint[i]) {
+        throw FormatException(
+            'The archive was written with schema ${_archiveHex(header.sublist(8))}, but this code is for schema ${_archiveHex(archiveFingerprint)}');
+      }
+    }
+    file.setPositionSync(end - 16);
+
+    final footer = ByteData.sublistView(file.readSync(16));
+    final index = footer.getUint64(0);
+    final count = footer.getUint64(8);
+
+    if (index < 16 || count < 0 || index + count * 8 + 16 != end) {
+      throw FormatException('The archive is corrupt');
+    }
+    file.setPositionSync(index);
+
+    final offsets = ByteData.sublistView(file.readSync(count * 8));
+
+    return ArchiveReader._(
+        file, decode, List.generate(count, (i) => offsets.getUint64(i * 8)), index);
+  }
+
+  /// The number of records
+  int get length => _offsets.length;
+
+  /// Read the record at [index]
+  T operator [](int index) {
+    RangeError.checkValidIndex(index, _offsets, 'index');
+    _file.setPositionSync(_offsets[index]);
+
+    final length = ByteData.sublistView(_file.readSync(4)).getUint32(0);
+
+    if (_offsets[index] + 4 + length > _index) {
+      throw FormatException('The archive is corrupt');
+    }
+    return _decode(_file.readSync(length));
+  }
+}
+"#;
+
+impl Generator for ArchiveGenerator {
+    fn name(&self) -> &str {
+        "archive"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "language",
+            "roots_only",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let (rust, dart) = match options.get("language") {
+            None => (true, true),
+            Some("rust") => (true, false),
+            Some("dart") => (false, true),
+            Some(value) => return Err(GenoError::InvalidOption(format!("language={value}"))),
+        };
+        let naming = |defaults| {
+            Naming::from_options(
+                options,
+                defaults,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )
+        };
+        let mut files = Vec::new();
+
+        if rust {
+            let schema = &*select_target(schema, options, Some("rust"))?;
+
+            files.push(GeneratedFile::new(
+                "archive.rs",
+                rust_archive(
+                    &records(schema, options)?,
+                    short_fingerprint(schema),
+                    &naming(rust_serde::DEFAULT_NAMING)?,
+                ),
+            ));
+        }
+        if dart {
+            let schema = &*select_target(schema, options, Some("dart"))?;
+
+            files.push(GeneratedFile::new(
+                "archive.dart",
+                dart_archive(
+                    &records(schema, options)?,
+                    short_fingerprint(schema),
+                    &naming(dart_mp::DEFAULT_NAMING)?,
+                ),
+            ));
+        }
+
+        Ok(files)
+    }
+}
+
+/// The structs with public `to_bytes` and `from_bytes`, which can be archived
+fn records<'a>(schema: &'a ast::Schema, options: &Options) -> Result<Vec<&'a str>, GenoError> {
+    let roots = Roots::from_options(schema, options)?;
+
+    Ok(schema
+        .declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            ast::Declaration::Struct { ident, .. } if roots.contains(ident) => Some(ident.as_str()),
+            _ => None,
+        })
+        .collect())
+}
+
+fn fingerprint_bytes(fingerprint: [u8; 8]) -> String {
+    fingerprint
+        .iter()
+        .map(|byte| format!("0x{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn rust_archive(records: &[&str], fingerprint: [u8; 8], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    w.line(
+        "// Archives of rust-mp records, generated by geno.  Include this file in the module that",
+    );
+    w.line("// holds them.");
+    if records.is_empty() {
+        return w.into_string();
+    }
+
+    w.blank();
+    w.line("/// The first 8 bytes of the fingerprint of the schema this file was generated from");
+    w.line(format!(
+        "pub const ARCHIVE_FINGERPRINT: [u8; 8] = [{}];",
+        fingerprint_bytes(fingerprint)
+    ));
+    w.write_str(RUST_ARCHIVE).unwrap();
+    for ident in records {
+        let rust_name = naming.type_name(ident);
+
+        w.blank();
+        w.block(
+            &format!("impl ArchiveRecord for {rust_name} {{"),
+            "}",
+            |w| {
+                w.block("fn encode_record(&self) -> Vec<u8> {", "}", |w| {
+                    w.line("self.to_bytes()");
+                });
+                w.blank();
+                w.block(
+                    "fn decode_record(bytes: &[u8]) -> Result<Self, DecodeError> {",
+                    "}",
+                    |w| {
+                        w.line("Self::from_bytes(bytes)");
+                    },
+                );
+            },
+        );
+    }
+
+    w.into_string()
+}
+
+fn dart_archive(records: &[&str], fingerprint: [u8; 8], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("  ");
+
+    w.line("// Archives of dart-mp records, generated by geno");
+    if records.is_empty() {
+        return w.into_string();
+    }
+    w.line("import 'dart:io';");
+    w.line("import 'dart:typed_data';");
+    w.blank();
+    w.line("import 'geno.dart';");
+    w.blank();
+    w.line("/// The first 8 bytes of the fingerprint of the schema this file was generated from");
+    w.line(format!(
+        "const archiveFingerprint = [{}];",
+        fingerprint_bytes(fingerprint)
+    ));
+    w.write_str(DART_ARCHIVE).unwrap();
+    for ident in records {
+        let class_name = naming.type_name(ident);
+        let function_name = to_ascii_identifier(&to_pascal_case(ident));
+
+        w.blank();
+        w.line(format!(
+            "/// Start an archive of [{class_name}] records in [file]"
+        ));
+        w.line(format!(
+            "ArchiveWriter<{class_name}> create{function_name}Archive(RandomAccessFile file) =>"
+        ));
+        w.line(format!(
+            "    ArchiveWriter(file, ({class_name} record) => record.toBytes());"
+        ));
+        w.blank();
+        w.line(format!("/// Open an archive of [{class_name}] records"));
+        w.line(format!(
+            "ArchiveReader<{class_name}> open{function_name}Archive(RandomAccessFile file) =>"
+        ));
+        w.line(format!(
+            "    ArchiveReader.open(file, {class_name}.fromBytes);"
+        ));
+    }
+
+    w.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = "meta { format = 1 }\nstruct point { x: f64, y: f64 }\n@root\nstruct shape { points: [point] }\n";
+
+    fn generate(options: &[&str]) -> Vec<String> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(SCHEMA)
+            .unwrap();
+
+        ArchiveGenerator
+            .generate(&schema, &Options::parse(options).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|file| String::from_utf8(file.contents).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rust() {
+        let output = &generate(&["language=rust"])[0];
+
+        assert!(output.contains("pub const ARCHIVE_FINGERPRINT: [u8; 8] = [0x"));
+        assert!(output.contains("pub struct ArchiveWriter<W: std::io::Write, T> {"));
+        assert!(
+            output.contains("pub fn get(&mut self, index: usize) -> Result<T, ArchiveError> {")
+        );
+        assert!(output.contains(
+            "impl ArchiveRecord for Shape {\n    fn encode_record(&self) -> Vec<u8> {\n        self.to_bytes()\n    }"
+        ));
+        assert!(output.contains("impl ArchiveRecord for Point {"));
+        assert!(!generate(&["language=rust", "roots_only=true"])[0].contains("for Point"));
+    }
+
+    #[test]
+    fn dart() {
+        let files = generate(&[]);
+
+        assert_eq!(files.len(), 2);
+        assert!(files[1].contains("const archiveFingerprint = [0x"));
+        assert!(files[1].contains("class ArchiveReader<T> {"));
+        assert!(files[1].contains(
+            "ArchiveWriter<Shape> createShapeArchive(RandomAccessFile file) =>\n    ArchiveWriter(file, (Shape record) => record.toBytes());"
+        ));
+        assert!(files[1].contains(
+            "ArchiveReader<Shape> openShapeArchive(RandomAccessFile file) =>\n    ArchiveReader.open(file, Shape.fromBytes);"
+        ));
+
+        let bytes = |output: &str, name: &str| {
+            let line = output.lines().find(|line| line.contains(name)).unwrap();
+
+            line[line.rfind('[').unwrap()..line.rfind(']').unwrap()].to_string()
+        };
+
+        assert_eq!(
+            bytes(&files[0], "ARCHIVE_FINGERPRINT: [u8; 8] ="),
+            bytes(&files[1], "archiveFingerprint =")
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Geno C header generator.  Writes `geno.h`, with a C struct for each struct marked
+//! `@abi(stable)`, for which the Rust generators write `#[repr(C)]` structs, so that Rust and C or
+//! C++ code can share values in memory.  The schema checks that the fields of such a struct are
+//! FFI-safe.
+//!
+//! Enums are a `typedef` of their base type, as C enums are the size of an `int`, with a
+//! `#define` for each variant.  Types are written in the order C needs, each after the types of
+//! its fields.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, rust_serde, util::*},
+};
+use std::fmt::Write as _;
+
+/// Generator for the `c-header` format
+pub struct CHeaderGenerator;
+
+/// Only integers, floats, bools, enums and fixed length arrays of them are written
+const CAPABILITIES: ast::Capabilities = ast::Capabilities::ALL;
+
+/// Variants are prefixed with the name of their enum, as C has no namespaces
+const DEFAULT_NAMING: Naming = Naming {
+    variants: NamingConvention::ScreamingSnake,
+    ..rust_serde::DEFAULT_NAMING
+};
+
+impl Generator for CHeaderGenerator {
+    fn name(&self) -> &str {
+        "c-header"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        // The layouts have to match the `rust-serde` and `rust-mp` types
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let naming = Naming::from_options(
+            options,
+            DEFAULT_NAMING,
+            &[
+                NamingConvention::Pascal,
+                NamingConvention::Camel,
+                NamingConvention::Snake,
+                NamingConvention::ScreamingSnake,
+                NamingConvention::Preserve,
+            ],
+        )?;
+        let mut order = Vec::new();
+
+        for decl in &schema.declarations {
+            if let ast::Declaration::Struct {
+                ident, annotations, ..
+            } = decl
+                && annotations.is_abi_stable()
+            {
+                collect_types(schema, ident, &mut order);
+            }
+        }
+
+        let mut w = CodeWriter::new("    ");
+
+        w.line("/* C structs for the @abi(stable) structs, generated by geno */");
+        w.line("#ifndef GENO_H");
+        w.line("#define GENO_H");
+        w.blank();
+        w.line("#include <stdbool.h>");
+        w.line("#include <stdint.h>");
+        for decl in order {
+            w.blank();
+            match decl {
+                ast::Declaration::Struct {
+                    ident,
+                    fields,
+                    annotations,
+                    ..
+                } => generate_struct(&mut w, &naming, ident, fields, annotations),
+                ast::Declaration::Enum {
+                    ident,
+                    base_type,
+                    variants,
+                    annotations,
+                    ..
+                } => generate_enum(&mut w, &naming, ident, base_type, variants, annotations),
+                _ => {}
+            }
+        }
+        w.blank();
+        w.line("#endif /* GENO_H */");
+
+        Ok(vec![GeneratedFile::new("geno.h", w.into_string())])
+    }
+}
+
+/// Add the types the struct `ident` uses to `order`, then the struct itself.  The schema checks
+/// that `@abi(stable)` structs don't contain themselves.
+fn collect_types<'a>(schema: &'a ast::Schema, ident: &str, order: &mut Vec<&'a ast::Declaration>) {
+    let Some(decl) = schema.declarations.iter().find(|decl| match decl {
+        ast::Declaration::Struct { ident: name, .. }
+        | ast::Declaration::Enum { ident: name, .. } => name == ident,
+        _ => false,
+    }) else {
+        return;
+    };
+
+    if order.iter().any(|done| std::ptr::eq(*done, decl)) {
+        return;
+    }
+    if let ast::Declaration::Struct { fields, .. } = decl {
+        for (_, field_type) in fields {
+            let mut field_type = field_type;
+            while let ast::FieldType::Array(inner, ..) = field_type {
+                field_type = inner;
+            }
+            if let ast::FieldType::UserDefined(name, _) = field_type {
+                collect_types(schema, name, order);
+            }
+        }
+    }
+    order.push(decl);
+}
+
+/// Write doc comments
+fn write_docs(w: &mut CodeWriter, comments: &[String]) {
+    for comment in comments {
+        w.line(format!("/* {} */", ast::Comments::text(comment)));
+    }
+}
+
+fn generate_struct(
+    w: &mut CodeWriter,
+    naming: &Naming,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let c_name = naming.type_name(ident);
+
+    write_docs(w, &annotations.comments.declaration);
+    w.block(
+        &format!("typedef struct {c_name} {{"),
+        &format!("}} {c_name};"),
+        |w| {
+            for (field_name, field_type) in fields {
+                let mut lengths = String::new();
+                let mut field_type = field_type;
+
+                while let ast::FieldType::Array(inner, length, _) = field_type {
+                    // Checked by the schema
+                    write!(lengths, "[{}]", length.unwrap_or_default()).unwrap();
+                    field_type = inner;
+                }
+                write_docs(w, annotations.comments.member(field_name));
+                writeln!(
+                    w,
+                    "{} {}{lengths};",
+                    type_str(field_type, naming),
+                    naming.field_name(field_name)
+                )
+                .unwrap();
+            }
+        },
+    );
+}
+
+fn generate_enum(
+    w: &mut CodeWriter,
+    naming: &Naming,
+    ident: &str,
+    base_type: &ast::IntegerType,
+    variants: &[(String, ast::IntegerValue)],
+    annotations: &ast::Annotations,
+) {
+    let c_name = naming.type_name(ident);
+    let prefix = to_screaming_snake_case(ident);
+
+    write_docs(w, &annotations.comments.declaration);
+    writeln!(w, "typedef {} {c_name};", integer_str(base_type)).unwrap();
+    for (variant_name, value) in variants {
+        write_docs(w, annotations.comments.member(variant_name));
+        writeln!(
+            w,
+            "#define {prefix}_{} (({c_name}){})",
+            naming.variant_name(variant_name),
+            value_str(base_type, value)
+        )
+        .unwrap();
+    }
+}
+
+/// The C type of a field that isn't an array
+fn type_str(ft: &ast::FieldType, naming: &Naming) -> String {
+    match ft {
+        ast::FieldType::Builtin(ast::BuiltinType::Integer(it), _) => integer_str(it).to_string(),
+        ast::FieldType::Builtin(ast::BuiltinType::Float(ast::FloatType::F32), _) => {
+            "float".to_string()
+        }
+        ast::FieldType::Builtin(ast::BuiltinType::Float(ast::FloatType::F64), _) => {
+            "double".to_string()
+        }
+        ast::FieldType::Builtin(ast::BuiltinType::Bool, _) => "bool".to_string(),
+        ast::FieldType::UserDefined(name, _) => naming.type_name(name),
+        _ => unreachable!("the schema checks that @abi(stable) fields are FFI-safe"),
+    }
+}
+
+fn integer_str(it: &ast::IntegerType) -> &'static str {
+    match it {
+        ast::IntegerType::I8 => "int8_t",
+        ast::IntegerType::I16 => "int16_t",
+        ast::IntegerType::I32 => "int32_t",
+        ast::IntegerType::I64 => "int64_t",
+        ast::IntegerType::U8 => "uint8_t",
+        ast::IntegerType::U16 => "uint16_t",
+        ast::IntegerType::U32 => "uint32_t",
+        ast::IntegerType::U64 => "uint64_t",
+    }
+}
+
+/// A variant value as a C literal.  64-bit values use the `stdint.h` macros, and the smallest
+/// `int64_t` is written as an expression, as its magnitude doesn't fit in one.
+fn value_str(base_type: &ast::IntegerType, value: &ast::IntegerValue) -> String {
+    let value = integer_value_str(value);
+
+    match base_type {
+        ast::IntegerType::I64 if value == i64::MIN.to_string() => {
+            format!("(INT64_C({}) - 1)", i64::MIN + 1)
+        }
+        ast::IntegerType::I64 => format!("INT64_C({value})"),
+        ast::IntegerType::U64 => format!("UINT64_C({value})"),
+        ast::IntegerType::U32 => format!("{value}u"),
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_str(schema: &str) -> String {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(schema)
+            .unwrap();
+        let files = CHeaderGenerator.generate(&schema, &Options::new()).unwrap();
+
+        String::from_utf8(files[0].contents.clone()).unwrap()
+    }
+
+    #[test]
+    fn structs_and_enums() {
+        let output = generate_str(
+            r#"meta { format = 1 }
+            @abi(stable) struct body { kind: body_kind, at: vec3, path: [[f64; 3]; 4], live: bool }
+            enum body_kind: u8 { rigid = 1, soft_body = 2 }
+            @abi(stable) struct vec3 { x: f32, y: f32, z: f32 }
+            struct other { name: string }"#,
+        );
+
+        assert!(output.contains("#include <stdint.h>"));
+        assert!(output.contains("typedef uint8_t BodyKind;"));
+        assert!(output.contains("#define BODY_KIND_SOFT_BODY ((BodyKind)2)"));
+        assert!(output.contains(
+            "typedef struct Body {\n    BodyKind kind;\n    Vec3 at;\n    double path[4][3];\n    bool live;\n} Body;"
+        ));
+        assert!(output.find("} Vec3;").unwrap() < output.find("} Body;").unwrap());
+        assert_eq!(output.matches("} Vec3;").count(), 1);
+        assert!(!output.contains("Other"));
+        assert!(output.ends_with("#endif /* GENO_H */\n"));
+    }
+
+    #[test]
+    fn large_values() {
+        let output = generate_str(
+            r#"meta { format = 1 }
+            enum big: i64 { low = -9223372036854775808, high = 9223372036854775807 }
+            @abi(stable) struct s { b: big, n: u64 }"#,
+        );
+
+        assert!(output.contains("#define BIG_LOW ((Big)(INT64_C(-9223372036854775807) - 1))"));
+        assert!(output.contains("#define BIG_HIGH ((Big)INT64_C(9223372036854775807))"));
+        assert!(output.contains("    uint64_t n;"));
+    }
+}
@@ -0,0 +1,885 @@
+//! Geno config generator.  Treats the structs marked `@config` as application config.  Each gets
+//! a commented example file, TOML or YAML, with the documentation, constraints and `@default` of
+//! every field, and `config.rs` gives it a `load` that reads a file and the environment over the
+//! defaults into the `rust-serde` type, using figment, and then checks the field constraints.
+//!
+//! Fields without a default are written with a placeholder value, commented out if they are
+//! nullable.  Placeholders of required fields don't meet every constraint, so they need filling
+//! in before the example loads.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, rust_serde, util::*},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Generator for the `config` format
+pub struct ConfigGenerator;
+
+/// Config is loaded into the `rust-serde` types
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+/// Syntax of the example files and the defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Yaml => "yaml",
+        }
+    }
+
+    /// The figment provider for the format
+    fn provider(self) -> &'static str {
+        match self {
+            Format::Toml => "Toml",
+            Format::Yaml => "Yaml",
+        }
+    }
+}
+
+/// An entry of an example document
+struct Entry {
+    key: String,
+    docs: Vec<String>,
+    value: Value,
+    /// Left out of the config, as the field is nullable and has no default
+    commented: bool,
+}
+
+/// The value of an entry.  Scalars are written the same way in TOML and YAML.
+enum Value {
+    Scalar(String),
+    Table(Vec<Entry>),
+}
+
+/// The fields or variants of a declaration, with its annotations
+type Members<'a, T> = (&'a [(String, T)], &'a ast::Annotations);
+
+struct Context<'a> {
+    structs: HashMap<&'a str, Members<'a, ast::FieldType>>,
+    enums: HashMap<&'a str, Members<'a, ast::IntegerValue>>,
+    naming: Naming,
+    format: Format,
+    /// Enums are serialized as their values rather than their names, as with
+    /// `rust-serde`'s `enum_repr=int`
+    enum_ints: bool,
+}
+
+impl Generator for ConfigGenerator {
+    fn name(&self) -> &str {
+        "config"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "format",
+            "enum_repr",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let ctx = Context {
+            structs: schema
+                .declarations
+                .iter()
+                .filter_map(|decl| match decl {
+                    ast::Declaration::Struct {
+                        ident,
+                        fields,
+                        annotations,
+                        ..
+                    } => Some((ident.as_str(), (fields.as_slice(), annotations))),
+                    _ => None,
+                })
+                .collect(),
+            enums: schema
+                .declarations
+                .iter()
+                .filter_map(|decl| match decl {
+                    ast::Declaration::Enum {
+                        ident,
+                        variants,
+                        annotations,
+                        ..
+                    } => Some((ident.as_str(), (variants.as_slice(), annotations))),
+                    _ => None,
+                })
+                .collect(),
+            naming: Naming::from_options(
+                options,
+                rust_serde::DEFAULT_NAMING,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )?,
+            format: match options.get("format") {
+                None | Some("toml") => Format::Toml,
+                Some("yaml") => Format::Yaml,
+                Some(value) => return Err(GenoError::InvalidOption(format!("format={value}"))),
+            },
+            enum_ints: match options.get("enum_repr") {
+                None | Some("string") => false,
+                Some("int") => true,
+                Some(value) => return Err(GenoError::InvalidOption(format!("enum_repr={value}"))),
+            },
+        };
+        let configs: Vec<&ast::Declaration> = schema
+            .declarations
+            .iter()
+            .filter(|decl| {
+                matches!(decl, ast::Declaration::Struct { annotations, .. } if annotations.is_config())
+            })
+            .collect();
+        let mut files = Vec::new();
+
+        for decl in &configs {
+            let ast::Declaration::Struct {
+                ident, annotations, ..
+            } = decl
+            else {
+                continue;
+            };
+            let mut docs: Vec<String> = annotations
+                .comments
+                .declaration
+                .iter()
+                .map(|comment| ast::Comments::text(comment))
+                .collect();
+
+            docs.push(format!("Example config for `{ident}`, generated by geno"));
+            files.push(GeneratedFile::new(
+                format!("{}.{}", to_snake_case(ident), ctx.format.extension()),
+                render(
+                    &docs,
+                    &entries(&ctx, ident, false, &mut Vec::new()),
+                    ctx.format,
+                ),
+            ));
+        }
+        files.push(GeneratedFile::new(
+            "config.rs",
+            loader(&ctx, schema, &configs),
+        ));
+
+        Ok(files)
+    }
+}
+
+/// The entries of a struct in its example, or with `defaults_only` only the fields with a
+/// `@default`.  `stack` holds the structs being written, so that recursion stops.
+fn entries<'a>(
+    ctx: &Context<'a>,
+    ident: &'a str,
+    defaults_only: bool,
+    stack: &mut Vec<&'a str>,
+) -> Vec<Entry> {
+    let Some((fields, annotations)) = ctx.structs.get(ident) else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+
+    stack.push(ident);
+    for (name, ft) in fields.iter() {
+        let nullable = field_nullable(ft);
+        let nested = match ft {
+            ast::FieldType::UserDefined(name, _) if ctx.structs.contains_key(name.as_str()) => {
+                Some(name.as_str())
+            }
+            _ => None,
+        };
+
+        if annotations.is_flattened(name) {
+            if let Some(nested) = nested.filter(|nested| !stack.contains(nested)) {
+                entries.extend(self::entries(ctx, nested, defaults_only, stack));
+            }
+            continue;
+        }
+
+        let default = annotations
+            .default_value(name)
+            .map(|value| default_str(ctx, ft, value));
+        let value = match (default, nested) {
+            (Some(default), _) => Value::Scalar(default),
+            (None, Some(nested)) if !stack.contains(&nested) && (!defaults_only || !nullable) => {
+                Value::Table(self::entries(ctx, nested, defaults_only, stack))
+            }
+            (None, _) if defaults_only => continue,
+            (None, _) => Value::Scalar(placeholder(ctx, ft, annotations.member(name))),
+        };
+
+        if defaults_only && matches!(&value, Value::Table(entries) if entries.is_empty()) {
+            continue;
+        }
+
+        let mut docs: Vec<String> = if defaults_only {
+            Vec::new()
+        } else {
+            annotations
+                .comments
+                .member(name)
+                .iter()
+                .map(|comment| ast::Comments::text(comment))
+                .chain(constraint_docs(annotations.member(name)))
+                .collect()
+        };
+
+        if !defaults_only
+            && !nullable
+            && annotations.default_value(name).is_none()
+            && let Value::Scalar(_) = value
+        {
+            docs.push("Required".to_string());
+        }
+        entries.push(Entry {
+            key: annotations.json_name(name).to_string(),
+            docs,
+            commented: nullable && annotations.default_value(name).is_none(),
+            value,
+        });
+    }
+    stack.pop();
+
+    entries
+}
+
+fn field_nullable(ft: &ast::FieldType) -> bool {
+    match ft {
+        ast::FieldType::Array(_, _, nullable) | ast::FieldType::Map(_, _, nullable) => *nullable,
+        _ => is_nullable(ft),
+    }
+}
+
+/// A `@default`, which validation has checked against the field type
+fn default_str(ctx: &Context, ft: &ast::FieldType, value: &ast::AnnotationValue) -> String {
+    match (ft, value) {
+        (
+            ast::FieldType::Builtin(ast::BuiltinType::Float(_), _),
+            ast::AnnotationValue::Integer(n),
+        ) => {
+            format!("{n}.0")
+        }
+        (_, ast::AnnotationValue::Integer(n)) => n.to_string(),
+        (_, ast::AnnotationValue::String(s)) => quoted(s),
+        (ast::FieldType::UserDefined(name, _), ast::AnnotationValue::Identifier(variant)) => {
+            let value = ctx
+                .enums
+                .get(name.as_str())
+                .and_then(|(variants, _)| variants.iter().find(|(name, _)| name == variant));
+
+            match value {
+                Some((_, value)) if ctx.enum_ints => integer_value_str(value),
+                _ => quoted(variant),
+            }
+        }
+        (_, ast::AnnotationValue::Identifier(id)) => id.clone(),
+    }
+}
+
+/// A value of the field's type for fields without a default
+fn placeholder(ctx: &Context, ft: &ast::FieldType, annotations: &[ast::Annotation]) -> String {
+    let min = annotations
+        .iter()
+        .filter(|annotation| annotation.name == "range")
+        .find_map(|annotation| annotation.bounds()?.min);
+
+    match ft {
+        ast::FieldType::Builtin(ast::BuiltinType::Integer(_), _) => min.unwrap_or(0).to_string(),
+        ast::FieldType::Builtin(ast::BuiltinType::Float(_), _) => {
+            format!("{}.0", min.unwrap_or(0))
+        }
+        ast::FieldType::Builtin(ast::BuiltinType::String, _) => quoted(""),
+        ast::FieldType::Builtin(ast::BuiltinType::LocalizedString, _) => "{}".to_string(),
+        ast::FieldType::Builtin(ast::BuiltinType::Bool, _) => "false".to_string(),
+        ast::FieldType::UserDefined(name, _) => match ctx.enums.get(name.as_str()) {
+            Some((variants, annotations)) => {
+                let (variant, value) = annotations.default_variant(variants);
+
+                if ctx.enum_ints {
+                    integer_value_str(value)
+                } else {
+                    quoted(variant)
+                }
+            }
+            // A struct holding itself
+            None => "{}".to_string(),
+        },
+        ast::FieldType::Array(item, Some(len), _) => {
+            let item = placeholder(ctx, item, &[]);
+
+            format!("[{}]", vec![item; *len].join(", "))
+        }
+        ast::FieldType::Array(_, None, _) => "[]".to_string(),
+        ast::FieldType::Map(..) => "{}".to_string(),
+    }
+}
+
+/// Lines describing the `@range`, `@len` and `@pattern` of a field
+fn constraint_docs(annotations: &[ast::Annotation]) -> Vec<String> {
+    annotations
+        .iter()
+        .filter_map(|annotation| {
+            let bounds = |what: &str| match annotation.bounds()? {
+                ast::Bounds {
+                    min: Some(min),
+                    max: Some(max),
+                } => Some(format!("{what} between {min} and {max}")),
+                ast::Bounds {
+                    min: Some(min),
+                    max: None,
+                } => Some(format!("{what} at least {min}")),
+                ast::Bounds {
+                    min: None,
+                    max: Some(max),
+                } => Some(format!("{what} at most {max}")),
+                _ => None,
+            };
+
+            match annotation.name.as_str() {
+                "range" => bounds("Value"),
+                "len" => bounds("Length"),
+                "pattern" => Some(format!("Matches `{}`", annotation.string()?)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// A string in double quotes, which both TOML and YAML read, with JSON escapes
+fn quoted(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+/// A key, quoted unless it is bare in both TOML and YAML
+fn key_str(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_string()
+    } else {
+        quoted(key)
+    }
+}
+
+fn render(docs: &[String], entries: &[Entry], format: Format) -> String {
+    let mut lines = Vec::new();
+
+    for doc in docs {
+        lines.extend(
+            doc.lines()
+                .map(|line| format!("# {line}").trim_end().to_string()),
+        );
+    }
+    match format {
+        Format::Toml => render_toml(&mut lines, &[], entries, false),
+        Format::Yaml => render_yaml(&mut lines, 0, entries, false),
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// A blank line between entries, but not after a section header or at the start
+fn separate(lines: &mut Vec<String>) {
+    if lines
+        .last()
+        .is_some_and(|last| !last.is_empty() && !last.trim_start_matches("# ").starts_with('['))
+    {
+        lines.push(String::new());
+    }
+}
+
+fn doc_lines(lines: &mut Vec<String>, indent: &str, docs: &[String]) {
+    for doc in docs {
+        lines.extend(
+            doc.lines()
+                .map(|line| format!("{indent}# {line}").trim_end().to_string()),
+        );
+    }
+}
+
+/// Scalars come before tables, which TOML needs, and tables are written as `[a.b]` sections
+fn render_toml(lines: &mut Vec<String>, path: &[String], entries: &[Entry], commented: bool) {
+    for entry in entries {
+        if let Value::Scalar(value) = &entry.value {
+            let prefix = if commented || entry.commented {
+                "# "
+            } else {
+                ""
+            };
+
+            separate(lines);
+            doc_lines(lines, "", &entry.docs);
+            lines.push(format!("{prefix}{} = {value}", key_str(&entry.key)));
+        }
+    }
+    for entry in entries {
+        if let Value::Table(table) = &entry.value {
+            let commented = commented || entry.commented;
+            let prefix = if commented { "# " } else { "" };
+            let mut path = path.to_vec();
+
+            path.push(key_str(&entry.key));
+            separate(lines);
+            doc_lines(lines, "", &entry.docs);
+            lines.push(format!("{prefix}[{}]", path.join(".")));
+            render_toml(lines, &path, table, commented);
+        }
+    }
+}
+
+fn render_yaml(lines: &mut Vec<String>, depth: usize, entries: &[Entry], commented: bool) {
+    let indent = "  ".repeat(depth);
+
+    for entry in entries {
+        let commented = commented || entry.commented;
+        let prefix = if commented { "# " } else { "" };
+
+        if depth == 0 && !lines.is_empty() {
+            lines.push(String::new());
+        }
+        doc_lines(lines, &indent, &entry.docs);
+        match &entry.value {
+            Value::Scalar(value) => {
+                lines.push(format!("{indent}{prefix}{}: {value}", key_str(&entry.key)))
+            }
+            Value::Table(table) if table.is_empty() => {
+                lines.push(format!("{indent}{prefix}{}: {{}}", key_str(&entry.key)))
+            }
+            Value::Table(table) => {
+                lines.push(format!("{indent}{prefix}{}:", key_str(&entry.key)));
+                render_yaml(lines, depth + 1, table, commented);
+            }
+        }
+    }
+}
+
+/// The structs that the config structs hold, directly or through other types, including
+/// themselves
+fn reachable<'a>(ctx: &Context<'a>, configs: &[&'a ast::Declaration]) -> HashSet<&'a str> {
+    fn visit<'a>(ctx: &Context<'a>, ft: &'a ast::FieldType, seen: &mut HashSet<&'a str>) {
+        match ft {
+            ast::FieldType::UserDefined(name, _) => {
+                if let Some((fields, _)) = ctx.structs.get(name.as_str())
+                    && seen.insert(name)
+                {
+                    for (_, ft) in fields.iter() {
+                        visit(ctx, ft, seen);
+                    }
+                }
+            }
+            ast::FieldType::Array(item, _, _) | ast::FieldType::Map(_, item, _) => {
+                visit(ctx, item, seen)
+            }
+            ast::FieldType::Builtin(..) => {}
+        }
+    }
+
+    let mut seen = HashSet::new();
+
+    for decl in configs {
+        if let ast::Declaration::Struct { ident, fields, .. } = decl
+            && seen.insert(ident.as_str())
+        {
+            for (_, ft) in fields {
+                visit(ctx, ft, &mut seen);
+            }
+        }
+    }
+
+    seen
+}
+
+fn loader(ctx: &Context, schema: &ast::Schema, configs: &[&ast::Declaration]) -> String {
+    let reachable = reachable(ctx, configs);
+    let provider = ctx.format.provider();
+    let uses_regex = reachable.iter().any(|ident| {
+        ctx.structs[ident].1.members.values().any(|annotations| {
+            annotations
+                .iter()
+                .any(|annotation| annotation.name == "pattern")
+        })
+    });
+    let mut w = CodeWriter::new("    ");
+
+    w.line("// Loading and checking of the @config structs, generated by geno.  Include this file in the");
+    w.line(format!(
+        "// module that holds the rust-serde types.  Needs the figment crate with the `{}` and `env`",
+        ctx.format.extension()
+    ));
+    if uses_regex {
+        w.line("// features, and the regex crate.");
+    } else {
+        w.line("// features.");
+    }
+
+    for decl in &schema.declarations {
+        let ast::Declaration::Struct {
+            ident,
+            fields,
+            annotations,
+            ..
+        } = decl
+        else {
+            continue;
+        };
+
+        if !reachable.contains(ident.as_str()) {
+            continue;
+        }
+
+        let rust_name = ctx.naming.type_name(ident);
+
+        w.blank();
+        w.block(&format!("impl {rust_name} {{"), "}", |w| {
+            if annotations.is_config() {
+                let defaults = render(
+                    &[],
+                    &entries(ctx, ident, true, &mut Vec::new()),
+                    ctx.format,
+                );
+                let prefix = format!("{}_", to_screaming_snake_case(ident));
+
+                w.line("/// The `@default` values, which the config file and environment override");
+                w.line(format!("pub const DEFAULTS: &str = {defaults:?};"));
+                w.blank();
+                w.line(format!(
+                    "/// Load from a {} file over the defaults, then from environment variables starting",
+                    provider.to_uppercase()
+                ));
+                w.line(format!(
+                    "/// with `{prefix}`, where `__` separates nested keys, and check the result"
+                ));
+                w.block(
+                    "pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, figment::Error> {",
+                    "}",
+                    |w| {
+                        w.line(format!(
+                            "use figment::providers::{{Env, Format, {provider}}};"
+                        ));
+                        w.blank();
+                        w.line("let config: Self = figment::Figment::new()");
+                        w.line(format!("    .merge({provider}::string(Self::DEFAULTS))"));
+                        w.line(format!("    .merge({provider}::file(path))"));
+                        w.line(format!("    .merge(Env::prefixed({prefix:?}).split(\"__\"))"));
+                        w.line("    .extract()?;");
+                        w.blank();
+                        w.line("config.validate()?;");
+                        w.line("Ok(config)");
+                    },
+                );
+                w.blank();
+            }
+            w.line("/// Check the constraints on the fields, naming the first one that fails");
+            w.block("pub fn validate(&self) -> Result<(), String> {", "}", |w| {
+                for (name, ft) in fields {
+                    validate_field(ctx, w, name, ft, annotations);
+                }
+                w.line("Ok(())");
+            });
+        });
+    }
+
+    w.into_string()
+}
+
+/// The checks of a field's constraints, and of the structs it holds
+fn validate_field(
+    ctx: &Context,
+    w: &mut CodeWriter,
+    name: &str,
+    ft: &ast::FieldType,
+    annotations: &ast::Annotations,
+) {
+    let field = format!("self.{}", ctx.naming.field_name(name));
+    let key = annotations.json_name(name);
+    let nullable = field_nullable(ft);
+    let value = if nullable {
+        "value".to_string()
+    } else {
+        field.clone()
+    };
+    let mut checks: Vec<(String, String)> = Vec::new();
+
+    for annotation in annotations.member(name) {
+        let bounds = annotation.bounds().unwrap_or_default();
+        let deref = if nullable { "*" } else { "" };
+        let int = |n: i64| n.to_string();
+        let (expr, min, max, what) = match (annotation.name.as_str(), ft) {
+            ("range", ast::FieldType::Builtin(ast::BuiltinType::Float(_), _)) => {
+                let float = |n: i64| format!("{n}.0");
+
+                (
+                    format!("{deref}{value}"),
+                    bounds.min.map(float),
+                    bounds.max.map(float),
+                    "",
+                )
+            }
+            ("range", _) => (
+                format!("{deref}{value}"),
+                bounds.min.map(int),
+                bounds.max.map(int),
+                "",
+            ),
+            ("len", ast::FieldType::Builtin(ast::BuiltinType::String, _)) => (
+                format!("{value}.chars().count()"),
+                bounds.min.map(int),
+                bounds.max.map(int),
+                "the length of ",
+            ),
+            ("len", _) => (
+                format!("{value}.len()"),
+                bounds.min.map(int),
+                bounds.max.map(int),
+                "the length of ",
+            ),
+            ("pattern", _) => {
+                let Some(pattern) = annotation.string() else {
+                    continue;
+                };
+                let subject = if nullable {
+                    value.clone()
+                } else {
+                    format!("&{value}")
+                };
+
+                checks.push((
+                    format!("!regex::Regex::new({pattern:?}).unwrap().is_match({subject})"),
+                    format!("{key} must match {pattern}"),
+                ));
+                continue;
+            }
+            _ => continue,
+        };
+        let (condition, message) = match (min, max) {
+            (Some(min), Some(max)) => (
+                format!("!({min}..={max}).contains(&{expr})"),
+                format!("{what}{key} must be between {min} and {max}"),
+            ),
+            (Some(min), None) => (
+                format!("{expr} < {min}"),
+                format!("{what}{key} must be at least {min}"),
+            ),
+            (None, Some(max)) => (
+                format!("{expr} > {max}"),
+                format!("{what}{key} must be at most {max}"),
+            ),
+            (None, None) => continue,
+        };
+
+        checks.push((condition, message));
+    }
+
+    let is_struct = |ft: &ast::FieldType| matches!(ft, ast::FieldType::UserDefined(name, _) if ctx.structs.contains_key(name.as_str()));
+    let nested = if annotations.is_flattened(name) && is_struct(ft) {
+        Some(format!("{value}.validate()?;"))
+    } else if is_struct(ft) {
+        Some(format!(
+            "{value}.validate().map_err(|err| format!(\"{key}.{{err}}\"))?;"
+        ))
+    } else {
+        match ft {
+            ast::FieldType::Array(item, _, _) if is_struct(item) && !is_nullable(item) => {
+                Some(format!(
+                    "for (i, item) in {value}.iter().enumerate() {{\n    item.validate().map_err(|err| format!(\"{key}[{{i}}].{{err}}\"))?;\n}}"
+                ))
+            }
+            _ => None,
+        }
+    };
+
+    if checks.is_empty() && nested.is_none() {
+        return;
+    }
+
+    let write_checks = |w: &mut CodeWriter| {
+        for (condition, message) in &checks {
+            w.block(&format!("if {condition} {{"), "}", |w| {
+                w.line(format!("return Err({message:?}.to_string());"));
+            });
+        }
+        if let Some(nested) = &nested {
+            for line in nested.lines() {
+                w.line(line);
+            }
+        }
+    };
+
+    if nullable {
+        w.block(
+            &format!("if let Some(value) = &{field} {{"),
+            "}",
+            write_checks,
+        );
+    } else {
+        write_checks(w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"meta { format = 1 }
+enum level: u8 { info = 1, debug = 2 }
+// Settings for the TLS listener
+struct tls { cert: string, @default(false) verify: bool }
+// The server
+@config
+struct server {
+    // Port to listen on
+    @range(1, 65535) @default(8080)
+    port: u16,
+    @len(max = 64) @pattern("^[a-z.]+$")
+    host: string,
+    @default(debug)
+    level: level,
+    @range(0, 1) @default(1)
+    ratio: f32,
+    tls: tls,
+    proxy: tls?,
+    tags: [string]?,
+}
+"#;
+
+    fn generate(options: &[&str]) -> Vec<(String, String)> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(SCHEMA)
+            .unwrap();
+
+        ConfigGenerator
+            .generate(&schema, &Options::parse(options).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|file| {
+                (
+                    file.path.to_string_lossy().into_owned(),
+                    String::from_utf8(file.contents).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn toml() {
+        let files = generate(&[]);
+
+        assert_eq!(files[0].0, "server.toml");
+        assert_eq!(
+            files[0].1,
+            r##"# The server
+# Example config for `server`, generated by geno
+
+# Port to listen on
+# Value between 1 and 65535
+port = 8080
+
+# Length at most 64
+# Matches `^[a-z.]+$`
+# Required
+host = ""
+
+level = "debug"
+
+# Value between 0 and 1
+ratio = 1.0
+
+# tags = []
+
+[tls]
+# Required
+cert = ""
+
+verify = false
+
+# [proxy]
+# Required
+# cert = ""
+
+# verify = false
+"##
+        );
+    }
+
+    #[test]
+    fn yaml() {
+        let files = generate(&["format=yaml", "enum_repr=int"]);
+
+        assert_eq!(files[0].0, "server.yaml");
+        assert!(files[0].1.contains("\nlevel: 2\n"));
+        assert!(
+            files[0]
+                .1
+                .contains("\ntls:\n  # Required\n  cert: \"\"\n  verify: false\n")
+        );
+        assert!(
+            files[0]
+                .1
+                .contains("\n# proxy:\n  # Required\n  # cert: \"\"\n")
+        );
+        assert!(files[1].1.contains(
+            r#"pub const DEFAULTS: &str = "port: 8080\n\nlevel: 2\n\nratio: 1.0\n\ntls:\n  verify: false\n";"#
+        ));
+        assert!(
+            files[1]
+                .1
+                .contains("use figment::providers::{Env, Format, Yaml};")
+        );
+    }
+
+    #[test]
+    fn loader() {
+        let files = generate(&[]);
+        let output = &files[1].1;
+
+        assert_eq!(files[1].0, "config.rs");
+        assert!(output.contains("the regex crate"));
+        assert!(output.contains(
+            r#"pub const DEFAULTS: &str = "port = 8080\n\nlevel = \"debug\"\n\nratio = 1.0\n\n[tls]\nverify = false\n";"#
+        ));
+        assert!(output.contains("    .merge(Env::prefixed(\"SERVER_\").split(\"__\"))"));
+        assert!(output.contains(
+            "if !(1..=65535).contains(&self.port) {\n            return Err(\"port must be between 1 and 65535\".to_string());"
+        ));
+        assert!(output.contains("if self.host.chars().count() > 64 {"));
+        assert!(
+            output.contains("if !regex::Regex::new(\"^[a-z.]+$\").unwrap().is_match(&self.host) {")
+        );
+        assert!(output.contains("if !(0.0..=1.0).contains(&self.ratio) {"));
+        assert!(output.contains("self.tls.validate().map_err(|err| format!(\"tls.{err}\"))?;"));
+        assert!(output.contains(
+            "if let Some(value) = &self.proxy {\n            value.validate().map_err(|err| format!(\"proxy.{err}\"))?;\n        }"
+        ));
+        assert!(output.contains("impl Tls {\n    /// Check the constraints"));
+    }
+}
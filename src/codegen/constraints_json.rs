@@ -0,0 +1,197 @@
+//! Geno constraints generator.  Writes `constraints.json`, a language neutral description of the
+//! rules each value must follow, so that API gateways and form builders can check data without
+//! code generated from the schema.
+//!
+//! Every struct field is listed under its struct with its schema `type`, whether it is
+//! `nullable`, and any `range`, `len` or `pattern` from its annotations.  Enums are listed with
+//! the value of each variant.  Names are the identifiers in the schema, fields renamed with
+//! `@json` also have their `json` name, and `@flatten` fields are marked `flatten`.  Fields
+//! with doc comments have them as their `description`.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, util::*},
+};
+use serde_json::{Map, Value, json};
+
+/// Generator for the `constraints-json` format
+pub struct ConstraintsJsonGenerator;
+
+impl Generator for ConstraintsJsonGenerator {
+    fn name(&self) -> &str {
+        "constraints-json"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&["version", "order"])
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        let schema = &*select_schema(schema, options)?;
+        let mut contents = serde_json::to_string_pretty(&constraints(schema)).unwrap();
+
+        contents.push('\n');
+
+        Ok(vec![GeneratedFile::new("constraints.json", contents)])
+    }
+}
+
+/// The constraints document for a schema
+pub fn constraints(schema: &ast::Schema) -> Value {
+    let mut structs = Map::new();
+    let mut enums = Map::new();
+
+    for decl in &schema.declarations {
+        match decl {
+            ast::Declaration::Enum {
+                ident, variants, ..
+            } => {
+                enums.insert(
+                    ident.clone(),
+                    variants
+                        .iter()
+                        .map(|(name, value)| (name.clone(), integer_value(value)))
+                        .collect(),
+                );
+            }
+            ast::Declaration::Struct {
+                ident,
+                fields,
+                annotations,
+                ..
+            } => {
+                structs.insert(
+                    ident.clone(),
+                    fields
+                        .iter()
+                        .map(|(name, ft)| (name.clone(), field(ft, annotations, name)))
+                        .collect(),
+                );
+            }
+            ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => {}
+        }
+    }
+
+    json!({ "structs": structs, "enums": enums })
+}
+
+fn field(ft: &ast::FieldType, annotations: &ast::Annotations, name: &str) -> Value {
+    let mut field = Map::new();
+
+    field.insert(
+        "type".to_string(),
+        schema_type_str(&non_nullable(ft)).into(),
+    );
+    field.insert("nullable".to_string(), is_nullable(ft).into());
+
+    let description: Vec<String> = annotations
+        .comments
+        .member(name)
+        .iter()
+        .map(|comment| ast::Comments::text(comment))
+        .collect();
+
+    if !description.is_empty() {
+        field.insert("description".to_string(), description.join("\n").into());
+    }
+    if annotations.is_flattened(name) {
+        field.insert("flatten".to_string(), true.into());
+    } else if annotations.json_name(name) != name {
+        field.insert("json".to_string(), annotations.json_name(name).into());
+    }
+
+    // Validation has already checked the arguments and the field types
+    for annotation in annotations.member(name) {
+        match annotation.name.as_str() {
+            "range" | "len" => {
+                field.insert(annotation.name.clone(), json!(annotation.bounds()));
+            }
+            "pattern" => {
+                field.insert(annotation.name.clone(), json!(annotation.string()));
+            }
+            _ => {}
+        }
+    }
+
+    Value::Object(field)
+}
+
+fn integer_value(value: &ast::IntegerValue) -> Value {
+    match value {
+        ast::IntegerValue::I8(n) => json!(n),
+        ast::IntegerValue::I16(n) => json!(n),
+        ast::IntegerValue::I32(n) => json!(n),
+        ast::IntegerValue::I64(n) => json!(n),
+        ast::IntegerValue::U8(n) => json!(n),
+        ast::IntegerValue::U16(n) => json!(n),
+        ast::IntegerValue::U32(n) => json!(n),
+        ast::IntegerValue::U64(n) => json!(n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constraints_document() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                r#"meta { format = 1 }
+enum role: u8 { admin = 1, guest = 2 }
+struct user {
+    @range(0, 150) age: u8,
+    @len(max = 64) @pattern("^[a-z]+$") name: string?,
+    @len(1) @json(name = "role-list") roles: [role],
+}"#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            constraints(&schema),
+            json!({
+                "structs": {
+                    "user": {
+                        "age": { "type": "u8", "nullable": false, "range": { "min": 0, "max": 150 } },
+                        "name": {
+                            "type": "string",
+                            "nullable": true,
+                            "len": { "max": 64 },
+                            "pattern": "^[a-z]+$",
+                        },
+                        "roles": {
+                            "type": "[role]",
+                            "nullable": false,
+                            "json": "role-list",
+                            "len": { "min": 1 },
+                        },
+                    }
+                },
+                "enums": { "role": { "admin": 1, "guest": 2 } },
+            })
+        );
+    }
+
+    #[test]
+    fn descriptions() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 }\nstruct user {\n    /// Full name\n    /* as written */\n    name: string,\n    age: u8,\n}",
+            )
+            .unwrap();
+        let constraints = constraints(&schema);
+
+        assert_eq!(
+            constraints["structs"]["user"]["name"]["description"],
+            "Full name\nas written"
+        );
+        assert!(
+            constraints["structs"]["user"]["age"]
+                .get("description")
+                .is_none()
+        );
+    }
+}
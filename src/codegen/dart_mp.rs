@@ -0,0 +1,3008 @@
+//! Geno Dart/MessagePacker generator.  All type categories are handled:
+//!
+//! - Primitive types: p.packInt() / u.unpackInt()! etc.
+//! - Nullable primitives: null check with p.packNull() fallback / u.unpackInt() (returns nullable)
+//! - Enums: _pack(p) packs the int value / _unpack(u) does firstWhere lookup
+//! - Nullable enums: null check + _pack / _unpackNullable checks unpackInt() for null
+//! - Lists: packListLength + element loop / List.generate(u.unpackListLength(), ...)
+//! - Fixed length lists: as lists, throwing ArgumentError on pack / FormatException on unpack if the length is wrong
+//! - Nullable lists: presence marker packBool(true) / u.unpackBool() == null ? null : ...
+//! - Maps: packMapLength + entry loop / Map.fromEntries(List.generate(u.unpackMapLength(), ...))
+//! - Nested structs: _pack(p) / Type._unpack(u) — correctly recursive
+//! - Nullable structs: presence marker packBool(true) + _pack / _unpackNullable checks unpackBool() for null
+//!
+//! Here's a summary of the serialization protocol:
+//!
+//! ┌─────────────────────┬─────────────────────────────────────────┬─────────────────────────────┐
+//! │        Type         │               Pack format               │           Unpack            │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Primitives          │ Direct packXXX                          │ unpackXXX()!                │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Nullable primitives │ packNull or packXXX                     │ unpackXXX() (returns T?)    │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Enums               │ packInt(value)                          │ firstWhere on unpackInt()!  │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Nullable enums      │ packNull or packInt(value)              │ Check unpackInt() for null  │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Structs             │ Sequential field packing                │ Sequential field unpacking  │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Nullable structs    │ packNull or packBool(true) + fields     │ Check unpackBool() for null │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Nullable lists/maps │ packNull or packBool(true) + collection │ Check unpackBool() for null │
+//! └─────────────────────┴─────────────────────────────────────────┴─────────────────────────────┘
+//!
+//! The table above describes the default `positional` wire format.  With `wire=keyed` (or
+//! `wire = "keyed"` in the schema metadata) structs are self-describing instead:
+//!
+//! ┌─────────────────────┬─────────────────────────────────────────┬─────────────────────────────┐
+//! │        Type         │               Pack format               │           Unpack            │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Structs             │ packMapLength + name/value per field    │ unpackMap() and look up by  │
+//! │                     │                                         │ name, ignoring unknown keys │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Null fields         │ Omitted from the map                    │ Missing key is null         │
+//! ├─────────────────────┼─────────────────────────────────────────┼─────────────────────────────┤
+//! │ Nullable structs,   │ packNull or the value, no presence      │ Check for null              │
+//! │ lists and maps      │ marker                                  │                             │
+//! └─────────────────────┴─────────────────────────────────────────┴─────────────────────────────┘
+//!
+//! Keys are the field names as written in the schema, so they don't change with `field_case`.
+//! A missing non-nullable field throws a FormatException, except for enums with a `@default`
+//! variant, which missing and unknown values fall back to in every wire format and in JSON.
+//!
+//! With `wire=bitmap` the layout is positional, but each struct with nullable fields starts with
+//! one packInt per 32 nullable fields, whose bits say which of them are present, least
+//! significant bit first.  Null fields are left out and present ones are packed without a
+//! presence marker.  Nullable values inside lists and maps are packed as in `positional`.
+//!
+//! The `rust-mp` generator produces the same bytes for all three formats.
+//!
+use crate::{
+    GenoError, ast,
+    codegen::{CodeSink, GeneratedFile, GeneratedFiles, Generator, Options, util::*},
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Generator for the `dart-mp` format
+pub struct DartMpGenerator;
+
+/// Dart maps compare keys with `==`, and `double.nan != double.nan`, so float keys can't be
+/// looked up reliably.  Dart integers are signed, so `u64` values above `i64::MAX` don't fit.
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    u64: false,
+    ..ast::Capabilities::ALL
+};
+
+impl Generator for DartMpGenerator {
+    fn name(&self) -> &str {
+        "dart-mp"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "int64",
+            "equality",
+            "to_string",
+            "copy_with",
+            "json",
+            "framing",
+            "isolate",
+            "envelope",
+            "compression",
+            "compress_above",
+            "max_decompressed",
+            "mask_sensitive",
+            "redaction",
+            "enum_helpers",
+            "property_tests",
+            "roots_only",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("dart"))?;
+        let config = Config::from_options(schema, options)?;
+        let tests = config
+            .property_tests
+            .then(|| GeneratedFile::new("geno_test.dart", generate_tests(schema, &config)));
+        let mut files = vec![GeneratedFile::new("geno.dart", generate(schema, config))];
+
+        files.extend(tests);
+        Ok(files)
+    }
+    fn generate_to(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+        sink: &mut dyn CodeSink,
+    ) -> Result<(), GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("dart"))?;
+        let config = Config::from_options(schema, options)?;
+
+        sink.write_file(Path::new("geno.dart"), &mut |out| {
+            let mut w = CodeWriter::to_writer("  ", out);
+
+            write_file(&mut w, schema, config.clone());
+            Ok(w.finish()?)
+        })?;
+
+        if config.property_tests {
+            sink.write_file(Path::new("geno_test.dart"), &mut |out| {
+                Ok(out.write_all(generate_tests(schema, &config).as_bytes())?)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A `package:test` file that checks each root type decodes to the random values it was encoded
+/// from, for the `property_tests` option.  It imports the generated code from `geno.dart` beside
+/// it, and the same seed always gives the same values.
+fn generate_tests(schema: &ast::Schema, config: &Config) -> String {
+    let mut w = CodeWriter::new("  ");
+
+    writeln!(w, "import 'dart:math' as math;").unwrap();
+    w.blank();
+    writeln!(w, "import 'package:test/test.dart';").unwrap();
+    w.blank();
+    writeln!(w, "import 'geno.dart';").unwrap();
+    w.blank();
+    writeln!(w, "const _seed = 0;").unwrap();
+    writeln!(w, "const _runs = 100;").unwrap();
+    w.blank();
+    w.block("void main() {", "}", |w| {
+        let mut first = true;
+
+        for decl in &schema.declarations {
+            let ident = match decl {
+                ast::Declaration::Enum { ident, .. } | ast::Declaration::Struct { ident, .. }
+                    if config.roots.contains(ident) =>
+                {
+                    ident
+                }
+                _ => continue,
+            };
+            let dart_name = config.naming.type_name(ident);
+
+            if !first {
+                w.blank();
+            }
+            first = false;
+            w.block(
+                &format!("test('{dart_name} round trips', () {{"),
+                "});",
+                |w| {
+                    writeln!(w, "final random = math.Random(_seed);").unwrap();
+                    w.block("for (var i = 0; i < _runs; i++) {", "}", |w| {
+                        writeln!(w, "final value = {dart_name}.arbitrary(random);").unwrap();
+                        writeln!(
+                            w,
+                            "expect({dart_name}.fromBytes(value.toBytes()), equals(value));"
+                        )
+                        .unwrap();
+                    });
+                },
+            );
+        }
+    });
+
+    w.into_string()
+}
+
+/// Write a program that decodes each test vector listed in `<input>/index.txt` and encodes it
+/// again into `<output>`, for the [crate::roundtrip] harness.  The program imports the generated
+/// code from `package:geno_driver/geno.dart`.
+pub(crate) fn generate_driver(
+    schema: &ast::Schema,
+    options: &Options,
+) -> Result<String, GenoError> {
+    schema.check_capabilities(&CAPABILITIES)?;
+
+    let schema = &*select_target(schema, options, Some("dart"))?;
+    let config = Config::from_options(schema, options)?;
+    let mut w = CodeWriter::new("  ");
+
+    writeln!(w, "import 'dart:io';").unwrap();
+    writeln!(w, "import 'dart:typed_data';").unwrap();
+    w.blank();
+    writeln!(w, "import 'package:geno_driver/geno.dart';").unwrap();
+    w.blank();
+    w.block(
+        "Uint8List reencode(String typeName, Uint8List bytes) {",
+        "}",
+        |w| {
+            w.block("switch (typeName) {", "}", |w| {
+                for decl in &schema.declarations {
+                    let ident = match decl {
+                        ast::Declaration::Enum { ident, .. } => ident,
+                        ast::Declaration::Struct { ident, .. } => ident,
+                        ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => {
+                            continue;
+                        }
+                    };
+                    if !config.roots.contains(ident) {
+                        continue;
+                    }
+                    writeln!(w, "case '{ident}':").unwrap();
+                    w.indent();
+                    writeln!(
+                        w,
+                        "return {}.fromBytes(bytes).toBytes();",
+                        config.naming.type_name(ident)
+                    )
+                    .unwrap();
+                    w.dedent();
+                }
+            });
+            writeln!(w, "throw ArgumentError(\"unknown type '$typeName'\");").unwrap();
+        },
+    );
+    w.blank();
+    w.block("void main(List<String> args) {", "}", |w| {
+        writeln!(w, "var failed = false;").unwrap();
+        w.block(
+            "for (final line in File('${args[0]}/index.txt').readAsLinesSync()) {",
+            "}",
+            |w| {
+                writeln!(w, "final parts = line.split(' ');").unwrap();
+                writeln!(w, "if (parts.length != 2) continue;").unwrap();
+                w.block("try {", "} catch (err) {", |w| {
+                    writeln!(
+                        w,
+                        "final bytes = reencode(parts[0], File('${{args[0]}}/${{parts[1]}}').readAsBytesSync());"
+                    )
+                    .unwrap();
+                    writeln!(
+                        w,
+                        "File('${{args[1]}}/${{parts[1]}}').writeAsBytesSync(bytes);"
+                    )
+                    .unwrap();
+                });
+                w.indent();
+                writeln!(w, "stderr.writeln('${{parts[1]}}: $err');").unwrap();
+                writeln!(w, "failed = true;").unwrap();
+                w.dedent();
+                writeln!(w, "}}").unwrap();
+            },
+        );
+        writeln!(w, "exit(failed ? 1 : 0);").unwrap();
+    });
+
+    Ok(w.into_string())
+}
+
+/// Settings for one run of the generator, read from the [Options]
+#[derive(Clone)]
+struct Config {
+    naming: Naming,
+    /// Generate `operator ==` and `hashCode`
+    equality: bool,
+    /// Generate `toString`
+    to_string: bool,
+    /// Generate `copyWith`
+    copy_with: bool,
+    /// Struct layout on the wire
+    wire: WireFormat,
+    /// Generate `toJson` and `fromJson`
+    json: bool,
+    /// Generate `writeDelimitedTo` and `readDelimitedFrom`
+    framing: bool,
+    /// Generate `fromBytesAsync` and `const` constructors, for Flutter apps
+    isolate: bool,
+    /// Generate `toEnvelope` and `fromEnvelope`
+    envelope: Option<Envelope>,
+    /// Compress large messages in `toBytes` and decompress them in `fromBytes`
+    compression: Option<Compression>,
+    /// Show `@sensitive` and `@pii` fields as `***` in `toString`
+    mask_sensitive: bool,
+    /// Generate `toRedactedJson`, which leaves out `@sensitive` and `@pii` fields
+    redaction: bool,
+    /// Write a `when` method with one callback per variant for every enum
+    enum_helpers: bool,
+    /// Generate `arbitrary` factories and a `geno_test.dart` file of round trip tests
+    property_tests: bool,
+    /// Types that get public methods to encode and decode them
+    roots: Roots,
+    /// How 64-bit integer fields without an `@int64` annotation are represented
+    int64: Int64,
+}
+
+impl Config {
+    fn from_options(schema: &ast::Schema, options: &Options) -> Result<Self, GenoError> {
+        let int64 = Int64::from_options(options)?;
+        let equality = options.get_bool("equality", true)?;
+        let property_tests = options.get_bool("property_tests", false)?;
+        let json = options.get_bool("json", false)?;
+        let redaction = options.get_bool("redaction", false)?;
+
+        int64.check(schema)?;
+        // The round trip tests compare values with `==`
+        if property_tests && !equality {
+            return Err(GenoError::InvalidOption(
+                "property_tests=true needs equality=true".to_string(),
+            ));
+        }
+        if redaction && !json {
+            return Err(GenoError::InvalidOption(
+                "redaction=true needs json=true".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            naming: Naming::from_options(
+                options,
+                DEFAULT_NAMING,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )?,
+            equality,
+            to_string: options.get_bool("to_string", true)?,
+            copy_with: options.get_bool("copy_with", true)?,
+            wire: WireFormat::from_options(schema, options)?,
+            json,
+            framing: options.get_bool("framing", false)?,
+            isolate: options.get_bool("isolate", false)?,
+            envelope: Envelope::from_options(schema, options)?,
+            compression: Compression::from_options(options)?,
+            mask_sensitive: options.get_bool("mask_sensitive", true)?,
+            redaction,
+            enum_helpers: options.get_bool("enum_helpers", false)?,
+            property_tests,
+            roots: Roots::from_options(schema, options)?,
+            int64,
+        })
+    }
+}
+
+pub(crate) const DEFAULT_NAMING: Naming = Naming {
+    types: NamingConvention::Pascal,
+    fields: NamingConvention::Camel,
+    variants: NamingConvention::Camel,
+    ascii: true,
+};
+
+/// Everything the code generation functions need to know about the schema being generated
+struct Context<'a> {
+    config: Config,
+    enum_names: HashSet<&'a str>,
+    /// Enums with a `@default` variant
+    enum_defaults: HashSet<&'a str>,
+    /// Struct fields that implement an interface's getter
+    interface_fields: HashSet<(&'a str, &'a str)>,
+    /// Struct fields that `arbitrary` leaves null or empty, so that it stops
+    recursive_fields: HashSet<(&'a str, &'a str)>,
+}
+
+fn generate(schema: &ast::Schema, config: Config) -> String {
+    let mut w = CodeWriter::new("  ");
+
+    write_file(&mut w, schema, config);
+
+    w.into_string()
+}
+
+fn write_file(w: &mut CodeWriter, schema: &ast::Schema, config: Config) {
+    let enum_names: HashSet<&str> = schema
+        .declarations
+        .iter()
+        .filter_map(|d| match d {
+            ast::Declaration::Enum { ident, .. } => Some(ident.as_str()),
+            _ => None,
+        })
+        .collect();
+    let enum_defaults: HashSet<&str> = schema
+        .declarations
+        .iter()
+        .filter_map(|d| match d {
+            ast::Declaration::Enum {
+                ident, annotations, ..
+            } if annotations
+                .members
+                .values()
+                .flatten()
+                .any(|annotation| annotation.name == "default") =>
+            {
+                Some(ident.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    let interfaces: HashMap<&str, &[(String, ast::FieldType)]> = schema
+        .declarations
+        .iter()
+        .filter_map(|d| match d {
+            ast::Declaration::Interface { ident, fields, .. } => {
+                Some((ident.as_str(), fields.as_slice()))
+            }
+            _ => None,
+        })
+        .collect();
+    let interface_fields: HashSet<(&str, &str)> = schema
+        .declarations
+        .iter()
+        .filter_map(|d| match d {
+            ast::Declaration::Struct {
+                ident, implements, ..
+            } => Some((ident, implements)),
+            _ => None,
+        })
+        .flat_map(|(ident, implements)| {
+            implements
+                .iter()
+                .filter_map(|interface| interfaces.get(interface.as_str()))
+                .flat_map(|fields| fields.iter())
+                .map(move |(field_name, _)| (ident.as_str(), field_name.as_str()))
+        })
+        .collect();
+    let ctx = Context {
+        config,
+        enum_names,
+        enum_defaults,
+        interface_fields,
+        recursive_fields: recursive_fields(schema),
+    };
+
+    let codec = ctx.config.compression.map(|compression| compression.codec);
+
+    if codec == Some(Codec::Zlib) {
+        writeln!(w, "import 'dart:io' show zlib;").unwrap();
+    }
+    if ctx.config.property_tests {
+        writeln!(w, "import 'dart:math' as math;").unwrap();
+    }
+    writeln!(w, "import 'dart:typed_data';").unwrap();
+    w.blank();
+    if codec == Some(Codec::Zstd) {
+        writeln!(w, "import 'package:es_compression/zstd.dart';").unwrap();
+    }
+    if ctx.config.isolate {
+        writeln!(w, "import 'package:flutter/foundation.dart' show compute;").unwrap();
+    }
+    writeln!(w, "import 'package:messagepack/messagepack.dart';").unwrap();
+    // Internal types are marked `@internal`, so the analyzer flags uses outside the package
+    if schema.declarations.iter().any(|decl| match decl {
+        ast::Declaration::Enum { visibility, .. }
+        | ast::Declaration::Struct { visibility, .. }
+        | ast::Declaration::Interface { visibility, .. } => !visibility.is_public(),
+        ast::Declaration::Topic { .. } => false,
+    }) {
+        writeln!(w, "import 'package:meta/meta.dart';").unwrap();
+    }
+
+    generate_helpers(w, &ctx, schema);
+
+    for decl in &schema.declarations {
+        w.blank();
+        match decl {
+            ast::Declaration::Enum {
+                ident,
+                base_type,
+                variants,
+                visibility,
+                annotations,
+            } => generate_enum(
+                w,
+                &ctx,
+                ident,
+                *visibility,
+                base_type,
+                variants,
+                annotations,
+            ),
+            ast::Declaration::Struct {
+                ident,
+                fields,
+                implements,
+                visibility,
+                annotations,
+            } => generate_struct(w, &ctx, ident, implements, *visibility, fields, annotations),
+            ast::Declaration::Interface {
+                ident,
+                fields,
+                visibility,
+                annotations,
+            } => generate_interface(w, &ctx, ident, *visibility, fields, annotations),
+            ast::Declaration::Topic { ident, payload, .. } => {
+                generate_topic(w, &ctx, ident, payload)
+            }
+        }
+    }
+}
+
+fn generate_topic(w: &mut CodeWriter, ctx: &Context, ident: &str, payload: &str) {
+    let dart_name = ctx.config.naming.type_name(payload);
+
+    writeln!(w, "/// The `{ident}` topic").unwrap();
+    writeln!(
+        w,
+        "final {}Topic = Topic<{dart_name}>('{ident}', (message) => message.toBytes(), {dart_name}.fromBytes);",
+        to_ascii_identifier(&to_lower_camel_case(ident))
+    )
+    .unwrap();
+}
+
+fn generate_enum(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    visibility: ast::Visibility,
+    _base_type: &ast::IntegerType,
+    variants: &[(String, ast::IntegerValue)],
+    annotations: &ast::Annotations,
+) {
+    let dart_name = ctx.config.naming.type_name(ident);
+    // Unknown and missing values fall back to the `@default` variant, if there is one
+    let fallback = ctx.enum_defaults.contains(ident).then(|| {
+        let (variant_name, _) = annotations.default_variant(variants);
+        format!(
+            "{dart_name}.{}",
+            ctx.config.naming.variant_name(variant_name)
+        )
+    });
+
+    if !visibility.is_public() {
+        writeln!(w, "@internal").unwrap();
+    }
+    w.block(&format!("enum {dart_name} {{"), "}", |w| {
+        for (i, (variant_name, value)) in variants.iter().enumerate() {
+            let dart_variant = ctx.config.naming.variant_name(variant_name);
+            let trailing = if i < variants.len() - 1 { "," } else { ";" };
+            let actual_value = integer_value_str(value);
+
+            writeln!(w, "{dart_variant}({actual_value}){trailing}").unwrap();
+        }
+
+        w.blank();
+        writeln!(w, "final int value;").unwrap();
+        writeln!(w, "const {dart_name}(this.value);").unwrap();
+
+        if ctx.config.enum_helpers {
+            generate_enum_when(w, ctx, &dart_name, variants);
+        }
+
+        if ctx.config.property_tests {
+            w.blank();
+            writeln!(w, "/// A random value, for property tests").unwrap();
+            writeln!(
+                w,
+                "static {dart_name} arbitrary(math.Random random) => values[random.nextInt(values.length)];"
+            )
+            .unwrap();
+        }
+
+        if ctx.config.roots.contains(ident) {
+            generate_bytes_methods(w, ctx, &dart_name);
+        }
+
+        // _pack
+        w.blank();
+        w.block("void _pack(Packer p) {", "}", |w| {
+            writeln!(w, "p.packInt(value);").unwrap();
+        });
+
+        // _unpack
+        w.blank();
+        w.block(
+            &format!("static {dart_name} _unpack(Unpacker u) {{"),
+            "}",
+            |w| {
+                writeln!(w, "return _fromValue(u.unpackInt());").unwrap();
+            },
+        );
+
+        if ctx.config.wire.protocol().layout == StructLayout::Sequence {
+            // _unpackNullable
+            w.blank();
+            w.block(
+                &format!("static {dart_name}? _unpackNullable(Unpacker u) {{"),
+                "}",
+                |w| {
+                    writeln!(w, "final v = u.unpackInt();").unwrap();
+                    writeln!(w, "return v == null ? null : _fromValue(v);").unwrap();
+                },
+            );
+        }
+
+        // _fromValue
+        w.blank();
+        w.block(
+            &format!("static {dart_name} _fromValue(Object? value) {{"),
+            "}",
+            |w| match &fallback {
+                Some(fallback) => {
+                    writeln!(w, "final v = value as int?;").unwrap();
+                    writeln!(
+                        w,
+                        "return values.firstWhere((e) => e.value == v, orElse: () => {fallback});"
+                    )
+                    .unwrap();
+                }
+                None => {
+                    writeln!(w, "final v = value as int;").unwrap();
+                    writeln!(
+                        w,
+                        "return values.firstWhere((e) => e.value == v, orElse: () => throw FormatException('Unknown {dart_name} value $v'));"
+                    )
+                    .unwrap();
+                }
+            },
+        );
+
+        if ctx.config.json {
+            generate_enum_json_methods(w, &dart_name, variants, fallback.as_deref());
+        }
+    });
+}
+
+/// A `when` method taking one callback per variant, for the `enum_helpers` option.  Adding a
+/// variant to the schema then breaks every call until it is handled.
+fn generate_enum_when(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    dart_name: &str,
+    variants: &[(String, ast::IntegerValue)],
+) {
+    w.blank();
+    writeln!(w, "/// Call the callback for this variant").unwrap();
+    w.line("T when<T>({");
+    w.indent();
+    for (variant_name, _) in variants {
+        writeln!(
+            w,
+            "required T Function() {},",
+            ctx.config.naming.field_name(variant_name)
+        )
+        .unwrap();
+    }
+    w.dedent();
+    w.block("}) => switch (this) {", "};", |w| {
+        for (variant_name, _) in variants {
+            writeln!(
+                w,
+                "{dart_name}.{} => {}(),",
+                ctx.config.naming.variant_name(variant_name),
+                ctx.config.naming.field_name(variant_name)
+            )
+            .unwrap();
+        }
+    });
+}
+
+/// Enums are written to JSON as their schema variant name
+fn generate_enum_json_methods(
+    w: &mut CodeWriter,
+    dart_name: &str,
+    variants: &[(String, ast::IntegerValue)],
+    fallback: Option<&str>,
+) {
+    let names: Vec<String> = variants
+        .iter()
+        .map(|(name, _)| format!("'{name}'"))
+        .collect();
+
+    w.blank();
+    writeln!(w, "static const _jsonNames = [{}];", names.join(", ")).unwrap();
+
+    w.blank();
+    writeln!(w, "String toJson() => _jsonNames[index];").unwrap();
+
+    w.blank();
+    w.block(
+        &format!("static {dart_name} fromJson(Object? json) {{"),
+        "}",
+        |w| match fallback {
+            Some(fallback) => {
+                writeln!(
+                    w,
+                    "final i = json is String ? _jsonNames.indexOf(json) : -1;"
+                )
+                .unwrap();
+                writeln!(w, "return i < 0 ? {fallback} : values[i];").unwrap();
+            }
+            None => {
+                writeln!(w, "final i = _jsonNames.indexOf(json as String);").unwrap();
+                w.block("if (i < 0) {", "}", |w| {
+                    writeln!(
+                        w,
+                        "throw FormatException('Unknown {dart_name} name $json');"
+                    )
+                    .unwrap();
+                });
+                writeln!(w, "return values[i];").unwrap();
+            }
+        },
+    );
+}
+
+/// An abstract class with a getter for each field of the interface
+fn generate_interface(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    visibility: ast::Visibility,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    if !visibility.is_public() {
+        writeln!(w, "@internal").unwrap();
+    }
+    w.block(
+        &format!("abstract class {} {{", ctx.config.naming.type_name(ident)),
+        "}",
+        |w| {
+            for (field_name, field_type) in fields {
+                let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+                writeln!(
+                    w,
+                    "{} get {};",
+                    field_type_str_as(ctx, field_type, int64),
+                    ctx.config.naming.field_name(field_name)
+                )
+                .unwrap();
+            }
+        },
+    );
+}
+
+fn generate_struct(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    implements: &[String],
+    visibility: ast::Visibility,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let dart_name = ctx.config.naming.type_name(ident);
+    let implements_str = if implements.is_empty() {
+        String::new()
+    } else {
+        let names: Vec<String> = implements
+            .iter()
+            .map(|interface| ctx.config.naming.type_name(interface))
+            .collect();
+
+        format!(" implements {}", names.join(", "))
+    };
+
+    if !visibility.is_public() {
+        writeln!(w, "@internal").unwrap();
+    }
+    w.block(&format!("class {dart_name}{implements_str} {{"), "}", |w| {
+        // Fields
+        for (field_name, field_type) in fields {
+            let dart_field = ctx.config.naming.field_name(field_name);
+            let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+            if ctx.interface_fields.contains(&(ident, field_name.as_str())) {
+                writeln!(w, "@override").unwrap();
+            }
+            writeln!(
+                w,
+                "final {} {dart_field};",
+                field_type_str_as(ctx, field_type, int64)
+            )
+            .unwrap();
+        }
+
+        // Constructor, which can be const as every field is final
+        let constructor = if ctx.config.isolate { "const " } else { "" };
+
+        w.blank();
+        w.block(&format!("{constructor}{dart_name}({{"), "});", |w| {
+            for (field_name, field_type) in fields {
+                let dart_field = ctx.config.naming.field_name(field_name);
+                if is_nullable(field_type) {
+                    writeln!(w, "this.{dart_field},").unwrap();
+                } else {
+                    writeln!(w, "required this.{dart_field},").unwrap();
+                }
+            }
+        });
+
+        if ctx.config.property_tests {
+            generate_arbitrary(w, ctx, ident, &dart_name, fields, annotations);
+        }
+        if ctx.config.copy_with {
+            generate_copy_with(w, ctx, &dart_name, fields, annotations);
+        }
+        if ctx.config.equality {
+            generate_equality(w, ctx, &dart_name, fields);
+        }
+        if ctx.config.to_string {
+            generate_to_string(w, ctx, &dart_name, fields, annotations);
+        }
+
+        if ctx.config.roots.contains(ident) {
+            generate_bytes_methods(w, ctx, &dart_name);
+            if ctx.config.isolate {
+                generate_async_methods(w, &dart_name);
+            }
+            if ctx.config.framing {
+                generate_framing_methods(w, &dart_name);
+            }
+            if let Some(envelope) = ctx.config.envelope {
+                generate_envelope_methods(w, &dart_name, envelope);
+            }
+        }
+
+        match ctx.config.wire.protocol().layout {
+            StructLayout::Sequence => {
+                generate_positional_methods(w, ctx, &dart_name, fields, annotations)
+            }
+            StructLayout::Map => generate_keyed_methods(w, ctx, &dart_name, fields, annotations),
+        }
+
+        if ctx.config.json {
+            generate_json_methods(w, ctx, &dart_name, fields, annotations);
+        }
+    });
+}
+
+/// Fields are packed one after the other in declaration order.  In the bitmap wire format the
+/// fields are led by the presence bitmap words, and null fields are left out.
+fn generate_positional_methods(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    dart_name: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let bits = ctx.config.wire.protocol().presence_bits(fields);
+    let word_count = bits
+        .iter()
+        .flatten()
+        .map(|(word, _)| word + 1)
+        .max()
+        .unwrap_or(0);
+
+    // _pack
+    w.blank();
+    w.block("void _pack(Packer p) {", "}", |w| {
+        for word in 0..word_count {
+            let flags: Vec<String> = fields
+                .iter()
+                .zip(&bits)
+                .filter_map(|((field_name, _), bit)| match bit {
+                    Some((n, mask)) if *n == word => Some(format!(
+                        "({} != null ? 0x{mask:x} : 0)",
+                        ctx.config.naming.field_name(field_name)
+                    )),
+                    _ => None,
+                })
+                .collect();
+            writeln!(w, "p.packInt({});", flags.join(" | ")).unwrap();
+        }
+        for ((field_name, field_type), bit) in fields.iter().zip(&bits) {
+            let dart_field = ctx.config.naming.field_name(field_name);
+            let path = format!("{dart_name}.{dart_field}");
+            let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+            if bit.is_some() {
+                w.block(&format!("if ({dart_field} != null) {{"), "}", |w| {
+                    let expr = format!("{dart_field}!");
+                    generate_pack_value(w, ctx, &path, &expr, &non_nullable(field_type), int64);
+                });
+            } else {
+                generate_pack_value(w, ctx, &path, &dart_field, field_type, int64);
+            }
+        }
+    });
+
+    // _unpack
+    w.blank();
+    w.block(
+        &format!("static {dart_name} _unpack(Unpacker u) {{"),
+        "}",
+        |w| {
+            for word in 0..word_count {
+                writeln!(w, "final presence{word} = u.unpackInt()!;").unwrap();
+            }
+            for ((field_name, field_type), bit) in fields.iter().zip(&bits) {
+                let dart_field = ctx.config.naming.field_name(field_name);
+                let path = format!("{dart_name}.{dart_field}");
+                let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+                let expr = match bit {
+                    Some((word, mask)) => format!(
+                        "(presence{word} & 0x{mask:x}) != 0 ? {} : null",
+                        generate_unpack_value(ctx, &path, &non_nullable(field_type), int64)
+                    ),
+                    None => generate_unpack_value(ctx, &path, field_type, int64),
+                };
+                writeln!(w, "final {dart_field} = {expr};").unwrap();
+            }
+            w.block(&format!("return {dart_name}("), ");", |w| {
+                for (field_name, _) in fields {
+                    let dart_field = ctx.config.naming.field_name(field_name);
+                    writeln!(w, "{dart_field}: {dart_field},").unwrap();
+                }
+            });
+        },
+    );
+
+    // _unpackNullable
+    w.blank();
+    w.block(
+        &format!("static {dart_name}? _unpackNullable(Unpacker u) {{"),
+        "}",
+        |w| {
+            writeln!(w, "if (u.unpackBool() == null) return null;").unwrap();
+            writeln!(w, "return _unpack(u);").unwrap();
+        },
+    );
+}
+
+/// Fields are packed as a map from schema field name to value, leaving out null fields.
+/// Unpacking goes through the generic `unpackMap()` so unknown fields are skipped.
+fn generate_keyed_methods(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    dart_name: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let required_count = fields
+        .iter()
+        .filter(|(_, field_type)| !is_nullable(field_type))
+        .count();
+
+    // _pack
+    w.blank();
+    w.block("void _pack(Packer p) {", "}", |w| {
+        let has_nullable = required_count < fields.len();
+
+        if has_nullable {
+            writeln!(w, "var length = {required_count};").unwrap();
+            for (field_name, field_type) in fields {
+                if is_nullable(field_type) {
+                    let dart_field = ctx.config.naming.field_name(field_name);
+                    writeln!(w, "if ({dart_field} != null) length++;").unwrap();
+                }
+            }
+            writeln!(w, "p.packMapLength(length);").unwrap();
+        } else {
+            writeln!(w, "p.packMapLength({required_count});").unwrap();
+        }
+
+        for (field_name, field_type) in fields {
+            let dart_field = ctx.config.naming.field_name(field_name);
+            let path = format!("{dart_name}.{dart_field}");
+            let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+
+            if is_nullable(field_type) {
+                w.block(&format!("if ({dart_field} != null) {{"), "}", |w| {
+                    writeln!(w, "p.packString('{field_name}');").unwrap();
+                    generate_pack_value(
+                        w,
+                        ctx,
+                        &path,
+                        &format!("{dart_field}!"),
+                        &non_nullable(field_type),
+                        int64,
+                    );
+                });
+            } else {
+                writeln!(w, "p.packString('{field_name}');").unwrap();
+                generate_pack_value(w, ctx, &path, &dart_field, field_type, int64);
+            }
+        }
+    });
+
+    // _unpack
+    w.blank();
+    w.block(
+        &format!("static {dart_name} _unpack(Unpacker u) {{"),
+        "}",
+        |w| {
+            writeln!(w, "return _fromValue(u.unpackMap());").unwrap();
+        },
+    );
+
+    // _fromValue
+    w.blank();
+    w.block(
+        &format!("static {dart_name} _fromValue(Object? value) {{"),
+        "}",
+        |w| {
+            writeln!(w, "final m = value as Map;").unwrap();
+            w.block(&format!("return {dart_name}("), ");", |w| {
+                for (field_name, field_type) in fields {
+                    let dart_field = ctx.config.naming.field_name(field_name);
+                    let path = format!("{dart_name}.{dart_field}");
+                    let value = if is_nullable(field_type) || has_fallback(ctx, field_type) {
+                        format!("m['{field_name}']")
+                    } else {
+                        format!("_required(m, '{field_name}', '{dart_name}')")
+                    };
+                    let expr = generate_from_value_field(
+                        ctx,
+                        ValueSource::MessagePack,
+                        &path,
+                        field_type,
+                        &value,
+                        ctx.config.int64.for_field(annotations.member(field_name)),
+                    );
+
+                    writeln!(w, "{dart_field}: {expr},").unwrap();
+                }
+            });
+        },
+    );
+}
+
+/// JSON objects use the schema field names as keys, unless `@json` gives another name, and
+/// `@flatten` fields are inlined into them.  Enums are written as their schema variant names and
+/// map keys are converted to strings.  Null fields are written as `null`.
+fn generate_json_methods(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    dart_name: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    generate_to_json_method(w, ctx, fields, annotations, false);
+    if ctx.config.redaction {
+        generate_to_json_method(w, ctx, fields, annotations, true);
+    }
+
+    // fromJson
+    w.blank();
+    w.block(
+        &format!("static {dart_name} fromJson(Map<String, dynamic> json) {{"),
+        "}",
+        |w| {
+            w.block(&format!("return {dart_name}("), ");", |w| {
+                for (field_name, field_type) in fields {
+                    let dart_field = ctx.config.naming.field_name(field_name);
+                    if annotations.is_flattened(field_name) {
+                        let type_name = ctx.config.naming.type_name(&schema_type_str(field_type));
+                        writeln!(w, "{dart_field}: {type_name}.fromJson(json),").unwrap();
+                        continue;
+                    }
+                    let json_name = dart_json_name(annotations, field_name);
+                    let path = format!("{dart_name}.{dart_field}");
+                    let value = if is_nullable(field_type) || has_fallback(ctx, field_type) {
+                        format!("json['{json_name}']")
+                    } else {
+                        format!("_required(json, '{json_name}', '{dart_name}')")
+                    };
+                    let expr = generate_from_value_field(
+                        ctx,
+                        ValueSource::Json,
+                        &path,
+                        field_type,
+                        &value,
+                        ctx.config.int64.for_field(annotations.member(field_name)),
+                    );
+
+                    writeln!(w, "{dart_field}: {expr},").unwrap();
+                }
+            });
+        },
+    );
+}
+
+/// Write `toJson`, or with `redacted` `toRedactedJson`, which leaves out `@sensitive` and `@pii`
+/// fields and redacts nested structs in turn
+fn generate_to_json_method(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+    redacted: bool,
+) {
+    let method = if redacted { "toRedactedJson" } else { "toJson" };
+
+    w.blank();
+    w.block(&format!("Map<String, dynamic> {method}() {{"), "}", |w| {
+        w.block("return {", "};", |w| {
+            for (field_name, field_type) in fields {
+                if redacted && annotations.is_sensitive(field_name) {
+                    continue;
+                }
+                let dart_field = ctx.config.naming.field_name(field_name);
+                if annotations.is_flattened(field_name) {
+                    writeln!(w, "...{dart_field}.{method}(),").unwrap();
+                    continue;
+                }
+                let json_name = dart_json_name(annotations, field_name);
+                let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+                let expr = generate_to_json_field(ctx, &dart_field, field_type, int64, redacted);
+                writeln!(w, "'{json_name}': {expr},").unwrap();
+            }
+        });
+    });
+}
+
+/// The JSON name of a field as the inside of a Dart string literal
+fn dart_json_name(annotations: &ast::Annotations, field_name: &str) -> String {
+    annotations.json_name(field_name).replace('$', "\\$")
+}
+
+/// Whether a missing value of this type falls back to the `@default` variant of an enum
+fn has_fallback(ctx: &Context, ft: &ast::FieldType) -> bool {
+    matches!(ft, ast::FieldType::UserDefined(name, _) if ctx.enum_defaults.contains(name.as_str()))
+}
+
+/// Private top level helpers, only written when some generated class uses them
+fn generate_helpers(w: &mut CodeWriter, ctx: &Context, schema: &ast::Schema) {
+    if has_topics(schema) {
+        w.blank();
+        writeln!(w, "/// A publish/subscribe topic whose messages are [T]").unwrap();
+        w.block("class Topic<T> {", "}", |w| {
+            writeln!(w, "/// Name of the topic").unwrap();
+            writeln!(w, "final String name;").unwrap();
+            writeln!(w, "final Uint8List Function(T) _encode;").unwrap();
+            writeln!(w, "final T Function(Uint8List) _decode;").unwrap();
+            w.blank();
+            writeln!(w, "const Topic(this.name, this._encode, this._decode);").unwrap();
+            w.blank();
+            writeln!(w, "/// Encode a message to publish on the topic").unwrap();
+            writeln!(w, "Uint8List encode(T message) => _encode(message);").unwrap();
+            w.blank();
+            writeln!(w, "/// Decode a message received from the topic").unwrap();
+            writeln!(w, "T decode(Uint8List bytes) => _decode(bytes);").unwrap();
+        });
+    }
+
+    if has_localized_strings(schema) {
+        generate_localized_string(w);
+    }
+
+    let field_types = || {
+        schema.declarations.iter().flat_map(|decl| match decl {
+            ast::Declaration::Struct { fields, .. } => fields.iter().map(|(_, ft)| ft).collect(),
+            _ => Vec::new(),
+        })
+    };
+
+    if ctx.config.copy_with && field_types().any(is_nullable) {
+        // Distinguishes an omitted `copyWith` argument from an explicit null
+        w.blank();
+        writeln!(w, "const _unset = Object();").unwrap();
+    }
+
+    let layout = ctx.config.wire.protocol().layout;
+    let from_map = layout == StructLayout::Map || ctx.config.json;
+    let nullable_bigint = schema.declarations.iter().any(|decl| match decl {
+        ast::Declaration::Struct {
+            fields,
+            annotations,
+            ..
+        } => fields.iter().any(|(name, ft)| {
+            is_int64(ft)
+                && is_nullable(ft)
+                && ctx.config.int64.for_field(annotations.member(name)) == Int64::BigInt
+        }),
+        _ => false,
+    });
+
+    if layout == StructLayout::Sequence && nullable_bigint {
+        w.blank();
+        writeln!(
+            w,
+            "BigInt? _parseBigInt(String? value) => value == null ? null : BigInt.parse(value);"
+        )
+        .unwrap();
+    }
+
+    if from_map && field_types().any(|ft| !is_nullable(ft)) {
+        w.blank();
+        w.block(
+            "Object _required(Map m, String key, String type) {",
+            "}",
+            |w| {
+                writeln!(w, "final value = m[key];").unwrap();
+                w.block("if (value == null) {", "}", |w| {
+                    writeln!(w, "throw FormatException('$type.$key is missing');").unwrap();
+                });
+                writeln!(w, "return value;").unwrap();
+            },
+        );
+    }
+
+    if from_map && field_types().any(has_fixed_array) {
+        w.blank();
+        w.block(
+            "List<T> _checkFixedList<T>(List<T> list, String path, int expected) {",
+            "}",
+            |w| {
+                w.block("if (list.length != expected) {", "}", |w| {
+                    writeln!(
+                        w,
+                        "throw FormatException('$path must have $expected elements, got ${{list.length}}');"
+                    )
+                    .unwrap();
+                });
+                writeln!(w, "return list;").unwrap();
+            },
+        );
+    }
+
+    if layout == StructLayout::Sequence && field_types().any(has_fixed_array) {
+        w.blank();
+        w.block(
+            "List<T> _unpackFixedList<T>(Unpacker u, String path, int expected, T Function() unpack) {",
+            "}",
+            |w| {
+                writeln!(w, "final length = u.unpackListLength();").unwrap();
+                w.block("if (length != expected) {", "}", |w| {
+                    writeln!(
+                        w,
+                        "throw FormatException('$path must have $expected elements, got $length');"
+                    )
+                    .unwrap();
+                });
+                writeln!(w, "return List.generate(length, (_) => unpack());").unwrap();
+            },
+        );
+    }
+
+    let has_structs = schema
+        .declarations
+        .iter()
+        .any(|decl| matches!(decl, ast::Declaration::Struct { .. }));
+
+    if ctx.config.framing && has_structs {
+        w.blank();
+        w.block("Uint8List _frame(Uint8List bytes) {", "}", |w| {
+            writeln!(w, "final framed = Uint8List(4 + bytes.length);").unwrap();
+            writeln!(
+                w,
+                "ByteData.sublistView(framed).setUint32(0, bytes.length);"
+            )
+            .unwrap();
+            writeln!(w, "framed.setRange(4, framed.length, bytes);").unwrap();
+            writeln!(w, "return framed;").unwrap();
+        });
+
+        w.blank();
+        writeln!(
+            w,
+            "/// Longest message `readDelimitedFrom` accepts unless it is given another `maxFrameLength`"
+        )
+        .unwrap();
+        writeln!(w, "const defaultMaxFrameLength = 8 * 1024 * 1024;").unwrap();
+
+        w.blank();
+        w.block(
+            "Stream<Uint8List> _readFrames(Stream<List<int>> stream, int maxFrameLength) async* {",
+            "}",
+            |w| {
+                writeln!(w, "var pending = Uint8List(0);").unwrap();
+                w.block("await for (final chunk in stream) {", "}", |w| {
+                    writeln!(
+                        w,
+                        "pending = (BytesBuilder(copy: false)..add(pending)..add(chunk)).takeBytes();"
+                    )
+                    .unwrap();
+                    w.block("while (pending.length >= 4) {", "}", |w| {
+                        writeln!(
+                            w,
+                            "final length = ByteData.sublistView(pending).getUint32(0);"
+                        )
+                        .unwrap();
+                        w.block("if (length > maxFrameLength) {", "}", |w| {
+                            writeln!(
+                                w,
+                                "throw FormatException('Frame of $length bytes is longer than the limit of $maxFrameLength');"
+                            )
+                            .unwrap();
+                        });
+                        writeln!(w, "if (pending.length < 4 + length) break;").unwrap();
+                        writeln!(w, "yield Uint8List.sublistView(pending, 4, 4 + length);").unwrap();
+                        writeln!(w, "pending = Uint8List.sublistView(pending, 4 + length);").unwrap();
+                    });
+                });
+                w.block("if (pending.isNotEmpty) {", "}", |w| {
+                    writeln!(w, "throw FormatException('Stream ended inside a message');").unwrap();
+                });
+            },
+        );
+    }
+
+    let has_roots = schema.declarations.iter().any(|decl| match decl {
+        ast::Declaration::Enum { ident, .. } | ast::Declaration::Struct { ident, .. } => {
+            ctx.config.roots.contains(ident)
+        }
+        ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => false,
+    });
+
+    if let Some(compression) = ctx.config.compression.filter(|_| has_roots) {
+        generate_compression_helpers(w, compression);
+    }
+
+    if let Some(envelope) = ctx.config.envelope.filter(|_| has_structs) {
+        generate_envelope_helpers(w, envelope);
+    }
+
+    if ctx.config.property_tests && has_structs {
+        w.blank();
+        w.block(
+            "int _arbitraryInt(math.Random random, int bits, bool signed) {",
+            "}",
+            |w| {
+                writeln!(
+                    w,
+                    "final value = bits > 32 ? (random.nextInt(1 << 32) << 32) | random.nextInt(1 << 32) : random.nextInt(1 << bits);"
+                )
+                .unwrap();
+                writeln!(
+                    w,
+                    "return signed && bits < 64 ? value - (1 << (bits - 1)) : value;"
+                )
+                .unwrap();
+            },
+        );
+
+        w.blank();
+        w.block("String _arbitraryString(math.Random random) {", "}", |w| {
+            writeln!(
+                w,
+                "return String.fromCharCodes(List.generate(random.nextInt(8), (_) => 0x20 + random.nextInt(0x2e0)));"
+            )
+            .unwrap();
+        });
+    }
+
+    if ctx.config.equality && field_types().any(is_collection) {
+        w.blank();
+        w.block("bool _deepEquals(Object? a, Object? b) {", "}", |w| {
+            w.block("if (a is List && b is List) {", "}", |w| {
+                writeln!(w, "if (a.length != b.length) return false;").unwrap();
+                w.block("for (var i = 0; i < a.length; i++) {", "}", |w| {
+                    writeln!(w, "if (!_deepEquals(a[i], b[i])) return false;").unwrap();
+                });
+                writeln!(w, "return true;").unwrap();
+            });
+            w.block("if (a is Map && b is Map) {", "}", |w| {
+                writeln!(w, "if (a.length != b.length) return false;").unwrap();
+                w.block("for (final key in a.keys) {", "}", |w| {
+                    writeln!(
+                        w,
+                        "if (!b.containsKey(key) || !_deepEquals(a[key], b[key])) return false;"
+                    )
+                    .unwrap();
+                });
+                writeln!(w, "return true;").unwrap();
+            });
+            writeln!(w, "return a == b;").unwrap();
+        });
+
+        w.blank();
+        w.block("int _deepHash(Object? o) {", "}", |w| {
+            writeln!(w, "if (o is List) return Object.hashAll(o.map(_deepHash));").unwrap();
+            writeln!(
+                w,
+                "if (o is Map) return Object.hashAllUnordered(o.entries.map((e) => Object.hash(_deepHash(e.key), _deepHash(e.value))));"
+            )
+            .unwrap();
+            writeln!(w, "return o.hashCode;").unwrap();
+        });
+    }
+}
+
+/// A compressed message is a marker that no MessagePack value starts with, the algorithm and the
+/// compressed bytes.  Messages are only compressed when that makes them smaller.
+fn generate_compression_helpers(w: &mut CodeWriter, compression: Compression) {
+    let codec = match compression.codec {
+        Codec::Zlib => "zlib",
+        Codec::Zstd => "zstd",
+    };
+    let marker = Compression::MARKER;
+    let id = compression.codec.id();
+
+    w.blank();
+    writeln!(w, "/// Messages longer than this many bytes are compressed").unwrap();
+    writeln!(w, "const _compressAbove = {};", compression.threshold).unwrap();
+    writeln!(
+        w,
+        "/// Compressed messages that decompress to more than this many bytes are rejected"
+    )
+    .unwrap();
+    writeln!(
+        w,
+        "const _maxDecompressed = {};",
+        compression.max_decompressed
+    )
+    .unwrap();
+
+    w.blank();
+    w.block("Uint8List _compress(Uint8List bytes) {", "}", |w| {
+        writeln!(w, "if (bytes.length <= _compressAbove) return bytes;").unwrap();
+        writeln!(
+            w,
+            "final compressed = Uint8List.fromList([0x{marker:02x}, {id}, ...{codec}.encode(bytes)]);"
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "return compressed.length < bytes.length ? compressed : bytes;"
+        )
+        .unwrap();
+    });
+
+    w.blank();
+    w.block("Uint8List _decompress(Uint8List bytes) {", "}", |w| {
+        writeln!(
+            w,
+            "if (bytes.isEmpty || bytes[0] != 0x{marker:02x}) return bytes;"
+        )
+        .unwrap();
+        w.block(
+            &format!("if (bytes.length < 2 || bytes[1] != {id}) {{"),
+            "}",
+            |w| {
+                writeln!(
+                    w,
+                    "throw FormatException('Message is compressed with an unknown algorithm');"
+                )
+                .unwrap();
+            },
+        );
+        writeln!(w, "final decoded = _LimitedSink();").unwrap();
+        writeln!(
+            w,
+            "{codec}.decoder.startChunkedConversion(decoded)..add(Uint8List.sublistView(bytes, 2))..close();"
+        )
+        .unwrap();
+        writeln!(w, "return decoded.builder.takeBytes();").unwrap();
+    });
+
+    // Decompressed chunks are counted as they arrive, so a message that would decompress to more
+    // than the limit is an error without ever being held in memory
+    w.blank();
+    w.block("class _LimitedSink implements Sink<List<int>> {", "}", |w| {
+        writeln!(w, "final builder = BytesBuilder(copy: false);").unwrap();
+        w.blank();
+        writeln!(w, "@override").unwrap();
+        w.block("void add(List<int> chunk) {", "}", |w| {
+            writeln!(w, "builder.add(chunk);").unwrap();
+            w.block("if (builder.length > _maxDecompressed) {", "}", |w| {
+                writeln!(
+                    w,
+                    "throw FormatException('Message decompresses to more than $_maxDecompressed bytes');"
+                )
+                .unwrap();
+            });
+        });
+        w.blank();
+        writeln!(w, "@override").unwrap();
+        writeln!(w, "void close() {{}}").unwrap();
+    });
+}
+
+/// An envelope is a MessagePack list of the schema fingerprint and the payload as binaries, then
+/// the CRC-32 of the payload if there is a checksum.  Readers check the checksum if there is one.
+fn generate_envelope_helpers(w: &mut CodeWriter, envelope: Envelope) {
+    let bytes: Vec<String> = envelope
+        .fingerprint
+        .iter()
+        .map(|byte| format!("0x{byte:02x}"))
+        .collect();
+
+    w.blank();
+    writeln!(
+        w,
+        "/// The first 8 bytes of the fingerprint of the schema this file was generated from"
+    )
+    .unwrap();
+    writeln!(w, "const schemaFingerprint = [{}];", bytes.join(", ")).unwrap();
+
+    w.blank();
+    w.block(
+        "Uint8List _seal(Uint8List payload, bool checksum) {",
+        "}",
+        |w| {
+            writeln!(w, "final p = Packer();").unwrap();
+            writeln!(w, "p.packListLength(checksum ? 3 : 2);").unwrap();
+            writeln!(w, "p.packBinary(schemaFingerprint);").unwrap();
+            writeln!(w, "p.packBinary(payload);").unwrap();
+            w.block("if (checksum) {", "}", |w| {
+                writeln!(w, "p.packInt(_crc32(payload));").unwrap();
+            });
+            writeln!(w, "return p.takeBytes();").unwrap();
+        },
+    );
+
+    w.blank();
+    w.block("Uint8List _open(Uint8List bytes) {", "}", |w| {
+        writeln!(w, "final u = Unpacker(bytes);").unwrap();
+        writeln!(w, "final length = u.unpackListLength();").unwrap();
+        w.block("if (length != 2 && length != 3) {", "}", |w| {
+            writeln!(
+                w,
+                "throw FormatException('Envelope must have 2 or 3 elements, got $length');"
+            )
+            .unwrap();
+        });
+        writeln!(w, "final fingerprint = _hex(u.unpackBinary());").unwrap();
+        w.block("if (fingerprint != _hex(schemaFingerprint)) {", "}", |w| {
+            writeln!(
+                w,
+                "throw FormatException('Message was written with schema $fingerprint, but this code is for schema ${{_hex(schemaFingerprint)}}');"
+            )
+            .unwrap();
+        });
+        writeln!(w, "final payload = Uint8List.fromList(u.unpackBinary());").unwrap();
+        w.block("if (length == 3) {", "}", |w| {
+            writeln!(w, "final expected = u.unpackInt();").unwrap();
+            writeln!(w, "final found = _crc32(payload);").unwrap();
+            w.block("if (found != expected) {", "}", |w| {
+                writeln!(
+                    w,
+                    "throw FormatException('Message checksum is ${{_hex32(found)}}, but the envelope says ${{_hex32(expected ?? 0)}}');"
+                )
+                .unwrap();
+            });
+        });
+        writeln!(w, "return payload;").unwrap();
+    });
+
+    w.blank();
+    writeln!(
+        w,
+        "String _hex(List<int> bytes) => bytes.map((byte) => byte.toRadixString(16).padLeft(2, '0')).join();"
+    )
+    .unwrap();
+
+    w.blank();
+    writeln!(
+        w,
+        "String _hex32(int value) => value.toRadixString(16).padLeft(8, '0');"
+    )
+    .unwrap();
+
+    // CRC-32 as used by zlib and Ethernet
+    w.blank();
+    w.block("int _crc32(List<int> bytes) {", "}", |w| {
+        writeln!(w, "var crc = 0xffffffff;").unwrap();
+        w.block("for (final byte in bytes) {", "}", |w| {
+            writeln!(w, "crc ^= byte;").unwrap();
+            w.block("for (var i = 0; i < 8; i++) {", "}", |w| {
+                writeln!(
+                    w,
+                    "crc = (crc & 1) != 0 ? (crc >> 1) ^ 0xedb88320 : crc >> 1;"
+                )
+                .unwrap();
+            });
+        });
+        writeln!(w, "return crc ^ 0xffffffff;").unwrap();
+    });
+}
+
+/// The `LocalizedString` class, written once for all `lstring` fields.  It is packed as a map of
+/// locale to text.
+fn generate_localized_string(w: &mut CodeWriter) {
+    w.blank();
+    writeln!(
+        w,
+        "/// Text in several languages, keyed by locale such as `en` or `fr-CA`"
+    )
+    .unwrap();
+    w.block("class LocalizedString {", "}", |w| {
+        writeln!(w, "/// The text for each locale").unwrap();
+        writeln!(w, "final Map<String, String> values;").unwrap();
+        w.blank();
+        writeln!(w, "const LocalizedString([this.values = const {{}}]);").unwrap();
+        w.blank();
+        writeln!(
+            w,
+            "/// The text for [locale], or for its language if there is none, so `fr-CA` falls back to `fr`"
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "String? operator [](String locale) => values[locale] ?? values[locale.split('-').first];"
+        )
+        .unwrap();
+        w.blank();
+        w.block("void _pack(Packer p) {", "}", |w| {
+            writeln!(w, "p.packMapLength(values.length);").unwrap();
+            w.block("for (final e in values.entries) {", "}", |w| {
+                writeln!(w, "p.packString(e.key);").unwrap();
+                writeln!(w, "p.packString(e.value);").unwrap();
+            });
+        });
+        w.blank();
+        w.block("static LocalizedString _unpack(Unpacker u) {", "}", |w| {
+            writeln!(
+                w,
+                "return LocalizedString(Map.fromEntries(List.generate(u.unpackMapLength(), (_) => MapEntry(u.unpackString()!, u.unpackString()!))));"
+            )
+            .unwrap();
+        });
+        w.blank();
+        writeln!(w, "Map<String, String> toJson() => values;").unwrap();
+        w.blank();
+        w.block("static LocalizedString fromJson(Object? json) {", "}", |w| {
+            writeln!(
+                w,
+                "return LocalizedString((json as Map).map((k, v) => MapEntry(k as String, v as String)));"
+            )
+            .unwrap();
+        });
+        w.blank();
+        writeln!(w, "@override").unwrap();
+        w.block("bool operator ==(Object other) {", "}", |w| {
+            writeln!(
+                w,
+                "return other is LocalizedString &&\n    values.length == other.values.length &&\n    values.entries.every((e) => other.values[e.key] == e.value);"
+            )
+            .unwrap();
+        });
+        w.blank();
+        writeln!(w, "@override").unwrap();
+        writeln!(
+            w,
+            "int get hashCode => Object.hashAllUnordered(values.entries.map((e) => Object.hash(e.key, e.value)));"
+        )
+        .unwrap();
+        w.blank();
+        writeln!(w, "@override").unwrap();
+        writeln!(w, "String toString() => values.toString();").unwrap();
+    });
+}
+
+fn has_fixed_array(ft: &ast::FieldType) -> bool {
+    match ft {
+        ast::FieldType::Array(inner, length, _) => length.is_some() || has_fixed_array(inner),
+        ast::FieldType::Map(_, value_type, _) => has_fixed_array(value_type),
+        _ => false,
+    }
+}
+
+fn is_collection(ft: &ast::FieldType) -> bool {
+    matches!(ft, ast::FieldType::Array(..) | ast::FieldType::Map(..))
+}
+
+/// The `arbitrary` factory of a class, which builds random values for property tests.  Fields
+/// that would make it call itself forever are left null or empty.
+fn generate_arbitrary(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    dart_name: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    w.blank();
+    writeln!(w, "/// A random value, for property tests").unwrap();
+    w.block(
+        &format!("static {dart_name} arbitrary(math.Random random) {{"),
+        "}",
+        |w| {
+            if fields.is_empty() {
+                writeln!(w, "return {dart_name}();").unwrap();
+                return;
+            }
+            w.block(&format!("return {dart_name}("), ");", |w| {
+                for (field_name, field_type) in fields {
+                    let dart_field = ctx.config.naming.field_name(field_name);
+                    let value = if ctx.recursive_fields.contains(&(ident, field_name.as_str())) {
+                        empty_value(field_type)
+                    } else {
+                        arbitrary_value(
+                            ctx,
+                            field_type,
+                            ctx.config.int64.for_field(annotations.member(field_name)),
+                        )
+                    };
+
+                    writeln!(w, "{dart_field}: {value},").unwrap();
+                }
+            });
+        },
+    );
+}
+
+/// An expression for a random value of a field type.  Doubles are never NaN, which isn't equal
+/// to itself, and lists and maps have up to 3 entries.
+fn arbitrary_value(ctx: &Context, ft: &ast::FieldType, int64: Int64) -> String {
+    let value = match ft {
+        ast::FieldType::Builtin(bt, _) => match bt {
+            ast::BuiltinType::Integer(it) => {
+                let (bits, signed) = match it {
+                    ast::IntegerType::I8 => (8, true),
+                    ast::IntegerType::I16 => (16, true),
+                    ast::IntegerType::I32 => (32, true),
+                    ast::IntegerType::I64 => (64, true),
+                    ast::IntegerType::U8 => (8, false),
+                    ast::IntegerType::U16 => (16, false),
+                    ast::IntegerType::U32 => (32, false),
+                    ast::IntegerType::U64 => (64, false),
+                };
+                let value = format!("_arbitraryInt(random, {bits}, {signed})");
+
+                match int64 {
+                    Int64::BigInt if is_int64(ft) => format!("BigInt.from({value})"),
+                    Int64::String if is_int64(ft) => format!("{value}.toString()"),
+                    _ => value,
+                }
+            }
+            ast::BuiltinType::Float(_) => "random.nextDouble() * 2000 - 1000".to_string(),
+            ast::BuiltinType::String => "_arbitraryString(random)".to_string(),
+            ast::BuiltinType::LocalizedString => "LocalizedString({if (random.nextBool()) 'en': _arbitraryString(random), if (random.nextBool()) 'fr': _arbitraryString(random)})".to_string(),
+            ast::BuiltinType::Bool => "random.nextBool()".to_string(),
+        },
+        ast::FieldType::UserDefined(name, _) => {
+            format!("{}.arbitrary(random)", ctx.config.naming.type_name(name))
+        }
+        ast::FieldType::Array(inner, length, _) => {
+            let length = match length {
+                Some(length) => length.to_string(),
+                None => "random.nextInt(4)".to_string(),
+            };
+
+            format!(
+                "List.generate({length}, (_) => {})",
+                arbitrary_value(ctx, inner, Int64::Int)
+            )
+        }
+        ast::FieldType::Map(key_type, value_type, _) => format!(
+            "Map.fromEntries(List.generate(random.nextInt(4), (_) => MapEntry({}, {})))",
+            arbitrary_value(ctx, &map_key_field_type(key_type), Int64::Int),
+            arbitrary_value(ctx, value_type, Int64::Int)
+        ),
+    };
+
+    if is_nullable(ft) {
+        format!("random.nextBool() ? null : {value}")
+    } else {
+        value
+    }
+}
+
+/// An expression for a null or empty value of a field type that [recursive_fields] holds
+fn empty_value(ft: &ast::FieldType) -> String {
+    match ft {
+        _ if is_nullable(ft) => "null".to_string(),
+        ast::FieldType::Array(inner, Some(length), _) => {
+            format!("List.generate({length}, (_) => {})", empty_value(inner))
+        }
+        ast::FieldType::Array(..) => "[]".to_string(),
+        ast::FieldType::Map(..) => "{}".to_string(),
+        _ => unreachable!("a field that can't be empty is never left empty"),
+    }
+}
+
+fn generate_copy_with(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    dart_name: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    w.blank();
+
+    if fields.is_empty() {
+        w.block(&format!("{dart_name} copyWith() {{"), "}", |w| {
+            writeln!(w, "return {dart_name}();").unwrap();
+        });
+        return;
+    }
+
+    w.block(&format!("{dart_name} copyWith({{"), "}) {", |w| {
+        for (field_name, field_type) in fields {
+            let dart_field = ctx.config.naming.field_name(field_name);
+            if is_nullable(field_type) {
+                writeln!(w, "Object? {dart_field} = _unset,").unwrap();
+            } else {
+                let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+                let dart_type = field_type_str_as(ctx, field_type, int64);
+                writeln!(w, "{dart_type}? {dart_field},").unwrap();
+            }
+        }
+    });
+    w.indent();
+    w.block(&format!("return {dart_name}("), ");", |w| {
+        for (field_name, field_type) in fields {
+            let dart_field = ctx.config.naming.field_name(field_name);
+            if is_nullable(field_type) {
+                let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+                let dart_type = field_type_str_as(ctx, field_type, int64);
+                writeln!(
+                    w,
+                    "{dart_field}: identical({dart_field}, _unset) ? this.{dart_field} : {dart_field} as {dart_type},"
+                )
+                .unwrap();
+            } else {
+                writeln!(w, "{dart_field}: {dart_field} ?? this.{dart_field},").unwrap();
+            }
+        }
+    });
+    w.dedent();
+    writeln!(w, "}}").unwrap();
+}
+
+fn generate_equality(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    dart_name: &str,
+    fields: &[(String, ast::FieldType)],
+) {
+    // operator ==
+    w.blank();
+    writeln!(w, "@override").unwrap();
+    w.block("bool operator ==(Object other) {", "}", |w| {
+        writeln!(w, "if (identical(this, other)) return true;").unwrap();
+
+        let conditions = std::iter::once(format!("other is {dart_name}"))
+            .chain(fields.iter().map(|(field_name, field_type)| {
+                let dart_field = ctx.config.naming.field_name(field_name);
+                if is_collection(field_type) {
+                    format!("_deepEquals({dart_field}, other.{dart_field})")
+                } else {
+                    format!("{dart_field} == other.{dart_field}")
+                }
+            }))
+            .collect::<Vec<_>>();
+
+        writeln!(w, "return {};", conditions.join(" &&\n    ")).unwrap();
+    });
+
+    // hashCode
+    w.blank();
+    writeln!(w, "@override").unwrap();
+    w.block("int get hashCode {", "}", |w| {
+        let values = fields
+            .iter()
+            .map(|(field_name, field_type)| {
+                let dart_field = ctx.config.naming.field_name(field_name);
+                if is_collection(field_type) {
+                    format!("_deepHash({dart_field})")
+                } else {
+                    dart_field
+                }
+            })
+            .collect::<Vec<_>>();
+
+        writeln!(w, "return Object.hashAll([{}]);", values.join(", ")).unwrap();
+    });
+}
+
+fn generate_to_string(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    dart_name: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let values = fields
+        .iter()
+        .map(|(field_name, _)| {
+            let dart_field = ctx.config.naming.field_name(field_name);
+
+            if ctx.config.mask_sensitive && annotations.is_sensitive(field_name) {
+                format!("{dart_field}: ***")
+            } else {
+                format!("{dart_field}: ${dart_field}")
+            }
+        })
+        .collect::<Vec<_>>();
+
+    w.blank();
+    writeln!(w, "@override").unwrap();
+    w.block("String toString() {", "}", |w| {
+        writeln!(w, "return '{dart_name}({})';", values.join(", ")).unwrap();
+    });
+}
+
+/// The public `toBytes` and `fromBytes` methods shared by enums and classes
+fn generate_bytes_methods(w: &mut CodeWriter, ctx: &Context, dart_name: &str) {
+    let compressed = ctx.config.compression.is_some();
+
+    // toBytes
+    w.blank();
+    w.block("Uint8List toBytes() {", "}", |w| {
+        writeln!(w, "final p = Packer();").unwrap();
+        writeln!(w, "_pack(p);").unwrap();
+        if compressed {
+            writeln!(w, "return _compress(p.takeBytes());").unwrap();
+        } else {
+            writeln!(w, "return p.takeBytes();").unwrap();
+        }
+    });
+
+    // fromBytes
+    w.blank();
+    w.block(
+        &format!("static {dart_name} fromBytes(Uint8List bytes) {{"),
+        "}",
+        |w| {
+            if compressed {
+                writeln!(w, "return _unpack(Unpacker(_decompress(bytes)));").unwrap();
+            } else {
+                writeln!(w, "return _unpack(Unpacker(bytes));").unwrap();
+            }
+        },
+    );
+}
+
+/// Large messages are decoded in a background isolate so they don't hold up the UI
+fn generate_async_methods(w: &mut CodeWriter, dart_name: &str) {
+    // fromBytesAsync
+    w.blank();
+    w.block(
+        &format!("static Future<{dart_name}> fromBytesAsync(Uint8List bytes) {{"),
+        "}",
+        |w| {
+            writeln!(w, "return compute(fromBytes, bytes);").unwrap();
+        },
+    );
+}
+
+/// Messages are framed with a 4 byte big-endian length prefix
+fn generate_framing_methods(w: &mut CodeWriter, dart_name: &str) {
+    // writeDelimitedTo
+    w.blank();
+    w.block("void writeDelimitedTo(Sink<List<int>> sink) {", "}", |w| {
+        writeln!(w, "sink.add(_frame(toBytes()));").unwrap();
+    });
+
+    // readDelimitedFrom
+    w.blank();
+    w.block(
+        &format!("static Stream<{dart_name}> readDelimitedFrom(Stream<List<int>> stream, {{int maxFrameLength = defaultMaxFrameLength}}) {{"),
+        "}",
+        |w| {
+            writeln!(w, "return _readFrames(stream, maxFrameLength).map(fromBytes);").unwrap();
+        },
+    );
+}
+
+/// Messages are wrapped in an envelope with the schema fingerprint
+fn generate_envelope_methods(w: &mut CodeWriter, dart_name: &str, envelope: Envelope) {
+    // toEnvelope
+    w.blank();
+    w.block("Uint8List toEnvelope() {", "}", |w| {
+        writeln!(w, "return _seal(toBytes(), {});", envelope.checksum).unwrap();
+    });
+
+    // fromEnvelope
+    w.blank();
+    w.block(
+        &format!("static {dart_name} fromEnvelope(Uint8List bytes) {{"),
+        "}",
+        |w| {
+            writeln!(w, "return fromBytes(_open(bytes));").unwrap();
+        },
+    );
+}
+
+/// Write statements that pack a struct field, with a 64-bit integer as `int64` asks
+fn generate_pack_value(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    path: &str,
+    expr: &str,
+    ft: &ast::FieldType,
+    int64: Int64,
+) {
+    if !(is_int64(ft) && int64.is_string()) {
+        generate_pack_field(w, ctx, path, expr, ft, 0);
+        return;
+    }
+
+    let to_string = if int64 == Int64::BigInt {
+        ".toString()"
+    } else {
+        ""
+    };
+
+    if is_nullable(ft) {
+        generate_nullable_pack(w, expr, |w| {
+            writeln!(w, "p.packString({expr}!{to_string});").unwrap();
+        });
+    } else {
+        writeln!(w, "p.packString({expr}{to_string});").unwrap();
+    }
+}
+
+/// An expression that unpacks a struct field, with a 64-bit integer as `int64` asks
+fn generate_unpack_value(ctx: &Context, path: &str, ft: &ast::FieldType, int64: Int64) -> String {
+    match (int64, is_nullable(ft)) {
+        (Int64::BigInt, false) if is_int64(ft) => "BigInt.parse(u.unpackString()!)".to_string(),
+        (Int64::BigInt, true) if is_int64(ft) => "_parseBigInt(u.unpackString())".to_string(),
+        (Int64::String, false) if is_int64(ft) => "u.unpackString()!".to_string(),
+        (Int64::String, true) if is_int64(ft) => "u.unpackString()".to_string(),
+        _ => generate_unpack_expr(ctx, path, ft),
+    }
+}
+
+/// Write `if (expr != null) { <present> } else { p.packNull(); }`
+fn generate_nullable_pack(w: &mut CodeWriter, expr: &str, present: impl FnOnce(&mut CodeWriter)) {
+    w.block(&format!("if ({expr} != null) {{"), "} else {", present);
+    w.indent();
+    writeln!(w, "p.packNull();").unwrap();
+    w.dedent();
+    writeln!(w, "}}").unwrap();
+}
+
+fn generate_pack_field(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    path: &str,
+    expr: &str,
+    ft: &ast::FieldType,
+    depth: usize,
+) {
+    let marker = ctx
+        .config
+        .wire
+        .protocol()
+        .has_presence_marker(ft, |name| ctx.enum_names.contains(name));
+
+    match ft {
+        ast::FieldType::Builtin(ast::BuiltinType::LocalizedString, nullable) => {
+            if *nullable {
+                generate_nullable_pack(w, expr, |w| {
+                    if marker {
+                        writeln!(w, "p.packBool(true);").unwrap();
+                    }
+                    writeln!(w, "{expr}!._pack(p);").unwrap();
+                });
+            } else {
+                writeln!(w, "{expr}._pack(p);").unwrap();
+            }
+        }
+        ast::FieldType::Builtin(bt, nullable) => {
+            let method = builtin_pack_method(bt);
+            if *nullable {
+                generate_nullable_pack(w, expr, |w| {
+                    writeln!(w, "p.{method}({expr}!);").unwrap();
+                });
+            } else {
+                writeln!(w, "p.{method}({expr});").unwrap();
+            }
+        }
+        ast::FieldType::UserDefined(_, nullable) => {
+            if *nullable {
+                generate_nullable_pack(w, expr, |w| {
+                    if marker {
+                        writeln!(w, "p.packBool(true);").unwrap();
+                    }
+                    writeln!(w, "{expr}!._pack(p);").unwrap();
+                });
+            } else {
+                writeln!(w, "{expr}._pack(p);").unwrap();
+            }
+        }
+        ast::FieldType::Array(inner, length, nullable) => {
+            let var = format!("e{depth}");
+            let pack_list = |w: &mut CodeWriter, src: &str| {
+                if let Some(len) = length {
+                    w.block(&format!("if ({src}.length != {len}) {{"), "}", |w| {
+                        writeln!(
+                            w,
+                            "throw ArgumentError('{path} must have {len} elements, got ${{{src}.length}}');"
+                        )
+                        .unwrap();
+                    });
+                }
+                writeln!(w, "p.packListLength({src}.length);").unwrap();
+                w.block(&format!("for (final {var} in {src}) {{"), "}", |w| {
+                    generate_pack_field(w, ctx, path, &var, inner, depth + 1);
+                });
+            };
+            if *nullable {
+                generate_nullable_pack(w, expr, |w| {
+                    if marker {
+                        writeln!(w, "p.packBool(true);").unwrap();
+                    }
+                    pack_list(w, &format!("{expr}!"));
+                });
+            } else {
+                pack_list(w, expr);
+            }
+        }
+        ast::FieldType::Map(key_type, value_type, nullable) => {
+            let var = format!("e{depth}");
+            let key_type = map_key_field_type(key_type);
+            let pack_map = |w: &mut CodeWriter, src: &str| {
+                writeln!(w, "p.packMapLength({src}.length);").unwrap();
+                w.block(
+                    &format!("for (final {var} in {src}.entries) {{"),
+                    "}",
+                    |w| {
+                        generate_pack_field(
+                            w,
+                            ctx,
+                            path,
+                            &format!("{var}.key"),
+                            &key_type,
+                            depth + 1,
+                        );
+                        generate_pack_field(
+                            w,
+                            ctx,
+                            path,
+                            &format!("{var}.value"),
+                            value_type,
+                            depth + 1,
+                        );
+                    },
+                );
+            };
+            if *nullable {
+                generate_nullable_pack(w, expr, |w| {
+                    if marker {
+                        writeln!(w, "p.packBool(true);").unwrap();
+                    }
+                    pack_map(w, &format!("{expr}!"));
+                });
+            } else {
+                pack_map(w, expr);
+            }
+        }
+    }
+}
+
+fn generate_unpack_expr(ctx: &Context, path: &str, ft: &ast::FieldType) -> String {
+    match ft {
+        ast::FieldType::Builtin(ast::BuiltinType::LocalizedString, nullable) => {
+            if *nullable {
+                "u.unpackBool() == null ? null : LocalizedString._unpack(u)".to_string()
+            } else {
+                "LocalizedString._unpack(u)".to_string()
+            }
+        }
+        ast::FieldType::Builtin(bt, nullable) => {
+            let method = builtin_unpack_method(bt);
+            if *nullable {
+                format!("u.{method}()")
+            } else {
+                format!("u.{method}()!")
+            }
+        }
+        ast::FieldType::UserDefined(name, nullable) => {
+            let dart_name = ctx.config.naming.type_name(name);
+            if *nullable {
+                format!("{dart_name}._unpackNullable(u)")
+            } else {
+                format!("{dart_name}._unpack(u)")
+            }
+        }
+        ast::FieldType::Array(inner, length, nullable) => {
+            let inner_expr = generate_unpack_expr(ctx, path, inner);
+            let base = match length {
+                Some(len) => format!("_unpackFixedList(u, '{path}', {len}, () => {inner_expr})"),
+                None => format!("List.generate(u.unpackListLength(), (_) => {inner_expr})"),
+            };
+            if *nullable {
+                format!("u.unpackBool() == null ? null : {base}")
+            } else {
+                base
+            }
+        }
+        ast::FieldType::Map(key_type, value_type, nullable) => {
+            let key_expr = generate_unpack_expr(ctx, path, &map_key_field_type(key_type));
+            let value_expr = generate_unpack_expr(ctx, path, value_type);
+            let base = format!(
+                "Map.fromEntries(List.generate(u.unpackMapLength(), (_) => MapEntry({key_expr}, {value_expr})))"
+            );
+            if *nullable {
+                format!("u.unpackBool() == null ? null : {base}")
+            } else {
+                base
+            }
+        }
+    }
+}
+
+/// Where a generic value passed to [generate_from_value_expr] came from
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ValueSource {
+    /// `unpackMap()`, with enums as ints
+    MessagePack,
+    /// `jsonDecode()`, with enums as names and map keys as strings
+    Json,
+}
+
+/// Write an expression converting a field to a value that `jsonEncode()` accepts.  With
+/// `redacted` structs are converted with `toRedactedJson()`.
+fn generate_to_json_expr(
+    ctx: &Context,
+    expr: &str,
+    ft: &ast::FieldType,
+    depth: usize,
+    redacted: bool,
+) -> String {
+    let access = if is_nullable(ft) { "?." } else { "." };
+
+    match ft {
+        ast::FieldType::UserDefined(name, _)
+            if redacted && !ctx.enum_names.contains(name.as_str()) =>
+        {
+            format!("{expr}{access}toRedactedJson()")
+        }
+        ast::FieldType::Builtin(ast::BuiltinType::LocalizedString, _)
+        | ast::FieldType::UserDefined(..) => format!("{expr}{access}toJson()"),
+        ast::FieldType::Builtin(..) => expr.to_string(),
+        ast::FieldType::Array(inner, _, _) => {
+            if is_scalar(inner) {
+                return expr.to_string();
+            }
+            let var = format!("e{depth}");
+            let inner_expr = generate_to_json_expr(ctx, &var, inner, depth + 1, redacted);
+            format!("{expr}{access}map(({var}) => {inner_expr}).toList()")
+        }
+        ast::FieldType::Map(key_type, value_type, _) => {
+            let key_var = format!("k{depth}");
+            let value_var = format!("v{depth}");
+            let key_expr = match key_type {
+                ast::MapKeyType::Builtin(ast::BuiltinType::String) => key_var.clone(),
+                ast::MapKeyType::Builtin(_) => format!("{key_var}.toString()"),
+                ast::MapKeyType::UserDefined(_) => format!("{key_var}.toJson()"),
+            };
+            let value_expr =
+                generate_to_json_expr(ctx, &value_var, value_type, depth + 1, redacted);
+            format!(
+                "{expr}{access}map(({key_var}, {value_var}) => MapEntry({key_expr}, {value_expr}))"
+            )
+        }
+    }
+}
+
+/// Write an expression converting a struct field for `jsonEncode()`, with a 64-bit integer as
+/// `int64` asks.  Integers packed as strings are written to JSON as strings too.
+fn generate_to_json_field(
+    ctx: &Context,
+    expr: &str,
+    ft: &ast::FieldType,
+    int64: Int64,
+    redacted: bool,
+) -> String {
+    match (int64, is_nullable(ft)) {
+        (Int64::BigInt, false) if is_int64(ft) => format!("{expr}.toString()"),
+        (Int64::BigInt, true) if is_int64(ft) => format!("{expr}?.toString()"),
+        _ => generate_to_json_expr(ctx, expr, ft, 0, redacted),
+    }
+}
+
+/// Convert a value from `unpackMap()` or `jsonDecode()` to a struct field, with a 64-bit integer
+/// as `int64` asks
+fn generate_from_value_field(
+    ctx: &Context,
+    source: ValueSource,
+    path: &str,
+    ft: &ast::FieldType,
+    value: &str,
+    int64: Int64,
+) -> String {
+    match (int64, is_nullable(ft)) {
+        (Int64::BigInt, false) if is_int64(ft) => format!("BigInt.parse({value} as String)"),
+        (Int64::BigInt, true) if is_int64(ft) => {
+            format!("{value} == null ? null : BigInt.parse({value} as String)")
+        }
+        (Int64::String, false) if is_int64(ft) => format!("{value} as String"),
+        (Int64::String, true) if is_int64(ft) => format!("{value} as String?"),
+        _ => generate_from_value_expr(ctx, source, path, ft, value, 0),
+    }
+}
+
+/// Convert a value from `unpackMap()` or `jsonDecode()` to a field type.  `value` must be free
+/// of side effects because nullable types evaluate it twice.
+fn generate_from_value_expr(
+    ctx: &Context,
+    source: ValueSource,
+    path: &str,
+    ft: &ast::FieldType,
+    value: &str,
+    depth: usize,
+) -> String {
+    let nullable = is_nullable(ft);
+    let base = match ft {
+        ast::FieldType::Builtin(bt, _) => {
+            return match (bt, nullable) {
+                (ast::BuiltinType::LocalizedString, false) => {
+                    format!("LocalizedString.fromJson({value})")
+                }
+                (ast::BuiltinType::LocalizedString, true) => {
+                    format!("{value} == null ? null : LocalizedString.fromJson({value})")
+                }
+                (ast::BuiltinType::Float(_), false) => format!("({value} as num).toDouble()"),
+                (ast::BuiltinType::Float(_), true) => format!("({value} as num?)?.toDouble()"),
+                (_, false) => format!("{value} as {}", builtin_type_str(bt)),
+                (_, true) => format!("{value} as {}?", builtin_type_str(bt)),
+            };
+        }
+        ast::FieldType::UserDefined(name, _) => {
+            let type_name = ctx.config.naming.type_name(name);
+            match source {
+                ValueSource::MessagePack => format!("{type_name}._fromValue({value})"),
+                ValueSource::Json if ctx.enum_names.contains(name.as_str()) => {
+                    format!("{type_name}.fromJson({value})")
+                }
+                ValueSource::Json => {
+                    format!("{type_name}.fromJson({value} as Map<String, dynamic>)")
+                }
+            }
+        }
+        ast::FieldType::Array(inner, length, _) => {
+            let var = format!("e{depth}");
+            let inner_expr = generate_from_value_expr(ctx, source, path, inner, &var, depth + 1);
+            let list = format!("({value} as List).map(({var}) => {inner_expr}).toList()");
+            match length {
+                Some(len) => format!("_checkFixedList({list}, '{path}', {len})"),
+                None => list,
+            }
+        }
+        ast::FieldType::Map(key_type, value_type, _) => {
+            let key_var = format!("k{depth}");
+            let value_var = format!("v{depth}");
+            let key_expr = match (source, key_type) {
+                (ValueSource::Json, ast::MapKeyType::Builtin(ast::BuiltinType::Integer(_))) => {
+                    format!("int.parse({key_var} as String)")
+                }
+                (ValueSource::Json, ast::MapKeyType::Builtin(ast::BuiltinType::Bool)) => {
+                    format!("{key_var} == 'true'")
+                }
+                _ => generate_from_value_expr(
+                    ctx,
+                    source,
+                    path,
+                    &map_key_field_type(key_type),
+                    &key_var,
+                    depth + 1,
+                ),
+            };
+            let value_expr =
+                generate_from_value_expr(ctx, source, path, value_type, &value_var, depth + 1);
+            format!(
+                "({value} as Map).map(({key_var}, {value_var}) => MapEntry({key_expr}, {value_expr}))"
+            )
+        }
+    };
+
+    if nullable {
+        format!("{value} == null ? null : {base}")
+    } else {
+        base
+    }
+}
+
+/// The Dart type used for a field type, with the default naming conventions
+pub fn field_type_str(ft: &ast::FieldType) -> String {
+    type_str(ft, &DEFAULT_NAMING)
+}
+
+fn type_str(ft: &ast::FieldType, naming: &Naming) -> String {
+    match ft {
+        ast::FieldType::Builtin(bt, nullable) => {
+            let base = builtin_type_str(bt);
+            if *nullable { format!("{base}?") } else { base }
+        }
+        ast::FieldType::UserDefined(name, nullable) => {
+            let dart_name = naming.type_name(name);
+            if *nullable {
+                format!("{dart_name}?")
+            } else {
+                dart_name
+            }
+        }
+        ast::FieldType::Array(inner, _length, nullable) => {
+            let inner_str = type_str(inner, naming);
+            let base = format!("List<{inner_str}>");
+            if *nullable { format!("{base}?") } else { base }
+        }
+        ast::FieldType::Map(key_type, value_type, nullable) => {
+            let key_str = type_str(&map_key_field_type(key_type), naming);
+            let value_str = type_str(value_type, naming);
+            let base = format!("Map<{key_str}, {value_str}>");
+            if *nullable { format!("{base}?") } else { base }
+        }
+    }
+}
+
+/// The Dart type of a struct field, with a 64-bit integer as `int64` asks
+fn field_type_str_as(ctx: &Context, ft: &ast::FieldType, int64: Int64) -> String {
+    let base = match int64 {
+        Int64::BigInt if is_int64(ft) => "BigInt",
+        Int64::String if is_int64(ft) => "String",
+        _ => return type_str(ft, &ctx.config.naming),
+    };
+
+    if is_nullable(ft) {
+        format!("{base}?")
+    } else {
+        base.to_string()
+    }
+}
+
+fn builtin_type_str(bt: &ast::BuiltinType) -> String {
+    match bt {
+        ast::BuiltinType::Integer(_) => "int".to_string(),
+        ast::BuiltinType::Float(_) => "double".to_string(),
+        ast::BuiltinType::String => "String".to_string(),
+        ast::BuiltinType::LocalizedString => "LocalizedString".to_string(),
+        ast::BuiltinType::Bool => "bool".to_string(),
+    }
+}
+
+fn builtin_pack_method(bt: &ast::BuiltinType) -> &'static str {
+    match bt {
+        ast::BuiltinType::Integer(_) => "packInt",
+        ast::BuiltinType::Float(_) => "packDouble",
+        ast::BuiltinType::String => "packString",
+        ast::BuiltinType::LocalizedString => unreachable!("lstring is packed by LocalizedString"),
+        ast::BuiltinType::Bool => "packBool",
+    }
+}
+
+fn builtin_unpack_method(bt: &ast::BuiltinType) -> &'static str {
+    match bt {
+        ast::BuiltinType::Integer(_) => "unpackInt",
+        ast::BuiltinType::Float(_) => "unpackDouble",
+        ast::BuiltinType::String => "unpackString",
+        ast::BuiltinType::LocalizedString => unreachable!("lstring is unpacked by LocalizedString"),
+        ast::BuiltinType::Bool => "unpackBool",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> ast::Schema {
+        ast::Schema {
+            metadata: HashMap::new(),
+            declarations: vec![ast::Declaration::Struct {
+                ident: "point".to_string(),
+                fields: vec![
+                    (
+                        "x".to_string(),
+                        ast::FieldType::Builtin(ast::BuiltinType::String, false),
+                    ),
+                    (
+                        "tags".to_string(),
+                        ast::FieldType::Array(
+                            Box::new(ast::FieldType::Builtin(ast::BuiltinType::String, false)),
+                            None,
+                            true,
+                        ),
+                    ),
+                ],
+                implements: Vec::new(),
+                visibility: Default::default(),
+                annotations: Default::default(),
+            }],
+        }
+    }
+
+    fn generate_with(options: &[&str]) -> String {
+        let config = Config::from_options(&schema(), &Options::parse(options).unwrap()).unwrap();
+
+        generate(&schema(), config)
+    }
+
+    #[test]
+    fn value_methods() {
+        let output = generate_with(&[]);
+
+        assert!(output.contains("const _unset = Object();"));
+        assert!(output.contains("bool _deepEquals(Object? a, Object? b) {"));
+        assert!(output.contains("Object? tags = _unset,"));
+        assert!(output.contains("x: x ?? this.x,"));
+        assert!(output.contains("return other is Point &&\n        x == other.x &&\n        _deepEquals(tags, other.tags);"));
+        assert!(output.contains("return Object.hashAll([x, _deepHash(tags)]);"));
+        assert!(output.contains("return 'Point(x: $x, tags: $tags)';"));
+    }
+
+    #[test]
+    fn value_methods_disabled() {
+        let output = generate_with(&["equality=false", "to_string=false", "copy_with=false"]);
+
+        assert!(!output.contains("_unset"));
+        assert!(!output.contains("_deepEquals"));
+        assert!(!output.contains("operator =="));
+        assert!(!output.contains("toString"));
+        assert!(!output.contains("copyWith"));
+        assert!(matches!(
+            Config::from_options(&schema(), &Options::parse(["equality=yes"]).unwrap()),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn fixed_length_lists() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } struct rgb { c: [u8; 3]? }")
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(output.contains(
+            "if (c!.length != 3) {\n        throw ArgumentError('Rgb.c must have 3 elements, got ${c!.length}');"
+        ));
+        assert!(output.contains("_unpackFixedList(u, 'Rgb.c', 3, () => u.unpackInt()!)"));
+        assert!(output.contains("List<T> _unpackFixedList<T>("));
+    }
+
+    #[test]
+    fn topics() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct user_event { id: u64 } topic user_events: user_event;",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(output.contains("class Topic<T> {"));
+        assert!(output.contains(
+            "final userEventsTopic = Topic<UserEvent>('user_events', (message) => message.toBytes(), UserEvent.fromBytes);"
+        ));
+    }
+
+    #[test]
+    fn interfaces() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } internal interface named { name: string, big: i64? } interface aged { age: u8 }
+                struct user: named, aged { id: u64, name: string, big: i64?, age: u8 }",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(
+            output.contains(
+                "@internal\nabstract class Named {\n  String get name;\n  int? get big;\n}"
+            )
+        );
+        assert!(output.contains("class User implements Named, Aged {\n  final int id;\n  @override\n  final String name;"));
+        assert!(output.contains("  @override\n  final int age;"));
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct größe { straße: string } topic größen: größe;",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(output.contains("class Gru00f6u00dfe {"));
+        assert!(output.contains("final String strau00dfe;"));
+        assert!(output.contains("final gru00f6u00dfenTopic = Topic<Gru00f6u00dfe>('größen',"));
+        assert!(!output.contains("straße;"));
+    }
+
+    #[test]
+    fn roots_only() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum kind: u8 { a = 1 } @root struct order { k: kind } struct part { k: kind }",
+            )
+            .unwrap();
+        let options = Options::parse(["roots_only=true"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert_eq!(output.matches("Uint8List toBytes()").count(), 1);
+        assert!(output.contains("static Order fromBytes(Uint8List bytes) {"));
+        assert!(!output.contains("static Part fromBytes(Uint8List bytes) {"));
+    }
+
+    #[test]
+    fn sensitive_fields() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } struct user { id: u64, @pii email: string }")
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(output.contains("return 'User(id: $id, email: ***)';"));
+
+        let options = Options::parse(["mask_sensitive=false"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert!(output.contains("return 'User(id: $id, email: $email)';"));
+    }
+
+    #[test]
+    fn redaction() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum color: u8 { red = 1 } struct user { id: i64, @pii email: string, c: color, home: address? } struct address { @sensitive street: string, city: string }",
+            )
+            .unwrap();
+        let options = Options::parse(["json=true", "redaction=true"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+        let redacted = &output[output.find("toRedactedJson() {").unwrap()..];
+        let redacted = &redacted[..redacted.find("};").unwrap()];
+
+        assert!(redacted.contains("'id': id,"));
+        assert!(!redacted.contains("email"));
+        assert!(redacted.contains("'c': c.toJson(),"));
+        assert!(redacted.contains("'home': home?.toRedactedJson(),"));
+        assert!(output.contains("'street': street,"));
+        assert_eq!(output.matches("toRedactedJson() {").count(), 2);
+
+        let options = Options::parse(["redaction=true"]).unwrap();
+
+        assert!(matches!(
+            Config::from_options(&schema, &options),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn enum_helpers() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } enum color: u8 { red = 1, dark_green = 2 }")
+            .unwrap();
+        let options = Options::parse(["enum_helpers=true"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert!(output.contains(
+            "  T when<T>({\n    required T Function() red,\n    required T Function() darkGreen,\n  }) => switch (this) {\n    Color.red => red(),\n    Color.darkGreen => darkGreen(),\n  };"
+        ));
+    }
+
+    #[test]
+    fn target_fields() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1, wire = \"keyed\" } struct user { id: u64, @only(rust) password: string?, @exclude(rust) seen: bool? }",
+            )
+            .unwrap();
+        let output = &DartMpGenerator.generate(&schema, &Options::new()).unwrap()[0];
+        let output = String::from_utf8(output.contents.clone()).unwrap();
+
+        assert!(!output.contains("password"));
+        assert!(output.contains("final bool? seen;"));
+    }
+
+    #[test]
+    fn map_keys() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum color: u8 { red = 1 } struct palette { m: { color: bool }, n: { i32: string } }",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(output.contains("final Map<Color, bool> m;"));
+        assert!(output.contains("e0.key._pack(p);"));
+        assert!(output.contains("MapEntry(Color._unpack(u), u.unpackBool()!)"));
+        assert!(output.contains("p.packInt(e0.key);"));
+        assert!(output.contains("MapEntry(u.unpackInt()!, u.unpackString()!)"));
+    }
+
+    #[test]
+    fn keyed_wire_format() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1, wire = \"keyed\" } struct point { x_pos: i32, tags: [string]? }",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(output.contains("if (tags != null) length++;"));
+        assert!(output.contains("p.packString('x_pos');"));
+        assert!(output.contains("return _fromValue(u.unpackMap());"));
+        assert!(output.contains("_required(m, 'x_pos', 'Point')"));
+        assert!(!output.contains("p.packBool(true);"));
+
+        let options = Options::parse(["wire=positional"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert!(!output.contains("u.unpackMap()"));
+        assert!(matches!(
+            Config::from_options(&schema, &Options::parse(["wire=json"]).unwrap()),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn bitmap_wire_format() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1, wire = \"bitmap\" } struct point { x: i32, tags: [string?]?, label: string? }",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(
+            output.contains("p.packInt((tags != null ? 0x1 : 0) | (label != null ? 0x2 : 0));")
+        );
+        assert!(output.contains("final presence0 = u.unpackInt()!;"));
+        assert!(
+            output.contains("final label = (presence0 & 0x2) != 0 ? u.unpackString()! : null;")
+        );
+        assert!(!output.contains("p.packBool(true);"));
+        assert!(output.contains("p.packNull();"));
+    }
+
+    #[test]
+    fn json_methods() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum color: u8 { dark_red = 1 } struct point { x_pos: i32, c: color?, m: { i32: color } }",
+            )
+            .unwrap();
+        let options = Options::parse(["json=true"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert!(output.contains("static const _jsonNames = ['dark_red'];"));
+        assert!(output.contains("'x_pos': xPos,"));
+        assert!(output.contains("'c': c?.toJson(),"));
+        assert!(output.contains("'m': m.map((k0, v0) => MapEntry(k0.toString(), v0.toJson())),"));
+        assert!(output.contains("xPos: _required(json, 'x_pos', 'Point') as int,"));
+        assert!(output.contains("MapEntry(int.parse(k0 as String), Color.fromJson(v0))"));
+
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(!output.contains("toJson"));
+        assert!(!output.contains("_required"));
+
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1, wire = \"keyed\" } struct point { @json(name = \"$x-pos\") x_pos: i32, @flatten tag: tag } struct tag { label: string }",
+            )
+            .unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert!(output.contains("'\\$x-pos': xPos,"));
+        assert!(output.contains("xPos: _required(json, '\\$x-pos', 'Point') as int,"));
+        assert!(output.contains("p.packString('x_pos');"));
+        assert!(output.contains("...tag.toJson(),"));
+        assert!(output.contains("tag: Tag.fromJson(json),"));
+    }
+
+    #[test]
+    fn default_variants() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1, wire = \"keyed\" } enum status: i8 { active = 1, @default unknown = -1 } enum color { red = 1 } struct user { s: status, c: color }",
+            )
+            .unwrap();
+        let options = Options::parse(["json=true"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert!(output.contains(
+            "return values.firstWhere((e) => e.value == v, orElse: () => Status.unknown);"
+        ));
+        assert!(output.contains("return i < 0 ? Status.unknown : values[i];"));
+        assert!(output.contains("s: Status._fromValue(m['s']),"));
+        assert!(output.contains("s: Status.fromJson(json['s']),"));
+        assert!(output.contains("c: Color._fromValue(_required(m, 'c', 'User')),"));
+        assert!(output.contains("throw FormatException('Unknown Color value $v')"));
+    }
+
+    #[test]
+    fn internal_types() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } internal enum kind: u8 { a = 1 } internal struct entry { k: kind }",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::new()).unwrap(),
+        );
+
+        assert!(output.contains("import 'package:meta/meta.dart';"));
+        assert!(output.contains("@internal\nenum Kind {"));
+        assert!(output.contains("@internal\nclass Entry {"));
+        assert!(!generate_with(&[]).contains("package:meta"));
+    }
+
+    #[test]
+    fn framing_methods() {
+        let output = generate_with(&["framing=true"]);
+
+        assert!(output.contains("void writeDelimitedTo(Sink<List<int>> sink) {"));
+        assert!(output.contains(
+            "static Stream<Point> readDelimitedFrom(Stream<List<int>> stream, {int maxFrameLength = defaultMaxFrameLength}) {"
+        ));
+        assert!(output.contains("Uint8List _frame(Uint8List bytes) {"));
+        assert!(output.contains(
+            "Stream<Uint8List> _readFrames(Stream<List<int>> stream, int maxFrameLength) async* {"
+        ));
+        // A length prefix is checked before waiting for the rest of the frame
+        assert!(output.contains("if (length > maxFrameLength) {"));
+
+        let output = generate_with(&[]);
+
+        assert!(!output.contains("Delimited"));
+        assert!(!output.contains("_frame"));
+    }
+
+    #[test]
+    fn isolate_methods() {
+        let output = generate_with(&["isolate=true"]);
+
+        assert!(output.contains("import 'package:flutter/foundation.dart' show compute;"));
+        assert!(output.contains("  const Point({"));
+        assert!(output.contains(
+            "static Future<Point> fromBytesAsync(Uint8List bytes) {\n    return compute(fromBytes, bytes);"
+        ));
+
+        let output = generate_with(&[]);
+
+        assert!(!output.contains("compute"));
+        assert!(!output.contains("const Point({"));
+    }
+
+    #[test]
+    fn compression_helpers() {
+        let output = generate_with(&["compression=zlib", "compress_above=100"]);
+
+        assert!(output.starts_with("import 'dart:io' show zlib;\n"));
+        assert!(output.contains("const _compressAbove = 100;"));
+        assert!(output.contains("[0xc1, 1, ...zlib.encode(bytes)]"));
+        assert!(output.contains("return _compress(p.takeBytes());"));
+        assert!(output.contains("return _unpack(Unpacker(_decompress(bytes)));"));
+
+        let output = generate_with(&["compression=zstd"]);
+
+        assert!(output.contains("import 'package:es_compression/zstd.dart';"));
+        assert!(output.contains(
+            "zstd.decoder.startChunkedConversion(decoded)..add(Uint8List.sublistView(bytes, 2))..close();"
+        ));
+        assert!(output.contains("if (builder.length > _maxDecompressed) {"));
+        assert!(!generate_with(&[]).contains("_compress"));
+    }
+
+    #[test]
+    fn envelope_methods() {
+        let output = generate_with(&["envelope=fingerprint"]);
+
+        assert!(output.contains("const schemaFingerprint = [0x"));
+        assert!(output.contains("return _seal(toBytes(), false);"));
+        assert!(output.contains("static Point fromEnvelope(Uint8List bytes) {"));
+        assert!(output.contains("int _crc32(List<int> bytes) {"));
+        assert!(generate_with(&["envelope=crc32"]).contains("return _seal(toBytes(), true);"));
+        assert!(!generate_with(&[]).contains("Envelope"));
+    }
+
+    #[test]
+    fn int64_fields() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct big { @int64(string) id: u64, maybe: i64?, ids: [i64] }",
+            )
+            .unwrap();
+        let options = Options::parse(["int64=bigint", "json=true"]).unwrap();
+        let output = generate(&schema, Config::from_options(&schema, &options).unwrap());
+
+        assert!(output.contains("final String id;"));
+        assert!(output.contains("final BigInt? maybe;"));
+        assert!(output.contains("final List<int> ids;"));
+        assert!(output.contains("p.packString(id);"));
+        assert!(output.contains("p.packString(maybe!.toString());"));
+        assert!(output.contains("final maybe = _parseBigInt(u.unpackString());"));
+        assert!(output.contains("'maybe': maybe?.toString(),"));
+        assert!(output.contains(
+            "maybe: json['maybe'] == null ? null : BigInt.parse(json['maybe'] as String),"
+        ));
+
+        let options = Options::parse(["int64=error"]).unwrap();
+        assert!(matches!(
+            Config::from_options(&schema, &options),
+            Err(GenoError::Int64NotAllowed(field)) if field == "big.maybe"
+        ));
+        assert!(matches!(
+            Config::from_options(&schema, &Options::parse(["int64=long"]).unwrap()),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn driver() {
+        let output = generate_driver(&schema(), &Options::new()).unwrap();
+
+        assert!(output.contains("import 'package:geno_driver/geno.dart';"));
+        assert!(output.contains("case 'point':\n      return Point.fromBytes(bytes).toBytes();"));
+        assert!(output.contains("stderr.writeln('${parts[1]}: $err');"));
+    }
+
+    #[test]
+    fn localized_strings() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct product { name: lstring, tagline: lstring?, notes: [lstring] }",
+            )
+            .unwrap();
+        let output = generate(
+            &schema,
+            Config::from_options(&schema, &Options::parse(["json=true"]).unwrap()).unwrap(),
+        );
+
+        assert_eq!(output.matches("class LocalizedString {").count(), 1);
+        assert!(output.contains("final LocalizedString name;"));
+        assert!(output.contains("name._pack(p);"));
+        assert!(output.contains("p.packBool(true);\n      tagline!._pack(p);"));
+        assert!(output.contains("u.unpackBool() == null ? null : LocalizedString._unpack(u)"));
+        assert!(output.contains("notes.map((e0) => e0.toJson()).toList()"));
+        assert!(output.contains("LocalizedString.fromJson("));
+        assert!(!generate_with(&[]).contains("LocalizedString"));
+    }
+
+    #[test]
+    fn property_tests() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum kind: u8 { a = 1, b = 2 } @root struct node { @int64(bigint) id: i64, kind: kind, children: [node], parent: node?, tags: {string: f64} }",
+            )
+            .unwrap();
+        let options = Options::parse(["property_tests=true", "roots_only=true"]).unwrap();
+        let files = DartMpGenerator.generate(&schema, &options).unwrap();
+        let output = String::from_utf8(files[0].contents.clone()).unwrap();
+        let tests = String::from_utf8(files[1].contents.clone()).unwrap();
+
+        assert_eq!(files[1].path, std::path::Path::new("geno_test.dart"));
+        assert!(output.contains("import 'dart:math' as math;"));
+        assert!(output.contains(
+            "static Kind arbitrary(math.Random random) => values[random.nextInt(values.length)];"
+        ));
+        assert!(output.contains("id: BigInt.from(_arbitraryInt(random, 64, true)),"));
+        assert!(output.contains("kind: Kind.arbitrary(random),"));
+        assert!(output.contains("children: [],\n      parent: null,"));
+        assert!(output.contains(
+            "tags: Map.fromEntries(List.generate(random.nextInt(4), (_) => MapEntry(_arbitraryString(random), random.nextDouble() * 2000 - 1000))),"
+        ));
+        assert!(tests.contains("test('Node round trips', () {"));
+        assert!(tests.contains("expect(Node.fromBytes(value.toBytes()), equals(value));"));
+        assert!(!tests.contains("Kind"));
+        assert!(!generate_with(&[]).contains("arbitrary"));
+        assert!(matches!(
+            Config::from_options(
+                &schema,
+                &Options::parse(["property_tests=true", "equality=false"]).unwrap()
+            ),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+}
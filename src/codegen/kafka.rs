@@ -0,0 +1,368 @@
+//! Geno Kafka generator.  Writes `kafka.rs`, with a typed producer and consumer for each topic,
+//! which wrap the `rdkafka` clients and send the `rust-mp` payloads of the topic.
+//!
+//! Messages use the framing of the Confluent wire format, a zero magic byte and the big endian
+//! schema id, followed by the MessagePack payload.  The schema ids are resolved when the code is
+//! generated, by fetching the schema of each topic from a [schema registry](crate::schema_registry)
+//! and checking that it is the schema being generated, or are given with `schema_id`.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, rust_serde, util::*},
+    schema_registry::{RegistryClient, RegistryMode, SchemaSelector},
+};
+use std::fmt::Write;
+
+/// Generator for the `kafka` format
+pub struct KafkaGenerator;
+
+/// The payloads are encoded by the `rust-mp` types
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+/// The error and framing functions, written once for all topics
+const KAFKA_SUPPORT: &str = r#"
+/// An error sending or receiving a message on a topic
+#[derive(Debug)]
+pub enum KafkaError {
+    /// An error from the Kafka client
+    Client(rdkafka::error::KafkaError),
+    /// The message has no payload
+    NoPayload,
+    /// The payload doesn't start with the magic byte and a schema id
+    NotFramed,
+    /// The payload was written with another schema
+    SchemaId(u32),
+    /// The payload doesn't decode
+    Decode(DecodeError),
+}
+
+impl std::fmt::Display for KafkaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KafkaError::Client(err) => write!(f, "{err}"),
+            KafkaError::NoPayload => write!(f, "the message has no payload"),
+            KafkaError::NotFramed => write!(f, "the payload has no schema id"),
+            KafkaError::SchemaId(id) => write!(f, "the payload has schema id {id}"),
+            KafkaError::Decode(err) => write!(f, "{}", err.0),
+        }
+    }
+}
+
+impl std::error::Error for KafkaError {}
+
+impl From<rdkafka::error::KafkaError> for KafkaError {
+    fn from(err: rdkafka::error::KafkaError) -> Self {
+        KafkaError::Client(err)
+    }
+}
+
+/// Put the magic byte and schema id in front of a payload
+fn frame(schema_id: u32, payload: Vec<u8>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len() + 5);
+
+    bytes.push(0);
+    bytes.extend_from_slice(&schema_id.to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// The payload after the magic byte, checking that it has the schema id
+fn unframe(schema_id: u32, bytes: &[u8]) -> Result<&[u8], KafkaError> {
+    let [0, a, b, c, d, payload @ ..] = bytes else {
+        return Err(KafkaError::NotFramed);
+    };
+    let id = u32::from_be_bytes([*a, *b, *c, *d]);
+
+    if id != schema_id {
+        return Err(KafkaError::SchemaId(id));
+    }
+    Ok(payload)
+}
+"#;
+
+impl Generator for KafkaGenerator {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "schema_id",
+            "registry",
+            "subject",
+            "confluent",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let full_schema = schema;
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let naming = Naming::from_options(
+            options,
+            rust_serde::DEFAULT_NAMING,
+            &[
+                NamingConvention::Pascal,
+                NamingConvention::Camel,
+                NamingConvention::Snake,
+                NamingConvention::ScreamingSnake,
+                NamingConvention::Preserve,
+            ],
+        )?;
+        let topics: Vec<(&str, &str)> = schema
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                ast::Declaration::Topic { ident, payload, .. } => {
+                    Some((ident.as_str(), payload.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut w = CodeWriter::new("    ");
+
+        w.line("// Kafka producers and consumers for the rust-mp topics, generated by geno.  Include this");
+        w.line("// file in the module that holds them.  Needs the rdkafka crate.");
+        if !topics.is_empty() {
+            let resolver = SchemaIds::from_options(options)?;
+
+            w.write_str(KAFKA_SUPPORT).unwrap();
+            for (ident, payload) in topics {
+                let schema_id = resolver.resolve(full_schema, ident)?;
+
+                generate_topic(&mut w, &naming, ident, payload, schema_id);
+            }
+        }
+
+        Ok(vec![GeneratedFile::new("kafka.rs", w.into_string())])
+    }
+}
+
+/// Where the schema ids of the topics come from
+enum SchemaIds {
+    /// The same id for every topic
+    Fixed(u32),
+    /// The newest version of a subject in a registry, `<topic>-value` unless a subject is given
+    Registry(RegistryClient, Option<String>),
+}
+
+impl SchemaIds {
+    fn from_options(options: &Options) -> Result<Self, GenoError> {
+        match (options.get("schema_id"), options.get("registry")) {
+            (Some(id), None) => id
+                .parse()
+                .map(SchemaIds::Fixed)
+                .map_err(|_| GenoError::InvalidOption(format!("schema_id={id}"))),
+            (None, Some(url)) => {
+                let mode = if options.get_bool("confluent", false)? {
+                    RegistryMode::Confluent
+                } else {
+                    RegistryMode::Geno
+                };
+
+                Ok(SchemaIds::Registry(
+                    RegistryClient::new(url, mode),
+                    options.get("subject").map(String::from),
+                ))
+            }
+            (Some(id), Some(url)) => Err(GenoError::InvalidOption(format!(
+                "schema_id={id} and registry={url} can't both be given"
+            ))),
+            (None, None) => Err(GenoError::InvalidOption(
+                "registry or schema_id is needed for the schema ids of the topics".to_string(),
+            )),
+        }
+    }
+
+    /// The id of the schema of a topic, which must be the schema being generated
+    fn resolve(&self, schema: &ast::Schema, topic: &str) -> Result<u32, GenoError> {
+        let (client, subject) = match self {
+            SchemaIds::Fixed(id) => return Ok(*id),
+            SchemaIds::Registry(client, subject) => (
+                client,
+                subject.clone().unwrap_or_else(|| format!("{topic}-value")),
+            ),
+        };
+        let registered = client.fetch(&SchemaSelector::Subject(subject.clone(), None))?;
+        let fingerprint = schema.fingerprint();
+
+        if registered.fingerprint != fingerprint {
+            return Err(GenoError::Incompatible(format!(
+                "subject '{subject}' has schema {}, not {fingerprint}",
+                registered.fingerprint
+            )));
+        }
+
+        u32::try_from(registered.id).map_err(|_| {
+            GenoError::Registry(format!(
+                "schema id {} of subject '{subject}' doesn't fit in 32 bits",
+                registered.id
+            ))
+        })
+    }
+}
+
+fn generate_topic(w: &mut CodeWriter, naming: &Naming, ident: &str, payload: &str, id: u32) {
+    let rust_name = naming.type_name(payload);
+    let topic_name = naming.type_name(ident);
+    let topic_const = to_screaming_snake_case(ident);
+
+    w.blank();
+    w.line(format!(
+        "/// Sends `{rust_name}` messages on the `{ident}` topic"
+    ));
+    w.line(format!(
+        "pub struct {topic_name}Producer(pub rdkafka::producer::FutureProducer);"
+    ));
+    w.blank();
+    w.block(&format!("impl {topic_name}Producer {{"), "}", |w| {
+        w.line("/// The id of the schema the messages are written with");
+        w.line(format!("pub const SCHEMA_ID: u32 = {id};"));
+        w.blank();
+        w.line("/// Send a message, with a key to choose its partition");
+        w.block(
+            &format!(
+                "pub async fn send(&self, key: Option<&[u8]>, message: &{rust_name}) -> Result<(), KafkaError> {{"
+            ),
+            "}",
+            |w| {
+                w.line(format!(
+                    "let payload = frame(Self::SCHEMA_ID, {topic_const}.encode(message));"
+                ));
+                w.line(format!(
+                    "let mut record = rdkafka::producer::FutureRecord::<[u8], [u8]>::to({topic_const}.name).payload(&payload);"
+                ));
+                w.blank();
+                w.block("if let Some(key) = key {", "}", |w| {
+                    w.line("record = record.key(key);");
+                });
+                w.line("self.0");
+                w.line("    .send(record, rdkafka::util::Timeout::Never)");
+                w.line("    .await");
+                w.line("    .map(|_| ())");
+                w.line("    .map_err(|(err, _)| KafkaError::Client(err))");
+            },
+        );
+    });
+
+    w.blank();
+    w.line(format!(
+        "/// Receives `{rust_name}` messages from the `{ident}` topic"
+    ));
+    w.line(format!(
+        "pub struct {topic_name}Consumer(pub rdkafka::consumer::StreamConsumer);"
+    ));
+    w.blank();
+    w.block(&format!("impl {topic_name}Consumer {{"), "}", |w| {
+        w.line("/// The id of the schema the messages must be written with");
+        w.line(format!("pub const SCHEMA_ID: u32 = {id};"));
+        w.blank();
+        w.line("/// Subscribe a consumer to the topic");
+        w.block(
+            "pub fn subscribe(consumer: rdkafka::consumer::StreamConsumer) -> Result<Self, KafkaError> {",
+            "}",
+            |w| {
+                w.line(format!(
+                    "rdkafka::consumer::Consumer::subscribe(&consumer, &[{topic_const}.name])?;"
+                ));
+                w.line("Ok(Self(consumer))");
+            },
+        );
+        w.blank();
+        w.line("/// Wait for the next message");
+        w.block(
+            &format!("pub async fn recv(&self) -> Result<{rust_name}, KafkaError> {{"),
+            "}",
+            |w| {
+                w.line("let message = self.0.recv().await?;");
+                w.line(
+                    "let payload = rdkafka::Message::payload(&message).ok_or(KafkaError::NoPayload)?;",
+                );
+                w.blank();
+                w.line(format!(
+                    "{topic_const}.decode(unframe(Self::SCHEMA_ID, payload)?).map_err(KafkaError::Decode)"
+                ));
+            },
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str =
+        "meta { format = 1 }\nstruct user_event { id: u32 }\ntopic user_events: user_event;\n";
+
+    fn generate(input: &str, options: &[&str]) -> Result<String, GenoError> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+        let files = KafkaGenerator.generate(&schema, &Options::parse(options).unwrap())?;
+
+        Ok(String::from_utf8(files[0].contents.clone()).unwrap())
+    }
+
+    #[test]
+    fn producers_and_consumers() {
+        let output = generate(SCHEMA, &["schema_id=42"]).unwrap();
+
+        assert!(output.contains("pub enum KafkaError {"));
+        assert!(
+            output
+                .contains("pub struct UserEventsProducer(pub rdkafka::producer::FutureProducer);")
+        );
+        assert!(output.contains("pub const SCHEMA_ID: u32 = 42;"));
+        assert!(output.contains(
+            "pub async fn send(&self, key: Option<&[u8]>, message: &UserEvent) -> Result<(), KafkaError> {"
+        ));
+        assert!(
+            output.contains("let payload = frame(Self::SCHEMA_ID, USER_EVENTS.encode(message));")
+        );
+        assert!(
+            output
+                .contains("pub struct UserEventsConsumer(pub rdkafka::consumer::StreamConsumer);")
+        );
+        assert!(output.contains(
+            "USER_EVENTS.decode(unframe(Self::SCHEMA_ID, payload)?).map_err(KafkaError::Decode)"
+        ));
+    }
+
+    #[test]
+    fn schema_ids() {
+        assert!(matches!(
+            generate(SCHEMA, &[]),
+            Err(GenoError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            generate(SCHEMA, &["schema_id=-1"]),
+            Err(GenoError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            generate(SCHEMA, &["schema_id=1", "registry=http://localhost"]),
+            Err(GenoError::InvalidOption(_))
+        ));
+        assert!(
+            !generate("meta { format = 1 }\nstruct a { b: i8 }", &[])
+                .unwrap()
+                .contains("KafkaError")
+        );
+    }
+}
@@ -0,0 +1,702 @@
+//! Geno mock data generator.  Writes fake but realistic instances of each struct in the schema,
+//! for UI prototypes and load tests, as a `<struct>.json` file holding an array of instances in
+//! the canonical JSON form, or with `encoding=msgpack` as `<struct>.<n>.msgpack` files packed
+//! like the [test vectors](super::testvectors).
+//!
+//! Values are chosen from the field names, so an `email` field gets an email address, a
+//! `first_name` a first name and an `age` a number between 18 and 90.  `@range` and `@len`
+//! bound numbers, strings and collections.  The instances are random, but the same `seed` option
+//! always gives the same ones.
+//!
+//! With `factories=rust` or `factories=dart` the instances are also written as `fake()`
+//! functions for the types of the `rust-serde` generator, or of `dart-mp` with `json=true`.
+use crate::{
+    GenoError, ast,
+    codegen::{
+        GeneratedFile, GeneratedFiles, Generator, Options, dart_mp, rust_serde,
+        testvectors::{Builder, Value, integer_max, integer_min, integer_value},
+        util::*,
+    },
+};
+
+/// Generator for the `mock-data` format
+pub struct MockDataGenerator;
+
+/// MessagePack instances are packed like the test vectors, which have no float map keys
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+/// Instances of each struct, unless the `count` option says otherwise
+const DEFAULT_COUNT: usize = 5;
+
+const FIRST_NAMES: [&str; 12] = [
+    "Ada", "Alan", "Barbara", "Dennis", "Edsger", "Frances", "Grace", "Ken", "Linus", "Margaret",
+    "Niklaus", "Radia",
+];
+const LAST_NAMES: [&str; 12] = [
+    "Allen",
+    "Dijkstra",
+    "Hopper",
+    "Kernighan",
+    "Liskov",
+    "Lovelace",
+    "Hamilton",
+    "Perlman",
+    "Ritchie",
+    "Thompson",
+    "Torvalds",
+    "Wirth",
+];
+const CITIES: [&str; 8] = [
+    "Amsterdam",
+    "Austin",
+    "Berlin",
+    "Lagos",
+    "Melbourne",
+    "Osaka",
+    "Seattle",
+    "Toronto",
+];
+const COUNTRIES: [&str; 8] = [
+    "Australia",
+    "Canada",
+    "Germany",
+    "Japan",
+    "Netherlands",
+    "Nigeria",
+    "United Kingdom",
+    "United States",
+];
+const STREETS: [&str; 6] = ["Maple", "Oak", "Pine", "Cedar", "Elm", "Willow"];
+const WORDS: [&str; 16] = [
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "consectetur",
+    "adipiscing",
+    "elit",
+    "sed",
+    "do",
+    "eiusmod",
+    "tempor",
+    "incididunt",
+    "ut",
+    "labore",
+    "magna",
+];
+
+/// How the instances are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Json,
+    MsgPack,
+}
+
+/// The language of the `fake()` functions
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Factories {
+    Rust,
+    Dart,
+}
+
+impl Generator for MockDataGenerator {
+    fn name(&self) -> &str {
+        "mock-data"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "int64",
+            "roots_only",
+            "count",
+            "seed",
+            "encoding",
+            "factories",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, None)?;
+        let roots = Roots::from_options(schema, options)?;
+        let int64 = Int64::from_options(options)?;
+        let count = match options.get("count") {
+            None => DEFAULT_COUNT,
+            Some(value) => value
+                .parse()
+                .map_err(|_| GenoError::InvalidOption(format!("count={value}")))?,
+        };
+        let seed = match options.get("seed") {
+            None => 0,
+            Some(value) => value
+                .parse()
+                .map_err(|_| GenoError::InvalidOption(format!("seed={value}")))?,
+        };
+        let encoding = match options.get("encoding") {
+            None | Some("json") => Encoding::Json,
+            Some("msgpack") => Encoding::MsgPack,
+            Some(value) => return Err(GenoError::InvalidOption(format!("encoding={value}"))),
+        };
+        let factories = match options.get("factories") {
+            None => None,
+            Some("rust") => Some(Factories::Rust),
+            Some("dart") => Some(Factories::Dart),
+            Some(value) => return Err(GenoError::InvalidOption(format!("factories={value}"))),
+        };
+
+        int64.check(schema)?;
+
+        let builder = Builder::new(schema, int64);
+        let wire = WireFormat::from_options(schema, options)?;
+        let mut faker = Faker {
+            builder: &builder,
+            rng: Rng(seed),
+        };
+        let mut files = Vec::new();
+        let mut instances = Vec::new();
+
+        for decl in &schema.declarations {
+            let ast::Declaration::Struct { ident, .. } = decl else {
+                continue;
+            };
+
+            if !roots.contains(ident) {
+                continue;
+            }
+
+            let ft = ast::FieldType::UserDefined(ident.clone(), false);
+            let values = (0..count)
+                .map(|_| faker.value(ident, &ft, None, &mut Vec::new()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let json: Vec<serde_json::Value> = values.iter().map(Value::to_json).collect();
+
+            match encoding {
+                Encoding::Json => {
+                    let mut contents = serde_json::to_string_pretty(&json).unwrap();
+
+                    contents.push('\n');
+                    files.push(GeneratedFile::new(format!("{ident}.json"), contents));
+                }
+                Encoding::MsgPack => {
+                    for (i, value) in values.iter().enumerate() {
+                        let mut bytes = Vec::new();
+
+                        builder.pack(&mut bytes, wire.protocol(), &ft, value);
+                        files.push(GeneratedFile::new(format!("{ident}.{i}.msgpack"), bytes));
+                    }
+                }
+            }
+            instances.push((ident.as_str(), json));
+        }
+
+        // The type names follow the naming options, as they do for the generator of the types
+        let naming = |defaults| {
+            Naming::from_options(
+                options,
+                defaults,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )
+        };
+
+        match factories {
+            Some(Factories::Rust) => files.push(GeneratedFile::new(
+                "mock.rs",
+                rust_factories(&instances, &naming(rust_serde::DEFAULT_NAMING)?),
+            )),
+            Some(Factories::Dart) => files.push(GeneratedFile::new(
+                "mock.dart",
+                dart_factories(&instances, &naming(dart_mp::DEFAULT_NAMING)?),
+            )),
+            None => {}
+        }
+
+        Ok(files)
+    }
+}
+
+/// A SplitMix64 generator, small and good enough for fake data
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from `min` to `max` inclusive
+    fn range(&mut self, min: i128, max: i128) -> i128 {
+        if max <= min {
+            return min;
+        }
+
+        min + (self.next() as i128) % (max - min + 1)
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.range(0, items.len() as i128 - 1) as usize]
+    }
+
+    fn chance(&mut self, percent: i128) -> bool {
+        self.range(0, 99) < percent
+    }
+}
+
+/// Builds fake values for the types of one schema
+struct Faker<'a> {
+    builder: &'a Builder<'a>,
+    rng: Rng,
+}
+
+impl<'a> Faker<'a> {
+    /// A fake value for a field, from its name and the `@range` or `@len` bounds on it.  `stack`
+    /// holds the structs being built, so that recursive types end with a null or an empty
+    /// collection.
+    fn value(
+        &mut self,
+        field: &str,
+        ft: &ast::FieldType,
+        bounds: Option<ast::Bounds>,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<Value, GenoError> {
+        if is_nullable(ft) {
+            if self.builder.recurses(ft, stack) || self.rng.chance(20) {
+                return Ok(Value::Null);
+            }
+            return self.value(field, &non_nullable(ft), bounds, stack);
+        }
+
+        Ok(match ft {
+            ast::FieldType::Builtin(bt, _) => self.builtin(field, bt, bounds),
+            ast::FieldType::UserDefined(name, _) => {
+                if let Some(variants) = self.builder.enums.get(name.as_str()) {
+                    let (variant_name, value) =
+                        &variants[self.rng.range(0, variants.len() as i128 - 1) as usize];
+
+                    Value::Enum(variant_name.clone(), integer_value(value))
+                } else {
+                    let (ident, fields) =
+                        self.builder.structs.get_key_value(name.as_str()).unwrap();
+
+                    if stack.contains(ident) {
+                        return Err(GenoError::RecursiveType(name.clone()));
+                    }
+                    stack.push(ident);
+
+                    let annotations = self.builder.annotations[ident];
+                    let mut values = Vec::new();
+
+                    for (field_name, field_type) in fields.iter() {
+                        let bounds = annotations
+                            .member(field_name)
+                            .iter()
+                            .filter(|annotation| {
+                                matches!(annotation.name.as_str(), "range" | "len")
+                            })
+                            .find_map(|annotation| annotation.bounds());
+                        let value = match self.value(field_name, field_type, bounds, stack)? {
+                            Value::Int(n)
+                                if self.builder.int64_strings.contains(&(*ident, field_name)) =>
+                            {
+                                Value::String(n.to_string())
+                            }
+                            value => value,
+                        };
+                        let json_name = (!annotations.is_flattened(field_name))
+                            .then(|| annotations.json_name(field_name).to_string());
+
+                        values.push((json_name, value));
+                    }
+                    stack.pop();
+                    Value::Struct(values)
+                }
+            }
+            ast::FieldType::Array(inner, length, _) => {
+                let len = match length {
+                    Some(len) => *len as i128,
+                    None if self.builder.recurses(inner, stack) => 0,
+                    None => self.length(bounds, 1, 3),
+                };
+
+                Value::List(
+                    (0..len)
+                        .map(|_| self.value(field, inner, None, stack))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            ast::FieldType::Map(key_type, value_type, _) => {
+                let len = if self.builder.recurses(value_type, stack) {
+                    0
+                } else {
+                    self.length(bounds, 1, 2)
+                };
+                let key_type = map_key_field_type(key_type);
+                let mut entries: Vec<(Value, Value)> = Vec::new();
+
+                // Keys that are already taken are skipped, so small key types give fewer entries
+                for _ in 0..len {
+                    let key = self.value(field, &key_type, None, stack)?;
+
+                    if !entries.iter().any(|(existing, _)| *existing == key) {
+                        entries.push((key, self.value(field, value_type, None, stack)?));
+                    }
+                }
+
+                Value::Map(entries)
+            }
+        })
+    }
+
+    /// A collection length within the `@len` bounds, or from `min` to `max` without them
+    fn length(&mut self, bounds: Option<ast::Bounds>, min: i128, max: i128) -> i128 {
+        match bounds {
+            Some(bounds) => {
+                let low = bounds.min.unwrap_or(0).into();
+
+                self.rng
+                    .range(low, bounds.max.map_or(low.max(max), Into::into))
+            }
+            None => self.rng.range(min, max),
+        }
+    }
+
+    fn builtin(
+        &mut self,
+        field: &str,
+        bt: &ast::BuiltinType,
+        bounds: Option<ast::Bounds>,
+    ) -> Value {
+        let words = words(field);
+        let has = |hints: &[&str]| {
+            words
+                .iter()
+                .any(|word| hints.iter().any(|hint| word.starts_with(hint)))
+        };
+
+        match bt {
+            ast::BuiltinType::Integer(it) => {
+                let (min, max) = if has(&["age"]) {
+                    (18, 90)
+                } else if has(&["year"]) {
+                    (1970, 2030)
+                } else if has(&["port"]) {
+                    (1024, 65535)
+                } else if has(&["timestamp", "time"]) || words.last() == Some(&"at".to_string()) {
+                    (1_600_000_000, 1_800_000_000)
+                } else {
+                    (0, 1000)
+                };
+                let (min, max) = match bounds {
+                    Some(bounds) => (
+                        bounds.min.map_or(min, Into::into),
+                        bounds.max.map_or(max, Into::into),
+                    ),
+                    None => (min, max),
+                };
+                let min = min.clamp(integer_min(it), integer_max(it));
+                let max = max.clamp(min, integer_max(it));
+
+                Value::Int(self.rng.range(min, max))
+            }
+            ast::BuiltinType::Float(_) => {
+                let (min, max) = if has(&["lat"]) {
+                    (-90, 90)
+                } else if has(&["lon", "lng"]) {
+                    (-180, 180)
+                } else {
+                    (0, 1000)
+                };
+                let (min, max) = match bounds {
+                    Some(bounds) => (
+                        bounds.min.map_or(min, Into::into),
+                        bounds.max.map_or(max, Into::into),
+                    ),
+                    None => (min, max),
+                };
+
+                // Two decimal places, like prices and amounts
+                Value::Float(self.rng.range(min * 100, max.max(min) * 100) as f64 / 100.0)
+            }
+            ast::BuiltinType::String => Value::String(self.string(&words, bounds)),
+            ast::BuiltinType::LocalizedString => Value::Map(vec![(
+                Value::String("en".to_string()),
+                Value::String(self.string(&words, bounds)),
+            )]),
+            ast::BuiltinType::Bool => Value::Bool(self.rng.chance(50)),
+        }
+    }
+
+    /// A string to suit a field with these words in its name, fitted to the `@len` bounds
+    fn string(&mut self, words: &[String], bounds: Option<ast::Bounds>) -> String {
+        let has = |hints: &[&str]| {
+            words
+                .iter()
+                .any(|word| hints.iter().any(|hint| word.starts_with(hint)))
+        };
+        let rng = &mut self.rng;
+        let first = rng.pick(&FIRST_NAMES);
+        let last = rng.pick(&LAST_NAMES);
+        let mut s = if has(&["email"]) {
+            format!("{}.{}@example.com", first, last).to_lowercase()
+        } else if has(&["first", "given"]) {
+            first.to_string()
+        } else if has(&["last", "surname", "family"]) {
+            last.to_string()
+        } else if has(&["name", "author", "owner", "user"]) {
+            format!("{first} {last}")
+        } else if has(&["phone", "mobile"]) {
+            format!("+1-555-{:04}", rng.range(0, 9999))
+        } else if has(&["url", "website", "link", "href"]) {
+            format!("https://example.com/{}", rng.pick(&WORDS))
+        } else if has(&["city"]) {
+            rng.pick(&CITIES).to_string()
+        } else if has(&["country"]) {
+            rng.pick(&COUNTRIES).to_string()
+        } else if has(&["street", "address"]) {
+            format!("{} {} Street", rng.range(1, 999), rng.pick(&STREETS))
+        } else if has(&["uuid", "guid", "id", "key", "token"]) {
+            let hex = format!("{:016x}{:016x}", rng.next(), rng.next());
+
+            format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            )
+        } else if has(&["color", "colour"]) {
+            format!("#{:06x}", rng.range(0, 0xff_ffff))
+        } else if has(&["date", "time"]) {
+            format!(
+                "20{:02}-{:02}-{:02}T{:02}:{:02}:00Z",
+                rng.range(15, 30),
+                rng.range(1, 12),
+                rng.range(1, 28),
+                rng.range(0, 23),
+                rng.range(0, 59)
+            )
+        } else {
+            let count = rng.range(2, 6);
+
+            (0..count)
+                .map(|_| rng.pick(&WORDS))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        if let Some(bounds) = bounds {
+            let min = bounds.min.unwrap_or(0).max(0) as usize;
+            let max = bounds.max.map_or(usize::MAX, |max| max.max(0) as usize);
+
+            while s.chars().count() < min {
+                s.push('x');
+            }
+            s = s.chars().take(max).collect();
+        }
+
+        s
+    }
+}
+
+/// The lowercase words of a field name, however it is written
+fn words(field: &str) -> Vec<String> {
+    to_snake_case(field)
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// A `fake()` constructor for each `rust-serde` struct, deserializing one of the instances
+fn rust_factories(instances: &[(&str, Vec<serde_json::Value>)], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    w.line(
+        "// Fake instances of the rust-serde types, generated by geno.  Include this file in the",
+    );
+    w.line("// module that holds them.  Deserializing them needs serde_json.");
+    for (ident, values) in instances {
+        w.blank();
+        w.block(&format!("impl {} {{", naming.type_name(ident)), "}", |w| {
+            w.line(format!(
+                "/// One of {} fake instances, which repeat as `index` grows",
+                values.len()
+            ));
+            w.block("pub fn fake(index: usize) -> Self {", "}", |w| {
+                w.block(
+                    &format!("const FAKES: [&str; {}] = [", values.len()),
+                    "];",
+                    |w| {
+                        for value in values {
+                            w.line(format!("{:?},", value.to_string()));
+                        }
+                    },
+                );
+                w.blank();
+                w.line("serde_json::from_str(FAKES[index % FAKES.len()]).unwrap()");
+            });
+        });
+    }
+
+    w.into_string()
+}
+
+/// A `fake<Type>()` function for each `dart-mp` struct, decoding one of the instances with the
+/// `fromJson` that `json=true` generates
+fn dart_factories(instances: &[(&str, Vec<serde_json::Value>)], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("  ");
+
+    w.line("// Fake instances of the dart-mp types, generated by geno.  The types need to be");
+    w.line("// generated with -O json=true.");
+    w.blank();
+    w.line("import 'dart:convert';");
+    w.blank();
+    w.line("import 'geno.dart';");
+    for (ident, values) in instances {
+        let type_name = naming.type_name(ident);
+        let list = format!("_fake{type_name}");
+
+        w.blank();
+        w.line(format!(
+            "/// One of {} fake instances, which repeat as `index` grows",
+            values.len()
+        ));
+        w.line(format!(
+            "{type_name} fake{type_name}(int index) => {type_name}.fromJson("
+        ));
+        w.line(format!(
+            "    jsonDecode({list}[index % {list}.length]) as Map<String, dynamic>);"
+        ));
+        w.blank();
+        w.block(&format!("const {list} = ["), "];", |w| {
+            for value in values {
+                let escaped = value
+                    .to_string()
+                    .replace('\\', "\\\\")
+                    .replace('\'', "\\'")
+                    .replace('$', "\\$");
+
+                w.line(format!("'{escaped}',"));
+            }
+        });
+    }
+
+    w.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> ast::Schema {
+        crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                r#"meta { format = 1 }
+enum role: u8 { admin = 1, member = 2 }
+struct user {
+    email: string,
+    first_name: string,
+    @range(min = 18, max = 20) age: u8,
+    @len(max = 3) tags: [string],
+    @len(min = 10, max = 10) code: string,
+    role: role,
+    manager: user?,
+}"#,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn fake_users() {
+        let options = Options::parse(["count=20", "seed=7"]).unwrap();
+        let files = MockDataGenerator.generate(&schema(), &options).unwrap();
+        let users: Vec<serde_json::Value> =
+            serde_json::from_str(files[0].as_str().unwrap()).unwrap();
+
+        assert_eq!(files[0].path.to_str(), Some("user.json"));
+        assert_eq!(users.len(), 20);
+        for user in &users {
+            let email = user["email"].as_str().unwrap();
+            let age = user["age"].as_i64().unwrap();
+
+            assert!(email.ends_with("@example.com") && email.contains('.'));
+            assert!(FIRST_NAMES.contains(&user["first_name"].as_str().unwrap()));
+            assert!((18..=20).contains(&age));
+            assert!(user["tags"].as_array().unwrap().len() <= 3);
+            assert_eq!(user["code"].as_str().unwrap().chars().count(), 10);
+            assert!(matches!(user["role"].as_str(), Some("admin" | "member")));
+            assert!(user["manager"].is_null());
+        }
+
+        // The same seed gives the same instances, another seed others
+        assert_eq!(
+            MockDataGenerator.generate(&schema(), &options).unwrap(),
+            files
+        );
+        assert_ne!(
+            MockDataGenerator
+                .generate(&schema(), &Options::parse(["count=20"]).unwrap())
+                .unwrap(),
+            files
+        );
+    }
+
+    #[test]
+    fn msgpack_and_factories() {
+        let options = Options::parse(["count=2", "encoding=msgpack", "factories=rust"]).unwrap();
+        let files = MockDataGenerator.generate(&schema(), &options).unwrap();
+        let paths: Vec<_> = files
+            .iter()
+            .map(|file| file.path.to_str().unwrap())
+            .collect();
+
+        assert_eq!(paths, ["user.0.msgpack", "user.1.msgpack", "mock.rs"]);
+
+        let factories = files[2].as_str().unwrap();
+
+        assert!(factories.contains("impl User {"));
+        assert!(factories.contains("pub fn fake(index: usize) -> Self {"));
+        assert!(factories.contains("const FAKES: [&str; 2] = ["));
+
+        let options = Options::parse(["count=1", "factories=dart"]).unwrap();
+        let files = MockDataGenerator.generate(&schema(), &options).unwrap();
+        let factories = files[1].as_str().unwrap();
+
+        assert!(factories.contains("User fakeUser(int index) => User.fromJson("));
+        assert!(factories.contains("jsonDecode(_fakeUser[index % _fakeUser.length])"));
+        assert!(matches!(
+            MockDataGenerator.generate(&schema(), &Options::parse(["encoding=xml"]).unwrap()),
+            Err(GenoError::InvalidOption(option)) if option == "encoding=xml"
+        ));
+    }
+}
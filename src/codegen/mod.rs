@@ -0,0 +1,456 @@
+use crate::{GenoError, ast};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Readers and writers for files of records that can be read in any order
+pub mod archive;
+/// C structs for the structs marked `@abi(stable)`
+pub mod c_header;
+/// Example config files and loaders for the structs marked `@config`
+pub mod config;
+/// Validation constraints as JSON
+pub mod constraints_json;
+/// Dart classes and enums with MessagePack serialization
+pub mod dart_mp;
+/// Typed Kafka producers and consumers for each topic
+pub mod kafka;
+/// Fake instances of the schema's structs as JSON or MessagePack
+pub mod mock_data;
+/// Declarations grouped by their `@owner` as JSON
+pub mod owners_json;
+/// CSV and Arrow codecs for the flat `@record` structs
+pub mod records;
+/// Redis key builders and value codecs for the structs with a `@key`
+pub mod redis;
+/// clap parsers for the `@cli` structs
+pub mod rust_clap;
+/// Rust structs and enums with MessagePack serialization, compatible with `dart-mp`
+pub mod rust_mp;
+/// Rust structs and enums with `serde` derives
+pub mod rust_serde;
+/// SQL tables, with SQLx rows and queries for the `rust-serde` types
+pub mod rust_sqlx;
+/// Sinks that write generated files to disk or stdout
+pub mod sink;
+/// User supplied Tera templates
+pub mod template;
+/// MessagePack conformance test vectors
+pub mod testvectors;
+/// Helpers shared by all generators
+pub mod util;
+/// Tagged WebSocket messages and dispatchers for the topics
+pub mod websocket;
+
+/// A single generated source file
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedFile {
+    /// Path of the file, relative to the output location chosen by the caller
+    pub path: PathBuf,
+    /// Generated source code, or binary data for generators such as `testvectors`
+    pub contents: Vec<u8>,
+}
+
+impl GeneratedFile {
+    /// Create a new generated file
+    pub fn new(path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) -> Self {
+        Self {
+            path: path.into(),
+            contents: contents.into(),
+        }
+    }
+
+    /// The contents as text, or `None` if they aren't valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.contents).ok()
+    }
+}
+
+/// All of the files produced by one generator run
+pub type GeneratedFiles = Vec<GeneratedFile>;
+
+/// Where a generator writes its files.  The contents of each file are written as they are
+/// generated, so a sink that writes them out doesn't have to hold them in memory.
+pub trait CodeSink {
+    /// Add the file at `path`, relative to the output location, whose contents are written by
+    /// `contents`.  If `contents` fails the file isn't added.
+    fn write_file(
+        &mut self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> Result<(), GenoError>,
+    ) -> Result<(), GenoError>;
+}
+
+/// Collects the files in memory
+impl CodeSink for GeneratedFiles {
+    fn write_file(
+        &mut self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> Result<(), GenoError>,
+    ) -> Result<(), GenoError> {
+        let mut buffer = Vec::new();
+
+        contents(&mut buffer)?;
+        self.push(GeneratedFile::new(path, buffer));
+
+        Ok(())
+    }
+}
+
+/// Generator specific options, given on the command line as `key=value` pairs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+    values: BTreeMap<String, String>,
+}
+
+impl Options {
+    /// Create an empty set of options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse options from `key=value` strings
+    pub fn parse<I, S>(args: I) -> Result<Self, GenoError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut options = Self::new();
+
+        for arg in args {
+            let arg = arg.as_ref();
+            match arg.split_once('=') {
+                Some((key, value)) if !key.is_empty() => options.set(key, value),
+                _ => return Err(GenoError::InvalidOption(arg.to_string())),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Set an option value, replacing any previous value
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.values.insert(key.into(), value.into());
+    }
+
+    /// Get an option value
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Get a `true`/`false` option value, or `default` if the option is not set
+    pub fn get_bool(&self, key: &str, default: bool) -> Result<bool, GenoError> {
+        match self.get(key) {
+            None => Ok(default),
+            Some("true") => Ok(true),
+            Some("false") => Ok(false),
+            Some(value) => Err(GenoError::InvalidOption(format!("{key}={value}"))),
+        }
+    }
+
+    /// Iterate over all options as `(key, value)` pairs
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Check that each key is one that at least one of `generators` reads, so that a misspelt
+    /// option fails rather than being ignored.  If any of them takes any key, every key passes.
+    pub fn check_keys<'a>(
+        &self,
+        generators: impl IntoIterator<Item = &'a dyn Generator>,
+    ) -> Result<(), GenoError> {
+        let mut known = BTreeSet::new();
+
+        for generator in generators {
+            match generator.option_keys() {
+                Some(keys) => known.extend(keys.iter().copied()),
+                None => return Ok(()),
+            }
+        }
+
+        match self.iter().find(|(key, _)| !known.contains(key)) {
+            Some((key, value)) => Err(GenoError::InvalidOption(format!("{key}={value}"))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A code generator for one output format
+pub trait Generator: Send + Sync {
+    /// Format name used to select the generator, e.g. `rust-serde`
+    fn name(&self) -> &str;
+
+    /// What the target language can represent.  Generators check the schema against these
+    /// before generating anything.
+    fn capabilities(&self) -> ast::Capabilities {
+        ast::Capabilities::ALL
+    }
+
+    /// Keys of the options the generator reads, or `None` if it takes any key, as the template
+    /// generator does.  [Options::check_keys] rejects any other key.
+    fn option_keys(&self) -> Option<&[&str]> {
+        None
+    }
+
+    /// Generate source files from a validated schema
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError>;
+
+    /// Generate source files from a validated schema into `sink`.  Generators that can write
+    /// their output as they go override this, the rest pass on the files from
+    /// [Generator::generate].
+    fn generate_to(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+        sink: &mut dyn CodeSink,
+    ) -> Result<(), GenoError> {
+        for file in self.generate(schema, options)? {
+            sink.write_file(&file.path, &mut |out| Ok(out.write_all(&file.contents)?))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A collection of generators looked up by format name
+pub struct Registry {
+    generators: Vec<Box<dyn Generator>>,
+}
+
+impl Registry {
+    /// Create a registry without any generators
+    pub fn new() -> Self {
+        Self {
+            generators: Vec::new(),
+        }
+    }
+
+    /// Create a registry containing all of the generators built into this crate
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+
+        registry.register(Box::new(rust_serde::RustSerdeGenerator));
+        registry.register(Box::new(dart_mp::DartMpGenerator));
+        registry.register(Box::new(rust_mp::RustMpGenerator));
+        registry.register(Box::new(testvectors::TestVectorsGenerator));
+        registry.register(Box::new(constraints_json::ConstraintsJsonGenerator));
+        registry.register(Box::new(owners_json::OwnersJsonGenerator));
+        registry.register(Box::new(mock_data::MockDataGenerator));
+        registry.register(Box::new(rust_sqlx::RustSqlxGenerator));
+        registry.register(Box::new(redis::RedisGenerator));
+        registry.register(Box::new(kafka::KafkaGenerator));
+        registry.register(Box::new(websocket::WebSocketGenerator));
+        registry.register(Box::new(config::ConfigGenerator));
+        registry.register(Box::new(records::RecordsGenerator));
+        registry.register(Box::new(rust_clap::RustClapGenerator));
+        registry.register(Box::new(archive::ArchiveGenerator));
+        registry.register(Box::new(c_header::CHeaderGenerator));
+
+        registry
+    }
+
+    /// Add a generator, replacing any existing generator with the same name
+    pub fn register(&mut self, generator: Box<dyn Generator>) {
+        self.generators.retain(|g| g.name() != generator.name());
+        self.generators.push(generator);
+    }
+
+    /// Find a generator by format name
+    pub fn get(&self, name: &str) -> Option<&dyn Generator> {
+        self.generators
+            .iter()
+            .find(|g| g.name() == name)
+            .map(|g| g.as_ref())
+    }
+
+    /// Names of all registered generators
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.generators.iter().map(|g| g.name())
+    }
+
+    /// Run the named generator
+    pub fn generate(
+        &self,
+        name: &str,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        match self.get(name) {
+            Some(generator) => {
+                options.check_keys([generator])?;
+                generator.generate(schema, options)
+            }
+            None => Err(GenoError::UnknownGenerator(name.to_string())),
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::with_builtins()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn schema() -> ast::Schema {
+        ast::Schema {
+            metadata: HashMap::new(),
+            declarations: vec![ast::Declaration::Struct {
+                ident: "point".to_string(),
+                fields: vec![(
+                    "x".to_string(),
+                    ast::FieldType::Builtin(ast::BuiltinType::Bool, false),
+                )],
+                implements: Vec::new(),
+                visibility: Default::default(),
+                annotations: Default::default(),
+            }],
+        }
+    }
+
+    #[test]
+    fn builtins_are_registered() {
+        let registry = Registry::with_builtins();
+        let names: Vec<&str> = registry.names().collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "rust-serde",
+                "dart-mp",
+                "rust-mp",
+                "testvectors",
+                "constraints-json",
+                "owners-json",
+                "mock-data",
+                "rust-sqlx",
+                "redis",
+                "kafka",
+                "websocket",
+                "config",
+                "records",
+                "rust-clap",
+                "archive",
+                "c-header"
+            ]
+        );
+
+        let files = registry
+            .generate("rust-serde", &schema(), &Options::new())
+            .unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert!(files[0].as_str().unwrap().contains("pub struct Point"));
+    }
+
+    #[test]
+    fn target_capabilities() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } struct a { m: [{ f64 : string }] }")
+            .unwrap();
+        let registry = Registry::default();
+
+        assert!(
+            registry
+                .generate("rust-serde", &schema, &Options::new())
+                .is_err()
+        );
+        assert!(
+            registry
+                .generate("constraints-json", &schema, &Options::new())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn unknown_generator() {
+        let result = Registry::new().generate("cobol", &schema(), &Options::new());
+
+        assert!(matches!(result, Err(GenoError::UnknownGenerator(_))));
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = Options::parse(["a=1", "b=x=y"]).unwrap();
+
+        assert_eq!(options.get("a"), Some("1"));
+        assert_eq!(options.get("b"), Some("x=y"));
+        assert!(matches!(
+            Options::parse(["novalue"]),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn option_keys() {
+        let registry = Registry::with_builtins();
+        let generators = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| registry.get(name).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let options = Options::parse(["derives=Hash", "framing=true"]).unwrap();
+
+        assert!(
+            options
+                .check_keys(generators(&["rust-serde", "rust-mp"]))
+                .is_ok()
+        );
+        assert!(matches!(
+            options.check_keys(generators(&["rust-serde"])),
+            Err(GenoError::InvalidOption(option)) if option == "framing=true"
+        ));
+        for option in ["derive=Hash", "default=true", "constructor=true"] {
+            assert!(matches!(
+                registry.generate("rust-serde", &schema(), &Options::parse([option]).unwrap()),
+                Err(GenoError::InvalidOption(_))
+            ));
+        }
+        assert!(matches!(
+            registry.generate(
+                "rust-mp",
+                &schema(),
+                &Options::parse(["compression_threshold=16"]).unwrap()
+            ),
+            Err(GenoError::InvalidOption(_))
+        ));
+        // Every option the generators read is declared
+        assert!(
+            registry
+                .generate(
+                    "rust-mp",
+                    &schema(),
+                    &Options::parse(["compress_above=16"]).unwrap()
+                )
+                .is_ok()
+        );
+        // Templates take any key
+        let template = template::TemplateGenerator::new("templates");
+
+        assert!(options.check_keys([&template as &dyn Generator]).is_ok());
+    }
+
+    #[test]
+    fn bool_options() {
+        let options = Options::parse(["on=true", "off=false", "bad=yes"]).unwrap();
+
+        assert!(options.get_bool("on", false).unwrap());
+        assert!(!options.get_bool("off", true).unwrap());
+        assert!(options.get_bool("unset", true).unwrap());
+        assert!(matches!(
+            options.get_bool("bad", true),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+}
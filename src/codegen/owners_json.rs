@@ -0,0 +1,101 @@
+//! Geno ownership generator.  Writes `owners.json`, listing the declarations each team owns
+//! according to their `@owner("team")` annotations, so that schema changes can be routed to the
+//! right reviewers.
+//!
+//! Declarations without an owner are listed under `unowned`.  Teams and the declarations under
+//! them are sorted by name.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, util::*},
+};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Generator for the `owners-json` format
+pub struct OwnersJsonGenerator;
+
+impl Generator for OwnersJsonGenerator {
+    fn name(&self) -> &str {
+        "owners-json"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&["version", "order"])
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        let schema = &*select_schema(schema, options)?;
+        let mut contents = serde_json::to_string_pretty(&owners(schema)).unwrap();
+
+        contents.push('\n');
+
+        Ok(vec![GeneratedFile::new("owners.json", contents)])
+    }
+}
+
+/// The ownership document for a schema
+pub fn owners(schema: &ast::Schema) -> Value {
+    let mut owners: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    let mut unowned = BTreeSet::new();
+
+    for decl in &schema.declarations {
+        let (ident, annotations) = match decl {
+            ast::Declaration::Enum {
+                ident, annotations, ..
+            }
+            | ast::Declaration::Struct {
+                ident, annotations, ..
+            }
+            | ast::Declaration::Interface {
+                ident, annotations, ..
+            }
+            | ast::Declaration::Topic {
+                ident, annotations, ..
+            } => (ident.as_str(), annotations),
+        };
+
+        match annotations.owner() {
+            Some(owner) => {
+                owners.entry(owner).or_default().insert(ident);
+            }
+            None => {
+                unowned.insert(ident);
+            }
+        }
+    }
+
+    json!({ "owners": owners, "unowned": unowned })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owners_document() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                r#"meta { format = 1 }
+@owner("team-payments") enum currency: u8 { usd = 1 }
+@owner("team-payments") struct payment { amount: u64, currency: currency }
+@owner("team-identity") struct user { name: string }
+struct audit { user: user, payment: payment }"#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            owners(&schema),
+            json!({
+                "owners": {
+                    "team-identity": ["user"],
+                    "team-payments": ["currency", "payment"],
+                },
+                "unowned": ["audit"],
+            })
+        );
+    }
+}
@@ -0,0 +1,618 @@
+//! Geno records generator.  Treats the structs marked `@record` as flat rows for analytics
+//! export, and writes `records.rs`, which gives each of their `rust-serde` types a CSV header and
+//! serializer, an Arrow schema, conversions to and from Arrow record batches, and a Parquet writer.
+//!
+//! Every field of a record is a scalar, which the schema checks, so each field is one CSV cell and
+//! one Arrow column, named by the field's JSON name.  Nulls are empty cells.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, rust_serde, util::*},
+};
+use std::{collections::HashMap, fmt::Write};
+
+/// Generator for the `records` format
+pub struct RecordsGenerator;
+
+/// Rows are the `rust-serde` types
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+/// The column lookup, written once for all records
+const RECORDS_SUPPORT: &str = r#"
+/// A column of a record batch as the array type of its field, which has no nulls unless the field
+/// is nullable
+fn record_column<'a, T: 'static>(
+    batch: &'a arrow::record_batch::RecordBatch,
+    name: &str,
+    nullable: bool,
+) -> Result<&'a T, arrow::error::ArrowError> {
+    use arrow::array::Array as _;
+
+    let column = batch.column_by_name(name).ok_or_else(|| {
+        arrow::error::ArrowError::SchemaError(format!("no column {name}"))
+    })?;
+
+    if !nullable && column.null_count() > 0 {
+        return Err(arrow::error::ArrowError::InvalidArgumentError(format!(
+            "column {name} has nulls"
+        )));
+    }
+    column.as_any().downcast_ref::<T>().ok_or_else(|| {
+        arrow::error::ArrowError::SchemaError(format!(
+            "column {name} has type {}",
+            column.data_type()
+        ))
+    })
+}
+"#;
+
+/// How a field is written as a cell and a column
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column<'a> {
+    Integer(&'a ast::IntegerType),
+    Float(&'a ast::FloatType),
+    String,
+    Bool,
+    /// An enum written as its variant name
+    EnumName(&'a str),
+    /// An enum written as its value, which has the base type
+    EnumInt(&'a str, &'a ast::IntegerType),
+}
+
+impl Column<'_> {
+    /// The Arrow array and data type of the column
+    fn arrow_types(self) -> (&'static str, &'static str) {
+        let integer = |it: &ast::IntegerType| match it {
+            ast::IntegerType::I8 => ("Int8Array", "Int8"),
+            ast::IntegerType::I16 => ("Int16Array", "Int16"),
+            ast::IntegerType::I32 => ("Int32Array", "Int32"),
+            ast::IntegerType::I64 => ("Int64Array", "Int64"),
+            ast::IntegerType::U8 => ("UInt8Array", "UInt8"),
+            ast::IntegerType::U16 => ("UInt16Array", "UInt16"),
+            ast::IntegerType::U32 => ("UInt32Array", "UInt32"),
+            ast::IntegerType::U64 => ("UInt64Array", "UInt64"),
+        };
+
+        match self {
+            Column::Integer(it) | Column::EnumInt(_, it) => integer(it),
+            Column::Float(ast::FloatType::F32) => ("Float32Array", "Float32"),
+            Column::Float(ast::FloatType::F64) => ("Float64Array", "Float64"),
+            Column::String | Column::EnumName(_) => ("StringArray", "Utf8"),
+            Column::Bool => ("BooleanArray", "Boolean"),
+        }
+    }
+}
+
+/// A field of a record, with its column
+type RecordField<'a> = (&'a str, &'a ast::FieldType, Column<'a>);
+
+/// The base type and variants of an enum
+type EnumDecl<'a> = (&'a ast::IntegerType, &'a [(String, ast::IntegerValue)]);
+
+struct Context<'a> {
+    enums: HashMap<&'a str, EnumDecl<'a>>,
+    naming: Naming,
+    /// Enums are written as their values rather than their names, as with `rust-serde`'s
+    /// `enum_repr=int`
+    enum_ints: bool,
+}
+
+impl<'a> Context<'a> {
+    fn column(&self, ft: &'a ast::FieldType) -> Option<Column<'a>> {
+        match ft {
+            ast::FieldType::Builtin(ast::BuiltinType::Integer(it), _) => Some(Column::Integer(it)),
+            ast::FieldType::Builtin(ast::BuiltinType::Float(ft), _) => Some(Column::Float(ft)),
+            ast::FieldType::Builtin(ast::BuiltinType::String, _) => Some(Column::String),
+            ast::FieldType::Builtin(ast::BuiltinType::Bool, _) => Some(Column::Bool),
+            ast::FieldType::UserDefined(name, _) => {
+                let (base_type, _) = self.enums.get(name.as_str())?;
+
+                Some(if self.enum_ints {
+                    Column::EnumInt(name, base_type)
+                } else {
+                    Column::EnumName(name)
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Generator for RecordsGenerator {
+    fn name(&self) -> &str {
+        "records"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "enum_repr",
+            "parquet",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let ctx = Context {
+            enums: schema
+                .declarations
+                .iter()
+                .filter_map(|decl| match decl {
+                    ast::Declaration::Enum {
+                        ident,
+                        base_type,
+                        variants,
+                        ..
+                    } => Some((ident.as_str(), (base_type, variants.as_slice()))),
+                    _ => None,
+                })
+                .collect(),
+            naming: Naming::from_options(
+                options,
+                rust_serde::DEFAULT_NAMING,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )?,
+            enum_ints: match options.get("enum_repr") {
+                None | Some("string") => false,
+                Some("int") => true,
+                Some(value) => return Err(GenoError::InvalidOption(format!("enum_repr={value}"))),
+            },
+        };
+        let parquet = options.get_bool("parquet", true)?;
+        let records: Vec<_> = schema
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                ast::Declaration::Struct {
+                    ident,
+                    fields,
+                    annotations,
+                    ..
+                } if annotations.is_record() => Some((ident, fields, annotations)),
+                _ => None,
+            })
+            .collect();
+        let mut w = CodeWriter::new("    ");
+        let mut enums: Vec<&str> = Vec::new();
+
+        w.line("// CSV and Arrow codecs for the @record structs, generated by geno.  Include this file in");
+        if parquet {
+            w.line("// the module that holds the rust-serde types.  Needs the csv and arrow crates, and the");
+            w.line("// parquet crate with its `arrow` feature.");
+        } else {
+            w.line(
+                "// the module that holds the rust-serde types.  Needs the csv and arrow crates.",
+            );
+        }
+        if !records.is_empty() {
+            w.write_str(RECORDS_SUPPORT).unwrap();
+        }
+        for (ident, fields, annotations) in records {
+            let record: Vec<RecordField> = fields
+                .iter()
+                .map(|(name, ft)| {
+                    ctx.column(ft)
+                        .map(|column| (name.as_str(), ft, column))
+                        .ok_or_else(|| {
+                            GenoError::InvalidAnnotation(
+                                format!("{ident}.{name}"),
+                                "@record".to_string(),
+                            )
+                        })
+                })
+                .collect::<Result<_, _>>()?;
+
+            for (_, _, column) in &record {
+                if let Column::EnumName(name) = column
+                    && !enums.contains(name)
+                {
+                    enums.push(name);
+                }
+            }
+            generate_record(&mut w, &ctx, ident, &record, annotations, parquet);
+        }
+        for name in enums {
+            generate_enum_names(&mut w, &ctx, name);
+        }
+
+        Ok(vec![GeneratedFile::new("records.rs", w.into_string())])
+    }
+}
+
+fn generate_record(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    record: &[RecordField],
+    annotations: &ast::Annotations,
+    parquet: bool,
+) {
+    let rust_name = ctx.naming.type_name(ident);
+    let count = record.len();
+
+    w.blank();
+    w.block(&format!("impl {rust_name} {{"), "}", |w| {
+        w.line("/// The CSV header, with the name of each field");
+        w.line(format!(
+            "pub const CSV_HEADER: [&str; {count}] = [{}];",
+            record
+                .iter()
+                .map(|(name, _, _)| format!("{:?}", annotations.json_name(name)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        w.blank();
+        w.line("/// The fields as CSV cells, which are empty for nulls");
+        w.block(
+            &format!("pub fn csv_record(&self) -> [String; {count}] {{"),
+            "}",
+            |w| {
+                w.block("[", "]", |w| {
+                    for (name, ft, column) in record {
+                        let field = format!("self.{}", ctx.naming.field_name(name));
+
+                        w.line(format!("{},", csv_cell(&field, *column, is_nullable(ft))));
+                    }
+                });
+            },
+        );
+        w.blank();
+        w.line("/// Write the CSV header and then a record for each row");
+        w.block(
+            "pub fn write_csv<'a, W: std::io::Write>(writer: W, rows: impl IntoIterator<Item = &'a Self>) -> Result<(), csv::Error> {",
+            "}",
+            |w| {
+                w.line("let mut writer = csv::Writer::from_writer(writer);");
+                w.blank();
+                w.line("writer.write_record(Self::CSV_HEADER)?;");
+                w.block("for row in rows {", "}", |w| {
+                    w.line("writer.write_record(row.csv_record())?;");
+                });
+                w.line("writer.flush()?;");
+                w.line("Ok(())");
+            },
+        );
+        w.blank();
+        w.line("/// The Arrow schema of a batch of rows");
+        w.block("pub fn arrow_schema() -> arrow::datatypes::Schema {", "}", |w| {
+            w.block("arrow::datatypes::Schema::new(vec![", "])", |w| {
+                for (name, ft, column) in record {
+                    w.line(format!(
+                        "arrow::datatypes::Field::new({:?}, arrow::datatypes::DataType::{}, {}),",
+                        annotations.json_name(name),
+                        column.arrow_types().1,
+                        is_nullable(ft)
+                    ));
+                }
+            });
+        });
+        w.blank();
+        w.line("/// Convert rows to an Arrow record batch");
+        w.block(
+            "pub fn to_record_batch(rows: &[Self]) -> Result<arrow::record_batch::RecordBatch, arrow::error::ArrowError> {",
+            "}",
+            |w| {
+                w.block("let columns: Vec<arrow::array::ArrayRef> = vec![", "];", |w| {
+                    for (name, ft, column) in record {
+                        w.line(format!(
+                            "std::sync::Arc::new(arrow::array::{}::{}),",
+                            column.arrow_types().0,
+                            to_array(ctx, &ctx.naming.field_name(name), *column, is_nullable(ft))
+                        ));
+                    }
+                });
+                w.blank();
+                w.line(
+                    "arrow::record_batch::RecordBatch::try_new(std::sync::Arc::new(Self::arrow_schema()), columns)",
+                );
+            },
+        );
+        w.blank();
+        w.line("/// Convert an Arrow record batch to rows, finding the columns by name");
+        w.block(
+            "pub fn from_record_batch(batch: &arrow::record_batch::RecordBatch) -> Result<Vec<Self>, arrow::error::ArrowError> {",
+            "}",
+            |w| {
+                if record.iter().any(|(_, ft, _)| is_nullable(ft)) {
+                    w.line("use arrow::array::Array as _;");
+                    w.blank();
+                }
+                for (name, ft, column) in record {
+                    w.line(format!(
+                        "let {} = record_column::<arrow::array::{}>(batch, {:?}, {})?;",
+                        column_var(name),
+                        column.arrow_types().0,
+                        annotations.json_name(name),
+                        is_nullable(ft)
+                    ));
+                }
+                w.blank();
+                w.line("let mut rows = Vec::with_capacity(batch.num_rows());");
+                w.blank();
+                w.block("for i in 0..batch.num_rows() {", "}", |w| {
+                    w.block("rows.push(Self {", "});", |w| {
+                        for (name, ft, column) in record {
+                            let column_var = column_var(name);
+                            let value = from_array(ctx, &column_var, *column);
+
+                            if is_nullable(ft) {
+                                w.line(format!(
+                                    "{}: if {column_var}.is_valid(i) {{ Some({value}) }} else {{ None }},",
+                                    ctx.naming.field_name(name)
+                                ));
+                            } else {
+                                w.line(format!("{}: {value},", ctx.naming.field_name(name)));
+                            }
+                        }
+                    });
+                });
+                w.line("Ok(rows)");
+            },
+        );
+        if parquet {
+            w.blank();
+            w.line("/// Write rows to Parquet, as a single row group");
+            w.block(
+                "pub fn write_parquet<W: std::io::Write + Send>(writer: W, rows: &[Self]) -> Result<(), parquet::errors::ParquetError> {",
+                "}",
+                |w| {
+                    w.line("let batch = Self::to_record_batch(rows)?;");
+                    w.line("let mut writer = parquet::arrow::ArrowWriter::try_new(writer, batch.schema(), None)?;");
+                    w.blank();
+                    w.line("writer.write(&batch)?;");
+                    w.line("writer.close()?;");
+                    w.line("Ok(())");
+                },
+            );
+        }
+    });
+}
+
+/// The local variable holding the column of a field
+fn column_var(name: &str) -> String {
+    format!("{}_column", to_snake_case(name))
+}
+
+/// The CSV cell of a field
+fn csv_cell(field: &str, column: Column, nullable: bool) -> String {
+    let cell = |value: &str| match column {
+        Column::String => value.to_string(),
+        Column::EnumName(_) => format!("{value}.as_str().to_string()"),
+        Column::EnumInt(_, it) => format!("{}::from({value}).to_string()", integer_type_str(it)),
+        _ => format!("{value}.to_string()"),
+    };
+
+    match (column, nullable) {
+        (Column::String | Column::EnumInt(..), false) => cell(&format!("{field}.clone()")),
+        (_, false) => cell(field),
+        (Column::String, true) => format!("{field}.clone().unwrap_or_default()"),
+        (Column::EnumName(_), true) => format!(
+            "{field}.as_ref().map(|value| {}).unwrap_or_default()",
+            cell("value")
+        ),
+        (Column::EnumInt(..), true) => format!(
+            "{field}.clone().map(|value| {}).unwrap_or_default()",
+            cell("value")
+        ),
+        (_, true) => format!("{field}.map(|value| {}).unwrap_or_default()", cell("value")),
+    }
+}
+
+/// The constructor call that makes the array of a column from `rows`
+fn to_array(ctx: &Context, field: &str, column: Column, nullable: bool) -> String {
+    let values = match (column, nullable) {
+        (Column::String | Column::EnumName(_), false) => format!("row.{field}.as_str()"),
+        (Column::String, true) => format!("row.{field}.as_deref()"),
+        (Column::Bool, false) => format!("Some(row.{field})"),
+        (Column::EnumName(name), true) => {
+            format!(
+                "row.{field}.as_ref().map({}::as_str)",
+                ctx.naming.type_name(name)
+            )
+        }
+        (Column::EnumInt(_, it), false) => {
+            format!("{}::from(row.{field}.clone())", integer_type_str(it))
+        }
+        (Column::EnumInt(_, it), true) => {
+            format!("row.{field}.clone().map({}::from)", integer_type_str(it))
+        }
+        _ => format!("row.{field}"),
+    };
+    let constructor = if nullable || column == Column::Bool {
+        "from_iter"
+    } else {
+        "from_iter_values"
+    };
+
+    format!("{constructor}(rows.iter().map(|row| {values}))")
+}
+
+/// The value of a field in row `i` of its column
+fn from_array(ctx: &Context, column_var: &str, column: Column) -> String {
+    match column {
+        Column::String => format!("{column_var}.value(i).to_string()"),
+        Column::EnumName(name) => format!(
+            "{column_var}.value(i).parse::<{}>().map_err(arrow::error::ArrowError::ParseError)?",
+            ctx.naming.type_name(name)
+        ),
+        Column::EnumInt(name, _) => format!(
+            "{}::try_from({column_var}.value(i)).map_err(arrow::error::ArrowError::ParseError)?",
+            ctx.naming.type_name(name)
+        ),
+        _ => format!("{column_var}.value(i)"),
+    }
+}
+
+/// Conversions between an enum and the names of its variants, for enums written as names
+fn generate_enum_names(w: &mut CodeWriter, ctx: &Context, ident: &str) {
+    let rust_name = ctx.naming.type_name(ident);
+    let variants = ctx.enums[ident].1;
+
+    w.blank();
+    w.block(&format!("impl {rust_name} {{"), "}", |w| {
+        w.line("/// The name of the variant in the schema");
+        w.block("pub fn as_str(&self) -> &'static str {", "}", |w| {
+            w.block("match self {", "}", |w| {
+                for (variant, _) in variants {
+                    w.line(format!(
+                        "{rust_name}::{} => {variant:?},",
+                        ctx.naming.variant_name(variant)
+                    ));
+                }
+            });
+        });
+    });
+    w.blank();
+    w.block(
+        &format!("impl std::str::FromStr for {rust_name} {{"),
+        "}",
+        |w| {
+            w.line("type Err = String;");
+            w.blank();
+            w.block(
+                "fn from_str(s: &str) -> Result<Self, Self::Err> {",
+                "}",
+                |w| {
+                    w.block("match s {", "}", |w| {
+                        for (variant, _) in variants {
+                            w.line(format!(
+                                "{variant:?} => Ok({rust_name}::{}),",
+                                ctx.naming.variant_name(variant)
+                            ));
+                        }
+                        w.line(format!(
+                            "_ => Err(format!(\"invalid {rust_name} name {{s}}\")),"
+                        ));
+                    });
+                },
+            );
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"meta { format = 1 }
+enum level: u8 { info = 1, warn = 2 }
+@record
+struct event {
+    id: u64,
+    @json(name = "event-name")
+    name: string,
+    level: level,
+    previous: level?,
+    ratio: f32?,
+    ok: bool,
+}
+struct other { events: [event] }
+"#;
+
+    fn generate(options: &[&str]) -> String {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(SCHEMA)
+            .unwrap();
+        let files = RecordsGenerator
+            .generate(&schema, &Options::parse(options).unwrap())
+            .unwrap();
+
+        String::from_utf8(files[0].contents.clone()).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        let output = generate(&[]);
+
+        assert!(output.contains(
+            "pub const CSV_HEADER: [&str; 6] = [\"id\", \"event-name\", \"level\", \"previous\", \"ratio\", \"ok\"];"
+        ));
+        assert!(output.contains(
+            "        [\n            self.id.to_string(),\n            self.name.clone(),\n            self.level.as_str().to_string(),\n            self.previous.as_ref().map(|value| value.as_str().to_string()).unwrap_or_default(),\n            self.ratio.map(|value| value.to_string()).unwrap_or_default(),\n            self.ok.to_string(),\n        ]"
+        ));
+        assert!(output.contains("writer.write_record(Self::CSV_HEADER)?;"));
+        assert!(!output.contains("impl Other"));
+    }
+
+    #[test]
+    fn arrow() {
+        let output = generate(&[]);
+
+        assert!(output.contains(
+            "arrow::datatypes::Field::new(\"event-name\", arrow::datatypes::DataType::Utf8, false),"
+        ));
+        assert!(output.contains(
+            "arrow::datatypes::Field::new(\"ratio\", arrow::datatypes::DataType::Float32, true),"
+        ));
+        assert!(output.contains(
+            "std::sync::Arc::new(arrow::array::UInt64Array::from_iter_values(rows.iter().map(|row| row.id))),"
+        ));
+        assert!(output.contains(
+            "std::sync::Arc::new(arrow::array::StringArray::from_iter(rows.iter().map(|row| row.previous.as_ref().map(Level::as_str)))),"
+        ));
+        assert!(output.contains(
+            "std::sync::Arc::new(arrow::array::BooleanArray::from_iter(rows.iter().map(|row| Some(row.ok)))),"
+        ));
+        assert!(output.contains(
+            "let name_column = record_column::<arrow::array::StringArray>(batch, \"event-name\", false)?;"
+        ));
+        assert!(output.contains(
+            "previous: if previous_column.is_valid(i) { Some(previous_column.value(i).parse::<Level>().map_err(arrow::error::ArrowError::ParseError)?) } else { None },"
+        ));
+        assert!(output.contains("impl std::str::FromStr for Level {"));
+        assert!(output.contains("Level::Warn => \"warn\","));
+        assert!(
+            output.contains("parquet::arrow::ArrowWriter::try_new(writer, batch.schema(), None)?;")
+        );
+    }
+
+    #[test]
+    fn options() {
+        let output = generate(&["enum_repr=int", "parquet=false"]);
+
+        assert!(output.contains("u8::from(self.level.clone()).to_string(),"));
+        assert!(output.contains(
+            "std::sync::Arc::new(arrow::array::UInt8Array::from_iter(rows.iter().map(|row| row.previous.clone().map(u8::from)))),"
+        ));
+        assert!(output.contains(
+            "level: Level::try_from(level_column.value(i)).map_err(arrow::error::ArrowError::ParseError)?,"
+        ));
+        assert!(!output.contains("FromStr"));
+        assert!(!output.contains("parquet::"));
+        assert!(
+            !RecordsGenerator
+                .generate(
+                    &crate::GenoAstBuilder::new("test.geno".into())
+                        .build_from_str("meta { format = 1 }\nstruct a { b: i8 }")
+                        .unwrap(),
+                    &Options::default()
+                )
+                .map(|files| String::from_utf8(files[0].contents.clone()).unwrap())
+                .unwrap()
+                .contains("record_column")
+        );
+    }
+}
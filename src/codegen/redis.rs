@@ -0,0 +1,467 @@
+//! Geno Redis generator.  For each struct with a `@key("user:{id}")` annotation, writes helpers
+//! that build its keys from its fields, and codecs that store its values in the binary wire
+//! format, in `redis.rs` for the `rust-mp` types and `redis.dart` for the `dart-mp` types.
+//!
+//! The keys of a struct also get a pattern for `SCAN`, with each placeholder replaced by `*`.
+//! Enum placeholders are written as the variant's value, so keys don't change when a variant is
+//! renamed.
+use crate::{
+    GenoError, ast,
+    ast::KeyPart,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, dart_mp, rust_serde, util::*},
+};
+
+/// Generator for the `redis` format
+pub struct RedisGenerator;
+
+/// The values are encoded by the `rust-mp` and `dart-mp` types
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+/// A struct whose values are stored under keys
+struct Keyed<'a> {
+    ident: &'a str,
+    template: &'a str,
+    parts: Vec<KeyPart<'a>>,
+    /// The fields in the key, in the order they first appear, with their types
+    fields: Vec<(&'a str, &'a ast::FieldType)>,
+}
+
+impl Generator for RedisGenerator {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "language",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let (rust, dart) = match options.get("language") {
+            None => (true, true),
+            Some("rust") => (true, false),
+            Some("dart") => (false, true),
+            Some(value) => return Err(GenoError::InvalidOption(format!("language={value}"))),
+        };
+        // The type names follow the naming options, as they do for the generator of the types
+        let naming = |defaults| {
+            Naming::from_options(
+                options,
+                defaults,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )
+        };
+        let mut files = Vec::new();
+
+        if rust {
+            let schema = &*select_target(schema, options, Some("rust"))?;
+
+            files.push(GeneratedFile::new(
+                "redis.rs",
+                rust_helpers(schema, &keyed(schema), &naming(rust_serde::DEFAULT_NAMING)?),
+            ));
+        }
+        if dart {
+            let schema = &*select_target(schema, options, Some("dart"))?;
+
+            files.push(GeneratedFile::new(
+                "redis.dart",
+                dart_helpers(schema, &keyed(schema), &naming(dart_mp::DEFAULT_NAMING)?),
+            ));
+        }
+
+        Ok(files)
+    }
+}
+
+/// The structs with a `@key`, which validation has checked
+fn keyed(schema: &ast::Schema) -> Vec<Keyed<'_>> {
+    schema
+        .declarations
+        .iter()
+        .filter_map(|decl| {
+            let ast::Declaration::Struct {
+                ident,
+                fields,
+                annotations,
+                ..
+            } = decl
+            else {
+                return None;
+            };
+            let template = annotations.key()?;
+            let parts = ast::key_parts(template)?;
+            let mut key_fields: Vec<(&str, &ast::FieldType)> = Vec::new();
+
+            for part in &parts {
+                if let KeyPart::Field(name) = part
+                    && !key_fields.iter().any(|(field, _)| field == name)
+                {
+                    let (field, ft) = fields.iter().find(|(field, _)| field == name)?;
+
+                    key_fields.push((field, ft));
+                }
+            }
+
+            Some(Keyed {
+                ident,
+                template,
+                parts,
+                fields: key_fields,
+            })
+        })
+        .collect()
+}
+
+fn is_enum(schema: &ast::Schema, ft: &ast::FieldType) -> bool {
+    let ast::FieldType::UserDefined(name, _) = ft else {
+        return false;
+    };
+
+    schema
+        .declarations
+        .iter()
+        .any(|decl| matches!(decl, ast::Declaration::Enum { ident, .. } if ident == name))
+}
+
+/// A `SCAN` pattern matching every key of the template, with the glob characters in its text
+/// escaped
+fn scan_pattern(parts: &[KeyPart]) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            KeyPart::Text(text) => text
+                .chars()
+                .map(|c| match c {
+                    '*' | '?' | '[' | ']' => format!("\\{c}"),
+                    _ => c.to_string(),
+                })
+                .collect(),
+            KeyPart::Field(_) => "*".to_string(),
+        })
+        .collect()
+}
+
+fn rust_helpers(schema: &ast::Schema, keyed: &[Keyed], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    w.line(
+        "// Redis keys and values for the rust-mp types, generated by geno.  Include this file in",
+    );
+    w.line("// the module that holds them.  The value codecs need the redis crate.");
+    for keyed in keyed {
+        let rust_name = naming.type_name(keyed.ident);
+        let params: Vec<String> = keyed
+            .fields
+            .iter()
+            .map(|(name, ft)| {
+                let param_type = match ft {
+                    ast::FieldType::Builtin(ast::BuiltinType::String, _) => "&str".to_string(),
+                    _ => rust_serde::type_str(ft, naming),
+                };
+
+                format!("{}: {param_type}", naming.field_name(name))
+            })
+            .collect();
+        let args: Vec<String> = keyed
+            .fields
+            .iter()
+            .map(|(name, ft)| match ft {
+                ast::FieldType::Builtin(ast::BuiltinType::String, _) => {
+                    format!("&self.{}", naming.field_name(name))
+                }
+                _ => format!("self.{}", naming.field_name(name)),
+            })
+            .collect();
+        let mut format_str = String::new();
+        let mut format_args = Vec::new();
+
+        for part in &keyed.parts {
+            match part {
+                KeyPart::Text(text) => format_str.push_str(text),
+                KeyPart::Field(name) => {
+                    let (_, ft) = keyed
+                        .fields
+                        .iter()
+                        .find(|(field, _)| field == name)
+                        .unwrap();
+                    let ast::FieldType::UserDefined(enum_name, _) = ft else {
+                        format_str.push_str("{}");
+                        format_args.push(naming.field_name(name));
+                        continue;
+                    };
+                    let base_type = schema.declarations.iter().find_map(|decl| match decl {
+                        ast::Declaration::Enum {
+                            ident, base_type, ..
+                        } if ident == enum_name => Some(integer_type_str(base_type)),
+                        _ => None,
+                    });
+
+                    format_str.push_str("{}");
+                    format_args.push(format!(
+                        "{}::from({})",
+                        base_type.unwrap_or_default(),
+                        naming.field_name(name)
+                    ));
+                }
+            }
+        }
+
+        let format_call = if format_args.is_empty() {
+            format!("{format_str:?}.to_string()")
+        } else {
+            format!("format!({format_str:?}, {})", format_args.join(", "))
+        };
+
+        w.blank();
+        w.block(&format!("impl {rust_name} {{"), "}", |w| {
+            w.line(format!(
+                "/// The template of the keys that `{rust_name}` values are stored under"
+            ));
+            w.line(format!(
+                "pub const KEY_TEMPLATE: &str = {:?};",
+                keyed.template
+            ));
+            w.line(format!(
+                "/// A pattern matching the key of every `{rust_name}`, for `SCAN`"
+            ));
+            w.line(format!(
+                "pub const KEY_PATTERN: &str = {:?};",
+                scan_pattern(&keyed.parts)
+            ));
+            w.blank();
+            w.line(format!(
+                "/// The key of the `{rust_name}` with these fields"
+            ));
+            w.block(
+                &format!("pub fn redis_key_for({}) -> String {{", params.join(", ")),
+                "}",
+                |w| w.line(&format_call),
+            );
+            w.blank();
+            w.line("/// The key this value is stored under");
+            w.block("pub fn redis_key(&self) -> String {", "}", |w| {
+                w.line(format!("Self::redis_key_for({})", args.join(", ")));
+            });
+        });
+
+        w.blank();
+        w.block(
+            &format!("impl redis::ToRedisArgs for {rust_name} {{"),
+            "}",
+            |w| {
+                w.block(
+                    "fn write_redis_args<W: ?Sized + redis::RedisWrite>(&self, out: &mut W) {",
+                    "}",
+                    |w| w.line("out.write_arg(&self.to_bytes());"),
+                );
+            },
+        );
+
+        w.blank();
+        w.block(
+            &format!("impl redis::FromRedisValue for {rust_name} {{"),
+            "}",
+            |w| {
+                w.block(
+                    "fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {",
+                    "}",
+                    |w| {
+                        w.line("let bytes: Vec<u8> = redis::from_redis_value(v)?;");
+                        w.blank();
+                        w.line(format!(
+                            "Self::from_bytes(&bytes).map_err(|err| (redis::ErrorKind::TypeError, \"Invalid {rust_name}\", err.0).into())"
+                        ));
+                    },
+                );
+            },
+        );
+    }
+
+    w.into_string()
+}
+
+fn dart_helpers(schema: &ast::Schema, keyed: &[Keyed], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("  ");
+
+    w.line("// Redis keys and values for the dart-mp types, generated by geno");
+    w.line("import 'dart:typed_data';");
+    w.blank();
+    w.line("import 'geno.dart';");
+    for keyed in keyed {
+        let dart_name = naming.type_name(keyed.ident);
+        let params: Vec<String> = keyed
+            .fields
+            .iter()
+            .map(|(name, ft)| {
+                let param_type = match ft {
+                    ast::FieldType::Builtin(ast::BuiltinType::Integer(_), _) => "int".to_string(),
+                    ast::FieldType::Builtin(ast::BuiltinType::String, _) => "String".to_string(),
+                    ast::FieldType::Builtin(ast::BuiltinType::Bool, _) => "bool".to_string(),
+                    ast::FieldType::UserDefined(enum_name, _) => naming.type_name(enum_name),
+                    _ => unreachable!("key fields are integers, strings, bools or enums"),
+                };
+
+                format!("{param_type} {}", naming.field_name(name))
+            })
+            .collect();
+        let args: Vec<String> = keyed
+            .fields
+            .iter()
+            .map(|(name, _)| naming.field_name(name))
+            .collect();
+        let key: String = keyed
+            .parts
+            .iter()
+            .map(|part| match part {
+                KeyPart::Text(text) => text.to_string(),
+                KeyPart::Field(name) => {
+                    let (_, ft) = keyed
+                        .fields
+                        .iter()
+                        .find(|(field, _)| field == name)
+                        .unwrap();
+                    let dart_field = naming.field_name(name);
+
+                    if is_enum(schema, ft) {
+                        format!("${{{dart_field}.value}}")
+                    } else {
+                        format!("${{{dart_field}}}")
+                    }
+                }
+            })
+            .collect();
+
+        w.blank();
+        w.line(format!("/// Redis keys and values of [{dart_name}]"));
+        w.block(
+            &format!("extension {dart_name}Redis on {dart_name} {{"),
+            "}",
+            |w| {
+                w.line(format!(
+                    "/// The template of the keys that [{dart_name}] values are stored under"
+                ));
+                w.line(format!("static const keyTemplate = '{}';", keyed.template));
+                w.line(format!(
+                    "/// A pattern matching the key of every [{dart_name}], for `SCAN`"
+                ));
+                w.line(format!(
+                    "static const keyPattern = '{}';",
+                    scan_pattern(&keyed.parts).replace('\\', "\\\\")
+                ));
+                w.blank();
+                w.line(format!("/// The key of the [{dart_name}] with these fields"));
+                w.line(format!(
+                    "static String redisKeyFor({}) => '{key}';",
+                    params.join(", ")
+                ));
+                w.blank();
+                w.line("/// The key this value is stored under");
+                w.line(format!(
+                    "String get redisKey => redisKeyFor({});",
+                    args.join(", ")
+                ));
+                w.blank();
+                w.line("/// The value to store, encoded as MessagePack");
+                w.line("Uint8List get redisValue => toBytes();");
+                w.blank();
+                w.line("/// Decode a value read from Redis");
+                w.line(format!(
+                    "static {dart_name} fromRedisValue(List<int> bytes) => {dart_name}.fromBytes(bytes is Uint8List ? bytes : Uint8List.fromList(bytes));"
+                ));
+            },
+        );
+    }
+
+    w.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(input: &str, options: &[&str]) -> Vec<String> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+
+        RedisGenerator
+            .generate(&schema, &Options::parse(options).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|file| String::from_utf8(file.contents).unwrap())
+            .collect()
+    }
+
+    const SCHEMA: &str = r#"meta { format = 1 }
+enum region: u8 { eu = 1, us = 2 }
+@key("session:{region}:{user_id}:{user_id}") struct session { user_id: string, region: region, ttl: i32 }
+@key("config*") struct config { debug: bool }
+struct plain { a: i8 }"#;
+
+    #[test]
+    fn rust() {
+        let files = generate(SCHEMA, &["language=rust"]);
+        let output = &files[0];
+
+        assert_eq!(files.len(), 1);
+        assert!(
+            output.contains(
+                "pub const KEY_TEMPLATE: &str = \"session:{region}:{user_id}:{user_id}\";"
+            )
+        );
+        assert!(output.contains("pub const KEY_PATTERN: &str = \"session:*:*:*\";"));
+        assert!(output.contains(
+            "pub fn redis_key_for(region: Region, user_id: &str) -> String {\n        format!(\"session:{}:{}:{}\", u8::from(region), user_id, user_id)"
+        ));
+        assert!(output.contains("Self::redis_key_for(self.region, &self.user_id)"));
+        assert!(output.contains("impl redis::ToRedisArgs for Session {"));
+        assert!(output.contains("impl redis::FromRedisValue for Session {"));
+        assert!(output.contains("pub const KEY_PATTERN: &str = \"config\\\\*\";"));
+        assert!(output.contains("\"config*\".to_string()"));
+        assert!(!output.contains("impl Plain"));
+    }
+
+    #[test]
+    fn dart() {
+        let files = generate(SCHEMA, &["language=dart"]);
+        let output = &files[0];
+
+        assert!(output.contains("extension SessionRedis on Session {"));
+        assert!(output.contains(
+            "static String redisKeyFor(Region region, String userId) => 'session:${region.value}:${userId}:${userId}';"
+        ));
+        assert!(output.contains("String get redisKey => redisKeyFor(region, userId);"));
+        assert!(output.contains("static const keyPattern = 'config\\\\*';"));
+        assert!(output.contains(
+            "static Session fromRedisValue(List<int> bytes) => Session.fromBytes(bytes is Uint8List ? bytes : Uint8List.fromList(bytes));"
+        ));
+        assert_eq!(generate(SCHEMA, &[]).len(), 2);
+    }
+}
@@ -0,0 +1,408 @@
+//! Geno Rust clap generator.  Treats the structs marked `@cli` as the arguments of a command line
+//! tool, and writes `cli.rs`, with a clap `Parser` for each of them.  Fields become long options,
+//! with their doc comments as help and their `@default` as default values, the structs they hold
+//! are flattened in as `Args`, and the enums they use become `ValueEnum`s.
+//!
+//! The types are written afresh, so they have the same names as the `rust-serde` types, and
+//! belong in a module of their own.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, rust_serde, util::*},
+};
+use std::collections::HashSet;
+
+/// Generator for the `rust-clap` format
+pub struct RustClapGenerator;
+
+/// The arguments are Rust types
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+impl Generator for RustClapGenerator {
+    fn name(&self) -> &str {
+        "rust-clap"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let naming = Naming::from_options(
+            options,
+            rust_serde::DEFAULT_NAMING,
+            &[
+                NamingConvention::Pascal,
+                NamingConvention::Camel,
+                NamingConvention::Snake,
+                NamingConvention::ScreamingSnake,
+                NamingConvention::Preserve,
+            ],
+        )?;
+        let mut used = HashSet::new();
+
+        for decl in &schema.declarations {
+            if let ast::Declaration::Struct {
+                ident, annotations, ..
+            } = decl
+                && annotations.is_cli()
+            {
+                collect_types(schema, ident, &mut Vec::new(), &mut used)?;
+            }
+        }
+
+        let mut w = CodeWriter::new("    ");
+
+        w.line("// Command line arguments for the @cli structs, generated by geno.  Include this file in a");
+        w.line("// module of its own.  Needs the clap crate with its `derive` feature.");
+        for decl in &schema.declarations {
+            match decl {
+                ast::Declaration::Struct {
+                    ident,
+                    fields,
+                    visibility,
+                    annotations,
+                    ..
+                } if used.contains(ident.as_str()) => {
+                    w.blank();
+                    generate_struct(
+                        &mut w,
+                        schema,
+                        &naming,
+                        ident,
+                        fields,
+                        *visibility,
+                        annotations,
+                    );
+                }
+                ast::Declaration::Enum {
+                    ident,
+                    variants,
+                    visibility,
+                    annotations,
+                    ..
+                } if used.contains(ident.as_str()) => {
+                    w.blank();
+                    generate_enum(&mut w, &naming, ident, variants, *visibility, annotations);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(vec![GeneratedFile::new("cli.rs", w.into_string())])
+    }
+}
+
+/// The struct or enum named `ident`
+fn find<'a>(schema: &'a ast::Schema, ident: &str) -> Option<&'a ast::Declaration> {
+    schema.declarations.iter().find(|decl| match decl {
+        ast::Declaration::Struct { ident: name, .. }
+        | ast::Declaration::Enum { ident: name, .. } => name == ident,
+        _ => false,
+    })
+}
+
+/// Add the struct `ident`, and the structs and enums its arguments use, to `used`, checking that
+/// each field is an argument clap can parse.  `stack` holds the structs being flattened.
+fn collect_types<'a>(
+    schema: &'a ast::Schema,
+    ident: &'a str,
+    stack: &mut Vec<&'a str>,
+    used: &mut HashSet<&'a str>,
+) -> Result<(), GenoError> {
+    let Some(ast::Declaration::Struct { fields, .. }) = find(schema, ident) else {
+        return Ok(());
+    };
+
+    used.insert(ident);
+    stack.push(ident);
+    for (name, ft) in fields {
+        let invalid =
+            || GenoError::InvalidAnnotation(format!("{ident}.{name}"), "@cli".to_string());
+        let element = match ft {
+            ast::FieldType::Array(element, None, _) => element,
+            ast::FieldType::Array(..) | ast::FieldType::Map(..) => return Err(invalid()),
+            _ => ft,
+        };
+
+        match element {
+            ast::FieldType::Builtin(ast::BuiltinType::LocalizedString, _) => {
+                return Err(invalid());
+            }
+            ast::FieldType::Builtin(_, nullable) if element != ft && *nullable => {
+                return Err(invalid());
+            }
+            ast::FieldType::Builtin(..) => {}
+            ast::FieldType::UserDefined(type_name, nullable) => match find(schema, type_name) {
+                Some(ast::Declaration::Enum { ident, .. }) if element == ft || !*nullable => {
+                    used.insert(ident);
+                }
+                Some(ast::Declaration::Struct { ident, .. })
+                    if element == ft && !*nullable && !stack.contains(&ident.as_str()) =>
+                {
+                    collect_types(schema, ident, stack, used)?;
+                }
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        }
+    }
+    stack.pop();
+
+    Ok(())
+}
+
+/// Write doc comments, which clap uses as help
+fn write_docs(w: &mut CodeWriter, comments: &[String]) {
+    for comment in comments {
+        w.line(format!("/// {}", ast::Comments::text(comment)));
+    }
+}
+
+fn generate_struct(
+    w: &mut CodeWriter,
+    schema: &ast::Schema,
+    naming: &Naming,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+    visibility: ast::Visibility,
+    annotations: &ast::Annotations,
+) {
+    let rust_name = naming.type_name(ident);
+    let vis = rust_serde::visibility_str(visibility);
+
+    write_docs(w, &annotations.comments.declaration);
+    if annotations.is_cli() {
+        w.line("#[derive(Debug, Clone, PartialEq, clap::Parser)]");
+        w.line("#[command(version)]");
+    } else {
+        w.line("#[derive(Debug, Clone, PartialEq, clap::Args)]");
+    }
+    w.block(&format!("{vis} struct {rust_name} {{"), "}", |w| {
+        for (name, ft) in fields {
+            let is_enum = |type_name: &str| {
+                matches!(find(schema, type_name), Some(ast::Declaration::Enum { .. }))
+            };
+            let (flatten, value_enum) = match ft {
+                ast::FieldType::UserDefined(type_name, _) => {
+                    (!is_enum(type_name), is_enum(type_name))
+                }
+                ast::FieldType::Array(element, _, _) => (
+                    false,
+                    matches!(&**element, ast::FieldType::UserDefined(type_name, _) if is_enum(type_name)),
+                ),
+                _ => (false, false),
+            };
+
+            write_docs(w, annotations.comments.member(name));
+            if flatten {
+                w.line("#[command(flatten)]");
+            } else {
+                let mut args = vec!["long".to_string()];
+
+                if value_enum {
+                    args.push("value_enum".to_string());
+                }
+                args.extend(range_parser(ft, annotations.member(name)));
+                if let Some(value) = annotations.default_value(name) {
+                    args.extend(default_args(ft, value));
+                }
+                w.line(format!("#[arg({})]", args.join(", ")));
+            }
+            w.line(format!(
+                "pub {}: {},",
+                naming.field_name(name),
+                rust_serde::type_str(ft, naming)
+            ));
+        }
+    });
+}
+
+/// A `value_parser` for an integer with a `@range`
+fn range_parser(ft: &ast::FieldType, annotations: &[ast::Annotation]) -> Option<String> {
+    let ast::FieldType::Builtin(ast::BuiltinType::Integer(it), _) = ft else {
+        return None;
+    };
+    let bounds = annotations
+        .iter()
+        .find(|annotation| annotation.name == "range")?
+        .bounds()?;
+    let range = match (bounds.min, bounds.max) {
+        (Some(min), Some(max)) => format!("{min}..={max}"),
+        (Some(min), None) => format!("{min}.."),
+        (None, Some(max)) => format!("..={max}"),
+        (None, None) => return None,
+    };
+
+    Some(format!(
+        "value_parser = clap::value_parser!({}).range({range})",
+        integer_type_str(it)
+    ))
+}
+
+/// The arguments that give a field its `@default`, as the text clap parses, which also works for
+/// nullable fields
+fn default_args(ft: &ast::FieldType, value: &ast::AnnotationValue) -> Vec<String> {
+    let text = match (ft, value) {
+        (ast::FieldType::Builtin(ast::BuiltinType::Bool, false), _) => {
+            if *value != ast::AnnotationValue::Identifier("true".to_string()) {
+                return Vec::new();
+            }
+            // A flag that is on unless it is turned off, as in `--color false`
+            return vec![
+                "default_value = \"true\"".to_string(),
+                "action = clap::ArgAction::Set".to_string(),
+            ];
+        }
+        (
+            ast::FieldType::Builtin(ast::BuiltinType::Float(_), _),
+            ast::AnnotationValue::Integer(n),
+        ) => format!("{n}.0"),
+        (_, ast::AnnotationValue::Integer(n)) => n.to_string(),
+        (_, ast::AnnotationValue::String(s) | ast::AnnotationValue::Identifier(s)) => s.clone(),
+    };
+
+    vec![format!("default_value = {text:?}")]
+}
+
+fn generate_enum(
+    w: &mut CodeWriter,
+    naming: &Naming,
+    ident: &str,
+    variants: &[(String, ast::IntegerValue)],
+    visibility: ast::Visibility,
+    annotations: &ast::Annotations,
+) {
+    let rust_name = naming.type_name(ident);
+    let vis = rust_serde::visibility_str(visibility);
+
+    write_docs(w, &annotations.comments.declaration);
+    w.line("#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]");
+    w.block(&format!("{vis} enum {rust_name} {{"), "}", |w| {
+        for (variant, _) in variants {
+            write_docs(w, annotations.comments.member(variant));
+            w.line(format!("#[value(name = {variant:?})]"));
+            w.line(format!("{},", naming.variant_name(variant)));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"meta { format = 1 }
+// How much to log
+enum level: u8 {
+    // Only problems
+    warn = 1,
+    debug = 2,
+}
+struct tls {
+    // Certificate file
+    cert: string?,
+}
+// Serves files
+@cli
+struct serve {
+    // Port to listen on
+    @range(1, 65535) @default(8080)
+    port: u16,
+    @default("localhost")
+    host: string,
+    @default(debug)
+    level: level,
+    @default(true)
+    color: bool,
+    verbose: bool,
+    @default(1)
+    ratio: f32,
+    roots: [string],
+    tls: tls,
+}
+struct unused { a: i8 }
+"#;
+
+    fn generate(input: &str) -> Result<String, GenoError> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+        let files = RustClapGenerator.generate(&schema, &Options::default())?;
+
+        Ok(String::from_utf8(files[0].contents.clone()).unwrap())
+    }
+
+    #[test]
+    fn parser() {
+        let output = generate(SCHEMA).unwrap();
+
+        assert!(output.contains(
+            "/// Serves files\n#[derive(Debug, Clone, PartialEq, clap::Parser)]\n#[command(version)]\npub struct Serve {"
+        ));
+        assert!(output.contains(
+            "    /// Port to listen on\n    #[arg(long, value_parser = clap::value_parser!(u16).range(1..=65535), default_value = \"8080\")]\n    pub port: u16,"
+        ));
+        assert!(
+            output
+                .contains("    #[arg(long, default_value = \"localhost\")]\n    pub host: String,")
+        );
+        assert!(output.contains(
+            "    #[arg(long, value_enum, default_value = \"debug\")]\n    pub level: Level,"
+        ));
+        assert!(output.contains(
+            "    #[arg(long, default_value = \"true\", action = clap::ArgAction::Set)]\n    pub color: bool,"
+        ));
+        assert!(output.contains("    #[arg(long)]\n    pub verbose: bool,"));
+        assert!(output.contains("    #[arg(long, default_value = \"1.0\")]\n    pub ratio: f32,"));
+        assert!(output.contains("    #[arg(long)]\n    pub roots: Vec<String>,"));
+        assert!(output.contains("    #[command(flatten)]\n    pub tls: Tls,"));
+        assert!(output.contains(
+            "#[derive(Debug, Clone, PartialEq, clap::Args)]\npub struct Tls {\n    /// Certificate file\n    #[arg(long)]\n    pub cert: Option<String>,"
+        ));
+        assert!(output.contains(
+            "/// How much to log\n#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]\npub enum Level {\n    /// Only problems\n    #[value(name = \"warn\")]\n    Warn,"
+        ));
+        assert!(!output.contains("Unused"));
+    }
+
+    #[test]
+    fn invalid_fields() {
+        for field in [
+            "a: {string: i8}",
+            "a: lstring",
+            "a: [i8; 2]",
+            "a: [i8?]",
+            "a: t?",
+            "a: [t]",
+        ] {
+            let input = format!(
+                "meta {{ format = 1 }}\nstruct t {{ b: i8 }}\n@cli\nstruct s {{ {field} }}"
+            );
+
+            assert!(
+                matches!(generate(&input), Err(GenoError::InvalidAnnotation(path, _)) if path == "s.a"),
+                "{field}"
+            );
+        }
+    }
+}
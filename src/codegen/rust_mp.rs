@@ -0,0 +1,3050 @@
+//! Geno Rust/MessagePack generator.  Produces the same bytes as the `dart-mp` generator, so the
+//! two can exchange messages.  See the `dart_mp` module for a description of the protocol.
+//!
+//! The generated file has no dependencies outside the standard library.  It contains a small
+//! private MessagePack reader and writer, a `DecodeError` type and, for every declaration, a
+//! type with `to_bytes` and `from_bytes` methods.
+use crate::{
+    GenoError, ast,
+    codegen::{
+        CodeSink, GeneratedFile, GeneratedFiles, Generator, Options,
+        rust_serde::{
+            DEFAULT_NAMING, generate_enum_conversions, generate_enum_map, generate_trait,
+            generate_trait_impls, type_str, visibility_str,
+        },
+        util::*,
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    path::Path,
+};
+
+/// Generator for the `rust-mp` format
+pub struct RustMpGenerator;
+
+/// Floats aren't `Eq` or `Hash`, so they can't be `HashMap` keys
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+impl Generator for RustMpGenerator {
+    fn name(&self) -> &str {
+        "rust-mp"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "int64",
+            "json",
+            "framing",
+            "tokio_codec",
+            "property_tests",
+            "envelope",
+            "compression",
+            "compress_above",
+            "max_decompressed",
+            "size_bounds",
+            "borrowed",
+            "mask_sensitive",
+            "redaction",
+            "enum_helpers",
+            "roots_only",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let config = Config::from_options(schema, options)?;
+
+        Ok(vec![GeneratedFile::new(
+            "geno.rs",
+            generate(schema, config),
+        )])
+    }
+    fn generate_to(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+        sink: &mut dyn CodeSink,
+    ) -> Result<(), GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let config = Config::from_options(schema, options)?;
+
+        sink.write_file(Path::new("geno.rs"), &mut |out| {
+            let mut w = CodeWriter::to_writer("    ", out);
+
+            write_file(&mut w, schema, config.clone());
+            Ok(w.finish()?)
+        })
+    }
+}
+
+/// Write the `main.rs` of a program that decodes each test vector listed in `<input>/index.txt`
+/// and encodes it again into `<output>`, for the [crate::roundtrip] harness.  The program
+/// expects the generated code in `geno.rs` alongside it.
+pub(crate) fn generate_driver(
+    schema: &ast::Schema,
+    options: &Options,
+) -> Result<String, GenoError> {
+    schema.check_capabilities(&CAPABILITIES)?;
+
+    let schema = &*select_target(schema, options, Some("rust"))?;
+    let config = Config::from_options(schema, options)?;
+    let mut w = CodeWriter::new("    ");
+
+    writeln!(w, "mod geno;").unwrap();
+    w.blank();
+    writeln!(w, "use std::{{env, fs, path::PathBuf, process::exit}};").unwrap();
+    w.blank();
+    w.block(
+        "fn reencode(type_name: &str, bytes: &[u8]) -> Result<Vec<u8>, String> {",
+        "}",
+        |w| {
+            w.block("match type_name {", "}", |w| {
+                for decl in &schema.declarations {
+                    let ident = match decl {
+                        ast::Declaration::Enum { ident, .. } => ident,
+                        ast::Declaration::Struct { ident, .. } => ident,
+                        ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => {
+                            continue;
+                        }
+                    };
+                    if !config.roots.contains(ident) {
+                        continue;
+                    }
+                    writeln!(
+                        w,
+                        "\"{ident}\" => geno::{}::from_bytes(bytes).map(|value| value.to_bytes()).map_err(|err| err.to_string()),",
+                        config.naming.type_name(ident)
+                    )
+                    .unwrap();
+                }
+                writeln!(w, "_ => Err(format!(\"unknown type '{{type_name}}'\")),").unwrap();
+            });
+        },
+    );
+    w.blank();
+    w.block("fn main() {", "}", |w| {
+        writeln!(
+            w,
+            "let args: Vec<PathBuf> = env::args_os().skip(1).map(PathBuf::from).collect();"
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "let index = fs::read_to_string(args[0].join(\"index.txt\")).expect(\"unable to read index.txt\");"
+        )
+        .unwrap();
+        writeln!(w, "let mut failed = false;").unwrap();
+        w.blank();
+        w.block("for line in index.lines() {", "}", |w| {
+            writeln!(
+                w,
+                "let Some((type_name, file)) = line.split_once(' ') else {{ continue }};"
+            )
+            .unwrap();
+            writeln!(
+                w,
+                "let result = fs::read(args[0].join(file)).map_err(|err| err.to_string()).and_then(|bytes| reencode(type_name, &bytes));"
+            )
+            .unwrap();
+            w.block("match result {", "}", |w| {
+                writeln!(
+                    w,
+                    "Ok(bytes) => fs::write(args[1].join(file), bytes).expect(\"unable to write output\"),"
+                )
+                .unwrap();
+                w.block("Err(err) => {", "}", |w| {
+                    writeln!(w, "eprintln!(\"{{file}}: {{err}}\");").unwrap();
+                    writeln!(w, "failed = true;").unwrap();
+                });
+            });
+        });
+        writeln!(w, "exit(i32::from(failed));").unwrap();
+    });
+
+    Ok(w.into_string())
+}
+
+/// Settings for one run of the generator, read from the [Options]
+#[derive(Clone)]
+struct Config {
+    naming: Naming,
+    /// Struct layout on the wire
+    wire: WireFormat,
+    /// Derive `Serialize` and `Deserialize` with the same JSON field names as `dart-mp`
+    json: bool,
+    /// Generate `write_delimited_to` and `read_delimited_from`
+    framing: bool,
+    /// The [roots](ast::Schema::roots) that get a `tokio_util` codec, with the `tokio_codec`
+    /// option
+    tokio_codec: Option<HashSet<String>>,
+    /// Generate `proptest` strategies for every type and a round trip test for each root
+    property_tests: bool,
+    /// Generate `to_envelope` and `from_envelope`
+    envelope: Option<Envelope>,
+    /// Compress large messages in `to_bytes` and decompress them in `from_bytes`
+    compression: Option<Compression>,
+    /// Generate `packed_size_upper_bound`
+    size_bounds: bool,
+    /// Generate `unpack_into` and the borrowing `...Ref` types
+    borrowed: bool,
+    /// Show `@sensitive` and `@pii` fields as `***` in `Debug` output
+    mask_sensitive: bool,
+    /// Generate `redacted()` and a `Display` impl for every struct
+    redaction: Option<Redaction>,
+    /// Write a `map` method with one argument per variant for every enum
+    enum_helpers: bool,
+    /// Types that get public methods to encode and decode them
+    roots: Roots,
+    /// How 64-bit integer fields without an `@int64` annotation are packed
+    int64: Int64,
+}
+
+impl Config {
+    fn from_options(schema: &ast::Schema, options: &Options) -> Result<Self, GenoError> {
+        let int64 = Int64::from_options(options)?;
+        let wire = WireFormat::from_options(schema, options)?;
+        let borrowed = options.get_bool("borrowed", false)?;
+
+        int64.check(schema)?;
+        if borrowed && wire.protocol().layout == StructLayout::Map {
+            return Err(GenoError::InvalidOption(
+                "borrowed=true needs wire=positional or wire=bitmap".to_string(),
+            ));
+        }
+        // A `...Ref` can't borrow from bytes that were decompressed into a temporary buffer
+        if borrowed
+            && options
+                .get("compression")
+                .is_some_and(|value| value != "none")
+        {
+            return Err(GenoError::InvalidOption(
+                "borrowed=true can't be used with compression".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            naming: Naming::from_options(
+                options,
+                DEFAULT_NAMING,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )?,
+            wire,
+            json: options.get_bool("json", false)?,
+            framing: options.get_bool("framing", false)?,
+            tokio_codec: options
+                .get_bool("tokio_codec", false)?
+                .then(|| schema.roots().into_iter().map(String::from).collect()),
+            property_tests: options.get_bool("property_tests", false)?,
+            envelope: Envelope::from_options(schema, options)?,
+            compression: Compression::from_options(options)?,
+            size_bounds: options.get_bool("size_bounds", false)?,
+            borrowed,
+            mask_sensitive: options.get_bool("mask_sensitive", true)?,
+            redaction: Redaction::from_options(schema, options)?,
+            enum_helpers: options.get_bool("enum_helpers", false)?,
+            roots: Roots::from_options(schema, options)?,
+            int64,
+        })
+    }
+}
+
+/// Everything the code generation functions need to know about the schema being generated
+#[derive(Clone)]
+struct Context<'a> {
+    config: Config,
+    /// Base type of each enum
+    enum_types: HashMap<&'a str, &'a ast::IntegerType>,
+    /// Most bytes each struct packs to, with the `size_bounds` option
+    size_bounds: HashMap<&'a str, Option<usize>>,
+    /// Structs that hold a string, directly or not, and so get a `...Ref` type with the
+    /// `borrowed` option
+    borrowing: HashSet<&'a str>,
+    /// Whether code is being generated for the `...Ref` types, which borrow strings from the
+    /// bytes they were decoded from
+    borrowed: bool,
+    /// The [boxed_fields] of the schema
+    boxed: HashSet<(&'a str, &'a str)>,
+}
+
+impl Context<'_> {
+    /// Whether a field of a struct is one of the [boxed_fields]
+    fn is_boxed(&self, ident: &str, field_name: &str) -> bool {
+        self.boxed.contains(&(ident, field_name))
+    }
+}
+
+/// MessagePack reader and writer included in every generated file.  Integers are written in
+/// their smallest encoding and floats always as `float 64`, matching the Dart `messagepack`
+/// package, and the readers accept any encoding of the expected type.
+const RUNTIME: &str = r#"#[allow(dead_code)]
+mod mp {
+    use super::DecodeError;
+
+    pub fn write_nil(wr: &mut Vec<u8>) {
+        wr.push(0xc0);
+    }
+
+    pub fn write_bool(wr: &mut Vec<u8>, value: bool) {
+        wr.push(if value { 0xc3 } else { 0xc2 });
+    }
+
+    pub fn write_int(wr: &mut Vec<u8>, value: i64) {
+        if value >= 0 {
+            write_uint(wr, value as u64);
+        } else if value >= -32 {
+            wr.push(value as u8);
+        } else if value >= i8::MIN as i64 {
+            wr.push(0xd0);
+            wr.push(value as u8);
+        } else if value >= i16::MIN as i64 {
+            wr.push(0xd1);
+            wr.extend_from_slice(&(value as i16).to_be_bytes());
+        } else if value >= i32::MIN as i64 {
+            wr.push(0xd2);
+            wr.extend_from_slice(&(value as i32).to_be_bytes());
+        } else {
+            wr.push(0xd3);
+            wr.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    pub fn write_uint(wr: &mut Vec<u8>, value: u64) {
+        if value < 0x80 {
+            wr.push(value as u8);
+        } else if value <= u8::MAX as u64 {
+            wr.push(0xcc);
+            wr.push(value as u8);
+        } else if value <= u16::MAX as u64 {
+            wr.push(0xcd);
+            wr.extend_from_slice(&(value as u16).to_be_bytes());
+        } else if value <= u32::MAX as u64 {
+            wr.push(0xce);
+            wr.extend_from_slice(&(value as u32).to_be_bytes());
+        } else {
+            wr.push(0xcf);
+            wr.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    pub fn write_f64(wr: &mut Vec<u8>, value: f64) {
+        wr.push(0xcb);
+        wr.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_str(wr: &mut Vec<u8>, value: &str) {
+        let len = value.len();
+
+        if len < 32 {
+            wr.push(0xa0 | len as u8);
+        } else if len <= u8::MAX as usize {
+            wr.push(0xd9);
+            wr.push(len as u8);
+        } else if len <= u16::MAX as usize {
+            wr.push(0xda);
+            wr.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            wr.push(0xdb);
+            wr.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        wr.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn write_array_len(wr: &mut Vec<u8>, len: usize) {
+        write_container_len(wr, len, 0x90, 0xdc);
+    }
+
+    pub fn write_map_len(wr: &mut Vec<u8>, len: usize) {
+        write_container_len(wr, len, 0x80, 0xde);
+    }
+
+    pub fn write_bin(wr: &mut Vec<u8>, bytes: &[u8]) {
+        let len = bytes.len();
+
+        if len <= u8::MAX as usize {
+            wr.push(0xc4);
+            wr.push(len as u8);
+        } else if len <= u16::MAX as usize {
+            wr.push(0xc5);
+            wr.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            wr.push(0xc6);
+            wr.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        wr.extend_from_slice(bytes);
+    }
+
+    fn write_container_len(wr: &mut Vec<u8>, len: usize, fix: u8, marker16: u8) {
+        if len < 16 {
+            wr.push(fix | len as u8);
+        } else if len <= u16::MAX as usize {
+            wr.push(marker16);
+            wr.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            wr.push(marker16 + 1);
+            wr.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+
+    fn read_bytes<'a>(rd: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+        if rd.len() < len {
+            return Err(DecodeError("unexpected end of input".to_string()));
+        }
+        let (bytes, rest) = rd.split_at(len);
+        *rd = rest;
+        Ok(bytes)
+    }
+
+    fn read_be<const N: usize>(rd: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+        Ok(read_bytes(rd, N)?.try_into().unwrap())
+    }
+
+    fn read_marker(rd: &mut &[u8]) -> Result<u8, DecodeError> {
+        Ok(read_be::<1>(rd)?[0])
+    }
+
+    fn unexpected(marker: u8, expected: &str) -> DecodeError {
+        DecodeError(format!("expected {expected}, found marker 0x{marker:02x}"))
+    }
+
+    pub fn missing(path: &str) -> DecodeError {
+        DecodeError(format!("{path} is missing"))
+    }
+
+    /// Consume a nil if it is the next value
+    pub fn read_nil(rd: &mut &[u8]) -> bool {
+        if rd.first() == Some(&0xc0) {
+            *rd = &rd[1..];
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn read_bool(rd: &mut &[u8]) -> Result<bool, DecodeError> {
+        match read_marker(rd)? {
+            0xc2 => Ok(false),
+            0xc3 => Ok(true),
+            marker => Err(unexpected(marker, "bool")),
+        }
+    }
+
+    pub fn read_int<T: TryFrom<i128>>(rd: &mut &[u8]) -> Result<T, DecodeError> {
+        let marker = read_marker(rd)?;
+        let value = match marker {
+            0x00..=0x7f => marker as i128,
+            0xe0..=0xff => marker as i8 as i128,
+            0xcc => u8::from_be_bytes(read_be(rd)?) as i128,
+            0xcd => u16::from_be_bytes(read_be(rd)?) as i128,
+            0xce => u32::from_be_bytes(read_be(rd)?) as i128,
+            0xcf => u64::from_be_bytes(read_be(rd)?) as i128,
+            0xd0 => i8::from_be_bytes(read_be(rd)?) as i128,
+            0xd1 => i16::from_be_bytes(read_be(rd)?) as i128,
+            0xd2 => i32::from_be_bytes(read_be(rd)?) as i128,
+            0xd3 => i64::from_be_bytes(read_be(rd)?) as i128,
+            _ => return Err(unexpected(marker, "integer")),
+        };
+
+        T::try_from(value).map_err(|_| DecodeError(format!("integer {value} is out of range")))
+    }
+
+    /// Read an integer packed as a decimal string
+    pub fn read_int_str<T: std::str::FromStr>(rd: &mut &[u8], path: &str) -> Result<T, DecodeError> {
+        let value = read_str(rd)?;
+
+        value
+            .parse()
+            .map_err(|_| DecodeError(format!("{path} is not an integer: {value:?}")))
+    }
+
+    pub fn read_f64(rd: &mut &[u8]) -> Result<f64, DecodeError> {
+        match read_marker(rd)? {
+            0xca => Ok(f32::from_be_bytes(read_be(rd)?) as f64),
+            0xcb => Ok(f64::from_be_bytes(read_be(rd)?)),
+            marker => Err(unexpected(marker, "float")),
+        }
+    }
+
+    pub fn read_f32(rd: &mut &[u8]) -> Result<f32, DecodeError> {
+        Ok(read_f64(rd)? as f32)
+    }
+
+    pub fn read_str(rd: &mut &[u8]) -> Result<String, DecodeError> {
+        read_str_ref(rd).map(String::from)
+    }
+
+    pub fn read_str_ref<'a>(rd: &mut &'a [u8]) -> Result<&'a str, DecodeError> {
+        let marker = read_marker(rd)?;
+        let len = match marker {
+            0xa0..=0xbf => (marker & 0x1f) as usize,
+            0xd9 => u8::from_be_bytes(read_be(rd)?) as usize,
+            0xda => u16::from_be_bytes(read_be(rd)?) as usize,
+            0xdb => u32::from_be_bytes(read_be(rd)?) as usize,
+            _ => return Err(unexpected(marker, "string")),
+        };
+
+        std::str::from_utf8(read_bytes(rd, len)?)
+            .map_err(|_| DecodeError("string is not valid UTF-8".to_string()))
+    }
+
+    pub fn read_array_len(rd: &mut &[u8]) -> Result<usize, DecodeError> {
+        let marker = read_marker(rd)?;
+        match marker {
+            0x90..=0x9f => Ok((marker & 0x0f) as usize),
+            0xdc => Ok(u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdd => Ok(u32::from_be_bytes(read_be(rd)?) as usize),
+            _ => Err(unexpected(marker, "array")),
+        }
+    }
+
+    pub fn read_map_len(rd: &mut &[u8]) -> Result<usize, DecodeError> {
+        let marker = read_marker(rd)?;
+        match marker {
+            0x80..=0x8f => Ok((marker & 0x0f) as usize),
+            0xde => Ok(u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdf => Ok(u32::from_be_bytes(read_be(rd)?) as usize),
+            _ => Err(unexpected(marker, "map")),
+        }
+    }
+
+    pub fn read_bin<'a>(rd: &mut &'a [u8]) -> Result<&'a [u8], DecodeError> {
+        let end = || DecodeError("unexpected end of input".to_string());
+        let header = match rd.first() {
+            Some(0xc4) => 2,
+            Some(0xc5) => 3,
+            Some(0xc6) => 5,
+            Some(marker) => {
+                return Err(DecodeError(format!("expected binary, found marker 0x{marker:02x}")));
+            }
+            None => return Err(end()),
+        };
+        let len = rd
+            .get(1..header)
+            .ok_or_else(end)?
+            .iter()
+            .fold(0, |len, byte| len << 8 | usize::from(*byte));
+        let bytes = rd.get(header..header + len).ok_or_else(end)?;
+
+        *rd = &rd[header + len..];
+        Ok(bytes)
+    }
+
+    /// Skip over a value of any type
+    pub fn skip(rd: &mut &[u8]) -> Result<(), DecodeError> {
+        let marker = read_marker(rd)?;
+        let (len, items) = match marker {
+            0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => (0, 0),
+            0x80..=0x8f => (0, 2 * (marker & 0x0f) as usize),
+            0x90..=0x9f => (0, (marker & 0x0f) as usize),
+            0xa0..=0xbf => ((marker & 0x1f) as usize, 0),
+            0xc4 | 0xd9 => (u8::from_be_bytes(read_be(rd)?) as usize, 0),
+            0xc5 | 0xda => (u16::from_be_bytes(read_be(rd)?) as usize, 0),
+            0xc6 | 0xdb => (u32::from_be_bytes(read_be(rd)?) as usize, 0),
+            0xc7 => (u8::from_be_bytes(read_be(rd)?) as usize + 1, 0),
+            0xc8 => (u16::from_be_bytes(read_be(rd)?) as usize + 1, 0),
+            0xc9 => (u32::from_be_bytes(read_be(rd)?) as usize + 1, 0),
+            0xca => (4, 0),
+            0xcb => (8, 0),
+            0xcc | 0xd0 => (1, 0),
+            0xcd | 0xd1 => (2, 0),
+            0xce | 0xd2 => (4, 0),
+            0xcf | 0xd3 => (8, 0),
+            0xd4 => (2, 0),
+            0xd5 => (3, 0),
+            0xd6 => (5, 0),
+            0xd7 => (9, 0),
+            0xd8 => (17, 0),
+            0xdc => (0, u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdd => (0, u32::from_be_bytes(read_be(rd)?) as usize),
+            0xde => (0, 2 * u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdf => (0, 2 * u32::from_be_bytes(read_be(rd)?) as usize),
+            _ => return Err(unexpected(marker, "a value")),
+        };
+
+        read_bytes(rd, len)?;
+        for _ in 0..items {
+            skip(rd)?;
+        }
+        Ok(())
+    }
+
+    /// Write a message with a 4 byte big-endian length prefix
+    pub fn write_frame<W: std::io::Write>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+        let len = u32::try_from(bytes.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "message is too long to frame")
+        })?;
+        writer.write_all(&len.to_be_bytes())?;
+        writer.write_all(bytes)
+    }
+
+    /// Longest message `read_frame` accepts unless it is given another limit
+    pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+    /// Read a length prefixed message, or `None` if the reader is at the end of the stream.  A
+    /// length prefix over `max_frame_length` is an error, before anything is allocated for it.
+    pub fn read_frame<R: std::io::Read>(reader: &mut R, max_frame_length: usize) -> std::io::Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 4];
+        let mut read = 0;
+
+        while read < len.len() {
+            match reader.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let len = u32::from_be_bytes(len) as usize;
+
+        if len > max_frame_length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("frame of {len} bytes is longer than the limit of {max_frame_length}"),
+            ));
+        }
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+
+    pub fn fixed_array<T, const N: usize>(items: Vec<T>, path: &str) -> Result<[T; N], DecodeError> {
+        let len = items.len();
+        items
+            .try_into()
+            .map_err(|_| DecodeError(format!("{path} must have {N} elements, got {len}")))
+    }
+}
+"#;
+
+fn generate(schema: &ast::Schema, config: Config) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    write_file(&mut w, schema, config);
+
+    w.into_string()
+}
+
+fn write_file(w: &mut CodeWriter, schema: &ast::Schema, config: Config) {
+    let enum_types = schema
+        .declarations
+        .iter()
+        .filter_map(|d| match d {
+            ast::Declaration::Enum {
+                ident, base_type, ..
+            } => Some((ident.as_str(), base_type)),
+            _ => None,
+        })
+        .collect();
+    let size_bounds = schema
+        .declarations
+        .iter()
+        .filter_map(|d| match d {
+            ast::Declaration::Struct { ident, .. } if config.size_bounds => Some((
+                ident.as_str(),
+                packed_size_upper_bound(schema, ident, config.wire, config.int64),
+            )),
+            _ => None,
+        })
+        .collect();
+    let borrowing = if config.borrowed {
+        borrowing_structs(schema)
+    } else {
+        HashSet::new()
+    };
+    let ctx = Context {
+        config,
+        enum_types,
+        size_bounds,
+        borrowing,
+        borrowed: false,
+        boxed: boxed_fields(schema),
+    };
+
+    writeln!(
+        w,
+        "#![allow(unused_imports, clippy::wrong_self_convention)]"
+    )
+    .unwrap();
+    w.blank();
+    if ctx.config.json {
+        writeln!(w, "use serde::{{Deserialize, Serialize}};").unwrap();
+    }
+    writeln!(w, "use std::collections::HashMap;").unwrap();
+    w.blank();
+    writeln!(w, "/// Error returned when a message can't be decoded").unwrap();
+    writeln!(w, "#[derive(Debug, Clone, PartialEq)]").unwrap();
+    writeln!(w, "pub struct DecodeError(pub String);").unwrap();
+    w.blank();
+    w.block("impl std::fmt::Display for DecodeError {", "}", |w| {
+        w.block(
+            "fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {",
+            "}",
+            |w| {
+                writeln!(w, "f.write_str(&self.0)").unwrap();
+            },
+        );
+    });
+    w.blank();
+    writeln!(w, "impl std::error::Error for DecodeError {{}}").unwrap();
+    w.blank();
+    w.write_str(RUNTIME).unwrap();
+    if ctx.config.json && has_string_int64(&ctx, schema) {
+        w.write_str(INT64_STRING).unwrap();
+    }
+    if let Some(envelope) = ctx.config.envelope {
+        let bytes: Vec<String> = envelope
+            .fingerprint
+            .iter()
+            .map(|byte| format!("0x{byte:02x}"))
+            .collect();
+
+        w.blank();
+        writeln!(
+            w,
+            "/// The first 8 bytes of the fingerprint of the schema this file was generated from"
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "pub const SCHEMA_FINGERPRINT: [u8; 8] = [{}];",
+            bytes.join(", ")
+        )
+        .unwrap();
+        w.write_str(ENVELOPE).unwrap();
+    }
+    if let Some(compression) = ctx.config.compression {
+        write_compression(w, compression);
+    }
+    if ctx.config.tokio_codec.is_some() {
+        w.write_str(TOKIO_CODEC).unwrap();
+    }
+    if has_topics(schema) {
+        w.write_str(TOPIC_TYPE).unwrap();
+    }
+    if has_localized_strings(schema) {
+        w.blank();
+        writeln!(
+            w,
+            "/// Text in several languages, keyed by locale such as `en` or `fr-CA`"
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash{})]",
+            json_derives(&ctx)
+        )
+        .unwrap();
+        if ctx.config.json {
+            writeln!(w, "#[serde(transparent)]").unwrap();
+        }
+        writeln!(
+            w,
+            "pub struct LocalizedString(pub std::collections::BTreeMap<String, String>);"
+        )
+        .unwrap();
+        w.write_str(LOCALIZED_STRING_IMPL).unwrap();
+    }
+
+    for decl in &schema.declarations {
+        w.blank();
+        match decl {
+            ast::Declaration::Enum {
+                ident,
+                base_type,
+                variants,
+                visibility,
+                annotations,
+            } => generate_enum(
+                w,
+                &ctx,
+                ident,
+                *visibility,
+                base_type,
+                variants,
+                annotations,
+            ),
+            ast::Declaration::Struct {
+                ident,
+                fields,
+                implements,
+                visibility,
+                annotations,
+            } => {
+                generate_struct(w, &ctx, ident, *visibility, fields, annotations);
+                generate_trait_impls(w, &ctx.config.naming, schema, ident, implements);
+            }
+            ast::Declaration::Interface {
+                ident,
+                fields,
+                visibility,
+                ..
+            } => generate_trait(w, &ctx.config.naming, ident, *visibility, fields),
+            ast::Declaration::Topic { ident, payload, .. } => {
+                generate_topic(w, &ctx, ident, payload)
+            }
+        }
+    }
+
+    if ctx.config.property_tests {
+        generate_property_tests(w, &ctx, schema);
+    }
+}
+
+/// Serde helpers for the `json` option, for 64-bit integers that are written as strings
+const INT64_STRING: &str = r#"
+mod int64_string {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use std::{fmt::Display, str::FromStr};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T: FromStr, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value
+            .parse()
+            .map_err(|_| D::Error::custom(format!("{value:?} is not an integer")))
+    }
+
+    #[allow(dead_code)]
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::{fmt::Display, str::FromStr};
+
+        pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, T: FromStr, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(value) => super::deserialize(serde::de::value::StringDeserializer::new(value)).map(Some),
+                None => Ok(None),
+            }
+        }
+    }
+}
+"#;
+
+/// The envelope for the `envelope` option: a MessagePack array of the schema fingerprint and the
+/// payload as binaries, then the CRC-32 of the payload if there is a checksum
+const ENVELOPE: &str = r#"
+#[allow(dead_code)]
+mod envelope {
+    use super::{DecodeError, SCHEMA_FINGERPRINT, mp};
+
+    /// Wrap a payload with the schema fingerprint and, if `checksum` is set, its CRC-32
+    pub fn seal(payload: &[u8], checksum: bool) -> Vec<u8> {
+        let mut wr = Vec::with_capacity(payload.len() + 24);
+
+        mp::write_array_len(&mut wr, if checksum { 3 } else { 2 });
+        mp::write_bin(&mut wr, &SCHEMA_FINGERPRINT);
+        mp::write_bin(&mut wr, payload);
+        if checksum {
+            mp::write_uint(&mut wr, u64::from(crc32(payload)));
+        }
+        wr
+    }
+
+    /// The payload of an envelope, once its fingerprint and any checksum have been checked
+    pub fn open(bytes: &[u8]) -> Result<&[u8], DecodeError> {
+        let mut rd = bytes;
+        let len = mp::read_array_len(&mut rd)?;
+
+        if len != 2 && len != 3 {
+            return Err(DecodeError(format!("envelope must have 2 or 3 elements, got {len}")));
+        }
+
+        let fingerprint = mp::read_bin(&mut rd)?;
+
+        if fingerprint != SCHEMA_FINGERPRINT {
+            return Err(DecodeError(format!(
+                "message was written with schema {}, but this code is for schema {}",
+                hex(fingerprint),
+                hex(&SCHEMA_FINGERPRINT)
+            )));
+        }
+
+        let payload = mp::read_bin(&mut rd)?;
+
+        if len == 3 {
+            let expected = mp::read_int::<u32>(&mut rd)?;
+            let found = crc32(payload);
+
+            if found != expected {
+                return Err(DecodeError(format!(
+                    "message checksum is {found:08x}, but the envelope says {expected:08x}"
+                )));
+            }
+        }
+        Ok(payload)
+    }
+
+    /// CRC-32 as used by zlib and Ethernet
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = u32::MAX;
+
+        for byte in bytes {
+            crc ^= u32::from(*byte);
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
+"#;
+
+/// Compression for the `compression` option, written inside a `compression` module after its
+/// `THRESHOLD`, `MAX_DECOMPRESSED`, `ALGORITHM` and codec.  A compressed message is the marker, the algorithm and the
+/// compressed bytes.
+const COMPRESSION: &str = r#"
+/// Starts a compressed message, as no MessagePack value starts with it
+const MARKER: u8 = 0xc1;
+
+/// Compress a message longer than the threshold, if that makes it smaller
+pub fn compress(bytes: Vec<u8>) -> Vec<u8> {
+    if bytes.len() <= THRESHOLD {
+        return bytes;
+    }
+
+    let mut compressed = vec![MARKER, ALGORITHM];
+
+    compressed.extend(encode(&bytes));
+    if compressed.len() < bytes.len() {
+        compressed
+    } else {
+        bytes
+    }
+}
+
+/// The message, decompressed if it starts with the marker.  The codec stops one byte past
+/// `MAX_DECOMPRESSED`, so a message that would decompress to more is an error without ever being
+/// held in memory.
+pub fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>, DecodeError> {
+    match bytes {
+        [MARKER, ALGORITHM, compressed @ ..] => match decode(compressed, MAX_DECOMPRESSED as u64 + 1) {
+            Ok(decoded) if decoded.len() > MAX_DECOMPRESSED => Err(DecodeError(format!(
+                "message decompresses to more than {MAX_DECOMPRESSED} bytes"
+            ))),
+            Ok(decoded) => Ok(Cow::Owned(decoded)),
+            Err(err) => Err(DecodeError(format!("message can't be decompressed: {err}"))),
+        },
+        [MARKER, algorithm, ..] => Err(DecodeError(format!(
+            "message is compressed with unknown algorithm {algorithm}"
+        ))),
+        _ => Ok(Cow::Borrowed(bytes)),
+    }
+}
+"#;
+
+/// The zlib codec for [COMPRESSION], from the `flate2` crate
+const ZLIB_CODEC: &str = r#"
+fn encode(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+
+    encoder.write_all(bytes).expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+fn decode(bytes: &[u8], limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+
+    flate2::read::ZlibDecoder::new(bytes).take(limit).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+"#;
+
+/// The Zstandard codec for [COMPRESSION], from the `zstd` crate
+const ZSTD_CODEC: &str = r#"
+fn encode(bytes: &[u8]) -> Vec<u8> {
+    zstd::bulk::compress(bytes, 0).expect("compressing to a Vec can't fail")
+}
+
+fn decode(bytes: &[u8], limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+
+    zstd::stream::read::Decoder::new(bytes)?.take(limit).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+"#;
+
+/// Whether any struct field has a 64-bit integer packed as a string
+fn has_string_int64(ctx: &Context, schema: &ast::Schema) -> bool {
+    schema.declarations.iter().any(|decl| match decl {
+        ast::Declaration::Struct {
+            fields,
+            annotations,
+            ..
+        } => fields.iter().any(|(name, ft)| {
+            is_int64(ft)
+                && ctx
+                    .config
+                    .int64
+                    .for_field(annotations.member(name))
+                    .is_string()
+        }),
+        _ => false,
+    })
+}
+
+/// Framing shared by the codecs of the `tokio_codec` option, the same as `mp::write_frame`
+const TOKIO_CODEC: &str = r#"
+#[allow(dead_code)]
+mod tokio_codec {
+    use tokio_util::bytes::{Buf, BytesMut};
+
+    /// Codecs reject longer frames unless they are given another limit
+    pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+    /// Append a message with a 4 byte big-endian length prefix
+    pub fn encode(bytes: &[u8], dst: &mut BytesMut, max_frame_length: usize) -> std::io::Result<()> {
+        if bytes.len() > max_frame_length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("message of {} bytes is longer than the limit of {max_frame_length}", bytes.len()),
+            ));
+        }
+        let len = u32::try_from(bytes.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "message is too long to frame")
+        })?;
+        dst.reserve(4 + bytes.len());
+        dst.extend_from_slice(&len.to_be_bytes());
+        dst.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Take the next whole message from `src` without its length prefix, or `None` if more bytes
+    /// are needed
+    pub fn decode(src: &mut BytesMut, max_frame_length: usize) -> std::io::Result<Option<BytesMut>> {
+        let Some(prefix) = src.get(..4) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+
+        if len > max_frame_length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("frame of {len} bytes is longer than the limit of {max_frame_length}"),
+            ));
+        }
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        Ok(Some(src.split_to(len)))
+    }
+}
+"#;
+
+/// The `Topic` type, written once for all topics
+const TOPIC_TYPE: &str = r#"
+/// A publish/subscribe topic whose messages are `T`
+#[derive(Debug, Clone, Copy)]
+pub struct Topic<T: 'static> {
+    /// Name of the topic
+    pub name: &'static str,
+    encode: fn(&T) -> Vec<u8>,
+    decode: fn(&[u8]) -> Result<T, DecodeError>,
+}
+
+impl<T> Topic<T> {
+    /// Encode a message to publish on the topic
+    pub fn encode(&self, message: &T) -> Vec<u8> {
+        (self.encode)(message)
+    }
+
+    /// Decode a message received from the topic
+    pub fn decode(&self, bytes: &[u8]) -> Result<T, DecodeError> {
+        (self.decode)(bytes)
+    }
+}
+"#;
+
+/// The methods of the `LocalizedString` type, which is packed as a map of locale to text
+const LOCALIZED_STRING_IMPL: &str = r#"
+impl LocalizedString {
+    /// The text for a locale, or for its language if there is none, so `fr-CA` falls back to `fr`
+    pub fn get(&self, locale: &str) -> Option<&str> {
+        self.0
+            .get(locale)
+            .or_else(|| self.0.get(locale.split('-').next()?))
+            .map(String::as_str)
+    }
+
+    fn pack(&self, wr: &mut Vec<u8>) {
+        mp::write_map_len(wr, self.0.len());
+        for (locale, text) in &self.0 {
+            mp::write_str(wr, locale);
+            mp::write_str(wr, text);
+        }
+    }
+
+    fn unpack(rd: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut map = std::collections::BTreeMap::new();
+        for _ in 0..mp::read_map_len(rd)? {
+            let locale = mp::read_str(rd)?;
+            map.insert(locale, mp::read_str(rd)?);
+        }
+        Ok(Self(map))
+    }
+}
+"#;
+
+fn generate_topic(w: &mut CodeWriter, ctx: &Context, ident: &str, payload: &str) {
+    let rust_name = ctx.config.naming.type_name(payload);
+
+    writeln!(w, "/// The `{ident}` topic").unwrap();
+    w.block(
+        &format!(
+            "pub const {}: Topic<{rust_name}> = Topic {{",
+            to_screaming_snake_case(ident)
+        ),
+        "};",
+        |w| {
+            writeln!(w, "name: \"{ident}\",").unwrap();
+            writeln!(w, "encode: {rust_name}::to_bytes,").unwrap();
+            writeln!(w, "decode: {rust_name}::from_bytes,").unwrap();
+        },
+    );
+}
+
+fn generate_enum(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    visibility: ast::Visibility,
+    base_type: &ast::IntegerType,
+    variants: &[(String, ast::IntegerValue)],
+    annotations: &ast::Annotations,
+) {
+    let (default_variant, _) = annotations.default_variant(variants);
+    let rust_name = ctx.config.naming.type_name(ident);
+    let vis = visibility_str(visibility);
+    let base_type_str = integer_type_str(base_type);
+
+    writeln!(
+        w,
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default{})]",
+        json_derives(ctx)
+    )
+    .unwrap();
+    writeln!(w, "#[repr({base_type_str})]").unwrap();
+    w.block(&format!("{vis} enum {rust_name} {{"), "}", |w| {
+        for (variant_name, value) in variants {
+            let rust_variant = ctx.config.naming.variant_name(variant_name);
+
+            if variant_name == default_variant {
+                writeln!(w, "#[default]").unwrap();
+            }
+            if ctx.config.json && rust_variant != *variant_name {
+                writeln!(w, "#[serde(rename = \"{variant_name}\")]").unwrap();
+            }
+            writeln!(w, "{rust_variant} = {},", integer_value_str(value)).unwrap();
+        }
+    });
+
+    generate_enum_conversions(w, &ctx.config.naming, &rust_name, base_type_str, variants);
+    if ctx.config.enum_helpers {
+        generate_enum_map(w, &ctx.config.naming, &rust_name, variants);
+    }
+
+    w.blank();
+    w.block(&format!("impl {rust_name} {{"), "}", |w| {
+        if ctx.config.roots.contains(ident) {
+            generate_bytes_methods(w, ctx);
+        }
+
+        w.blank();
+        w.block("fn pack(&self, wr: &mut Vec<u8>) {", "}", |w| {
+            writeln!(w, "{}", write_int_call("*self", base_type)).unwrap();
+        });
+
+        w.blank();
+        w.block(
+            "fn unpack(rd: &mut &[u8]) -> Result<Self, DecodeError> {",
+            "}",
+            |w| {
+                writeln!(
+                    w,
+                    "Self::try_from(mp::read_int::<{base_type_str}>(rd)?).map_err(DecodeError)"
+                )
+                .unwrap();
+            },
+        );
+    });
+}
+
+fn generate_struct(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    visibility: ast::Visibility,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let rust_name = ctx.config.naming.type_name(ident);
+    let vis = visibility_str(visibility);
+    let masked: Vec<(String, bool)> = fields
+        .iter()
+        .map(|(field_name, _)| {
+            (
+                ctx.config.naming.field_name(field_name),
+                ctx.config.mask_sensitive && annotations.is_sensitive(field_name),
+            )
+        })
+        .collect();
+    let masked_debug = masked.iter().any(|(_, masked)| *masked);
+
+    if masked_debug {
+        writeln!(w, "#[derive(Clone, PartialEq{})]", json_derives(ctx)).unwrap();
+    } else {
+        writeln!(w, "#[derive(Debug, Clone, PartialEq{})]", json_derives(ctx)).unwrap();
+    }
+    if annotations.is_abi_stable() {
+        writeln!(w, "#[repr(C)]").unwrap();
+    }
+    w.block(&format!("{vis} struct {rust_name} {{"), "}", |w| {
+        for (field_name, field_type) in fields {
+            let rust_field = ctx.config.naming.field_name(field_name);
+            let json_name = annotations.json_name(field_name);
+
+            if ctx.config.json && annotations.is_flattened(field_name) {
+                writeln!(w, "#[serde(flatten)]").unwrap();
+            } else if ctx.config.json && rust_field != json_name {
+                writeln!(w, "#[serde(rename = \"{json_name}\")]").unwrap();
+            }
+            if ctx.config.json
+                && is_int64(field_type)
+                && ctx
+                    .config
+                    .int64
+                    .for_field(annotations.member(field_name))
+                    .is_string()
+            {
+                if is_nullable(field_type) {
+                    writeln!(w, "#[serde(default, with = \"int64_string::option\")]").unwrap();
+                } else {
+                    writeln!(w, "#[serde(with = \"int64_string\")]").unwrap();
+                }
+            }
+            writeln!(
+                w,
+                "pub {rust_field}: {},",
+                boxed_type_str(field_type, ctx.is_boxed(ident, field_name), |ft| {
+                    type_str(ft, &ctx.config.naming)
+                })
+            )
+            .unwrap();
+        }
+    });
+
+    if masked_debug {
+        write_masked_debug_impl(w, &rust_name, &masked);
+    }
+    if let Some(redaction) = &ctx.config.redaction {
+        redaction.write_impls(w, &ctx.config.naming, ident, fields, annotations);
+    }
+
+    w.blank();
+    w.block(&format!("impl {rust_name} {{"), "}", |w| {
+        if ctx.config.roots.contains(ident) {
+            generate_bytes_methods(w, ctx);
+            if ctx.config.framing {
+                generate_framing_methods(w);
+            }
+            if let Some(envelope) = ctx.config.envelope {
+                generate_envelope_methods(w, envelope);
+            }
+            if let Some(bound) = ctx.size_bounds.get(ident) {
+                generate_size_bound_method(w, *bound);
+            }
+            if ctx.config.borrowed {
+                generate_unpack_into_method(w, ctx);
+            }
+        }
+
+        match ctx.config.wire.protocol().layout {
+            StructLayout::Sequence => {
+                generate_positional_methods(w, ctx, ident, fields, annotations);
+                if ctx.config.borrowed {
+                    generate_unpack_in_place(w, ctx, ident, fields, annotations);
+                }
+            }
+            StructLayout::Map => generate_keyed_methods(w, ctx, ident, fields, annotations),
+        }
+    });
+
+    if let Some(roots) = &ctx.config.tokio_codec
+        && roots.contains(ident)
+    {
+        generate_tokio_codec(w, &rust_name);
+    }
+
+    if ctx.borrowing.contains(ident) {
+        let ref_ctx = Context {
+            borrowed: true,
+            ..ctx.clone()
+        };
+
+        generate_ref_struct(w, &ref_ctx, ident, visibility, fields, annotations, &masked);
+    }
+}
+
+/// The structs that hold a `string`, directly or through other structs, lists and maps
+fn borrowing_structs(schema: &ast::Schema) -> HashSet<&str> {
+    fn holds(ft: &ast::FieldType, borrowing: &HashSet<&str>) -> bool {
+        match ft {
+            ast::FieldType::Builtin(bt, _) => *bt == ast::BuiltinType::String,
+            ast::FieldType::UserDefined(name, _) => borrowing.contains(name.as_str()),
+            ast::FieldType::Array(inner, _, _) => holds(inner, borrowing),
+            ast::FieldType::Map(key_type, value_type, _) => {
+                *key_type == ast::MapKeyType::Builtin(ast::BuiltinType::String)
+                    || holds(value_type, borrowing)
+            }
+        }
+    }
+
+    let mut borrowing = HashSet::new();
+
+    // Each pass finds the structs that hold one found by the pass before
+    loop {
+        let found: Vec<&str> = schema
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                ast::Declaration::Struct { ident, fields, .. }
+                    if !borrowing.contains(ident.as_str())
+                        && fields.iter().any(|(_, ft)| holds(ft, &borrowing)) =>
+                {
+                    Some(ident.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+
+        if found.is_empty() {
+            return borrowing;
+        }
+        borrowing.extend(found);
+    }
+}
+
+/// A `...Ref` struct has the fields of the struct, but with `&str` in place of each `String`
+/// borrowed from the bytes it was decoded from, and `...Ref` in place of each struct that holds
+/// a string
+fn generate_ref_struct(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    visibility: ast::Visibility,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+    masked: &[(String, bool)],
+) {
+    let rust_name = ctx.config.naming.type_name(ident);
+    let ref_name = format!("{rust_name}Ref");
+    let vis = visibility_str(visibility);
+    let masked_debug = masked.iter().any(|(_, masked)| *masked);
+
+    w.blank();
+    writeln!(
+        w,
+        "/// A [{rust_name}] that borrows its strings from the bytes it was decoded from"
+    )
+    .unwrap();
+    if masked_debug {
+        writeln!(w, "#[derive(Clone, PartialEq)]").unwrap();
+    } else {
+        writeln!(w, "#[derive(Debug, Clone, PartialEq)]").unwrap();
+    }
+    w.block(&format!("{vis} struct {ref_name}<'a> {{"), "}", |w| {
+        for (field_name, field_type) in fields {
+            writeln!(
+                w,
+                "pub {}: {},",
+                ctx.config.naming.field_name(field_name),
+                boxed_type_str(field_type, ctx.is_boxed(ident, field_name), |ft| {
+                    ref_type_str(ctx, ft)
+                })
+            )
+            .unwrap();
+        }
+    });
+
+    if masked_debug {
+        write_masked_debug_impl(w, &format!("{ref_name}<'_>"), masked);
+    }
+
+    w.blank();
+    w.block(&format!("impl<'a> {ref_name}<'a> {{"), "}", |w| {
+        if ctx.config.roots.contains(ident) {
+            writeln!(
+                w,
+                "/// Decode from MessagePack, borrowing strings from `bytes`"
+            )
+            .unwrap();
+            w.block(
+                "pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, DecodeError> {",
+                "}",
+                |w| {
+                    writeln!(w, "let mut rd = bytes;").unwrap();
+                    writeln!(w, "Self::unpack(&mut rd)").unwrap();
+                },
+            );
+            w.blank();
+        }
+
+        writeln!(w, "/// Copy into a [{rust_name}] that owns its strings").unwrap();
+        w.block(
+            &format!("pub fn into_owned(self) -> {rust_name} {{"),
+            "}",
+            |w| {
+                w.block(&format!("{rust_name} {{"), "}", |w| {
+                    for (field_name, field_type) in fields {
+                        let rust_field = ctx.config.naming.field_name(field_name);
+                        let expr = format!("self.{rust_field}");
+                        let owned = owned_expr(ctx, &expr, field_type, 0);
+                        // The box is only replaced when the struct in it is
+                        let boxed = owned != expr && ctx.is_boxed(ident, field_name);
+
+                        writeln!(
+                            w,
+                            "{rust_field}: {},",
+                            boxed_value_str(&owned, field_type, boxed)
+                        )
+                        .unwrap();
+                    }
+                });
+            },
+        );
+
+        generate_positional_unpack(w, ctx, ident, fields, annotations);
+    });
+}
+
+/// The type of a field in a `...Ref` struct
+fn ref_type_str(ctx: &Context, ft: &ast::FieldType) -> String {
+    let inner = match ft {
+        ast::FieldType::Builtin(ast::BuiltinType::String, _) => "&'a str".to_string(),
+        ast::FieldType::UserDefined(name, _) if ctx.borrowing.contains(name.as_str()) => {
+            format!("{}Ref<'a>", ctx.config.naming.type_name(name))
+        }
+        ast::FieldType::Array(inner, Some(length), _) => {
+            format!("[{}; {length}]", ref_type_str(ctx, inner))
+        }
+        ast::FieldType::Array(inner, None, _) => format!("Vec<{}>", ref_type_str(ctx, inner)),
+        ast::FieldType::Map(key_type, value_type, _) => format!(
+            "HashMap<{}, {}>",
+            ref_type_str(ctx, &map_key_field_type(key_type)),
+            ref_type_str(ctx, value_type)
+        ),
+        _ => return type_str(ft, &ctx.config.naming),
+    };
+
+    if is_nullable(ft) {
+        format!("Option<{inner}>")
+    } else {
+        inner
+    }
+}
+
+/// An expression that turns `expr`, a value of a `...Ref` struct field, into the owned value
+fn owned_expr(ctx: &Context, expr: &str, ft: &ast::FieldType, depth: usize) -> String {
+    let borrows = |ft: &ast::FieldType| ref_type_str(ctx, ft) != type_str(ft, &ctx.config.naming);
+
+    if !borrows(ft) {
+        return expr.to_string();
+    }
+    if is_nullable(ft) {
+        let value = format!("value{depth}");
+
+        return format!(
+            "{expr}.map(|{value}| {})",
+            owned_expr(ctx, &value, &non_nullable(ft), depth + 1)
+        );
+    }
+
+    let item = format!("e{depth}");
+
+    match ft {
+        ast::FieldType::Builtin(..) => format!("{expr}.to_string()"),
+        ast::FieldType::UserDefined(..) => format!("{expr}.into_owned()"),
+        ast::FieldType::Array(inner, Some(_), _) => {
+            format!(
+                "{expr}.map(|{item}| {})",
+                owned_expr(ctx, &item, inner, depth + 1)
+            )
+        }
+        ast::FieldType::Array(inner, None, _) => format!(
+            "{expr}.into_iter().map(|{item}| {}).collect()",
+            owned_expr(ctx, &item, inner, depth + 1)
+        ),
+        ast::FieldType::Map(key_type, value_type, _) => {
+            let key = format!("k{depth}");
+            let value = format!("v{depth}");
+
+            format!(
+                "{expr}.into_iter().map(|({key}, {value})| ({}, {})).collect()",
+                owned_expr(ctx, &key, &map_key_field_type(key_type), depth + 1),
+                owned_expr(ctx, &value, value_type, depth + 1)
+            )
+        }
+    }
+}
+
+/// The public `unpack_into` method, which decodes into an existing value so that its strings and
+/// lists can be reused
+fn generate_unpack_into_method(w: &mut CodeWriter, ctx: &Context) {
+    w.blank();
+    writeln!(
+        w,
+        "/// Decode from MessagePack into this value, reusing the memory of its strings and lists"
+    )
+    .unwrap();
+    w.block(
+        "pub fn unpack_into(&mut self, bytes: &[u8]) -> Result<(), DecodeError> {",
+        "}",
+        |w| {
+            if ctx.config.compression.is_some() {
+                writeln!(w, "let bytes = compression::decompress(bytes)?;").unwrap();
+                writeln!(w, "let mut rd = &*bytes;").unwrap();
+            } else {
+                writeln!(w, "let mut rd = bytes;").unwrap();
+            }
+            writeln!(w, "self.unpack_in_place(&mut rd)").unwrap();
+        },
+    );
+}
+
+/// Decode into an existing value.  Required strings are copied into the existing `String`,
+/// required lists are cleared and refilled, and required structs are decoded in place, while
+/// every other field is replaced.
+fn generate_unpack_in_place(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let rust_name = ctx.config.naming.type_name(ident);
+    let bits = ctx.config.wire.protocol().presence_bits(fields);
+    let word_count = bits
+        .iter()
+        .flatten()
+        .map(|(word, _)| word + 1)
+        .max()
+        .unwrap_or(0);
+
+    w.blank();
+    w.block(
+        "fn unpack_in_place(&mut self, rd: &mut &[u8]) -> Result<(), DecodeError> {",
+        "}",
+        |w| {
+            for word in 0..word_count {
+                writeln!(w, "let presence{word} = mp::read_int::<u32>(rd)?;").unwrap();
+            }
+            for ((field_name, field_type), bit) in fields.iter().zip(&bits) {
+                let rust_field = ctx.config.naming.field_name(field_name);
+                let path = format!("{rust_name}.{rust_field}");
+                let var = format!("f_{rust_field}");
+                let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+                let boxed = ctx.is_boxed(ident, field_name);
+
+                match (bit, field_type) {
+                    (Some((word, mask)), _) => {
+                        w.block(
+                            &format!("self.{rust_field} = if presence{word} & 0x{mask:x} != 0 {{"),
+                            "} else {",
+                            |w| {
+                                generate_unpack_value(
+                                    w,
+                                    ctx,
+                                    &path,
+                                    "value",
+                                    &non_nullable(field_type),
+                                    int64,
+                                );
+                                writeln!(
+                                    w,
+                                    "Some({})",
+                                    boxed_value_str("value", &non_nullable(field_type), boxed)
+                                )
+                                .unwrap();
+                            },
+                        );
+                        w.indent();
+                        writeln!(w, "None").unwrap();
+                        w.dedent();
+                        writeln!(w, "}};").unwrap();
+                    }
+                    (None, ast::FieldType::Builtin(ast::BuiltinType::String, false)) => {
+                        writeln!(w, "let {var} = mp::read_str_ref(rd)?;").unwrap();
+                        writeln!(w, "self.{rust_field}.clear();").unwrap();
+                        writeln!(w, "self.{rust_field}.push_str({var});").unwrap();
+                    }
+                    (None, ast::FieldType::UserDefined(name, false))
+                        if !ctx.enum_types.contains_key(name.as_str()) =>
+                    {
+                        writeln!(w, "self.{rust_field}.unpack_in_place(rd)?;").unwrap();
+                    }
+                    (None, ast::FieldType::Array(inner, None, false)) => {
+                        writeln!(w, "self.{rust_field}.clear();").unwrap();
+                        w.block("for _ in 0..mp::read_array_len(rd)? {", "}", |w| {
+                            generate_unpack_field(w, ctx, &path, "e0", inner, 1);
+                            writeln!(w, "self.{rust_field}.push(e0);").unwrap();
+                        });
+                    }
+                    (None, _) => {
+                        generate_unpack_value(w, ctx, &path, &var, field_type, int64);
+                        writeln!(
+                            w,
+                            "self.{rust_field} = {};",
+                            boxed_value_str(&var, field_type, boxed)
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+            writeln!(w, "Ok(())").unwrap();
+        },
+    );
+}
+
+/// Messages are framed with a 4 byte big-endian length prefix
+fn generate_framing_methods(w: &mut CodeWriter) {
+    w.blank();
+    writeln!(w, "/// Write as a length prefixed message").unwrap();
+    w.block(
+        "pub fn write_delimited_to<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {",
+        "}",
+        |w| {
+            writeln!(w, "mp::write_frame(writer, &self.to_bytes())").unwrap();
+        },
+    );
+
+    w.blank();
+    writeln!(
+        w,
+        "/// Read a length prefixed message, or `None` if the reader is at the end of the stream."
+    )
+    .unwrap();
+    writeln!(
+        w,
+        "/// Messages longer than [mp::MAX_FRAME_LENGTH] are an error."
+    )
+    .unwrap();
+    w.block(
+        "pub fn read_delimited_from<R: std::io::Read>(reader: &mut R) -> std::io::Result<Option<Self>> {",
+        "}",
+        |w| {
+            writeln!(
+                w,
+                "Self::read_delimited_from_with_limit(reader, mp::MAX_FRAME_LENGTH)"
+            )
+            .unwrap();
+        },
+    );
+
+    w.blank();
+    writeln!(
+        w,
+        "/// Read a length prefixed message like `read_delimited_from`, with another limit on its length"
+    )
+    .unwrap();
+    w.block(
+        "pub fn read_delimited_from_with_limit<R: std::io::Read>(reader: &mut R, max_frame_length: usize) -> std::io::Result<Option<Self>> {",
+        "}",
+        |w| {
+            w.block("match mp::read_frame(reader, max_frame_length)? {", "}", |w| {
+                writeln!(
+                    w,
+                    "Some(bytes) => Self::from_bytes(&bytes).map(Some).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),"
+                )
+                .unwrap();
+                writeln!(w, "None => Ok(None),").unwrap();
+            });
+        },
+    );
+}
+
+/// A codec type for `Framed` transports, framing messages like `write_delimited_to`, see
+/// [TOKIO_CODEC]
+fn generate_tokio_codec(w: &mut CodeWriter, rust_name: &str) {
+    let codec_name = format!("{rust_name}Codec");
+
+    w.blank();
+    writeln!(
+        w,
+        "/// Length prefixed [tokio_util::codec] for [{rust_name}] messages"
+    )
+    .unwrap();
+    writeln!(w, "#[derive(Debug, Clone, Copy)]").unwrap();
+    w.block(&format!("pub struct {codec_name} {{"), "}", |w| {
+        writeln!(
+            w,
+            "/// Longest message to encode or decode, longer ones are an error"
+        )
+        .unwrap();
+        writeln!(w, "pub max_frame_length: usize,").unwrap();
+    });
+
+    w.blank();
+    w.block(&format!("impl Default for {codec_name} {{"), "}", |w| {
+        w.block("fn default() -> Self {", "}", |w| {
+            writeln!(
+                w,
+                "Self {{ max_frame_length: tokio_codec::MAX_FRAME_LENGTH }}"
+            )
+            .unwrap();
+        });
+    });
+
+    w.blank();
+    w.block(
+        &format!("impl tokio_util::codec::Encoder<{rust_name}> for {codec_name} {{"),
+        "}",
+        |w| {
+            writeln!(w, "type Error = std::io::Error;").unwrap();
+            w.blank();
+            w.block(
+                &format!("fn encode(&mut self, item: {rust_name}, dst: &mut tokio_util::bytes::BytesMut) -> std::io::Result<()> {{"),
+                "}",
+                |w| {
+                    writeln!(
+                        w,
+                        "tokio_codec::encode(&item.to_bytes(), dst, self.max_frame_length)"
+                    )
+                    .unwrap();
+                },
+            );
+        },
+    );
+
+    w.blank();
+    w.block(
+        &format!("impl tokio_util::codec::Decoder for {codec_name} {{"),
+        "}",
+        |w| {
+            writeln!(w, "type Item = {rust_name};").unwrap();
+            writeln!(w, "type Error = std::io::Error;").unwrap();
+            w.blank();
+            w.block(
+                &format!("fn decode(&mut self, src: &mut tokio_util::bytes::BytesMut) -> std::io::Result<Option<{rust_name}>> {{"),
+                "}",
+                |w| {
+                    w.block(
+                        "match tokio_codec::decode(src, self.max_frame_length)? {",
+                        "}",
+                        |w| {
+                            writeln!(
+                                w,
+                                "Some(frame) => {rust_name}::from_bytes(&frame).map(Some).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),"
+                            )
+                            .unwrap();
+                            writeln!(w, "None => Ok(None),").unwrap();
+                        },
+                    );
+                },
+            );
+        },
+    );
+}
+
+/// A `proptest` strategy for every enum and struct, and a test that each root type decodes to
+/// the value it was encoded from.  Fields that would make a strategy hold itself are left empty.
+fn generate_property_tests(w: &mut CodeWriter, ctx: &Context, schema: &ast::Schema) {
+    let naming = &ctx.config.naming;
+    let recursive = recursive_fields(schema);
+
+    w.blank();
+    writeln!(w, "#[cfg(test)]").unwrap();
+    w.block("mod property_tests {", "}", |w| {
+        writeln!(w, "use super::*;").unwrap();
+        writeln!(w, "use proptest::prelude::*;").unwrap();
+
+        for decl in &schema.declarations {
+            let (ident, strategy) = match decl {
+                ast::Declaration::Enum {
+                    ident, variants, ..
+                } => {
+                    let rust_name = naming.type_name(ident);
+                    let variants: Vec<String> = variants
+                        .iter()
+                        .map(|(name, _)| format!("{rust_name}::{}", naming.variant_name(name)))
+                        .collect();
+
+                    (
+                        ident,
+                        format!("proptest::sample::select(vec![{}])", variants.join(", ")),
+                    )
+                }
+                ast::Declaration::Struct { ident, fields, .. } => {
+                    let rust_name = naming.type_name(ident);
+                    let strategies: Vec<String> = fields
+                        .iter()
+                        .map(|(name, ft)| {
+                            if recursive.contains(&(ident.as_str(), name.as_str())) {
+                                "Just(Default::default())".to_string()
+                            } else {
+                                field_strategy(ft, naming)
+                            }
+                        })
+                        .collect();
+                    let bindings: Vec<String> =
+                        (0..fields.len()).map(|i| format!("f{i}")).collect();
+                    let values: Vec<String> = fields
+                        .iter()
+                        .zip(&bindings)
+                        .map(|((name, ft), binding)| {
+                            format!(
+                                "{}: {}",
+                                naming.field_name(name),
+                                // A recursive field is already its default, boxed or not
+                                boxed_value_str(
+                                    binding,
+                                    ft,
+                                    ctx.is_boxed(ident, name)
+                                        && !recursive.contains(&(ident.as_str(), name.as_str()))
+                                )
+                            )
+                        })
+                        .collect();
+                    let literal = format!("{rust_name} {{ {} }}", values.join(", "));
+
+                    if fields.is_empty() {
+                        (ident, format!("Just({rust_name} {{}})"))
+                    } else {
+                        // Tuples of strategies are strategies, up to 12 of them
+                        (
+                            ident,
+                            format!(
+                                "{}.prop_map(|{}| {literal})",
+                                tuples(&strategies),
+                                tuples(&bindings)
+                            ),
+                        )
+                    }
+                }
+                _ => continue,
+            };
+            let rust_name = naming.type_name(ident);
+
+            w.blank();
+            w.block(&format!("impl Arbitrary for {rust_name} {{"), "}", |w| {
+                writeln!(w, "type Parameters = ();").unwrap();
+                writeln!(w, "type Strategy = BoxedStrategy<Self>;").unwrap();
+                w.blank();
+                w.block(
+                    "fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {",
+                    "}",
+                    |w| {
+                        writeln!(w, "{strategy}.boxed()").unwrap();
+                    },
+                );
+            });
+        }
+
+        w.blank();
+        w.block("proptest! {", "}", |w| {
+            let mut first = true;
+
+            for decl in &schema.declarations {
+                let ident = match decl {
+                    ast::Declaration::Enum { ident, .. } | ast::Declaration::Struct { ident, .. }
+                        if ctx.config.roots.contains(ident) =>
+                    {
+                        ident
+                    }
+                    _ => continue,
+                };
+                let rust_name = naming.type_name(ident);
+
+                if !first {
+                    w.blank();
+                }
+                first = false;
+                writeln!(w, "#[test]").unwrap();
+                w.block(
+                    &format!(
+                        "fn {}_round_trips(value in any::<{rust_name}>()) {{",
+                        to_snake_case(ident)
+                    ),
+                    "}",
+                    |w| {
+                        writeln!(
+                            w,
+                            "prop_assert_eq!({rust_name}::from_bytes(&value.to_bytes()), Ok(value));"
+                        )
+                        .unwrap();
+                    },
+                );
+            }
+        });
+    });
+}
+
+/// Groups of at most 10 items as nested tuples, `(a, b)` or `((a, ..), (k, ..))`
+fn tuples(items: &[String]) -> String {
+    fn tuple(items: &[String]) -> String {
+        if items.len() == 1 {
+            format!("({},)", items[0])
+        } else {
+            format!("({})", items.join(", "))
+        }
+    }
+
+    if items.len() <= 10 {
+        tuple(items)
+    } else {
+        let groups: Vec<String> = items.chunks(10).map(tuple).collect();
+
+        tuple(&groups)
+    }
+}
+
+/// The `proptest` strategy for values of a field type.  Floats are never NaN, which isn't equal
+/// to itself, and lists and maps have up to 3 entries.
+fn field_strategy(ft: &ast::FieldType, naming: &Naming) -> String {
+    let strategy = match ft {
+        ast::FieldType::Builtin(bt, _) => match bt {
+            ast::BuiltinType::Float(ast::FloatType::F32) => {
+                "(prop::num::f32::NORMAL | prop::num::f32::ZERO)".to_string()
+            }
+            ast::BuiltinType::Float(ast::FloatType::F64) => {
+                "(prop::num::f64::NORMAL | prop::num::f64::ZERO)".to_string()
+            }
+            ast::BuiltinType::LocalizedString => {
+                "prop::collection::btree_map(any::<String>(), any::<String>(), 0..4).prop_map(LocalizedString)"
+                    .to_string()
+            }
+            _ => format!("any::<{}>()", type_str(&non_nullable(ft), naming)),
+        },
+        ast::FieldType::UserDefined(name, _) => format!("any::<{}>()", naming.type_name(name)),
+        ast::FieldType::Array(inner, None, _) => {
+            format!(
+                "prop::collection::vec({}, 0..4)",
+                field_strategy(inner, naming)
+            )
+        }
+        ast::FieldType::Array(inner, Some(len), _) => format!(
+            "prop::collection::vec({}, {len}).prop_map(|v| <{}>::try_from(v).unwrap())",
+            field_strategy(inner, naming),
+            type_str(&non_nullable(ft), naming)
+        ),
+        ast::FieldType::Map(key_type, value_type, _) => format!(
+            "prop::collection::hash_map({}, {}, 0..4)",
+            field_strategy(&map_key_field_type(key_type), naming),
+            field_strategy(value_type, naming)
+        ),
+    };
+
+    if is_nullable(ft) {
+        format!("proptest::option::of({strategy})")
+    } else {
+        strategy
+    }
+}
+
+/// Messages are wrapped in an envelope with the schema fingerprint, see [ENVELOPE]
+fn generate_envelope_methods(w: &mut CodeWriter, envelope: Envelope) {
+    w.blank();
+    writeln!(
+        w,
+        "/// Encode as MessagePack in an envelope with the schema fingerprint"
+    )
+    .unwrap();
+    w.block("pub fn to_envelope(&self) -> Vec<u8> {", "}", |w| {
+        writeln!(w, "envelope::seal(&self.to_bytes(), {})", envelope.checksum).unwrap();
+    });
+
+    w.blank();
+    writeln!(
+        w,
+        "/// Decode from an envelope, failing if it was written with another schema or is corrupt"
+    )
+    .unwrap();
+    w.block(
+        "pub fn from_envelope(bytes: &[u8]) -> Result<Self, DecodeError> {",
+        "}",
+        |w| {
+            writeln!(w, "Self::from_bytes(envelope::open(bytes)?)").unwrap();
+        },
+    );
+}
+
+/// The bound is worked out by [packed_size_upper_bound] when the code is generated
+fn generate_size_bound_method(w: &mut CodeWriter, bound: Option<usize>) {
+    w.blank();
+    writeln!(
+        w,
+        "/// The most bytes `to_bytes` writes for a value that meets its `@len` constraints, or `None` if there is no limit"
+    )
+    .unwrap();
+    w.block(
+        "pub const fn packed_size_upper_bound() -> Option<usize> {",
+        "}",
+        |w| match bound {
+            Some(bound) => writeln!(w, "Some({bound})").unwrap(),
+            None => writeln!(w, "None").unwrap(),
+        },
+    );
+}
+
+/// Extra derives for the `json` option
+fn json_derives(ctx: &Context) -> &'static str {
+    if ctx.config.json {
+        ", Serialize, Deserialize"
+    } else {
+        ""
+    }
+}
+
+/// The public `to_bytes` and `from_bytes` methods shared by enums and structs
+fn generate_bytes_methods(w: &mut CodeWriter, ctx: &Context) {
+    let compressed = ctx.config.compression.is_some();
+
+    writeln!(w, "/// Encode as MessagePack").unwrap();
+    w.block("pub fn to_bytes(&self) -> Vec<u8> {", "}", |w| {
+        writeln!(w, "let mut wr = Vec::new();").unwrap();
+        writeln!(w, "self.pack(&mut wr);").unwrap();
+        if compressed {
+            writeln!(w, "compression::compress(wr)").unwrap();
+        } else {
+            writeln!(w, "wr").unwrap();
+        }
+    });
+
+    w.blank();
+    writeln!(w, "/// Decode from MessagePack").unwrap();
+    w.block(
+        "pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {",
+        "}",
+        |w| {
+            if compressed {
+                writeln!(w, "let bytes = compression::decompress(bytes)?;").unwrap();
+                writeln!(w, "let mut rd = &*bytes;").unwrap();
+            } else {
+                writeln!(w, "let mut rd = bytes;").unwrap();
+            }
+            writeln!(w, "Self::unpack(&mut rd)").unwrap();
+        },
+    );
+}
+
+/// The `compression` module used by `to_bytes` and `from_bytes`, see [COMPRESSION]
+fn write_compression(w: &mut CodeWriter, compression: Compression) {
+    w.blank();
+    writeln!(w, "#[allow(dead_code)]").unwrap();
+    w.block("mod compression {", "}", |w| {
+        writeln!(w, "use super::DecodeError;").unwrap();
+        match compression.codec {
+            Codec::Zlib => writeln!(
+                w,
+                "use std::{{borrow::Cow, io::{{Read as _, Write as _}}}};"
+            )
+            .unwrap(),
+            Codec::Zstd => writeln!(w, "use std::{{borrow::Cow, io::Read as _}};").unwrap(),
+        }
+        w.blank();
+        writeln!(w, "/// Messages longer than this many bytes are compressed").unwrap();
+        writeln!(w, "const THRESHOLD: usize = {};", compression.threshold).unwrap();
+        writeln!(
+            w,
+            "/// Compressed messages that decompress to more than this many bytes are rejected"
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "const MAX_DECOMPRESSED: usize = {};",
+            compression.max_decompressed
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "/// Follows the marker to say which algorithm compressed a message"
+        )
+        .unwrap();
+        writeln!(w, "const ALGORITHM: u8 = {};", compression.codec.id()).unwrap();
+        w.write_str(COMPRESSION).unwrap();
+        w.write_str(match compression.codec {
+            Codec::Zlib => ZLIB_CODEC,
+            Codec::Zstd => ZSTD_CODEC,
+        })
+        .unwrap();
+    });
+}
+
+fn generate_positional_methods(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let bits = ctx.config.wire.protocol().presence_bits(fields);
+    let word_count = bits
+        .iter()
+        .flatten()
+        .map(|(word, _)| word + 1)
+        .max()
+        .unwrap_or(0);
+
+    w.blank();
+    w.block("fn pack(&self, wr: &mut Vec<u8>) {", "}", |w| {
+        for word in 0..word_count {
+            let flags: Vec<String> = fields
+                .iter()
+                .zip(&bits)
+                .filter_map(|((field_name, _), bit)| match bit {
+                    Some((n, mask)) if *n == word => {
+                        let rust_field = ctx.config.naming.field_name(field_name);
+                        Some(match mask.trailing_zeros() {
+                            0 => format!("u32::from(self.{rust_field}.is_some())"),
+                            shift => format!("(u32::from(self.{rust_field}.is_some()) << {shift})"),
+                        })
+                    }
+                    _ => None,
+                })
+                .collect();
+            writeln!(w, "mp::write_uint(wr, u64::from({}));", flags.join(" | ")).unwrap();
+        }
+        for ((field_name, field_type), bit) in fields.iter().zip(&bits) {
+            let rust_field = ctx.config.naming.field_name(field_name);
+            let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+            if bit.is_some() {
+                w.block(
+                    &format!("if let Some(value) = &self.{rust_field} {{"),
+                    "}",
+                    |w| {
+                        generate_pack_value(w, ctx, "value", &non_nullable(field_type), int64);
+                    },
+                );
+            } else {
+                let expr = format!("&self.{rust_field}");
+                generate_pack_value(w, ctx, &expr, field_type, int64);
+            }
+        }
+    });
+
+    generate_positional_unpack(w, ctx, ident, fields, annotations);
+}
+
+/// The `unpack` method of the positional and bitmap formats, which in a `...Ref` struct borrows
+/// from the bytes being read
+fn generate_positional_unpack(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let rust_name = ctx.config.naming.type_name(ident);
+    let bits = ctx.config.wire.protocol().presence_bits(fields);
+    let word_count = bits
+        .iter()
+        .flatten()
+        .map(|(word, _)| word + 1)
+        .max()
+        .unwrap_or(0);
+    let lifetime = if ctx.borrowed { "'a " } else { "" };
+
+    w.blank();
+    w.block(
+        &format!("fn unpack(rd: &mut &{lifetime}[u8]) -> Result<Self, DecodeError> {{"),
+        "}",
+        |w| {
+            for word in 0..word_count {
+                writeln!(w, "let presence{word} = mp::read_int::<u32>(rd)?;").unwrap();
+            }
+            for ((field_name, field_type), bit) in fields.iter().zip(&bits) {
+                let rust_field = ctx.config.naming.field_name(field_name);
+                let path = format!("{rust_name}.{rust_field}");
+                let var = format!("f_{rust_field}");
+                let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+                match bit {
+                    Some((word, mask)) => {
+                        w.block(
+                            &format!("let {var} = if presence{word} & 0x{mask:x} != 0 {{"),
+                            "} else {",
+                            |w| {
+                                generate_unpack_value(
+                                    w,
+                                    ctx,
+                                    &path,
+                                    "value",
+                                    &non_nullable(field_type),
+                                    int64,
+                                );
+                                writeln!(w, "Some(value)").unwrap();
+                            },
+                        );
+                        w.indent();
+                        writeln!(w, "None").unwrap();
+                        w.dedent();
+                        writeln!(w, "}};").unwrap();
+                    }
+                    None => generate_unpack_value(w, ctx, &path, &var, field_type, int64),
+                }
+            }
+            w.block("Ok(Self {", "})", |w| {
+                for (field_name, field_type) in fields {
+                    let rust_field = ctx.config.naming.field_name(field_name);
+                    let value = boxed_value_str(
+                        &format!("f_{rust_field}"),
+                        field_type,
+                        ctx.is_boxed(ident, field_name),
+                    );
+
+                    writeln!(w, "{rust_field}: {value},").unwrap();
+                }
+            });
+        },
+    );
+}
+
+fn generate_keyed_methods(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let rust_name = ctx.config.naming.type_name(ident);
+    let required_count = fields
+        .iter()
+        .filter(|(_, field_type)| !is_nullable(field_type))
+        .count();
+
+    w.blank();
+    w.block("fn pack(&self, wr: &mut Vec<u8>) {", "}", |w| {
+        if required_count < fields.len() {
+            writeln!(w, "let mut len = {required_count};").unwrap();
+            for (field_name, field_type) in fields {
+                if is_nullable(field_type) {
+                    let rust_field = ctx.config.naming.field_name(field_name);
+                    writeln!(w, "len += self.{rust_field}.is_some() as usize;").unwrap();
+                }
+            }
+            writeln!(w, "mp::write_map_len(wr, len);").unwrap();
+        } else {
+            writeln!(w, "mp::write_map_len(wr, {required_count});").unwrap();
+        }
+
+        for (field_name, field_type) in fields {
+            let rust_field = ctx.config.naming.field_name(field_name);
+            let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+
+            if is_nullable(field_type) {
+                w.block(
+                    &format!("if let Some(value) = &self.{rust_field} {{"),
+                    "}",
+                    |w| {
+                        writeln!(w, "mp::write_str(wr, \"{field_name}\");").unwrap();
+                        generate_pack_value(w, ctx, "value", &non_nullable(field_type), int64);
+                    },
+                );
+            } else {
+                writeln!(w, "mp::write_str(wr, \"{field_name}\");").unwrap();
+                let expr = format!("&self.{rust_field}");
+                generate_pack_value(w, ctx, &expr, field_type, int64);
+            }
+        }
+    });
+
+    w.blank();
+    w.block(
+        "fn unpack(rd: &mut &[u8]) -> Result<Self, DecodeError> {",
+        "}",
+        |w| {
+            for (field_name, _) in fields {
+                let rust_field = ctx.config.naming.field_name(field_name);
+                writeln!(w, "let mut f_{rust_field} = None;").unwrap();
+            }
+            w.block("for _ in 0..mp::read_map_len(rd)? {", "}", |w| {
+                w.block("match mp::read_str(rd)?.as_str() {", "}", |w| {
+                    for (field_name, field_type) in fields {
+                        let rust_field = ctx.config.naming.field_name(field_name);
+                        let path = format!("{rust_name}.{rust_field}");
+                        let int64 = ctx.config.int64.for_field(annotations.member(field_name));
+
+                        w.block(&format!("\"{field_name}\" => {{"), "}", |w| {
+                            generate_unpack_value(w, ctx, &path, "value", field_type, int64);
+                            if is_nullable(field_type) {
+                                writeln!(w, "f_{rust_field} = value;").unwrap();
+                            } else {
+                                writeln!(w, "f_{rust_field} = Some(value);").unwrap();
+                            }
+                        });
+                    }
+                    writeln!(w, "_ => mp::skip(rd)?,").unwrap();
+                });
+            });
+            w.block("Ok(Self {", "})", |w| {
+                for (field_name, field_type) in fields {
+                    let rust_field = ctx.config.naming.field_name(field_name);
+                    let value = if is_nullable(field_type) {
+                        format!("f_{rust_field}")
+                    } else {
+                        format!(
+                            "f_{rust_field}.ok_or_else(|| mp::missing(\"{rust_name}.{rust_field}\"))?"
+                        )
+                    };
+                    let value =
+                        boxed_value_str(&value, field_type, ctx.is_boxed(ident, field_name));
+
+                    writeln!(w, "{rust_field}: {value},").unwrap();
+                }
+            });
+        },
+    );
+}
+
+/// The value behind a reference expression, e.g. `&self.x` -> `self.x`, `e0` -> `*e0`
+fn deref(expr: &str) -> String {
+    match expr.strip_prefix('&') {
+        Some(place) => place.to_string(),
+        None => format!("*{expr}"),
+    }
+}
+
+/// A reference expression used as a method receiver, relying on auto-deref
+fn receiver(expr: &str) -> &str {
+    expr.strip_prefix('&').unwrap_or(expr)
+}
+
+/// Statement writing an integer, or an enum when `value` is `*self`
+fn write_int_call(value: &str, it: &ast::IntegerType) -> String {
+    match it {
+        ast::IntegerType::U64 if value != "*self" => format!("mp::write_uint(wr, {value});"),
+        ast::IntegerType::I64 if value != "*self" => format!("mp::write_int(wr, {value});"),
+        ast::IntegerType::U8
+        | ast::IntegerType::U16
+        | ast::IntegerType::U32
+        | ast::IntegerType::U64 => format!("mp::write_uint(wr, {value} as u64);"),
+        _ => format!("mp::write_int(wr, {value} as i64);"),
+    }
+}
+
+/// Write statements that pack the value `expr` refers to.  `expr` is always a reference.
+fn generate_pack_field(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    expr: &str,
+    ft: &ast::FieldType,
+    depth: usize,
+) {
+    if is_nullable(ft) {
+        let var = format!("value{depth}");
+        let marker = ctx
+            .config
+            .wire
+            .protocol()
+            .has_presence_marker(ft, |name| ctx.enum_types.contains_key(name));
+
+        w.block(
+            &format!("if let Some({var}) = {expr} {{"),
+            "} else {",
+            |w| {
+                if marker {
+                    writeln!(w, "mp::write_bool(wr, true);").unwrap();
+                }
+                generate_pack_field(w, ctx, &var, &non_nullable(ft), depth + 1);
+            },
+        );
+        w.indent();
+        writeln!(w, "mp::write_nil(wr);").unwrap();
+        w.dedent();
+        writeln!(w, "}}").unwrap();
+        return;
+    }
+
+    match ft {
+        ast::FieldType::Builtin(bt, _) => match bt {
+            ast::BuiltinType::Integer(it) => {
+                writeln!(w, "{}", write_int_call(&deref(expr), it)).unwrap();
+            }
+            ast::BuiltinType::Float(ast::FloatType::F32) => {
+                writeln!(w, "mp::write_f64(wr, {} as f64);", deref(expr)).unwrap();
+            }
+            ast::BuiltinType::Float(ast::FloatType::F64) => {
+                writeln!(w, "mp::write_f64(wr, {});", deref(expr)).unwrap();
+            }
+            ast::BuiltinType::String => {
+                writeln!(w, "mp::write_str(wr, {expr});").unwrap();
+            }
+            ast::BuiltinType::LocalizedString => {
+                writeln!(w, "{}.pack(wr);", receiver(expr)).unwrap();
+            }
+            ast::BuiltinType::Bool => {
+                writeln!(w, "mp::write_bool(wr, {});", deref(expr)).unwrap();
+            }
+        },
+        ast::FieldType::UserDefined(..) => {
+            writeln!(w, "{}.pack(wr);", receiver(expr)).unwrap();
+        }
+        ast::FieldType::Array(inner, _, _) => {
+            let var = format!("e{depth}");
+            writeln!(w, "mp::write_array_len(wr, {}.len());", receiver(expr)).unwrap();
+            w.block(&format!("for {var} in {expr} {{"), "}", |w| {
+                generate_pack_field(w, ctx, &var, inner, depth + 1);
+            });
+        }
+        ast::FieldType::Map(key_type, value_type, _) => {
+            let key_var = format!("k{depth}");
+            let value_var = format!("v{depth}");
+            writeln!(w, "mp::write_map_len(wr, {}.len());", receiver(expr)).unwrap();
+            w.block(
+                &format!("for ({key_var}, {value_var}) in {expr} {{"),
+                "}",
+                |w| {
+                    generate_pack_field(w, ctx, &key_var, &map_key_field_type(key_type), depth + 1);
+                    generate_pack_field(w, ctx, &value_var, value_type, depth + 1);
+                },
+            );
+        }
+    }
+}
+
+/// Write statements that pack a struct field, with a 64-bit integer as `int64` asks.  `expr` is
+/// always a reference.
+fn generate_pack_value(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    expr: &str,
+    ft: &ast::FieldType,
+    int64: Int64,
+) {
+    if !(is_int64(ft) && int64.is_string()) {
+        generate_pack_field(w, ctx, expr, ft, 0);
+        return;
+    }
+
+    if is_nullable(ft) {
+        w.block(
+            &format!("if let Some(value0) = {expr} {{"),
+            "} else {",
+            |w| {
+                writeln!(w, "mp::write_str(wr, &value0.to_string());").unwrap();
+            },
+        );
+        w.indent();
+        writeln!(w, "mp::write_nil(wr);").unwrap();
+        w.dedent();
+        writeln!(w, "}}").unwrap();
+    } else {
+        writeln!(w, "mp::write_str(wr, &{}.to_string());", receiver(expr)).unwrap();
+    }
+}
+
+/// Write statements that unpack a struct field into a new variable `var`, with a 64-bit integer
+/// as `int64` asks
+fn generate_unpack_value(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    path: &str,
+    var: &str,
+    ft: &ast::FieldType,
+    int64: Int64,
+) {
+    let read = match ft {
+        ast::FieldType::Builtin(ast::BuiltinType::Integer(it), _)
+            if is_int64(ft) && int64.is_string() =>
+        {
+            format!(
+                "mp::read_int_str::<{}>(rd, \"{path}\")?",
+                integer_type_str(it)
+            )
+        }
+        _ => {
+            generate_unpack_field(w, ctx, path, var, ft, 0);
+            return;
+        }
+    };
+
+    if is_nullable(ft) {
+        w.block(
+            &format!("let {var} = if mp::read_nil(rd) {{"),
+            "} else {",
+            |w| {
+                writeln!(w, "None").unwrap();
+            },
+        );
+        w.indent();
+        writeln!(w, "Some({read})").unwrap();
+        w.dedent();
+        writeln!(w, "}};").unwrap();
+    } else {
+        writeln!(w, "let {var} = {read};").unwrap();
+    }
+}
+
+/// Write statements that unpack a value into a new variable `var`
+fn generate_unpack_field(
+    w: &mut CodeWriter,
+    ctx: &Context,
+    path: &str,
+    var: &str,
+    ft: &ast::FieldType,
+    depth: usize,
+) {
+    if is_nullable(ft) {
+        let marker = ctx
+            .config
+            .wire
+            .protocol()
+            .has_presence_marker(ft, |name| ctx.enum_types.contains_key(name));
+        let inner_var = format!("value{depth}");
+
+        w.block(
+            &format!("let {var} = if mp::read_nil(rd) {{"),
+            "} else {",
+            |w| {
+                writeln!(w, "None").unwrap();
+            },
+        );
+        w.indent();
+        if marker {
+            writeln!(w, "mp::read_bool(rd)?;").unwrap();
+        }
+        generate_unpack_field(w, ctx, path, &inner_var, &non_nullable(ft), depth + 1);
+        writeln!(w, "Some({inner_var})").unwrap();
+        w.dedent();
+        writeln!(w, "}};").unwrap();
+        return;
+    }
+
+    match ft {
+        ast::FieldType::Builtin(bt, _) => {
+            let expr = match bt {
+                ast::BuiltinType::Integer(it) => {
+                    format!("mp::read_int::<{}>(rd)?", integer_type_str(it))
+                }
+                ast::BuiltinType::Float(ast::FloatType::F32) => "mp::read_f32(rd)?".to_string(),
+                ast::BuiltinType::Float(ast::FloatType::F64) => "mp::read_f64(rd)?".to_string(),
+                ast::BuiltinType::String if ctx.borrowed => "mp::read_str_ref(rd)?".to_string(),
+                ast::BuiltinType::String => "mp::read_str(rd)?".to_string(),
+                ast::BuiltinType::LocalizedString => "LocalizedString::unpack(rd)?".to_string(),
+                ast::BuiltinType::Bool => "mp::read_bool(rd)?".to_string(),
+            };
+            writeln!(w, "let {var} = {expr};").unwrap();
+        }
+        ast::FieldType::UserDefined(name, _) => {
+            let suffix = if ctx.borrowed && ctx.borrowing.contains(name.as_str()) {
+                "Ref"
+            } else {
+                ""
+            };
+
+            writeln!(
+                w,
+                "let {var} = {}{suffix}::unpack(rd)?;",
+                ctx.config.naming.type_name(name)
+            )
+            .unwrap();
+        }
+        ast::FieldType::Array(inner, length, _) => {
+            let items_var = format!("items{depth}");
+            let element_var = format!("e{depth}");
+
+            writeln!(w, "let mut {items_var} = Vec::new();").unwrap();
+            w.block("for _ in 0..mp::read_array_len(rd)? {", "}", |w| {
+                generate_unpack_field(w, ctx, path, &element_var, inner, depth + 1);
+                writeln!(w, "{items_var}.push({element_var});").unwrap();
+            });
+            match length {
+                Some(_) => {
+                    writeln!(w, "let {var} = mp::fixed_array({items_var}, \"{path}\")?;").unwrap()
+                }
+                None => writeln!(w, "let {var} = {items_var};").unwrap(),
+            }
+        }
+        ast::FieldType::Map(key_type, value_type, _) => {
+            let map_var = format!("map{depth}");
+            let key_var = format!("k{depth}");
+            let value_var = format!("v{depth}");
+
+            writeln!(w, "let mut {map_var} = HashMap::new();").unwrap();
+            w.block("for _ in 0..mp::read_map_len(rd)? {", "}", |w| {
+                generate_unpack_field(
+                    w,
+                    ctx,
+                    path,
+                    &key_var,
+                    &map_key_field_type(key_type),
+                    depth + 1,
+                );
+                generate_unpack_field(w, ctx, path, &value_var, value_type, depth + 1);
+                writeln!(w, "{map_var}.insert({key_var}, {value_var});").unwrap();
+            });
+            writeln!(w, "let {var} = {map_var};").unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_str(input: &str, options: &[&str]) -> String {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+        let config = Config::from_options(&schema, &Options::parse(options).unwrap()).unwrap();
+
+        generate(&schema, config)
+    }
+
+    #[test]
+    fn positional() {
+        let output = generate_str(
+            "meta { format = 1 } enum color: u8 { red = 1 } struct point { x: i32, c: color?, tags: [string]? }",
+            &[],
+        );
+
+        assert!(output.contains("mp::write_int(wr, self.x as i64);"));
+        assert!(output.contains("if let Some(value0) = &self.c {\n            value0.pack(wr);"));
+        assert!(
+            output.contains(
+                "if let Some(value0) = &self.tags {\n            mp::write_bool(wr, true);"
+            )
+        );
+        assert!(output.contains("let f_x = mp::read_int::<i32>(rd)?;"));
+        assert!(output.contains("Self::try_from(mp::read_int::<u8>(rd)?).map_err(DecodeError)"));
+    }
+
+    #[test]
+    fn topics() {
+        let output = generate_str(
+            "meta { format = 1 } struct user_event { id: u64 } topic user_events: user_event;",
+            &[],
+        );
+
+        assert!(output.contains("pub struct Topic<T: 'static> {"));
+        assert!(output.contains("pub const USER_EVENTS: Topic<UserEvent> = Topic {"));
+        assert!(output.contains("decode: UserEvent::from_bytes,"));
+        assert!(!generate_str("meta { format = 1 } struct a { b: i8 }", &[]).contains("Topic"));
+    }
+
+    #[test]
+    fn roots_only() {
+        let schema = "meta { format = 1 } enum kind: u8 { a = 1 } @root struct order { k: kind } struct part { k: kind }";
+
+        assert_eq!(
+            generate_str(schema, &[]).matches("pub fn to_bytes").count(),
+            3
+        );
+
+        let output = generate_str(schema, &["roots_only=true", "framing=true"]);
+
+        assert_eq!(output.matches("pub fn to_bytes").count(), 1);
+        assert_eq!(output.matches("pub fn read_delimited_from<").count(), 1);
+        assert!(output.contains("impl Part {\n    fn pack(&self, wr: &mut Vec<u8>) {"));
+    }
+
+    #[test]
+    fn sensitive_fields() {
+        let schema = "meta { format = 1 } struct user { id: u64, @pii email: string }";
+        let output = generate_str(schema, &[]);
+
+        assert!(output.contains("#[derive(Clone, PartialEq)]\npub struct User {"));
+        assert!(output.contains("impl std::fmt::Debug for User {"));
+        assert!(output.contains(".field(\"id\", &self.id)"));
+        assert!(output.contains(".field(\"email\", &\"***\")"));
+
+        let output = generate_str(schema, &["mask_sensitive=false"]);
+
+        assert!(output.contains("#[derive(Debug, Clone, PartialEq)]\npub struct User {"));
+        assert!(!output.contains("impl std::fmt::Debug for User {"));
+    }
+
+    #[test]
+    fn redaction() {
+        let schema = "meta { format = 1 } struct user { id: u64, @sensitive pin: [u8; 4], home: address? } struct address { @pii street: string }";
+        let output = generate_str(schema, &["redaction=true"]);
+
+        assert!(output.contains("id: self.id.clone(),"));
+        assert!(output.contains("pin: std::array::from_fn(|_| 0),"));
+        assert!(output.contains("home: self.home.as_ref().map(|v0| v0.redacted()),"));
+        assert!(output.contains("street: String::new(),"));
+        assert!(output.contains("impl std::fmt::Display for Address {"));
+    }
+
+    #[test]
+    fn abi_stable_structs() {
+        let output = generate_str(
+            "meta { format = 1 } @abi(stable) struct vec2 { x: f32, y: f32 }",
+            &[],
+        );
+
+        assert!(output.contains("#[repr(C)]\npub struct Vec2 {"));
+    }
+
+    #[test]
+    fn keyed() {
+        let output = generate_str(
+            "meta { format = 1, wire = \"keyed\" } struct point { x_pos: i32, label: string? }",
+            &[],
+        );
+
+        assert!(output.contains("len += self.label.is_some() as usize;"));
+        assert!(output.contains("mp::write_str(wr, \"x_pos\");"));
+        assert!(output.contains("_ => mp::skip(rd)?,"));
+        assert!(output.contains("x_pos: f_x_pos.ok_or_else(|| mp::missing(\"Point.x_pos\"))?,"));
+        assert!(!output.contains("mp::write_bool(wr, true);"));
+
+        let output = generate_str(
+            "meta { format = 1, wire = \"keyed\" } struct point { x: i32 }",
+            &["wire=positional"],
+        );
+
+        assert!(!output.contains("mp::skip(rd)?"));
+    }
+
+    #[test]
+    fn bitmap() {
+        let output = generate_str(
+            "meta { format = 1 } struct point { x: i32, tags: [string]?, label: string? }",
+            &["wire=bitmap"],
+        );
+
+        assert!(output.contains(
+            "mp::write_uint(wr, u64::from(u32::from(self.tags.is_some()) | (u32::from(self.label.is_some()) << 1)));"
+        ));
+        assert!(output.contains("let presence0 = mp::read_int::<u32>(rd)?;"));
+        assert!(output.contains("let f_label = if presence0 & 0x2 != 0 {"));
+        assert!(!output.contains("mp::write_bool(wr, true);"));
+    }
+
+    #[test]
+    fn json_derives() {
+        let input =
+            "meta { format = 1 } enum color: u8 { dark_red = 1 } struct point { xPos: i32 }";
+        let output = generate_str(input, &["json=true"]);
+
+        assert!(output.contains("use serde::{Deserialize, Serialize};"));
+        assert!(output.contains("#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]"));
+        assert!(output.contains("#[serde(rename = \"dark_red\")]"));
+        assert!(output.contains("#[serde(rename = \"xPos\")]"));
+
+        assert!(!generate_str(input, &[]).contains("serde"));
+    }
+
+    #[test]
+    fn int64_fields() {
+        let input =
+            "meta { format = 1 } struct big { @int64(string) id: u64, maybe: i64?, n: i64 }";
+        let output = generate_str(input, &["int64=bigint", "json=true"]);
+
+        assert!(output.contains("pub fn read_int_str<T: std::str::FromStr>("));
+        assert!(output.contains("mp::write_str(wr, &self.id.to_string());"));
+        assert!(output.contains("let f_id = mp::read_int_str::<u64>(rd, \"Big.id\")?;"));
+        assert!(output.contains("Some(mp::read_int_str::<i64>(rd, \"Big.maybe\")?)"));
+        assert!(output.contains("mod int64_string {"));
+        assert!(output.contains("#[serde(with = \"int64_string\")]\n    pub id: u64,"));
+        assert!(output.contains(
+            "#[serde(default, with = \"int64_string::option\")]\n    pub maybe: Option<i64>,"
+        ));
+
+        let output = generate_str(input, &["json=true"]);
+        assert!(output.contains("mp::write_int(wr, self.n);"));
+        assert!(!output.contains("pub maybe: Option<i64>,\n    #[serde"));
+    }
+
+    #[test]
+    fn framing() {
+        let input = "meta { format = 1 } struct point { x: i32 }";
+
+        assert!(generate_str(input, &["framing=true"]).contains(
+            "pub fn read_delimited_from<R: std::io::Read>(reader: &mut R) -> std::io::Result<Option<Self>> {"
+        ));
+        // A length prefix is checked before a buffer is allocated for it
+        assert!(
+            generate_str(input, &["framing=true"])
+                .contains("Self::read_delimited_from_with_limit(reader, mp::MAX_FRAME_LENGTH)")
+        );
+        assert!(!generate_str(input, &[]).contains("write_delimited_to"));
+    }
+
+    #[test]
+    fn tokio_codec() {
+        let input =
+            "meta { format = 1 } struct point { x: i32 } @root struct line { a: point, b: point }";
+
+        // Only roots get a codec, whether or not the other types get public methods
+        for options in [
+            &["tokio_codec=true"][..],
+            &["tokio_codec=true", "roots_only=true"],
+        ] {
+            let output = generate_str(input, options);
+
+            assert!(output.contains("mod tokio_codec {"));
+            assert!(output.contains("impl tokio_util::codec::Encoder<Line> for LineCodec {"));
+            assert!(output.contains("impl tokio_util::codec::Decoder for LineCodec {"));
+            assert!(!output.contains("PointCodec"));
+        }
+        assert!(!generate_str(input, &[]).contains("tokio_util"));
+    }
+
+    #[test]
+    fn compression() {
+        let input = "meta { format = 1 } struct batch { items: [string] }";
+        let output = generate_str(input, &["compression=zlib", "compress_above=256"]);
+
+        assert!(output.contains("const THRESHOLD: usize = 256;"));
+        assert!(output.contains("const ALGORITHM: u8 = 1;"));
+        assert!(output.contains("flate2::read::ZlibDecoder::new(bytes).take(limit)"));
+        assert!(output.contains("const MAX_DECOMPRESSED: usize = 16777216;"));
+        assert!(output.contains("compression::compress(wr)"));
+        assert!(output.contains("let bytes = compression::decompress(bytes)?;"));
+
+        let output = generate_str(input, &["compression=zstd"]);
+
+        assert!(output.contains("const THRESHOLD: usize = 1024;"));
+        assert!(output.contains("zstd::stream::read::Decoder::new(bytes)?.take(limit)"));
+        assert!(
+            generate_str(input, &["compression=zstd", "max_decompressed=4096"])
+                .contains("const MAX_DECOMPRESSED: usize = 4096;")
+        );
+        assert!(!generate_str(input, &[]).contains("compression"));
+        assert!(
+            Config::from_options(
+                &crate::GenoAstBuilder::new("test.geno".into())
+                    .build_from_str(input)
+                    .unwrap(),
+                &Options::parse(["compression=zlib", "compress_above=big"]).unwrap()
+            )
+            .is_err()
+        );
+        assert!(
+            Config::from_options(
+                &crate::GenoAstBuilder::new("test.geno".into())
+                    .build_from_str(input)
+                    .unwrap(),
+                &Options::parse(["compression=zlib", "max_decompressed=-1"]).unwrap()
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn size_bounds() {
+        let input =
+            "meta { format = 1 } struct point { x: i32, y: i32 } struct tag { name: string }";
+        let output = generate_str(input, &["size_bounds=true"]);
+
+        assert!(output.contains(
+            "pub const fn packed_size_upper_bound() -> Option<usize> {\n        Some(10)\n    }"
+        ));
+        assert!(output.contains(
+            "pub const fn packed_size_upper_bound() -> Option<usize> {\n        None\n    }"
+        ));
+        assert!(!generate_str(input, &[]).contains("packed_size_upper_bound"));
+    }
+
+    #[test]
+    fn borrowed() {
+        let input = "meta { format = 1 } struct point { x: i32 }
+            struct label { text: string, at: point } struct shape { name: string?, labels: [label] }";
+        let output = generate_str(input, &["borrowed=true"]);
+
+        assert!(
+            output.contains(
+                "pub struct LabelRef<'a> {\n    pub text: &'a str,\n    pub at: Point,\n}"
+            )
+        );
+        assert!(output.contains("pub labels: Vec<LabelRef<'a>>,"));
+        assert!(!output.contains("PointRef"));
+        assert!(
+            output.contains("labels: self.labels.into_iter().map(|e0| e0.into_owned()).collect(),")
+        );
+        assert!(output.contains("fn unpack(rd: &mut &'a [u8]) -> Result<Self, DecodeError> {"));
+        assert!(
+            output.contains(
+                "pub fn unpack_into(&mut self, bytes: &[u8]) -> Result<(), DecodeError> {"
+            )
+        );
+        assert!(output.contains("self.text.clear();\n        self.text.push_str(f_text);"));
+        assert!(output.contains("self.at.unpack_in_place(rd)?;"));
+        assert!(!generate_str(input, &[]).contains("unpack_into"));
+
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+
+        for options in [
+            ["borrowed=true", "wire=keyed"],
+            ["borrowed=true", "compression=zlib"],
+        ] {
+            assert!(matches!(
+                Config::from_options(&schema, &Options::parse(options).unwrap()),
+                Err(GenoError::InvalidOption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn envelope() {
+        let input =
+            "meta { format = 1 } struct point { x: i32 } struct line { a: point, b: point }";
+        let output = generate_str(input, &["envelope=crc32"]);
+        let fingerprint = output
+            .lines()
+            .find(|line| line.starts_with("pub const SCHEMA_FINGERPRINT"))
+            .unwrap();
+
+        assert!(output.contains("envelope::seal(&self.to_bytes(), true)"));
+        assert!(
+            output.contains("pub fn from_envelope(bytes: &[u8]) -> Result<Self, DecodeError> {")
+        );
+        // The order of the declarations doesn't change the fingerprint
+        assert!(
+            generate_str(
+                "meta { format = 1 } struct line { a: point, b: point } struct point { x: i32 }",
+                &["envelope=fingerprint"]
+            )
+            .contains(&format!("{fingerprint}\n"))
+        );
+        assert!(
+            generate_str(input, &["envelope=fingerprint"])
+                .contains("envelope::seal(&self.to_bytes(), false)")
+        );
+        assert!(!generate_str(input, &[]).contains("envelope"));
+        assert!(matches!(
+            RustMpGenerator.generate(
+                &crate::GenoAstBuilder::new("test.geno".into())
+                    .build_from_str(input)
+                    .unwrap(),
+                &Options::parse(["envelope=md5"]).unwrap()
+            ),
+            Err(GenoError::InvalidOption(option)) if option == "envelope=md5"
+        ));
+    }
+
+    #[test]
+    fn driver() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum color: u8 { red = 1 } struct point { x: i32 }",
+            )
+            .unwrap();
+        let output = generate_driver(&schema, &Options::new()).unwrap();
+
+        assert!(output.starts_with("mod geno;\n"));
+        assert!(output.contains("\"color\" => geno::Color::from_bytes(bytes)"));
+        assert!(output.contains("\"point\" => geno::Point::from_bytes(bytes)"));
+    }
+
+    #[test]
+    fn localized_strings() {
+        let output = generate_str(
+            "meta { format = 1 } struct product { name: lstring, tagline: lstring? }",
+            &[],
+        );
+
+        assert_eq!(output.matches("pub struct LocalizedString(").count(), 1);
+        assert!(output.contains("pub name: LocalizedString,"));
+        assert!(output.contains("self.name.pack(wr);"));
+        assert!(output.contains(
+            "if let Some(value0) = &self.tagline {\n            mp::write_bool(wr, true);\n            value0.pack(wr);"
+        ));
+        assert!(output.contains("let f_name = LocalizedString::unpack(rd)?;"));
+        assert!(!output.contains("#[serde(transparent)]"));
+        assert!(
+            generate_str(
+                "meta { format = 1 } struct product { name: lstring }",
+                &["json=true"]
+            )
+            .contains("#[serde(transparent)]")
+        );
+    }
+
+    #[test]
+    fn recursive_structs() {
+        let input =
+            "meta { format = 1 } struct node { name: string, parent: node?, children: [node] }";
+        let output = generate_str(input, &[]);
+
+        assert!(output.contains("pub parent: Option<Box<Node>>,"));
+        assert!(output.contains("pub children: Vec<Node>,"));
+        assert!(output.contains("parent: f_parent.map(Box::new),"));
+
+        let output = generate_str(input, &["wire=keyed"]);
+
+        assert!(output.contains("parent: f_parent.map(Box::new),"));
+
+        let output = generate_str(input, &["borrowed=true"]);
+
+        assert!(output.contains("pub parent: Option<Box<NodeRef<'a>>>,"));
+        assert!(
+            output.contains("parent: self.parent.map(|value0| value0.into_owned()).map(Box::new),")
+        );
+    }
+
+    #[test]
+    fn property_tests() {
+        let input = "meta { format = 1 } enum kind: u8 { a = 1, b = 2 } struct node { id: u64, weight: f32, kind: kind?, children: [node], names: [string; 2] } @root struct tree { root: node }";
+        let output = generate_str(input, &["property_tests=true", "roots_only=true"]);
+
+        assert!(output.contains("#[cfg(test)]\nmod property_tests {"));
+        assert!(output.contains("impl Arbitrary for Kind {"));
+        assert!(output.contains("proptest::sample::select(vec![Kind::A, Kind::B]).boxed()"));
+        assert!(output.contains(
+            "(any::<u64>(), (prop::num::f32::NORMAL | prop::num::f32::ZERO), proptest::option::of(any::<Kind>()), Just(Default::default()), prop::collection::vec(any::<String>(), 2).prop_map(|v| <[String; 2]>::try_from(v).unwrap())).prop_map(|(f0, f1, f2, f3, f4)| Node { id: f0, weight: f1, kind: f2, children: f3, names: f4 }).boxed()"
+        ));
+        assert!(output.contains("(any::<Node>(),).prop_map(|(f0,)| Tree { root: f0 }).boxed()"));
+        assert!(output.contains("fn tree_round_trips(value in any::<Tree>()) {"));
+        assert!(!output.contains("fn node_round_trips"));
+        assert!(!generate_str(input, &[]).contains("proptest"));
+    }
+
+    #[test]
+    fn property_test_tuples() {
+        let fields: Vec<String> = (0..12).map(|i| format!("f{i}: u8")).collect();
+        let input = format!(
+            "meta {{ format = 1 }} struct wide {{ {} }}",
+            fields.join(", ")
+        );
+        let output = generate_str(&input, &["property_tests=true"]);
+
+        assert!(output.contains("any::<u8>(), any::<u8>()), (any::<u8>(), any::<u8>())).prop_map(|((f0, f1, f2, f3, f4, f5, f6, f7, f8, f9), (f10, f11))| Wide {"));
+    }
+}
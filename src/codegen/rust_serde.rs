@@ -0,0 +1,1222 @@
+use crate::{
+    GenoError, ast,
+    codegen::{CodeSink, GeneratedFile, GeneratedFiles, Generator, Options, util::*},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    path::Path,
+};
+
+/// Generator for the `rust-serde` format
+pub struct RustSerdeGenerator;
+
+/// Floats aren't `Eq` or `Hash`, so they can't be `HashMap` keys
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+impl Generator for RustSerdeGenerator {
+    fn name(&self) -> &str {
+        "rust-serde"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "derives",
+            "struct_attributes",
+            "enum_attributes",
+            "default_impl",
+            "constructors",
+            "mask_sensitive",
+            "redaction",
+            "enum_helpers",
+            "enum_repr",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        Ok(vec![GeneratedFile::new(
+            "geno.rs",
+            generate(schema, options)?,
+        )])
+    }
+
+    fn generate_to(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+        sink: &mut dyn CodeSink,
+    ) -> Result<(), GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let config = Config::from_options(schema, options)?;
+
+        sink.write_file(Path::new("geno.rs"), &mut |out| {
+            let mut w = CodeWriter::to_writer("    ", out);
+
+            write_file(&mut w, schema, &config);
+            Ok(w.finish()?)
+        })
+    }
+}
+
+/// Settings for one run of the generator, read from the [Options]
+struct Config {
+    naming: Naming,
+    /// Extra traits to derive where the field types allow it
+    derives: Vec<String>,
+    /// Attributes written on every struct, without the `#[...]`
+    struct_attributes: Vec<String>,
+    /// Attributes written on every enum, without the `#[...]`
+    enum_attributes: Vec<String>,
+    /// Write an `impl Default` for every struct
+    default_impl: bool,
+    /// Write a `new()` constructor and `with_*` setters for every struct
+    constructors: bool,
+    /// How enums are serialized
+    enum_repr: EnumRepr,
+    /// Write a `map` method with one argument per variant for every enum
+    enum_helpers: bool,
+    /// Show `@sensitive` and `@pii` fields as `***` in `Debug` output
+    mask_sensitive: bool,
+    /// Generate `redacted()` and a `Display` impl for every struct
+    redaction: Option<Redaction>,
+    /// The [boxed_fields] of the schema
+    boxed: HashSet<(String, String)>,
+}
+
+/// Serde representation of enums
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EnumRepr {
+    /// The variant name
+    String,
+    /// The variant value, as the enum's base type
+    Int,
+}
+
+impl Config {
+    fn from_options(schema: &ast::Schema, options: &Options) -> Result<Self, GenoError> {
+        Ok(Self {
+            naming: Naming::from_options(
+                options,
+                DEFAULT_NAMING,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )?,
+            derives: list_option(options, "derives", ','),
+            struct_attributes: list_option(options, "struct_attributes", ';'),
+            enum_attributes: list_option(options, "enum_attributes", ';'),
+            default_impl: options.get_bool("default_impl", false)?,
+            constructors: options.get_bool("constructors", false)?,
+            mask_sensitive: options.get_bool("mask_sensitive", true)?,
+            redaction: Redaction::from_options(schema, options)?,
+            boxed: boxed_fields(schema)
+                .into_iter()
+                .map(|(ident, field_name)| (ident.to_string(), field_name.to_string()))
+                .collect(),
+            enum_helpers: options.get_bool("enum_helpers", false)?,
+            enum_repr: match options.get("enum_repr") {
+                None | Some("string") => EnumRepr::String,
+                Some("int") => EnumRepr::Int,
+                Some(value) => return Err(GenoError::InvalidOption(format!("enum_repr={value}"))),
+            },
+        })
+    }
+
+    /// Whether a field of a struct is one of the [boxed_fields]
+    fn is_boxed(&self, ident: &str, field_name: &str) -> bool {
+        self.boxed
+            .contains(&(ident.to_string(), field_name.to_string()))
+    }
+}
+
+fn list_option(options: &Options, key: &str, separator: char) -> Vec<String> {
+    options
+        .get(key)
+        .map(|value| {
+            value
+                .split(separator)
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) const DEFAULT_NAMING: Naming = Naming {
+    types: NamingConvention::Pascal,
+    fields: NamingConvention::Snake,
+    variants: NamingConvention::Pascal,
+    ascii: false,
+};
+
+/// Generate a complete Rust source file for the schema, including the `use` prelude
+pub fn generate(schema: &ast::Schema, options: &Options) -> Result<String, GenoError> {
+    schema.check_capabilities(&CAPABILITIES)?;
+
+    let schema = &*select_target(schema, options, Some("rust"))?;
+    let config = Config::from_options(schema, options)?;
+    let mut w = CodeWriter::new("    ");
+
+    write_file(&mut w, schema, &config);
+
+    Ok(w.into_string())
+}
+
+fn write_file(w: &mut CodeWriter, schema: &ast::Schema, config: &Config) {
+    writeln!(w, "#![allow(unused_imports)]").unwrap();
+    w.blank();
+    writeln!(w, "use serde::{{Deserialize, Serialize}};").unwrap();
+    writeln!(w, "use std::collections::HashMap;").unwrap();
+    write_declarations(w, schema, config);
+}
+
+/// Generate only the Rust type declarations for the schema.  The caller is
+/// responsible for bringing `Serialize`, `Deserialize` and `HashMap` into scope.
+pub fn generate_declarations(schema: &ast::Schema, options: &Options) -> Result<String, GenoError> {
+    schema.check_capabilities(&CAPABILITIES)?;
+
+    let schema = &*select_target(schema, options, Some("rust"))?;
+    let config = Config::from_options(schema, options)?;
+    let mut w = CodeWriter::new("    ");
+
+    write_declarations(&mut w, schema, &config);
+
+    Ok(w.into_string())
+}
+
+fn write_declarations(w: &mut CodeWriter, schema: &ast::Schema, config: &Config) {
+    let derivable = derivable_traits(schema, &config.derives);
+
+    if has_topics(schema) {
+        w.blank();
+        w.write_str(TOPIC_TYPE).unwrap();
+    }
+    if has_localized_strings(schema) {
+        w.blank();
+        w.write_str(LOCALIZED_STRING_TYPE).unwrap();
+    }
+
+    for decl in &schema.declarations {
+        w.blank();
+        match decl {
+            ast::Declaration::Enum {
+                ident,
+                base_type,
+                variants,
+                visibility,
+                annotations,
+            } => generate_enum(
+                w,
+                config,
+                &derivable[ident.as_str()],
+                ident,
+                *visibility,
+                base_type,
+                variants,
+                annotations,
+            ),
+            ast::Declaration::Struct {
+                ident,
+                fields,
+                implements,
+                visibility,
+                annotations,
+            } => {
+                generate_struct(
+                    w,
+                    config,
+                    &derivable[ident.as_str()],
+                    ident,
+                    *visibility,
+                    fields,
+                    annotations,
+                );
+                generate_trait_impls(w, &config.naming, schema, ident, implements);
+            }
+            ast::Declaration::Interface {
+                ident,
+                fields,
+                visibility,
+                ..
+            } => generate_trait(w, &config.naming, ident, *visibility, fields),
+            ast::Declaration::Topic { ident, payload, .. } => {
+                generate_topic(w, config, ident, payload)
+            }
+        }
+    }
+}
+
+/// The `Topic` type, written once for all topics
+const TOPIC_TYPE: &str = r#"/// A publish/subscribe topic whose messages are `T`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Topic<T> {
+    /// Name of the topic
+    pub name: &'static str,
+    payload: std::marker::PhantomData<fn() -> T>,
+}
+"#;
+
+/// The `LocalizedString` type, written once for all `lstring` fields
+const LOCALIZED_STRING_TYPE: &str = r#"/// Text in several languages, keyed by locale such as `en` or `fr-CA`
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LocalizedString(pub std::collections::BTreeMap<String, String>);
+
+impl LocalizedString {
+    /// The text for a locale, or for its language if there is none, so `fr-CA` falls back to `fr`
+    pub fn get(&self, locale: &str) -> Option<&str> {
+        self.0
+            .get(locale)
+            .or_else(|| self.0.get(locale.split('-').next()?))
+            .map(String::as_str)
+    }
+}
+"#;
+
+fn generate_topic(w: &mut CodeWriter, config: &Config, ident: &str, payload: &str) {
+    writeln!(w, "/// The `{ident}` topic").unwrap();
+    writeln!(
+        w,
+        "pub const {}: Topic<{}> = Topic {{ name: \"{ident}\", payload: std::marker::PhantomData }};",
+        to_screaming_snake_case(ident),
+        config.naming.type_name(payload)
+    )
+    .unwrap();
+}
+
+/// Work out which of the requested extra derives each declaration can legally have.  A struct
+/// can only derive a trait if all of its field types implement it, so start by assuming every
+/// trait is derivable and remove traits until nothing changes, which also handles recursion.
+fn derivable_traits<'a>(
+    schema: &'a ast::Schema,
+    derives: &'a [String],
+) -> HashMap<&'a str, Vec<&'a str>> {
+    let boxed = boxed_fields(schema);
+    let mut derivable: HashMap<&str, Vec<&str>> = schema
+        .declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            ast::Declaration::Enum { ident, .. } | ast::Declaration::Struct { ident, .. } => {
+                Some((ident.as_str(), derives.iter().map(String::as_str).collect()))
+            }
+            ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => None,
+        })
+        .collect();
+
+    loop {
+        let mut changed = false;
+
+        for decl in &schema.declarations {
+            if let ast::Declaration::Struct { ident, fields, .. } = decl {
+                let allowed: Vec<&str> = derivable[ident.as_str()]
+                    .iter()
+                    .copied()
+                    .filter(|derive| {
+                        fields.iter().all(|(field_name, ft)| {
+                            // A `Box` is never `Copy`
+                            !(*derive == "Copy"
+                                && boxed.contains(&(ident.as_str(), field_name.as_str())))
+                                && field_can_derive(ft, derive, &derivable)
+                        })
+                    })
+                    .collect();
+
+                if allowed.len() != derivable[ident.as_str()].len() {
+                    derivable.insert(ident, allowed);
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            return derivable;
+        }
+    }
+}
+
+/// Whether the Rust type for a field implements a derivable trait.  Traits we know nothing
+/// about are assumed to be implemented by everything.
+fn field_can_derive(
+    ft: &ast::FieldType,
+    derive: &str,
+    derivable: &HashMap<&str, Vec<&str>>,
+) -> bool {
+    let nullable = is_nullable(ft);
+
+    match ft {
+        ast::FieldType::Builtin(bt, _) => match bt {
+            ast::BuiltinType::Float(_) => !matches!(derive, "Eq" | "Ord" | "Hash"),
+            ast::BuiltinType::String | ast::BuiltinType::LocalizedString => derive != "Copy",
+            _ => true,
+        },
+        ast::FieldType::UserDefined(name, _) => {
+            (nullable && derive == "Default")
+                || derivable
+                    .get(name.as_str())
+                    .is_some_and(|derives| derives.contains(&derive))
+        }
+        ast::FieldType::Array(inner, length, _) => {
+            let container = match (derive, length) {
+                ("Copy", None) => false,
+                // The standard library only implements `Default` for arrays up to 32 elements
+                ("Default", Some(len)) => nullable || *len <= 32,
+                ("Default", None) => true,
+                _ => true,
+            };
+            container && (derive == "Default" || field_can_derive(inner, derive, derivable))
+        }
+        ast::FieldType::Map(_, value_type, _) => match derive {
+            "Default" => true,
+            "Copy" | "Hash" | "PartialOrd" | "Ord" => false,
+            _ => field_can_derive(value_type, derive, derivable),
+        },
+    }
+}
+
+/// The `#[derive(...)]` line, adding the extra derives that aren't already in the base list
+fn write_derives(w: &mut CodeWriter, base: &[&str], extra: &[&str]) {
+    let mut derives = base.to_vec();
+
+    for derive in extra {
+        if !derives.contains(derive) {
+            derives.push(derive);
+        }
+    }
+
+    writeln!(w, "#[derive({})]", derives.join(", ")).unwrap();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn generate_enum(
+    w: &mut CodeWriter,
+    config: &Config,
+    derives: &[&str],
+    ident: &str,
+    visibility: ast::Visibility,
+    base_type: &ast::IntegerType,
+    variants: &[(String, ast::IntegerValue)],
+    annotations: &ast::Annotations,
+) {
+    let (default_variant, _) = annotations.default_variant(variants);
+    let rust_name = config.naming.type_name(ident);
+    let vis = visibility_str(visibility);
+    let base_type_str = integer_type_str(base_type);
+
+    write_derives(
+        w,
+        &[
+            "Debug",
+            "Clone",
+            "PartialEq",
+            "Eq",
+            "Hash",
+            "Serialize",
+            "Deserialize",
+            "Default",
+        ],
+        derives,
+    );
+    writeln!(w, "#[repr({base_type_str})]").unwrap();
+    if config.enum_repr == EnumRepr::Int {
+        writeln!(
+            w,
+            "#[serde(into = \"{base_type_str}\", try_from = \"{base_type_str}\")]"
+        )
+        .unwrap();
+    }
+    for attribute in &config.enum_attributes {
+        writeln!(w, "#[{attribute}]").unwrap();
+    }
+    w.block(&format!("{vis} enum {rust_name} {{"), "}", |w| {
+        for (variant_name, value) in variants {
+            let rust_variant = config.naming.variant_name(variant_name);
+
+            if variant_name == default_variant {
+                writeln!(w, "#[default]").unwrap();
+            }
+            if config.enum_repr == EnumRepr::String && rust_variant != *variant_name {
+                writeln!(w, "#[serde(rename = \"{variant_name}\")]").unwrap();
+            }
+            writeln!(w, "{rust_variant} = {},", integer_value_str(value)).unwrap()
+        }
+    });
+
+    generate_enum_conversions(w, &config.naming, &rust_name, base_type_str, variants);
+    if config.enum_helpers {
+        generate_enum_map(w, &config.naming, &rust_name, variants);
+    }
+}
+
+/// The Rust visibility of an enum or struct, keeping internal types within the crate
+pub(crate) fn visibility_str(visibility: ast::Visibility) -> &'static str {
+    match visibility {
+        ast::Visibility::Public => "pub",
+        ast::Visibility::Internal => "pub(crate)",
+    }
+}
+
+/// The trait for an interface, with a method reading each of its fields
+pub(crate) fn generate_trait(
+    w: &mut CodeWriter,
+    naming: &Naming,
+    ident: &str,
+    visibility: ast::Visibility,
+    fields: &[(String, ast::FieldType)],
+) {
+    let vis = visibility_str(visibility);
+
+    w.block(
+        &format!("{vis} trait {} {{", naming.type_name(ident)),
+        "}",
+        |w| {
+            for (field_name, field_type) in fields {
+                let (return_type, _) = accessor(field_type, naming, "", false);
+                writeln!(
+                    w,
+                    "fn {}(&self) -> {return_type};",
+                    naming.field_name(field_name)
+                )
+                .unwrap();
+            }
+        },
+    );
+}
+
+/// The impls of the traits for the interfaces a struct implements
+pub(crate) fn generate_trait_impls(
+    w: &mut CodeWriter,
+    naming: &Naming,
+    schema: &ast::Schema,
+    ident: &str,
+    implements: &[String],
+) {
+    let rust_name = naming.type_name(ident);
+    let boxed = boxed_fields(schema);
+
+    for interface in implements {
+        let Some(ast::Declaration::Interface { fields, .. }) = schema.declarations.iter().find(
+            |decl| matches!(decl, ast::Declaration::Interface { ident, .. } if ident == interface),
+        ) else {
+            continue;
+        };
+
+        w.blank();
+        w.block(
+            &format!("impl {} for {rust_name} {{", naming.type_name(interface)),
+            "}",
+            |w| {
+                for (field_name, field_type) in fields {
+                    let rust_field = naming.field_name(field_name);
+                    let (return_type, value) = accessor(
+                        field_type,
+                        naming,
+                        &format!("self.{rust_field}"),
+                        boxed.contains(&(ident, field_name.as_str())),
+                    );
+                    w.block(
+                        &format!("fn {rust_field}(&self) -> {return_type} {{"),
+                        "}",
+                        |w| writeln!(w, "{value}").unwrap(),
+                    );
+                }
+            },
+        );
+    }
+}
+
+/// The type a trait method returns for a field, and how to get it from the field: numbers and
+/// booleans by value, strings as `&str`, lists as slices and anything else by reference.  A
+/// boxed field is returned as a reference to the struct in its box.
+fn accessor(ft: &ast::FieldType, naming: &Naming, field: &str, boxed: bool) -> (String, String) {
+    let borrowed = match ft {
+        ast::FieldType::Builtin(
+            ast::BuiltinType::Integer(_) | ast::BuiltinType::Float(_) | ast::BuiltinType::Bool,
+            _,
+        ) => return (type_str(ft, naming), field.to_string()),
+        ast::FieldType::Builtin(ast::BuiltinType::String, _) => Some("str".to_string()),
+        ast::FieldType::Array(inner, None, _) => Some(format!("[{}]", type_str(inner, naming))),
+        _ => None,
+    };
+
+    match (is_nullable(ft), borrowed) {
+        (false, Some(borrowed)) => (format!("&{borrowed}"), format!("&{field}")),
+        (true, Some(borrowed)) => (
+            format!("Option<&{borrowed}>"),
+            format!("{field}.as_deref()"),
+        ),
+        (false, None) => (format!("&{}", type_str(ft, naming)), format!("&{field}")),
+        (true, None) => (
+            format!("Option<&{}>", type_str(&non_nullable(ft), naming)),
+            if boxed {
+                format!("{field}.as_deref()")
+            } else {
+                format!("{field}.as_ref()")
+            },
+        ),
+    }
+}
+
+/// A `map` method taking one argument per variant, for the `enum_helpers` option.  Adding a
+/// variant to the schema then breaks every call until it is handled.
+pub(crate) fn generate_enum_map(
+    w: &mut CodeWriter,
+    naming: &Naming,
+    rust_name: &str,
+    variants: &[(String, ast::IntegerValue)],
+) {
+    let params: Vec<String> = variants
+        .iter()
+        .map(|(variant_name, _)| format!("{}: T", naming.field_name(variant_name)))
+        .collect();
+
+    w.blank();
+    w.block(&format!("impl {rust_name} {{"), "}", |w| {
+        writeln!(w, "/// The argument for this variant").unwrap();
+        if variants.len() > 7 {
+            writeln!(w, "#[allow(clippy::too_many_arguments)]").unwrap();
+        }
+        w.block(
+            &format!("pub fn map<T>(&self, {}) -> T {{", params.join(", ")),
+            "}",
+            |w| {
+                w.block("match self {", "}", |w| {
+                    for (variant_name, _) in variants {
+                        writeln!(
+                            w,
+                            "Self::{} => {},",
+                            naming.variant_name(variant_name),
+                            naming.field_name(variant_name)
+                        )
+                        .unwrap();
+                    }
+                });
+            },
+        );
+    });
+}
+
+/// Conversions between an enum and its base type, which `enum_repr=int` also uses for serde
+pub(crate) fn generate_enum_conversions(
+    w: &mut CodeWriter,
+    naming: &Naming,
+    rust_name: &str,
+    base_type_str: &str,
+    variants: &[(String, ast::IntegerValue)],
+) {
+    w.blank();
+    w.block(
+        &format!("impl From<{rust_name}> for {base_type_str} {{"),
+        "}",
+        |w| {
+            w.block(
+                &format!("fn from(value: {rust_name}) -> Self {{"),
+                "}",
+                |w| {
+                    writeln!(w, "value as {base_type_str}").unwrap();
+                },
+            );
+        },
+    );
+
+    w.blank();
+    w.block(
+        &format!("impl TryFrom<{base_type_str}> for {rust_name} {{"),
+        "}",
+        |w| {
+            writeln!(w, "type Error = String;").unwrap();
+            w.blank();
+            w.block(
+                &format!("fn try_from(value: {base_type_str}) -> Result<Self, Self::Error> {{"),
+                "}",
+                |w| {
+                    w.block("match value {", "}", |w| {
+                        for (variant_name, value) in variants {
+                            writeln!(
+                                w,
+                                "{} => Ok(Self::{}),",
+                                integer_value_str(value),
+                                naming.variant_name(variant_name)
+                            )
+                            .unwrap();
+                        }
+                        writeln!(
+                            w,
+                            "_ => Err(format!(\"invalid {rust_name} value {{value}}\")),"
+                        )
+                        .unwrap();
+                    });
+                },
+            );
+        },
+    );
+}
+
+fn generate_struct(
+    w: &mut CodeWriter,
+    config: &Config,
+    derives: &[&str],
+    ident: &str,
+    visibility: ast::Visibility,
+    fields: &[(String, ast::FieldType)],
+    annotations: &ast::Annotations,
+) {
+    let rust_name = config.naming.type_name(ident);
+    let vis = visibility_str(visibility);
+    let masked: Vec<(String, bool)> = fields
+        .iter()
+        .map(|(field_name, _)| {
+            (
+                config.naming.field_name(field_name),
+                config.mask_sensitive && annotations.is_sensitive(field_name),
+            )
+        })
+        .collect();
+    let masked_debug = masked.iter().any(|(_, masked)| *masked);
+    let derives: Vec<&str> = derives
+        .iter()
+        .copied()
+        .filter(|derive| !(config.default_impl && *derive == "Default"))
+        .filter(|derive| !(masked_debug && *derive == "Debug"))
+        .collect();
+    let base: &[&str] = if masked_debug {
+        &["Clone", "PartialEq", "Serialize", "Deserialize"]
+    } else {
+        &["Debug", "Clone", "PartialEq", "Serialize", "Deserialize"]
+    };
+
+    write_derives(w, base, &derives);
+    if annotations.is_abi_stable() {
+        writeln!(w, "#[repr(C)]").unwrap();
+    }
+    for attribute in &config.struct_attributes {
+        writeln!(w, "#[{attribute}]").unwrap();
+    }
+    w.block(&format!("{vis} struct {rust_name} {{"), "}", |w| {
+        for (field_name, field_type) in fields {
+            let rust_field = config.naming.field_name(field_name);
+            let json_name = annotations.json_name(field_name);
+            if annotations.is_flattened(field_name) {
+                writeln!(w, "#[serde(flatten)]").unwrap();
+            } else if rust_field != json_name {
+                writeln!(w, "#[serde(rename = \"{json_name}\")]").unwrap();
+            }
+            writeln!(
+                w,
+                "pub {rust_field}: {},",
+                boxed_type_str(field_type, config.is_boxed(ident, field_name), |ft| {
+                    type_str(ft, &config.naming)
+                })
+            )
+            .unwrap();
+        }
+    });
+
+    if masked_debug {
+        write_masked_debug_impl(w, &rust_name, &masked);
+    }
+    if let Some(redaction) = &config.redaction {
+        redaction.write_impls(w, &config.naming, ident, fields, annotations);
+    }
+    if config.default_impl {
+        generate_default_impl(w, config, ident, fields);
+    }
+    if config.constructors {
+        generate_constructors(w, config, ident, fields);
+    }
+}
+
+fn generate_default_impl(
+    w: &mut CodeWriter,
+    config: &Config,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+) {
+    let rust_name = config.naming.type_name(ident);
+
+    w.blank();
+    w.block(&format!("impl Default for {rust_name} {{"), "}", |w| {
+        w.block("fn default() -> Self {", "}", |w| {
+            w.block("Self {", "}", |w| {
+                for (field_name, field_type) in fields {
+                    let rust_field = config.naming.field_name(field_name);
+                    writeln!(
+                        w,
+                        "{rust_field}: {},",
+                        boxed_value_str(
+                            &default_value_str(field_type, &config.naming),
+                            field_type,
+                            config.is_boxed(ident, field_name) && !is_nullable(field_type)
+                        )
+                    )
+                    .unwrap();
+                }
+            });
+        });
+    });
+}
+
+/// The value of a field in a default constructed struct.  Enums default to their `@default`
+/// variant, or their first.
+fn default_value_str(ft: &ast::FieldType, naming: &Naming) -> String {
+    if is_nullable(ft) {
+        return "None".to_string();
+    }
+
+    match ft {
+        ast::FieldType::Builtin(bt, _) => match bt {
+            ast::BuiltinType::Integer(_) => "0".to_string(),
+            ast::BuiltinType::Float(_) => "0.0".to_string(),
+            ast::BuiltinType::String => "String::new()".to_string(),
+            ast::BuiltinType::LocalizedString => "LocalizedString::default()".to_string(),
+            ast::BuiltinType::Bool => "false".to_string(),
+        },
+        ast::FieldType::UserDefined(name, _) => format!("{}::default()", naming.type_name(name)),
+        // `from_fn` works for arrays of any length, unlike `Default`
+        ast::FieldType::Array(inner, Some(_), _) => format!(
+            "std::array::from_fn(|_| {})",
+            default_value_str(inner, naming)
+        ),
+        ast::FieldType::Array(_, None, _) => "Vec::new()".to_string(),
+        ast::FieldType::Map(..) => "HashMap::new()".to_string(),
+    }
+}
+
+fn generate_constructors(
+    w: &mut CodeWriter,
+    config: &Config,
+    ident: &str,
+    fields: &[(String, ast::FieldType)],
+) {
+    let rust_name = config.naming.type_name(ident);
+    let required: Vec<String> = fields
+        .iter()
+        .filter(|(_, field_type)| !is_nullable(field_type))
+        .map(|(field_name, field_type)| {
+            format!(
+                "{}: {}",
+                config.naming.field_name(field_name),
+                type_str(field_type, &config.naming)
+            )
+        })
+        .collect();
+
+    w.blank();
+    w.block(&format!("impl {rust_name} {{"), "}", |w| {
+        writeln!(
+            w,
+            "/// Create a new `{rust_name}` from the required fields, leaving optional fields unset"
+        )
+        .unwrap();
+        w.block(
+            &format!("pub fn new({}) -> Self {{", required.join(", ")),
+            "}",
+            |w| {
+                w.block("Self {", "}", |w| {
+                    for (field_name, field_type) in fields {
+                        let rust_field = config.naming.field_name(field_name);
+                        if is_nullable(field_type) {
+                            writeln!(w, "{rust_field}: None,").unwrap();
+                        } else if config.is_boxed(ident, field_name) {
+                            writeln!(w, "{rust_field}: Box::new({rust_field}),").unwrap();
+                        } else {
+                            writeln!(w, "{rust_field},").unwrap();
+                        }
+                    }
+                });
+            },
+        );
+
+        for (field_name, field_type) in fields {
+            if !is_nullable(field_type) {
+                continue;
+            }
+
+            let rust_field = config.naming.field_name(field_name);
+            let value_type = type_str(&non_nullable(field_type), &config.naming);
+
+            w.blank();
+            writeln!(w, "/// Set `{rust_field}`").unwrap();
+            w.block(
+                &format!(
+                    "pub fn with_{rust_field}(mut self, {rust_field}: {value_type}) -> Self {{"
+                ),
+                "}",
+                |w| {
+                    if config.is_boxed(ident, field_name) {
+                        writeln!(w, "self.{rust_field} = Some(Box::new({rust_field}));").unwrap();
+                    } else {
+                        writeln!(w, "self.{rust_field} = Some({rust_field});").unwrap();
+                    }
+                    writeln!(w, "self").unwrap();
+                },
+            );
+        }
+    });
+}
+
+/// The Rust type used for a field type, with the default naming conventions
+pub fn field_type_str(ft: &ast::FieldType) -> String {
+    type_str(ft, &DEFAULT_NAMING)
+}
+
+pub(crate) fn type_str(ft: &ast::FieldType, naming: &Naming) -> String {
+    match ft {
+        ast::FieldType::Builtin(bt, nullable) => {
+            let base = builtin_type_str(bt);
+            if *nullable {
+                format!("Option<{base}>")
+            } else {
+                base
+            }
+        }
+        ast::FieldType::UserDefined(name, nullable) => {
+            let rust_name = naming.type_name(name);
+            if *nullable {
+                format!("Option<{rust_name}>")
+            } else {
+                rust_name
+            }
+        }
+        ast::FieldType::Array(inner, length, nullable) => {
+            let inner_str = type_str(inner, naming);
+            let base = match length {
+                Some(len) => format!("[{inner_str}; {len}]"),
+                None => format!("Vec<{inner_str}>"),
+            };
+            if *nullable {
+                format!("Option<{base}>")
+            } else {
+                base
+            }
+        }
+        ast::FieldType::Map(key_type, value_type, nullable) => {
+            let key_str = type_str(&map_key_field_type(key_type), naming);
+            let value_str = type_str(value_type, naming);
+            let base = format!("HashMap<{key_str}, {value_str}>");
+            if *nullable {
+                format!("Option<{base}>")
+            } else {
+                base
+            }
+        }
+    }
+}
+
+fn builtin_type_str(bt: &ast::BuiltinType) -> String {
+    match bt {
+        ast::BuiltinType::Integer(it) => integer_type_str(it).to_string(),
+        ast::BuiltinType::Float(ft) => match ft {
+            ast::FloatType::F32 => "f32".to_string(),
+            ast::FloatType::F64 => "f64".to_string(),
+        },
+        ast::BuiltinType::String => "String".to_string(),
+        ast::BuiltinType::LocalizedString => "LocalizedString".to_string(),
+        ast::BuiltinType::Bool => "bool".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> ast::Schema {
+        crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1, }
+                enum color: u8 { red = 1, }
+                struct point { x: i32, c: color, }
+                struct label { text: string, at: point, }
+                struct sample { value: f64, at: point?, }",
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn extra_derives_where_legal() {
+        let options = Options::parse(["derives=Eq, Hash,Copy"]).unwrap();
+        let output = generate_declarations(&schema(), &options).unwrap();
+
+        assert!(output.contains(
+            "#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default, Copy)]\n#[repr(u8)]\npub enum Color"
+        ));
+        assert!(output.contains(
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash, Copy)]\npub struct Point"
+        ));
+        assert!(output.contains(
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]\npub struct Label"
+        ));
+        assert!(output.contains(
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Copy)]\npub struct Sample"
+        ));
+    }
+
+    #[test]
+    fn container_attributes() {
+        let options = Options::parse([
+            "struct_attributes=serde(deny_unknown_fields); non_exhaustive",
+            "enum_attributes=non_exhaustive",
+        ])
+        .unwrap();
+        let output = generate_declarations(&schema(), &options).unwrap();
+
+        assert!(output.contains("#[repr(u8)]\n#[non_exhaustive]\npub enum Color"));
+        assert!(
+            output.contains("#[serde(deny_unknown_fields)]\n#[non_exhaustive]\npub struct Point")
+        );
+    }
+
+    #[test]
+    fn default_impl_and_constructors() {
+        let options =
+            Options::parse(["default_impl=true", "constructors=true", "derives=Default"]).unwrap();
+        let output = generate_declarations(&schema(), &options).unwrap();
+
+        assert!(output.contains(
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\npub struct Sample"
+        ));
+        assert!(output.contains("impl Default for Sample {"));
+        assert!(output.contains("value: 0.0,\n            at: None,"));
+        assert!(output.contains("text: String::new(),\n            at: Point::default(),"));
+        assert!(output.contains("pub fn new(value: f64) -> Self {"));
+        assert!(output.contains("pub fn with_at(mut self, at: Point) -> Self {"));
+    }
+
+    #[test]
+    fn sensitive_fields() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } struct user { id: u64, @sensitive token: string }")
+            .unwrap();
+        let options = Options::parse(["derives=Debug, Eq"]).unwrap();
+        let output = generate_declarations(&schema, &options).unwrap();
+
+        assert!(output.contains(
+            "#[derive(Clone, PartialEq, Serialize, Deserialize, Eq)]\npub struct User {"
+        ));
+        assert!(output.contains(".field(\"token\", &\"***\")"));
+    }
+
+    #[test]
+    fn redaction() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct user { @pii email: string, tags: [tag] } struct tag { @sensitive note: string? }",
+            )
+            .unwrap();
+        let options = Options::parse(["redaction=true"]).unwrap();
+        let output = generate_declarations(&schema, &options).unwrap();
+
+        assert!(output.contains("pub fn redacted(&self) -> Self {"));
+        assert!(output.contains("email: String::new(),"));
+        assert!(
+            output.contains("tags: self.tags.iter().map(|e0| e0.redacted()).collect::<Vec<_>>(),")
+        );
+        assert!(output.contains("note: None,"));
+        assert!(output.contains("impl std::fmt::Display for User {"));
+        assert!(output.contains("std::fmt::Debug::fmt(&self.redacted(), f)"));
+        assert!(
+            !generate_declarations(&schema, &Options::new())
+                .unwrap()
+                .contains("redacted")
+        );
+
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } enum e: u8 { a = 1 } struct s { @pii x: e }")
+            .unwrap();
+
+        assert!(matches!(
+            generate_declarations(&schema, &options),
+            Err(GenoError::InvalidAnnotation(path, _)) if path == "s.x"
+        ));
+    }
+
+    #[test]
+    fn enum_helpers() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } enum color: u8 { red = 1, dark_green = 2 }")
+            .unwrap();
+        let options = Options::parse(["enum_helpers=true"]).unwrap();
+        let output = generate_declarations(&schema, &options).unwrap();
+
+        assert!(output.contains("pub fn map<T>(&self, red: T, dark_green: T) -> T {"));
+        assert!(output.contains("Self::DarkGreen => dark_green,"));
+        assert!(
+            !generate_declarations(&schema, &Options::new())
+                .unwrap()
+                .contains("pub fn map<T>")
+        );
+    }
+
+    #[test]
+    fn abi_stable_structs() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } @abi(stable) struct vec2 { x: f32, y: f32 } struct s { a: i8 }")
+            .unwrap();
+        let output = generate_declarations(&schema, &Options::new()).unwrap();
+
+        assert!(output.contains("#[repr(C)]\npub struct Vec2 {"));
+        assert_eq!(output.matches("#[repr(C)]").count(), 1);
+    }
+
+    #[test]
+    fn enum_conversions() {
+        let output = generate_declarations(&schema(), &Options::new()).unwrap();
+
+        assert!(output.contains("impl From<Color> for u8 {"));
+        assert!(output.contains("1 => Ok(Self::Red),"));
+        assert!(output.contains("#[serde(rename = \"red\")]"));
+        assert!(!output.contains("try_from = "));
+
+        let options = Options::parse(["enum_repr=int"]).unwrap();
+        let output = generate_declarations(&schema(), &options).unwrap();
+
+        assert!(output.contains("#[serde(into = \"u8\", try_from = \"u8\")]"));
+        assert!(!output.contains("rename"));
+        assert!(matches!(
+            generate_declarations(&schema(), &Options::parse(["enum_repr=hex"]).unwrap()),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn json_names() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct base { n: i32 } struct user { @json(name = \"user-id\") id: u64, @json(name = \"name\") name: string, @flatten base: base }",
+            )
+            .unwrap();
+        let output = generate_declarations(&schema, &Options::new()).unwrap();
+
+        assert!(output.contains("#[serde(rename = \"user-id\")]\n    pub id: u64,"));
+        assert!(!output.contains("rename = \"name\""));
+        assert!(output.contains("#[serde(flatten)]\n    pub base: Base,"));
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } struct größe { straße: string, 名前: u8 }")
+            .unwrap();
+        let output = generate_declarations(&schema, &Options::new()).unwrap();
+
+        assert!(output.contains("pub struct Größe {"));
+        assert!(output.contains("pub straße: String,"));
+        assert!(output.contains("pub 名前: u8,"));
+    }
+
+    #[test]
+    fn default_variant() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum status: i8 { active = 1, @default unknown = -1 }",
+            )
+            .unwrap();
+        let output = generate_declarations(&schema, &Options::new()).unwrap();
+
+        assert!(
+            output.contains("#[default]\n    #[serde(rename = \"unknown\")]\n    Unknown = -1,")
+        );
+        assert!(!output.contains("#[default]\n    #[serde(rename = \"active\")]"));
+    }
+
+    #[test]
+    fn internal_types() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } internal enum kind: u8 { a = 1 } internal struct entry { k: kind } public struct log { n: u8 }",
+            )
+            .unwrap();
+        let output = generate_declarations(&schema, &Options::new()).unwrap();
+
+        assert!(output.contains("pub(crate) enum Kind {"));
+        assert!(output.contains("pub(crate) struct Entry {\n    pub k: Kind,"));
+        assert!(output.contains("\npub struct Log {"));
+    }
+
+    #[test]
+    fn interfaces() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } internal interface named { name: string, nick: string?, age: u8, tags: [string] }
+                struct user: named { id: u64, name: string, nick: string?, age: u8, tags: [string] }",
+            )
+            .unwrap();
+        let output = generate_declarations(&schema, &Options::new()).unwrap();
+
+        assert!(output.contains(
+            "pub(crate) trait Named {\n    fn name(&self) -> &str;\n    fn nick(&self) -> Option<&str>;\n    fn age(&self) -> u8;\n    fn tags(&self) -> &[String];\n}"
+        ));
+        assert!(output.contains(
+            "impl Named for User {\n    fn name(&self) -> &str {\n        &self.name\n    }"
+        ));
+        assert!(output.contains("self.nick.as_deref()"));
+        assert!(output.contains("fn age(&self) -> u8 {\n        self.age\n    }"));
+    }
+
+    #[test]
+    fn localized_strings() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct product { name: lstring, notes: [lstring]? }",
+            )
+            .unwrap();
+        let output =
+            generate_declarations(&schema, &Options::parse(["derives=Eq,Hash"]).unwrap()).unwrap();
+
+        assert_eq!(output.matches("pub struct LocalizedString(").count(), 1);
+        assert!(output.contains("pub name: LocalizedString,"));
+        assert!(output.contains("pub notes: Option<Vec<LocalizedString>>,"));
+        assert!(output.contains("#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]\npub struct Product"));
+        assert!(
+            !generate_declarations(&self::tests::schema(), &Options::new())
+                .unwrap()
+                .contains("LocalizedString")
+        );
+    }
+
+    #[test]
+    fn recursive_structs() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } interface tree { parent: node? }
+                struct leaf { value: i32, owner: node? }
+                struct node: tree { parent: node?, first: leaf?, children: [node] }",
+            )
+            .unwrap();
+        let options = Options::parse(["derives=Copy", "constructors=true"]).unwrap();
+        let output = generate_declarations(&schema, &options).unwrap();
+
+        assert!(output.contains("pub owner: Option<Box<Node>>,"));
+        assert!(output.contains("pub parent: Option<Box<Node>>,"));
+        assert!(output.contains("pub first: Option<Box<Leaf>>,"));
+        assert!(output.contains("pub children: Vec<Node>,"));
+        assert!(output.contains("self.parent = Some(Box::new(parent));"));
+        assert!(
+            output.contains("fn parent(&self) -> Option<&Node> {\n        self.parent.as_deref()")
+        );
+        assert!(!output.contains("Copy"));
+    }
+}
@@ -0,0 +1,655 @@
+//! Geno Rust/SQLx generator.  Writes the DDL for a table per struct in `schema.sql`, and in
+//! `sqlx.rs` an `sqlx::FromRow` impl and query constants for each of the `rust-serde` types, so
+//! rows read from the tables decode into the same types that go over the wire.
+//!
+//! Integers, floats, strings, bools and enums get a column of their own type.  SQL has no
+//! unsigned integers, so each integer gets the smallest signed column that holds it, and a `u64`
+//! above `i64::MAX` doesn't fit.  Enums are stored as their values.  `lstring`s, structs, lists
+//! and maps are stored as JSON in a single column.  The field named by the `primary_key` option,
+//! `id` by default, is the primary key of its table.
+//!
+//! With `diesel=true` a `diesel_schema.rs` also has a Diesel `table!` for each table with a
+//! primary key.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, rust_serde, util::*},
+};
+use std::fmt::Write as _;
+
+/// Generator for the `rust-sqlx` format
+pub struct RustSqlxGenerator;
+
+/// The rows decode into the `rust-serde` types, which can't have float map keys
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+/// The database the DDL and queries are written for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dialect {
+    Postgres,
+    Sqlite,
+    MySql,
+}
+
+impl Dialect {
+    fn from_options(options: &Options) -> Result<Self, GenoError> {
+        match options.get("dialect") {
+            None | Some("postgres") => Ok(Dialect::Postgres),
+            Some("sqlite") => Ok(Dialect::Sqlite),
+            Some("mysql") => Ok(Dialect::MySql),
+            Some(value) => Err(GenoError::InvalidOption(format!("dialect={value}"))),
+        }
+    }
+
+    /// The SQLx row type
+    fn row(self) -> &'static str {
+        match self {
+            Dialect::Postgres => "sqlx::postgres::PgRow",
+            Dialect::Sqlite => "sqlx::sqlite::SqliteRow",
+            Dialect::MySql => "sqlx::mysql::MySqlRow",
+        }
+    }
+
+    fn quote(self, ident: &str) -> String {
+        match self {
+            Dialect::MySql => format!("`{ident}`"),
+            Dialect::Postgres | Dialect::Sqlite => format!("\"{ident}\""),
+        }
+    }
+
+    /// The placeholder for the `n`th parameter of a query, from 1
+    fn placeholder(self, n: usize) -> String {
+        match self {
+            Dialect::Postgres => format!("${n}"),
+            Dialect::Sqlite | Dialect::MySql => "?".to_string(),
+        }
+    }
+
+    fn json_type(self) -> &'static str {
+        match self {
+            Dialect::Postgres => "JSONB",
+            Dialect::Sqlite => "TEXT",
+            Dialect::MySql => "JSON",
+        }
+    }
+}
+
+/// How a field is stored
+#[derive(Debug, Clone, PartialEq)]
+enum Column {
+    /// An integer column, read as `i16`, `i32` or `i64`, holding an integer or an enum value
+    Integer {
+        sql: &'static str,
+        rust: &'static str,
+        /// The Rust type of the field, or of the enum's base type, when it isn't `rust`
+        base: Option<&'static str>,
+        /// The enum stored in the column
+        enum_name: Option<String>,
+    },
+    /// A column read as the field's own Rust type
+    Plain {
+        sql: &'static str,
+        rust: &'static str,
+    },
+    /// A JSON column, read as `sqlx::types::Json` of the field's Rust type
+    Json { rust: String },
+}
+
+/// A table for a struct
+struct Table<'a> {
+    ident: &'a str,
+    name: String,
+    columns: Vec<(&'a str, Column, bool)>,
+    primary_key: Option<&'a str>,
+}
+
+impl Generator for RustSqlxGenerator {
+    fn name(&self) -> &str {
+        "rust-sqlx"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "dialect",
+            "primary_key",
+            "diesel",
+            "roots_only",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, Some("rust"))?;
+        let dialect = Dialect::from_options(options)?;
+        let roots = Roots::from_options(schema, options)?;
+        let primary_key = options.get("primary_key").unwrap_or("id");
+        let diesel = options.get_bool("diesel", false)?;
+        let naming = Naming::from_options(
+            options,
+            rust_serde::DEFAULT_NAMING,
+            &[
+                NamingConvention::Pascal,
+                NamingConvention::Camel,
+                NamingConvention::Snake,
+                NamingConvention::ScreamingSnake,
+                NamingConvention::Preserve,
+            ],
+        )?;
+        let tables: Vec<Table> = schema
+            .declarations
+            .iter()
+            .filter_map(|decl| match decl {
+                ast::Declaration::Struct { ident, fields, .. }
+                    if roots.contains(ident) && !fields.is_empty() =>
+                {
+                    Some(table(schema, ident, fields, primary_key, &naming))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut files = vec![
+            GeneratedFile::new("schema.sql", ddl(&tables, dialect)),
+            GeneratedFile::new("sqlx.rs", rows(&tables, dialect, &naming)),
+        ];
+
+        if diesel {
+            files.push(GeneratedFile::new(
+                "diesel_schema.rs",
+                diesel_tables(&tables, dialect),
+            ));
+        }
+
+        Ok(files)
+    }
+}
+
+fn table<'a>(
+    schema: &ast::Schema,
+    ident: &'a str,
+    fields: &'a [(String, ast::FieldType)],
+    primary_key: &str,
+    naming: &Naming,
+) -> Table<'a> {
+    let columns: Vec<(&str, Column, bool)> = fields
+        .iter()
+        .map(|(name, ft)| (name.as_str(), column(schema, ft, naming), is_nullable(ft)))
+        .collect();
+    let primary_key = columns
+        .iter()
+        .find(|(name, column, nullable)| {
+            *name == primary_key && !nullable && !matches!(column, Column::Json { .. })
+        })
+        .map(|(name, _, _)| *name);
+
+    Table {
+        ident,
+        name: to_snake_case(ident),
+        columns,
+        primary_key,
+    }
+}
+
+/// The smallest signed integer column that holds every value of an integer type
+fn integer_column(it: &ast::IntegerType) -> (&'static str, &'static str) {
+    match it {
+        ast::IntegerType::I8 | ast::IntegerType::U8 | ast::IntegerType::I16 => ("SMALLINT", "i16"),
+        ast::IntegerType::U16 | ast::IntegerType::I32 => ("INTEGER", "i32"),
+        ast::IntegerType::U32 | ast::IntegerType::I64 | ast::IntegerType::U64 => ("BIGINT", "i64"),
+    }
+}
+
+fn column(schema: &ast::Schema, ft: &ast::FieldType, naming: &Naming) -> Column {
+    let integer = |it: &ast::IntegerType, enum_name: Option<String>| {
+        let (sql, rust) = integer_column(it);
+        let base = integer_type_str(it);
+
+        Column::Integer {
+            sql,
+            rust,
+            base: (base != rust).then_some(base),
+            enum_name,
+        }
+    };
+
+    match ft {
+        ast::FieldType::Builtin(ast::BuiltinType::Integer(it), _) => integer(it, None),
+        ast::FieldType::Builtin(ast::BuiltinType::Float(ast::FloatType::F32), _) => Column::Plain {
+            sql: "REAL",
+            rust: "f32",
+        },
+        ast::FieldType::Builtin(ast::BuiltinType::Float(ast::FloatType::F64), _) => Column::Plain {
+            sql: "DOUBLE PRECISION",
+            rust: "f64",
+        },
+        ast::FieldType::Builtin(ast::BuiltinType::String, _) => Column::Plain {
+            sql: "TEXT",
+            rust: "String",
+        },
+        ast::FieldType::Builtin(ast::BuiltinType::Bool, _) => Column::Plain {
+            sql: "BOOLEAN",
+            rust: "bool",
+        },
+        ast::FieldType::UserDefined(name, _) => {
+            let base_type = schema.declarations.iter().find_map(|decl| match decl {
+                ast::Declaration::Enum {
+                    ident, base_type, ..
+                } if ident == name => Some(base_type),
+                _ => None,
+            });
+
+            match base_type {
+                Some(base_type) => integer(base_type, Some(naming.type_name(name))),
+                None => Column::Json {
+                    rust: rust_serde::type_str(&non_nullable(ft), naming),
+                },
+            }
+        }
+        _ => Column::Json {
+            rust: rust_serde::type_str(&non_nullable(ft), naming),
+        },
+    }
+}
+
+/// The SQL type of a column.  MySQL can't index a `TEXT` column, so a string primary key is a
+/// `VARCHAR` there.
+fn sql_type(column: &Column, dialect: Dialect, primary_key: bool) -> &'static str {
+    match column {
+        Column::Plain { sql: "TEXT", .. } if primary_key && dialect == Dialect::MySql => {
+            "VARCHAR(255)"
+        }
+        Column::Integer { sql, .. } | Column::Plain { sql, .. } => sql,
+        Column::Json { .. } => dialect.json_type(),
+    }
+}
+
+fn ddl(tables: &[Table], dialect: Dialect) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    w.line("-- Tables for the rust-sqlx types, generated by geno");
+    for table in tables {
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|(name, column, nullable)| {
+                let primary_key = table.primary_key == Some(*name);
+                let mut line = format!(
+                    "{} {}",
+                    dialect.quote(&to_snake_case(name)),
+                    sql_type(column, dialect, primary_key)
+                );
+
+                if !nullable {
+                    line.push_str(" NOT NULL");
+                }
+                if primary_key {
+                    line.push_str(" PRIMARY KEY");
+                }
+                line
+            })
+            .collect();
+
+        w.blank();
+        w.block(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (",
+                dialect.quote(&table.name)
+            ),
+            ");",
+            |w| {
+                w.line(columns.join(",\n"));
+            },
+        );
+    }
+
+    w.into_string()
+}
+
+/// The expression that reads a column from `row` as the field's Rust type
+fn read_column(name: &str, column: &Column, nullable: bool) -> String {
+    let column_name = to_snake_case(name);
+
+    match column {
+        Column::Integer {
+            rust,
+            base,
+            enum_name,
+            ..
+        } => {
+            // Converts `v` from the column's type to the field's
+            let convert = |v: &str| match (base, enum_name) {
+                (None, None) => None,
+                (_, None) => Some(format!("convert({v}, {column_name:?})")),
+                (None, Some(enum_name)) => {
+                    Some(format!("convert::<_, {enum_name}>({v}, {column_name:?})"))
+                }
+                (Some(base), Some(enum_name)) => Some(format!(
+                    "convert::<{base}, {enum_name}>(convert({v}, {column_name:?})?, {column_name:?})"
+                )),
+            };
+
+            if nullable {
+                let value = format!("row.try_get::<Option<{rust}>, _>({column_name:?})?");
+
+                match convert("v") {
+                    Some(convert) => format!("{value}.map(|v| {convert}).transpose()?"),
+                    None => value,
+                }
+            } else {
+                let value = format!("row.try_get::<{rust}, _>({column_name:?})?");
+
+                match convert(&value) {
+                    Some(convert) => format!("{convert}?"),
+                    None => value,
+                }
+            }
+        }
+        Column::Plain { rust, .. } if nullable => {
+            format!("row.try_get::<Option<{rust}>, _>({column_name:?})?")
+        }
+        Column::Plain { rust, .. } => format!("row.try_get::<{rust}, _>({column_name:?})?"),
+        Column::Json { rust } if nullable => format!(
+            "row.try_get::<Option<sqlx::types::Json<{rust}>>, _>({column_name:?})?.map(|json| json.0)"
+        ),
+        Column::Json { rust } => {
+            format!("row.try_get::<sqlx::types::Json<{rust}>, _>({column_name:?})?.0")
+        }
+    }
+}
+
+fn rows(tables: &[Table], dialect: Dialect, naming: &Naming) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    w.line(
+        "// Rows of the tables in schema.sql as the rust-serde types, generated by geno.  Include",
+    );
+    w.line("// this file in the module that holds them.  It needs sqlx with its `json` feature.");
+    w.line("use sqlx::Row as _;");
+
+    if tables.iter().any(|table| {
+        table.columns.iter().any(|(_, column, _)| {
+            matches!(
+                column,
+                Column::Integer { base: Some(_), .. }
+                    | Column::Integer {
+                        enum_name: Some(_),
+                        ..
+                    }
+            )
+        })
+    }) {
+        w.blank();
+        w.line("/// Convert a column value to the type of its field, failing if it's out of range");
+        w.line("fn convert<T, U>(value: T, column: &str) -> Result<U, sqlx::Error>");
+        w.line("where");
+        w.line("    U: TryFrom<T>,");
+        w.line("    U::Error: std::fmt::Display,");
+        w.block("{", "}", |w| {
+            w.block(
+                "U::try_from(value).map_err(|err| sqlx::Error::ColumnDecode {",
+                "})",
+                |w| {
+                    w.line("index: column.to_string(),");
+                    w.line("source: err.to_string().into(),");
+                },
+            );
+        });
+    }
+
+    for table in tables {
+        let rust_name = naming.type_name(table.ident);
+        let table_name = dialect.quote(&table.name);
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|(name, _, _)| dialect.quote(&to_snake_case(name)))
+            .collect();
+        let placeholders: Vec<String> = (1..=columns.len())
+            .map(|n| dialect.placeholder(n))
+            .collect();
+        let mut queries = vec![
+            (
+                "SELECT",
+                "Select every row",
+                format!("SELECT {} FROM {table_name}", columns.join(", ")),
+            ),
+            (
+                "INSERT",
+                "Insert a row, binding every column in order",
+                format!(
+                    "INSERT INTO {table_name} ({}) VALUES ({})",
+                    columns.join(", "),
+                    placeholders.join(", ")
+                ),
+            ),
+        ];
+
+        if let Some(primary_key) = table.primary_key {
+            let key = dialect.quote(&to_snake_case(primary_key));
+            let assignments: Vec<String> = columns
+                .iter()
+                .zip(&placeholders)
+                .map(|(column, placeholder)| format!("{column} = {placeholder}"))
+                .collect();
+
+            queries.extend([
+                (
+                    "SELECT_BY_KEY",
+                    "Select the row with a primary key",
+                    format!(
+                        "SELECT {} FROM {table_name} WHERE {key} = {}",
+                        columns.join(", "),
+                        dialect.placeholder(1)
+                    ),
+                ),
+                (
+                    "UPDATE_BY_KEY",
+                    "Update the row with a primary key, binding every column in order and then the key",
+                    format!(
+                        "UPDATE {table_name} SET {} WHERE {key} = {}",
+                        assignments.join(", "),
+                        dialect.placeholder(columns.len() + 1)
+                    ),
+                ),
+                (
+                    "DELETE_BY_KEY",
+                    "Delete the row with a primary key",
+                    format!(
+                        "DELETE FROM {table_name} WHERE {key} = {}",
+                        dialect.placeholder(1)
+                    ),
+                ),
+            ]);
+        }
+
+        w.blank();
+        w.block(&format!("impl {rust_name} {{"), "}", |w| {
+            w.line(format!("/// The `{}` table", table.name));
+            w.line(format!("pub const TABLE: &str = {:?};", table.name));
+            w.line("/// The columns of the table, in order");
+            let names: Vec<String> = table
+                .columns
+                .iter()
+                .map(|(name, _, _)| format!("{:?}", to_snake_case(name)))
+                .collect();
+            w.line(format!(
+                "pub const COLUMNS: &[&str] = &[{}];",
+                names.join(", ")
+            ));
+            for (name, doc, sql) in &queries {
+                w.line(format!("/// {doc}"));
+                w.line(format!("pub const {name}: &str = {sql:?};"));
+            }
+        });
+
+        w.blank();
+        w.block(
+            &format!(
+                "impl<'r> sqlx::FromRow<'r, {}> for {rust_name} {{",
+                dialect.row()
+            ),
+            "}",
+            |w| {
+                w.block(
+                    &format!(
+                        "fn from_row(row: &'r {}) -> Result<Self, sqlx::Error> {{",
+                        dialect.row()
+                    ),
+                    "}",
+                    |w| {
+                        w.block("Ok(Self {", "})", |w| {
+                            for (name, column, nullable) in &table.columns {
+                                writeln!(
+                                    w,
+                                    "{}: {},",
+                                    naming.field_name(name),
+                                    read_column(name, column, *nullable)
+                                )
+                                .unwrap();
+                            }
+                        });
+                    },
+                );
+            },
+        );
+    }
+
+    w.into_string()
+}
+
+/// Diesel `table!` declarations for the tables with a primary key
+fn diesel_tables(tables: &[Table], dialect: Dialect) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    w.line("// Diesel tables for schema.sql, generated by geno");
+    for table in tables {
+        let Some(primary_key) = table.primary_key else {
+            continue;
+        };
+
+        w.blank();
+        w.block("diesel::table! {", "}", |w| {
+            w.block(
+                &format!("{} ({}) {{", table.name, to_snake_case(primary_key)),
+                "}",
+                |w| {
+                    for (name, column, nullable) in &table.columns {
+                        let sql_type = match column {
+                            Column::Integer { rust: "i16", .. } => "SmallInt",
+                            Column::Integer { rust: "i32", .. } => "Integer",
+                            Column::Integer { .. } => "BigInt",
+                            Column::Plain { rust: "f32", .. } => "Float",
+                            Column::Plain { rust: "f64", .. } => "Double",
+                            Column::Plain { rust: "bool", .. } => "Bool",
+                            Column::Plain { .. } => "Text",
+                            Column::Json { .. } => match dialect {
+                                Dialect::Postgres => "Jsonb",
+                                Dialect::Sqlite => "Text",
+                                Dialect::MySql => "Json",
+                            },
+                        };
+
+                        if *nullable {
+                            w.line(format!("{} -> Nullable<{sql_type}>,", to_snake_case(name)));
+                        } else {
+                            w.line(format!("{} -> {sql_type},", to_snake_case(name)));
+                        }
+                    }
+                },
+            );
+        });
+    }
+
+    w.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(input: &str, options: &[&str]) -> Vec<String> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+
+        RustSqlxGenerator
+            .generate(&schema, &Options::parse(options).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|file| String::from_utf8(file.contents).unwrap())
+            .collect()
+    }
+
+    const SCHEMA: &str = "meta { format = 1 }
+enum role: u8 { admin = 1, member = 2 }
+struct user { id: u64, name: string, age: u8?, role: role, score: f64, tags: [string], address: address? }
+struct address { city: string }";
+
+    #[test]
+    fn postgres() {
+        let files = generate(SCHEMA, &["diesel=true"]);
+        let (ddl, rows, diesel) = (&files[0], &files[1], &files[2]);
+
+        assert!(ddl.contains(
+            "CREATE TABLE IF NOT EXISTS \"user\" (\n    \"id\" BIGINT NOT NULL PRIMARY KEY,\n    \"name\" TEXT NOT NULL,\n    \"age\" SMALLINT,\n    \"role\" SMALLINT NOT NULL,"
+        ));
+        assert!(ddl.contains("\"tags\" JSONB NOT NULL,\n    \"address\" JSONB\n);"));
+        assert!(
+            ddl.contains(
+                "CREATE TABLE IF NOT EXISTS \"address\" (\n    \"city\" TEXT NOT NULL\n);"
+            )
+        );
+        assert!(rows.contains("impl<'r> sqlx::FromRow<'r, sqlx::postgres::PgRow> for User {"));
+        assert!(rows.contains("id: convert(row.try_get::<i64, _>(\"id\")?, \"id\")?,"));
+        assert!(rows.contains("name: row.try_get::<String, _>(\"name\")?,"));
+        assert!(rows.contains(
+            "age: row.try_get::<Option<i16>, _>(\"age\")?.map(|v| convert(v, \"age\")).transpose()?,"
+        ));
+        assert!(rows.contains(
+            "role: convert::<u8, Role>(convert(row.try_get::<i16, _>(\"role\")?, \"role\")?, \"role\")?,"
+        ));
+        assert!(
+            rows.contains("tags: row.try_get::<sqlx::types::Json<Vec<String>>, _>(\"tags\")?.0,")
+        );
+        assert!(rows.contains(
+            "pub const SELECT_BY_KEY: &str = \"SELECT \\\"id\\\", \\\"name\\\", \\\"age\\\", \\\"role\\\", \\\"score\\\", \\\"tags\\\", \\\"address\\\" FROM \\\"user\\\" WHERE \\\"id\\\" = $1\";"
+        ));
+        assert!(rows.contains("WHERE \\\"id\\\" = $8\";"));
+        assert!(!rows.contains("impl<'r> sqlx::FromRow<'r, sqlx::postgres::PgRow> for Role"));
+        assert!(diesel.contains("    user (id) {\n        id -> BigInt,"));
+        assert!(diesel.contains("age -> Nullable<SmallInt>,"));
+        assert!(diesel.contains("tags -> Jsonb,"));
+        assert!(!diesel.contains("address (")); // No primary key
+    }
+
+    #[test]
+    fn mysql() {
+        let files = generate(
+            "meta { format = 1 } struct tag { name: string, count: i64 }",
+            &["dialect=mysql", "primary_key=name"],
+        );
+
+        assert_eq!(files.len(), 2);
+        assert!(files[0].contains("`name` VARCHAR(255) NOT NULL PRIMARY KEY,"));
+        assert!(files[1].contains("impl<'r> sqlx::FromRow<'r, sqlx::mysql::MySqlRow> for Tag {"));
+        assert!(files[1].contains("count: row.try_get::<i64, _>(\"count\")?,"));
+        assert!(files[1].contains("WHERE `name` = ?\";"));
+        assert!(!files[1].contains("fn convert"));
+    }
+}
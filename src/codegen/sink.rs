@@ -0,0 +1,322 @@
+//! Sinks that write generated files out as they are generated, rather than collecting them in
+//! memory first.  Files are written to a [Staging] directory, and only moved into place by
+//! [Staging::commit] once every generator has succeeded, so a failure never leaves truncated or
+//! half updated output behind.
+use crate::{GenoError, codegen::CodeSink};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// Generated files waiting to be moved into place.  They are held in a temporary directory next
+/// to the output, so that moving them is a rename, and are removed if they are never committed.
+pub struct Staging {
+    root: PathBuf,
+    dir: Option<TempDir>,
+    // Each file's target path and its path in the staging directory
+    files: Vec<(PathBuf, PathBuf)>,
+    count: usize,
+}
+
+impl Staging {
+    /// Create a staging area for output written under `root`, which must be on the same file
+    /// system as the output.  The staging directory is only created once a file is written.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+
+        Self {
+            root: if root.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                root
+            },
+            dir: None,
+            files: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// Move every staged file into place, returning their paths.  If any of them can't be moved,
+    /// the files already moved are put back the way they were.
+    pub fn commit(mut self) -> Result<Vec<PathBuf>, GenoError> {
+        let mut moved = Vec::new();
+
+        for (target, staged) in &self.files {
+            let backup = staged.with_extension("old");
+            let bytes = fs::metadata(staged).map_or(0, |metadata| metadata.len());
+
+            match replace(target, staged, &backup) {
+                Ok(replaced) => {
+                    tracing::info!(path = %target.display(), bytes, "wrote");
+                    moved.push((target, replaced.then_some(backup)));
+                }
+                Err(err) => {
+                    tracing::debug!(path = %target.display(), %err, "rolling back");
+                    for (target, backup) in moved.into_iter().rev() {
+                        let _ = match backup {
+                            Some(backup) => fs::rename(backup, target),
+                            None => fs::remove_file(target),
+                        };
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+
+        Ok(self.files.drain(..).map(|(target, _)| target).collect())
+    }
+
+    /// Write a file's contents to a new file in the staging directory, returning its path
+    fn stage(
+        &mut self,
+        contents: &mut dyn FnMut(&mut dyn Write) -> Result<(), GenoError>,
+    ) -> Result<PathBuf, GenoError> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => {
+                fs::create_dir_all(&self.root)?;
+                self.dir.insert(
+                    tempfile::Builder::new()
+                        .prefix(".geno-")
+                        .tempdir_in(&self.root)?,
+                )
+            }
+        };
+        let path = dir.path().join(format!("{}.tmp", self.count));
+        // Staged files are created like any other, so they end up with the usual permissions
+        let mut out = BufWriter::new(File::create(&path)?);
+
+        self.count += 1;
+        contents(&mut out)?;
+        out.flush()?;
+
+        Ok(path)
+    }
+}
+
+/// Move the staged file to `target`, first moving any file already there to `backup`.  Returns
+/// whether there was a file to back up.
+fn replace(target: &Path, staged: &Path, backup: &Path) -> io::Result<bool> {
+    if let Some(parent) = target.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+
+    let replaced = match fs::rename(target, backup) {
+        Ok(()) => true,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err),
+    };
+
+    if let Err(err) = fs::rename(staged, target) {
+        if replaced {
+            let _ = fs::rename(backup, target);
+        }
+        return Err(err);
+    }
+
+    Ok(replaced)
+}
+
+/// Writes files into a directory, creating the directories they need
+pub struct DirSink<'a> {
+    dir: PathBuf,
+    staging: &'a mut Staging,
+    written: Vec<PathBuf>,
+}
+
+impl<'a> DirSink<'a> {
+    /// Create a sink for the directory `dir`, staging the files in `staging`
+    pub fn new(dir: impl Into<PathBuf>, staging: &'a mut Staging) -> Self {
+        Self {
+            dir: dir.into(),
+            staging,
+            written: Vec::new(),
+        }
+    }
+
+    /// Paths the files written so far will be moved to
+    pub fn written(&self) -> &[PathBuf] {
+        &self.written
+    }
+}
+
+impl CodeSink for DirSink<'_> {
+    fn write_file(
+        &mut self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> Result<(), GenoError>,
+    ) -> Result<(), GenoError> {
+        let target = self.dir.join(path);
+        let staged = self.staging.stage(contents)?;
+
+        self.staging.files.push((target.clone(), staged));
+        self.written.push(target);
+
+        Ok(())
+    }
+}
+
+/// Writes the output of the `geno` command line.  A single file goes to the output path, or to
+/// stdout if there isn't one.  Several files go into the output path as a directory.  As the
+/// number of files isn't known until the generator is done, where they go is only decided by
+/// [OutputSink::finish].
+pub struct OutputSink<'a> {
+    path: Option<PathBuf>,
+    staging: &'a mut Staging,
+    // Each file's path from the generator and its path in the staging directory
+    files: Vec<(PathBuf, PathBuf)>,
+    written_stdout: bool,
+}
+
+impl<'a> OutputSink<'a> {
+    /// Create a sink for the output path, or stdout, staging the files in `staging`
+    pub fn new(path: Option<PathBuf>, staging: &'a mut Staging) -> Self {
+        Self {
+            path,
+            staging,
+            files: Vec::new(),
+            written_stdout: false,
+        }
+    }
+
+    /// Decide where the files go, returning the paths they will be moved to when the staging
+    /// area is committed.  Nothing is returned for stdout.
+    pub fn finish(self) -> Vec<PathBuf> {
+        let Some(output_path) = self.path else {
+            return Vec::new();
+        };
+        let single = self.files.len() == 1;
+        let mut written = Vec::new();
+
+        for (path, staged) in self.files {
+            let target = if single {
+                output_path.clone()
+            } else {
+                output_path.join(path)
+            };
+
+            self.staging.files.push((target.clone(), staged));
+            written.push(target);
+        }
+
+        written
+    }
+}
+
+impl CodeSink for OutputSink<'_> {
+    fn write_file(
+        &mut self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> Result<(), GenoError>,
+    ) -> Result<(), GenoError> {
+        if self.path.is_none() {
+            if self.written_stdout {
+                return Err(GenoError::MultipleFiles());
+            }
+
+            self.written_stdout = true;
+            let mut out = BufWriter::new(io::stdout().lock());
+
+            contents(&mut out)?;
+            return Ok(out.flush()?);
+        }
+
+        let staged = self.staging.stage(contents)?;
+
+        self.files.push((path.to_path_buf(), staged));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(sink: &mut dyn CodeSink, path: &str, text: &'static str) -> Result<(), GenoError> {
+        sink.write_file(Path::new(path), &mut |out| {
+            Ok(out.write_all(text.as_bytes())?)
+        })
+    }
+
+    #[test]
+    fn output_sink() {
+        let dir = TempDir::new().unwrap();
+        let single = dir.path().join("geno.rs");
+        let mut staging = Staging::new(dir.path());
+        let mut sink = OutputSink::new(Some(single.clone()), &mut staging);
+
+        write(&mut sink, "ignored.rs", "a").unwrap();
+        assert_eq!(sink.finish(), std::slice::from_ref(&single));
+        assert!(!single.exists());
+        assert_eq!(staging.commit().unwrap(), std::slice::from_ref(&single));
+        assert_eq!(fs::read_to_string(&single).unwrap(), "a");
+
+        let out = dir.path().join("out");
+        let mut staging = Staging::new(dir.path());
+        let mut sink = OutputSink::new(Some(out.clone()), &mut staging);
+
+        write(&mut sink, "lib/a.dart", "a").unwrap();
+        write(&mut sink, "b.dart", "b").unwrap();
+        sink.finish();
+        assert_eq!(
+            staging.commit().unwrap(),
+            [out.join("lib/a.dart"), out.join("b.dart")]
+        );
+        assert_eq!(fs::read_to_string(out.join("lib/a.dart")).unwrap(), "a");
+        // Only the output is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn failed_files_are_not_written() {
+        let dir = TempDir::new().unwrap();
+        let mut staging = Staging::new(dir.path());
+
+        fs::write(dir.path().join("geno.rs"), "old").unwrap();
+        write(
+            &mut DirSink::new(dir.path(), &mut staging),
+            "geno.rs",
+            "new",
+        )
+        .unwrap();
+
+        // A second target failing part way means nothing is committed
+        let mut second = DirSink::new(dir.path().join("dart"), &mut staging);
+        let result = second.write_file(Path::new("geno.dart"), &mut |out| {
+            out.write_all(b"partial")?;
+            Err(GenoError::Template("failed".to_string()))
+        });
+
+        assert!(result.is_err());
+        drop(staging);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("geno.rs")).unwrap(),
+            "old"
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn commit_rolls_back() {
+        let dir = TempDir::new().unwrap();
+        let mut staging = Staging::new(dir.path());
+        let mut sink = DirSink::new(dir.path(), &mut staging);
+
+        fs::write(dir.path().join("a.rs"), "old").unwrap();
+        // A file where a directory should be stops the last file being moved into place
+        fs::write(dir.path().join("c"), "").unwrap();
+        write(&mut sink, "a.rs", "new").unwrap();
+        write(&mut sink, "b.rs", "new").unwrap();
+        write(&mut sink, "c/c.rs", "new").unwrap();
+
+        assert!(staging.commit().is_err());
+        assert_eq!(fs::read_to_string(dir.path().join("a.rs")).unwrap(), "old");
+        assert!(!dir.path().join("b.rs").exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,465 @@
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, dart_mp, rust_serde, util::*},
+    imports,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tera::{Context, Map, Tera, Value};
+
+/// Extension that marks a file in the template directory as a template
+const TEMPLATE_EXTENSION: &str = "tera";
+
+/// Generator that renders user supplied [Tera](https://keats.github.io/tera/) templates.
+///
+/// Every `*.tera` file in the template directory produces one output file with the same relative
+/// path, minus the `.tera` extension.  Templates whose file name starts with `_` are partials that
+/// can be included or extended by other templates, but produce no output of their own.
+pub struct TemplateGenerator {
+    dir: PathBuf,
+}
+
+impl TemplateGenerator {
+    /// Create a generator for the templates in `dir`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The templates in the directory, sorted by path
+    pub fn templates(&self) -> Result<Vec<PathBuf>, GenoError> {
+        let mut paths = Vec::new();
+
+        collect_templates(&self.dir, &mut paths)?;
+        paths.sort();
+
+        Ok(paths)
+    }
+}
+
+impl Generator for TemplateGenerator {
+    fn name(&self) -> &str {
+        "template"
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        let schema = &*select_schema(schema, options)?;
+        let mut tera = Tera::default();
+        let templates = self
+            .templates()?
+            .iter()
+            .map(|path| {
+                let name = template_name(&self.dir, path);
+                (path.clone(), Some(name))
+            })
+            .collect::<Vec<_>>();
+
+        tera.add_template_files(templates.clone())
+            .map_err(template_error)?;
+        register_helpers(&mut tera);
+
+        let context =
+            Context::from_value(schema_context(schema, options)).map_err(template_error)?;
+        let mut files = Vec::new();
+
+        for (_, name) in templates {
+            let name = name.unwrap();
+            let is_partial = Path::new(&name)
+                .file_name()
+                .is_some_and(|file_name| file_name.to_string_lossy().starts_with('_'));
+
+            if is_partial {
+                continue;
+            }
+
+            let contents = tera.render(&name, &context).map_err(template_error)?;
+            let output_path = name
+                .strip_suffix(&format!(".{TEMPLATE_EXTENSION}"))
+                .unwrap_or(&name)
+                .to_string();
+
+            files.push(GeneratedFile::new(output_path, contents));
+        }
+
+        Ok(files)
+    }
+}
+
+fn collect_templates(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), GenoError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_templates(&path, paths)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == TEMPLATE_EXTENSION)
+        {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Templates are named by their path relative to the template directory, always using `/`
+fn template_name(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn template_error(err: tera::Error) -> GenoError {
+    // Tera puts the useful detail in the error source chain
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(&err);
+
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    GenoError::Template(message)
+}
+
+/// Build the template context.  The AST is flattened into plain objects that are easy to walk
+/// from a template, keeping the raw field type around for the type mapping filters.
+fn schema_context(schema: &ast::Schema, options: &Options) -> Value {
+    let meta: Map<String, Value> = schema
+        .metadata
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                ast::MetadataValue::String(s) => Value::from(s.clone()),
+                ast::MetadataValue::Integer(n) => integer_value(n),
+            };
+            (key.clone(), value)
+        })
+        .collect();
+    let roots = schema.roots();
+    let declarations: Vec<Value> = schema
+        .declarations
+        .iter()
+        .map(|decl| match decl {
+            ast::Declaration::Enum {
+                ident,
+                base_type,
+                variants,
+                visibility,
+                annotations,
+            } => {
+                let (default_variant, _) = annotations.default_variant(variants);
+
+                object([
+                    ("kind", Value::from("enum")),
+                    ("ident", Value::from(ident.clone())),
+                    ("namespace", namespace(ident)),
+                    ("internal", Value::from(!visibility.is_public())),
+                    ("comments", comments(&annotations.comments.declaration)),
+                    ("owner", Value::from(annotations.owner())),
+                    ("base_type", Value::from(integer_type_str(base_type))),
+                    (
+                        "variants",
+                        Value::Array(
+                            variants
+                                .iter()
+                                .map(|(ident, value)| {
+                                    object([
+                                        ("ident", Value::from(ident.clone())),
+                                        ("value", integer_value(value)),
+                                        ("default", Value::from(ident == default_variant)),
+                                        ("comments", comments(annotations.comments.member(ident))),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                ])
+            }
+            ast::Declaration::Struct {
+                ident,
+                fields,
+                implements,
+                visibility,
+                annotations,
+            } => object([
+                ("kind", Value::from("struct")),
+                ("ident", Value::from(ident.clone())),
+                ("namespace", namespace(ident)),
+                ("internal", Value::from(!visibility.is_public())),
+                ("comments", comments(&annotations.comments.declaration)),
+                ("owner", Value::from(annotations.owner())),
+                ("root", Value::from(roots.contains(&ident.as_str()))),
+                ("implements", Value::from(implements.clone())),
+                ("fields", field_values(fields, annotations)),
+            ]),
+            ast::Declaration::Interface {
+                ident,
+                fields,
+                visibility,
+                annotations,
+            } => object([
+                ("kind", Value::from("interface")),
+                ("ident", Value::from(ident.clone())),
+                ("namespace", namespace(ident)),
+                ("internal", Value::from(!visibility.is_public())),
+                ("comments", comments(&annotations.comments.declaration)),
+                ("owner", Value::from(annotations.owner())),
+                ("fields", field_values(fields, annotations)),
+            ]),
+            ast::Declaration::Topic {
+                ident,
+                payload,
+                annotations,
+            } => object([
+                ("kind", Value::from("topic")),
+                ("ident", Value::from(ident.clone())),
+                ("namespace", namespace(ident)),
+                ("comments", comments(&annotations.comments.declaration)),
+                ("owner", Value::from(annotations.owner())),
+                ("payload", Value::from(payload.clone())),
+            ]),
+        })
+        .collect();
+    let of_kind = |kind: &str| -> Vec<Value> {
+        declarations
+            .iter()
+            .filter(|decl| decl["kind"] == kind)
+            .cloned()
+            .collect()
+    };
+    let options: Map<String, Value> = options
+        .iter()
+        .map(|(key, value)| (key.to_string(), Value::from(value)))
+        .collect();
+
+    object([
+        ("meta", Value::Object(meta)),
+        ("enums", Value::Array(of_kind("enum"))),
+        ("structs", Value::Array(of_kind("struct"))),
+        ("interfaces", Value::Array(of_kind("interface"))),
+        ("topics", Value::Array(of_kind("topic"))),
+        ("declarations", Value::Array(declarations)),
+        ("options", Value::Object(options)),
+    ])
+}
+
+/// The fields of a struct or interface
+fn field_values(fields: &[(String, ast::FieldType)], annotations: &ast::Annotations) -> Value {
+    fields
+        .iter()
+        .map(|(ident, field_type)| {
+            object([
+                ("ident", Value::from(ident.clone())),
+                ("type", tera::to_value(field_type).unwrap()),
+                ("type_name", Value::from(schema_type_str(field_type))),
+                ("nullable", Value::from(is_nullable(field_type))),
+                ("sensitive", Value::from(annotations.is_sensitive(ident))),
+                ("json_name", Value::from(annotations.json_name(ident))),
+                ("flatten", Value::from(annotations.is_flattened(ident))),
+                ("comments", comments(annotations.comments.member(ident))),
+            ])
+        })
+        .collect()
+}
+
+/// The namespace of the package a declaration was imported from, or null for the schema's own
+fn namespace(ident: &str) -> Value {
+    Value::from(imports::split_namespace(ident).0)
+}
+
+/// The text of each comment, for documentation
+fn comments(comments: &[String]) -> Value {
+    comments
+        .iter()
+        .map(|comment| Value::from(ast::Comments::text(comment)))
+        .collect()
+}
+
+fn object<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Object(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn integer_value(v: &ast::IntegerValue) -> Value {
+    match v {
+        ast::IntegerValue::I8(n) => Value::from(*n),
+        ast::IntegerValue::I16(n) => Value::from(*n),
+        ast::IntegerValue::I32(n) => Value::from(*n),
+        ast::IntegerValue::I64(n) => Value::from(*n),
+        ast::IntegerValue::U8(n) => Value::from(*n),
+        ast::IntegerValue::U16(n) => Value::from(*n),
+        ast::IntegerValue::U32(n) => Value::from(*n),
+        ast::IntegerValue::U64(n) => Value::from(*n),
+    }
+}
+
+/// Case conversion filters for strings and type mapping filters for a field's `type`
+fn register_helpers(tera: &mut Tera) {
+    fn string_filter(
+        convert: fn(&str) -> String,
+    ) -> impl Fn(&Value, &HashMap<String, Value>) -> tera::Result<Value> {
+        move |value, _| match value.as_str() {
+            Some(s) => Ok(Value::from(convert(s))),
+            None => Err(tera::Error::msg(
+                "identifier filters can only be applied to strings",
+            )),
+        }
+    }
+
+    fn type_filter(
+        convert: fn(&ast::FieldType) -> String,
+    ) -> impl Fn(&Value, &HashMap<String, Value>) -> tera::Result<Value> {
+        move |value, _| {
+            let field_type: ast::FieldType = tera::from_value(value.clone()).map_err(|_| {
+                tera::Error::msg("type filters can only be applied to a field's 'type'")
+            })?;
+            Ok(Value::from(convert(&field_type)))
+        }
+    }
+
+    tera.register_filter("pascal_case", string_filter(to_pascal_case));
+    tera.register_filter("camel_case", string_filter(to_lower_camel_case));
+    tera.register_filter("snake_case", string_filter(to_snake_case));
+    tera.register_filter(
+        "screaming_snake_case",
+        string_filter(to_screaming_snake_case),
+    );
+    tera.register_filter("kebab_case", string_filter(to_kebab_case));
+    tera.register_filter("ascii_ident", string_filter(to_ascii_identifier));
+    tera.register_filter("rust_type", type_filter(rust_serde::field_type_str));
+    tera.register_filter("dart_type", type_filter(dart_mp::field_type_str));
+    tera.register_filter("schema_type", type_filter(schema_type_str));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn schema() -> ast::Schema {
+        ast::Schema {
+            metadata: HashMap::from([(
+                "format".to_string(),
+                ast::MetadataValue::Integer(ast::IntegerValue::I64(1)),
+            )]),
+            declarations: vec![
+                ast::Declaration::Enum {
+                    ident: "color".to_string(),
+                    base_type: ast::IntegerType::U8,
+                    variants: vec![("darkRed".to_string(), ast::IntegerValue::U8(1))],
+                    visibility: Default::default(),
+                    annotations: Default::default(),
+                },
+                ast::Declaration::Struct {
+                    ident: "pixel".to_string(),
+                    fields: vec![(
+                        "pixel_color".to_string(),
+                        ast::FieldType::UserDefined("color".to_string(), true),
+                    )],
+                    implements: Vec::new(),
+                    visibility: Default::default(),
+                    annotations: Default::default(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let dir = TempDir::new().unwrap();
+
+        fs::write(
+            dir.path().join("_header.tera"),
+            "// format {{ meta.format }}\n",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(
+            dir.path().join("src/types.rs.tera"),
+            r#"{% include "_header.tera" %}{% for s in structs %}struct {{ s.ident | pascal_case }} { {% for f in s.fields %}{{ f.ident | camel_case }}: {{ f.type | rust_type }} ({{ f.type_name }}){% endfor %} }
+{% endfor %}{% for e in enums %}{{ e.variants.0.ident | snake_case }} = {{ e.variants.0.value }} {{ options.license }} {{ e.variants.0.ident | screaming_snake_case }} {{ e.variants.0.ident | kebab_case }}{% endfor %}"#,
+        )
+        .unwrap();
+
+        let mut options = Options::new();
+        options.set("license", "MIT");
+
+        let files = TemplateGenerator::new(dir.path())
+            .generate(&schema(), &options)
+            .unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, PathBuf::from("src/types.rs"));
+        assert_eq!(
+            files[0].as_str(),
+            Some(
+                "// format 1\nstruct Pixel { pixelColor: Option<Color> (color?) }\ndark_red = 1 MIT DARK_RED dark-red"
+            )
+        );
+    }
+
+    #[test]
+    fn comments_in_context() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 }
+/// A pixel
+struct pixel {
+    /* Which color,
+     * if any */
+    pixel_color: u8?,
+}",
+            )
+            .unwrap();
+        let context = schema_context(&schema, &Options::new());
+
+        assert_eq!(
+            context["structs"][0]["comments"],
+            tera::to_value(["A pixel"]).unwrap()
+        );
+        assert_eq!(
+            context["structs"][0]["fields"][0]["comments"],
+            tera::to_value(["Which color,\nif any"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn namespaces_in_context() {
+        let mut schema = schema();
+
+        if let ast::Declaration::Enum { ident, .. } = &mut schema.declarations[0] {
+            *ident = "common.color".to_string();
+        }
+
+        let context = schema_context(&schema, &Options::new());
+
+        assert_eq!(context["enums"][0]["namespace"], Value::from("common"));
+        assert_eq!(context["structs"][0]["namespace"], Value::Null);
+    }
+
+    #[test]
+    fn template_errors() {
+        let dir = TempDir::new().unwrap();
+
+        fs::write(dir.path().join("bad.tera"), "{{ structs | pascal_case }}").unwrap();
+
+        let result = TemplateGenerator::new(dir.path()).generate(&schema(), &Options::new());
+
+        assert!(matches!(result, Err(GenoError::Template(_))));
+    }
+}
@@ -0,0 +1,721 @@
+//! Geno conformance test vector generator.  Writes deterministic sample values of every type in
+//! the schema as MessagePack files, plus a `manifest.json` listing each vector with its value in
+//! the canonical JSON form used by the `json` option of the MessagePack generators, and the
+//! [WireProtocol] the vectors were packed by.
+//!
+//! Each struct gets a `min` vector, with minimum integers, empty strings, lists and maps and
+//! null nullable fields, and a `max` vector with maximum integers, long strings, non-empty
+//! collections and every nullable field present.  Each enum gets one vector per variant.
+//!
+//! The MessagePack encoding follows the `dart-mp` protocol, including the `wire` option, with
+//! integers in their smallest encoding and floats always as `float 64`.  `u64` values are
+//! limited to `i64::MAX` because Dart integers are signed.  64-bit integer fields follow the
+//! `int64` option and `@int64` annotations, so `bigint` and `string` fields are decimal strings.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, util::*},
+};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+/// Generator for the `testvectors` format
+pub struct TestVectorsGenerator;
+
+/// The vectors are read by the `dart-mp` and `rust-mp` code, neither of which has float map keys
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+impl Generator for TestVectorsGenerator {
+    fn name(&self) -> &str {
+        "testvectors"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&["version", "order", "wire", "int64", "roots_only"])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let schema = &*select_target(schema, options, None)?;
+        let roots = Roots::from_options(schema, options)?;
+        let int64 = Int64::from_options(options)?;
+
+        int64.check(schema)?;
+
+        let mut vectors = Vectors {
+            builder: Builder::new(schema, int64),
+            wire: WireFormat::from_options(schema, options)?,
+            files: Vec::new(),
+            manifest: Vec::new(),
+        };
+
+        for decl in &schema.declarations {
+            match decl {
+                ast::Declaration::Enum { ident, .. } | ast::Declaration::Struct { ident, .. }
+                    if !roots.contains(ident) => {}
+                ast::Declaration::Enum {
+                    ident, variants, ..
+                } => {
+                    for (variant_name, value) in variants {
+                        let value = Value::Enum(variant_name.clone(), integer_value(value));
+                        vectors.add(ident, variant_name, &value);
+                    }
+                }
+                ast::Declaration::Struct { ident, .. } => {
+                    let ft = ast::FieldType::UserDefined(ident.clone(), false);
+                    for sample in [Sample::Min, Sample::Max] {
+                        let value = vectors.builder.value(&ft, sample, &mut Vec::new())?;
+                        vectors.add(ident, sample.name(), &value);
+                    }
+                }
+                ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => {}
+            }
+        }
+
+        Ok(vectors.finish())
+    }
+}
+
+/// The vector files and manifest entries written so far
+struct Vectors<'a> {
+    builder: Builder<'a>,
+    wire: WireFormat,
+    files: GeneratedFiles,
+    manifest: Vec<serde_json::Value>,
+}
+
+impl Vectors<'_> {
+    fn add(&mut self, ident: &str, name: &str, value: &Value) {
+        let file = format!("{ident}.{name}.msgpack");
+        let ft = ast::FieldType::UserDefined(ident.to_string(), false);
+        let mut bytes = Vec::new();
+
+        self.builder
+            .pack(&mut bytes, self.wire.protocol(), &ft, value);
+        self.manifest.push(json!({
+            "type": ident,
+            "name": name,
+            "file": file,
+            "value": value.to_json(),
+        }));
+        self.files.push(GeneratedFile::new(file, bytes));
+    }
+
+    fn finish(mut self) -> GeneratedFiles {
+        let manifest = json!({
+            "wire": match self.wire {
+                WireFormat::Positional => "positional",
+                WireFormat::Bitmap => "bitmap",
+                WireFormat::Keyed => "keyed",
+            },
+            "protocol": self.wire.protocol(),
+            "vectors": self.manifest,
+        });
+        let mut contents = serde_json::to_string_pretty(&manifest).unwrap();
+
+        contents.push('\n');
+        self.files
+            .push(GeneratedFile::new("manifest.json", contents));
+        self.files
+    }
+}
+
+/// Which set of edge values to build
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Sample {
+    Min,
+    Max,
+}
+
+impl Sample {
+    fn name(&self) -> &'static str {
+        match self {
+            Sample::Min => "min",
+            Sample::Max => "max",
+        }
+    }
+}
+
+/// A sample value of some field type
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Value {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    /// Schema variant name and value
+    Enum(String, i128),
+    /// Field values in declaration order, by JSON name, or with no name if flattened
+    Struct(Vec<(Option<String>, Value)>),
+}
+
+impl Value {
+    /// The canonical JSON form, with map keys as strings and enums as variant names
+    pub(super) fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => json!(b),
+            Value::Int(n) => json!(*n as i64),
+            Value::Float(f) => json!(f),
+            Value::String(s) => json!(s),
+            Value::List(items) => items.iter().map(Value::to_json).collect(),
+            Value::Map(entries) => serde_json::Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.to_key_string(), value.to_json()))
+                    .collect(),
+            ),
+            Value::Enum(name, _) => json!(name),
+            Value::Struct(fields) => {
+                let mut object = serde_json::Map::new();
+
+                for (name, value) in fields {
+                    match (name, value.to_json()) {
+                        (Some(name), value) => {
+                            object.insert(name.clone(), value);
+                        }
+                        // Flattened structs are inlined
+                        (None, serde_json::Value::Object(inner)) => object.extend(inner),
+                        _ => unreachable!("only structs are flattened"),
+                    }
+                }
+
+                serde_json::Value::Object(object)
+            }
+        }
+    }
+
+    fn to_key_string(&self) -> String {
+        match self {
+            Value::Bool(b) => b.to_string(),
+            Value::Int(n) => n.to_string(),
+            Value::String(s) => s.clone(),
+            Value::Enum(name, _) => name.clone(),
+            _ => unreachable!("map keys are validated by the schema"),
+        }
+    }
+}
+
+/// Builds and packs sample values for the types of one schema
+pub(super) struct Builder<'a> {
+    pub(super) structs: HashMap<&'a str, &'a [(String, ast::FieldType)]>,
+    pub(super) enums: HashMap<&'a str, &'a [(String, ast::IntegerValue)]>,
+    /// Struct and field names of the 64-bit integers packed as strings
+    pub(super) int64_strings: HashSet<(&'a str, &'a str)>,
+    /// Struct annotations, for the JSON names of fields
+    pub(super) annotations: HashMap<&'a str, &'a ast::Annotations>,
+}
+
+impl<'a> Builder<'a> {
+    pub(super) fn new(schema: &'a ast::Schema, int64: Int64) -> Self {
+        let mut structs = HashMap::new();
+        let mut enums = HashMap::new();
+        let mut int64_strings = HashSet::new();
+        let mut struct_annotations = HashMap::new();
+
+        for decl in &schema.declarations {
+            match decl {
+                ast::Declaration::Enum {
+                    ident, variants, ..
+                } => {
+                    enums.insert(ident.as_str(), variants.as_slice());
+                }
+                ast::Declaration::Struct {
+                    ident,
+                    fields,
+                    annotations,
+                    ..
+                } => {
+                    structs.insert(ident.as_str(), fields.as_slice());
+                    struct_annotations.insert(ident.as_str(), annotations);
+                    for (field_name, field_type) in fields {
+                        if is_int64(field_type)
+                            && int64.for_field(annotations.member(field_name)).is_string()
+                        {
+                            int64_strings.insert((ident.as_str(), field_name.as_str()));
+                        }
+                    }
+                }
+                ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => {}
+            }
+        }
+
+        Self {
+            structs,
+            enums,
+            int64_strings,
+            annotations: struct_annotations,
+        }
+    }
+
+    /// Build a sample value.  `stack` holds the structs being built, so that recursive types
+    /// end with a null or an empty collection instead of recursing forever.
+    fn value(
+        &self,
+        ft: &ast::FieldType,
+        sample: Sample,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<Value, GenoError> {
+        if is_nullable(ft) {
+            if sample == Sample::Min || self.recurses(ft, stack) {
+                return Ok(Value::Null);
+            }
+            return self.value(&non_nullable(ft), sample, stack);
+        }
+
+        Ok(match ft {
+            ast::FieldType::Builtin(bt, _) => builtin_value(bt, sample),
+            ast::FieldType::UserDefined(name, _) => {
+                if let Some(variants) = self.enums.get(name.as_str()) {
+                    let (variant_name, value) = match sample {
+                        Sample::Min => variants.first(),
+                        Sample::Max => variants.last(),
+                    }
+                    .unwrap();
+                    Value::Enum(variant_name.clone(), integer_value(value))
+                } else {
+                    let (ident, fields) = self.structs.get_key_value(name.as_str()).unwrap();
+                    if stack.contains(ident) {
+                        return Err(GenoError::RecursiveType(name.clone()));
+                    }
+                    stack.push(ident);
+                    let values = fields
+                        .iter()
+                        .map(|(field_name, field_type)| {
+                            let value = match self.value(field_type, sample, stack)? {
+                                Value::Int(n)
+                                    if self.int64_strings.contains(&(*ident, field_name)) =>
+                                {
+                                    Value::String(n.to_string())
+                                }
+                                value => value,
+                            };
+                            let annotations = self.annotations[ident];
+                            let json_name = (!annotations.is_flattened(field_name))
+                                .then(|| annotations.json_name(field_name).to_string());
+                            Ok((json_name, value))
+                        })
+                        .collect::<Result<_, GenoError>>()?;
+                    stack.pop();
+                    Value::Struct(values)
+                }
+            }
+            ast::FieldType::Array(inner, length, _) => {
+                let len = match (length, sample) {
+                    (Some(len), _) => *len,
+                    (None, Sample::Max) if !self.recurses(inner, stack) => 2,
+                    (None, _) => 0,
+                };
+                Value::List(
+                    (0..len)
+                        .map(|_| self.value(inner, sample, stack))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            ast::FieldType::Map(key_type, value_type, _) => {
+                if sample == Sample::Min || self.recurses(value_type, stack) {
+                    Value::Map(Vec::new())
+                } else {
+                    let key = self.value(&map_key_field_type(key_type), sample, stack)?;
+                    Value::Map(vec![(key, self.value(value_type, sample, stack)?)])
+                }
+            }
+        })
+    }
+
+    /// Returns `true` if the type holds a struct that is already being built
+    pub(super) fn recurses(&self, ft: &ast::FieldType, stack: &[&str]) -> bool {
+        match ft {
+            ast::FieldType::Builtin(..) => false,
+            ast::FieldType::UserDefined(name, _) => stack.contains(&name.as_str()),
+            ast::FieldType::Array(inner, _, _) => self.recurses(inner, stack),
+            ast::FieldType::Map(_, value_type, _) => self.recurses(value_type, stack),
+        }
+    }
+
+    /// Pack a value the way the `dart-mp` generator does
+    pub(super) fn pack(
+        &self,
+        wr: &mut Vec<u8>,
+        protocol: WireProtocol,
+        ft: &ast::FieldType,
+        value: &Value,
+    ) {
+        if is_nullable(ft) {
+            let marker = protocol.has_presence_marker(ft, |name| self.enums.contains_key(name));
+
+            if *value == Value::Null {
+                wr.push(0xc0);
+            } else {
+                if marker {
+                    wr.push(0xc3);
+                }
+                self.pack(wr, protocol, &non_nullable(ft), value);
+            }
+            return;
+        }
+
+        match (ft, value) {
+            (ast::FieldType::Builtin(..), Value::Bool(b)) => wr.push(if *b { 0xc3 } else { 0xc2 }),
+            (ast::FieldType::Builtin(..), Value::Int(n)) => write_int(wr, *n),
+            (ast::FieldType::Builtin(..), Value::Float(f)) => {
+                wr.push(0xcb);
+                wr.extend_from_slice(&f.to_be_bytes());
+            }
+            (ast::FieldType::Builtin(..), Value::String(s)) => write_str(wr, s),
+            (ast::FieldType::Builtin(..), Value::Map(entries)) => {
+                write_len(wr, entries.len(), 0x80, 0xde);
+                for (locale, text) in entries {
+                    let (Value::String(locale), Value::String(text)) = (locale, text) else {
+                        unreachable!("localized strings are maps of strings");
+                    };
+                    write_str(wr, locale);
+                    write_str(wr, text);
+                }
+            }
+            (ast::FieldType::UserDefined(..), Value::Enum(_, n)) => write_int(wr, *n),
+            (ast::FieldType::UserDefined(name, _), Value::Struct(values)) => {
+                self.pack_struct(wr, protocol, self.structs[name.as_str()], values)
+            }
+            (ast::FieldType::Array(inner, _, _), Value::List(items)) => {
+                write_len(wr, items.len(), 0x90, 0xdc);
+                for item in items {
+                    self.pack(wr, protocol, inner, item);
+                }
+            }
+            (ast::FieldType::Map(key_type, value_type, _), Value::Map(entries)) => {
+                let key_type = map_key_field_type(key_type);
+                write_len(wr, entries.len(), 0x80, 0xde);
+                for (key, value) in entries {
+                    self.pack(wr, protocol, &key_type, key);
+                    self.pack(wr, protocol, value_type, value);
+                }
+            }
+            _ => unreachable!("sample values match their field types"),
+        }
+    }
+
+    fn pack_struct(
+        &self,
+        wr: &mut Vec<u8>,
+        protocol: WireProtocol,
+        fields: &[(String, ast::FieldType)],
+        values: &[(Option<String>, Value)],
+    ) {
+        if protocol.presence_bitmap {
+            let bits = presence_bits(fields);
+            let word_count = bits
+                .iter()
+                .flatten()
+                .map(|(word, _)| word + 1)
+                .max()
+                .unwrap_or(0);
+            let mut words = vec![0u32; word_count];
+
+            for (bit, (_, value)) in bits.iter().zip(values) {
+                if let Some((word, mask)) = bit
+                    && *value != Value::Null
+                {
+                    words[*word] |= mask;
+                }
+            }
+            for word in words {
+                write_int(wr, word.into());
+            }
+        }
+
+        // Fields that are left out when null are known to be present otherwise
+        let present: Vec<_> = fields
+            .iter()
+            .zip(values)
+            .filter(|(_, (_, value))| !(protocol.omit_null_fields && *value == Value::Null))
+            .map(|((field_name, ft), (_, value))| {
+                let ft = if protocol.omit_null_fields {
+                    non_nullable(ft)
+                } else {
+                    ft.clone()
+                };
+
+                (field_name, ft, value)
+            })
+            .collect();
+
+        if protocol.layout == StructLayout::Map {
+            write_len(wr, present.len(), 0x80, 0xde);
+        }
+        for (field_name, ft, value) in present {
+            if protocol.layout == StructLayout::Map {
+                write_str(wr, field_name);
+            }
+            self.pack(wr, protocol, &ft, value);
+        }
+    }
+}
+
+fn builtin_value(bt: &ast::BuiltinType, sample: Sample) -> Value {
+    match (bt, sample) {
+        (ast::BuiltinType::Integer(it), Sample::Min) => Value::Int(integer_min(it)),
+        (ast::BuiltinType::Integer(it), Sample::Max) => Value::Int(integer_max(it)),
+        (ast::BuiltinType::Float(ast::FloatType::F32), Sample::Min) => {
+            Value::Float(f32::MIN.into())
+        }
+        (ast::BuiltinType::Float(ast::FloatType::F32), Sample::Max) => {
+            Value::Float(f32::MAX.into())
+        }
+        (ast::BuiltinType::Float(ast::FloatType::F64), Sample::Min) => Value::Float(f64::MIN),
+        (ast::BuiltinType::Float(ast::FloatType::F64), Sample::Max) => Value::Float(f64::MAX),
+        (ast::BuiltinType::String, Sample::Min) => Value::String(String::new()),
+        // Long enough for a `str 16` header, with multi-byte characters
+        (ast::BuiltinType::String, Sample::Max) => Value::String("geno ✓ ".repeat(40)),
+        (ast::BuiltinType::LocalizedString, Sample::Min) => Value::Map(Vec::new()),
+        (ast::BuiltinType::LocalizedString, Sample::Max) => Value::Map(
+            [("en", "geno"), ("fr-CA", "géno ✓")]
+                .into_iter()
+                .map(|(locale, text)| (Value::String(locale.into()), Value::String(text.into())))
+                .collect(),
+        ),
+        (ast::BuiltinType::Bool, sample) => Value::Bool(sample == Sample::Max),
+    }
+}
+
+pub(super) fn integer_min(it: &ast::IntegerType) -> i128 {
+    match it {
+        ast::IntegerType::I8 => i8::MIN.into(),
+        ast::IntegerType::I16 => i16::MIN.into(),
+        ast::IntegerType::I32 => i32::MIN.into(),
+        ast::IntegerType::I64 => i64::MIN.into(),
+        _ => 0,
+    }
+}
+
+pub(super) fn integer_max(it: &ast::IntegerType) -> i128 {
+    match it {
+        ast::IntegerType::I8 => i8::MAX.into(),
+        ast::IntegerType::I16 => i16::MAX.into(),
+        ast::IntegerType::I32 => i32::MAX.into(),
+        ast::IntegerType::I64 | ast::IntegerType::U64 => i64::MAX.into(),
+        ast::IntegerType::U8 => u8::MAX.into(),
+        ast::IntegerType::U16 => u16::MAX.into(),
+        ast::IntegerType::U32 => u32::MAX.into(),
+    }
+}
+
+pub(super) fn integer_value(v: &ast::IntegerValue) -> i128 {
+    match v {
+        ast::IntegerValue::I8(n) => (*n).into(),
+        ast::IntegerValue::I16(n) => (*n).into(),
+        ast::IntegerValue::I32(n) => (*n).into(),
+        ast::IntegerValue::I64(n) => (*n).into(),
+        ast::IntegerValue::U8(n) => (*n).into(),
+        ast::IntegerValue::U16(n) => (*n).into(),
+        ast::IntegerValue::U32(n) => (*n).into(),
+        ast::IntegerValue::U64(n) => (*n).into(),
+    }
+}
+
+/// Write an integer in its smallest encoding
+fn write_int(wr: &mut Vec<u8>, n: i128) {
+    match n {
+        0..=0x7f => wr.push(n as u8),
+        -32..=-1 => wr.push(n as i8 as u8),
+        0x80..=0xff => wr.extend_from_slice(&[0xcc, n as u8]),
+        0x100..=0xffff => {
+            wr.push(0xcd);
+            wr.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            wr.push(0xce);
+            wr.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        0x1_0000_0000.. => {
+            wr.push(0xcf);
+            wr.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+        -0x80..=-33 => wr.extend_from_slice(&[0xd0, n as i8 as u8]),
+        -0x8000..=-0x81 => {
+            wr.push(0xd1);
+            wr.extend_from_slice(&(n as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            wr.push(0xd2);
+            wr.extend_from_slice(&(n as i32).to_be_bytes());
+        }
+        _ => {
+            wr.push(0xd3);
+            wr.extend_from_slice(&(n as i64).to_be_bytes());
+        }
+    }
+}
+
+fn write_str(wr: &mut Vec<u8>, s: &str) {
+    let len = s.len();
+
+    if len < 32 {
+        wr.push(0xa0 | len as u8);
+    } else if len <= 0xff {
+        wr.extend_from_slice(&[0xd9, len as u8]);
+    } else if len <= 0xffff {
+        wr.push(0xda);
+        wr.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        wr.push(0xdb);
+        wr.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    wr.extend_from_slice(s.as_bytes());
+}
+
+/// Write an array or map header, given the fix marker and the 16 bit marker
+fn write_len(wr: &mut Vec<u8>, len: usize, fix: u8, marker16: u8) {
+    if len < 16 {
+        wr.push(fix | len as u8);
+    } else if len <= 0xffff {
+        wr.push(marker16);
+        wr.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        wr.push(marker16 + 1);
+        wr.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_str(input: &str, options: &[&str]) -> Result<GeneratedFiles, GenoError> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+
+        TestVectorsGenerator.generate(&schema, &Options::parse(options).unwrap())
+    }
+
+    fn file<'a>(files: &'a GeneratedFiles, path: &str) -> &'a [u8] {
+        &files
+            .iter()
+            .find(|f| f.path.to_str() == Some(path))
+            .unwrap()
+            .contents
+    }
+
+    #[test]
+    fn vectors_and_manifest() {
+        let files = generate_str(
+            "meta { format = 1 } enum color: i8 { red = -1, green = 2 } struct point { x: u16, c: color?, @json(name = \"tag-list\") tags: [bool]? }",
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(file(&files, "color.red.msgpack"), &[0xff]);
+        assert_eq!(file(&files, "point.min.msgpack"), &[0x00, 0xc0, 0xc0]);
+        assert_eq!(
+            file(&files, "point.max.msgpack"),
+            &[0xcd, 0xff, 0xff, 0x02, 0xc3, 0x92, 0xc3, 0xc3]
+        );
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(file(&files, "manifest.json")).unwrap();
+        assert_eq!(manifest["wire"], "positional");
+        assert_eq!(manifest["protocol"]["layout"], "sequence");
+        assert_eq!(manifest["protocol"]["presence_markers"], true);
+        assert_eq!(
+            manifest["vectors"][3]["value"],
+            json!({ "x": 65535, "c": "green", "tag-list": [true, true] })
+        );
+    }
+
+    #[test]
+    fn wire_formats() {
+        let input = "meta { format = 1 } struct point { x: u8, y: string? }";
+
+        let files = generate_str(input, &["wire=bitmap"]).unwrap();
+        assert_eq!(file(&files, "point.min.msgpack"), &[0x00, 0x00]);
+
+        let files = generate_str(input, &["wire=keyed"]).unwrap();
+        assert_eq!(file(&files, "point.min.msgpack"), &[0x81, 0xa1, b'x', 0x00]);
+    }
+
+    #[test]
+    fn int64_strings() {
+        let files = generate_str(
+            "meta { format = 1 } struct big { @int64(string) id: u64, n: i64 }",
+            &[],
+        )
+        .unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_slice(file(&files, "manifest.json")).unwrap();
+
+        assert_eq!(
+            file(&files, "big.min.msgpack"),
+            &[0xa1, b'0', 0xd3, 0x80, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            manifest["vectors"][1]["value"],
+            json!({ "id": "9223372036854775807", "n": 9223372036854775807i64 })
+        );
+    }
+
+    #[test]
+    fn flattened_structs() {
+        let files = generate_str(
+            "meta { format = 1 } struct env { id: u8 } struct order { @flatten env: env, n: u8 }",
+            &[],
+        )
+        .unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_slice(file(&files, "manifest.json")).unwrap();
+
+        assert_eq!(file(&files, "order.max.msgpack"), &[0xcc, 0xff, 0xcc, 0xff]);
+        assert_eq!(
+            manifest["vectors"][3]["value"],
+            json!({ "id": 255, "n": 255 })
+        );
+    }
+
+    #[test]
+    fn recursive_types() {
+        let files = generate_str(
+            "meta { format = 1 } struct node { children: [node], next: node? }",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(file(&files, "node.max.msgpack"), &[0x90, 0xc0]);
+
+        assert!(matches!(
+            generate_str("meta { format = 1 } struct node { next: node }", &[]),
+            Err(GenoError::RecursiveType(_))
+        ));
+    }
+
+    #[test]
+    fn localized_strings() {
+        let files =
+            generate_str("meta { format = 1 } struct product { name: lstring? }", &[]).unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_slice(file(&files, "manifest.json")).unwrap();
+        let mut max = vec![0xc3, 0x82, 0xa2];
+
+        max.extend(b"en\xa4geno\xa5fr-CA");
+        max.push(0xa9);
+        max.extend("géno ✓".as_bytes());
+        assert_eq!(file(&files, "product.min.msgpack"), &[0xc0]);
+        assert_eq!(file(&files, "product.max.msgpack"), max);
+        assert_eq!(
+            manifest["vectors"][1]["value"]["name"],
+            json!({ "en": "geno", "fr-CA": "géno ✓" })
+        );
+    }
+}
@@ -0,0 +1,1807 @@
+use crate::{GenoError, ast, codegen::Options};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{self, Write as _},
+    str::FromStr,
+};
+
+/// Code is passed on to the sink of a [CodeWriter] in pieces of about this many bytes
+const FLUSH_SIZE: usize = 64 * 1024;
+
+/// Builds source code, indenting each line to the current level.  Implements
+/// [fmt::Write] so the `write!` and `writeln!` macros can be used with it.
+///
+/// The code is kept in memory, unless the writer was created with [CodeWriter::to_writer], in
+/// which case it is passed on as it is written.
+pub struct CodeWriter<'a> {
+    out: String,
+    sink: Option<&'a mut dyn io::Write>,
+    // The first error from the sink, returned by `finish`
+    error: Option<io::Error>,
+    // Whether the code already passed on to the sink ends by opening a block
+    flushed_open: bool,
+    indent_unit: String,
+    level: usize,
+    at_line_start: bool,
+}
+
+impl CodeWriter<'static> {
+    /// Create a writer that indents by `indent_unit` for each level
+    pub fn new(indent_unit: &str) -> Self {
+        Self {
+            out: String::new(),
+            sink: None,
+            error: None,
+            flushed_open: false,
+            indent_unit: indent_unit.to_string(),
+            level: 0,
+            at_line_start: true,
+        }
+    }
+}
+
+impl<'a> CodeWriter<'a> {
+    /// Create a writer that indents by `indent_unit` for each level and passes the code on to
+    /// `sink` as it goes, so that only a little of it is held in memory.  Call
+    /// [CodeWriter::finish] to write the rest.
+    pub fn to_writer(indent_unit: &str, sink: &'a mut dyn io::Write) -> Self {
+        Self {
+            sink: Some(sink),
+            ..CodeWriter::new(indent_unit)
+        }
+    }
+
+    /// Increase the indentation level for subsequent lines
+    pub fn indent(&mut self) {
+        self.level += 1;
+    }
+
+    /// Decrease the indentation level for subsequent lines
+    pub fn dedent(&mut self) {
+        self.level = self.level.saturating_sub(1);
+    }
+
+    /// Write `open`, the lines produced by `body` one level deeper, then `close`
+    pub fn block(&mut self, open: &str, close: &str, body: impl FnOnce(&mut Self)) {
+        self.line(open);
+        self.indent();
+        body(self);
+        self.dedent();
+        self.line(close);
+    }
+
+    /// Write a single line at the current indentation
+    pub fn line(&mut self, s: impl AsRef<str>) {
+        self.push(s.as_ref());
+        self.push("\n");
+    }
+
+    /// Write an empty line, unless it would be the first line of a block
+    pub fn blank(&mut self) {
+        let after_open = if self.out.is_empty() {
+            self.flushed_open
+        } else {
+            self.out.ends_with("{\n")
+        };
+
+        if !after_open {
+            self.push("\n");
+        }
+    }
+
+    /// The code written so far, less any already passed on to the sink
+    pub fn as_str(&self) -> &str {
+        &self.out
+    }
+
+    /// Consume the writer, returning the code
+    pub fn into_string(self) -> String {
+        self.out
+    }
+
+    /// Consume the writer, passing the rest of the code on to the sink
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush();
+
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) {
+        let Some(sink) = &mut self.sink else {
+            return;
+        };
+
+        if self.error.is_none()
+            && let Err(err) = sink.write_all(self.out.as_bytes())
+        {
+            self.error = Some(err);
+        }
+        if !self.out.is_empty() {
+            self.flushed_open = self.out.ends_with("{\n");
+            self.out.clear();
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        for line in s.split_inclusive('\n') {
+            // Blank lines never get trailing whitespace
+            if self.at_line_start && line != "\n" {
+                for _ in 0..self.level {
+                    self.out.push_str(&self.indent_unit);
+                }
+            }
+            self.out.push_str(line);
+            self.at_line_start = line.ends_with('\n');
+        }
+        // Only whole lines are flushed, so `blank` can still tell whether a block was just opened
+        if self.at_line_start && self.out.len() >= FLUSH_SIZE {
+            self.flush();
+        }
+    }
+}
+
+impl fmt::Write for CodeWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s);
+        Ok(())
+    }
+}
+
+/// Returns `true` if the field type is nullable at the top level
+pub fn is_nullable(ft: &ast::FieldType) -> bool {
+    match ft {
+        ast::FieldType::Builtin(_, nullable) => *nullable,
+        ast::FieldType::UserDefined(_, nullable) => *nullable,
+        ast::FieldType::Array(_, _, nullable) => *nullable,
+        ast::FieldType::Map(_, _, nullable) => *nullable,
+    }
+}
+
+/// The same field type without the outer `?`
+pub fn non_nullable(ft: &ast::FieldType) -> ast::FieldType {
+    match ft.clone() {
+        ast::FieldType::Builtin(bt, _) => ast::FieldType::Builtin(bt, false),
+        ast::FieldType::UserDefined(name, _) => ast::FieldType::UserDefined(name, false),
+        ast::FieldType::Array(inner, length, _) => ast::FieldType::Array(inner, length, false),
+        ast::FieldType::Map(key_type, value_type, _) => {
+            ast::FieldType::Map(key_type, value_type, false)
+        }
+    }
+}
+
+/// The schema spelling of a field type, e.g. `[string; 10]?`
+pub fn schema_type_str(ft: &ast::FieldType) -> String {
+    let (base, nullable) = match ft {
+        ast::FieldType::Builtin(bt, nullable) => {
+            (schema_builtin_type_str(bt).to_string(), nullable)
+        }
+        ast::FieldType::UserDefined(name, nullable) => (name.clone(), nullable),
+        ast::FieldType::Array(inner, length, nullable) => match length {
+            Some(len) => (format!("[{}; {len}]", schema_type_str(inner)), nullable),
+            None => (format!("[{}]", schema_type_str(inner)), nullable),
+        },
+        ast::FieldType::Map(key_type, value_type, nullable) => (
+            format!(
+                "{{{}: {}}}",
+                schema_map_key_type_str(key_type),
+                schema_type_str(value_type)
+            ),
+            nullable,
+        ),
+    };
+
+    if *nullable { format!("{base}?") } else { base }
+}
+
+/// The field type equivalent to a map key type, so keys can be handled like any other value
+pub fn map_key_field_type(kt: &ast::MapKeyType) -> ast::FieldType {
+    match kt {
+        ast::MapKeyType::Builtin(bt) => ast::FieldType::Builtin(bt.clone(), false),
+        ast::MapKeyType::UserDefined(name) => ast::FieldType::UserDefined(name.clone(), false),
+    }
+}
+
+/// The schema spelling of a map key type
+pub fn schema_map_key_type_str(kt: &ast::MapKeyType) -> &str {
+    match kt {
+        ast::MapKeyType::Builtin(bt) => schema_builtin_type_str(bt),
+        ast::MapKeyType::UserDefined(name) => name,
+    }
+}
+
+/// The schema spelling of a builtin type
+pub fn schema_builtin_type_str(bt: &ast::BuiltinType) -> &'static str {
+    match bt {
+        ast::BuiltinType::Integer(it) => integer_type_str(it),
+        ast::BuiltinType::Float(ast::FloatType::F32) => "f32",
+        ast::BuiltinType::Float(ast::FloatType::F64) => "f64",
+        ast::BuiltinType::String => "string",
+        ast::BuiltinType::LocalizedString => "lstring",
+        ast::BuiltinType::Bool => "bool",
+    }
+}
+
+/// The schema (and Rust) spelling of an integer type
+pub fn integer_type_str(t: &ast::IntegerType) -> &'static str {
+    t.as_str()
+}
+
+/// The decimal representation of an integer value
+pub fn integer_value_str(v: &ast::IntegerValue) -> String {
+    match v {
+        ast::IntegerValue::I8(n) => n.to_string(),
+        ast::IntegerValue::I16(n) => n.to_string(),
+        ast::IntegerValue::I32(n) => n.to_string(),
+        ast::IntegerValue::I64(n) => n.to_string(),
+        ast::IntegerValue::U8(n) => n.to_string(),
+        ast::IntegerValue::U16(n) => n.to_string(),
+        ast::IntegerValue::U32(n) => n.to_string(),
+        ast::IntegerValue::U64(n) => n.to_string(),
+    }
+}
+
+/// A naming convention for generated identifiers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NamingConvention {
+    /// `PascalCase`
+    Pascal,
+    /// `lowerCamelCase`
+    Camel,
+    /// `snake_case`
+    Snake,
+    /// `SCREAMING_SNAKE_CASE`
+    ScreamingSnake,
+    /// `kebab-case`
+    Kebab,
+    /// Keep the identifier exactly as written in the schema
+    Preserve,
+}
+
+impl NamingConvention {
+    /// Convert an identifier to this naming convention
+    pub fn apply(&self, s: &str) -> String {
+        match self {
+            NamingConvention::Pascal => to_pascal_case(s),
+            NamingConvention::Camel => to_lower_camel_case(s),
+            NamingConvention::Snake => to_snake_case(s),
+            NamingConvention::ScreamingSnake => to_screaming_snake_case(s),
+            NamingConvention::Kebab => to_kebab_case(s),
+            NamingConvention::Preserve => s.to_string(),
+        }
+    }
+}
+
+impl FromStr for NamingConvention {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pascal" => Ok(NamingConvention::Pascal),
+            "camel" => Ok(NamingConvention::Camel),
+            "snake" => Ok(NamingConvention::Snake),
+            "screaming-snake" => Ok(NamingConvention::ScreamingSnake),
+            "kebab" => Ok(NamingConvention::Kebab),
+            "preserve" => Ok(NamingConvention::Preserve),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The naming conventions a generator uses for each kind of identifier, configurable with the
+/// `type_case`, `field_case` and `variant_case` options
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Naming {
+    /// Convention for enum and struct names
+    pub types: NamingConvention,
+    /// Convention for struct field names
+    pub fields: NamingConvention,
+    /// Convention for enum variant names
+    pub variants: NamingConvention,
+    /// Escape the characters that aren't ASCII with [to_ascii_identifier], for targets whose
+    /// identifiers must be ASCII
+    pub ascii: bool,
+}
+
+impl Naming {
+    /// Read the naming options, falling back to the generator's defaults.  `allowed` lists the
+    /// conventions that produce valid identifiers in the target language.
+    pub fn from_options(
+        options: &Options,
+        defaults: Naming,
+        allowed: &[NamingConvention],
+    ) -> Result<Self, GenoError> {
+        let get = |key: &str, default: NamingConvention| match options.get(key) {
+            None => Ok(default),
+            Some(value) => match value.parse::<NamingConvention>() {
+                Ok(convention) if allowed.contains(&convention) => Ok(convention),
+                _ => Err(GenoError::InvalidOption(format!("{key}={value}"))),
+            },
+        };
+
+        Ok(Self {
+            types: get("type_case", defaults.types)?,
+            fields: get("field_case", defaults.fields)?,
+            variants: get("variant_case", defaults.variants)?,
+            ascii: defaults.ascii,
+        })
+    }
+
+    /// Name of an enum or struct
+    pub fn type_name(&self, s: &str) -> String {
+        self.identifier(self.types.apply(s))
+    }
+
+    /// Name of a struct field
+    pub fn field_name(&self, s: &str) -> String {
+        self.identifier(self.fields.apply(s))
+    }
+
+    /// Name of an enum variant
+    pub fn variant_name(&self, s: &str) -> String {
+        self.identifier(self.variants.apply(s))
+    }
+
+    fn identifier(&self, s: String) -> String {
+        if self.ascii && !s.is_ascii() {
+            to_ascii_identifier(&s)
+        } else {
+            s
+        }
+    }
+}
+
+/// Write an `impl Debug` for a Rust struct that shows `***` in place of masked fields.  Each
+/// field is given as its Rust name and whether it is masked.
+pub fn write_masked_debug_impl(w: &mut CodeWriter, rust_name: &str, fields: &[(String, bool)]) {
+    use fmt::Write;
+
+    // Any lifetime, such as `Ref<'_>`, isn't part of the name shown
+    let debug_name = rust_name.split('<').next().unwrap_or(rust_name);
+
+    w.blank();
+    w.block(
+        &format!("impl std::fmt::Debug for {rust_name} {{"),
+        "}",
+        |w| {
+            w.block(
+                "fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {",
+                "}",
+                |w| {
+                    writeln!(w, "f.debug_struct(\"{debug_name}\")").unwrap();
+                    w.indent();
+                    for (rust_field, masked) in fields {
+                        if *masked {
+                            writeln!(w, ".field(\"{rust_field}\", &\"***\")").unwrap();
+                        } else {
+                            writeln!(w, ".field(\"{rust_field}\", &self.{rust_field})").unwrap();
+                        }
+                    }
+                    w.line(".finish()");
+                    w.dedent();
+                },
+            );
+        },
+    );
+}
+
+/// The `redaction` option of the Rust generators, which gives every struct a `redacted()` method
+/// returning a copy with its `@sensitive` and `@pii` fields emptied, and a `Display` impl that
+/// shows the redacted copy, so that telemetry can log values without leaking personal data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redaction {
+    /// Names of the structs in the schema, whose fields are redacted in turn
+    structs: HashSet<String>,
+    /// The [boxed_fields] of the schema
+    boxed: HashSet<(String, String)>,
+}
+
+impl Redaction {
+    /// Read the `redaction` option, which defaults to `false`.  A sensitive field must be of a
+    /// type with an empty value, so a struct or enum field has to be nullable.
+    pub fn from_options(
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<Option<Self>, GenoError> {
+        if !options.get_bool("redaction", false)? {
+            return Ok(None);
+        }
+
+        let mut structs = HashSet::new();
+
+        for declaration in &schema.declarations {
+            if let ast::Declaration::Struct {
+                ident,
+                fields,
+                annotations,
+                ..
+            } = declaration
+            {
+                for (field_name, field_type) in fields {
+                    if annotations.is_sensitive(field_name) && empty_value_str(field_type).is_none()
+                    {
+                        return Err(GenoError::InvalidAnnotation(
+                            format!("{ident}.{field_name}"),
+                            "@sensitive".to_string(),
+                        ));
+                    }
+                }
+                structs.insert(ident.clone());
+            }
+        }
+
+        let boxed = boxed_fields(schema)
+            .into_iter()
+            .map(|(ident, field_name)| (ident.to_string(), field_name.to_string()))
+            .collect();
+
+        Ok(Some(Self { structs, boxed }))
+    }
+
+    /// Write `redacted()` and the `Display` impl for a struct, which must also implement `Debug`
+    pub fn write_impls(
+        &self,
+        w: &mut CodeWriter,
+        naming: &Naming,
+        ident: &str,
+        fields: &[(String, ast::FieldType)],
+        annotations: &ast::Annotations,
+    ) {
+        use fmt::Write;
+
+        let rust_name = naming.type_name(ident);
+
+        w.blank();
+        w.block(&format!("impl {rust_name} {{"), "}", |w| {
+            w.line(
+                "/// A copy with the `@sensitive` and `@pii` fields emptied, safe to log or export",
+            );
+            w.block("pub fn redacted(&self) -> Self {", "}", |w| {
+                w.block("Self {", "}", |w| {
+                    for (field_name, field_type) in fields {
+                        let rust_field = naming.field_name(field_name);
+                        let value = if annotations.is_sensitive(field_name) {
+                            // Checked in `from_options`
+                            empty_value_str(field_type).unwrap()
+                        } else {
+                            let boxed = self
+                                .boxed
+                                .contains(&(ident.to_string(), field_name.clone()));
+
+                            boxed_value_str(
+                                &self.redacted_str(&format!("self.{rust_field}"), field_type, 0),
+                                field_type,
+                                boxed,
+                            )
+                        };
+                        writeln!(w, "{rust_field}: {value},").unwrap();
+                    }
+                });
+            });
+        });
+
+        w.blank();
+        w.block(
+            &format!("impl std::fmt::Display for {rust_name} {{"),
+            "}",
+            |w| {
+                w.block(
+                    "fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {",
+                    "}",
+                    |w| {
+                        w.line("std::fmt::Debug::fmt(&self.redacted(), f)");
+                    },
+                );
+            },
+        );
+    }
+
+    /// An expression copying `expr`, redacting any structs inside it
+    fn redacted_str(&self, expr: &str, ft: &ast::FieldType, depth: usize) -> String {
+        if !self.holds_struct(ft) {
+            return format!("{expr}.clone()");
+        }
+        if is_nullable(ft) {
+            let var = format!("v{depth}");
+            let inner = self.redacted_str(&var, &non_nullable(ft), depth + 1);
+            return format!("{expr}.as_ref().map(|{var}| {inner})");
+        }
+
+        let var = format!("e{depth}");
+        match ft {
+            ast::FieldType::UserDefined(..) => format!("{expr}.redacted()"),
+            ast::FieldType::Array(inner, Some(_), _) => {
+                let inner = self.redacted_str(&var, inner, depth + 1);
+                format!("{expr}.each_ref().map(|{var}| {inner})")
+            }
+            ast::FieldType::Array(inner, None, _) => {
+                let inner = self.redacted_str(&var, inner, depth + 1);
+                format!("{expr}.iter().map(|{var}| {inner}).collect::<Vec<_>>()")
+            }
+            ast::FieldType::Map(_, value_type, _) => {
+                let key_var = format!("k{depth}");
+                let inner = self.redacted_str(&var, value_type, depth + 1);
+                format!(
+                    "{expr}.iter().map(|({key_var}, {var})| ({key_var}.clone(), {inner})).collect::<HashMap<_, _>>()"
+                )
+            }
+            ast::FieldType::Builtin(..) => format!("{expr}.clone()"),
+        }
+    }
+
+    /// Whether values of the type hold a struct, which has its own sensitive fields
+    fn holds_struct(&self, ft: &ast::FieldType) -> bool {
+        match ft {
+            ast::FieldType::UserDefined(name, _) => self.structs.contains(name),
+            ast::FieldType::Array(inner, _, _) => self.holds_struct(inner),
+            ast::FieldType::Map(_, value_type, _) => self.holds_struct(value_type),
+            ast::FieldType::Builtin(..) => false,
+        }
+    }
+}
+
+/// The empty value a sensitive field is redacted to, if its type has one
+fn empty_value_str(ft: &ast::FieldType) -> Option<String> {
+    if is_nullable(ft) {
+        return Some("None".to_string());
+    }
+
+    match ft {
+        ast::FieldType::Builtin(bt, _) => Some(
+            match bt {
+                ast::BuiltinType::Integer(_) => "0",
+                ast::BuiltinType::Float(_) => "0.0",
+                ast::BuiltinType::String => "String::new()",
+                ast::BuiltinType::LocalizedString => "LocalizedString::default()",
+                ast::BuiltinType::Bool => "false",
+            }
+            .to_string(),
+        ),
+        ast::FieldType::Array(inner, Some(_), _) => {
+            empty_value_str(inner).map(|value| format!("std::array::from_fn(|_| {value})"))
+        }
+        ast::FieldType::Array(_, None, _) => Some("Vec::new()".to_string()),
+        ast::FieldType::Map(..) => Some("HashMap::new()".to_string()),
+        ast::FieldType::UserDefined(..) => None,
+    }
+}
+
+/// The types that get public methods to encode and decode them on their own.  With the
+/// `roots_only` option these are the [roots](ast::Schema::roots) of the schema, otherwise every
+/// type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Roots(Option<HashSet<String>>);
+
+impl Roots {
+    /// Read the `roots_only` option
+    pub fn from_options(schema: &ast::Schema, options: &Options) -> Result<Self, GenoError> {
+        Ok(Self(options.get_bool("roots_only", false)?.then(|| {
+            schema.roots().into_iter().map(String::from).collect()
+        })))
+    }
+
+    /// Whether `ident` gets the public methods
+    pub fn contains(&self, ident: &str) -> bool {
+        self.0.as_ref().is_none_or(|roots| roots.contains(ident))
+    }
+}
+
+/// How 64-bit integer fields are represented, from the `int64` option or an `@int64` annotation
+/// on the field.  JavaScript numbers are only exact up to 2^53, so code that may be compiled to
+/// JavaScript needs 64-bit values as something other than a plain integer.  Integers inside
+/// arrays and maps are always plain integers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Int64 {
+    /// A native integer, packed as a MessagePack integer
+    Int,
+    /// A `BigInt` in Dart, packed as a decimal string
+    BigInt,
+    /// A `String` in Dart, packed as a decimal string
+    String,
+    /// No 64-bit integer fields are allowed
+    Error,
+}
+
+impl Int64 {
+    /// Read the `int64` option
+    pub fn from_options(options: &Options) -> Result<Self, GenoError> {
+        match options.get("int64") {
+            None => Ok(Int64::Int),
+            Some(value) => value
+                .parse()
+                .map_err(|_| GenoError::InvalidOption(format!("int64={value}"))),
+        }
+    }
+
+    /// The representation of a field with `annotations`, which is this one unless the field has
+    /// an `@int64` annotation
+    pub fn for_field(self, annotations: &[ast::Annotation]) -> Self {
+        annotations
+            .iter()
+            .find(|annotation| annotation.name == "int64")
+            .and_then(|annotation| annotation.identifier()?.parse().ok())
+            .unwrap_or(self)
+    }
+
+    /// Whether values are packed as decimal strings
+    pub fn is_string(self) -> bool {
+        matches!(self, Int64::BigInt | Int64::String)
+    }
+
+    /// Check that no field uses [Int64::Error]
+    pub fn check(self, schema: &ast::Schema) -> Result<(), GenoError> {
+        for decl in &schema.declarations {
+            if let ast::Declaration::Struct {
+                ident,
+                fields,
+                annotations,
+                ..
+            } = decl
+            {
+                for (name, ft) in fields {
+                    if is_int64(ft) && self.for_field(annotations.member(name)) == Int64::Error {
+                        return Err(GenoError::Int64NotAllowed(format!("{ident}.{name}")));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Int64 {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "int" => Ok(Int64::Int),
+            "bigint" => Ok(Int64::BigInt),
+            "string" => Ok(Int64::String),
+            "error" => Ok(Int64::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the MessagePack generators wrap messages from the `envelope` option, so that a receiver
+/// can tell a message written with another schema from a corrupt one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// The first 8 bytes of the schema's [fingerprint](ast::Schema::fingerprint)
+    pub fingerprint: [u8; 8],
+    /// Whether a CRC-32 of the payload is written after it
+    pub checksum: bool,
+}
+
+impl Envelope {
+    /// Read the `envelope` option, which is `none` (the default), `fingerprint` or `crc32`
+    pub fn from_options(
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<Option<Self>, GenoError> {
+        let checksum = match options.get("envelope") {
+            None | Some("none") => return Ok(None),
+            Some("fingerprint") => false,
+            Some("crc32") => true,
+            Some(value) => return Err(GenoError::InvalidOption(format!("envelope={value}"))),
+        };
+
+        Ok(Some(Self {
+            fingerprint: short_fingerprint(schema),
+            checksum,
+        }))
+    }
+}
+
+/// The first 8 bytes of the schema's [fingerprint](ast::Schema::fingerprint), which generated code
+/// checks to tell data written with another schema.  The fingerprint is of the declarations in
+/// sorted order, so the `order` option doesn't change it.
+pub fn short_fingerprint(schema: &ast::Schema) -> [u8; 8] {
+    let hex = schema.sorted().fingerprint();
+    let mut fingerprint = [0u8; 8];
+
+    for (i, byte) in fingerprint.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    fingerprint
+}
+
+/// How the MessagePack generators compress large messages, from the `compression`,
+/// `compress_above` and `max_decompressed` options
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Compression {
+    /// The compression algorithm
+    pub codec: Codec,
+    /// Messages longer than this many bytes are compressed
+    pub threshold: usize,
+    /// Compressed messages that decompress to more than this many bytes are rejected
+    pub max_decompressed: usize,
+}
+
+/// A compression algorithm for [Compression]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    /// zlib, from `flate2` in Rust and `dart:io` in Dart
+    Zlib,
+    /// Zstandard, from `zstd` in Rust and `es_compression` in Dart
+    Zstd,
+}
+
+impl Compression {
+    /// The byte that starts a compressed message.  No MessagePack value starts with it, so
+    /// uncompressed messages need no marker of their own.
+    pub const MARKER: u8 = 0xc1;
+
+    /// Messages longer than this are compressed unless `compress_above` says otherwise
+    pub const DEFAULT_THRESHOLD: usize = 1024;
+
+    /// Messages can't decompress to more than this unless `max_decompressed` says otherwise
+    pub const DEFAULT_MAX_DECOMPRESSED: usize = 16 * 1024 * 1024;
+
+    /// Read the `compression` option, which is `none` (the default), `zlib` or `zstd`, the
+    /// `compress_above` threshold in bytes and the `max_decompressed` limit in bytes
+    pub fn from_options(options: &Options) -> Result<Option<Self>, GenoError> {
+        let codec = match options.get("compression") {
+            None | Some("none") => return Ok(None),
+            Some("zlib") => Codec::Zlib,
+            Some("zstd") => Codec::Zstd,
+            Some(value) => return Err(GenoError::InvalidOption(format!("compression={value}"))),
+        };
+        let threshold = match options.get("compress_above") {
+            None => Self::DEFAULT_THRESHOLD,
+            Some(value) => value
+                .parse()
+                .map_err(|_| GenoError::InvalidOption(format!("compress_above={value}")))?,
+        };
+
+        let max_decompressed = match options.get("max_decompressed") {
+            None => Self::DEFAULT_MAX_DECOMPRESSED,
+            Some(value) => value
+                .parse()
+                .map_err(|_| GenoError::InvalidOption(format!("max_decompressed={value}")))?,
+        };
+
+        Ok(Some(Self {
+            codec,
+            threshold,
+            max_decompressed,
+        }))
+    }
+}
+
+impl Codec {
+    /// The byte after [Compression::MARKER] that says which algorithm compressed a message
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Zlib => 1,
+            Codec::Zstd => 2,
+        }
+    }
+}
+
+/// Whether a field type is an `i64` or a `u64`, nullable or not
+pub fn is_int64(ft: &ast::FieldType) -> bool {
+    matches!(
+        ft,
+        ast::FieldType::Builtin(
+            ast::BuiltinType::Integer(ast::IntegerType::I64 | ast::IntegerType::U64),
+            _
+        )
+    )
+}
+
+/// Returns `true` if the field type is a builtin written as a single MessagePack value, which is
+/// every builtin but `lstring`
+pub fn is_scalar(ft: &ast::FieldType) -> bool {
+    matches!(ft, ast::FieldType::Builtin(bt, _) if *bt != ast::BuiltinType::LocalizedString)
+}
+
+/// Whether any struct field of the schema is an `lstring`, or holds one
+pub fn has_localized_strings(schema: &ast::Schema) -> bool {
+    fn holds(ft: &ast::FieldType) -> bool {
+        match ft {
+            ast::FieldType::Builtin(bt, _) => *bt == ast::BuiltinType::LocalizedString,
+            ast::FieldType::UserDefined(..) => false,
+            ast::FieldType::Array(inner, _, _) => holds(inner),
+            ast::FieldType::Map(_, value_type, _) => holds(value_type),
+        }
+    }
+
+    schema.declarations.iter().any(|decl| match decl {
+        ast::Declaration::Struct { fields, .. } => fields.iter().any(|(_, ft)| holds(ft)),
+        _ => false,
+    })
+}
+
+/// The `(struct, field)` pairs that arbitrary values leave null or empty, so that building a
+/// value of a recursive type stops: the fields that can be null or empty and that hold the
+/// struct they are in, directly or not
+pub fn recursive_fields(schema: &ast::Schema) -> HashSet<(&str, &str)> {
+    fn can_be_empty(ft: &ast::FieldType) -> bool {
+        match ft {
+            ast::FieldType::Array(inner, Some(_), false) => can_be_empty(inner),
+            ast::FieldType::Array(..) | ast::FieldType::Map(..) => true,
+            _ => is_nullable(ft),
+        }
+    }
+
+    self_reaching_fields(schema, false, can_be_empty)
+}
+
+/// The `(struct, field)` pairs of struct fields that hold the struct they are in without a list
+/// or map in between, directly or through other structs and fixed length lists.  Where structs
+/// are stored inline, as in Rust, these must be boxed or the struct has an infinite size.
+pub fn boxed_fields(schema: &ast::Schema) -> HashSet<(&str, &str)> {
+    self_reaching_fields(schema, true, |ft| {
+        matches!(ft, ast::FieldType::UserDefined(..))
+    })
+}
+
+/// The Rust type of a struct field, from the type `type_of` gives its value, with the struct
+/// boxed if the field is one of the [boxed_fields]
+pub fn boxed_type_str(
+    ft: &ast::FieldType,
+    boxed: bool,
+    type_of: impl Fn(&ast::FieldType) -> String,
+) -> String {
+    if !boxed {
+        return type_of(ft);
+    }
+
+    let inner = format!("Box<{}>", type_of(&non_nullable(ft)));
+
+    if is_nullable(ft) {
+        format!("Option<{inner}>")
+    } else {
+        inner
+    }
+}
+
+/// `expr`, an unboxed value of a struct field, boxed if the field is one of the [boxed_fields]
+pub fn boxed_value_str(expr: &str, ft: &ast::FieldType, boxed: bool) -> String {
+    match (boxed, is_nullable(ft)) {
+        (false, _) => expr.to_string(),
+        (true, true) => format!("{expr}.map(Box::new)"),
+        (true, false) => format!("Box::new({expr})"),
+    }
+}
+
+/// The `(struct, field)` pairs of the fields `include` accepts that hold the struct they are in.
+/// With `inline` only the structs and fixed length lists a value holds are followed, and not its
+/// lists and maps.
+fn self_reaching_fields(
+    schema: &ast::Schema,
+    inline: bool,
+    include: impl Fn(&ast::FieldType) -> bool,
+) -> HashSet<(&str, &str)> {
+    fn reaches<'a>(
+        structs: &HashMap<&'a str, &'a [(String, ast::FieldType)]>,
+        ft: &'a ast::FieldType,
+        target: &str,
+        inline: bool,
+        visited: &mut HashSet<&'a str>,
+    ) -> bool {
+        match ft {
+            ast::FieldType::Builtin(..) => false,
+            ast::FieldType::UserDefined(name, _) => {
+                name == target
+                    || (visited.insert(name)
+                        && structs.get(name.as_str()).is_some_and(|fields| {
+                            fields
+                                .iter()
+                                .any(|(_, ft)| reaches(structs, ft, target, inline, visited))
+                        }))
+            }
+            ast::FieldType::Array(_, None, _) | ast::FieldType::Map(..) if inline => false,
+            ast::FieldType::Array(inner, _, _) => reaches(structs, inner, target, inline, visited),
+            ast::FieldType::Map(_, value_type, _) => {
+                reaches(structs, value_type, target, inline, visited)
+            }
+        }
+    }
+
+    let structs: HashMap<&str, &[(String, ast::FieldType)]> = schema
+        .declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            ast::Declaration::Struct { ident, fields, .. } => {
+                Some((ident.as_str(), fields.as_slice()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    structs
+        .iter()
+        .flat_map(|(ident, fields)| {
+            fields
+                .iter()
+                .filter(|(_, ft)| {
+                    include(ft) && reaches(&structs, ft, ident, inline, &mut HashSet::new())
+                })
+                .map(|(name, _)| (*ident, name.as_str()))
+        })
+        .collect()
+}
+
+/// Whether the schema declares any topics
+pub fn has_topics(schema: &ast::Schema) -> bool {
+    schema
+        .declarations
+        .iter()
+        .any(|decl| matches!(decl, ast::Declaration::Topic { .. }))
+}
+
+/// The schema to generate code for, as chosen by the `version` option.  A version number leaves
+/// out the fields and variants that aren't in that version, and `all` keeps every one so that
+/// data written at any version can be read.  Without the option the schema's own `version` is
+/// used.
+pub fn select_version<'a>(
+    schema: &'a ast::Schema,
+    options: &Options,
+) -> Result<Cow<'a, ast::Schema>, GenoError> {
+    let latest = schema.version();
+
+    match (options.get("version"), latest) {
+        (None, None) => Ok(Cow::Borrowed(schema)),
+        (None, Some(latest)) => Ok(Cow::Owned(schema.at_version(latest))),
+        (Some("all"), _) => Ok(Cow::Owned(schema.all_versions())),
+        (Some(value), latest) => match value.parse::<i64>() {
+            Ok(version) if version > 0 && latest.is_some_and(|latest| version <= latest) => {
+                let schema = schema.at_version(version);
+
+                schema.validate()?;
+                Ok(Cow::Owned(schema))
+            }
+            _ => Err(GenoError::InvalidOption(format!("version={value}"))),
+        },
+    }
+}
+
+/// The schema to generate code for, at the version chosen by [select_version] and in the order
+/// chosen by the `order` option.  With `order=sorted` the declarations are
+/// [sorted](ast::Schema::sorted), otherwise they are in source order.
+pub fn select_schema<'a>(
+    schema: &'a ast::Schema,
+    options: &Options,
+) -> Result<Cow<'a, ast::Schema>, GenoError> {
+    let schema = select_version(schema, options)?;
+
+    match options.get("order") {
+        None | Some("source") => Ok(schema),
+        Some("sorted") => Ok(Cow::Owned(schema.sorted())),
+        Some(value) => Err(GenoError::InvalidOption(format!("order={value}"))),
+    }
+}
+
+/// As [select_schema], keeping only the fields in the code for `target`, or with no target only
+/// the fields every target has.  Readers in a target without a field skip it and writers leave it
+/// out, so fields can only be left out of some targets with the keyed wire format.
+pub fn select_target<'a>(
+    schema: &'a ast::Schema,
+    options: &Options,
+    target: Option<&str>,
+) -> Result<Cow<'a, ast::Schema>, GenoError> {
+    let schema = select_schema(schema, options)?;
+
+    match schema.target_fields().into_iter().next() {
+        None => Ok(schema),
+        Some(field) if WireFormat::from_options(&schema, options)? != WireFormat::Keyed => {
+            Err(GenoError::TargetFieldNeedsKeyed(field))
+        }
+        Some(_) => Ok(Cow::Owned(schema.for_target(target))),
+    }
+}
+
+/// How the MessagePack generators lay out structs on the wire
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WireFormat {
+    /// Fields in declaration order with no framing, the most compact layout
+    Positional,
+    /// As [WireFormat::Positional], but led by a bitmap of the nullable fields that are present
+    /// so that null fields take no space at all
+    Bitmap,
+    /// A map from field name to value, with null fields left out.  Readers skip fields they don't
+    /// know about and treat missing nullable fields as null, so fields can be added over time.
+    Keyed,
+}
+
+impl WireFormat {
+    /// Read the `wire` option, falling back to the `wire` metadata value in the schema.  Reading
+    /// every version with `version=all` needs the [WireFormat::Keyed] format.
+    pub fn from_options(schema: &ast::Schema, options: &Options) -> Result<Self, GenoError> {
+        let wire = Self::from_options_or_metadata(schema, options)?;
+
+        if wire != WireFormat::Keyed && options.get("version") == Some("all") {
+            return Err(GenoError::InvalidOption(
+                "version=all needs wire=keyed".to_string(),
+            ));
+        }
+
+        Ok(wire)
+    }
+
+    fn from_options_or_metadata(
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<Self, GenoError> {
+        if let Some(value) = options.get("wire") {
+            return value
+                .parse()
+                .map_err(|_| GenoError::InvalidOption(format!("wire={value}")));
+        }
+
+        match schema.metadata.get("wire") {
+            None => Ok(WireFormat::Positional),
+            Some(ast::MetadataValue::String(value)) => value
+                .parse()
+                .map_err(|_| GenoError::InvalidWireFormat(value.clone())),
+            Some(ast::MetadataValue::Integer(value)) => {
+                Err(GenoError::InvalidWireFormat(integer_value_str(value)))
+            }
+        }
+    }
+}
+
+impl WireFormat {
+    /// The packing rules of the format
+    pub const fn protocol(self) -> WireProtocol {
+        match self {
+            WireFormat::Positional => WireProtocol {
+                layout: StructLayout::Sequence,
+                presence_bitmap: false,
+                omit_null_fields: false,
+                presence_markers: true,
+            },
+            WireFormat::Bitmap => WireProtocol {
+                layout: StructLayout::Sequence,
+                presence_bitmap: true,
+                omit_null_fields: true,
+                presence_markers: true,
+            },
+            WireFormat::Keyed => WireProtocol {
+                layout: StructLayout::Map,
+                presence_bitmap: false,
+                omit_null_fields: true,
+                presence_markers: false,
+            },
+        }
+    }
+}
+
+impl FromStr for WireFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "positional" => Ok(WireFormat::Positional),
+            "keyed" => Ok(WireFormat::Keyed),
+            "bitmap" => Ok(WireFormat::Bitmap),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How a struct's fields are laid out on the wire
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StructLayout {
+    /// The field values one after the other in declaration order, with no header
+    Sequence,
+    /// A map from field name to value
+    Map,
+}
+
+/// The rules a [WireFormat] packs structs and nullable values by.  The MessagePack generators and
+/// the conformance test vectors all follow these, so a new format only needs describing here.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct WireProtocol {
+    /// How a struct's fields are laid out
+    pub layout: StructLayout,
+    /// Whether a struct is led by the words of a bitmap of its nullable fields that are present,
+    /// see [presence_bits]
+    pub presence_bitmap: bool,
+    /// Whether null struct fields are left out rather than packed as nil
+    pub omit_null_fields: bool,
+    /// Whether a present nullable `lstring`, struct, list or map is led by a `true`, so it can't
+    /// be mistaken for a nil
+    pub presence_markers: bool,
+}
+
+impl WireProtocol {
+    /// Whether a present value of the nullable type is led by a presence marker.  `is_enum` says
+    /// whether a user defined type is an enum, which like scalars never has a marker.
+    pub fn has_presence_marker(&self, ft: &ast::FieldType, is_enum: impl Fn(&str) -> bool) -> bool {
+        self.presence_markers
+            && is_nullable(ft)
+            && !is_scalar(ft)
+            && !matches!(ft, ast::FieldType::UserDefined(name, _) if is_enum(name))
+    }
+
+    /// The [presence_bits] of the fields, or all `None` if structs have no presence bitmap
+    pub fn presence_bits(&self, fields: &[(String, ast::FieldType)]) -> Vec<Option<(usize, u32)>> {
+        if self.presence_bitmap {
+            presence_bits(fields)
+        } else {
+            vec![None; fields.len()]
+        }
+    }
+}
+
+/// Number of flags in each word of a presence bitmap.  Kept to 32 so that the words are exact
+/// in every Dart runtime.
+pub const PRESENCE_WORD_BITS: usize = 32;
+
+/// Where each field lives in a presence bitmap, as a `(word, mask)` pair, or `None` for
+/// non-nullable fields.  Nullable fields are numbered in declaration order from the least
+/// significant bit of the first word.
+pub fn presence_bits(fields: &[(String, ast::FieldType)]) -> Vec<Option<(usize, u32)>> {
+    let mut index = 0;
+
+    fields
+        .iter()
+        .map(|(_, ft)| {
+            is_nullable(ft).then(|| {
+                let bit = (
+                    index / PRESENCE_WORD_BITS,
+                    1 << (index % PRESENCE_WORD_BITS),
+                );
+                index += 1;
+                bit
+            })
+        })
+        .collect()
+}
+
+/// The most bytes the MessagePack generators write for a struct or enum whose `@len` constraints
+/// are met, or `None` if a string, list or map in it has no maximum length or the type holds
+/// itself.  A string of `n` characters is taken to be up to `4n` bytes of UTF-8.
+pub fn packed_size_upper_bound(
+    schema: &ast::Schema,
+    ident: &str,
+    wire: WireFormat,
+    int64: Int64,
+) -> Option<usize> {
+    SizeBound {
+        schema,
+        protocol: wire.protocol(),
+        int64,
+        visiting: Vec::new(),
+    }
+    .type_size(ident)
+}
+
+struct SizeBound<'a> {
+    schema: &'a ast::Schema,
+    protocol: WireProtocol,
+    int64: Int64,
+    // Structs being sized, to stop at recursive ones
+    visiting: Vec<&'a str>,
+}
+
+impl<'a> SizeBound<'a> {
+    fn type_size(&mut self, ident: &str) -> Option<usize> {
+        let decl = self.schema.declarations.iter().find(|decl| match decl {
+            ast::Declaration::Enum { ident: name, .. }
+            | ast::Declaration::Struct { ident: name, .. } => name == ident,
+            ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => false,
+        })?;
+
+        match decl {
+            ast::Declaration::Enum { base_type, .. } => Some(int_size(base_type)),
+            ast::Declaration::Struct {
+                ident,
+                fields,
+                annotations,
+                ..
+            } => {
+                if self.visiting.contains(&ident.as_str()) {
+                    return None;
+                }
+                self.visiting.push(ident);
+
+                let nullable = fields.iter().filter(|(_, ft)| is_nullable(ft)).count();
+                let protocol = self.protocol;
+                let mut size = match protocol.layout {
+                    StructLayout::Sequence => 0,
+                    StructLayout::Map => container_header_size(fields.len()),
+                };
+
+                if protocol.presence_bitmap {
+                    // Each word of the presence bitmap is a `u32`
+                    size += 5 * nullable.div_ceil(PRESENCE_WORD_BITS);
+                }
+
+                for (name, ft) in fields {
+                    let field = annotations.member(name);
+                    let len = field
+                        .iter()
+                        .find(|annotation| annotation.name == "len")
+                        .and_then(|annotation| annotation.bounds()?.max);
+
+                    if protocol.layout == StructLayout::Map {
+                        size += str_size(name.len());
+                    }
+                    size += if is_int64(ft) && self.int64.for_field(field).is_string() {
+                        // The longest decimal `i64` or `u64` is 20 characters
+                        str_size(20)
+                    } else {
+                        self.field_size(ft, len.map(|len| len as usize))?
+                    };
+                }
+
+                self.visiting.pop();
+                Some(size)
+            }
+            ast::Declaration::Interface { .. } | ast::Declaration::Topic { .. } => None,
+        }
+    }
+
+    /// The size of a value with at most `len` elements or characters.  Nullable structs, lists
+    /// and maps may have a presence marker, while other null values are a nil, which is no
+    /// bigger than the value.
+    fn field_size(&mut self, ft: &ast::FieldType, len: Option<usize>) -> Option<usize> {
+        let size = match ft {
+            ast::FieldType::Builtin(bt, _) => builtin_size(bt, len)?,
+            ast::FieldType::UserDefined(ident, _) => self.type_size(ident)?,
+            ast::FieldType::Array(inner, fixed, _) => {
+                let len = fixed.or(len)?;
+
+                container_header_size(len) + len * self.field_size(inner, None)?
+            }
+            ast::FieldType::Map(key_type, value_type, _) => {
+                let len = len?;
+                let key_size = match key_type {
+                    ast::MapKeyType::Builtin(bt) => builtin_size(bt, None)?,
+                    ast::MapKeyType::UserDefined(ident) => self.type_size(ident)?,
+                };
+
+                container_header_size(len) + len * (key_size + self.field_size(value_type, None)?)
+            }
+        };
+        let marker = self
+            .protocol
+            .has_presence_marker(ft, |ident| self.is_enum(ident));
+
+        Some(size + usize::from(marker))
+    }
+
+    fn is_enum(&self, ident: &str) -> bool {
+        self.schema
+            .declarations
+            .iter()
+            .any(|decl| matches!(decl, ast::Declaration::Enum { ident: name, .. } if name == ident))
+    }
+}
+
+fn builtin_size(bt: &ast::BuiltinType, len: Option<usize>) -> Option<usize> {
+    match bt {
+        ast::BuiltinType::Integer(it) => Some(int_size(it)),
+        // Floats are always written as `float 64`
+        ast::BuiltinType::Float(_) => Some(9),
+        ast::BuiltinType::Bool => Some(1),
+        ast::BuiltinType::String => Some(str_size(4 * len?)),
+        ast::BuiltinType::LocalizedString => None,
+    }
+}
+
+fn int_size(it: &ast::IntegerType) -> usize {
+    match it {
+        ast::IntegerType::I8 | ast::IntegerType::U8 => 2,
+        ast::IntegerType::I16 | ast::IntegerType::U16 => 3,
+        ast::IntegerType::I32 | ast::IntegerType::U32 => 5,
+        ast::IntegerType::I64 | ast::IntegerType::U64 => 9,
+    }
+}
+
+fn str_size(len: usize) -> usize {
+    len + match len {
+        0..32 => 1,
+        32..256 => 2,
+        256..65536 => 3,
+        _ => 5,
+    }
+}
+
+fn container_header_size(len: usize) -> usize {
+    match len {
+        0..16 => 1,
+        16..65536 => 3,
+        _ => 5,
+    }
+}
+
+/// Split an identifier into words.  Words are separated by `_`, `-`, the `.` after the namespace
+/// of an imported type, a lower case letter or digit followed by an upper case letter, or the last
+/// letter of an acronym followed by a lower case letter.  Digits stay with the word they follow.
+/// "userID" -> ["user", "ID"], "HTTPServer" -> ["HTTP", "Server"], "v2Config" -> ["v2", "Config"],
+/// "common.address" -> ["common", "address"]
+pub fn split_words(s: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+
+    for part in s.split(['_', '-', '.']) {
+        let chars: Vec<(usize, char)> = part.char_indices().collect();
+        let mut start = 0;
+
+        for (i, &(offset, c)) in chars.iter().enumerate().skip(1) {
+            let prev = chars[i - 1].1;
+            let next = chars.get(i + 1).map(|&(_, c)| c);
+            let is_boundary = c.is_uppercase()
+                && (prev.is_lowercase()
+                    || prev.is_ascii_digit()
+                    || (prev.is_uppercase() && next.is_some_and(|n| n.is_lowercase())));
+
+            if is_boundary {
+                words.push(&part[start..offset]);
+                start = offset;
+            }
+        }
+
+        if start < part.len() {
+            words.push(&part[start..]);
+        }
+    }
+
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+
+    match chars.next() {
+        None => String::new(),
+        Some(c) => c
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+    }
+}
+
+/// Converts a string to PascalCase.
+/// "type1" -> "Type1", "kiwiFruit" -> "KiwiFruit", "alpha_beta" -> "AlphaBeta", "HTTPServer" -> "HttpServer"
+pub fn to_pascal_case(s: &str) -> String {
+    split_words(s).into_iter().map(capitalize).collect()
+}
+
+/// Converts a string to lowerCamelCase.
+/// "alpha_beta" -> "alphaBeta", "AlphaBeta" -> "alphaBeta", "userID" -> "userId"
+pub fn to_lower_camel_case(s: &str) -> String {
+    split_words(s)
+        .into_iter()
+        .enumerate()
+        .map(|(i, word)| {
+            if i == 0 {
+                word.to_lowercase()
+            } else {
+                capitalize(word)
+            }
+        })
+        .collect()
+}
+
+/// Converts a string to snake_case.
+/// "alphaBeta" -> "alpha_beta", "alpha_beta" -> "alpha_beta", "userID" -> "user_id"
+pub fn to_snake_case(s: &str) -> String {
+    join_words(s, "_", str::to_lowercase)
+}
+
+/// Converts a string to SCREAMING_SNAKE_CASE.
+/// "alphaBeta" -> "ALPHA_BETA", "v2Config" -> "V2_CONFIG"
+pub fn to_screaming_snake_case(s: &str) -> String {
+    join_words(s, "_", str::to_uppercase)
+}
+
+/// Converts a string to kebab-case.
+/// "alphaBeta" -> "alpha-beta", "HTTPServer" -> "http-server"
+pub fn to_kebab_case(s: &str) -> String {
+    join_words(s, "-", str::to_lowercase)
+}
+
+/// Escapes each character of an identifier that isn't ASCII as `u` followed by its code point in
+/// lower case hex, for targets whose identifiers must be ASCII.
+/// "größe" -> "gru00f6u00dfe", "名前" -> "u540du524d"
+pub fn to_ascii_identifier(s: &str) -> String {
+    let mut ascii = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if c.is_ascii() {
+            ascii.push(c);
+        } else {
+            ascii.push_str(&format!("u{:04x}", c as u32));
+        }
+    }
+
+    ascii
+}
+
+fn join_words(s: &str, separator: &str, convert: fn(&str) -> String) -> String {
+    split_words(s)
+        .into_iter()
+        .map(convert)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write as _;
+
+    #[test]
+    fn code_writer_indents() {
+        let mut w = CodeWriter::new("  ");
+
+        w.block("class A {", "}", |w| {
+            writeln!(w, "int a;").unwrap();
+            w.blank();
+            write!(w, "void f() {{\n  return;\n}}\n").unwrap();
+        });
+
+        assert_eq!(
+            w.into_string(),
+            "class A {\n  int a;\n\n  void f() {\n    return;\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn code_writer_streams() {
+        let mut out = Vec::new();
+        let mut w = CodeWriter::to_writer("  ", &mut out);
+        let mut expected = String::new();
+
+        for i in 0..10_000 {
+            w.block(&format!("class A{i} {{"), "}", |w| {
+                w.blank();
+                writeln!(w, "int a;").unwrap();
+            });
+            w.blank();
+            expected.push_str(&format!("class A{i} {{\n  int a;\n}}\n\n"));
+        }
+
+        assert!(w.as_str().len() < FLUSH_SIZE);
+        w.finish().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn case_conversion() {
+        assert_eq!(to_pascal_case("type1"), "Type1");
+        assert_eq!(to_pascal_case("kiwiFruit"), "KiwiFruit");
+        assert_eq!(to_pascal_case("alpha_beta"), "AlphaBeta");
+        assert_eq!(to_lower_camel_case("alpha_beta"), "alphaBeta");
+        assert_eq!(to_lower_camel_case("AlphaBeta"), "alphaBeta");
+        assert_eq!(to_snake_case("alphaBeta"), "alpha_beta");
+        assert_eq!(to_snake_case("alpha_beta"), "alpha_beta");
+    }
+
+    #[test]
+    fn acronyms_and_digits() {
+        assert_eq!(split_words("HTTPServer"), vec!["HTTP", "Server"]);
+        assert_eq!(split_words("userID"), vec!["user", "ID"]);
+        assert_eq!(split_words("v2Config"), vec!["v2", "Config"]);
+        assert_eq!(split_words("a10"), vec!["a10"]);
+        assert_eq!(split_words("kebab-case_mix"), vec!["kebab", "case", "mix"]);
+        assert_eq!(split_words("common.address"), vec!["common", "address"]);
+        assert_eq!(to_snake_case("userID"), "user_id");
+        assert_eq!(to_snake_case("v2Config"), "v2_config");
+        assert_eq!(to_pascal_case("HTTPServer"), "HttpServer");
+        assert_eq!(to_pascal_case("common.address"), "CommonAddress");
+        assert_eq!(to_lower_camel_case("userID"), "userId");
+        assert_eq!(to_screaming_snake_case("kiwiFruit"), "KIWI_FRUIT");
+        assert_eq!(to_kebab_case("HTTPServer"), "http-server");
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        assert_eq!(split_words("größeZahl"), vec!["größe", "Zahl"]);
+        assert_eq!(to_pascal_case("größe_zahl"), "GrößeZahl");
+        assert_eq!(to_snake_case("ÉtatCivil"), "état_civil");
+        assert_eq!(to_pascal_case("名前"), "名前");
+        assert_eq!(to_ascii_identifier("größe"), "gru00f6u00dfe");
+        assert_eq!(to_ascii_identifier("名前"), "u540du524d");
+
+        let naming = Naming {
+            types: NamingConvention::Pascal,
+            fields: NamingConvention::Camel,
+            variants: NamingConvention::Camel,
+            ascii: true,
+        };
+
+        assert_eq!(naming.type_name("straße"), "Strau00dfe");
+        assert_eq!(naming.field_name("user_id"), "userId");
+    }
+
+    #[test]
+    fn naming_options() {
+        let defaults = Naming {
+            types: NamingConvention::Pascal,
+            fields: NamingConvention::Snake,
+            variants: NamingConvention::Pascal,
+            ascii: false,
+        };
+        let allowed = [NamingConvention::Pascal, NamingConvention::ScreamingSnake];
+        let options = Options::parse(["variant_case=screaming-snake"]).unwrap();
+        let naming = Naming::from_options(&options, defaults, &allowed).unwrap();
+
+        assert_eq!(naming.variant_name("kiwiFruit"), "KIWI_FRUIT");
+        assert_eq!(naming.field_name("kiwiFruit"), "kiwi_fruit");
+
+        let options = Options::parse(["field_case=kebab"]).unwrap();
+
+        assert!(matches!(
+            Naming::from_options(&options, defaults, &allowed),
+            Err(GenoError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn order_option() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } topic b: order; struct order { l: [line] } topic a: order;
+                struct line { k: kind, o: order? } enum kind { x = 1 } struct audit { id: u64 }",
+            )
+            .unwrap();
+        let idents = |options: &[&str]| {
+            select_schema(&schema, &Options::parse(options).unwrap())
+                .unwrap()
+                .declarations
+                .iter()
+                .map(|decl| match decl {
+                    ast::Declaration::Enum { ident, .. }
+                    | ast::Declaration::Struct { ident, .. }
+                    | ast::Declaration::Interface { ident, .. }
+                    | ast::Declaration::Topic { ident, .. } => ident.clone(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(idents(&[]), ["b", "order", "a", "line", "kind", "audit"]);
+        assert_eq!(idents(&["order=source"]), idents(&[]));
+        assert_eq!(
+            idents(&["order=sorted"]),
+            ["audit", "kind", "line", "order", "a", "b"]
+        );
+        assert!(select_schema(&schema, &Options::parse(["order=name"]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn target_option() {
+        let build = |meta: &str| {
+            crate::GenoAstBuilder::new("test.geno".into())
+                .build_from_str(&format!(
+                    "meta {{ format = 1{meta} }} struct user {{ id: u64, @only(rust) hash: string? }}"
+                ))
+                .unwrap()
+        };
+        let fields = |schema: &ast::Schema, options: &[&str], target| match &select_target(
+            schema,
+            &Options::parse(options).unwrap(),
+            target,
+        )
+        .unwrap()
+        .declarations[0]
+        {
+            ast::Declaration::Struct { fields, .. } => fields.len(),
+            _ => unreachable!(),
+        };
+        let keyed = build(", wire = \"keyed\"");
+
+        assert_eq!(fields(&keyed, &[], Some("rust")), 2);
+        assert_eq!(fields(&keyed, &[], Some("dart")), 1);
+        assert_eq!(fields(&keyed, &[], None), 1);
+        assert_eq!(fields(&build(""), &["wire=keyed"], Some("dart")), 1);
+        assert!(matches!(
+            select_target(&build(""), &Options::new(), Some("rust")),
+            Err(GenoError::TargetFieldNeedsKeyed(field)) if field == "user.hash"
+        ));
+        assert!(matches!(
+            select_target(&keyed, &Options::parse(["wire=bitmap"]).unwrap(), None),
+            Err(GenoError::TargetFieldNeedsKeyed(_))
+        ));
+    }
+
+    #[test]
+    fn version_option() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1, version = 2 } struct p { @removed(2) a: i8, @since(2) b: i8 }",
+            )
+            .unwrap();
+        let fields =
+            |options: &[&str]| match &select_version(&schema, &Options::parse(options).unwrap())
+                .unwrap()
+                .declarations[0]
+            {
+                ast::Declaration::Struct { fields, .. } => fields
+                    .iter()
+                    .map(|(name, ft)| format!("{name}{}", if is_nullable(ft) { "?" } else { "" }))
+                    .collect::<Vec<_>>(),
+                _ => panic!("expected a struct"),
+            };
+
+        assert_eq!(fields(&[]), vec!["b"]);
+        assert_eq!(fields(&["version=1"]), vec!["a"]);
+        assert_eq!(fields(&["version=all"]), vec!["a?", "b?"]);
+
+        for value in ["0", "3", "latest"] {
+            let options = Options::parse([format!("version={value}")]).unwrap();
+
+            assert!(select_version(&schema, &options).is_err());
+        }
+
+        let options = Options::parse(["version=all"]).unwrap();
+
+        assert!(WireFormat::from_options(&schema, &options).is_err());
+
+        let options = Options::parse(["version=all", "wire=keyed"]).unwrap();
+
+        assert_eq!(
+            WireFormat::from_options(&schema, &options).unwrap(),
+            WireFormat::Keyed
+        );
+    }
+
+    #[test]
+    fn nullable() {
+        let bool_type = ast::FieldType::Builtin(ast::BuiltinType::Bool, false);
+
+        assert!(!is_nullable(&bool_type));
+        assert!(is_nullable(&ast::FieldType::Array(
+            Box::new(bool_type),
+            None,
+            true
+        )));
+    }
+
+    #[test]
+    fn schema_types() {
+        let ft = ast::FieldType::Map(
+            ast::MapKeyType::Builtin(ast::BuiltinType::String),
+            Box::new(ast::FieldType::Array(
+                Box::new(ast::FieldType::UserDefined("point".to_string(), true)),
+                Some(3),
+                false,
+            )),
+            true,
+        );
+
+        assert_eq!(schema_type_str(&ft), "{string: [point?; 3]}?");
+    }
+
+    #[test]
+    fn presence_bitmap() {
+        let field = |nullable| {
+            (
+                "f".to_string(),
+                ast::FieldType::Builtin(ast::BuiltinType::Bool, nullable),
+            )
+        };
+        let mut fields = vec![field(true), field(false), field(true)];
+        fields.extend((0..31).map(|_| field(true)));
+
+        let bits = presence_bits(&fields);
+
+        assert_eq!(bits[0], Some((0, 0x1)));
+        assert_eq!(bits[1], None);
+        assert_eq!(bits[2], Some((0, 0x2)));
+        assert_eq!(bits[32], Some((0, 0x8000_0000)));
+        assert_eq!(bits[33], Some((1, 0x1)));
+    }
+
+    #[test]
+    fn wire_protocols() {
+        let tags = ast::FieldType::Array(
+            Box::new(ast::FieldType::Builtin(ast::BuiltinType::String, false)),
+            None,
+            true,
+        );
+        let kind = ast::FieldType::UserDefined("kind".to_string(), true);
+        let fields = [
+            ("tags".to_string(), tags.clone()),
+            ("kind".to_string(), kind.clone()),
+        ];
+        let is_enum = |name: &str| name == "kind";
+
+        for wire in [
+            WireFormat::Positional,
+            WireFormat::Bitmap,
+            WireFormat::Keyed,
+        ] {
+            let protocol = wire.protocol();
+
+            assert_eq!(
+                protocol.layout == StructLayout::Map,
+                wire == WireFormat::Keyed
+            );
+            assert_eq!(
+                protocol.has_presence_marker(&tags, is_enum),
+                wire != WireFormat::Keyed
+            );
+            assert!(!protocol.has_presence_marker(&kind, is_enum));
+            assert!(!protocol.has_presence_marker(&non_nullable(&tags), is_enum));
+            assert_eq!(
+                protocol.presence_bits(&fields),
+                match wire {
+                    WireFormat::Bitmap => vec![Some((0, 1)), Some((0, 2))],
+                    _ => vec![None, None],
+                }
+            );
+        }
+        assert!(!WireFormat::Positional.protocol().omit_null_fields);
+        assert!(WireFormat::Bitmap.protocol().omit_null_fields);
+    }
+
+    #[test]
+    fn boxed() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } struct leaf { owner: node? }
+                struct node { parent: node?, first: leaf?, pair: [leaf; 2]?, children: [node] }
+                struct list { items: {string: list} }",
+            )
+            .unwrap();
+        let mut boxed: Vec<_> = boxed_fields(&schema).into_iter().collect();
+        let mut recursive: Vec<_> = recursive_fields(&schema).into_iter().collect();
+
+        boxed.sort();
+        recursive.sort();
+        assert_eq!(
+            boxed,
+            [("leaf", "owner"), ("node", "first"), ("node", "parent")]
+        );
+        assert_eq!(
+            recursive,
+            [
+                ("leaf", "owner"),
+                ("list", "items"),
+                ("node", "children"),
+                ("node", "first"),
+                ("node", "pair"),
+                ("node", "parent")
+            ]
+        );
+
+        let ft = ast::FieldType::UserDefined("node".to_string(), true);
+
+        assert_eq!(
+            boxed_type_str(&ft, true, schema_type_str),
+            "Option<Box<node>>"
+        );
+        assert_eq!(boxed_value_str("x", &ft, true), "x.map(Box::new)");
+        assert_eq!(
+            boxed_value_str("x", &non_nullable(&ft), true),
+            "Box::new(x)"
+        );
+        assert_eq!(boxed_value_str("x", &ft, false), "x");
+    }
+
+    #[test]
+    fn size_upper_bound() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                "meta { format = 1 } enum color: u16 { red = 1 }
+                struct point { x: i32, c: color? }
+                struct shape { @len(max = 8) name: string, points: [point; 2], inner: point? }
+                struct node { @len(max = 2) next: [node] }
+                struct free { name: string }",
+            )
+            .unwrap();
+        let bound = |ident, wire| packed_size_upper_bound(&schema, ident, wire, Int64::Int);
+
+        assert_eq!(bound("point", WireFormat::Positional), Some(8));
+        assert_eq!(bound("point", WireFormat::Bitmap), Some(13));
+        // A map header, then `x` and `c` as fixstr keys
+        assert_eq!(bound("point", WireFormat::Keyed), Some(13));
+        // 2 + 32 for the name, 1 + 16 for the points and 1 + 8 for the inner point
+        assert_eq!(bound("shape", WireFormat::Positional), Some(60));
+        assert_eq!(bound("node", WireFormat::Positional), None);
+        assert_eq!(bound("free", WireFormat::Positional), None);
+    }
+}
@@ -0,0 +1,414 @@
+//! Geno WebSocket generator.  Writes a `SocketMessage` type holding a message on any one of the
+//! topics, with a codec that tags each message with its topic, in `websocket.rs` for the `rust-mp`
+//! types and `websocket.dart` for the `dart-mp` types.  Received messages are dispatched to a
+//! handler with a method for each topic, so adding a topic is a compile error until it's handled.
+//!
+//! A message is a MessagePack array of the topic name and the payload as binary, so the payloads
+//! are the same bytes as the topic's `encode`.
+use crate::{
+    GenoError, ast,
+    codegen::{GeneratedFile, GeneratedFiles, Generator, Options, dart_mp, rust_serde, util::*},
+};
+
+/// Generator for the `websocket` format
+pub struct WebSocketGenerator;
+
+/// The payloads are encoded by the `rust-mp` and `dart-mp` types
+const CAPABILITIES: ast::Capabilities = ast::Capabilities {
+    float_map_keys: false,
+    ..ast::Capabilities::ALL
+};
+
+impl Generator for WebSocketGenerator {
+    fn name(&self) -> &str {
+        "websocket"
+    }
+
+    fn option_keys(&self) -> Option<&[&str]> {
+        Some(&[
+            "version",
+            "order",
+            "wire",
+            "type_case",
+            "field_case",
+            "variant_case",
+            "language",
+        ])
+    }
+
+    fn capabilities(&self) -> ast::Capabilities {
+        CAPABILITIES
+    }
+
+    fn generate(
+        &self,
+        schema: &ast::Schema,
+        options: &Options,
+    ) -> Result<GeneratedFiles, GenoError> {
+        schema.check_capabilities(&CAPABILITIES)?;
+
+        let (rust, dart) = match options.get("language") {
+            None => (true, true),
+            Some("rust") => (true, false),
+            Some("dart") => (false, true),
+            Some(value) => return Err(GenoError::InvalidOption(format!("language={value}"))),
+        };
+        let naming = |defaults| {
+            Naming::from_options(
+                options,
+                defaults,
+                &[
+                    NamingConvention::Pascal,
+                    NamingConvention::Camel,
+                    NamingConvention::Snake,
+                    NamingConvention::ScreamingSnake,
+                    NamingConvention::Preserve,
+                ],
+            )
+        };
+        let mut files = Vec::new();
+
+        if rust {
+            let schema = &*select_target(schema, options, Some("rust"))?;
+
+            files.push(GeneratedFile::new(
+                "websocket.rs",
+                rust_dispatcher(&topics(schema), &naming(rust_serde::DEFAULT_NAMING)?),
+            ));
+        }
+        if dart {
+            let schema = &*select_target(schema, options, Some("dart"))?;
+
+            files.push(GeneratedFile::new(
+                "websocket.dart",
+                dart_dispatcher(&topics(schema), &naming(dart_mp::DEFAULT_NAMING)?),
+            ));
+        }
+
+        Ok(files)
+    }
+}
+
+/// The topics and their payloads
+fn topics(schema: &ast::Schema) -> Vec<(&str, &str)> {
+    schema
+        .declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            ast::Declaration::Topic { ident, payload, .. } => {
+                Some((ident.as_str(), payload.as_str()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn rust_dispatcher(topics: &[(&str, &str)], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("    ");
+
+    w.line(
+        "// WebSocket messages for the rust-mp topics, generated by geno.  Include this file in the",
+    );
+    w.line("// module that holds them.");
+    if topics.is_empty() {
+        return w.into_string();
+    }
+
+    w.blank();
+    w.line("/// A message on one of the topics");
+    w.line("#[derive(Debug, Clone, PartialEq)]");
+    w.block("pub enum SocketMessage {", "}", |w| {
+        for (ident, payload) in topics {
+            w.line(format!("/// A message on the `{ident}` topic"));
+            w.line(format!(
+                "{}({}),",
+                naming.type_name(ident),
+                naming.type_name(payload)
+            ));
+        }
+    });
+
+    w.blank();
+    w.block("impl SocketMessage {", "}", |w| {
+        w.line("/// Name of the topic of the message");
+        w.block("pub fn topic(&self) -> &'static str {", "}", |w| {
+            w.block("match self {", "}", |w| {
+                for (ident, _) in topics {
+                    w.line(format!(
+                        "SocketMessage::{}(_) => {}.name,",
+                        naming.type_name(ident),
+                        to_screaming_snake_case(ident)
+                    ));
+                }
+            });
+        });
+        w.blank();
+        w.line("/// Encode as a MessagePack array of the topic name and the payload");
+        w.block("pub fn to_bytes(&self) -> Vec<u8> {", "}", |w| {
+            w.block("let payload = match self {", "};", |w| {
+                for (ident, _) in topics {
+                    w.line(format!(
+                        "SocketMessage::{}(message) => {}.encode(message),",
+                        naming.type_name(ident),
+                        to_screaming_snake_case(ident)
+                    ));
+                }
+            });
+            w.line("let mut wr = Vec::with_capacity(payload.len() + 32);");
+            w.blank();
+            w.line("mp::write_array_len(&mut wr, 2);");
+            w.line("mp::write_str(&mut wr, self.topic());");
+            w.line("mp::write_bin(&mut wr, &payload);");
+            w.line("wr");
+        });
+        w.blank();
+        w.line("/// Decode a message, failing for topics that aren't in the schema");
+        w.block(
+            "pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {",
+            "}",
+            |w| {
+                w.line("let mut rd = bytes;");
+                w.line("let len = mp::read_array_len(&mut rd)?;");
+                w.blank();
+                w.block("if len != 2 {", "}", |w| {
+                    w.line(
+                        "return Err(DecodeError(format!(\"message must have 2 elements, got {len}\")));",
+                    );
+                });
+                w.blank();
+                w.line("let topic = mp::read_str(&mut rd)?;");
+                w.line("let payload = mp::read_bin(&mut rd)?;");
+                w.blank();
+                w.block("match topic.as_str() {", "}", |w| {
+                    for (ident, _) in topics {
+                        w.line(format!(
+                            "{ident:?} => {}.decode(payload).map(SocketMessage::{}),",
+                            to_screaming_snake_case(ident),
+                            naming.type_name(ident)
+                        ));
+                    }
+                    w.line("_ => Err(DecodeError(format!(\"unknown topic '{topic}'\"))),");
+                });
+            },
+        );
+        w.blank();
+        w.line("/// Pass the message to the handler method of its topic");
+        w.block(
+            "pub fn dispatch<H: SocketHandler + ?Sized>(self, handler: &mut H) -> H::Output {",
+            "}",
+            |w| {
+                w.block("match self {", "}", |w| {
+                    for (ident, _) in topics {
+                        w.line(format!(
+                            "SocketMessage::{}(message) => handler.on_{}(message),",
+                            naming.type_name(ident),
+                            to_snake_case(ident)
+                        ));
+                    }
+                });
+            },
+        );
+    });
+
+    w.blank();
+    w.line("/// Handles the messages of each topic");
+    w.block("pub trait SocketHandler {", "}", |w| {
+        w.line("/// What handling a message gives");
+        w.line("type Output;");
+        for (ident, payload) in topics {
+            w.blank();
+            w.line(format!("/// Handle a message on the `{ident}` topic"));
+            w.line(format!(
+                "fn on_{}(&mut self, message: {}) -> Self::Output;",
+                to_snake_case(ident),
+                naming.type_name(payload)
+            ));
+        }
+    });
+
+    w.into_string()
+}
+
+fn dart_dispatcher(topics: &[(&str, &str)], naming: &Naming) -> String {
+    let mut w = CodeWriter::new("  ");
+
+    w.line("// WebSocket messages for the dart-mp topics, generated by geno");
+    if topics.is_empty() {
+        return w.into_string();
+    }
+    w.line("import 'dart:typed_data';");
+    w.blank();
+    w.line("import 'package:messagepack/messagepack.dart';");
+    w.blank();
+    w.line("import 'geno.dart';");
+
+    let topic_var =
+        |ident: &str| format!("{}Topic", to_ascii_identifier(&to_lower_camel_case(ident)));
+    let handler_method = |ident: &str| format!("on{}", to_ascii_identifier(&to_pascal_case(ident)));
+    let class_name = |ident: &str| format!("{}SocketMessage", naming.type_name(ident));
+
+    w.blank();
+    w.line("/// A message on one of the topics");
+    w.block("sealed class SocketMessage {", "}", |w| {
+        w.line("const SocketMessage();");
+        w.blank();
+        w.line("/// Name of the topic of the message");
+        w.line("String get topic;");
+        w.blank();
+        w.line("Uint8List get _payload;");
+        w.blank();
+        w.line("/// Pass the message to the handler method of its topic");
+        w.line("R dispatch<R>(SocketHandler<R> handler);");
+        w.blank();
+        w.line("/// Encode as a MessagePack list of the topic name and the payload");
+        w.block("Uint8List toBytes() {", "}", |w| {
+            w.line("final p = Packer();");
+            w.line("p.packListLength(2);");
+            w.line("p.packString(topic);");
+            w.line("p.packBinary(_payload);");
+            w.line("return p.takeBytes();");
+        });
+        w.blank();
+        w.line("/// Decode a message, failing for topics that aren't in the schema");
+        w.block(
+            "static SocketMessage fromBytes(Uint8List bytes) {",
+            "}",
+            |w| {
+                w.line("final u = Unpacker(bytes);");
+                w.line("final length = u.unpackListLength();");
+                w.block("if (length != 2) {", "}", |w| {
+                    w.line("throw FormatException('Message must have 2 elements, got $length');");
+                });
+                w.line("final topic = u.unpackString();");
+                w.line("final payload = Uint8List.fromList(u.unpackBinary());");
+                w.block("return switch (topic) {", "};", |w| {
+                    for (ident, _) in topics {
+                        w.line(format!(
+                            "'{ident}' => {}({}.decode(payload)),",
+                            class_name(ident),
+                            topic_var(ident)
+                        ));
+                    }
+                    w.line("_ => throw FormatException(\"Unknown topic '$topic'\"),");
+                });
+            },
+        );
+    });
+
+    for (ident, payload) in topics {
+        let dart_payload = naming.type_name(payload);
+
+        w.blank();
+        w.line(format!("/// A message on the `{ident}` topic"));
+        w.block(
+            &format!("final class {} extends SocketMessage {{", class_name(ident)),
+            "}",
+            |w| {
+                w.line(format!("final {dart_payload} message;"));
+                w.blank();
+                w.line(format!("const {}(this.message);", class_name(ident)));
+                w.blank();
+                w.line("@override");
+                w.line(format!("String get topic => {}.name;", topic_var(ident)));
+                w.blank();
+                w.line("@override");
+                w.line(format!(
+                    "Uint8List get _payload => {}.encode(message);",
+                    topic_var(ident)
+                ));
+                w.blank();
+                w.line("@override");
+                w.line(format!(
+                    "R dispatch<R>(SocketHandler<R> handler) => handler.{}(message);",
+                    handler_method(ident)
+                ));
+            },
+        );
+    }
+
+    w.blank();
+    w.line("/// Handles the messages of each topic");
+    w.block("abstract interface class SocketHandler<R> {", "}", |w| {
+        for (i, (ident, payload)) in topics.iter().enumerate() {
+            if i > 0 {
+                w.blank();
+            }
+            w.line(format!("/// Handle a message on the `{ident}` topic"));
+            w.line(format!(
+                "R {}({} message);",
+                handler_method(ident),
+                naming.type_name(payload)
+            ));
+        }
+    });
+
+    w.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = "meta { format = 1 }
+struct chat { text: string }
+struct presence { online: bool }
+topic chat_messages: chat;
+topic presence_updates: presence;
+";
+
+    fn generate(input: &str, options: &[&str]) -> Vec<String> {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(input)
+            .unwrap();
+
+        WebSocketGenerator
+            .generate(&schema, &Options::parse(options).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|file| String::from_utf8(file.contents).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rust() {
+        let output = &generate(SCHEMA, &["language=rust"])[0];
+
+        assert!(output.contains("pub enum SocketMessage {\n    /// A message on the `chat_messages` topic\n    ChatMessages(Chat),"));
+        assert!(output.contains("SocketMessage::PresenceUpdates(_) => PRESENCE_UPDATES.name,"));
+        assert!(output.contains(
+            "\"chat_messages\" => CHAT_MESSAGES.decode(payload).map(SocketMessage::ChatMessages),"
+        ));
+        assert!(output.contains(
+            "SocketMessage::ChatMessages(message) => handler.on_chat_messages(message),"
+        ));
+        assert!(
+            output
+                .contains("fn on_presence_updates(&mut self, message: Presence) -> Self::Output;")
+        );
+    }
+
+    #[test]
+    fn dart() {
+        let output = &generate(SCHEMA, &["language=dart"])[0];
+
+        assert!(output.contains("sealed class SocketMessage {"));
+        assert!(output.contains(
+            "'chat_messages' => ChatMessagesSocketMessage(chatMessagesTopic.decode(payload)),"
+        ));
+        assert!(
+            output.contains("final class PresenceUpdatesSocketMessage extends SocketMessage {")
+        );
+        assert!(output.contains(
+            "R dispatch<R>(SocketHandler<R> handler) => handler.onChatMessages(message);"
+        ));
+        assert!(output.contains("R onPresenceUpdates(Presence message);"));
+    }
+
+    #[test]
+    fn no_topics() {
+        let files = generate("meta { format = 1 }\nstruct a { b: i8 }", &[]);
+
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|file| !file.contains("SocketMessage")));
+    }
+}
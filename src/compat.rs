@@ -0,0 +1,434 @@
+//! Compatibility of schema changes.  Two versions of a schema are compared by what they put on
+//! the wire, and each difference is classed as a patch, minor or major change:
+//!
+//! - A patch changes nothing on the wire, such as a comment, an `@owner` or an interface
+//! - A minor change is additive, such as a new declaration, enum variant or, with the `keyed`
+//!   wire format, a new nullable field
+//! - A major change breaks readers or writers of the old schema, such as a removed declaration or
+//!   a changed field type
+//!
+//! A schema's `version` is either a single integer or a `"major.minor.patch"` string.  An integer
+//! version stays the same for a patch and goes up by one for any other change.  A semantic
+//! version bumps the component for the class of change, resetting those after it, and bumps the
+//! patch version for a patch that changes anything at all.  Each schema is compared as it is at
+//! its own `version`.
+pub use crate::ChangeClass;
+use crate::{
+    GenoError,
+    ast::{self, Declaration, SchemaVersion},
+    codegen::{
+        Options,
+        util::{WireFormat, is_nullable, schema_type_str, select_version},
+    },
+};
+use std::collections::HashMap;
+
+/// A difference on the wire between two versions of a schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// How much the change affects the wire
+    pub class: ChangeClass,
+    /// What changed, naming the declaration and any field or variant
+    pub description: String,
+}
+
+impl Change {
+    fn new(class: ChangeClass, description: String) -> Self {
+        Self { class, description }
+    }
+}
+
+/// The changes on the wire from `old` to `new`, in the order of the declarations
+pub fn changes(old: &ast::Schema, new: &ast::Schema) -> Result<Vec<Change>, GenoError> {
+    let old = select_version(old, &Options::new())?;
+    let new = select_version(new, &Options::new())?;
+    let old_wire = WireFormat::from_options(&old, &Options::new())?;
+    let new_wire = WireFormat::from_options(&new, &Options::new())?;
+    let mut changes = Vec::new();
+
+    if old_wire != new_wire {
+        changes.push(Change::new(
+            ChangeClass::Major,
+            format!("wire format changed from {old_wire:?} to {new_wire:?}").to_lowercase(),
+        ));
+    }
+
+    let old_decls: HashMap<&str, &Declaration> =
+        on_wire(&old).map(|decl| (ident(decl), decl)).collect();
+    let new_idents: Vec<&str> = on_wire(&new).map(ident).collect();
+
+    for decl in on_wire(&old) {
+        if !new_idents.contains(&ident(decl)) {
+            changes.push(Change::new(
+                ChangeClass::Major,
+                format!("'{}' removed", ident(decl)),
+            ));
+        }
+    }
+    for decl in on_wire(&new) {
+        match old_decls.get(ident(decl)) {
+            Some(old_decl) => compare(old_decl, decl, new_wire, &mut changes),
+            None => changes.push(Change::new(
+                ChangeClass::Minor,
+                format!("'{}' added", ident(decl)),
+            )),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// The most significant class of the changes, a patch if there are none
+pub fn class(changes: &[Change]) -> ChangeClass {
+    changes
+        .iter()
+        .map(|change| change.class)
+        .max()
+        .unwrap_or(ChangeClass::Patch)
+}
+
+/// The version a schema has, taking a schema without one to be version 1
+pub fn version(schema: &ast::Schema) -> SchemaVersion {
+    schema.schema_version().unwrap_or(SchemaVersion::Integer(1))
+}
+
+/// The version the new schema should have after these changes to the old one.  The new schema
+/// can move from an integer version to a semantic one, taking integer version `n` to be `n.0.0`.
+pub fn next_version(old: &ast::Schema, new: &ast::Schema, changes: &[Change]) -> SchemaVersion {
+    let version = match (version(old), version(new)) {
+        (SchemaVersion::Integer(major), SchemaVersion::Semantic(..)) => {
+            SchemaVersion::Semantic(major, 0, 0)
+        }
+        (version, _) => version,
+    };
+
+    match (version, class(changes)) {
+        (SchemaVersion::Integer(version), ChangeClass::Patch) => SchemaVersion::Integer(version),
+        (SchemaVersion::Integer(version), _) => SchemaVersion::Integer(version + 1),
+        (SchemaVersion::Semantic(major, minor, patch), ChangeClass::Patch) => {
+            if unchanged(old, new) {
+                SchemaVersion::Semantic(major, minor, patch)
+            } else {
+                SchemaVersion::Semantic(major, minor, patch + 1)
+            }
+        }
+        (SchemaVersion::Semantic(major, minor, _), ChangeClass::Minor) => {
+            SchemaVersion::Semantic(major, minor + 1, 0)
+        }
+        (SchemaVersion::Semantic(major, _, _), ChangeClass::Major) => {
+            SchemaVersion::Semantic(major + 1, 0, 0)
+        }
+    }
+}
+
+/// Check that the new schema's version is the one its changes call for, returning the changes
+pub fn check_version(old: &ast::Schema, new: &ast::Schema) -> Result<Vec<Change>, GenoError> {
+    let changes = changes(old, new)?;
+    let expected = next_version(old, new, &changes);
+    let found = version(new);
+
+    if found != expected {
+        return Err(GenoError::VersionMismatch(class(&changes), found, expected));
+    }
+
+    Ok(changes)
+}
+
+/// Whether two schemas are the same apart from their versions
+fn unchanged(old: &ast::Schema, new: &ast::Schema) -> bool {
+    let metadata = |schema: &ast::Schema| {
+        let mut metadata = schema.metadata.clone();
+
+        metadata.remove("version");
+        metadata
+    };
+
+    old.declarations == new.declarations && metadata(old) == metadata(new)
+}
+
+/// The declarations of a schema that put something on the wire, so not interfaces
+fn on_wire(schema: &ast::Schema) -> impl Iterator<Item = &Declaration> {
+    schema
+        .declarations
+        .iter()
+        .filter(|decl| !matches!(decl, Declaration::Interface { .. }))
+}
+
+fn ident(decl: &Declaration) -> &str {
+    match decl {
+        Declaration::Enum { ident, .. }
+        | Declaration::Struct { ident, .. }
+        | Declaration::Interface { ident, .. }
+        | Declaration::Topic { ident, .. } => ident,
+    }
+}
+
+fn compare(old: &Declaration, new: &Declaration, wire: WireFormat, changes: &mut Vec<Change>) {
+    let mut push = |class, description| changes.push(Change::new(class, description));
+
+    match (old, new) {
+        (
+            Declaration::Enum {
+                ident,
+                base_type: old_base,
+                variants: old_variants,
+                ..
+            },
+            Declaration::Enum {
+                base_type: new_base,
+                variants: new_variants,
+                ..
+            },
+        ) => {
+            if old_base != new_base {
+                push(ChangeClass::Major, format!("'{ident}' base type changed"));
+            }
+            for (name, value) in old_variants {
+                match new_variants.iter().find(|(new_name, _)| new_name == name) {
+                    None => push(ChangeClass::Major, format!("'{ident}.{name}' removed")),
+                    Some((_, new_value)) if new_value != value => push(
+                        ChangeClass::Major,
+                        format!("'{ident}.{name}' value changed"),
+                    ),
+                    Some(_) => {}
+                }
+            }
+            for (name, _) in new_variants {
+                if !old_variants.iter().any(|(old_name, _)| old_name == name) {
+                    push(ChangeClass::Minor, format!("'{ident}.{name}' added"));
+                }
+            }
+        }
+        (
+            Declaration::Struct {
+                ident,
+                fields: old_fields,
+                annotations: old_annotations,
+                ..
+            },
+            Declaration::Struct {
+                fields: new_fields,
+                annotations: new_annotations,
+                ..
+            },
+        ) => {
+            // Only the keyed format finds fields by name, the others by position
+            let keyed = wire == WireFormat::Keyed;
+
+            for (name, ft) in old_fields {
+                match new_fields.iter().find(|(new_name, _)| new_name == name) {
+                    None if keyed && is_nullable(ft) => {
+                        push(ChangeClass::Minor, format!("'{ident}.{name}' removed"))
+                    }
+                    None => push(ChangeClass::Major, format!("'{ident}.{name}' removed")),
+                    Some((_, new_ft)) if new_ft != ft => push(
+                        ChangeClass::Major,
+                        format!(
+                            "'{ident}.{name}' type changed from {} to {}",
+                            schema_type_str(ft),
+                            schema_type_str(new_ft)
+                        ),
+                    ),
+                    Some(_)
+                        if encoding(old_annotations, name) != encoding(new_annotations, name) =>
+                    {
+                        push(
+                            ChangeClass::Major,
+                            format!("'{ident}.{name}' encoding changed"),
+                        )
+                    }
+                    Some(_) => {}
+                }
+            }
+            for (name, ft) in new_fields {
+                if !old_fields.iter().any(|(old_name, _)| old_name == name) {
+                    let class = if keyed && is_nullable(ft) {
+                        ChangeClass::Minor
+                    } else {
+                        ChangeClass::Major
+                    };
+
+                    push(class, format!("'{ident}.{name}' added"));
+                }
+            }
+
+            let order = |fields: &[(String, ast::FieldType)],
+                         others: &[(String, ast::FieldType)]| {
+                fields
+                    .iter()
+                    .filter(|(name, _)| others.iter().any(|(other, _)| other == name))
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>()
+            };
+
+            if !keyed && order(old_fields, new_fields) != order(new_fields, old_fields) {
+                push(ChangeClass::Major, format!("'{ident}' fields reordered"));
+            }
+        }
+        (
+            Declaration::Topic {
+                ident,
+                payload: old_payload,
+                ..
+            },
+            Declaration::Topic {
+                payload: new_payload,
+                ..
+            },
+        ) => {
+            if old_payload != new_payload {
+                push(
+                    ChangeClass::Major,
+                    format!("'{ident}' payload changed from '{old_payload}' to '{new_payload}'"),
+                );
+            }
+        }
+        _ => push(ChangeClass::Major, format!("'{}' kind changed", ident(old))),
+    }
+}
+
+/// The annotations of a field that change how its value is written
+fn encoding<'a>(annotations: &'a ast::Annotations, field: &str) -> Vec<&'a ast::Annotation> {
+    annotations
+        .member(field)
+        .iter()
+        .filter(|annotation| matches!(annotation.name.as_str(), "int64" | "flatten"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(source: &str) -> ast::Schema {
+        crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str(source)
+            .unwrap()
+    }
+
+    fn described(changes: &[Change]) -> Vec<(ChangeClass, &str)> {
+        changes
+            .iter()
+            .map(|change| (change.class, change.description.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn change_classes() {
+        let old = schema(
+            "meta { format = 1, version = 2 } enum kind: u8 { a = 1, b = 2 }
+            struct item { id: u64, note: string? } struct gone { x: u8 }",
+        );
+        let new = schema(
+            "meta { format = 1, version = 3 } enum kind: u8 { a = 1, c = 3 }
+            // Items
+            @owner(\"team\") struct item { id: u32, note: string?, tag: string? }
+            struct extra { y: u8 }",
+        );
+        let found = changes(&old, &new).unwrap();
+
+        assert_eq!(
+            described(&found),
+            [
+                (ChangeClass::Major, "'gone' removed"),
+                (ChangeClass::Major, "'kind.b' removed"),
+                (ChangeClass::Minor, "'kind.c' added"),
+                (ChangeClass::Major, "'item.id' type changed from u64 to u32"),
+                (ChangeClass::Major, "'item.tag' added"),
+                (ChangeClass::Minor, "'extra' added"),
+            ]
+        );
+        assert_eq!(class(&found), ChangeClass::Major);
+        assert_eq!(next_version(&old, &new, &found), SchemaVersion::Integer(3));
+        assert!(check_version(&old, &new).is_ok());
+
+        // Comments and owners don't change the wire
+        let patched = schema(
+            "meta { format = 1, version = 2 } enum kind: u8 { a = 1, b = 2 }
+            /// Items
+            @owner(\"team\") struct item { id: u64, note: string? } struct gone { x: u8 }",
+        );
+
+        assert!(changes(&old, &patched).unwrap().is_empty());
+        assert!(check_version(&old, &patched).is_ok());
+    }
+
+    #[test]
+    fn keyed_fields() {
+        let old =
+            schema("meta { format = 1, wire = \"keyed\" } struct item { id: u64, note: string? }");
+        let new = schema(
+            "meta { format = 1, version = 2, wire = \"keyed\" } struct item { tag: string?, id: u64 }",
+        );
+
+        assert_eq!(
+            described(&changes(&old, &new).unwrap()),
+            [
+                (ChangeClass::Minor, "'item.note' removed"),
+                (ChangeClass::Minor, "'item.tag' added"),
+            ]
+        );
+        assert!(check_version(&old, &new).is_ok());
+        assert!(matches!(
+            check_version(&old, &old.clone()),
+            Ok(changes) if changes.is_empty()
+        ));
+
+        let unbumped = schema(
+            "meta { format = 1, wire = \"keyed\" } struct item { id: u64, note: string?, n: u8 }",
+        );
+
+        assert!(matches!(
+            check_version(&old, &unbumped),
+            Err(GenoError::VersionMismatch(
+                ChangeClass::Major,
+                SchemaVersion::Integer(1),
+                SchemaVersion::Integer(2)
+            ))
+        ));
+    }
+
+    #[test]
+    fn semantic_versions() {
+        let at = |version: &str, body: &str| {
+            schema(&format!(
+                "meta {{ format = 1, version = \"{version}\", wire = \"keyed\" }} {body}"
+            ))
+        };
+        let old = at("1.4.2", "struct item { id: u64 }");
+        let expected = |new: &ast::Schema| next_version(&old, new, &changes(&old, new).unwrap());
+
+        assert_eq!(old.version(), Some(1));
+        assert_eq!(expected(&old), SchemaVersion::Semantic(1, 4, 2));
+        // An owner changes nothing on the wire, but is still a patch
+        assert_eq!(
+            expected(&at("1.4.2", "@owner(\"team\") struct item { id: u64 }")),
+            SchemaVersion::Semantic(1, 4, 3)
+        );
+        assert_eq!(
+            expected(&at("1.4.2", "struct item { id: u64, tag: string? }")),
+            SchemaVersion::Semantic(1, 5, 0)
+        );
+        assert_eq!(
+            expected(&at("1.4.2", "struct item { id: u32 }")),
+            SchemaVersion::Semantic(2, 0, 0)
+        );
+        assert!(check_version(&old, &at("1.5.0", "struct item { id: u64, n: u8? }")).is_ok());
+        assert!(matches!(
+            check_version(&old, &at("2.0.0", "struct item { id: u64, n: u8? }")),
+            Err(GenoError::VersionMismatch(ChangeClass::Minor, found, expected))
+                if found.to_string() == "2.0.0" && expected.to_string() == "1.5.0"
+        ));
+
+        // An integer version can move to a semantic one
+        let old = schema("meta { format = 1, version = 3 } struct item { id: u64 }");
+        let new = |version: &str, body: &str| {
+            schema(&format!(
+                "meta {{ format = 1, version = \"{version}\" }} {body}"
+            ))
+        };
+
+        assert!(check_version(&old, &new("3.0.0", "struct item { id: u64 }")).is_ok());
+        assert!(check_version(&old, &new("4.0.0", "struct item { id: u32 }")).is_ok());
+    }
+}
@@ -0,0 +1,86 @@
+//! Makefile dependency files.  A depfile names the files a run of `geno` generated and the files
+//! they were generated from, so that Make, Ninja and the Bazel and Buck rules built on them can
+//! tell when generated code is stale:
+//!
+//! ```text
+//! out/geno.rs: api.geno
+//! ```
+//!
+//! The inputs are the schema, every module it [imports](crate::imports), directly or through
+//! other modules, and, for template formats, the templates.  Targets and inputs are sorted, so
+//! the same run always writes the same depfile.
+use crate::GenoError;
+use std::{fs, path::Path, path::PathBuf};
+
+/// The depfile text making each of `targets` depend on all of `inputs`
+pub fn depfile(targets: &[PathBuf], inputs: &[PathBuf]) -> String {
+    let mut targets: Vec<String> = targets.iter().map(|path| escape(path)).collect();
+    let mut inputs: Vec<String> = inputs.iter().map(|path| escape(path)).collect();
+
+    targets.sort();
+    targets.dedup();
+    inputs.sort();
+    inputs.dedup();
+
+    targets
+        .iter()
+        .map(|target| {
+            let mut rule = format!("{target}:");
+
+            for input in &inputs {
+                rule.push_str(" \\\n  ");
+                rule.push_str(input);
+            }
+            rule.push('\n');
+            rule
+        })
+        .collect()
+}
+
+/// Write a depfile to `path`
+pub fn write(path: &Path, targets: &[PathBuf], inputs: &[PathBuf]) -> Result<(), GenoError> {
+    Ok(fs::write(path, depfile(targets, inputs))?)
+}
+
+/// A path with the characters Make treats specially escaped
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules() {
+        let targets = [
+            PathBuf::from("out/b.dart"),
+            PathBuf::from("out/a file.rs"),
+            PathBuf::from("out/b.dart"),
+        ];
+        let inputs = [
+            PathBuf::from("templates/x.tera"),
+            PathBuf::from("api$#.geno"),
+        ];
+
+        assert_eq!(
+            depfile(&targets, &inputs),
+            "out/a\\ file.rs: \\\n  api$$\\#.geno \\\n  templates/x.tera\n\
+             out/b.dart: \\\n  api$$\\#.geno \\\n  templates/x.tera\n"
+        );
+        assert_eq!(depfile(&[], &inputs), "");
+    }
+}
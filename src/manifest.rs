@@ -0,0 +1,323 @@
+//! Manifests of generated files.  A `geno-manifest.json` in an output directory lists each file
+//! generated into it, along with the generator that wrote it, the schema it came from, the
+//! [fingerprint](crate::ast::Schema::fingerprint) of that schema and a SHA-256 of the file's
+//! contents.  Build systems can use it to tell which files are stale, and to find files that are
+//! no longer generated.
+//!
+//! Several generators and schemas can share an output directory.  Recording the files from a run
+//! replaces the entries from the previous run of the same generator on the same schema, and
+//! leaves the others alone.
+//!
+//! [clean] removes the files listed in a manifest.  Files that have been changed since they were
+//! generated are left in place unless forced, so hand edits aren't lost.
+use crate::{GenoError, codegen::GeneratedFile};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// File name of the manifest within an output directory
+pub const MANIFEST_FILE: &str = "geno-manifest.json";
+
+/// The generated files in an output directory
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Every generated file, sorted by path
+    pub files: Vec<ManifestEntry>,
+}
+
+/// One generated file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the file relative to the manifest, with `/` separators
+    pub path: String,
+    /// Generator that wrote the file
+    pub target: String,
+    /// Path of the schema the file was generated from
+    pub schema: String,
+    /// Fingerprint of the schema
+    pub schema_hash: String,
+    /// SHA-256 of the file's contents, as lowercase hex
+    pub content_hash: String,
+}
+
+impl Manifest {
+    /// Read the manifest in `dir`, or an empty manifest if there isn't one
+    pub fn read(dir: &Path) -> Result<Self, GenoError> {
+        let path = dir.join(MANIFEST_FILE);
+
+        match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|err| {
+                GenoError::InvalidManifest(path.to_string_lossy().into_owned(), err.to_string())
+            }),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Write the manifest into `dir`
+    pub fn write(&self, dir: &Path) -> Result<(), GenoError> {
+        let mut contents = serde_json::to_string_pretty(self).unwrap();
+
+        contents.push('\n');
+        fs::write(dir.join(MANIFEST_FILE), contents)?;
+
+        Ok(())
+    }
+
+    /// Replace the entries for `target` and `schema` with `files`, whose paths are relative to
+    /// the manifest
+    pub fn record(
+        &mut self,
+        target: &str,
+        schema: &str,
+        schema_hash: &str,
+        files: &[GeneratedFile],
+    ) {
+        self.replace(
+            target,
+            schema,
+            schema_hash,
+            files
+                .iter()
+                .map(|file| (file.path.as_path(), content_hash(&file.contents))),
+        );
+    }
+
+    /// Replace the entries for `target` and `schema` with the files at `paths`, which have
+    /// already been written into `dir`, the directory of the manifest.  The files are hashed as
+    /// they are on disk.
+    pub fn record_written(
+        &mut self,
+        dir: &Path,
+        target: &str,
+        schema: &str,
+        schema_hash: &str,
+        paths: &[PathBuf],
+    ) -> Result<(), GenoError> {
+        let hashes = paths
+            .iter()
+            .map(|path| Ok((path.as_path(), file_hash(&dir.join(path))?)))
+            .collect::<Result<Vec<_>, GenoError>>()?;
+
+        self.replace(target, schema, schema_hash, hashes);
+
+        Ok(())
+    }
+
+    fn replace<'a>(
+        &mut self,
+        target: &str,
+        schema: &str,
+        schema_hash: &str,
+        files: impl IntoIterator<Item = (&'a Path, String)>,
+    ) {
+        self.files
+            .retain(|entry| entry.target != target || entry.schema != schema);
+        self.files
+            .extend(files.into_iter().map(|(path, content_hash)| {
+                ManifestEntry {
+                    path: path
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    target: target.to_string(),
+                    schema: schema.to_string(),
+                    schema_hash: schema_hash.to_string(),
+                    content_hash,
+                }
+            }));
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+}
+
+/// The outcome of cleaning a directory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cleaned {
+    /// Files that were removed
+    pub removed: Vec<PathBuf>,
+    /// Files that were left in place because they changed after they were generated
+    pub modified: Vec<PathBuf>,
+}
+
+/// Remove the generated files in `dir` whose manifest entries match `filter`, then drop them from
+/// the manifest.  Files that have changed since they were generated are kept unless `force` is
+/// set.  Directories emptied by removing files are removed too, and so is the manifest once it
+/// lists no files.
+pub fn clean(
+    dir: &Path,
+    filter: impl Fn(&ManifestEntry) -> bool,
+    force: bool,
+) -> Result<Cleaned, GenoError> {
+    let mut manifest = Manifest::read(dir)?;
+    let mut cleaned = Cleaned::default();
+    let mut kept = Vec::new();
+
+    for entry in manifest.files {
+        if !filter(&entry) {
+            kept.push(entry);
+            continue;
+        }
+
+        let path = dir.join(&entry.path);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        if !force && content_hash(&contents) != entry.content_hash {
+            cleaned.modified.push(path);
+            kept.push(entry);
+            continue;
+        }
+
+        fs::remove_file(&path)?;
+
+        // Stop at the first directory that still has something in it
+        for parent in path.ancestors().skip(1).take_while(|parent| *parent != dir) {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+        cleaned.removed.push(path);
+    }
+
+    manifest.files = kept;
+    if manifest.files.is_empty() {
+        match fs::remove_file(dir.join(MANIFEST_FILE)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    } else {
+        manifest.write(dir)?;
+    }
+
+    Ok(cleaned)
+}
+
+/// SHA-256 of some file contents, as lowercase hex
+pub fn content_hash(contents: &[u8]) -> String {
+    hex(&Sha256::digest(contents))
+}
+
+/// SHA-256 of the contents of the file at `path`, read a piece at a time
+pub fn file_hash(path: &Path) -> Result<String, GenoError> {
+    let mut hasher = Sha256::new();
+
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn record_and_read() {
+        let dir = TempDir::new().unwrap();
+        let mut manifest = Manifest::read(dir.path()).unwrap();
+
+        assert!(manifest.files.is_empty());
+
+        manifest.record(
+            "rust-serde",
+            "a.geno",
+            "1234",
+            &[GeneratedFile::new("src/geno.rs", "x")],
+        );
+        manifest.record(
+            "dart-mp",
+            "a.geno",
+            "1234",
+            &[GeneratedFile::new("geno.dart", "")],
+        );
+        manifest.record(
+            "rust-serde",
+            "a.geno",
+            "5678",
+            &[GeneratedFile::new("geno.rs", "y")],
+        );
+        manifest.write(dir.path()).unwrap();
+
+        let mut manifest = Manifest::read(dir.path()).unwrap();
+
+        fs::write(dir.path().join("geno.rs"), "z").unwrap();
+        manifest
+            .record_written(dir.path(), "rust-mp", "a.geno", "1234", &["geno.rs".into()])
+            .unwrap();
+        assert_eq!(manifest.files[2].content_hash, content_hash(b"z"));
+        assert_eq!(manifest.files[2].target, "rust-mp");
+
+        let manifest = Manifest::read(dir.path()).unwrap();
+        let paths: Vec<&str> = manifest
+            .files
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+
+        assert_eq!(paths, ["geno.dart", "geno.rs"]);
+        assert_eq!(manifest.files[1].schema_hash, "5678");
+        assert_eq!(manifest.files[1].content_hash, content_hash(b"y"));
+        assert_eq!(
+            manifest.files[0].content_hash,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        fs::write(dir.path().join(MANIFEST_FILE), "{").unwrap();
+        assert!(matches!(
+            Manifest::read(dir.path()),
+            Err(GenoError::InvalidManifest(..))
+        ));
+    }
+
+    #[test]
+    fn clean_files() {
+        let dir = TempDir::new().unwrap();
+        let files = [
+            GeneratedFile::new("lib/geno.dart", "a"),
+            GeneratedFile::new("lib/edited.dart", "b"),
+            GeneratedFile::new("gone.dart", "c"),
+        ];
+        let mut manifest = Manifest::default();
+
+        for file in &files {
+            fs::create_dir_all(dir.path().join(&file.path).parent().unwrap()).unwrap();
+            fs::write(dir.path().join(&file.path), &file.contents).unwrap();
+        }
+        fs::remove_file(dir.path().join("gone.dart")).unwrap();
+        fs::write(dir.path().join("lib/edited.dart"), "hand edited").unwrap();
+        manifest.record("dart-mp", "a.geno", "1234", &files);
+        manifest.record(
+            "rust-serde",
+            "a.geno",
+            "1234",
+            &[GeneratedFile::new("geno.rs", "d")],
+        );
+        fs::write(dir.path().join("geno.rs"), "d").unwrap();
+        manifest.write(dir.path()).unwrap();
+
+        let cleaned = clean(dir.path(), |entry| entry.target == "dart-mp", false).unwrap();
+
+        assert_eq!(cleaned.removed, [dir.path().join("lib/geno.dart")]);
+        assert_eq!(cleaned.modified, [dir.path().join("lib/edited.dart")]);
+        assert!(dir.path().join("geno.rs").exists());
+        assert_eq!(Manifest::read(dir.path()).unwrap().files.len(), 2);
+
+        let cleaned = clean(dir.path(), |_| true, true).unwrap();
+
+        assert_eq!(cleaned.removed.len(), 2);
+        assert!(!dir.path().join("lib").exists());
+        assert!(!dir.path().join(MANIFEST_FILE).exists());
+    }
+}
@@ -0,0 +1,415 @@
+//! Schema packages.  A package is a git repository of `.geno` files, added to a project with
+//! [add] under a namespace, so that its schemas can be [imported](crate::imports) by that
+//! namespace.  Its schemas are copied into `geno_modules/<namespace>`, keeping their paths within
+//! the repository, and the package is recorded in `geno-lock.json` in the project's root, along
+//! with the commit the version resolved to and a SHA-256 of the schemas.
+//!
+//! Packages are fetched with `git`, so any repository `git clone` can reach works, with the
+//! credentials git already has.  A package given without a scheme, such as
+//! `github.com/org/common-schemas`, is fetched over HTTPS.
+//!
+//! [vendor] fetches every locked package again at its commit, and [verify] checks the packages
+//! in `geno_modules` against the lockfile, so a package changed upstream or by hand is caught
+//! before any code is generated from it.  The packages a schema imports are also checked as it is
+//! built, by every command.
+pub use crate::lockfile::{LOCK_FILE, LockedPackage, Lockfile, schemas_hash};
+use crate::{
+    GenoAstBuilder, GenoError,
+    imports::{MODULES_DIR, is_identifier},
+    lockfile::{schema_files, verify_package},
+};
+use duct::cmd;
+use std::{fs, io::ErrorKind, path::Path};
+
+/// A package to add, written as `<source>` or `<source>@<version>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageSpec {
+    /// Repository of the package, as a URL, a local path or a host and path
+    pub source: String,
+    /// Tag or branch to fetch, or the repository's default branch if `None`
+    pub version: Option<String>,
+}
+
+impl PackageSpec {
+    /// Split a package into its source and version.  Only an `@` after the last `/` starts the
+    /// version, so `git@host:org/repo` is a source without one.
+    pub fn parse(s: &str) -> Result<Self, GenoError> {
+        let (source, version) = match s.rsplit_once('@') {
+            Some((source, version)) if !version.contains(['/', ':']) => {
+                (source, Some(version.to_string()))
+            }
+            _ => (s, None),
+        };
+
+        if source.is_empty() || version.as_deref() == Some("") {
+            return Err(GenoError::Package(format!("'{s}' isn't a valid package")));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            version,
+        })
+    }
+
+    /// The namespace the package is imported by unless another is given, from the last part of
+    /// its source, as in `common_schemas` for `github.com/org/common-schemas`
+    pub fn default_namespace(&self) -> String {
+        let name = self
+            .source
+            .trim_end_matches('/')
+            .rsplit(['/', ':', '\\'])
+            .next()
+            .unwrap_or_default();
+
+        name.trim_end_matches(".git").replace(['-', '.'], "_")
+    }
+
+    /// What to pass to `git clone`
+    fn url(&self) -> String {
+        if self.source.contains(':') || Path::new(&self.source).exists() {
+            self.source.clone()
+        } else {
+            format!("https://{}", self.source)
+        }
+    }
+}
+
+/// Fetch the package `spec` into the `geno_modules` of the project in `dir` under `namespace`,
+/// or its [default namespace](PackageSpec::default_namespace), replacing any package already
+/// there, and record it in the lockfile.  Each of the package's schemas must build, so a package
+/// is never added that can't be imported.  Returns the namespace and the lockfile entry.
+pub fn add(
+    dir: &Path,
+    spec: &PackageSpec,
+    namespace: Option<&str>,
+) -> Result<(String, LockedPackage), GenoError> {
+    let namespace = namespace
+        .map(String::from)
+        .unwrap_or_else(|| spec.default_namespace());
+
+    if !is_identifier(&namespace) {
+        return Err(GenoError::Package(format!(
+            "'{namespace}' isn't a valid namespace, give one with --as"
+        )));
+    }
+
+    let checkout = tempfile::tempdir()?;
+    let mut clone_args = vec!["clone", "--quiet", "--depth", "1"];
+
+    if let Some(version) = &spec.version {
+        clone_args.extend(["--branch", version]);
+    }
+
+    let url = spec.url();
+    let checkout_path = checkout.path().to_string_lossy();
+
+    clone_args.extend(["--", &url, &checkout_path]);
+    git(&clone_args)?;
+
+    let commit = git(&["-C", &checkout_path, "rev-parse", "HEAD"])?;
+    let package_dir = dir.join(MODULES_DIR).join(&namespace);
+
+    install(checkout.path(), &package_dir)?;
+
+    let locked = LockedPackage {
+        source: spec.source.clone(),
+        version: spec.version.clone(),
+        commit,
+        sha256: schemas_hash(&package_dir)?,
+    };
+    let mut lockfile = Lockfile::read(dir)?;
+
+    lockfile.packages.insert(namespace.clone(), locked.clone());
+    lockfile.write(dir)?;
+
+    Ok((namespace, locked))
+}
+
+/// Fetch every package in the lockfile of the project in `dir` into its `geno_modules` at the
+/// commit it was locked at, and remove any package that isn't in the lockfile.  A package whose
+/// schemas no longer have their locked hash, because its history was rewritten, is an error and
+/// is left as it was.  Returns the namespaces of the packages.
+pub fn vendor(dir: &Path) -> Result<Vec<String>, GenoError> {
+    let lockfile = Lockfile::read(dir)?;
+    let modules = dir.join(MODULES_DIR);
+
+    for (namespace, locked) in &lockfile.packages {
+        let checkout = tempfile::tempdir()?;
+        let checkout_path = checkout.path().to_string_lossy();
+        let url = PackageSpec {
+            source: locked.source.clone(),
+            version: None,
+        }
+        .url();
+
+        git(&[
+            "clone",
+            "--quiet",
+            "--no-checkout",
+            "--",
+            &url,
+            &checkout_path,
+        ])?;
+        git(&["-C", &checkout_path, "checkout", "--quiet", &locked.commit])?;
+        if schemas_hash(checkout.path())? != locked.sha256 {
+            return Err(GenoError::PackageMismatch(namespace.clone()));
+        }
+        install(checkout.path(), &modules.join(namespace))?;
+    }
+
+    for name in installed(&modules)? {
+        if !lockfile.packages.contains_key(&name) {
+            fs::remove_dir_all(modules.join(name))?;
+        }
+    }
+
+    Ok(lockfile.packages.into_keys().collect())
+}
+
+/// Check the packages in the `geno_modules` of the project in `dir` against its lockfile.  Each
+/// locked package must be there with its locked hash, and no other package may be.  A project
+/// without a lockfile has nothing to check.
+pub fn verify(dir: &Path) -> Result<(), GenoError> {
+    if !dir.join(LOCK_FILE).exists() {
+        return Ok(());
+    }
+
+    let lockfile = Lockfile::read(dir)?;
+    let modules = dir.join(MODULES_DIR);
+
+    for namespace in lockfile.packages.keys() {
+        verify_package(&modules, namespace)?;
+    }
+    if let Some(name) = installed(&modules)?
+        .into_iter()
+        .find(|name| !lockfile.packages.contains_key(name))
+    {
+        return Err(GenoError::Package(format!(
+            "'{name}' in {MODULES_DIR} isn't in {LOCK_FILE}, add it with geno add"
+        )));
+    }
+
+    Ok(())
+}
+
+/// The namespaces of the packages in a `geno_modules` directory, which may not exist
+fn installed(modules: &Path) -> Result<Vec<String>, GenoError> {
+    let entries = match fs::read_dir(modules) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut names = Vec::new();
+
+    for entry in entries {
+        let path = entry?.path();
+
+        // Skip the staging directories of installs, which start with a `.`
+        if path.is_dir() && !path.file_name().unwrap().to_string_lossy().starts_with('.') {
+            names.push(path.file_name().unwrap().to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(names)
+}
+
+/// Run `git`, returning its trimmed output
+fn git(args: &[&str]) -> Result<String, GenoError> {
+    let output = cmd("git", args)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|err| GenoError::Package(format!("could not run git: {err}")))?;
+
+    if !output.status.success() {
+        return Err(GenoError::Package(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Replace `package_dir` with the schemas in the checkout, then check that each of them builds.
+/// The schemas are copied into a directory next to it first, and a package already there is moved
+/// aside rather than removed, so that it can be put back if one of them doesn't build.  They're
+/// built in place, as that's where they import each other from.
+fn install(checkout: &Path, package_dir: &Path) -> Result<(), GenoError> {
+    let schemas = schema_files(checkout)?;
+
+    if schemas.is_empty() {
+        return Err(GenoError::Package(
+            "the package has no .geno files".to_string(),
+        ));
+    }
+
+    let modules = package_dir.parent().unwrap();
+
+    fs::create_dir_all(modules)?;
+
+    // Hidden, so that it's never taken for a package, and removed when dropped
+    let staging = tempfile::Builder::new()
+        .prefix(".staging-")
+        .tempdir_in(modules)?;
+    let staged = staging.path().join("package");
+    let previous = staging.path().join("previous");
+
+    for path in &schemas {
+        let target = staged.join(path);
+
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::copy(checkout.join(path), target)?;
+    }
+    match fs::rename(package_dir, &previous) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+
+    let restore = || -> Result<(), GenoError> {
+        if previous.exists() {
+            fs::rename(&previous, package_dir)?;
+        }
+        Ok(())
+    };
+
+    if let Err(err) = fs::rename(&staged, package_dir) {
+        restore()?;
+        return Err(err.into());
+    }
+
+    let built = schemas.iter().try_for_each(|path| {
+        GenoAstBuilder::new(package_dir.join(path))
+            .verify_packages(false)
+            .build()
+            .map(drop)
+    });
+
+    if built.is_err() {
+        fs::remove_dir_all(package_dir)?;
+        restore()?;
+    }
+
+    built
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_specs() {
+        let spec = PackageSpec::parse("github.com/org/common-schemas@v2").unwrap();
+
+        assert_eq!(spec.source, "github.com/org/common-schemas");
+        assert_eq!(spec.version.as_deref(), Some("v2"));
+        assert_eq!(spec.default_namespace(), "common_schemas");
+        assert_eq!(spec.url(), "https://github.com/org/common-schemas");
+
+        let spec = PackageSpec::parse("git@github.com:org/ids.git").unwrap();
+
+        assert_eq!(spec.version, None);
+        assert_eq!(spec.default_namespace(), "ids");
+        assert_eq!(spec.url(), "git@github.com:org/ids.git");
+
+        assert!(PackageSpec::parse("github.com/org/ids@").is_err());
+        assert!(PackageSpec::parse("@v1").is_err());
+    }
+
+    #[test]
+    fn lockfile_entries() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // A namespace that isn't an identifier could reach outside `geno_modules`
+        fs::write(
+            dir.path().join(LOCK_FILE),
+            r#"{"packages": {"../..": {"source": "github.com/org/common", "commit": "0a1b2c", "sha256": ""}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(vendor(dir.path()), Err(GenoError::Package(_))));
+        assert!(matches!(verify(dir.path()), Err(GenoError::Package(_))));
+    }
+
+    #[test]
+    fn verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let package_dir = dir.path().join(MODULES_DIR).join("common");
+
+        // Nothing to check without a lockfile
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("a.geno"), "a").unwrap();
+        assert!(verify(dir.path()).is_ok());
+
+        let mut lockfile = Lockfile::default();
+
+        lockfile.packages.insert(
+            "common".to_string(),
+            LockedPackage {
+                source: "github.com/org/common".to_string(),
+                version: None,
+                commit: "0".repeat(40),
+                sha256: schemas_hash(&package_dir).unwrap(),
+            },
+        );
+        lockfile.write(dir.path()).unwrap();
+        assert!(verify(dir.path()).is_ok());
+
+        fs::write(package_dir.join("a.geno"), "changed").unwrap();
+        assert!(matches!(
+            verify(dir.path()),
+            Err(GenoError::PackageMismatch(namespace)) if namespace == "common"
+        ));
+
+        fs::write(package_dir.join("a.geno"), "a").unwrap();
+        fs::create_dir(dir.path().join(MODULES_DIR).join("extra")).unwrap();
+        assert!(matches!(verify(dir.path()), Err(GenoError::Package(_))));
+
+        fs::remove_dir(dir.path().join(MODULES_DIR).join("extra")).unwrap();
+        fs::remove_dir_all(&package_dir).unwrap();
+        assert!(matches!(verify(dir.path()), Err(GenoError::Package(_))));
+    }
+
+    #[test]
+    fn installs() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkout = dir.path().join("checkout");
+        let package_dir = dir.path().join(MODULES_DIR).join("common");
+        let money = "meta { format = 1 }\nstruct money { cents: i64 }\n";
+
+        fs::create_dir(&checkout).unwrap();
+        fs::write(checkout.join("money.geno"), money).unwrap();
+        install(&checkout, &package_dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(package_dir.join("money.geno")).unwrap(),
+            money
+        );
+
+        // A package that doesn't build leaves the one already installed as it was
+        fs::write(
+            checkout.join("money.geno"),
+            "meta { format = 1 }\nstruct money {",
+        )
+        .unwrap();
+        fs::write(
+            checkout.join("point.geno"),
+            "meta { format = 1 }\nstruct point { x: f64 }\n",
+        )
+        .unwrap();
+        assert!(install(&checkout, &package_dir).is_err());
+        assert_eq!(
+            fs::read_to_string(package_dir.join("money.geno")).unwrap(),
+            money
+        );
+        assert!(!package_dir.join("point.geno").exists());
+        assert_eq!(
+            installed(&dir.path().join(MODULES_DIR)).unwrap(),
+            ["common"]
+        );
+        assert_eq!(
+            fs::read_dir(dir.path().join(MODULES_DIR)).unwrap().count(),
+            1
+        );
+    }
+}
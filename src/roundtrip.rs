@@ -0,0 +1,272 @@
+//! Cross-language round trip testing.  Generates code for each target along with a small driver
+//! program, then passes the conformance test vectors through the targets in turn.  Each target
+//! decodes the bytes written by the one before it and encodes them again, and every result must
+//! match the original vectors byte for byte.
+//!
+//! Driver programs are built with the target's own toolchain, `cargo` for `rust-mp` and `dart`
+//! for `dart-mp`, which must be on the `PATH`.
+use crate::{
+    GenoError, ast,
+    codegen::{Generator, Options, Registry, dart_mp, rust_mp, testvectors::TestVectorsGenerator},
+};
+use duct::{Expression, cmd};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A target language that can build a program to decode and encode test vectors
+pub trait Driver {
+    /// Format name of the target's generator, e.g. `rust-mp`
+    fn format(&self) -> &str;
+
+    /// Write the generated code and driver program into `dir`
+    fn write(&self, schema: &ast::Schema, options: &Options, dir: &Path) -> Result<(), GenoError>;
+
+    /// Command that decodes each vector listed in `input/index.txt` and writes it encoded again
+    /// to `output`.  Failures are printed to stderr as `<file>: <message>` lines.
+    fn command(&self, dir: &Path, input: &Path, output: &Path) -> Expression;
+}
+
+/// Driver for the `rust-mp` generator, built with `cargo`
+pub struct RustMpDriver;
+
+impl Driver for RustMpDriver {
+    fn format(&self) -> &str {
+        "rust-mp"
+    }
+
+    fn write(&self, schema: &ast::Schema, options: &Options, dir: &Path) -> Result<(), GenoError> {
+        let src_dir = dir.join("src");
+
+        fs::create_dir_all(&src_dir)?;
+        // The empty workspace keeps cargo from looking for one in the parent directories
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"geno-driver\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+        )?;
+        for file in rust_mp::RustMpGenerator.generate(schema, options)? {
+            fs::write(src_dir.join(&file.path), &file.contents)?;
+        }
+        fs::write(
+            src_dir.join("main.rs"),
+            rust_mp::generate_driver(schema, options)?,
+        )?;
+
+        Ok(())
+    }
+
+    fn command(&self, dir: &Path, input: &Path, output: &Path) -> Expression {
+        cmd!(
+            "cargo",
+            "run",
+            "--quiet",
+            "--manifest-path",
+            dir.join("Cargo.toml"),
+            "--",
+            input,
+            output
+        )
+    }
+}
+
+/// Driver for the `dart-mp` generator, run with `dart`
+pub struct DartMpDriver;
+
+impl Driver for DartMpDriver {
+    fn format(&self) -> &str {
+        "dart-mp"
+    }
+
+    fn write(&self, schema: &ast::Schema, options: &Options, dir: &Path) -> Result<(), GenoError> {
+        let lib_dir = dir.join("lib");
+        let bin_dir = dir.join("bin");
+
+        fs::create_dir_all(&lib_dir)?;
+        fs::create_dir_all(&bin_dir)?;
+        fs::write(
+            dir.join("pubspec.yaml"),
+            "name: geno_driver\nenvironment:\n  sdk: '>=3.0.0 <4.0.0'\ndependencies:\n  messagepack: ^0.2.1\n",
+        )?;
+        for file in dart_mp::DartMpGenerator.generate(schema, options)? {
+            fs::write(lib_dir.join(&file.path), &file.contents)?;
+        }
+        fs::write(
+            bin_dir.join("driver.dart"),
+            dart_mp::generate_driver(schema, options)?,
+        )?;
+
+        Ok(())
+    }
+
+    fn command(&self, dir: &Path, input: &Path, output: &Path) -> Expression {
+        cmd!("dart", "run", "bin/driver.dart", input, output).dir(dir)
+    }
+}
+
+/// Find the driver for a generator format
+pub fn driver(format: &str) -> Option<Box<dyn Driver>> {
+    match format {
+        "rust-mp" => Some(Box::new(RustMpDriver)),
+        "dart-mp" => Some(Box::new(DartMpDriver)),
+        _ => None,
+    }
+}
+
+/// A vector that didn't survive a trip through one of the targets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    /// Format name of the target
+    pub target: String,
+    /// Test vector file name
+    pub file: String,
+    /// What went wrong
+    pub message: String,
+}
+
+/// The outcome of a round trip test
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Number of test vectors
+    pub vectors: usize,
+    /// Every failed vector, in target order
+    pub failures: Vec<Failure>,
+}
+
+/// Run a round trip test through `targets` in order, using `work_dir` for the generated code,
+/// test vectors and results
+pub fn run(
+    schema: &ast::Schema,
+    options: &Options,
+    targets: &[String],
+    work_dir: &Path,
+) -> Result<Report, GenoError> {
+    let drivers = targets
+        .iter()
+        .map(|target| driver(target).ok_or_else(|| GenoError::UnknownGenerator(target.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let registry = Registry::with_builtins();
+
+    options.check_keys(
+        std::iter::once(&TestVectorsGenerator as &dyn Generator).chain(
+            drivers
+                .iter()
+                .filter_map(|driver| registry.get(driver.format())),
+        ),
+    )?;
+
+    let vectors_dir = work_dir.join("vectors");
+    let mut index = String::new();
+    let mut vectors = Vec::new();
+
+    fs::create_dir_all(&vectors_dir)?;
+    for file in TestVectorsGenerator.generate(schema, options)? {
+        fs::write(vectors_dir.join(&file.path), &file.contents)?;
+        let name = file.path.to_string_lossy().into_owned();
+        // Files are `<type>.<vector>.msgpack`, and the types of imported packages have a `.` too
+        if let Some((type_name, _)) = name
+            .strip_suffix(".msgpack")
+            .and_then(|stem| stem.rsplit_once('.'))
+        {
+            index.push_str(&format!("{type_name} {name}\n"));
+            vectors.push((name, file.contents));
+        }
+    }
+    fs::write(vectors_dir.join("index.txt"), &index)?;
+
+    let mut report = Report {
+        vectors: vectors.len(),
+        failures: Vec::new(),
+    };
+    let mut input = vectors_dir.clone();
+
+    for (i, driver) in drivers.iter().enumerate() {
+        let target = driver.format().to_string();
+        let dir = work_dir.join(format!("{i}-{target}"));
+        let output: PathBuf = dir.join("output");
+
+        driver.write(schema, options, &dir)?;
+        fs::create_dir_all(&output)?;
+        fs::write(output.join("index.txt"), &index)?;
+
+        let result = driver
+            .command(&dir, &input, &output)
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(|err| GenoError::Driver(target.clone(), err.to_string()))?;
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let mut failed_files = Vec::new();
+
+        for line in stderr.lines() {
+            if let Some((file, message)) = line.split_once(": ")
+                && vectors.iter().any(|(name, _)| name == file)
+            {
+                failed_files.push(file.to_string());
+                report.failures.push(Failure {
+                    target: target.clone(),
+                    file: file.to_string(),
+                    message: message.to_string(),
+                });
+            }
+        }
+        if !result.status.success() && failed_files.is_empty() {
+            return Err(GenoError::Driver(target, stderr.trim().to_string()));
+        }
+
+        for (name, expected) in &vectors {
+            let path = output.join(name);
+
+            if failed_files.contains(name) {
+                // Give the next target the original vector to work with
+                fs::write(&path, expected)?;
+                continue;
+            }
+            match fs::read(&path) {
+                Ok(actual) if actual == *expected => continue,
+                Ok(actual) => report.failures.push(Failure {
+                    target: target.clone(),
+                    file: name.clone(),
+                    message: format!(
+                        "encoded {} bytes that differ from the {} expected",
+                        actual.len(),
+                        expected.len()
+                    ),
+                }),
+                Err(_) => report.failures.push(Failure {
+                    target: target.clone(),
+                    file: name.clone(),
+                    message: "no output was written".to_string(),
+                }),
+            }
+            fs::write(&path, expected)?;
+        }
+
+        input = output;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_target() {
+        let schema = crate::GenoAstBuilder::new("test.geno".into())
+            .build_from_str("meta { format = 1 } struct point { x: i32 }")
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let result = run(
+            &schema,
+            &Options::new(),
+            &["rust-mp".to_string(), "cobol".to_string()],
+            dir.path(),
+        );
+
+        assert!(matches!(result, Err(GenoError::UnknownGenerator(name)) if name == "cobol"));
+        assert!(driver("dart-mp").is_some_and(|driver| driver.format() == "dart-mp"));
+    }
+}
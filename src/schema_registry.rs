@@ -0,0 +1,245 @@
+//! Client for an HTTP schema registry.  Schemas are published under a subject, where each new
+//! schema gets the next version number and a registry wide id, so services can resolve a schema
+//! id found in a message at runtime.  The registry decides whether a new version is compatible
+//! with the ones before it, and a rejected schema is reported as an error.
+//!
+//! The API is the subset of the Confluent Schema Registry API needed to publish and fetch:
+//!
+//! - `POST /subjects/<subject>/versions` publishes a schema and returns its `id`
+//! - `GET /subjects/<subject>/versions/<version>` fetches a version, or the newest with `latest`
+//! - `GET /schemas/ids/<id>` fetches a schema by id
+//!
+//! Schemas are sent as the `.geno` source along with their
+//! [fingerprint](crate::ast::Schema::fingerprint).  In [RegistryMode::Geno] the fingerprint is a
+//! top level `fingerprint` field.  In [RegistryMode::Confluent] the schema has a `schemaType` of
+//! `GENO`, which needs a matching schema provider on the server, and the fingerprint is the
+//! `geno.fingerprint` metadata property.
+use crate::{GenoAstBuilder, GenoError};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use ureq::Agent;
+
+/// Metadata property holding the fingerprint in [RegistryMode::Confluent]
+const FINGERPRINT_PROPERTY: &str = "geno.fingerprint";
+
+/// Flavor of registry API to use
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RegistryMode {
+    /// Plain JSON with a top level fingerprint
+    #[default]
+    Geno,
+    /// Confluent Schema Registry request format
+    Confluent,
+}
+
+impl RegistryMode {
+    fn content_type(self) -> &'static str {
+        match self {
+            RegistryMode::Geno => "application/json",
+            RegistryMode::Confluent => "application/vnd.schemaregistry.v1+json",
+        }
+    }
+}
+
+/// Which schema to fetch
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaSelector {
+    /// A version of a subject, or the newest version if `None`
+    Subject(String, Option<u32>),
+    /// A registry wide schema id
+    Id(u64),
+}
+
+/// A schema as stored in the registry
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisteredSchema {
+    /// Registry wide schema id
+    pub id: u64,
+    /// Version within the subject, if known
+    pub version: Option<u32>,
+    /// The `.geno` source
+    pub source: String,
+    /// Fingerprint of the schema
+    pub fingerprint: String,
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    id: Option<u64>,
+    version: Option<u32>,
+    schema: Option<String>,
+    fingerprint: Option<String>,
+    metadata: Option<Value>,
+}
+
+/// A connection to a schema registry
+pub struct RegistryClient {
+    base_url: String,
+    mode: RegistryMode,
+    agent: Agent,
+}
+
+impl RegistryClient {
+    /// Create a client for the registry at `base_url`
+    pub fn new(base_url: &str, mode: RegistryMode) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            mode,
+            agent: Agent::config_builder()
+                .http_status_as_error(false)
+                .build()
+                .into(),
+        }
+    }
+
+    /// Publish a schema under `subject`, returning its id and version.  The source is parsed and
+    /// validated first, so only good schemas reach the registry.
+    pub fn publish(&self, subject: &str, source: &str) -> Result<RegisteredSchema, GenoError> {
+        let schema = GenoAstBuilder::new(PathBuf::from(subject)).build_from_str(source)?;
+        let fingerprint = schema.fingerprint();
+        let url = format!("{}/subjects/{}/versions", self.base_url, subject);
+        let response = self
+            .agent
+            .post(&url)
+            .header("Content-Type", self.mode.content_type())
+            .send_json(publish_body(self.mode, source, &fingerprint))
+            .map_err(|err| GenoError::Registry(format!("{url}: {err}")))?;
+        let published = read_response(&url, response)?;
+
+        Ok(RegisteredSchema {
+            id: published
+                .id
+                .ok_or_else(|| GenoError::Registry(format!("{url}: no schema id returned")))?,
+            version: published.version,
+            source: source.to_string(),
+            fingerprint,
+        })
+    }
+
+    /// Fetch a schema, checking that it parses and matches its fingerprint if the registry has one
+    pub fn fetch(&self, selector: &SchemaSelector) -> Result<RegisteredSchema, GenoError> {
+        let url = match selector {
+            SchemaSelector::Subject(subject, Some(version)) => {
+                format!("{}/subjects/{subject}/versions/{version}", self.base_url)
+            }
+            SchemaSelector::Subject(subject, None) => {
+                format!("{}/subjects/{subject}/versions/latest", self.base_url)
+            }
+            SchemaSelector::Id(id) => format!("{}/schemas/ids/{id}", self.base_url),
+        };
+        let response = self
+            .agent
+            .get(&url)
+            .header("Accept", self.mode.content_type())
+            .call()
+            .map_err(|err| GenoError::Registry(format!("{url}: {err}")))?;
+        let fetched = read_response(&url, response)?;
+        let source = fetched
+            .schema
+            .ok_or_else(|| GenoError::Registry(format!("{url}: no schema returned")))?;
+        let schema = GenoAstBuilder::new(PathBuf::from(&url)).build_from_str(&source)?;
+        let fingerprint = schema.fingerprint();
+        let expected = match self.mode {
+            RegistryMode::Geno => fetched.fingerprint,
+            RegistryMode::Confluent => fetched
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata["properties"][FINGERPRINT_PROPERTY].as_str())
+                .map(String::from),
+        };
+
+        if let Some(expected) = expected
+            && expected != fingerprint
+        {
+            return Err(GenoError::Incompatible(format!(
+                "{url}: fingerprint {fingerprint} doesn't match the registered {expected}"
+            )));
+        }
+
+        Ok(RegisteredSchema {
+            id: match selector {
+                SchemaSelector::Id(id) => *id,
+                _ => fetched
+                    .id
+                    .ok_or_else(|| GenoError::Registry(format!("{url}: no schema id returned")))?,
+            },
+            version: fetched.version,
+            source,
+            fingerprint,
+        })
+    }
+}
+
+/// The body of a publish request
+fn publish_body(mode: RegistryMode, source: &str, fingerprint: &str) -> Value {
+    match mode {
+        RegistryMode::Geno => json!({ "schema": source, "fingerprint": fingerprint }),
+        RegistryMode::Confluent => json!({
+            "schema": source,
+            "schemaType": "GENO",
+            "metadata": { "properties": { FINGERPRINT_PROPERTY: fingerprint } },
+        }),
+    }
+}
+
+/// Decode a response, turning error statuses into errors with the registry's message
+fn read_response(
+    url: &str,
+    mut response: ureq::http::Response<ureq::Body>,
+) -> Result<SchemaResponse, GenoError> {
+    let status = response.status();
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(|err| GenoError::Registry(format!("{url}: {err}")))?;
+
+    if !status.is_success() {
+        // Confluent style errors have a `message`, anything else is passed on as it is
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|value| value["message"].as_str().map(String::from))
+            .unwrap_or(body);
+
+        // Registries answer a schema that breaks their compatibility rules with a conflict
+        if status == ureq::http::StatusCode::CONFLICT {
+            return Err(GenoError::Incompatible(format!("{url}: {message}")));
+        }
+        return Err(GenoError::Registry(format!("{url}: {status}: {message}")));
+    }
+
+    serde_json::from_str(&body).map_err(|err| GenoError::Registry(format!("{url}: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_bodies() {
+        let body = publish_body(RegistryMode::Geno, "meta { format = 1 }", "abc");
+
+        assert_eq!(body["fingerprint"], "abc");
+        assert_eq!(body["schema"], "meta { format = 1 }");
+
+        let body = publish_body(RegistryMode::Confluent, "meta { format = 1 }", "abc");
+
+        assert_eq!(body["schemaType"], "GENO");
+        assert_eq!(body["metadata"]["properties"]["geno.fingerprint"], "abc");
+        assert!(body.get("fingerprint").is_none());
+    }
+
+    #[test]
+    fn unreachable_registry() {
+        let client = RegistryClient::new("http://127.0.0.1:1/", RegistryMode::Geno);
+
+        assert!(matches!(
+            client.fetch(&SchemaSelector::Id(1)),
+            Err(GenoError::Registry(_))
+        ));
+        assert!(matches!(
+            client.publish("shapes", "meta { format = 2 }"),
+            Err(GenoError::InvalidMetadataFormat())
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+//! Golden file snapshot testing for generators.  A snapshot directory holds `.geno` inputs, and
+//! the expected output of each generator for an input `<name>.geno` is kept under
+//! `<name>/<generator>/`, with the same relative paths the generator gives its files.
+//!
+//! Checking compares freshly generated files against the snapshots.  Blessing writes the
+//! generated files over the snapshots instead, and removes any that are no longer generated.
+//! Any [Generator] works, so plugin authors can snapshot test their own generators too.
+use crate::{
+    GenoAstBuilder, GenoError,
+    codegen::{Generator, Options},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A generated file that differs from its snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    /// The generator produced a file that has no snapshot
+    Missing(PathBuf),
+    /// The generated file differs from the snapshot
+    Changed(PathBuf),
+    /// The snapshot is for a file the generator no longer produces
+    Unexpected(PathBuf),
+}
+
+impl Mismatch {
+    /// Path of the snapshot file
+    pub fn path(&self) -> &Path {
+        match self {
+            Mismatch::Missing(path) | Mismatch::Changed(path) | Mismatch::Unexpected(path) => path,
+        }
+    }
+}
+
+/// The outcome of checking or blessing snapshots
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Number of generated files compared
+    pub files: usize,
+    /// Every file that differed, which have been fixed if the snapshots were blessed
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Check the output of `generator` for every `.geno` file in `dir` against its snapshots, or
+/// update the snapshots to match if `bless` is set
+pub fn check(
+    generator: &dyn Generator,
+    dir: &Path,
+    options: &Options,
+    bless: bool,
+) -> Result<Report, GenoError> {
+    let mut report = Report::default();
+
+    for input_path in inputs(dir)? {
+        let schema = GenoAstBuilder::new(input_path.clone()).build()?;
+        let snapshot_dir = input_path.with_extension("").join(generator.name());
+        let files = generator.generate(&schema, options)?;
+        let mut expected = Vec::new();
+
+        for file in &files {
+            let path = snapshot_dir.join(&file.path);
+
+            report.files += 1;
+            let mismatch = match fs::read(&path) {
+                Ok(contents) if contents == file.contents => None,
+                Ok(_) => Some(Mismatch::Changed(path.clone())),
+                Err(_) => Some(Mismatch::Missing(path.clone())),
+            };
+            if let Some(mismatch) = mismatch {
+                if bless {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&path, &file.contents)?;
+                }
+                report.mismatches.push(mismatch);
+            }
+            expected.push(path);
+        }
+
+        for path in snapshot_files(&snapshot_dir)? {
+            if !expected.contains(&path) {
+                if bless {
+                    fs::remove_file(&path)?;
+                }
+                report.mismatches.push(Mismatch::Unexpected(path));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// The `.geno` files in a directory, sorted by name
+fn inputs(dir: &Path) -> Result<Vec<PathBuf>, GenoError> {
+    let mut paths = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "geno") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Every file under a snapshot directory, which may not exist yet
+fn snapshot_files(dir: &Path) -> Result<Vec<PathBuf>, GenoError> {
+    let mut paths = Vec::new();
+
+    if !dir.is_dir() {
+        return Ok(paths);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            paths.extend(snapshot_files(&path)?);
+        } else {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::rust_serde::RustSerdeGenerator;
+
+    #[test]
+    fn check_and_bless() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_dir = dir.path().join("point/rust-serde");
+
+        fs::write(
+            dir.path().join("point.geno"),
+            "meta { format = 1 }\nstruct point { x: i32 }\n",
+        )
+        .unwrap();
+
+        let report = check(&RustSerdeGenerator, dir.path(), &Options::new(), false).unwrap();
+
+        assert_eq!(report.files, 1);
+        assert_eq!(
+            report.mismatches,
+            vec![Mismatch::Missing(snapshot_dir.join("geno.rs"))]
+        );
+        assert!(!snapshot_dir.exists());
+
+        fs::create_dir_all(&snapshot_dir).unwrap();
+        fs::write(snapshot_dir.join("geno.rs"), "stale").unwrap();
+        fs::write(snapshot_dir.join("old.rs"), "stale").unwrap();
+
+        let report = check(&RustSerdeGenerator, dir.path(), &Options::new(), true).unwrap();
+
+        assert_eq!(
+            report.mismatches,
+            vec![
+                Mismatch::Changed(snapshot_dir.join("geno.rs")),
+                Mismatch::Unexpected(snapshot_dir.join("old.rs"))
+            ]
+        );
+        assert!(!snapshot_dir.join("old.rs").exists());
+
+        let report = check(&RustSerdeGenerator, dir.path(), &Options::new(), false).unwrap();
+
+        assert!(report.mismatches.is_empty());
+    }
+}
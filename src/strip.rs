@@ -0,0 +1,395 @@
+//! Stripped schemas, for shipping to clients that shouldn't learn the names of types and fields,
+//! or for shorter keys with the `keyed` wire format.  [strip] renames every type to a `t` token
+//! and every field and variant to an `m` token, drops comments and the annotations that don't
+//! change the wire, and returns the [Renames] that map the tokens back.
+//!
+//! Tokens come from a hash of the name, so a name gets the same token in every version of a
+//! schema and in every schema, unless the hashes of two names in a schema collide.  A field has
+//! the same token in every struct and interface, so interfaces still match their structs.
+use crate::{
+    ast::{
+        Annotation, AnnotationValue, Annotations, Declaration, FieldType, MapKeyType,
+        MetadataValue, Schema, Visibility,
+    },
+    codegen::util::schema_type_str,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Metadata that a stripped schema keeps, as it changes the wire
+const KEPT_METADATA: [&str; 3] = ["format", "version", "wire"];
+
+/// Annotations that a stripped schema keeps, as they change the wire or which targets see a field
+const KEPT_ANNOTATIONS: [&str; 7] = [
+    "default", "exclude", "flatten", "int64", "only", "removed", "since",
+];
+
+/// The tokens a stripped schema uses in place of names
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Renames {
+    /// Tokens of enums, structs, interfaces and topics, by their names
+    pub types: BTreeMap<String, String>,
+    /// Tokens of fields and variants, by their names
+    pub members: BTreeMap<String, String>,
+}
+
+/// A schema with its names replaced by tokens, and without comments or annotations that don't
+/// change the wire
+pub fn strip(schema: &Schema) -> (Schema, Renames) {
+    let mut types = BTreeSet::new();
+    let mut members = BTreeSet::new();
+
+    for decl in &schema.declarations {
+        match decl {
+            Declaration::Enum {
+                ident, variants, ..
+            } => {
+                types.insert(ident.as_str());
+                members.extend(variants.iter().map(|(name, _)| name.as_str()));
+            }
+            Declaration::Struct { ident, fields, .. }
+            | Declaration::Interface { ident, fields, .. } => {
+                types.insert(ident.as_str());
+                members.extend(fields.iter().map(|(name, _)| name.as_str()));
+            }
+            Declaration::Topic { ident, .. } => {
+                types.insert(ident.as_str());
+            }
+        }
+    }
+
+    let renames = Renames {
+        types: tokens('t', types),
+        members: tokens('m', members),
+    };
+    let stripped = Schema {
+        metadata: schema
+            .metadata
+            .iter()
+            .filter(|(key, _)| KEPT_METADATA.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        declarations: schema
+            .declarations
+            .iter()
+            .map(|decl| renames.declaration(decl))
+            .collect(),
+    };
+
+    (stripped, renames)
+}
+
+/// A token for each name, a prefix and at least four digits of the hash of the name
+fn tokens(prefix: char, names: BTreeSet<&str>) -> BTreeMap<String, String> {
+    let mut used = BTreeSet::new();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let digest = Sha256::digest(name.as_bytes());
+            let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+            let token = (4..)
+                .map(|digits| format!("{prefix}{:0digits$}", hash % 10u64.pow(digits as u32)))
+                .find(|token| used.insert(token.clone()))
+                .unwrap();
+
+            (name.to_string(), token)
+        })
+        .collect()
+}
+
+impl Renames {
+    fn type_name(&self, name: &str) -> String {
+        self.types[name].clone()
+    }
+
+    fn member(&self, name: &str) -> String {
+        self.members[name].clone()
+    }
+
+    fn declaration(&self, decl: &Declaration) -> Declaration {
+        match decl {
+            Declaration::Enum {
+                ident,
+                base_type,
+                variants,
+                visibility,
+                annotations,
+            } => Declaration::Enum {
+                ident: self.type_name(ident),
+                base_type: base_type.clone(),
+                variants: variants
+                    .iter()
+                    .map(|(name, value)| (self.member(name), value.clone()))
+                    .collect(),
+                visibility: *visibility,
+                annotations: self.annotations(annotations),
+            },
+            Declaration::Struct {
+                ident,
+                fields,
+                implements,
+                visibility,
+                annotations,
+            } => Declaration::Struct {
+                ident: self.type_name(ident),
+                fields: self.fields(fields),
+                implements: implements.iter().map(|name| self.type_name(name)).collect(),
+                visibility: *visibility,
+                annotations: self.annotations(annotations),
+            },
+            Declaration::Interface {
+                ident,
+                fields,
+                visibility,
+                annotations,
+            } => Declaration::Interface {
+                ident: self.type_name(ident),
+                fields: self.fields(fields),
+                visibility: *visibility,
+                annotations: self.annotations(annotations),
+            },
+            Declaration::Topic {
+                ident,
+                payload,
+                annotations,
+            } => Declaration::Topic {
+                ident: self.type_name(ident),
+                payload: self.type_name(payload),
+                annotations: self.annotations(annotations),
+            },
+        }
+    }
+
+    fn fields(&self, fields: &[(String, FieldType)]) -> Vec<(String, FieldType)> {
+        fields
+            .iter()
+            .map(|(name, ft)| (self.member(name), self.field_type(ft)))
+            .collect()
+    }
+
+    fn field_type(&self, ft: &FieldType) -> FieldType {
+        match ft {
+            FieldType::Array(inner, length, nullable) => {
+                FieldType::Array(Box::new(self.field_type(inner)), *length, *nullable)
+            }
+            FieldType::Map(key_type, value_type, nullable) => FieldType::Map(
+                match key_type {
+                    MapKeyType::UserDefined(name) => MapKeyType::UserDefined(self.type_name(name)),
+                    MapKeyType::Builtin(_) => key_type.clone(),
+                },
+                Box::new(self.field_type(value_type)),
+                *nullable,
+            ),
+            FieldType::Builtin(..) => ft.clone(),
+            FieldType::UserDefined(name, nullable) => {
+                FieldType::UserDefined(self.type_name(name), *nullable)
+            }
+        }
+    }
+
+    fn annotations(&self, annotations: &Annotations) -> Annotations {
+        let kept = |list: &[Annotation]| -> Vec<Annotation> {
+            list.iter()
+                .filter(|annotation| KEPT_ANNOTATIONS.contains(&annotation.name.as_str()))
+                .cloned()
+                .collect()
+        };
+
+        Annotations {
+            declaration: kept(&annotations.declaration),
+            members: annotations
+                .members
+                .iter()
+                .map(|(name, list)| (self.member(name), kept(list)))
+                .filter(|(_, list)| !list.is_empty())
+                .collect(),
+            comments: Default::default(),
+        }
+    }
+}
+
+/// A schema as source, with its declarations in order and without comments
+pub fn source(schema: &Schema) -> String {
+    let metadata: BTreeMap<&String, &MetadataValue> = schema.metadata.iter().collect();
+    let entries: Vec<String> = metadata
+        .into_iter()
+        .map(|(key, value)| match value {
+            MetadataValue::String(s) => format!("{key} = {}", quoted(s)),
+            MetadataValue::Integer(n) => format!("{key} = {}", n.to_i128()),
+        })
+        .collect();
+    let mut source = format!("meta {{ {} }}\n", entries.join(", "));
+
+    for decl in &schema.declarations {
+        let (keyword, ident, visibility, annotations) = match decl {
+            Declaration::Enum {
+                ident,
+                visibility,
+                annotations,
+                ..
+            } => ("enum", ident, *visibility, annotations),
+            Declaration::Struct {
+                ident,
+                visibility,
+                annotations,
+                ..
+            } => ("struct", ident, *visibility, annotations),
+            Declaration::Interface {
+                ident,
+                visibility,
+                annotations,
+                ..
+            } => ("interface", ident, *visibility, annotations),
+            Declaration::Topic {
+                ident, annotations, ..
+            } => ("topic", ident, Visibility::Public, annotations),
+        };
+        let member = |name: &str| {
+            annotations
+                .members
+                .get(name)
+                .map_or(String::new(), |list| annotation_list(list))
+        };
+
+        source.push('\n');
+        source.push_str(&annotation_list(&annotations.declaration));
+        if visibility == Visibility::Internal {
+            source.push_str("internal ");
+        }
+        source.push_str(&format!("{keyword} {ident}"));
+
+        let lines: Vec<String> = match decl {
+            Declaration::Enum {
+                base_type,
+                variants,
+                ..
+            } => {
+                source.push_str(&format!(": {}", base_type.as_str()));
+                variants
+                    .iter()
+                    .map(|(name, value)| format!("{}{name} = {}", member(name), value.to_i128()))
+                    .collect()
+            }
+            Declaration::Struct {
+                fields, implements, ..
+            } => {
+                if !implements.is_empty() {
+                    source.push_str(&format!(": {}", implements.join(", ")));
+                }
+                field_lines(fields, member)
+            }
+            Declaration::Interface { fields, .. } => field_lines(fields, member),
+            Declaration::Topic { payload, .. } => {
+                source.push_str(&format!(": {payload};\n"));
+                continue;
+            }
+        };
+
+        source.push_str(" {\n");
+        for line in lines {
+            source.push_str(&format!("    {line},\n"));
+        }
+        source.push_str("}\n");
+    }
+
+    source
+}
+
+fn field_lines(fields: &[(String, FieldType)], member: impl Fn(&str) -> String) -> Vec<String> {
+    fields
+        .iter()
+        .map(|(name, ft)| format!("{}{name}: {}", member(name), schema_type_str(ft)))
+        .collect()
+}
+
+/// Annotations as source, each followed by a space
+fn annotation_list(annotations: &[Annotation]) -> String {
+    annotations
+        .iter()
+        .map(|annotation| {
+            let args: Vec<String> = annotation
+                .args
+                .iter()
+                .map(|arg| {
+                    let value = match &arg.value {
+                        AnnotationValue::Integer(n) => n.to_string(),
+                        AnnotationValue::String(s) => quoted(s),
+                        AnnotationValue::Identifier(ident) => ident.clone(),
+                    };
+
+                    match &arg.name {
+                        Some(name) => format!("{name} = {value}"),
+                        None => value,
+                    }
+                })
+                .collect();
+
+            if args.is_empty() {
+                format!("@{} ", annotation.name)
+            } else {
+                format!("@{}({}) ", annotation.name, args.join(", "))
+            }
+        })
+        .collect()
+}
+
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GenoAstBuilder;
+
+    #[test]
+    fn strip_names() {
+        let schema = GenoAstBuilder::new("test.geno".into())
+            .build_from_str(
+                r#"meta { format = 1, version = 2, wire = "keyed", require_docs = 0 }
+/// Kinds of users
+@owner("team") enum kind: u8 { @default unknown = 0, admin = 1 }
+interface named { name: string }
+struct user: named {
+    @json(name = "user-id") @int64(string) id: u64,
+    // Secret
+    @sensitive name: string,
+    @since(2) kinds: {kind: [kind]?},
+    @only(rust) note: string?,
+}
+topic users: user;
+internal struct audit { by: user }"#,
+            )
+            .unwrap();
+        let (stripped, renames) = strip(&schema);
+        let text = source(&stripped);
+        let kind = &renames.types["kind"];
+        let user = &renames.types["user"];
+        let name = &renames.members["name"];
+
+        assert_eq!(renames.types.len(), 5);
+        assert_eq!(renames.members.len(), 7);
+        assert!(kind.starts_with('t') && renames.members["id"].starts_with('m'));
+        assert!(!text.contains("user") && !text.contains("owner") && !text.contains("//"));
+        assert!(!text.contains("require_docs") && !text.contains("@json"));
+        assert!(text.contains("wire = \"keyed\""));
+        assert!(text.contains(&format!("\nstruct {user}: {}", renames.types["named"])));
+        assert!(text.contains(&format!("internal struct {}", renames.types["audit"])));
+        assert!(text.contains(&format!("    {name}: string,")));
+        assert!(text.contains(&format!(
+            "@since(2) {}: {{{kind}: [{kind}]?}}",
+            renames.members["kinds"]
+        )));
+        assert!(text.contains(&format!("topic {}: {user};", renames.types["users"])));
+
+        // The stripped source builds into the stripped schema, with the same shape on the wire
+        let rebuilt = GenoAstBuilder::new("stripped.geno".into())
+            .build_from_str(&text)
+            .unwrap();
+
+        assert_eq!(rebuilt, stripped);
+        assert_eq!(strip(&schema).1, renames);
+    }
+}
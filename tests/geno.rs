@@ -101,6 +101,38 @@ fn generate_dart_mp() {
     assert!(stdout.contains("import 'package:messagepack/messagepack.dart'"));
 }
 
+#[test]
+fn generate_rust_mp_keyed() {
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "examples/example.geno",
+        "-f",
+        "rust-mp",
+        "-O",
+        "wire=keyed"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("pub struct DecodeError"));
+    assert!(stdout.contains("pub fn to_bytes(&self) -> Vec<u8>"));
+    assert!(stdout.contains("mp::write_map_len(wr, "));
+    assert!(stdout.contains("_ => mp::skip(rd)?,"));
+}
+
 #[test]
 fn generate_to_output_file() {
     let dir = TempDir::new().unwrap();
@@ -178,9 +210,110 @@ fn no_format_specified() {
     assert!(stderr.contains("No output format specified"));
 }
 
+#[test]
+fn exit_codes() {
+    let dir = TempDir::new().unwrap();
+    let code = |args: Vec<String>| {
+        cmd(
+            "cargo",
+            ["run", "--bin", "geno", "--"]
+                .map(String::from)
+                .into_iter()
+                .chain(args),
+        )
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap()
+        .status
+        .code()
+    };
+    let schema = |name: &str, source: &str| {
+        let path = dir.path().join(name);
+
+        fs::write(&path, source).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let template_dir = dir.path().join("templates");
+
+    fs::create_dir(&template_dir).unwrap();
+    fs::write(template_dir.join("bad.txt.tera"), "{{ missing }}").unwrap();
+
+    assert_eq!(code(vec!["--no-such-flag".into()]), Some(2));
+    assert_eq!(code(vec!["examples/example.geno".into()]), Some(2));
+    assert_eq!(
+        code(vec![
+            schema("parse.geno", "meta { format = 1 } struct {"),
+            "-f".into(),
+            "rust-serde".into()
+        ]),
+        Some(3)
+    );
+    assert_eq!(
+        code(vec![
+            schema("invalid.geno", "meta { format = 1 } struct a { b: c }"),
+            "-f".into(),
+            "rust-serde".into()
+        ]),
+        Some(4)
+    );
+    assert_eq!(
+        code(vec![
+            "examples/example.geno".into(),
+            "-f".into(),
+            format!("template:{}", template_dir.to_str().unwrap())
+        ]),
+        Some(5)
+    );
+    assert_eq!(
+        code(vec![
+            "does-not-exist.geno".into(),
+            "-f".into(),
+            "rust-serde".into()
+        ]),
+        Some(6)
+    );
+
+    // Output that can't be written is an I/O failure, even while a generator is writing it
+    #[cfg(target_os = "linux")]
+    assert_eq!(
+        cmd!(
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            "examples/example.geno",
+            "-f",
+            "rust-serde"
+        )
+        .stdout_path("/dev/full")
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap()
+        .status
+        .code(),
+        Some(6)
+    );
+}
+
 #[test]
 fn show_help() {
-    let output = cmd!["cargo", "run", "--bin", "geno", "--", "--help"]
+    let output = cmd!["cargo", "run", "-q", "--bin", "geno", "--", "--help"]
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Arguments"));
+    assert!(output.stderr.is_empty());
+
+    let output = cmd!["cargo", "run", "-q", "--bin", "geno", "--", "--version"]
         .stdout_capture()
         .stderr_capture()
         .unchecked()
@@ -188,6 +321,1779 @@ fn show_help() {
         .unwrap();
 
     assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("geno"));
+    assert!(output.stderr.is_empty());
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "-q",
+        "--bin",
+        "geno",
+        "--",
+        "--no-such-flag"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--no-such-flag"));
+}
+
+#[test]
+fn integrate_flutter() {
+    let dir = TempDir::new().unwrap();
+    let dir_str = dir.path().to_str().unwrap();
+
+    fs::write(dir.path().join("pubspec.yaml"), "name: my_app\n").unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "integrate",
+        "flutter",
+        "-d",
+        dir_str
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let build_yaml = fs::read_to_string(dir.path().join("build.yaml")).unwrap();
+    assert!(build_yaml.contains("package:my_app/geno_builder.dart"));
+    let builder = fs::read_to_string(dir.path().join("lib/geno_builder.dart")).unwrap();
+    assert!(builder.contains("Builder genoBuilder(BuilderOptions options)"));
+
+    // A second run must not clobber the existing files
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "integrate",
+        "flutter",
+        "-d",
+        dir_str
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--force"));
+}
+
+#[test]
+fn add_package() {
+    if which::which("git").is_err() {
+        return;
+    }
+
+    let dir = TempDir::new().unwrap();
+    let repo = dir.path().join("common-schemas");
+    let project = dir.path().join("project");
+    let git = |args: &[&str]| {
+        cmd("git", args)
+            .dir(&repo)
+            .stdout_capture()
+            .stderr_capture()
+            .run()
+            .unwrap();
+    };
+
+    fs::create_dir_all(repo.join("geo")).unwrap();
+    fs::create_dir(&project).unwrap();
+    fs::write(
+        repo.join("money.geno"),
+        "meta { format = 1 }\nstruct money { cents: i64 }\n",
+    )
+    .unwrap();
+    fs::write(
+        repo.join("geo/point.geno"),
+        "meta { format = 1 }\nstruct point { x: f64, y: f64 }\n",
+    )
+    .unwrap();
+    git(&["init", "--quiet"]);
+    git(&["add", "-A"]);
+    git(&[
+        "-c",
+        "user.name=geno",
+        "-c",
+        "user.email=geno@example.com",
+        "commit",
+        "--quiet",
+        "-m",
+        "Schemas",
+    ]);
+    git(&["tag", "v1"]);
+
+    let add = |package: String| {
+        cmd![
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            "add",
+            package,
+            "--as",
+            "common",
+            "-d",
+            project.to_str().unwrap()
+        ]
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap()
+    };
+    let output = add(format!("{}@v1", repo.display()));
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(project.join("geno_modules/common/geo/point.geno").exists());
+    let lockfile = fs::read_to_string(project.join("geno-lock.json")).unwrap();
+    assert!(lockfile.contains("\"common\""));
+    assert!(lockfile.contains("\"version\": \"v1\""));
+
+    // The package's schemas can be imported by namespace
+    let schema = project.join("api.geno");
+
+    fs::write(
+        &schema,
+        "meta { format = 1, imports = \"common, common.geo.point\" }\nstruct order { total: money, at: common.point }\n",
+    )
+    .unwrap();
+    let generate = || {
+        cmd![
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            schema.to_str().unwrap(),
+            "-f",
+            "rust-serde"
+        ]
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap()
+    };
+    let output = generate();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Imported types are named with their namespace, so they can't clash with the schema's
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("pub struct CommonMoney"));
+    assert!(stdout.contains("pub struct CommonPoint"));
+    assert!(stdout.contains("pub total: CommonMoney"));
+    assert!(stdout.contains("pub at: CommonPoint"));
+
+    // Versions that don't exist are errors
+    assert!(!add(format!("{}@v2", repo.display())).status.success());
+
+    // A changed package no longer matches the lockfile, until it's vendored again
+    fs::write(
+        project.join("geno_modules/common/money.geno"),
+        "meta { format = 1 }\nstruct money { cents: i32 }\n",
+    )
+    .unwrap();
+    let output = generate();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("geno-lock.json"));
+
+    // Every command that builds the schema checks its packages, not just generation
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "check",
+        schema.to_str().unwrap(),
+        "--budgets"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("geno-lock.json"));
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "vendor",
+        "-d",
+        project.to_str().unwrap()
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Vendored 'common'"));
+    assert!(generate().status.success());
+}
+
+#[test]
+fn new_generator_python() {
+    let dir = TempDir::new().unwrap();
+    let project = dir.path().join("geno-demo");
+    let new_generator = |name: &str| {
+        cmd![
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            "new-generator",
+            name,
+            "--lang",
+            "python",
+            "-d",
+            project.to_str().unwrap()
+        ]
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap()
+    };
+
+    assert!(!new_generator("Demo").status.success());
+    let output = new_generator("demo");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(project.join("tests/snapshots/example.geno").exists());
+    let module = fs::read_to_string(project.join("geno_demo.py")).unwrap();
+    assert!(module.contains("# Generated by geno-demo"));
+
+    // The project is never overwritten
+    assert!(!new_generator("demo").status.success());
+
+    // The new plugin works as it is
+    if cfg!(unix) && which::which("python3").is_ok() {
+        let output = cmd![
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            "examples/example.geno",
+            "-f",
+            "demo"
+        ]
+        .env("GENO_PLUGIN_PATH", &project)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+
+        assert!(
+            output.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("struct Type1\n  alpha: i8\n"));
+        assert!(stdout.contains("  kiwiFruit = 3\n"));
+    }
+}
+
+#[test]
+fn plugin_binary_reads_ast_from_stdin() {
+    let dir = TempDir::new().unwrap();
+    let ast_path = dir.path().join("example.ast");
+
+    cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "examples/example.geno",
+        "-t",
+        ast_path.to_str().unwrap()
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .run()
+    .unwrap();
+
+    let output = cmd!["cargo", "run", "--bin", "geno-dart-mp"]
+        .stdin_path(&ast_path)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("class Type1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn plugin_stderr_and_timeout() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let plugin = |name: &str, script: &str| {
+        let path = dir.path().join(name);
+
+        fs::write(&path, format!("#!/bin/sh\ncat > /dev/null\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    let path = format!(
+        "{}:{}",
+        dir.path().to_str().unwrap(),
+        std::env::var("PATH").unwrap()
+    );
+    let run = |format: &str| {
+        cmd![
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            "examples/example.geno",
+            "-f",
+            format,
+            "--plugin-timeout",
+            "1"
+        ]
+        .env("PATH", &path)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap()
+    };
+
+    plugin("geno-chatty", "echo progress >&2\necho code");
+    plugin("geno-hung", "sleep 30");
+    plugin("geno-broken", "echo bad schema >&2\nexit 3");
+
+    // Diagnostics go to stderr, leaving only the generated code on stdout
+    let output = run("chatty");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "code\n");
+    assert!(stderr.contains("geno-chatty: progress"));
+
+    let output = run("hung");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        stderr.contains("didn't finish within 1s"),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("geno-hung"));
+
+    let output = run("broken");
     let stderr = String::from_utf8_lossy(&output.stderr);
-    assert!(stderr.contains("Arguments"));
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr.contains("bad schema"), "stderr: {stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn doctor_finds_plugins() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let plugin_path = dir.path().join("geno-fake");
+
+    fs::write(&plugin_path, "#!/bin/sh\ncat > /dev/null\necho fake\n").unwrap();
+    fs::set_permissions(&plugin_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = cmd!["cargo", "run", "--bin", "geno", "--", "doctor"]
+        .env("GENO_PLUGIN_PATH", dir.path())
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(stdout.contains("rust-serde: built in"));
+    assert!(stdout.contains(&format!("fake: {}", plugin_path.to_str().unwrap())));
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "doctor",
+        "fake,missing"
+    ]
+    .env("GENO_PLUGIN_PATH", dir.path())
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("missing: not found"));
+
+    // Plugins on GENO_PLUGIN_PATH generate code like those on PATH
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "examples/example.geno",
+        "-f",
+        "fake"
+    ]
+    .env("GENO_PLUGIN_PATH", dir.path())
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "fake\n");
+}
+
+#[test]
+fn generate_from_templates() {
+    let dir = TempDir::new().unwrap();
+    let template_dir = dir.path().join("templates");
+    let out_dir = dir.path().join("out");
+
+    fs::create_dir(&template_dir).unwrap();
+    fs::write(
+        template_dir.join("names.txt.tera"),
+        "{% for s in structs %}{{ s.ident | snake_case }}\n{% endfor %}",
+    )
+    .unwrap();
+    fs::write(
+        template_dir.join("enums.txt.tera"),
+        "{% for e in enums %}{{ e.ident }}: {{ e.base_type }}\n{% endfor %}",
+    )
+    .unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "examples/example.geno",
+        "-f",
+        format!("template:{}", template_dir.to_str().unwrap()),
+        "-o",
+        out_dir.to_str().unwrap()
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(out_dir.join("names.txt")).unwrap(),
+        "type1\n"
+    );
+    assert_eq!(
+        fs::read_to_string(out_dir.join("enums.txt")).unwrap(),
+        "Enum1: i16\n"
+    );
+}
+
+#[test]
+fn generate_several_formats() {
+    let dir = TempDir::new().unwrap();
+    let template_dir = dir.path().join("templates");
+    let out_dir = dir.path().join("out");
+    let run = |formats: String| {
+        cmd![
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            "examples/example.geno",
+            "-f",
+            formats,
+            "-o",
+            out_dir.to_str().unwrap()
+        ]
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap()
+    };
+
+    let output = run("rust-serde,dart-mp".to_string());
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        fs::read_to_string(out_dir.join("rust-serde/geno.rs"))
+            .unwrap()
+            .contains("pub struct")
+    );
+    assert!(out_dir.join("dart-mp/geno.dart").exists());
+
+    // A target that fails leaves every target's output as it was
+    fs::create_dir(&template_dir).unwrap();
+    fs::write(template_dir.join("bad.txt.tera"), "{{ missing }}").unwrap();
+    fs::write(out_dir.join("rust-serde/geno.rs"), "old").unwrap();
+
+    let output = run(format!(
+        "rust-serde,template:{}",
+        template_dir.to_str().unwrap()
+    ));
+
+    assert!(!output.status.success());
+    assert_eq!(
+        fs::read_to_string(out_dir.join("rust-serde/geno.rs")).unwrap(),
+        "old"
+    );
+    assert!(!out_dir.join("template").exists());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn generate_test_vectors() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("shapes.geno");
+    let out_dir = dir.path().join("vectors");
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\nenum kind: u8 { circle = 1, square = 2 }\nstruct shape { kind: kind, size: f64, label: string? }\n",
+    )
+    .unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        schema_path.to_str().unwrap(),
+        "-f",
+        "testvectors",
+        "-o",
+        out_dir.to_str().unwrap()
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(out_dir.join("kind.square.msgpack")).unwrap(), [2]);
+    assert!(out_dir.join("shape.min.msgpack").exists());
+    assert!(out_dir.join("shape.max.msgpack").exists());
+    let manifest = fs::read_to_string(out_dir.join("manifest.json")).unwrap();
+    assert!(manifest.contains("\"file\": \"shape.max.msgpack\""));
+}
+
+#[test]
+fn warn_on_unreachable_types() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("orders.geno");
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\n@root struct order { id: u64, notes: [string?]? }\nstruct draft { id: u64 }\n",
+    )
+    .unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        schema_path.to_str().unwrap(),
+        "-f",
+        "rust-serde"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(stderr.contains("warning: type 'draft' isn't reachable from any root"));
+    assert!(stderr.contains("warning: field 'order.notes' is a nullable container"));
+    assert!(!stderr.contains("'order'"));
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        schema_path.to_str().unwrap(),
+        "-f",
+        "rust-serde",
+        "--allow",
+        "double_nullable"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(stderr.contains("'draft'"));
+    assert!(!stderr.contains("'order.notes'"));
+}
+
+#[test]
+fn check_target_capabilities() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("readings.geno");
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\nstruct reading { id: u64, samples: {f64: i32} }\n",
+    )
+    .unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        schema_path.to_str().unwrap(),
+        "-f",
+        "dart-mp"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains(
+        "warning: dart-mp: field 'reading.id' is a u64, which loses precision above i64::MAX"
+    ));
+    assert!(stderr.contains("error: dart-mp can't generate code for this schema"));
+    assert!(stderr.contains("map key type 'f64' of 'reading.samples' is not supported"));
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        schema_path.to_str().unwrap(),
+        "-f",
+        "constraints-json",
+        "-o",
+        dir.path().join("constraints.json").to_str().unwrap()
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(!stderr.contains("warning"));
+}
+
+#[test]
+fn generate_only_selected_types() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("shop.geno");
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\nstruct item { sku: string }\nstruct order { items: [item] }\nstruct customer { id: u64 }\n",
+    )
+    .unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        schema_path.to_str().unwrap(),
+        "-f",
+        "rust-serde",
+        "--only",
+        "order"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("pub struct Order"));
+    assert!(stdout.contains("pub struct Item"));
+    assert!(!stdout.contains("pub struct Customer"));
+}
+
+#[test]
+fn write_depfile() {
+    let dir = TempDir::new().unwrap();
+    let out_dir = dir.path().join("out");
+    let template_dir = dir.path().join("templates");
+    let depfile_path = dir.path().join("example.d");
+
+    fs::create_dir(&template_dir).unwrap();
+    fs::write(template_dir.join("types.txt.tera"), "{{ meta.format }}").unwrap();
+
+    let output = cmd!(
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "examples/example.geno",
+        "-f",
+        format!("rust-serde,template:{}", template_dir.to_str().unwrap()),
+        "-o",
+        &out_dir,
+        "--depfile",
+        &depfile_path
+    )
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let depfile = fs::read_to_string(&depfile_path).unwrap();
+    let inputs = format!(
+        ": \\\n  {} \\\n  examples/example.geno\n",
+        template_dir.join("types.txt.tera").to_str().unwrap()
+    );
+
+    assert_eq!(
+        depfile,
+        format!(
+            "{}{inputs}{}{inputs}",
+            out_dir.join("rust-serde").join("geno.rs").to_str().unwrap(),
+            out_dir.join("template").join("types.txt").to_str().unwrap()
+        )
+    );
+}
+
+#[test]
+fn depfile_imports() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("api.geno");
+    let out_path = dir.path().join("geno.rs");
+    let depfile_path = dir.path().join("api.d");
+    let modules = dir.path().join("geno_modules/common");
+    let depfile = || {
+        let output = cmd!(
+            "cargo",
+            "run",
+            "--bin",
+            "geno",
+            "--",
+            &schema_path,
+            "-f",
+            "rust-serde",
+            "-o",
+            &out_path,
+            "--depfile",
+            &depfile_path
+        )
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+
+        assert!(
+            output.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        fs::read_to_string(&depfile_path).unwrap()
+    };
+
+    fs::create_dir_all(&modules).unwrap();
+    fs::write(
+        &schema_path,
+        "meta { format = 1, imports = \"common.money\" }\nstruct order { total: money }\n",
+    )
+    .unwrap();
+    fs::write(
+        modules.join("money.geno"),
+        "meta { format = 1, imports = \"common.currency\" }\nstruct money { cents: i64, currency: currency }\n",
+    )
+    .unwrap();
+    fs::write(
+        modules.join("currency.geno"),
+        "meta { format = 1 }\nenum currency: u8 { usd = 1 }\n",
+    )
+    .unwrap();
+
+    // Modules imported through other modules are inputs too, so editing any of them makes the
+    // generated code stale
+    assert_eq!(
+        depfile(),
+        format!(
+            "{}: \\\n  {} \\\n  {} \\\n  {}\n",
+            out_path.to_str().unwrap(),
+            schema_path.to_str().unwrap(),
+            modules.join("currency.geno").to_str().unwrap(),
+            modules.join("money.geno").to_str().unwrap()
+        )
+    );
+
+    // A module that's no longer imported drops out
+    fs::write(
+        modules.join("money.geno"),
+        "meta { format = 1 }\nstruct money { cents: i64 }\n",
+    )
+    .unwrap();
+    assert!(!depfile().contains("currency.geno"));
+}
+
+#[test]
+fn write_manifest() {
+    let dir = TempDir::new().unwrap();
+    let out_dir = dir.path().join("out");
+    let schema_path = dir.path().join("shapes.geno");
+    let schema_str = schema_path.to_str().unwrap();
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\nstruct shape { size: f64, label: string? }\n",
+    )
+    .unwrap();
+
+    let run = |args: &[&str]| {
+        let mut all = vec!["run", "--bin", "geno", "--", schema_str];
+
+        all.extend_from_slice(args);
+        cmd("cargo", all)
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+
+    let output = run(&[
+        "-f",
+        "testvectors",
+        "-o",
+        out_dir.to_str().unwrap(),
+        "--manifest",
+    ]);
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let rust_path = out_dir.join("geno.rs");
+    let output = run(&[
+        "-f",
+        "rust-serde",
+        "-o",
+        rust_path.to_str().unwrap(),
+        "--manifest",
+    ]);
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out_dir.join("geno-manifest.json")).unwrap())
+            .unwrap();
+    let files = manifest["files"].as_array().unwrap();
+    let rust = files
+        .iter()
+        .find(|entry| entry["path"] == "geno.rs")
+        .unwrap();
+
+    assert_eq!(rust["target"], "rust-serde");
+    assert_eq!(rust["schema"], schema_str);
+    assert_eq!(rust["content_hash"].as_str().unwrap().len(), 64);
+    assert!(
+        files
+            .iter()
+            .any(|entry| entry["path"] == "manifest.json" && entry["target"] == "testvectors")
+    );
+
+    let output = cmd!(
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "clean",
+        out_dir.to_str().unwrap(),
+        "-f",
+        "testvectors"
+    )
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!out_dir.join("manifest.json").exists());
+    assert!(rust_path.exists());
+
+    // A manifest needs somewhere to go
+    let output = run(&["-f", "rust-serde", "--manifest"]);
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--output-path"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn round_trip_test() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("shapes.geno");
+    let work_dir = dir.path().join("work");
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\nenum kind: u8 { circle = 1, square = 2 }\nstruct shape { kind: kind, size: f64, @pii label: string?, tags: {string: i32}? }\ntopic shapes: shape;\n",
+    )
+    .unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "test",
+        schema_path.to_str().unwrap(),
+        "--targets",
+        "rust-mp,rust-mp",
+        "-O",
+        "wire=bitmap",
+        "-d",
+        work_dir.to_str().unwrap()
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "4 vectors through rust-mp -> rust-mp, 0 failed\n"
+    );
+    assert!(work_dir.join("1-rust-mp/output/shape.max.msgpack").exists());
+}
+
+#[test]
+fn snapshots() {
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "snapshot",
+        "tests/snapshots"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "run 'cargo run --bin geno -- snapshot tests/snapshots --bless' to update the snapshots\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Output that needs options, and crates the Rust snapshots aren't built with
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "snapshot",
+        "tests/snapshots/tokio_codec",
+        "-f",
+        "rust-mp",
+        "-O",
+        "tokio_codec=true"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "run 'cargo run --bin geno -- snapshot tests/snapshots/tokio_codec -f rust-mp -O tokio_codec=true --bless' to update the snapshots\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn rust_snapshots_compile() {
+    // The snapshots are only compared as text, so build the Rust ones as modules of a crate.
+    // Its dependencies are pinned by the workspace's lockfile, so nothing is fetched.  `rust-sqlx`
+    // is left out, as it needs a database driver.
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = TempDir::new().unwrap();
+    let mut lib = "#![allow(dead_code)]\n".to_string();
+
+    for entry in fs::read_dir(root.join("tests/snapshots")).unwrap() {
+        let schema_dir = entry.unwrap().path();
+
+        if !schema_dir.is_dir() {
+            continue;
+        }
+        for generator in ["rust-mp", "rust-serde", "rust-clap"] {
+            let Ok(files) = fs::read_dir(schema_dir.join(generator)) else {
+                continue;
+            };
+
+            for file in files {
+                let path = file.unwrap().path();
+                let module = format!(
+                    "{}_{}_{}",
+                    schema_dir.file_name().unwrap().to_string_lossy(),
+                    generator.replace('-', "_"),
+                    path.file_stem().unwrap().to_string_lossy()
+                );
+
+                lib.push_str(&format!("#[path = {:?}]\nmod {module};\n", path));
+            }
+        }
+    }
+
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), lib).unwrap();
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        r#"[package]
+name = "snapshots"
+version = "0.0.0"
+edition = "2021"
+publish = false
+
+[dependencies]
+clap = { version = "4", features = ["derive"] }
+serde = { version = "1", features = ["derive"] }
+
+[workspace]
+"#,
+    )
+    .unwrap();
+    fs::copy(root.join("Cargo.lock"), dir.path().join("Cargo.lock")).unwrap();
+
+    let output = cmd!("cargo", "check", "--quiet")
+        .dir(dir.path())
+        .env("CARGO_TARGET_DIR", root.join("target/snapshots"))
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "the Rust snapshots don't compile\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Serve a minimal schema registry on a local port, one request per connection
+fn start_registry() -> String {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    std::thread::spawn(move || {
+        let mut schemas: Vec<serde_json::Value> = Vec::new();
+
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut request_line = String::new();
+            let mut content_length = 0;
+
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length")
+                {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+
+            let mut body = vec![0; content_length];
+
+            reader.read_exact(&mut body).unwrap();
+
+            let parts: Vec<&str> = request_line.split_whitespace().collect();
+            let response = match (parts[0], parts[1]) {
+                ("POST", "/subjects/shapes/versions") => {
+                    let mut schema: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+                    schema["id"] = (schemas.len() + 100).into();
+                    schema["version"] = (schemas.len() + 1).into();
+                    schemas.push(schema.clone());
+                    Some(schema)
+                }
+                ("GET", "/subjects/shapes/versions/latest") => schemas.last().cloned(),
+                ("GET", path) => path
+                    .strip_prefix("/schemas/ids/")
+                    .and_then(|id| {
+                        schemas
+                            .iter()
+                            .find(|s| id.parse().is_ok_and(|id: u64| s["id"] == id))
+                    })
+                    .cloned(),
+                _ => None,
+            };
+            let (status, body) = match response {
+                Some(body) => ("200 OK", body.to_string()),
+                None => ("404 Not Found", r#"{"message":"not found"}"#.to_string()),
+            };
+
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        }
+    });
+
+    url
+}
+
+#[test]
+fn publish_and_fetch() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("shapes.geno");
+    let fetched_path = dir.path().join("fetched.geno");
+    let source = "meta { format = 1 }\nstruct shape { size: f64 }\n";
+    let url = start_registry();
+
+    fs::write(&schema_path, source).unwrap();
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "publish",
+        schema_path.to_str().unwrap(),
+        "-r",
+        &url,
+        "-s",
+        "shapes"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout)
+            .starts_with("Published 'shapes' version 1 as schema id 100 (")
+    );
+
+    let output = cmd![
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "fetch",
+        "-r",
+        &url,
+        "-s",
+        "shapes",
+        "-o",
+        fetched_path.to_str().unwrap()
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read_to_string(&fetched_path).unwrap(), source);
+
+    let output = cmd![
+        "cargo", "run", "--bin", "geno", "--", "fetch", "-r", &url, "--id", "100"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), source);
+
+    let output = cmd![
+        "cargo", "run", "--bin", "geno", "--", "fetch", "-r", &url, "--id", "7"
+    ]
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("404 Not Found: not found"));
+}
+
+#[test]
+fn kafka_schema_ids_from_registry() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("events.geno");
+    let url = start_registry();
+    let geno = |args: &[&str]| {
+        cmd("cargo", ["run", "--bin", "geno", "--"].iter().chain(args))
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+    let schema_str = schema_path.to_str().unwrap();
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\nstruct event { id: u32 }\ntopic events: event;\n",
+    )
+    .unwrap();
+
+    let registry = format!("registry={url}");
+    let output = geno(&[
+        schema_str,
+        "-f",
+        "kafka",
+        "-O",
+        &registry,
+        "-O",
+        "subject=shapes",
+    ]);
+
+    assert!(!output.status.success());
+
+    let output = geno(&["publish", schema_str, "-r", &url, "-s", "shapes"]);
+
+    assert!(output.status.success());
+
+    let output = geno(&[
+        schema_str,
+        "-f",
+        "kafka",
+        "-O",
+        &registry,
+        "-O",
+        "subject=shapes",
+    ]);
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("pub const SCHEMA_ID: u32 = 100;"));
+}
+
+#[test]
+fn compat_and_bump() {
+    let dir = TempDir::new().unwrap();
+    let old_path = dir.path().join("old.geno");
+    let new_path = dir.path().join("new.geno");
+    let geno = |args: &[&str]| {
+        cmd("cargo", ["run", "--bin", "geno", "--"].iter().chain(args))
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+    let old_str = old_path.to_str().unwrap();
+    let new_str = new_path.to_str().unwrap();
+
+    fs::write(
+        &old_path,
+        "meta { format = 1, version = 1 }\nenum kind: u8 { a = 1 }\n",
+    )
+    .unwrap();
+    fs::write(
+        &new_path,
+        "meta { format = 1, version = 1 }\nenum kind: u8 { a = 1, b = 2 }\n",
+    )
+    .unwrap();
+
+    let output = geno(&["compat", old_str, new_str]);
+
+    assert_eq!(output.status.code(), Some(7));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "minor: 'kind.b' added\nminor change, version 1\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("need version 2"));
+
+    let output = geno(&["bump", old_str, new_str]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&new_path).unwrap(),
+        "meta { format = 1, version = 2 }\nenum kind: u8 { a = 1, b = 2 }\n"
+    );
+    assert!(geno(&["compat", old_str, new_str]).status.success());
+
+    // A semantic version bumps the component for the class of change
+    fs::write(
+        &old_path,
+        "meta { format = 1, version = \"1.4.2\" }\nenum kind: u8 { a = 1 }\n",
+    )
+    .unwrap();
+    fs::write(
+        &new_path,
+        "meta { format = 1, version = \"1.4.2\" }\nenum kind: u8 { a = 1, b = 2 }\n",
+    )
+    .unwrap();
+
+    let output = geno(&["compat", old_str, new_str]);
+
+    assert_eq!(output.status.code(), Some(7));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("need version 1.5.0"));
+    assert!(geno(&["bump", old_str, new_str]).status.success());
+    assert_eq!(
+        fs::read_to_string(&new_path).unwrap(),
+        "meta { format = 1, version = \"1.5.0\" }\nenum kind: u8 { a = 1, b = 2 }\n"
+    );
+    assert!(geno(&["compat", old_str, new_str]).status.success());
+}
+
+#[test]
+fn strip_schema() {
+    let dir = TempDir::new().unwrap();
+    let stripped_path = dir.path().join("stripped.geno");
+    let map_path = dir.path().join("renames.json");
+
+    cmd!(
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "strip",
+        "examples/example.geno",
+        "-o",
+        &stripped_path,
+        "-m",
+        &map_path
+    )
+    .run()
+    .unwrap();
+
+    let renames: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&map_path).unwrap()).unwrap();
+    let stripped = fs::read_to_string(&stripped_path).unwrap();
+
+    for (name, token) in renames["types"].as_object().unwrap() {
+        assert!(stripped.contains(token.as_str().unwrap()));
+        assert!(!stripped.contains(&format!(" {name} ")));
+    }
+    assert!(!stripped.contains("//"));
+
+    // The stripped schema builds
+    let output = cmd!(
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        &stripped_path,
+        "-f",
+        "rust-serde"
+    )
+    .stdout_capture()
+    .run()
+    .unwrap();
+
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("pub struct T")
+    );
+}
+
+#[test]
+fn fmt_narrows_integers() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("narrow.geno");
+    let schema_str = schema_path.to_str().unwrap();
+    let source = "meta { format = 1 }\nenum kind { a = 1 }\nstruct item { @range(0, 9) n: u32 }\n";
+
+    fs::write(&schema_path, source).unwrap();
+
+    let output = cmd!("cargo", "run", "--bin", "geno", "--", "fmt", schema_str)
+        .stdout_capture()
+        .run()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "'kind' i32 -> u8\n'item.n' u32 -> u8\n"
+    );
+    assert_eq!(fs::read_to_string(&schema_path).unwrap(), source);
+
+    cmd!(
+        "cargo", "run", "--bin", "geno", "--", "fmt", schema_str, "--fix"
+    )
+    .stdout_capture()
+    .run()
+    .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&schema_path).unwrap(),
+        "meta { format = 1 }\nenum kind: u8 { a = 1 }\nstruct item { @range(0, 9) n: u8 }\n"
+    );
+}
+
+#[test]
+fn serve_json_lines() {
+    let schema = "meta { format = 1 } struct point { x: i32 }";
+    let requests = [
+        serde_json::json!({ "id": 1, "method": "parse", "params": { "source": schema } }),
+        serde_json::json!({ "id": 2, "method": "validate", "params": { "path": "examples/example.geno" } }),
+        serde_json::json!({
+            "id": 3,
+            "method": "generate",
+            "params": { "source": schema, "format": "rust-serde", "options": { "derives": "Eq,Hash" } },
+        }),
+        serde_json::json!({
+            "id": 4,
+            "method": "diff",
+            "params": {
+                "old": { "source": schema },
+                "new": { "source": "meta { format = 1, version = 2 } struct point { x: i32, y: i32 }" },
+            },
+        }),
+        serde_json::json!({ "id": 5, "method": "parse", "params": { "source": "struct" } }),
+        serde_json::json!({ "id": 6, "method": "check", "params": {} }),
+        serde_json::json!({ "id": 7, "method": "watch" }),
+    ];
+    let mut input: String = requests
+        .iter()
+        .map(|request| format!("{request}\n"))
+        .collect();
+
+    input.push_str("not json\n");
+
+    let output = cmd!("cargo", "run", "--bin", "geno", "--", "serve", "--stdio")
+        .stdin_bytes(input)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(responses.len(), 8);
+    assert_eq!(
+        responses[0]["result"]["declarations"][0]["Struct"]["ident"],
+        "point"
+    );
+    assert!(responses[1]["result"]["warnings"].is_array());
+    assert!(
+        responses[2]["result"]["files"][0]["contents"]
+            .as_str()
+            .unwrap()
+            .contains("#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]")
+    );
+    assert_eq!(responses[3]["result"]["class"], "major");
+    assert_eq!(responses[3]["result"]["version"], 2);
+    assert_eq!(responses[4]["id"], 5);
+    assert_eq!(responses[4]["error"]["code"], 3);
+    assert_eq!(responses[5]["result"]["schemas"], serde_json::json!([]));
+    assert_eq!(responses[6]["error"]["message"], "Unknown method 'watch'");
+    assert!(responses[7]["id"].is_null());
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon_socket() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        thread,
+        time::Duration,
+    };
+
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("point.geno");
+    let socket_path = dir.path().join("geno.sock");
+    let module_path = dir.path().join("geno_modules/common/units.geno");
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1, imports = \"common\" } struct point { x: i32, unit: unit }",
+    )
+    .unwrap();
+    fs::create_dir_all(module_path.parent().unwrap()).unwrap();
+    fs::write(&module_path, "meta { format = 1 } enum unit: u8 { mm = 1 }").unwrap();
+    // Build output and links back up the tree aren't searched for schemas
+    fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+    fs::write(dir.path().join("target/debug/copy.geno"), "struct").unwrap();
+    std::os::unix::fs::symlink(dir.path(), dir.path().join("loop")).unwrap();
+
+    let daemon = cmd!(
+        "cargo",
+        "run",
+        "--bin",
+        "geno",
+        "--",
+        "daemon",
+        dir.path(),
+        "--socket",
+        &socket_path,
+        "--poll-interval",
+        "50"
+    )
+    .stdout_null()
+    .stderr_null()
+    .start()
+    .unwrap();
+    let mut stream = (0..600)
+        .find_map(|_| {
+            UnixStream::connect(&socket_path).ok().or_else(|| {
+                thread::sleep(Duration::from_millis(100));
+                None
+            })
+        })
+        .expect("daemon did not start listening");
+    let mut responses = BufReader::new(stream.try_clone().unwrap()).lines();
+    let mut request = |request: serde_json::Value| -> serde_json::Value {
+        writeln!(stream, "{request}").unwrap();
+        serde_json::from_str(&responses.next().unwrap().unwrap()).unwrap()
+    };
+
+    let response = request(serde_json::json!({ "id": 1, "method": "check" }));
+
+    assert_eq!(
+        response["result"]["schemas"],
+        serde_json::json!([{ "path": schema_path, "warnings": [] }])
+    );
+
+    let response = request(serde_json::json!({
+        "id": 2,
+        "method": "generate",
+        "params": { "path": schema_path, "format": "rust-serde" },
+    }));
+
+    assert!(
+        response["result"]["files"][0]["contents"]
+            .as_str()
+            .unwrap()
+            .contains("pub struct Point")
+    );
+
+    // A change to an imported module is picked up, not just one to the schema
+    thread::sleep(Duration::from_millis(50));
+    fs::write(
+        &module_path,
+        "meta { format = 1 } enum unit: u8 { mm = 1, mm = 2 }",
+    )
+    .unwrap();
+
+    let response = request(serde_json::json!({ "id": 3, "method": "check" }));
+
+    assert_eq!(response["result"]["schemas"][0]["error"]["code"], 4);
+
+    fs::write(&module_path, "meta { format = 1 } enum unit: u8 { mm = 1 }").unwrap();
+    fs::write(&schema_path, "meta { format = 1 } struct point { x: nope }").unwrap();
+
+    let response = request(serde_json::json!({ "id": 4, "method": "check" }));
+
+    assert_eq!(response["result"]["schemas"][0]["error"]["code"], 4);
+
+    daemon.kill().unwrap();
+}
+
+#[test]
+fn check_budgets() {
+    let dir = TempDir::new().unwrap();
+    let schema_path = dir.path().join("ble.geno");
+    let schema_str = schema_path.to_str().unwrap();
+    let geno = |args: &[&str]| {
+        cmd("cargo", ["run", "--bin", "geno", "--"].iter().chain(args))
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+
+    fs::write(
+        &schema_path,
+        "meta { format = 1 }\n@root @budget(max_packed_bytes = 10) struct ping { seq: u32, ok: bool }\n",
+    )
+    .unwrap();
+
+    let output = geno(&["check", "--budgets", schema_str]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "ok: 'ping' packs to at most 6 of 10 bytes\n1 budgets checked, 0 over\n"
+    );
+
+    // The field names take up room in the keyed wire format
+    let output = geno(&["check", "--budgets", schema_str, "-O", "wire=keyed"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("over: 'ping' can pack to 14 bytes"));
 }
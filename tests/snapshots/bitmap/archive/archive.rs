@@ -0,0 +1,236 @@
+// Archives of rust-mp records, generated by geno.  Include this file in the module that
+// holds them.
+
+/// The first 8 bytes of the fingerprint of the schema this file was generated from
+pub const ARCHIVE_FINGERPRINT: [u8; 8] = [0xfa, 0x3a, 0x23, 0x91, 0x5a, 0x74, 0x04, 0x3c];
+
+/// An error reading an archive
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// An error from the file
+    Io(std::io::Error),
+    /// The file doesn't start with the archive magic
+    NotArchive,
+    /// The archive has a format version this code doesn't read
+    Version(u8),
+    /// The archive was written with the schema that has this fingerprint
+    Fingerprint([u8; 8]),
+    /// The index or a record length points outside the records
+    Corrupt,
+    /// There is no record at the index
+    OutOfRange(usize),
+    /// A record doesn't decode
+    Decode(DecodeError),
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArchiveError::Io(err) => write!(f, "{err}"),
+            ArchiveError::NotArchive => write!(f, "the file is not an archive"),
+            ArchiveError::Version(version) => write!(f, "the archive has format version {version}"),
+            ArchiveError::Fingerprint(fingerprint) => write!(
+                f,
+                "the archive was written with schema {}, but this code is for schema {}",
+                archive_hex(fingerprint),
+                archive_hex(&ARCHIVE_FINGERPRINT)
+            ),
+            ArchiveError::Corrupt => write!(f, "the archive is corrupt"),
+            ArchiveError::OutOfRange(index) => write!(f, "the archive has no record {index}"),
+            ArchiveError::Decode(err) => write!(f, "{}", err.0),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(err: std::io::Error) -> Self {
+        ArchiveError::Io(err)
+    }
+}
+
+fn archive_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+const ARCHIVE_MAGIC: [u8; 4] = *b"GENA";
+const ARCHIVE_VERSION: u8 = 1;
+
+/// A type that can be stored in an archive
+pub trait ArchiveRecord: Sized {
+    /// Encode a record
+    fn encode_record(&self) -> Vec<u8>;
+    /// Decode a record
+    fn decode_record(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Writes records to an archive, starting at the beginning of `W`, and then their index when it
+/// is finished
+pub struct ArchiveWriter<W: std::io::Write, T> {
+    writer: W,
+    offsets: Vec<u64>,
+    position: u64,
+    records: std::marker::PhantomData<fn(&T)>,
+}
+
+impl<W: std::io::Write, T: ArchiveRecord> ArchiveWriter<W, T> {
+    /// Start an archive, writing its header
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(&ARCHIVE_MAGIC)?;
+        writer.write_all(&[ARCHIVE_VERSION, 0, 0, 0])?;
+        writer.write_all(&ARCHIVE_FINGERPRINT)?;
+        Ok(Self {
+            writer,
+            offsets: Vec::new(),
+            position: 16,
+            records: std::marker::PhantomData,
+        })
+    }
+
+    /// Append a record
+    pub fn push(&mut self, record: &T) -> std::io::Result<()> {
+        let bytes = record.encode_record();
+        let length = u32::try_from(bytes.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "the record is too long")
+        })?;
+
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.offsets.push(self.position);
+        self.position += 4 + u64::from(length);
+        Ok(())
+    }
+
+    /// Write the index and the footer, and return the writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        for offset in &self.offsets {
+            self.writer.write_all(&offset.to_be_bytes())?;
+        }
+        self.writer.write_all(&self.position.to_be_bytes())?;
+        self.writer.write_all(&(self.offsets.len() as u64).to_be_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the records of an archive in any order, using its index
+pub struct ArchiveReader<R: std::io::Read + std::io::Seek, T> {
+    reader: R,
+    offsets: Vec<u64>,
+    index: u64,
+    records: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<R: std::io::Read + std::io::Seek, T: ArchiveRecord> ArchiveReader<R, T> {
+    /// Open an archive, checking its header and reading its index
+    pub fn open(mut reader: R) -> Result<Self, ArchiveError> {
+        use std::io::SeekFrom;
+
+        let end = reader.seek(SeekFrom::End(0))?;
+        let mut header = [0u8; 16];
+        let mut footer = [0u8; 16];
+
+        if end < 32 {
+            return Err(ArchiveError::NotArchive);
+        }
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+        if header[..4] != ARCHIVE_MAGIC {
+            return Err(ArchiveError::NotArchive);
+        }
+        if header[4] != ARCHIVE_VERSION {
+            return Err(ArchiveError::Version(header[4]));
+        }
+        if header[8..] != ARCHIVE_FINGERPRINT {
+            return Err(ArchiveError::Fingerprint(header[8..].try_into().unwrap()));
+        }
+
+        reader.seek(SeekFrom::End(-16))?;
+        reader.read_exact(&mut footer)?;
+
+        let index = u64::from_be_bytes(footer[..8].try_into().unwrap());
+        let count = u64::from_be_bytes(footer[8..].try_into().unwrap());
+
+        let index_end = count
+            .checked_mul(8)
+            .and_then(|length| length.checked_add(index))
+            .and_then(|length| length.checked_add(16));
+
+        if index < 16 || index_end != Some(end) {
+            return Err(ArchiveError::Corrupt);
+        }
+
+        let mut bytes = vec![0u8; (count * 8) as usize];
+
+        reader.seek(SeekFrom::Start(index))?;
+        reader.read_exact(&mut bytes)?;
+        Ok(Self {
+            reader,
+            offsets: bytes
+                .chunks_exact(8)
+                .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+                .collect(),
+            index,
+            records: std::marker::PhantomData,
+        })
+    }
+
+    /// The number of records
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Whether the archive has no records
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Read the record at `index`
+    pub fn get(&mut self, index: usize) -> Result<T, ArchiveError> {
+        let offset = *self.offsets.get(index).ok_or(ArchiveError::OutOfRange(index))?;
+        let mut length = [0u8; 4];
+
+        self.reader.seek(std::io::SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut length)?;
+
+        let length = u32::from_be_bytes(length);
+
+        if offset
+            .checked_add(4 + u64::from(length))
+            .is_none_or(|record_end| record_end > self.index)
+        {
+            return Err(ArchiveError::Corrupt);
+        }
+
+        let mut bytes = vec![0u8; length as usize];
+
+        self.reader.read_exact(&mut bytes)?;
+        T::decode_record(&bytes).map_err(ArchiveError::Decode)
+    }
+
+    /// Return the reader of the archive
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl ArchiveRecord for Point {
+    fn encode_record(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    fn decode_record(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl ArchiveRecord for Shape {
+    fn encode_record(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    fn decode_record(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::from_bytes(bytes)
+    }
+}
@@ -0,0 +1,3 @@
+// Loading and checking of the @config structs, generated by geno.  Include this file in the
+// module that holds the rust-serde types.  Needs the figment crate with the `toml` and `env`
+// features.
@@ -0,0 +1,3 @@
+// CSV and Arrow codecs for the @record structs, generated by geno.  Include this file in
+// the module that holds the rust-serde types.  Needs the csv and arrow crates, and the
+// parquet crate with its `arrow` feature.
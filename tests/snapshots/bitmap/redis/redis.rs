@@ -0,0 +1,2 @@
+// Redis keys and values for the rust-mp types, generated by geno.  Include this file in
+// the module that holds them.  The value codecs need the redis crate.
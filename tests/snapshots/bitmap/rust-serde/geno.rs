@@ -0,0 +1,52 @@
+#![allow(unused_imports)]
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[repr(u8)]
+pub enum Kind {
+    #[default]
+    #[serde(rename = "circle")]
+    Circle = 1,
+    #[serde(rename = "square")]
+    Square = 2,
+}
+
+impl From<Kind> for u8 {
+    fn from(value: Kind) -> Self {
+        value as u8
+    }
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::Circle),
+            2 => Ok(Self::Square),
+            _ => Err(format!("invalid Kind value {value}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shape {
+    pub kind: Kind,
+    pub origin: Point,
+    pub label: Option<String>,
+    pub scale: Option<f32>,
+    pub points: Vec<Point>,
+    pub corners: Option<[Point; 4]>,
+    pub tags: Option<HashMap<String, i32>>,
+    pub by_kind: HashMap<Kind, Vec<u64>>,
+    pub flags: HashMap<bool, String>,
+    pub parent: Option<Box<Shape>>,
+}
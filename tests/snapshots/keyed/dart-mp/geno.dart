@@ -0,0 +1,315 @@
+import 'dart:typed_data';
+
+import 'package:messagepack/messagepack.dart';
+
+const _unset = Object();
+
+Object _required(Map m, String key, String type) {
+  final value = m[key];
+  if (value == null) {
+    throw FormatException('$type.$key is missing');
+  }
+  return value;
+}
+
+List<T> _checkFixedList<T>(List<T> list, String path, int expected) {
+  if (list.length != expected) {
+    throw FormatException('$path must have $expected elements, got ${list.length}');
+  }
+  return list;
+}
+
+bool _deepEquals(Object? a, Object? b) {
+  if (a is List && b is List) {
+    if (a.length != b.length) return false;
+    for (var i = 0; i < a.length; i++) {
+      if (!_deepEquals(a[i], b[i])) return false;
+    }
+    return true;
+  }
+  if (a is Map && b is Map) {
+    if (a.length != b.length) return false;
+    for (final key in a.keys) {
+      if (!b.containsKey(key) || !_deepEquals(a[key], b[key])) return false;
+    }
+    return true;
+  }
+  return a == b;
+}
+
+int _deepHash(Object? o) {
+  if (o is List) return Object.hashAll(o.map(_deepHash));
+  if (o is Map) return Object.hashAllUnordered(o.entries.map((e) => Object.hash(_deepHash(e.key), _deepHash(e.value))));
+  return o.hashCode;
+}
+
+enum Kind {
+  circle(1),
+  square(2);
+
+  final int value;
+  const Kind(this.value);
+
+  Uint8List toBytes() {
+    final p = Packer();
+    _pack(p);
+    return p.takeBytes();
+  }
+
+  static Kind fromBytes(Uint8List bytes) {
+    return _unpack(Unpacker(bytes));
+  }
+
+  void _pack(Packer p) {
+    p.packInt(value);
+  }
+
+  static Kind _unpack(Unpacker u) {
+    return _fromValue(u.unpackInt());
+  }
+
+  static Kind _fromValue(Object? value) {
+    final v = value as int;
+    return values.firstWhere((e) => e.value == v, orElse: () => throw FormatException('Unknown Kind value $v'));
+  }
+}
+
+class Point {
+  final double x;
+  final double y;
+
+  Point({
+    required this.x,
+    required this.y,
+  });
+
+  Point copyWith({
+    double? x,
+    double? y,
+  }) {
+    return Point(
+      x: x ?? this.x,
+      y: y ?? this.y,
+    );
+  }
+
+  @override
+  bool operator ==(Object other) {
+    if (identical(this, other)) return true;
+    return other is Point &&
+        x == other.x &&
+        y == other.y;
+  }
+
+  @override
+  int get hashCode {
+    return Object.hashAll([x, y]);
+  }
+
+  @override
+  String toString() {
+    return 'Point(x: $x, y: $y)';
+  }
+
+  Uint8List toBytes() {
+    final p = Packer();
+    _pack(p);
+    return p.takeBytes();
+  }
+
+  static Point fromBytes(Uint8List bytes) {
+    return _unpack(Unpacker(bytes));
+  }
+
+  void _pack(Packer p) {
+    p.packMapLength(2);
+    p.packString('x');
+    p.packDouble(x);
+    p.packString('y');
+    p.packDouble(y);
+  }
+
+  static Point _unpack(Unpacker u) {
+    return _fromValue(u.unpackMap());
+  }
+
+  static Point _fromValue(Object? value) {
+    final m = value as Map;
+    return Point(
+      x: (_required(m, 'x', 'Point') as num).toDouble(),
+      y: (_required(m, 'y', 'Point') as num).toDouble(),
+    );
+  }
+}
+
+class Shape {
+  final Kind kind;
+  final Point origin;
+  final String? label;
+  final double? scale;
+  final List<Point> points;
+  final List<Point>? corners;
+  final Map<String, int>? tags;
+  final Map<Kind, List<int>> byKind;
+  final Map<bool, String> flags;
+  final Shape? parent;
+
+  Shape({
+    required this.kind,
+    required this.origin,
+    this.label,
+    this.scale,
+    required this.points,
+    this.corners,
+    this.tags,
+    required this.byKind,
+    required this.flags,
+    this.parent,
+  });
+
+  Shape copyWith({
+    Kind? kind,
+    Point? origin,
+    Object? label = _unset,
+    Object? scale = _unset,
+    List<Point>? points,
+    Object? corners = _unset,
+    Object? tags = _unset,
+    Map<Kind, List<int>>? byKind,
+    Map<bool, String>? flags,
+    Object? parent = _unset,
+  }) {
+    return Shape(
+      kind: kind ?? this.kind,
+      origin: origin ?? this.origin,
+      label: identical(label, _unset) ? this.label : label as String?,
+      scale: identical(scale, _unset) ? this.scale : scale as double?,
+      points: points ?? this.points,
+      corners: identical(corners, _unset) ? this.corners : corners as List<Point>?,
+      tags: identical(tags, _unset) ? this.tags : tags as Map<String, int>?,
+      byKind: byKind ?? this.byKind,
+      flags: flags ?? this.flags,
+      parent: identical(parent, _unset) ? this.parent : parent as Shape?,
+    );
+  }
+
+  @override
+  bool operator ==(Object other) {
+    if (identical(this, other)) return true;
+    return other is Shape &&
+        kind == other.kind &&
+        origin == other.origin &&
+        label == other.label &&
+        scale == other.scale &&
+        _deepEquals(points, other.points) &&
+        _deepEquals(corners, other.corners) &&
+        _deepEquals(tags, other.tags) &&
+        _deepEquals(byKind, other.byKind) &&
+        _deepEquals(flags, other.flags) &&
+        parent == other.parent;
+  }
+
+  @override
+  int get hashCode {
+    return Object.hashAll([kind, origin, label, scale, _deepHash(points), _deepHash(corners), _deepHash(tags), _deepHash(byKind), _deepHash(flags), parent]);
+  }
+
+  @override
+  String toString() {
+    return 'Shape(kind: $kind, origin: $origin, label: $label, scale: $scale, points: $points, corners: $corners, tags: $tags, byKind: $byKind, flags: $flags, parent: $parent)';
+  }
+
+  Uint8List toBytes() {
+    final p = Packer();
+    _pack(p);
+    return p.takeBytes();
+  }
+
+  static Shape fromBytes(Uint8List bytes) {
+    return _unpack(Unpacker(bytes));
+  }
+
+  void _pack(Packer p) {
+    var length = 5;
+    if (label != null) length++;
+    if (scale != null) length++;
+    if (corners != null) length++;
+    if (tags != null) length++;
+    if (parent != null) length++;
+    p.packMapLength(length);
+    p.packString('kind');
+    kind._pack(p);
+    p.packString('origin');
+    origin._pack(p);
+    if (label != null) {
+      p.packString('label');
+      p.packString(label!);
+    }
+    if (scale != null) {
+      p.packString('scale');
+      p.packDouble(scale!);
+    }
+    p.packString('points');
+    p.packListLength(points.length);
+    for (final e0 in points) {
+      e0._pack(p);
+    }
+    if (corners != null) {
+      p.packString('corners');
+      if (corners!.length != 4) {
+        throw ArgumentError('Shape.corners must have 4 elements, got ${corners!.length}');
+      }
+      p.packListLength(corners!.length);
+      for (final e0 in corners!) {
+        e0._pack(p);
+      }
+    }
+    if (tags != null) {
+      p.packString('tags');
+      p.packMapLength(tags!.length);
+      for (final e0 in tags!.entries) {
+        p.packString(e0.key);
+        p.packInt(e0.value);
+      }
+    }
+    p.packString('by_kind');
+    p.packMapLength(byKind.length);
+    for (final e0 in byKind.entries) {
+      e0.key._pack(p);
+      p.packListLength(e0.value.length);
+      for (final e1 in e0.value) {
+        p.packInt(e1);
+      }
+    }
+    p.packString('flags');
+    p.packMapLength(flags.length);
+    for (final e0 in flags.entries) {
+      p.packBool(e0.key);
+      p.packString(e0.value);
+    }
+    if (parent != null) {
+      p.packString('parent');
+      parent!._pack(p);
+    }
+  }
+
+  static Shape _unpack(Unpacker u) {
+    return _fromValue(u.unpackMap());
+  }
+
+  static Shape _fromValue(Object? value) {
+    final m = value as Map;
+    return Shape(
+      kind: Kind._fromValue(_required(m, 'kind', 'Shape')),
+      origin: Point._fromValue(_required(m, 'origin', 'Shape')),
+      label: m['label'] as String?,
+      scale: (m['scale'] as num?)?.toDouble(),
+      points: (_required(m, 'points', 'Shape') as List).map((e0) => Point._fromValue(e0)).toList(),
+      corners: m['corners'] == null ? null : _checkFixedList((m['corners'] as List).map((e0) => Point._fromValue(e0)).toList(), 'Shape.corners', 4),
+      tags: m['tags'] == null ? null : (m['tags'] as Map).map((k0, v0) => MapEntry(k0 as String, v0 as int)),
+      byKind: (_required(m, 'by_kind', 'Shape') as Map).map((k0, v0) => MapEntry(Kind._fromValue(k0), (v0 as List).map((e1) => e1 as int).toList())),
+      flags: (_required(m, 'flags', 'Shape') as Map).map((k0, v0) => MapEntry(k0 as bool, v0 as String)),
+      parent: m['parent'] == null ? null : Shape._fromValue(m['parent']),
+    );
+  }
+}
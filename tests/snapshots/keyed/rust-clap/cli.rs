@@ -0,0 +1,2 @@
+// Command line arguments for the @cli structs, generated by geno.  Include this file in a
+// module of its own.  Needs the clap crate with its `derive` feature.
@@ -0,0 +1,19 @@
+-- Tables for the rust-sqlx types, generated by geno
+
+CREATE TABLE IF NOT EXISTS "point" (
+    "x" DOUBLE PRECISION NOT NULL,
+    "y" DOUBLE PRECISION NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "shape" (
+    "kind" SMALLINT NOT NULL,
+    "origin" JSONB NOT NULL,
+    "label" TEXT,
+    "scale" REAL,
+    "points" JSONB NOT NULL,
+    "corners" JSONB,
+    "tags" JSONB,
+    "by_kind" JSONB NOT NULL,
+    "flags" JSONB NOT NULL,
+    "parent" JSONB
+);
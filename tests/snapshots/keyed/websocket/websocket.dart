@@ -0,0 +1 @@
+// WebSocket messages for the dart-mp topics, generated by geno
@@ -0,0 +1,2 @@
+// WebSocket messages for the rust-mp topics, generated by geno.  Include this file in the
+// module that holds them.
@@ -0,0 +1,141 @@
+// Archives of dart-mp records, generated by geno
+import 'dart:io';
+import 'dart:typed_data';
+
+import 'geno.dart';
+
+/// The first 8 bytes of the fingerprint of the schema this file was generated from
+const archiveFingerprint = [0xf3, 0x48, 0x05, 0x57, 0x35, 0x64, 0xb7, 0xb0];
+
+const _archiveMagic = [0x47, 0x45, 0x4e, 0x41];
+const _archiveVersion = 1;
+
+String _archiveHex(List<int> bytes) =>
+    bytes.map((byte) => byte.toRadixString(16).padLeft(2, '0')).join();
+
+/// Writes records to an archive, replacing the contents of the file, and then their index when
+/// it is closed
+class ArchiveWriter<T> {
+  final RandomAccessFile _file;
+  final Uint8List Function(T) _encode;
+  final _offsets = <int>[];
+  var _position = 16;
+
+  /// Start an archive, writing its header
+  ArchiveWriter(this._file, this._encode) {
+    _file
+      ..setPositionSync(0)
+      ..truncateSync(0)
+      ..writeFromSync([..._archiveMagic, _archiveVersion, 0, 0, 0, ...archiveFingerprint]);
+  }
+
+  /// Append a record
+  void add(T record) {
+    final bytes = _encode(record);
+
+    _file
+      ..writeFromSync((ByteData(4)..setUint32(0, bytes.length)).buffer.asUint8List())
+      ..writeFromSync(bytes);
+    _offsets.add(_position);
+    _position += 4 + bytes.length;
+  }
+
+  /// Write the index and the footer
+  void close() {
+    final index = ByteData(_offsets.length * 8 + 16);
+
+    for (var i = 0; i < _offsets.length; i++) {
+      index.setUint64(i * 8, _offsets[i]);
+    }
+    index
+      ..setUint64(_offsets.length * 8, _position)
+      ..setUint64(_offsets.length * 8 + 8, _offsets.length);
+    _file
+      ..writeFromSync(index.buffer.asUint8List())
+      ..flushSync();
+  }
+}
+
+/// Reads the records of an archive in any order, using its index
+class ArchiveReader<T> {
+  final RandomAccessFile _file;
+  final T Function(Uint8List) _decode;
+  final List<int> _offsets;
+  final int _index;
+
+  ArchiveReader._(this._file, this._decode, this._offsets, this._index);
+
+  /// Open an archive, checking its header and reading its index
+  factory ArchiveReader.open(RandomAccessFile file, T Function(Uint8List) decode) {
+    final end = file.lengthSync();
+
+    if (end < 32) {
+      throw FormatException('The file is not an archive');
+    }
+    file.setPositionSync(0);
+
+    final header = file.readSync(16);
+
+    for (var i = 0; i < 4; i++) {
+      if (header[i] != _archiveMagic[i]) {
+        throw FormatException('The file is not an archive');
+      }
+    }
+    if (header[4] != _archiveVersion) {
+      throw FormatException('The archive has format version ${header[4]}');
+    }
+    for (var i = 0; i < 8; i++) {
+      if (header[8 + i] != archiveFingerprint[i]) {
+        throw FormatException(
+            'The archive was written with schema ${_archiveHex(header.sublist(8))}, but this code is for schema ${_archiveHex(archiveFingerprint)}');
+      }
+    }
+    file.setPositionSync(end - 16);
+
+    final footer = ByteData.sublistView(file.readSync(16));
+    final index = footer.getUint64(0);
+    final count = footer.getUint64(8);
+
+    if (index < 16 || count < 0 || index + count * 8 + 16 != end) {
+      throw FormatException('The archive is corrupt');
+    }
+    file.setPositionSync(index);
+
+    final offsets = ByteData.sublistView(file.readSync(count * 8));
+
+    return ArchiveReader._(
+        file, decode, List.generate(count, (i) => offsets.getUint64(i * 8)), index);
+  }
+
+  /// The number of records
+  int get length => _offsets.length;
+
+  /// Read the record at [index]
+  T operator [](int index) {
+    RangeError.checkValidIndex(index, _offsets, 'index');
+    _file.setPositionSync(_offsets[index]);
+
+    final length = ByteData.sublistView(_file.readSync(4)).getUint32(0);
+
+    if (_offsets[index] + 4 + length > _index) {
+      throw FormatException('The archive is corrupt');
+    }
+    return _decode(_file.readSync(length));
+  }
+}
+
+/// Start an archive of [Point] records in [file]
+ArchiveWriter<Point> createPointArchive(RandomAccessFile file) =>
+    ArchiveWriter(file, (Point record) => record.toBytes());
+
+/// Open an archive of [Point] records
+ArchiveReader<Point> openPointArchive(RandomAccessFile file) =>
+    ArchiveReader.open(file, Point.fromBytes);
+
+/// Start an archive of [Shape] records in [file]
+ArchiveWriter<Shape> createShapeArchive(RandomAccessFile file) =>
+    ArchiveWriter(file, (Shape record) => record.toBytes());
+
+/// Open an archive of [Shape] records
+ArchiveReader<Shape> openShapeArchive(RandomAccessFile file) =>
+    ArchiveReader.open(file, Shape.fromBytes);
@@ -0,0 +1,8 @@
+/* C structs for the @abi(stable) structs, generated by geno */
+#ifndef GENO_H
+#define GENO_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#endif /* GENO_H */
@@ -0,0 +1,318 @@
+import 'dart:typed_data';
+
+import 'package:messagepack/messagepack.dart';
+
+const _unset = Object();
+
+List<T> _unpackFixedList<T>(Unpacker u, String path, int expected, T Function() unpack) {
+  final length = u.unpackListLength();
+  if (length != expected) {
+    throw FormatException('$path must have $expected elements, got $length');
+  }
+  return List.generate(length, (_) => unpack());
+}
+
+bool _deepEquals(Object? a, Object? b) {
+  if (a is List && b is List) {
+    if (a.length != b.length) return false;
+    for (var i = 0; i < a.length; i++) {
+      if (!_deepEquals(a[i], b[i])) return false;
+    }
+    return true;
+  }
+  if (a is Map && b is Map) {
+    if (a.length != b.length) return false;
+    for (final key in a.keys) {
+      if (!b.containsKey(key) || !_deepEquals(a[key], b[key])) return false;
+    }
+    return true;
+  }
+  return a == b;
+}
+
+int _deepHash(Object? o) {
+  if (o is List) return Object.hashAll(o.map(_deepHash));
+  if (o is Map) return Object.hashAllUnordered(o.entries.map((e) => Object.hash(_deepHash(e.key), _deepHash(e.value))));
+  return o.hashCode;
+}
+
+enum Kind {
+  circle(1),
+  square(2);
+
+  final int value;
+  const Kind(this.value);
+
+  Uint8List toBytes() {
+    final p = Packer();
+    _pack(p);
+    return p.takeBytes();
+  }
+
+  static Kind fromBytes(Uint8List bytes) {
+    return _unpack(Unpacker(bytes));
+  }
+
+  void _pack(Packer p) {
+    p.packInt(value);
+  }
+
+  static Kind _unpack(Unpacker u) {
+    return _fromValue(u.unpackInt());
+  }
+
+  static Kind? _unpackNullable(Unpacker u) {
+    final v = u.unpackInt();
+    return v == null ? null : _fromValue(v);
+  }
+
+  static Kind _fromValue(Object? value) {
+    final v = value as int;
+    return values.firstWhere((e) => e.value == v, orElse: () => throw FormatException('Unknown Kind value $v'));
+  }
+}
+
+class Point {
+  final double x;
+  final double y;
+
+  Point({
+    required this.x,
+    required this.y,
+  });
+
+  Point copyWith({
+    double? x,
+    double? y,
+  }) {
+    return Point(
+      x: x ?? this.x,
+      y: y ?? this.y,
+    );
+  }
+
+  @override
+  bool operator ==(Object other) {
+    if (identical(this, other)) return true;
+    return other is Point &&
+        x == other.x &&
+        y == other.y;
+  }
+
+  @override
+  int get hashCode {
+    return Object.hashAll([x, y]);
+  }
+
+  @override
+  String toString() {
+    return 'Point(x: $x, y: $y)';
+  }
+
+  Uint8List toBytes() {
+    final p = Packer();
+    _pack(p);
+    return p.takeBytes();
+  }
+
+  static Point fromBytes(Uint8List bytes) {
+    return _unpack(Unpacker(bytes));
+  }
+
+  void _pack(Packer p) {
+    p.packDouble(x);
+    p.packDouble(y);
+  }
+
+  static Point _unpack(Unpacker u) {
+    final x = u.unpackDouble()!;
+    final y = u.unpackDouble()!;
+    return Point(
+      x: x,
+      y: y,
+    );
+  }
+
+  static Point? _unpackNullable(Unpacker u) {
+    if (u.unpackBool() == null) return null;
+    return _unpack(u);
+  }
+}
+
+class Shape {
+  final Kind kind;
+  final Point origin;
+  final String? label;
+  final double? scale;
+  final List<Point> points;
+  final List<Point>? corners;
+  final Map<String, int>? tags;
+  final Map<Kind, List<int>> byKind;
+  final Map<bool, String> flags;
+  final Shape? parent;
+
+  Shape({
+    required this.kind,
+    required this.origin,
+    this.label,
+    this.scale,
+    required this.points,
+    this.corners,
+    this.tags,
+    required this.byKind,
+    required this.flags,
+    this.parent,
+  });
+
+  Shape copyWith({
+    Kind? kind,
+    Point? origin,
+    Object? label = _unset,
+    Object? scale = _unset,
+    List<Point>? points,
+    Object? corners = _unset,
+    Object? tags = _unset,
+    Map<Kind, List<int>>? byKind,
+    Map<bool, String>? flags,
+    Object? parent = _unset,
+  }) {
+    return Shape(
+      kind: kind ?? this.kind,
+      origin: origin ?? this.origin,
+      label: identical(label, _unset) ? this.label : label as String?,
+      scale: identical(scale, _unset) ? this.scale : scale as double?,
+      points: points ?? this.points,
+      corners: identical(corners, _unset) ? this.corners : corners as List<Point>?,
+      tags: identical(tags, _unset) ? this.tags : tags as Map<String, int>?,
+      byKind: byKind ?? this.byKind,
+      flags: flags ?? this.flags,
+      parent: identical(parent, _unset) ? this.parent : parent as Shape?,
+    );
+  }
+
+  @override
+  bool operator ==(Object other) {
+    if (identical(this, other)) return true;
+    return other is Shape &&
+        kind == other.kind &&
+        origin == other.origin &&
+        label == other.label &&
+        scale == other.scale &&
+        _deepEquals(points, other.points) &&
+        _deepEquals(corners, other.corners) &&
+        _deepEquals(tags, other.tags) &&
+        _deepEquals(byKind, other.byKind) &&
+        _deepEquals(flags, other.flags) &&
+        parent == other.parent;
+  }
+
+  @override
+  int get hashCode {
+    return Object.hashAll([kind, origin, label, scale, _deepHash(points), _deepHash(corners), _deepHash(tags), _deepHash(byKind), _deepHash(flags), parent]);
+  }
+
+  @override
+  String toString() {
+    return 'Shape(kind: $kind, origin: $origin, label: $label, scale: $scale, points: $points, corners: $corners, tags: $tags, byKind: $byKind, flags: $flags, parent: $parent)';
+  }
+
+  Uint8List toBytes() {
+    final p = Packer();
+    _pack(p);
+    return p.takeBytes();
+  }
+
+  static Shape fromBytes(Uint8List bytes) {
+    return _unpack(Unpacker(bytes));
+  }
+
+  void _pack(Packer p) {
+    kind._pack(p);
+    origin._pack(p);
+    if (label != null) {
+      p.packString(label!);
+    } else {
+      p.packNull();
+    }
+    if (scale != null) {
+      p.packDouble(scale!);
+    } else {
+      p.packNull();
+    }
+    p.packListLength(points.length);
+    for (final e0 in points) {
+      e0._pack(p);
+    }
+    if (corners != null) {
+      p.packBool(true);
+      if (corners!.length != 4) {
+        throw ArgumentError('Shape.corners must have 4 elements, got ${corners!.length}');
+      }
+      p.packListLength(corners!.length);
+      for (final e0 in corners!) {
+        e0._pack(p);
+      }
+    } else {
+      p.packNull();
+    }
+    if (tags != null) {
+      p.packBool(true);
+      p.packMapLength(tags!.length);
+      for (final e0 in tags!.entries) {
+        p.packString(e0.key);
+        p.packInt(e0.value);
+      }
+    } else {
+      p.packNull();
+    }
+    p.packMapLength(byKind.length);
+    for (final e0 in byKind.entries) {
+      e0.key._pack(p);
+      p.packListLength(e0.value.length);
+      for (final e1 in e0.value) {
+        p.packInt(e1);
+      }
+    }
+    p.packMapLength(flags.length);
+    for (final e0 in flags.entries) {
+      p.packBool(e0.key);
+      p.packString(e0.value);
+    }
+    if (parent != null) {
+      p.packBool(true);
+      parent!._pack(p);
+    } else {
+      p.packNull();
+    }
+  }
+
+  static Shape _unpack(Unpacker u) {
+    final kind = Kind._unpack(u);
+    final origin = Point._unpack(u);
+    final label = u.unpackString();
+    final scale = u.unpackDouble();
+    final points = List.generate(u.unpackListLength(), (_) => Point._unpack(u));
+    final corners = u.unpackBool() == null ? null : _unpackFixedList(u, 'Shape.corners', 4, () => Point._unpack(u));
+    final tags = u.unpackBool() == null ? null : Map.fromEntries(List.generate(u.unpackMapLength(), (_) => MapEntry(u.unpackString()!, u.unpackInt()!)));
+    final byKind = Map.fromEntries(List.generate(u.unpackMapLength(), (_) => MapEntry(Kind._unpack(u), List.generate(u.unpackListLength(), (_) => u.unpackInt()!))));
+    final flags = Map.fromEntries(List.generate(u.unpackMapLength(), (_) => MapEntry(u.unpackBool()!, u.unpackString()!)));
+    final parent = Shape._unpackNullable(u);
+    return Shape(
+      kind: kind,
+      origin: origin,
+      label: label,
+      scale: scale,
+      points: points,
+      corners: corners,
+      tags: tags,
+      byKind: byKind,
+      flags: flags,
+      parent: parent,
+    );
+  }
+
+  static Shape? _unpackNullable(Unpacker u) {
+    if (u.unpackBool() == null) return null;
+    return _unpack(u);
+  }
+}
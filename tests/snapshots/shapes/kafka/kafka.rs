@@ -0,0 +1,2 @@
+// Kafka producers and consumers for the rust-mp topics, generated by geno.  Include this
+// file in the module that holds them.  Needs the rdkafka crate.
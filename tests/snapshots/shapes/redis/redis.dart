@@ -0,0 +1,4 @@
+// Redis keys and values for the dart-mp types, generated by geno
+import 'dart:typed_data';
+
+import 'geno.dart';
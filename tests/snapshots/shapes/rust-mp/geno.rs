@@ -0,0 +1,611 @@
+#![allow(unused_imports, clippy::wrong_self_convention)]
+
+use std::collections::HashMap;
+
+/// Error returned when a message can't be decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError(pub String);
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[allow(dead_code)]
+mod mp {
+    use super::DecodeError;
+
+    pub fn write_nil(wr: &mut Vec<u8>) {
+        wr.push(0xc0);
+    }
+
+    pub fn write_bool(wr: &mut Vec<u8>, value: bool) {
+        wr.push(if value { 0xc3 } else { 0xc2 });
+    }
+
+    pub fn write_int(wr: &mut Vec<u8>, value: i64) {
+        if value >= 0 {
+            write_uint(wr, value as u64);
+        } else if value >= -32 {
+            wr.push(value as u8);
+        } else if value >= i8::MIN as i64 {
+            wr.push(0xd0);
+            wr.push(value as u8);
+        } else if value >= i16::MIN as i64 {
+            wr.push(0xd1);
+            wr.extend_from_slice(&(value as i16).to_be_bytes());
+        } else if value >= i32::MIN as i64 {
+            wr.push(0xd2);
+            wr.extend_from_slice(&(value as i32).to_be_bytes());
+        } else {
+            wr.push(0xd3);
+            wr.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    pub fn write_uint(wr: &mut Vec<u8>, value: u64) {
+        if value < 0x80 {
+            wr.push(value as u8);
+        } else if value <= u8::MAX as u64 {
+            wr.push(0xcc);
+            wr.push(value as u8);
+        } else if value <= u16::MAX as u64 {
+            wr.push(0xcd);
+            wr.extend_from_slice(&(value as u16).to_be_bytes());
+        } else if value <= u32::MAX as u64 {
+            wr.push(0xce);
+            wr.extend_from_slice(&(value as u32).to_be_bytes());
+        } else {
+            wr.push(0xcf);
+            wr.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    pub fn write_f64(wr: &mut Vec<u8>, value: f64) {
+        wr.push(0xcb);
+        wr.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_str(wr: &mut Vec<u8>, value: &str) {
+        let len = value.len();
+
+        if len < 32 {
+            wr.push(0xa0 | len as u8);
+        } else if len <= u8::MAX as usize {
+            wr.push(0xd9);
+            wr.push(len as u8);
+        } else if len <= u16::MAX as usize {
+            wr.push(0xda);
+            wr.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            wr.push(0xdb);
+            wr.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        wr.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn write_array_len(wr: &mut Vec<u8>, len: usize) {
+        write_container_len(wr, len, 0x90, 0xdc);
+    }
+
+    pub fn write_map_len(wr: &mut Vec<u8>, len: usize) {
+        write_container_len(wr, len, 0x80, 0xde);
+    }
+
+    pub fn write_bin(wr: &mut Vec<u8>, bytes: &[u8]) {
+        let len = bytes.len();
+
+        if len <= u8::MAX as usize {
+            wr.push(0xc4);
+            wr.push(len as u8);
+        } else if len <= u16::MAX as usize {
+            wr.push(0xc5);
+            wr.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            wr.push(0xc6);
+            wr.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        wr.extend_from_slice(bytes);
+    }
+
+    fn write_container_len(wr: &mut Vec<u8>, len: usize, fix: u8, marker16: u8) {
+        if len < 16 {
+            wr.push(fix | len as u8);
+        } else if len <= u16::MAX as usize {
+            wr.push(marker16);
+            wr.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            wr.push(marker16 + 1);
+            wr.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+
+    fn read_bytes<'a>(rd: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+        if rd.len() < len {
+            return Err(DecodeError("unexpected end of input".to_string()));
+        }
+        let (bytes, rest) = rd.split_at(len);
+        *rd = rest;
+        Ok(bytes)
+    }
+
+    fn read_be<const N: usize>(rd: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+        Ok(read_bytes(rd, N)?.try_into().unwrap())
+    }
+
+    fn read_marker(rd: &mut &[u8]) -> Result<u8, DecodeError> {
+        Ok(read_be::<1>(rd)?[0])
+    }
+
+    fn unexpected(marker: u8, expected: &str) -> DecodeError {
+        DecodeError(format!("expected {expected}, found marker 0x{marker:02x}"))
+    }
+
+    pub fn missing(path: &str) -> DecodeError {
+        DecodeError(format!("{path} is missing"))
+    }
+
+    /// Consume a nil if it is the next value
+    pub fn read_nil(rd: &mut &[u8]) -> bool {
+        if rd.first() == Some(&0xc0) {
+            *rd = &rd[1..];
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn read_bool(rd: &mut &[u8]) -> Result<bool, DecodeError> {
+        match read_marker(rd)? {
+            0xc2 => Ok(false),
+            0xc3 => Ok(true),
+            marker => Err(unexpected(marker, "bool")),
+        }
+    }
+
+    pub fn read_int<T: TryFrom<i128>>(rd: &mut &[u8]) -> Result<T, DecodeError> {
+        let marker = read_marker(rd)?;
+        let value = match marker {
+            0x00..=0x7f => marker as i128,
+            0xe0..=0xff => marker as i8 as i128,
+            0xcc => u8::from_be_bytes(read_be(rd)?) as i128,
+            0xcd => u16::from_be_bytes(read_be(rd)?) as i128,
+            0xce => u32::from_be_bytes(read_be(rd)?) as i128,
+            0xcf => u64::from_be_bytes(read_be(rd)?) as i128,
+            0xd0 => i8::from_be_bytes(read_be(rd)?) as i128,
+            0xd1 => i16::from_be_bytes(read_be(rd)?) as i128,
+            0xd2 => i32::from_be_bytes(read_be(rd)?) as i128,
+            0xd3 => i64::from_be_bytes(read_be(rd)?) as i128,
+            _ => return Err(unexpected(marker, "integer")),
+        };
+
+        T::try_from(value).map_err(|_| DecodeError(format!("integer {value} is out of range")))
+    }
+
+    /// Read an integer packed as a decimal string
+    pub fn read_int_str<T: std::str::FromStr>(rd: &mut &[u8], path: &str) -> Result<T, DecodeError> {
+        let value = read_str(rd)?;
+
+        value
+            .parse()
+            .map_err(|_| DecodeError(format!("{path} is not an integer: {value:?}")))
+    }
+
+    pub fn read_f64(rd: &mut &[u8]) -> Result<f64, DecodeError> {
+        match read_marker(rd)? {
+            0xca => Ok(f32::from_be_bytes(read_be(rd)?) as f64),
+            0xcb => Ok(f64::from_be_bytes(read_be(rd)?)),
+            marker => Err(unexpected(marker, "float")),
+        }
+    }
+
+    pub fn read_f32(rd: &mut &[u8]) -> Result<f32, DecodeError> {
+        Ok(read_f64(rd)? as f32)
+    }
+
+    pub fn read_str(rd: &mut &[u8]) -> Result<String, DecodeError> {
+        read_str_ref(rd).map(String::from)
+    }
+
+    pub fn read_str_ref<'a>(rd: &mut &'a [u8]) -> Result<&'a str, DecodeError> {
+        let marker = read_marker(rd)?;
+        let len = match marker {
+            0xa0..=0xbf => (marker & 0x1f) as usize,
+            0xd9 => u8::from_be_bytes(read_be(rd)?) as usize,
+            0xda => u16::from_be_bytes(read_be(rd)?) as usize,
+            0xdb => u32::from_be_bytes(read_be(rd)?) as usize,
+            _ => return Err(unexpected(marker, "string")),
+        };
+
+        std::str::from_utf8(read_bytes(rd, len)?)
+            .map_err(|_| DecodeError("string is not valid UTF-8".to_string()))
+    }
+
+    pub fn read_array_len(rd: &mut &[u8]) -> Result<usize, DecodeError> {
+        let marker = read_marker(rd)?;
+        match marker {
+            0x90..=0x9f => Ok((marker & 0x0f) as usize),
+            0xdc => Ok(u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdd => Ok(u32::from_be_bytes(read_be(rd)?) as usize),
+            _ => Err(unexpected(marker, "array")),
+        }
+    }
+
+    pub fn read_map_len(rd: &mut &[u8]) -> Result<usize, DecodeError> {
+        let marker = read_marker(rd)?;
+        match marker {
+            0x80..=0x8f => Ok((marker & 0x0f) as usize),
+            0xde => Ok(u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdf => Ok(u32::from_be_bytes(read_be(rd)?) as usize),
+            _ => Err(unexpected(marker, "map")),
+        }
+    }
+
+    pub fn read_bin<'a>(rd: &mut &'a [u8]) -> Result<&'a [u8], DecodeError> {
+        let end = || DecodeError("unexpected end of input".to_string());
+        let header = match rd.first() {
+            Some(0xc4) => 2,
+            Some(0xc5) => 3,
+            Some(0xc6) => 5,
+            Some(marker) => {
+                return Err(DecodeError(format!("expected binary, found marker 0x{marker:02x}")));
+            }
+            None => return Err(end()),
+        };
+        let len = rd
+            .get(1..header)
+            .ok_or_else(end)?
+            .iter()
+            .fold(0, |len, byte| len << 8 | usize::from(*byte));
+        let bytes = rd.get(header..header + len).ok_or_else(end)?;
+
+        *rd = &rd[header + len..];
+        Ok(bytes)
+    }
+
+    /// Skip over a value of any type
+    pub fn skip(rd: &mut &[u8]) -> Result<(), DecodeError> {
+        let marker = read_marker(rd)?;
+        let (len, items) = match marker {
+            0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => (0, 0),
+            0x80..=0x8f => (0, 2 * (marker & 0x0f) as usize),
+            0x90..=0x9f => (0, (marker & 0x0f) as usize),
+            0xa0..=0xbf => ((marker & 0x1f) as usize, 0),
+            0xc4 | 0xd9 => (u8::from_be_bytes(read_be(rd)?) as usize, 0),
+            0xc5 | 0xda => (u16::from_be_bytes(read_be(rd)?) as usize, 0),
+            0xc6 | 0xdb => (u32::from_be_bytes(read_be(rd)?) as usize, 0),
+            0xc7 => (u8::from_be_bytes(read_be(rd)?) as usize + 1, 0),
+            0xc8 => (u16::from_be_bytes(read_be(rd)?) as usize + 1, 0),
+            0xc9 => (u32::from_be_bytes(read_be(rd)?) as usize + 1, 0),
+            0xca => (4, 0),
+            0xcb => (8, 0),
+            0xcc | 0xd0 => (1, 0),
+            0xcd | 0xd1 => (2, 0),
+            0xce | 0xd2 => (4, 0),
+            0xcf | 0xd3 => (8, 0),
+            0xd4 => (2, 0),
+            0xd5 => (3, 0),
+            0xd6 => (5, 0),
+            0xd7 => (9, 0),
+            0xd8 => (17, 0),
+            0xdc => (0, u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdd => (0, u32::from_be_bytes(read_be(rd)?) as usize),
+            0xde => (0, 2 * u16::from_be_bytes(read_be(rd)?) as usize),
+            0xdf => (0, 2 * u32::from_be_bytes(read_be(rd)?) as usize),
+            _ => return Err(unexpected(marker, "a value")),
+        };
+
+        read_bytes(rd, len)?;
+        for _ in 0..items {
+            skip(rd)?;
+        }
+        Ok(())
+    }
+
+    /// Write a message with a 4 byte big-endian length prefix
+    pub fn write_frame<W: std::io::Write>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+        let len = u32::try_from(bytes.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "message is too long to frame")
+        })?;
+        writer.write_all(&len.to_be_bytes())?;
+        writer.write_all(bytes)
+    }
+
+    /// Longest message `read_frame` accepts unless it is given another limit
+    pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+    /// Read a length prefixed message, or `None` if the reader is at the end of the stream.  A
+    /// length prefix over `max_frame_length` is an error, before anything is allocated for it.
+    pub fn read_frame<R: std::io::Read>(reader: &mut R, max_frame_length: usize) -> std::io::Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 4];
+        let mut read = 0;
+
+        while read < len.len() {
+            match reader.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let len = u32::from_be_bytes(len) as usize;
+
+        if len > max_frame_length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("frame of {len} bytes is longer than the limit of {max_frame_length}"),
+            ));
+        }
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+
+    pub fn fixed_array<T, const N: usize>(items: Vec<T>, path: &str) -> Result<[T; N], DecodeError> {
+        let len = items.len();
+        items
+            .try_into()
+            .map_err(|_| DecodeError(format!("{path} must have {N} elements, got {len}")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(u8)]
+pub enum Kind {
+    #[default]
+    Circle = 1,
+    Square = 2,
+}
+
+impl From<Kind> for u8 {
+    fn from(value: Kind) -> Self {
+        value as u8
+    }
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::Circle),
+            2 => Ok(Self::Square),
+            _ => Err(format!("invalid Kind value {value}")),
+        }
+    }
+}
+
+impl Kind {
+    /// Encode as MessagePack
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wr = Vec::new();
+        self.pack(&mut wr);
+        wr
+    }
+
+    /// Decode from MessagePack
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut rd = bytes;
+        Self::unpack(&mut rd)
+    }
+
+    fn pack(&self, wr: &mut Vec<u8>) {
+        mp::write_uint(wr, *self as u64);
+    }
+
+    fn unpack(rd: &mut &[u8]) -> Result<Self, DecodeError> {
+        Self::try_from(mp::read_int::<u8>(rd)?).map_err(DecodeError)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Point {
+    /// Encode as MessagePack
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wr = Vec::new();
+        self.pack(&mut wr);
+        wr
+    }
+
+    /// Decode from MessagePack
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut rd = bytes;
+        Self::unpack(&mut rd)
+    }
+
+    fn pack(&self, wr: &mut Vec<u8>) {
+        mp::write_f64(wr, self.x);
+        mp::write_f64(wr, self.y);
+    }
+
+    fn unpack(rd: &mut &[u8]) -> Result<Self, DecodeError> {
+        let f_x = mp::read_f64(rd)?;
+        let f_y = mp::read_f64(rd)?;
+        Ok(Self {
+            x: f_x,
+            y: f_y,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shape {
+    pub kind: Kind,
+    pub origin: Point,
+    pub label: Option<String>,
+    pub scale: Option<f32>,
+    pub points: Vec<Point>,
+    pub corners: Option<[Point; 4]>,
+    pub tags: Option<HashMap<String, i32>>,
+    pub by_kind: HashMap<Kind, Vec<u64>>,
+    pub flags: HashMap<bool, String>,
+    pub parent: Option<Box<Shape>>,
+}
+
+impl Shape {
+    /// Encode as MessagePack
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wr = Vec::new();
+        self.pack(&mut wr);
+        wr
+    }
+
+    /// Decode from MessagePack
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut rd = bytes;
+        Self::unpack(&mut rd)
+    }
+
+    fn pack(&self, wr: &mut Vec<u8>) {
+        self.kind.pack(wr);
+        self.origin.pack(wr);
+        if let Some(value0) = &self.label {
+            mp::write_str(wr, value0);
+        } else {
+            mp::write_nil(wr);
+        }
+        if let Some(value0) = &self.scale {
+            mp::write_f64(wr, *value0 as f64);
+        } else {
+            mp::write_nil(wr);
+        }
+        mp::write_array_len(wr, self.points.len());
+        for e0 in &self.points {
+            e0.pack(wr);
+        }
+        if let Some(value0) = &self.corners {
+            mp::write_bool(wr, true);
+            mp::write_array_len(wr, value0.len());
+            for e1 in value0 {
+                e1.pack(wr);
+            }
+        } else {
+            mp::write_nil(wr);
+        }
+        if let Some(value0) = &self.tags {
+            mp::write_bool(wr, true);
+            mp::write_map_len(wr, value0.len());
+            for (k1, v1) in value0 {
+                mp::write_str(wr, k1);
+                mp::write_int(wr, *v1 as i64);
+            }
+        } else {
+            mp::write_nil(wr);
+        }
+        mp::write_map_len(wr, self.by_kind.len());
+        for (k0, v0) in &self.by_kind {
+            k0.pack(wr);
+            mp::write_array_len(wr, v0.len());
+            for e1 in v0 {
+                mp::write_uint(wr, *e1);
+            }
+        }
+        mp::write_map_len(wr, self.flags.len());
+        for (k0, v0) in &self.flags {
+            mp::write_bool(wr, *k0);
+            mp::write_str(wr, v0);
+        }
+        if let Some(value0) = &self.parent {
+            mp::write_bool(wr, true);
+            value0.pack(wr);
+        } else {
+            mp::write_nil(wr);
+        }
+    }
+
+    fn unpack(rd: &mut &[u8]) -> Result<Self, DecodeError> {
+        let f_kind = Kind::unpack(rd)?;
+        let f_origin = Point::unpack(rd)?;
+        let f_label = if mp::read_nil(rd) {
+            None
+        } else {
+            let value0 = mp::read_str(rd)?;
+            Some(value0)
+        };
+        let f_scale = if mp::read_nil(rd) {
+            None
+        } else {
+            let value0 = mp::read_f32(rd)?;
+            Some(value0)
+        };
+        let mut items0 = Vec::new();
+        for _ in 0..mp::read_array_len(rd)? {
+            let e0 = Point::unpack(rd)?;
+            items0.push(e0);
+        }
+        let f_points = items0;
+        let f_corners = if mp::read_nil(rd) {
+            None
+        } else {
+            mp::read_bool(rd)?;
+            let mut items1 = Vec::new();
+            for _ in 0..mp::read_array_len(rd)? {
+                let e1 = Point::unpack(rd)?;
+                items1.push(e1);
+            }
+            let value0 = mp::fixed_array(items1, "Shape.corners")?;
+            Some(value0)
+        };
+        let f_tags = if mp::read_nil(rd) {
+            None
+        } else {
+            mp::read_bool(rd)?;
+            let mut map1 = HashMap::new();
+            for _ in 0..mp::read_map_len(rd)? {
+                let k1 = mp::read_str(rd)?;
+                let v1 = mp::read_int::<i32>(rd)?;
+                map1.insert(k1, v1);
+            }
+            let value0 = map1;
+            Some(value0)
+        };
+        let mut map0 = HashMap::new();
+        for _ in 0..mp::read_map_len(rd)? {
+            let k0 = Kind::unpack(rd)?;
+            let mut items1 = Vec::new();
+            for _ in 0..mp::read_array_len(rd)? {
+                let e1 = mp::read_int::<u64>(rd)?;
+                items1.push(e1);
+            }
+            let v0 = items1;
+            map0.insert(k0, v0);
+        }
+        let f_by_kind = map0;
+        let mut map0 = HashMap::new();
+        for _ in 0..mp::read_map_len(rd)? {
+            let k0 = mp::read_bool(rd)?;
+            let v0 = mp::read_str(rd)?;
+            map0.insert(k0, v0);
+        }
+        let f_flags = map0;
+        let f_parent = if mp::read_nil(rd) {
+            None
+        } else {
+            mp::read_bool(rd)?;
+            let value0 = Shape::unpack(rd)?;
+            Some(value0)
+        };
+        Ok(Self {
+            kind: f_kind,
+            origin: f_origin,
+            label: f_label,
+            scale: f_scale,
+            points: f_points,
+            corners: f_corners,
+            tags: f_tags,
+            by_kind: f_by_kind,
+            flags: f_flags,
+            parent: f_parent.map(Box::new),
+        })
+    }
+}
@@ -0,0 +1,63 @@
+// Rows of the tables in schema.sql as the rust-serde types, generated by geno.  Include
+// this file in the module that holds them.  It needs sqlx with its `json` feature.
+use sqlx::Row as _;
+
+/// Convert a column value to the type of its field, failing if it's out of range
+fn convert<T, U>(value: T, column: &str) -> Result<U, sqlx::Error>
+where
+    U: TryFrom<T>,
+    U::Error: std::fmt::Display,
+{
+    U::try_from(value).map_err(|err| sqlx::Error::ColumnDecode {
+        index: column.to_string(),
+        source: err.to_string().into(),
+    })
+}
+
+impl Point {
+    /// The `point` table
+    pub const TABLE: &str = "point";
+    /// The columns of the table, in order
+    pub const COLUMNS: &[&str] = &["x", "y"];
+    /// Select every row
+    pub const SELECT: &str = "SELECT \"x\", \"y\" FROM \"point\"";
+    /// Insert a row, binding every column in order
+    pub const INSERT: &str = "INSERT INTO \"point\" (\"x\", \"y\") VALUES ($1, $2)";
+}
+
+impl<'r> sqlx::FromRow<'r, sqlx::postgres::PgRow> for Point {
+    fn from_row(row: &'r sqlx::postgres::PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            x: row.try_get::<f64, _>("x")?,
+            y: row.try_get::<f64, _>("y")?,
+        })
+    }
+}
+
+impl Shape {
+    /// The `shape` table
+    pub const TABLE: &str = "shape";
+    /// The columns of the table, in order
+    pub const COLUMNS: &[&str] = &["kind", "origin", "label", "scale", "points", "corners", "tags", "by_kind", "flags", "parent"];
+    /// Select every row
+    pub const SELECT: &str = "SELECT \"kind\", \"origin\", \"label\", \"scale\", \"points\", \"corners\", \"tags\", \"by_kind\", \"flags\", \"parent\" FROM \"shape\"";
+    /// Insert a row, binding every column in order
+    pub const INSERT: &str = "INSERT INTO \"shape\" (\"kind\", \"origin\", \"label\", \"scale\", \"points\", \"corners\", \"tags\", \"by_kind\", \"flags\", \"parent\") VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)";
+}
+
+impl<'r> sqlx::FromRow<'r, sqlx::postgres::PgRow> for Shape {
+    fn from_row(row: &'r sqlx::postgres::PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            kind: convert::<u8, Kind>(convert(row.try_get::<i16, _>("kind")?, "kind")?, "kind")?,
+            origin: row.try_get::<sqlx::types::Json<Point>, _>("origin")?.0,
+            label: row.try_get::<Option<String>, _>("label")?,
+            scale: row.try_get::<Option<f32>, _>("scale")?,
+            points: row.try_get::<sqlx::types::Json<Vec<Point>>, _>("points")?.0,
+            corners: row.try_get::<Option<sqlx::types::Json<[Point; 4]>>, _>("corners")?.map(|json| json.0),
+            tags: row.try_get::<Option<sqlx::types::Json<HashMap<String, i32>>>, _>("tags")?.map(|json| json.0),
+            by_kind: row.try_get::<sqlx::types::Json<HashMap<Kind, Vec<u64>>>, _>("by_kind")?.0,
+            flags: row.try_get::<sqlx::types::Json<HashMap<bool, String>>, _>("flags")?.0,
+            parent: row.try_get::<Option<sqlx::types::Json<Shape>>, _>("parent")?.map(|json| json.0),
+        })
+    }
+}